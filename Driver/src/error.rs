@@ -26,6 +26,9 @@ pub enum DriverError {
 
     #[error("Parse error: {0}")]
     Parse(String),
+
+    #[error("Cache location error: {0}")]
+    CacheLocation(String),
 }
 
 pub type DriverResult<T> = Result<T, DriverError>;
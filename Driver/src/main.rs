@@ -43,7 +43,13 @@ fn run_service() {
     println!("ptree-driver v{} - Starting", DRIVER_VERSION);
     
     // Create service with default config
-    let config = ServiceConfig::default();
+    let config = match ServiceConfig::new() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Service error: {}", e);
+            std::process::exit(1);
+        }
+    };
     let mut service = PtreeService::new(config);
 
     // Setup signal handlers (Ctrl+C)
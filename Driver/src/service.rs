@@ -2,7 +2,7 @@
 // Runs as a system service monitoring file system changes via USN Journal
 
 use crate::usn_journal::USNTracker;
-use crate::error::DriverResult;
+use crate::error::{DriverError, DriverResult};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -23,19 +23,20 @@ pub struct ServiceConfig {
     pub log_path: std::path::PathBuf,
 }
 
-impl Default for ServiceConfig {
-    fn default() -> Self {
-        ServiceConfig {
+impl ServiceConfig {
+    /// Default configuration, with the cache file the CLI uses by default
+    ///
+    /// The location comes from `ptree_cache::get_cache_path`, so both
+    /// components read and write the same cache; this fails where it does.
+    pub fn new() -> DriverResult<Self> {
+        let cache_path = ptree_cache::get_cache_path().map_err(|e| DriverError::CacheLocation(format!("{:#}", e)))?;
+        Ok(ServiceConfig {
             drive_letter: 'C',
             check_interval: 60,
-            cache_path: std::path::PathBuf::from(
-                std::env::var("APPDATA").unwrap_or_else(|_| "C:\\Users\\User\\AppData\\Roaming".to_string())
-            ).join("ptree")
-            .join("cache")
-            .join("ptree.dat"),
+            cache_path,
             log_path: std::path::PathBuf::from("C:\\ProgramData\\ptree")
                 .join("service.log"),
-        }
+        })
     }
 }
 
@@ -188,14 +189,15 @@ mod tests {
 
     #[test]
     fn test_service_creation() {
-        let config = ServiceConfig::default();
+        let config = ServiceConfig::new().unwrap();
         let service = PtreeService::new(config);
         assert_eq!(service.config.drive_letter, 'C');
+        assert_eq!(service.config.cache_path, ptree_cache::get_cache_path().unwrap());
     }
 
     #[test]
     fn test_service_stop_signal() {
-        let config = ServiceConfig::default();
+        let config = ServiceConfig::new().unwrap();
         let service = PtreeService::new(config);
         assert!(!service.should_exit.load(Ordering::Relaxed));
         service.stop();
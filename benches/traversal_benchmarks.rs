@@ -1,4 +1,4 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, BenchmarkId};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -108,7 +108,7 @@ fn bench_parallel_sorting(c: &mut Criterion) {
     let mut group = c.benchmark_group("parallel_sorting");
 
     for size in [50, 100, 500, 1000, 5000].iter() {
-        let mut names: Vec<String> = (0..*size)
+        let names: Vec<String> = (0..*size)
             .map(|i| format!("directory_name_{:04}", i))
             .collect();
 
@@ -221,12 +221,337 @@ fn bench_file_enumeration(c: &mut Criterion) {
     let _ = fs::remove_dir_all(&temp_dir);
 }
 
+/// Benchmark the size/latency tradeoff of compressed cache data files (--compress-cache)
+fn bench_cache_compression(c: &mut Criterion) {
+    use ptree_cache::cache_rkyv::{write_cache_files, RkyvCacheIndex, RkyvDirEntry, RkyvMmapCache};
+
+    let temp_dir = std::env::temp_dir().join("ptree_compression_bench");
+    let _ = fs::remove_dir_all(&temp_dir);
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    // Long, repetitive paths like a deep source tree
+    let fixture: Vec<RkyvDirEntry> = (0..200_000)
+        .map(|i| {
+            let path = PathBuf::from(format!(
+                "C:\\Users\\someone\\source\\repos\\project_{:03}\\node_modules\\package_{:04}\\lib\\dir_{}",
+                i % 97,
+                i % 1013,
+                i
+            ));
+            RkyvDirEntry {
+                name: format!("dir_{}", i),
+                path,
+                modified: chrono::Utc::now(),
+                content_hash: i as u64,
+                children: vec![format!("index_{}.js", i), "package.json".to_string()].into(),
+                symlink_target: None,
+                is_hidden: false,
+                is_dir: true,
+                size: 4096,
+                skipped_children: false,
+                is_mount: false,
+                access_error: None,
+                evicted: false,
+                file_count: 2,
+                dir_count: 0,
+                total_size: 0,
+                size_is_lower_bound: false,
+                spilled_children: 0,
+            }
+        })
+        .collect();
+    let probes: Vec<PathBuf> = fixture.iter().step_by(997).map(|entry| entry.path.clone()).collect();
+
+    let mut group = c.benchmark_group("cache_compression");
+    group.sample_size(10);
+
+    for compress in [false, true] {
+        let label = if compress { "zstd" } else { "raw" };
+        let index_path = temp_dir.join(format!("{}.idx", label));
+        let data_path = temp_dir.join(format!("{}.dat", label));
+        let entries = fixture.iter().cloned().map(Ok);
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), entries, compress, None, None).unwrap();
+        eprintln!(
+            "cache_compression/{}: {} entries, data file {} bytes",
+            label,
+            fixture.len(),
+            fs::metadata(&data_path).unwrap().len()
+        );
+
+        let cache = RkyvMmapCache::open(&index_path, &data_path).unwrap();
+        group.bench_function(BenchmarkId::new("get_entry", label), |b| {
+            b.iter(|| {
+                for path in &probes {
+                    black_box(cache.get_entry(path).unwrap());
+                }
+            })
+        });
+        group.bench_function(BenchmarkId::new("get_all", label), |b| {
+            b.iter(|| black_box(cache.get_all().unwrap().len()))
+        });
+    }
+
+    group.finish();
+    let _ = fs::remove_dir_all(&temp_dir);
+}
+
+/// Benchmark miss-heavy lookups (journal paths that were never cached) with and without the path filter
+fn bench_bloom_misses(c: &mut Criterion) {
+    use ptree_cache::cache_rkyv::{write_cache_files, RkyvCacheIndex, RkyvDirEntry, RkyvMmapCache};
+    use ptree_cache::{DiskCache, DEFAULT_BLOOM_FP_RATE};
+
+    let temp_dir = std::env::temp_dir().join("ptree_bloom_bench");
+    let _ = fs::remove_dir_all(&temp_dir);
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    let fixture: Vec<RkyvDirEntry> = (0..200_000)
+        .map(|i| {
+            let path = PathBuf::from(format!("/home/someone/src/project_{:03}/module_{:04}/dir_{}", i % 97, i % 1013, i));
+            RkyvDirEntry {
+                name: format!("dir_{}", i),
+                path,
+                modified: chrono::Utc::now(),
+                content_hash: i as u64,
+                children: vec![format!("file_{}.rs", i)].into(),
+                symlink_target: None,
+                is_hidden: false,
+                is_dir: true,
+                size: 4096,
+                skipped_children: false,
+                is_mount: false,
+                access_error: None,
+                evicted: false,
+                file_count: 1,
+                dir_count: 0,
+                total_size: 0,
+                size_is_lower_bound: false,
+                spilled_children: 0,
+            }
+        })
+        .collect();
+    // Files below cached directories: what a journal mostly reports
+    let misses: Vec<PathBuf> = fixture.iter().step_by(20).map(|entry| entry.path.join(&entry.children[0])).collect();
+
+    let mut group = c.benchmark_group("bloom_misses");
+    group.sample_size(10);
+
+    for filtered in [false, true] {
+        let label = if filtered { "bloom" } else { "no_bloom" };
+        let cache_path = temp_dir.join(format!("{}.dat", label));
+        let index_path = cache_path.with_extension("idx");
+        let entries = fixture.iter().cloned().map(Ok);
+        let fp_rate = filtered.then_some(DEFAULT_BLOOM_FP_RATE);
+        write_cache_files(&index_path, &cache_path, RkyvCacheIndex::new(), entries, false, None, fp_rate).unwrap();
+
+        let rkyv = RkyvMmapCache::open(&index_path, &cache_path).unwrap();
+        if let Some(bloom) = &rkyv.bloom {
+            group.bench_function("may_contain", |b| b.iter(|| misses.iter().filter(|path| bloom.may_contain(path)).count()));
+        }
+        group.bench_function(BenchmarkId::new("index_get_entry", label), |b| {
+            b.iter(|| misses.iter().filter(|path| rkyv.get_entry(path).unwrap().is_some()).count())
+        });
+        drop(rkyv);
+
+        let mut cache = DiskCache::open(&cache_path).unwrap();
+        cache.load_all_entries_lazy(&cache_path).unwrap();
+        group.bench_function(BenchmarkId::new("entries_get_entry", label), |b| {
+            b.iter(|| misses.iter().filter(|path| cache.get_entry(path).is_some()).count())
+        });
+    }
+
+    group.finish();
+    let _ = fs::remove_dir_all(&temp_dir);
+}
+
+/// Benchmark SipHash against the cache's FxHash maps on a million synthetic paths
+fn bench_path_hashing(c: &mut Criterion) {
+    use ptree_cache::FastHashMap;
+    use std::collections::HashMap;
+
+    let paths: Vec<PathBuf> = (0..1_000_000)
+        .map(|i| {
+            PathBuf::from(format!(
+                "C:\\Users\\someone\\source\\repos\\project_{:03}\\src\\module_{:04}\\dir_{}",
+                i % 97,
+                i % 1013,
+                i
+            ))
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("path_hashing");
+    group.sample_size(10);
+
+    group.bench_function("insert_siphash", |b| {
+        b.iter(|| {
+            let mut map = HashMap::with_capacity(paths.len());
+            for (i, path) in paths.iter().enumerate() {
+                map.insert(path.clone(), i as u64);
+            }
+            black_box(map.len())
+        })
+    });
+    group.bench_function("insert_fxhash", |b| {
+        b.iter(|| {
+            let mut map = FastHashMap::with_capacity_and_hasher(paths.len(), Default::default());
+            for (i, path) in paths.iter().enumerate() {
+                map.insert(path.clone(), i as u64);
+            }
+            black_box(map.len())
+        })
+    });
+
+    let siphash: HashMap<&Path, u64> = paths.iter().enumerate().map(|(i, path)| (path.as_path(), i as u64)).collect();
+    let fxhash: FastHashMap<&Path, u64> = paths.iter().enumerate().map(|(i, path)| (path.as_path(), i as u64)).collect();
+    group.bench_function("lookup_siphash", |b| {
+        b.iter(|| paths.iter().map(|path| siphash[path.as_path()]).sum::<u64>())
+    });
+    group.bench_function("lookup_fxhash", |b| {
+        b.iter(|| paths.iter().map(|path| fxhash[path.as_path()]).sum::<u64>())
+    });
+
+    group.finish();
+}
+
+fn bench_archived_rendering(c: &mut Criterion) {
+    use ptree_cache::cache_limcode::LimcodeCache;
+    use ptree_cache::{DirEntry, DiskCache};
+
+    // 100 directories of 1000 files below one root
+    let temp_dir = std::env::temp_dir().join(format!("ptree_bench_limcode_{}", std::process::id()));
+    fs::create_dir_all(&temp_dir).unwrap();
+    let (index_path, data_path) = (temp_dir.join("bench.limidx"), temp_dir.join("bench.limdat"));
+    let _ = fs::remove_file(&data_path);
+    let entry = |path: PathBuf, children: Vec<String>| DirEntry {
+        name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        is_dir: !children.is_empty(),
+        path,
+        modified: chrono::Utc::now(),
+        children: children.into(),
+        ..Default::default()
+    };
+    let mut cache = DiskCache::new();
+    cache.root = PathBuf::from("/bench");
+    let dirs: Vec<String> = (0..100).map(|i| format!("dir_{:03}", i)).collect();
+    cache.entries.insert(cache.root.clone(), entry(cache.root.clone(), dirs.clone()));
+    for dir in &dirs {
+        let dir_path = cache.root.join(dir);
+        let files: Vec<String> = (0..1000).map(|i| format!("file_{:04}.txt", i)).collect();
+        for file in &files {
+            cache.entries.insert(dir_path.join(file), entry(dir_path.join(file), Vec::new()));
+        }
+        cache.entries.insert(dir_path.clone(), entry(dir_path, files));
+    }
+
+    let mut writer = LimcodeCache::open(&index_path, &data_path).unwrap();
+    for entry in cache.entries.values() {
+        let offset = writer.append_entry(&entry.into()).unwrap();
+        writer.index.offsets.insert(entry.path.to_string_lossy().into_owned(), offset);
+    }
+    writer.save_index(&index_path).unwrap();
+    let source = LimcodeCache::open(&index_path, &data_path).unwrap();
+
+    let mut group = c.benchmark_group("archived_rendering");
+    group.sample_size(10);
+
+    group.bench_function("get_all_batch", |b| b.iter(|| black_box(source.get_all_batch().unwrap().len())));
+    group.bench_function("get_all_then_render", |b| {
+        b.iter(|| {
+            let mut loaded = DiskCache::new();
+            loaded.root = PathBuf::from("/bench");
+            loaded.entries.extend(source.get_all().unwrap());
+            black_box(loaded.build_tree_output().unwrap().len())
+        })
+    });
+    group.bench_function("render_streamed", |b| {
+        b.iter(|| {
+            let mut sink = std::io::sink();
+            cache.write_tree_streamed(&source, &mut sink, None, None).unwrap();
+        })
+    });
+
+    group.finish();
+    drop(source);
+    let _ = fs::remove_dir_all(&temp_dir);
+}
+
+/// Benchmark cold open and full save of the rkyv backend against Limcode (and SQLite, when built in)
+fn bench_cache_backends(c: &mut Criterion) {
+    use ptree_cache::{DirEntry, DiskCache};
+    use ptree_core::CacheBackend;
+
+    let temp_dir = std::env::temp_dir().join(format!("ptree_bench_backends_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&temp_dir);
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    // 500 directories of 100 subdirectories below one root
+    let entry = |path: PathBuf, children: Vec<String>| DirEntry {
+        name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        path,
+        modified: chrono::Utc::now(),
+        dir_count: children.len() as u32,
+        children: children.into(),
+        is_dir: true,
+        ..Default::default()
+    };
+    let mut fixture = DiskCache::new();
+    fixture.root = PathBuf::from("/bench");
+    let dirs: Vec<String> = (0..500).map(|i| format!("dir_{:03}", i)).collect();
+    fixture.entries.insert(fixture.root.clone(), entry(fixture.root.clone(), dirs.clone()));
+    for dir in &dirs {
+        let dir_path = fixture.root.join(dir);
+        let subdirs: Vec<String> = (0..100).map(|i| format!("sub_{:03}", i)).collect();
+        for sub in &subdirs {
+            fixture.entries.insert(dir_path.join(sub), entry(dir_path.join(sub), Vec::new()));
+        }
+        fixture.entries.insert(dir_path.clone(), entry(dir_path, subdirs));
+    }
+
+    let mut group = c.benchmark_group("cache_backends");
+    group.sample_size(10);
+
+    let mut backends = vec![CacheBackend::Rkyv, CacheBackend::Limcode];
+    if cfg!(feature = "sqlite") {
+        backends.push(CacheBackend::Sqlite);
+    }
+    for backend in backends {
+        let label = format!("{:?}", backend).to_lowercase();
+        let cache_path = temp_dir.join(&label).join("ptree.dat");
+        let mut cache = fixture.clone();
+        cache.backend = backend;
+        cache.clone().save(&cache_path).unwrap();
+
+        // A cache never loaded from disk is written whole every time
+        group.bench_function(BenchmarkId::new("full_save", &label), |b| {
+            b.iter_batched(|| cache.clone(), |mut cache| cache.save(&cache_path).unwrap(), BatchSize::LargeInput)
+        });
+        group.bench_function(BenchmarkId::new("cold_open", &label), |b| {
+            b.iter(|| black_box(DiskCache::open(&cache_path).unwrap().root))
+        });
+        group.bench_function(BenchmarkId::new("cold_open_load_all", &label), |b| {
+            b.iter(|| {
+                let mut opened = DiskCache::open(&cache_path).unwrap();
+                opened.load_all_entries_lazy(&cache_path).unwrap();
+                black_box(opened.entries.len())
+            })
+        });
+    }
+
+    group.finish();
+    let _ = fs::remove_dir_all(&temp_dir);
+}
+
 criterion_group!(
     benches,
     bench_tree_traversal,
     bench_directory_sorting,
     bench_parallel_sorting,
     bench_cache_operations,
-    bench_file_enumeration
+    bench_file_enumeration,
+    bench_cache_compression,
+    bench_bloom_misses,
+    bench_path_hashing,
+    bench_archived_rendering,
+    bench_cache_backends
 );
 criterion_main!(benches);
@@ -0,0 +1,279 @@
+use crate::cache::{DirEntry, DiskCache, USNJournalState};
+use crate::merge::MergePolicy;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use ptree_core::PTreeError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+// ============================================================================
+// Portable Archives (ptree export / import)
+// ============================================================================
+
+/// First bytes of every archive
+pub const ARCHIVE_MAGIC: &[u8; 8] = b"PTREEARC";
+
+/// Archive layout version; readers reject any other value
+///
+/// - 1: first layout
+/// - 2: entries carry `evicted`, `file_count` and `dir_count`
+/// - 3: entries carry their child names packed (`ChildNames`)
+pub const ARCHIVE_VERSION: u16 = 3;
+
+/// Payload compression: zstd, the only scheme so far
+const COMPRESSION_ZSTD: u8 = 1;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Everything needed to rebuild a `DiskCache` on another machine
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivePayload {
+    root: PathBuf,
+    last_scanned_root: PathBuf,
+    last_scan: DateTime<Utc>,
+    skip_stats: HashMap<String, usize>,
+    usn_state: USNJournalState,
+    entries: Vec<DirEntry>,
+}
+
+impl DiskCache {
+    /// Write the whole cache as one self-describing archive
+    ///
+    /// Layout: `ARCHIVE_MAGIC`, `ARCHIVE_VERSION` (u16 LE), a compression byte,
+    /// then the zstd-compressed bincode payload. Unlike the `.idx`/`.dat`
+    /// pair, the archive needs nothing else to be read back.
+    pub fn write_archive<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut entries: Vec<DirEntry> = self.entries.values().cloned().collect();
+        entries.extend(self.pending_writes.iter().map(|(_, entry)| entry.clone()));
+
+        let payload = ArchivePayload {
+            root: self.root.clone(),
+            last_scanned_root: self.last_scanned_root.clone(),
+            last_scan: self.last_scan,
+            skip_stats: self.skip_stats.clone(),
+            usn_state: self.usn_position(),
+            entries,
+        };
+
+        writer.write_all(ARCHIVE_MAGIC)?;
+        writer.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+        writer.write_all(&[COMPRESSION_ZSTD])?;
+        let mut encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+        bincode::serialize_into(&mut encoder, &payload)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Read an archive written by `write_archive` into an in-memory cache
+    ///
+    /// Fails with `PTreeError::Cache` for anything that is not an archive or
+    /// was written with another `ARCHIVE_VERSION`.
+    pub fn read_archive<R: Read>(mut reader: R) -> Result<DiskCache> {
+        let mut header = [0u8; 11];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| PTreeError::Cache("not a ptree archive (file too short)".to_string()))?;
+        if &header[..8] != ARCHIVE_MAGIC {
+            return Err(PTreeError::Cache("not a ptree archive (bad magic number)".to_string()).into());
+        }
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version != ARCHIVE_VERSION {
+            return Err(PTreeError::Cache(format!(
+                "archive version {} is not supported (this ptree reads version {})",
+                version, ARCHIVE_VERSION
+            ))
+            .into());
+        }
+        if header[10] != COMPRESSION_ZSTD {
+            return Err(PTreeError::Cache(format!("unknown archive compression {}", header[10])).into());
+        }
+
+        let payload: ArchivePayload = bincode::deserialize_from(zstd::Decoder::new(reader)?)
+            .map_err(|e| PTreeError::Cache(format!("corrupt archive: {}", e)))?;
+
+        let mut cache = DiskCache::new();
+        cache.root = payload.root;
+        cache.last_scanned_root = payload.last_scanned_root;
+        cache.last_scan = payload.last_scan;
+        cache.skip_stats = payload.skip_stats;
+        cache.set_usn_position(payload.usn_state);
+        cache.entries = payload
+            .entries
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        Ok(cache)
+    }
+}
+
+/// What `import_cache` did with each incoming entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Paths that were not cached locally
+    pub added: usize,
+    /// Local entries replaced by the incoming one
+    pub updated: usize,
+    /// Incoming entries dropped because the local one is at least as new
+    pub skipped: usize,
+}
+
+impl DiskCache {
+    /// Merge another cache (usually from `read_archive`) into this one
+    ///
+    /// New paths are added; for paths present in both, the entry with the
+    /// newer `modified` wins, or always the incoming one with `replace`.
+    /// Afterwards every imported entry is listed in its parent's `children`,
+    /// so subtrees imported below a cached directory show up in the tree,
+    /// and the child lists are merged (see `merge`): directories cached only
+    /// locally stay listed under an imported parent that replaced theirs.
+    pub fn import_cache(&mut self, other: DiskCache, replace: bool) -> ImportSummary {
+        let policy = if replace { MergePolicy::OtherWins } else { MergePolicy::NewestWins };
+        self.merge(other, policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use std::path::Path;
+
+    fn entry(path: &str, children: &[&str]) -> DirEntry {
+        DirEntry {
+            modified: DateTime::parse_from_rfc3339("2024-05-06T07:08:09.123456789Z").unwrap().with_timezone(&Utc),
+            content_hash: 0xDEAD_BEEF,
+            ..test_entry(path, children)
+        }
+    }
+
+    #[test]
+    fn test_round_trip_keeps_every_field() -> Result<()> {
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        cache.last_scanned_root = PathBuf::from("/scan/sub");
+        cache.skip_stats.insert(".git".to_string(), 4);
+
+        let mut hidden = entry("/scan/.cache", &[]);
+        hidden.is_hidden = true;
+        let mut link = entry("/scan/link", &[]);
+        link.symlink_target = Some(PathBuf::from("/elsewhere"));
+        link.is_dir = false;
+        for e in [entry("/scan", &[".cache", "link"]), hidden, link] {
+            cache.entries.insert(e.path.clone(), e);
+        }
+
+        let mut bytes = Vec::new();
+        cache.write_archive(&mut bytes)?;
+        assert_eq!(&bytes[..8], ARCHIVE_MAGIC);
+
+        let restored = DiskCache::read_archive(bytes.as_slice())?;
+        assert_eq!(restored.root, cache.root);
+        assert_eq!(restored.last_scanned_root, cache.last_scanned_root);
+        assert_eq!(restored.last_scan, cache.last_scan);
+        assert_eq!(restored.skip_stats, cache.skip_stats);
+        assert_eq!(restored.entries.len(), 3);
+        for (path, original) in &cache.entries {
+            let copy = &restored.entries[path];
+            assert_eq!(copy.path, original.path);
+            assert_eq!(copy.name, original.name);
+            assert_eq!(copy.modified, original.modified);
+            assert_eq!(copy.content_hash, original.content_hash);
+            assert_eq!(copy.children, original.children);
+            assert_eq!(copy.symlink_target, original.symlink_target);
+            assert_eq!(copy.is_hidden, original.is_hidden);
+            assert_eq!(copy.is_dir, original.is_dir);
+        }
+        assert!(restored.entries[Path::new("/scan/.cache")].is_hidden);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_prefers_newer_entries_and_links_new_subtrees() {
+        let older = DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let mut local = DiskCache::new();
+        local.root = PathBuf::from("/scan");
+        local.entries.insert(PathBuf::from("/scan"), entry("/scan", &["a"]));
+        let mut stale = entry("/scan/a", &[]);
+        stale.modified = older;
+        local.entries.insert(stale.path.clone(), stale);
+
+        let mut incoming = DiskCache::new();
+        let mut old_root = entry("/scan", &["a", "b"]);
+        old_root.modified = older;
+        for e in [old_root, entry("/scan/a", &["x"]), entry("/scan/b", &[]), entry("/other", &[])] {
+            incoming.entries.insert(e.path.clone(), e);
+        }
+
+        let summary = local.import_cache(incoming, false);
+        assert_eq!(summary, ImportSummary { added: 2, updated: 1, skipped: 1 });
+        assert_eq!(local.entries[Path::new("/scan/a")].children, ["x"]);
+        // The local root was newer and kept, yet still links the imported child
+        assert_eq!(local.entries[Path::new("/scan")].children, ["a", "b"]);
+        assert!(local.entries.contains_key(Path::new("/other")));
+    }
+
+    #[test]
+    fn test_import_keeps_local_only_directories_listed() {
+        for replace in [false, true] {
+            let mut local = DiskCache::new();
+            local.root = PathBuf::from("/scan");
+            let mut old_root = entry("/scan", &["a", "local"]);
+            old_root.modified = DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+            for e in [old_root, entry("/scan/a", &[]), entry("/scan/local", &["deep"]), entry("/scan/local/deep", &[])] {
+                local.entries.insert(e.path.clone(), e);
+            }
+
+            let mut incoming = DiskCache::new();
+            for e in [entry("/scan", &["a", "b"]), entry("/scan/a", &[]), entry("/scan/b", &[])] {
+                incoming.entries.insert(e.path.clone(), e);
+            }
+
+            local.import_cache(incoming, replace);
+            assert_eq!(local.entries[Path::new("/scan")].children, ["a", "b", "local"], "replace: {}", replace);
+            assert_eq!(local.entries[Path::new("/scan/local")].children, ["deep"]);
+            let tree = local.build_tree_output().unwrap();
+            assert!(tree.contains("local\n") && tree.contains("deep\n"), "{}", tree);
+
+            // Saving keeps only what parents list, so the local subtree must survive it
+            let dir = std::env::temp_dir().join(format!("ptree_import_local_{}_{}", std::process::id(), replace));
+            let _ = std::fs::remove_dir_all(&dir);
+            let cache_path = dir.join("ptree.dat");
+            local.save(&cache_path).unwrap();
+            let mut reopened = DiskCache::open(&cache_path).unwrap();
+            reopened.load_all_entries_lazy(&cache_path).unwrap();
+            assert!(reopened.entries.contains_key(Path::new("/scan/local/deep")));
+            assert!(reopened.entries.contains_key(Path::new("/scan/b")));
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[test]
+    fn test_import_replace_overwrites_newer_entries() {
+        let mut local = DiskCache::new();
+        local.entries.insert(PathBuf::from("/scan"), entry("/scan", &["kept"]));
+        let mut incoming = DiskCache::new();
+        let mut older = entry("/scan", &[]);
+        older.modified = DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        incoming.entries.insert(older.path.clone(), older);
+
+        let summary = local.import_cache(incoming, true);
+        assert_eq!(summary.updated, 1);
+        assert!(local.entries[Path::new("/scan")].children.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_foreign_files_and_other_versions() -> Result<()> {
+        let err = DiskCache::read_archive(&b"not an archive at all"[..]).unwrap_err();
+        assert!(err.to_string().contains("bad magic"), "{}", err);
+
+        let mut bytes = Vec::new();
+        DiskCache::new().write_archive(&mut bytes)?;
+        bytes[8..10].copy_from_slice(&(ARCHIVE_VERSION + 1).to_le_bytes());
+        let err = DiskCache::read_archive(bytes.as_slice()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::Cache(_))));
+        assert!(err.to_string().contains(&format!("version {}", ARCHIVE_VERSION + 1)), "{}", err);
+        Ok(())
+    }
+}
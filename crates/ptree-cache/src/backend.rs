@@ -0,0 +1,170 @@
+use crate::cache::DiskCache;
+use crate::cache_kv::{is_kv_cache, kv_path};
+use crate::cache_limcode::{is_limcode_cache, limcode_paths};
+use crate::cache_sqlite::{is_sqlite_cache, sqlite_path};
+use anyhow::Result;
+use ptree_core::CacheBackend;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Cache Backends (--backend)
+// ============================================================================
+
+/// Files a backend keeps for the cache path `cache_path`
+pub fn backend_files(backend: CacheBackend, cache_path: &Path) -> Vec<PathBuf> {
+    match backend {
+        CacheBackend::Rkyv => vec![cache_path.with_extension("idx"), cache_path.with_extension("dat")],
+        CacheBackend::Sqlite => vec![sqlite_path(cache_path)],
+        CacheBackend::Kv => vec![kv_path(cache_path)],
+        CacheBackend::Limcode => {
+            let (index, data) = limcode_paths(cache_path);
+            vec![index, data]
+        }
+    }
+}
+
+/// Backend the cache at `cache_path` was saved with, or None when nothing is saved there
+///
+/// The database and Limcode backends are recognised by their file headers,
+/// so a half-written or foreign file under their name is not mistaken for one.
+pub fn stored_backend(cache_path: &Path) -> Option<CacheBackend> {
+    if is_sqlite_cache(cache_path) {
+        Some(CacheBackend::Sqlite)
+    } else if is_kv_cache(cache_path) {
+        Some(CacheBackend::Kv)
+    } else if is_limcode_cache(cache_path) {
+        Some(CacheBackend::Limcode)
+    } else if cache_path.with_extension("idx").exists() {
+        Some(CacheBackend::Rkyv)
+    } else {
+        None
+    }
+}
+
+/// Remove the files other backends left for `cache_path`, once `kept` has saved it
+///
+/// Failures are logged rather than returned: the save itself succeeded.
+pub(crate) fn remove_other_backends(cache_path: &Path, kept: CacheBackend) {
+    let others = [CacheBackend::Rkyv, CacheBackend::Sqlite, CacheBackend::Kv, CacheBackend::Limcode]
+        .into_iter()
+        .filter(|&backend| backend != kept)
+        .flat_map(|backend| backend_files(backend, cache_path));
+    for file in others.filter(|file| file.is_file()) {
+        match std::fs::remove_file(&file) {
+            Ok(()) => log::info!("removed {}, left from another cache backend", file.display()),
+            Err(e) => log::warn!("cannot remove {}: {}", file.display(), e),
+        }
+    }
+}
+
+impl DiskCache {
+    /// Switch the storage `save` writes to, loading every entry first when it changes
+    ///
+    /// A cache opened from one backend holds its metadata only; saving it
+    /// through another has to write every entry, so they are loaded from
+    /// `path` and the cache is marked to be saved whole.
+    pub fn set_backend(&mut self, backend: CacheBackend, path: &Path) -> Result<()> {
+        if backend == self.backend {
+            return Ok(());
+        }
+        self.load_all_entries_lazy(path)?;
+        self.backend = backend;
+        self.dirty = true;
+        self.changed = None;
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "sqlite", feature = "kv"))]
+pub(crate) use meta::BackendMeta;
+
+#[cfg(any(feature = "sqlite", feature = "kv"))]
+mod meta {
+    use crate::cache::DiskCache;
+    use anyhow::Result;
+    use chrono::{DateTime, Utc};
+    use ptree_core::{CacheBackend, PTreeError};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    /// Scan metadata the database backends keep beside their entries, as key/value strings
+    ///
+    /// What the rkyv backend keeps in its index, less the offsets.
+    pub(crate) struct BackendMeta {
+        pub root: PathBuf,
+        pub last_scanned_root: PathBuf,
+        pub last_scan: DateTime<Utc>,
+        pub skip_stats: HashMap<String, usize>,
+        #[cfg(windows)]
+        pub usn_state: crate::cache::USNJournalState,
+    }
+
+    impl BackendMeta {
+        /// `cache`'s metadata as the pairs to store, `schema_version` first
+        pub(crate) fn pairs(cache: &DiskCache, schema_version: u32) -> Result<Vec<(&'static str, String)>> {
+            Ok(vec![
+                ("schema_version", schema_version.to_string()),
+                ("root", cache.root.to_string_lossy().into_owned()),
+                ("last_scanned_root", cache.last_scanned_root.to_string_lossy().into_owned()),
+                ("last_scan", cache.last_scan.to_rfc3339()),
+                ("skip_stats", serde_json::to_string(&cache.skip_stats)?),
+                #[cfg(windows)]
+                ("usn_state", serde_json::to_string(&cache.usn_state)?),
+            ])
+        }
+
+        /// Check the stored schema version against `supported`, failing with `CacheVersion` for a newer one
+        pub(crate) fn check_version(get: impl Fn(&str) -> Result<Option<String>>, supported: u32) -> Result<()> {
+            if let Some(found) = get("schema_version")? {
+                let found: u32 = found.parse().unwrap_or(u32::MAX);
+                if found > supported {
+                    return Err(PTreeError::CacheVersion { found, supported }.into());
+                }
+            }
+            Ok(())
+        }
+
+        /// Read the pairs back through `get`; None when nothing was saved yet
+        pub(crate) fn read(get: impl Fn(&str) -> Result<Option<String>>) -> Result<Option<Self>> {
+            let Some(root) = get("root")? else {
+                return Ok(None);
+            };
+            let corrupt = |key: &str, e: &dyn std::fmt::Display| PTreeError::CacheCorrupt(format!("meta {}: {}", key, e));
+            let last_scan = get("last_scan")?.unwrap_or_default();
+            Ok(Some(BackendMeta {
+                root: PathBuf::from(root),
+                last_scanned_root: PathBuf::from(get("last_scanned_root")?.unwrap_or_default()),
+                last_scan: DateTime::parse_from_rfc3339(&last_scan)
+                    .map_err(|e| corrupt("last_scan", &e))?
+                    .with_timezone(&Utc),
+                skip_stats: match get("skip_stats")? {
+                    Some(json) => serde_json::from_str(&json).map_err(|e| corrupt("skip_stats", &e))?,
+                    None => HashMap::new(),
+                },
+                #[cfg(windows)]
+                usn_state: match get("usn_state")? {
+                    Some(json) => serde_json::from_str(&json).map_err(|e| corrupt("usn_state", &e))?,
+                    None => Default::default(),
+                },
+            }))
+        }
+
+        /// A cache opened from `backend` with this metadata and no entries loaded yet
+        pub(crate) fn into_cache(self, backend: CacheBackend) -> DiskCache {
+            DiskCache {
+                entries: crate::intern::EntryMap::new(),
+                last_scan: self.last_scan,
+                root: self.root,
+                last_scanned_root: self.last_scanned_root,
+                #[cfg(windows)]
+                usn_state: self.usn_state,
+                skip_stats: self.skip_stats,
+                dirty: false,
+                changed: Some(Default::default()),
+                backend,
+                load: crate::cache::CacheLoad::Loaded,
+                ..DiskCache::new_empty()
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::cache::DiskCache;
+use anyhow::Result;
+use parking_lot::{Condvar, Mutex};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Background Saving (output first, save while it renders)
+// ============================================================================
+
+/// A cache save running on its own thread, started by `DiskCache::save_in_background`
+///
+/// Join it with `wait` to see how it went. Dropping it waits for the save
+/// as well (an early error return still leaves a complete cache), ignoring
+/// its outcome.
+pub struct BackgroundSave {
+    /// Taken by `wait`
+    handle: Option<JoinHandle<Result<Duration>>>,
+    state: Arc<SaveState>,
+}
+
+/// Stops a `BackgroundSave` from another thread, e.g. a Ctrl+C handler
+#[derive(Clone)]
+pub struct SaveCanceller(Arc<SaveState>);
+
+struct SaveState {
+    cancel: AtomicBool,
+    finished: Mutex<bool>,
+    done: Condvar,
+}
+
+impl DiskCache {
+    /// Save `cache` to `path` on a new thread while the caller goes on reading it
+    ///
+    /// Call `prepare_save` before sharing the cache. The files are written
+    /// as by `save`, but the shared cache is not marked clean afterwards.
+    pub fn save_in_background(cache: &Arc<DiskCache>, path: PathBuf) -> BackgroundSave {
+        let state = Arc::new(SaveState { cancel: AtomicBool::new(false), finished: Mutex::new(false), done: Condvar::new() });
+        let handle = {
+            let (cache, state) = (Arc::clone(cache), Arc::clone(&state));
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                let result = cache.write_files(&path, &state.cancel).map(|()| start.elapsed());
+                *state.finished.lock() = true;
+                state.done.notify_all();
+                result
+            })
+        };
+        BackgroundSave { handle: Some(handle), state }
+    }
+}
+
+impl BackgroundSave {
+    /// A handle that can cancel this save from any thread
+    pub fn canceller(&self) -> SaveCanceller {
+        SaveCanceller(Arc::clone(&self.state))
+    }
+
+    /// Wait for the save to finish, returning how long it took
+    pub fn wait(mut self) -> Result<Duration> {
+        let handle = self.handle.take().expect("joined only once");
+        match handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Drop for BackgroundSave {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            if let Ok(Err(e)) = handle.join() {
+                log::warn!("background cache save failed: {:#}", e);
+            }
+        }
+    }
+}
+
+impl SaveCanceller {
+    /// Stop the save and block until its thread is done
+    ///
+    /// A save cancelled before its files were renamed into place leaves the
+    /// previous pair as it was; one that already finished stays saved.
+    pub fn cancel(&self) {
+        self.0.cancel.store(true, Ordering::Relaxed);
+        let mut finished = self.0.finished.lock();
+        while !*finished {
+            self.0.done.wait(&mut finished);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use crate::cache::DirEntry;
+    use std::fs;
+    use std::path::Path;
+
+    fn cache(dirs: usize) -> DiskCache {
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        let names: Vec<String> = (0..dirs).map(|i| format!("d{}", i)).collect();
+        for path in std::iter::once(PathBuf::from("/scan")).chain(names.iter().map(|name| Path::new("/scan").join(name))) {
+            let children = if path == Path::new("/scan") { names.clone() } else { Vec::new() };
+            cache.add_entry(path.clone(), DirEntry { children: children.into(), ..test_entry(&path, &[]) });
+        }
+        cache
+    }
+
+    #[test]
+    fn test_background_save_writes_while_the_cache_is_read() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_background_save_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join("ptree.dat");
+
+        let mut cache = cache(100);
+        cache.prepare_save();
+        let cache = Arc::new(cache);
+        let save = DiskCache::save_in_background(&cache, cache_path.clone());
+        assert!(cache.build_tree_output()?.contains("d99"));
+        save.wait()?;
+
+        let mut loaded = DiskCache::open(&cache_path)?;
+        loaded.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(loaded.entries.len(), 101);
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancelled_save_keeps_the_previous_pair() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_background_cancel_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join("ptree.dat");
+        cache(1).save(&cache_path)?;
+        let data = fs::read(cache_path.with_extension("dat"))?;
+
+        let mut cache = cache(10);
+        cache.prepare_save();
+        let cache = Arc::new(cache);
+        // Holding the lock keeps the save from writing anything before it is cancelled
+        let lock = crate::lock::CacheLock::exclusive(&cache_path)?;
+        let save = DiskCache::save_in_background(&cache, cache_path.clone());
+        save.state.cancel.store(true, Ordering::Relaxed);
+        drop(lock);
+        save.canceller().cancel();
+
+        assert!(save.wait().is_err());
+        assert_eq!(fs::read(cache_path.with_extension("dat"))?, data);
+        assert!(!cache_path.with_extension("dat.tmp").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
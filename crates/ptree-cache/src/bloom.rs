@@ -0,0 +1,210 @@
+use crate::cache::DiskCache;
+use anyhow::Result;
+use ptree_core::CacheBackend;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Bloom Filter over Cached Paths
+// ============================================================================
+
+/// False-positive rate a cache's filter is built for unless `--bloom-fp-rate` says otherwise
+pub const DEFAULT_BLOOM_FP_RATE: f64 = 0.01;
+
+/// Most hash functions a filter uses, however low its rate
+const MAX_HASHES: u32 = 16;
+
+/// Words in a block: a path's bits all fall in one 64-byte cache line
+const BLOCK_WORDS: usize = 8;
+
+/// Set of paths answering "certainly not here" or "maybe here"
+///
+/// Lookups of paths that mostly aren't cached (files the journal reports,
+/// names a search tries) are ruled out with one hash of the path and a few
+/// bit probes, before any map or offset lookup. Paths are never removed;
+/// a removed path only adds to the false positives until the filter is
+/// rebuilt (a whole save or compaction).
+///
+/// Each path is hashed once, to a 512-bit block and the bit positions
+/// within it, so a lookup touches one cache line.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PathBloom {
+    bits: Vec<u64>,
+    hashes: u32,
+    /// Rate the filter was sized for
+    fp_rate: f64,
+    /// Paths inserted, counting repeats
+    items: u64,
+}
+
+/// What `ptree cache-info` reports about a filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSummary {
+    pub bytes: usize,
+    /// Rate the filter was sized for, and the rate expected at its current fill
+    pub fp_rate: f64,
+    pub estimated_fp_rate: f64,
+}
+
+impl PathBloom {
+    /// An empty filter sized for `expected` paths at `fp_rate`
+    pub fn new(expected: usize, fp_rate: f64) -> Self {
+        let fp_rate = fp_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(expected.max(1) as f64) * fp_rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let words = bits.div_ceil(64).next_multiple_of(BLOCK_WORDS);
+        let hashes = ((words as f64 * 64.0 / expected.max(1) as f64) * ln2).round().clamp(1.0, MAX_HASHES as f64) as u32;
+        PathBloom { bits: vec![0; words], hashes, fp_rate, items: 0 }
+    }
+
+    /// A filter for exactly `paths`
+    pub fn from_paths<'a>(paths: impl ExactSizeIterator<Item = &'a Path>, fp_rate: f64) -> Self {
+        let mut bloom = Self::new(paths.len(), fp_rate);
+        for path in paths {
+            bloom.insert(path);
+        }
+        bloom
+    }
+
+    pub fn insert(&mut self, path: &Path) {
+        let (block, probes) = self.probes(path);
+        for bit in probes {
+            self.bits[block + bit / 64] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// False only when `path` was never inserted
+    pub fn may_contain(&self, path: &Path) -> bool {
+        let (block, mut probes) = self.probes(path);
+        probes.all(|bit| self.bits[block + bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Rate the filter was sized for
+    pub fn fp_rate(&self) -> f64 {
+        self.fp_rate
+    }
+
+    /// Rate expected with the paths inserted so far; grows past `fp_rate`
+    /// as delta saves add paths beyond the ones it was sized for
+    pub fn estimated_fp_rate(&self) -> f64 {
+        let fill = -(self.hashes as f64) * self.items as f64 / self.len_bits() as f64;
+        (1.0 - fill.exp()).powi(self.hashes as i32)
+    }
+
+    pub fn summary(&self) -> BloomSummary {
+        BloomSummary { bytes: self.bits.len() * 8, fp_rate: self.fp_rate, estimated_fp_rate: self.estimated_fp_rate() }
+    }
+
+    fn len_bits(&self) -> usize {
+        self.bits.len() * 64
+    }
+
+    /// First word of `path`'s block, and its `hashes` bit positions within the block
+    fn probes(&self, path: &Path) -> (usize, impl Iterator<Item = usize>) {
+        let hash = path_hash(path);
+        let blocks = (self.bits.len() / BLOCK_WORDS) as u64;
+        let block = ((hash as u128 * blocks as u128) >> 64) as usize * BLOCK_WORDS;
+        // An odd step visits distinct bits of the power-of-two block
+        let second = mix(hash ^ 0x9E37_79B9_7F4A_7C15);
+        let (first, step) = (second as usize, (second >> 32) as usize | 1);
+        let bits = BLOCK_WORDS * 64;
+        (block, (0..self.hashes as usize).map(move |i| first.wrapping_add(i.wrapping_mul(step)) % bits))
+    }
+}
+
+/// 64-bit hash of `path`, alike for paths `PathBuf` considers equal
+///
+/// `/a/b/`, `/a//b` and `/a/./b` are the path `/a/b` to the index, so
+/// the bytes hashed are those of the path rebuilt from its components.
+/// Paths that surely are in that form already (nearly all) are hashed as
+/// they are, several times faster than splitting them into components.
+/// The hash is CRC32, fixed here so a filter saved by one build reads the
+/// same in any other.
+fn path_hash(path: &Path) -> u64 {
+    let hash = |bytes: &[u8]| mix(crc32fast::hash(bytes) as u64 ^ ((bytes.len() as u64) << 32));
+    let bytes = path.as_os_str().as_encoded_bytes();
+    if may_be_uncanonical(bytes) {
+        hash(path.components().collect::<PathBuf>().as_os_str().as_encoded_bytes())
+    } else {
+        hash(bytes)
+    }
+}
+
+/// Whether `bytes` may differ from the path its components rebuild
+///
+/// True for a trailing separator, a `/` on Windows, and any separator
+/// followed by another or by `.`, which includes hidden names like
+/// `.git` that are canonical after all. Checked without branching, so the
+/// scan vectorizes.
+fn may_be_uncanonical(bytes: &[u8]) -> bool {
+    let separator = |byte: u8| (byte == b'/') | (cfg!(windows) & (byte == b'\\'));
+    let pairs = bytes.iter().zip(&bytes[1.min(bytes.len())..]);
+    let suspect = pairs.fold(false, |suspect, (&previous, &byte)| {
+        suspect | (separator(previous) & (separator(byte) | (byte == b'.')))
+    });
+    let trailing = bytes.len() > 1 && separator(bytes[bytes.len() - 1]);
+    suspect || trailing || cfg!(windows) && bytes.contains(&b'/')
+}
+
+/// Spread every input bit over the output (MurmurHash3's finalizer)
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+    x ^ (x >> 33)
+}
+
+impl DiskCache {
+    /// Change the rate the saved filter is built for (`--bloom-fp-rate`), loading every entry first
+    ///
+    /// Delta saves only add to the filter already saved, so a new rate has
+    /// the cache saved whole, which needs its entries loaded from `path`.
+    /// The database backends keep no filter and only remember the rate.
+    pub fn set_bloom_fp_rate(&mut self, fp_rate: f64, path: &Path) -> Result<()> {
+        if fp_rate == self.bloom_fp_rate {
+            return Ok(());
+        }
+        self.bloom_fp_rate = fp_rate;
+        if self.backend == CacheBackend::Rkyv && self.changed.is_some() {
+            self.load_all_entries_lazy(path)?;
+            self.dirty = true;
+            self.changed = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inserted_paths_are_never_ruled_out_and_misses_mostly_are() {
+        let mut paths: Vec<PathBuf> = (0..10_000).map(|i| PathBuf::from(format!("/scan/dir{}/sub", i))).collect();
+        paths.extend([PathBuf::from("/scan/.git/objects"), PathBuf::from("relative/../name")]);
+        let bloom = PathBloom::from_paths(paths.iter().map(PathBuf::as_path), 0.01);
+        assert!(paths.iter().all(|path| bloom.may_contain(path)));
+        for (same, inserted) in [
+            ("/scan//dir7/sub/", "/scan/dir7/sub"),
+            ("/scan/./dir7/sub", "/scan/dir7/sub"),
+            ("/scan/dir7/sub/.", "/scan/dir7/sub"),
+            ("/scan/.git//objects", "/scan/.git/objects"),
+            ("relative/./../name/", "relative/../name"),
+        ] {
+            assert_eq!(Path::new(same), Path::new(inserted));
+            assert!(bloom.may_contain(Path::new(same)), "{}", same);
+        }
+
+        let false_positives = (0..10_000)
+            .filter(|i| bloom.may_contain(Path::new(&format!("/scan/dir{}/file.txt", i))))
+            .count();
+        assert!(false_positives < 300, "{} false positives in 10000", false_positives);
+        let summary = bloom.summary();
+        assert!((summary.estimated_fp_rate - 0.01).abs() < 0.005, "{:?}", summary);
+
+        let decoded: PathBloom = bincode::deserialize(&bincode::serialize(&bloom).unwrap()).unwrap();
+        assert_eq!(decoded, bloom);
+    }
+}
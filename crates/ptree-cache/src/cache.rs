@@ -1,35 +1,392 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::{self, File};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use chrono::{DateTime, Utc};
 use anyhow::Result;
+use crate::cache_rkyv::{compact_cache_files, CompactStats};
+use crate::backend::{remove_other_backends, stored_backend};
+use crate::bloom::{PathBloom, DEFAULT_BLOOM_FP_RATE};
+use crate::spill::DEFAULT_SPILL_CHILDREN;
+use crate::children::ChildNames;
+use crate::crypt::CacheKey;
+use crate::intern::EntryMap;
+use crate::lock::CacheLock;
+use crate::snapshot::{CacheDiff, Snapshot};
 use serde_json::json;
 use colored::Colorize;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use rayon::slice::ParallelSliceMut;
+use ptree_core::{CacheBackend, Charset, MtimeFormat, PTreeError, SortKey};
+use crate::theme::{HeatLevel, HeatmapThresholds, Theme};
+use crate::format::{EntryContext, EntryTemplate};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::OnceLock;
+use unicode_width::UnicodeWidthChar;
 
-#[cfg(windows)]
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct USNJournalState;
+/// Hash map for cache keys (paths, path components), using FxHash
+///
+/// Cache maps never see untrusted keys, so SipHash's resistance to
+/// collision attacks buys nothing and costs a good part of every load.
+/// The one place to change if the hasher is swapped.
+pub type FastHashMap<K, V> = HashMap<K, V, rustc_hash::FxBuildHasher>;
 
-#[cfg(not(windows))]
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct USNJournalState;
+/// Position in a drive's USN journal, saved so change tracking resumes where it stopped
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct USNJournalState {
+    /// Journal instance the position belongs to (a new ID means the journal was reset)
+    pub journal_id: u64,
+    /// Last USN already processed (0 = none yet)
+    pub last_usn: i64,
+}
 
 /// Directory metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// `Default` is an empty, non-directory entry modified at the Unix epoch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DirEntry {
     pub path: PathBuf,
     pub name: String,
     pub modified: DateTime<Utc>,
     pub content_hash: u64, // NEW FIELD - Merkle tree hash for change detection
-    pub children: Vec<String>, // child names only, not full paths
+    pub children: ChildNames, // child names only, not full paths
     pub symlink_target: Option<PathBuf>, // If this entry is a symlink, store target
     pub is_hidden: bool, // Whether the directory has hidden attribute
     pub is_dir: bool, // Whether this entry is a directory (vs file/symlink)
+    pub size: u64, // File size in bytes (0 for directories and symlinks)
+    pub skipped_children: bool, // Some children were skipped or unreadable during traversal
+    pub is_mount: bool, // Another volume is mounted here; left unscanned by --one-file-system
+    pub access_error: Option<String>, // Why listing this directory failed, if it did
+    pub evicted: bool, // Descendants dropped to fit --max-cache-bytes; rescanned when --subtree needs them
+    pub file_count: u32, // Immediate children that are files or symlinks (0 in caches older than format 6)
+    pub dir_count: u32, // Immediate subdirectories, mount points included
+    pub total_size: u64, // Bytes of everything below (a file's own size); see DiskCache::compute_totals
+    pub size_is_lower_bound: bool, // Part of the subtree was skipped or unreadable, so total_size is a minimum
+    #[serde(skip)]
+    pub generation: u64, // Traversal that last visited the entry (DiskCache::scan_generation); never saved
+    #[serde(skip)]
+    pub spilled_children: u32, // Names a shallow read left in the spill record (children is then empty); see RkyvMmapCache::get_entry_shallow
+}
+
+/// Display options consulted by the output builders (never persisted)
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Append each entry's stored modification time in this format
+    pub mtime: Option<MtimeFormat>,
+
+    /// Append each directory's immediate child count
+    pub show_counts: bool,
+
+    /// Append each directory's total size, everything below it included (`--du`)
+    pub du: bool,
+
+    /// Append each file's own size (`--size`); `du` already shows it
+    pub size: bool,
+
+    /// Mark entries added/modified since the previous scan and list removed ones
+    pub changes: Option<ChangeSet>,
+
+    /// Color directory names by recency in the colored tree (layered over the theme)
+    pub heatmap: Option<HeatmapThresholds>,
+
+    /// Add `is_hidden`/`symlink_target` to JSON nodes
+    pub json_metadata: bool,
+
+    /// Stat symlink targets and flag links whose target is gone (`--check-symlinks`)
+    pub check_symlinks: bool,
+
+    /// Leave hidden entries (and their subtrees) out of the output
+    pub hide_hidden: bool,
+
+    /// Names matching any of these globs are pruned with their subtrees
+    pub ignore: Option<GlobSet>,
+
+    /// When set, only matching names and their ancestors are shown
+    pub matches: Option<GlobSet>,
+
+    /// Only directories modified after this (`--newer-than`) and their ancestors are shown
+    pub modified_after: Option<DateTime<Utc>>,
+
+    /// Only directories modified before this (`--older-than`) and their ancestors are shown
+    pub modified_before: Option<DateTime<Utc>>,
+
+    /// Leave out directories left without visible children by the other filters (`--prune`)
+    pub prune: bool,
+
+    /// Append a "N directories, M files" footer (and a JSON "summary" object)
+    pub show_report: bool,
+
+    /// Render from this cached path instead of `root` (see `--subtree`)
+    pub display_root: Option<PathBuf>,
+
+    /// Collapse directories with more than this many children into one placeholder line
+    pub file_limit: Option<usize>,
+
+    /// `file_limit` when none is set: the spill threshold, so spilled directories show collapsed
+    pub spill_limit: Option<usize>,
+
+    /// Render each tree line from this template instead of the default name
+    pub entry_format: Option<EntryTemplate>,
+
+    /// Print each entry's full path instead of its name in tree output
+    pub full_path: bool,
+
+    /// Emit bare names (or paths) with no indentation or connectors
+    pub no_indent: bool,
+
+    /// Character set for tree connectors and the symlink arrow
+    pub charset: Charset,
+
+    /// Ordering of children within each directory
+    pub sort: SortKey,
+
+    /// Compare names naturally (`file2` before `file10`, case-insensitive)
+    pub natural_sort: bool,
+
+    /// Invert the child ordering (applied after the sort key and tie-breaks)
+    pub reverse: bool,
+
+    /// Stop after this many tree/NDJSON lines (JSON: nodes); rendering stops early too
+    pub line_limit: Option<usize>,
+
+    /// Tree lines whose `--du`/`--counts`/`--mtime` annotations are aligned
+    /// into one column together (0 = annotations follow names directly)
+    ///
+    /// Alignment holds a window's lines back until its widest name is known,
+    /// so this bounds the buffering on huge trees; each window is aligned on
+    /// its own.
+    pub align_window: usize,
+
+    /// Paths kept by `matches`, computed on first use
+    matched_branches: OnceLock<HashSet<PathBuf>>,
+
+    /// Directories kept by the time filter, mapped to whether they passed it
+    /// themselves (false = ancestor kept as scaffolding), computed on first use
+    time_branches: OnceLock<HashMap<PathBuf, bool>>,
+
+    /// Directories hidden by `prune`, computed on first use
+    pruned_dirs: OnceLock<HashSet<PathBuf>>,
+
+    /// Symlinks whose target is missing, computed on first use with `check_symlinks`
+    broken_links: OnceLock<HashSet<PathBuf>>,
+}
+
+impl OutputOptions {
+    /// Set the `--ignore` globs (an empty list clears the filter)
+    pub fn set_ignore_patterns(&mut self, patterns: &[String]) -> Result<()> {
+        self.ignore = build_glob_set(patterns)?;
+        self.pruned_dirs = OnceLock::new();
+        Ok(())
+    }
+
+    /// Set the `--match` globs (an empty list clears the filter)
+    pub fn set_match_patterns(&mut self, patterns: &[String]) -> Result<()> {
+        self.matches = build_glob_set(patterns)?;
+        self.matched_branches = OnceLock::new();
+        self.pruned_dirs = OnceLock::new();
+        Ok(())
+    }
+
+    /// Set the `--newer-than`/`--older-than` bounds (both None clears the filter)
+    pub fn set_time_filter(&mut self, after: Option<DateTime<Utc>>, before: Option<DateTime<Utc>>) {
+        self.modified_after = after;
+        self.modified_before = before;
+        self.time_branches = OnceLock::new();
+        self.pruned_dirs = OnceLock::new();
+    }
+
+    /// Turn `--prune` on or off
+    pub fn set_prune(&mut self, prune: bool) {
+        self.prune = prune;
+        self.pruned_dirs = OnceLock::new();
+    }
+
+    /// Render from `root` instead of the cache root, dropping results computed for the old one
+    pub fn set_display_root(&mut self, root: Option<PathBuf>) {
+        self.display_root = root;
+        self.matched_branches = OnceLock::new();
+        self.time_branches = OnceLock::new();
+        self.pruned_dirs = OnceLock::new();
+    }
+
+    /// Child count above which a directory is collapsed: `file_limit`, or else `spill_limit`
+    pub(crate) fn collapse_limit(&self) -> Option<usize> {
+        self.file_limit.or(self.spill_limit)
+    }
+
+    pub(crate) fn has_time_filter(&self) -> bool {
+        self.modified_after.is_some() || self.modified_before.is_some()
+    }
+
+    fn has_child_filters(&self) -> bool {
+        self.ignore.is_some() || self.filters_on_entries()
+    }
+
+    /// Whether a child filter looks past the child's name (all but `--ignore` do)
+    pub(crate) fn filters_on_entries(&self) -> bool {
+        self.hide_hidden || self.matches.is_some() || self.has_time_filter() || self.prune
+    }
+}
+
+/// Counts of what an output builder actually renders (after depth and filters)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderSummary {
+    pub directories: usize,
+    pub files: usize,
+    /// Rendered children with no cached entry, so their kind is unknown
+    pub unknown: usize,
+}
+
+impl RenderSummary {
+    /// File count, or None when some rendered children could not be classified
+    pub fn known_files(&self) -> Option<usize> {
+        (self.unknown == 0).then_some(self.files)
+    }
+
+    /// `tree`-style footer, e.g. "3 directories, 12 files"
+    pub fn report_line(&self) -> String {
+        let dirs = match self.directories {
+            1 => "1 directory".to_string(),
+            n => format!("{} directories", n),
+        };
+        let files = match self.known_files() {
+            Some(1) => "1 file".to_string(),
+            Some(n) => format!("{} files", n),
+            None => "? files".to_string(),
+        };
+        format!("{}, {}", dirs, files)
+    }
+}
+
+/// What became of the files `DiskCache::open` was pointed at (`DiskCache::load`)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CacheLoad {
+    /// The cache was made in memory, not opened
+    #[default]
+    NotOpened,
+    /// Read from the saved files
+    Loaded,
+    /// Nothing saved there yet
+    Missing,
+    /// The files couldn't be used: the cache starts empty and the next save replaces them
+    Failed {
+        kind: LoadFailure,
+        /// The error, as logged
+        reason: String,
+        /// Files moved aside (`quarantine_cache_files`)
+        moved: usize,
+    },
+}
+
+/// Why a saved cache couldn't be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadFailure {
+    /// Damaged or incomplete files (`PTreeError::CacheCorrupt`); moved aside
+    Corrupt,
+    /// A layout this build doesn't read (`PTreeError::CacheVersion`)
+    ///
+    /// Only ever an error: starting empty would overwrite the newer cache.
+    Version,
+    /// Encrypted, and the key can't be had (`PTreeError::CacheKey`); moved aside
+    Key,
+    /// The files couldn't be read; left in place
+    Io,
+}
+
+impl LoadFailure {
+    /// Classify an error from loading a cache
+    pub fn of(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<PTreeError>() {
+            Some(PTreeError::CacheCorrupt(_)) => LoadFailure::Corrupt,
+            Some(PTreeError::CacheVersion { .. }) => LoadFailure::Version,
+            Some(PTreeError::CacheKey(_)) => LoadFailure::Key,
+            _ => LoadFailure::Io,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LoadFailure::Corrupt => "corrupt",
+            LoadFailure::Version => "version mismatch",
+            LoadFailure::Key => "key unavailable",
+            LoadFailure::Io => "unreadable",
+        }
+    }
+}
+
+impl std::fmt::Display for CacheLoad {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CacheLoad::NotOpened => write!(f, "not opened"),
+            CacheLoad::Loaded => write!(f, "loaded"),
+            CacheLoad::Missing => write!(f, "missing (first scan)"),
+            CacheLoad::Failed { kind, reason, moved: 0 } => write!(f, "{}: {}; rescanning", kind.label(), reason),
+            CacheLoad::Failed { kind, reason, moved } => {
+                write!(f, "{}: {}; moved {} file(s) aside, rescanning", kind.label(), reason, moved)
+            }
+        }
+    }
+}
+
+/// How a path differs from the previous scan (see `--changes`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+}
+
+/// Differences between a pre-scan snapshot and the current cache
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSet {
+    pub added: HashSet<PathBuf>,
+    pub modified: HashSet<PathBuf>,
+    /// Sorted paths that were cached before but are gone from their parent now
+    pub removed: Vec<PathBuf>,
+}
+
+impl From<CacheDiff> for ChangeSet {
+    fn from(diff: CacheDiff) -> Self {
+        ChangeSet {
+            added: diff.added.into_iter().collect(),
+            modified: diff.modified.into_iter().collect(),
+            removed: diff.removed,
+        }
+    }
+}
+
+impl ChangeSet {
+    pub fn kind_of(&self, path: &Path) -> Option<ChangeKind> {
+        if self.added.contains(path) {
+            Some(ChangeKind::Added)
+        } else if self.modified.contains(path) {
+            Some(ChangeKind::Modified)
+        } else {
+            None
+        }
+    }
+}
+
+/// Compile name globs; matching is case-insensitive on Windows
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(cfg!(windows))
+            .literal_separator(true)
+            .build()
+            .map_err(|e| PTreeError::InvalidArgument(format!("invalid glob pattern '{}': {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    Ok(Some(builder.build()?))
 }
 
 /// Compute Merkle tree-style content hash for a directory
@@ -50,7 +407,7 @@ pub struct DirEntry {
 pub fn compute_content_hash(
     path: &Path,
     modified: DateTime<Utc>,
-    children: &[String],
+    children: &ChildNames,
     child_hashes: &HashMap<PathBuf, u64>,
 ) -> u64 {
     let mut hasher = DefaultHasher::new();
@@ -66,7 +423,7 @@ pub fn compute_content_hash(
     children.len().hash(&mut hasher);
 
     // 4. Hash sorted child names
-    let mut sorted_children = children.to_vec();
+    let mut sorted_children: Vec<&str> = children.iter().collect();
     sorted_children.sort();
     for child_name in &sorted_children {
         child_name.hash(&mut hasher);
@@ -101,6 +458,25 @@ pub fn has_directory_changed(old_entry: &DirEntry, new_entry: &DirEntry) -> bool
     old_entry.content_hash != new_entry.content_hash
 }
 
+/// Whether two entries record the same scan result, the derived totals aside
+fn same_scan(a: &DirEntry, b: &DirEntry) -> bool {
+    a.path == b.path
+        && a.name == b.name
+        && a.modified == b.modified
+        && a.content_hash == b.content_hash
+        && a.children == b.children
+        && a.symlink_target == b.symlink_target
+        && a.is_hidden == b.is_hidden
+        && a.is_dir == b.is_dir
+        && a.size == b.size
+        && a.skipped_children == b.skipped_children
+        && a.is_mount == b.is_mount
+        && a.access_error == b.access_error
+        && a.evicted == b.evicted
+        && a.file_count == b.file_count
+        && a.dir_count == b.dir_count
+}
+
 /// In-memory tree cache
 ///
 /// Memory Model (Hard-Bounded per README spec):
@@ -111,12 +487,12 @@ pub fn has_directory_changed(old_entry: &DirEntry, new_entry: &DirEntry) -> bool
 ///
 /// This is enforced at the type level through bounded path handling and
 /// non-recursive DFS traversal. The 200-byte bound includes:
-/// - PathBuf key in HashMap (varies, but path length is constrained)
+/// - One node in the interned path table (`EntryMap`): the name once, no full path key
 /// - DirEntry value (name String, metadata, Vec<String> children)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiskCache {
     /// Map of absolute paths to directory entries
-    pub entries: HashMap<PathBuf, DirEntry>,
+    pub entries: EntryMap,
 
     /// Last scan timestamp
     pub last_scan: DateTime<Utc>,
@@ -146,6 +522,74 @@ pub struct DiskCache {
     /// Skip statistics: count of skipped directories by name
     #[serde(skip)]
     pub skip_stats: std::collections::HashMap<String, usize>,
+
+    /// Display options for output builders
+    #[serde(skip)]
+    pub output_options: OutputOptions,
+
+    /// Save the data file as zstd blocks (`--compress-cache`); kept from a compressed cache on load
+    #[serde(skip)]
+    pub compress: bool,
+
+    /// Seal the saved files with a `CacheKey` (`--encrypt-cache`); kept from an encrypted cache on load
+    #[serde(skip)]
+    pub encrypt: bool,
+
+    /// Evict stale subtrees on save until the cache fits in this many bytes (`--max-cache-bytes`)
+    #[serde(skip)]
+    pub max_bytes: Option<u64>,
+
+    /// False-positive rate of the path filter saved with the index (`--bloom-fp-rate`); kept from the loaded cache
+    #[serde(skip)]
+    pub bloom_fp_rate: f64,
+
+    /// Directories with more children than this are saved with their names in a spill record
+    /// (`--spill-children`, 0 = never); kept from the loaded cache
+    #[serde(skip)]
+    pub spill_children: usize,
+
+    /// Directories whose `total_size` is out of date, recomputed by `refresh_totals`
+    #[serde(skip)]
+    pub(crate) stale_totals: HashSet<PathBuf>,
+
+    /// Entries differ from the files they were loaded from, so `save` has to write the data file
+    ///
+    /// Set by `add_entry` (for entries that differ from the cached ones),
+    /// `remove_entry`, and merges; direct changes to `entries` go through
+    /// `mark_changed`. Caches not loaded from disk start dirty.
+    #[serde(skip)]
+    pub dirty: bool,
+
+    /// Paths added or changed since the cache was loaded or last saved, appended by a delta save
+    ///
+    /// None for caches not loaded from disk, which are always saved whole.
+    #[serde(skip)]
+    pub(crate) changed: Option<HashSet<PathBuf>>,
+
+    /// Cache file this cache was opened from, where `traverse_disk` saves it back
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+
+    /// Storage `save` writes; the one the cache was loaded from, until `set_backend` (`--backend`)
+    #[serde(skip)]
+    pub backend: CacheBackend,
+
+    /// How `open` fared with the saved files, for `-v` and `--debug`
+    #[serde(skip)]
+    pub load: CacheLoad,
+
+    /// Traversals run on this cache so far; each stamps the entries it visits with its number
+    ///
+    /// Kept in memory only: saving the stamps would have every rescan
+    /// rewrite every record. Entries loaded from disk are generation 0.
+    #[serde(skip)]
+    pub scan_generation: u64,
+}
+
+impl Default for DiskCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DiskCache {
@@ -153,40 +597,126 @@ impl DiskCache {
     // Cache Loading & Saving
     // ============================================================================
 
+    /// Create an empty in-memory cache (nothing is read from or written to disk)
+    pub fn new() -> Self {
+        Self::new_empty()
+    }
+
     /// Open or create cache file with fast cold-start lazy loading
      /// 
      /// Strategy:
      /// - Load index only (~1ms for millions of entries)
      /// - Defer entry deserialization until output phase
      /// - Use in-memory entries for traversal building
+     ///
+     /// The cache remembers `path` (`DiskCache::path`). Files that can't be
+     /// used leave it empty, to be rebuilt by the scan; `load` says why.
      pub fn open(path: &Path) -> Result<Self> {
-         fs::create_dir_all(path.parent().unwrap())?;
-    
+         Self::open_with(path, false)
+     }
+
+     /// Like `open`, but files that can't be used are an error, and stay where they are (`--strict-cache`)
+     pub fn open_strict(path: &Path) -> Result<Self> {
+         Self::open_with(path, true)
+     }
+
+     fn open_with(path: &Path, strict: bool) -> Result<Self> {
+         create_cache_dir(path)?;
+         let backend = stored_backend(path).unwrap_or(CacheBackend::Rkyv);
+         let opened = match backend {
+             CacheBackend::Sqlite => Self::open_sqlite(path),
+             CacheBackend::Kv => Self::open_kv(path),
+             CacheBackend::Limcode => Self::open_limcode(path),
+             CacheBackend::Rkyv => Self::open_rkyv(path),
+         };
+         let mut cache = match opened {
+             Ok(cache) => cache,
+             Err(e) => {
+                 let kind = LoadFailure::of(&e);
+                 // Written by a newer ptree: starting empty would overwrite it on save
+                 if strict || kind == LoadFailure::Version {
+                     return Err(e);
+                 }
+                 // Database backends report a database in use as an error of their own
+                 if matches!(backend, CacheBackend::Sqlite | CacheBackend::Kv) && kind == LoadFailure::Io {
+                     return Err(e);
+                 }
+                 // Damaged files are kept for inspection, encrypted ones for when
+                 // the key is back; unreadable ones may yet be fine, so stay put
+                 let moved = match kind {
+                     LoadFailure::Corrupt | LoadFailure::Key => quarantine_cache_files(path)?.len(),
+                     _ => 0,
+                 };
+                 let load = CacheLoad::Failed { kind, reason: format!("{:#}", e), moved };
+                 log::warn!("cache {} {}", path.display(), load);
+                 // The rebuilt cache keeps the storage, and the encryption, it had
+                 DiskCache { backend, encrypt: kind == LoadFailure::Key, load, ..Self::new_empty() }
+             }
+         };
+         cache.path = Some(path.to_path_buf());
+         Ok(cache)
+     }
+
+     /// Open the rkyv pair at `path`; without an index there is no cache yet
+     fn open_rkyv(path: &Path) -> Result<Self> {
          // Load from lazy cache format (index only, deferred entry loading)
          let index_path = path.with_extension("idx");
          let data_path = path.with_extension("dat");
-         
-         if index_path.exists() && data_path.exists() {
-             if let Ok(cache) = Self::load_from_lazy_cache(&index_path, &data_path) {
-                 return Ok(cache);
+         match (index_path.exists(), data_path.exists()) {
+             // Nothing points into a data file without an index, but it may be a legacy cache
+             (false, _) => match Self::migrate_legacy(path)? {
+                 Some(_) => Self::load_from_lazy_cache(&index_path, &data_path),
+                 None => Ok(DiskCache { load: CacheLoad::Missing, ..Self::new_empty() }),
+             },
+             (true, false) => {
+                 Err(PTreeError::CacheCorrupt(format!("{} has no data file {}", index_path.display(), data_path.display())).into())
              }
+             (true, true) => Self::load_from_lazy_cache(&index_path, &data_path),
          }
-    
-         Ok(Self::new_empty())
      }
      
+     /// Check every record of the cache at `path` against its checksum (`--verify-cache`)
+     ///
+     /// Returns the offset and path of each record that fails. A damaged
+     /// header or index fails the whole call, as it does for `open`.
+     pub fn verify_files(path: &Path) -> Result<Vec<(u64, PathBuf)>> {
+         use crate::cache_rkyv::RkyvMmapCache;
+
+         // A database has no per-record checksums; it checks its own pages
+         match stored_backend(path) {
+             Some(CacheBackend::Sqlite) => return Self::verify_sqlite(path).map(|()| Vec::new()),
+             Some(CacheBackend::Kv) => return Self::verify_kv(path).map(|()| Vec::new()),
+             Some(CacheBackend::Limcode) => return Self::verify_limcode(path),
+             _ => {}
+         }
+
+         let rkyv_cache = RkyvMmapCache::open(&path.with_extension("idx"), &path.with_extension("dat"))?;
+         rkyv_cache.corrupt_records()
+     }
+
+     /// Rewrite the data file of the cache at `path` without dead records (`ptree clean --compact`)
+     pub fn compact(path: &Path) -> Result<CompactStats> {
+         compact_cache_files(&path.with_extension("idx"), &path.with_extension("dat"))
+     }
+
      /// Load from lazy cache format - index only (fast cold start)
      /// Entries not loaded until output phase to minimize startup time
      fn load_from_lazy_cache(index_path: &Path, data_path: &Path) -> Result<Self> {
          use crate::cache_rkyv::RkyvMmapCache;
          
          let rkyv_cache = RkyvMmapCache::open(index_path, data_path)?;
+         log::info!(
+             "loaded cache index {}: {} entries, last scan {}",
+             index_path.display(),
+             format_count(rkyv_cache.len()),
+             format_mtime(rkyv_cache.index.last_scan, MtimeFormat::Relative, Utc::now())
+         );
          
          // DO NOT load all entries - keep HashMap empty for cold-start speed
          // Entries will be loaded on-demand during output formatting
          
-         Ok(DiskCache {
-             entries: HashMap::new(), // Empty - entries loaded on-demand
+         let mut cache = DiskCache {
+             entries: EntryMap::new(), // Empty - entries loaded on-demand
              last_scan: rkyv_cache.index.last_scan,
              root: rkyv_cache.index.root.clone(),
              last_scanned_root: rkyv_cache.index.last_scanned_root.clone(),
@@ -196,16 +726,44 @@ impl DiskCache {
              flush_threshold: 5000,
              show_hidden: false,
              skip_stats: rkyv_cache.index.skip_stats.clone(),
-         })
+             output_options: OutputOptions::default(),
+             compress: rkyv_cache.is_compressed(),
+             encrypt: rkyv_cache.key().is_some(),
+             max_bytes: None,
+             bloom_fp_rate: rkyv_cache.bloom.as_ref().map_or(DEFAULT_BLOOM_FP_RATE, PathBloom::fp_rate),
+             spill_children: rkyv_cache.index.spill_children,
+            stale_totals: HashSet::new(),
+            dirty: false,
+            changed: Some(HashSet::new()),
+            path: None,
+            backend: CacheBackend::Rkyv,
+            load: CacheLoad::Loaded,
+            scan_generation: 0,
+         };
+
+         // Lookups of paths the saved cache never held skip the map walk
+         cache.entries.attach_bloom(rkyv_cache.bloom.clone());
+
+         // The mapping has to go before the data file can be replaced; later
+         // lazy loads reopen the index and see the new offsets
+         let dead = rkyv_cache.needs_compaction().then(|| rkyv_cache.dead_bytes());
+         drop(rkyv_cache);
+         if let Some(dead) = dead {
+             match compact_cache_files(index_path, data_path) {
+                 Ok(stats) => log::info!("compacted {}: reclaimed {} bytes", data_path.display(), format_count(stats.reclaimed() as usize)),
+                 Err(e) => log::warn!("cannot compact {} ({} dead bytes): {:#}", data_path.display(), dead, e),
+             }
+         }
+         Ok(cache)
      }
     
     /// Create a new empty cache with default USN state
     #[cfg(windows)]
-    fn new_empty() -> Self {
+    pub(crate) fn new_empty() -> Self {
         DiskCache {
             // Pre-allocate for typical disk with ~100k directories
             // Reduces reallocation overhead during traversal
-            entries: HashMap::with_capacity(100_000),
+            entries: EntryMap::with_capacity(100_000),
             last_scan: Utc::now(),
             root: PathBuf::new(),
             last_scanned_root: PathBuf::new(),
@@ -214,16 +772,29 @@ impl DiskCache {
             flush_threshold: 5000,
             show_hidden: false,
             skip_stats: HashMap::new(),
+            output_options: OutputOptions::default(),
+            compress: false,
+            encrypt: false,
+            max_bytes: None,
+            bloom_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            spill_children: DEFAULT_SPILL_CHILDREN,
+            stale_totals: HashSet::new(),
+            dirty: true,
+            changed: None,
+            path: None,
+            backend: CacheBackend::Rkyv,
+            load: CacheLoad::NotOpened,
+            scan_generation: 0,
         }
     }
     
     /// Create a new empty cache with default USN state (non-Windows)
     #[cfg(not(windows))]
-    fn new_empty() -> Self {
+    pub(crate) fn new_empty() -> Self {
         DiskCache {
             // Pre-allocate for typical disk with ~100k directories
             // Reduces reallocation overhead during traversal
-            entries: HashMap::with_capacity(100_000),
+            entries: EntryMap::with_capacity(100_000),
             last_scan: Utc::now(),
             root: PathBuf::new(),
             last_scanned_root: PathBuf::new(),
@@ -231,71 +802,263 @@ impl DiskCache {
             flush_threshold: 5000,
             show_hidden: false,
             skip_stats: HashMap::new(),
+            output_options: OutputOptions::default(),
+            compress: false,
+            encrypt: false,
+            max_bytes: None,
+            bloom_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            spill_children: DEFAULT_SPILL_CHILDREN,
+            stale_totals: HashSet::new(),
+            dirty: true,
+            changed: None,
+            path: None,
+            backend: CacheBackend::Rkyv,
+            load: CacheLoad::NotOpened,
+            scan_generation: 0,
         }
     }
 
     /// Save cache using rkyv mmap format (index + data files with O(1) access)
+     ///
+     /// A clean cache (see `dirty`) only has its index rewritten, carrying
+     /// the new `last_scan`, skip counts and journal position; the data file
+     /// is left as it is. A dirty one loaded from disk appends just its
+     /// changed entries when it can (see `save_delta`), and is written whole
+     /// otherwise. The bytes written are logged either way.
      pub fn save(&mut self, path: &Path) -> Result<()> {
+         self.prepare_save();
+         self.write_files(path, &AtomicBool::new(false))?;
+         self.dirty = false;
+         self.changed = Some(HashSet::new());
+         Ok(())
+     }
+
+     /// Flush buffered writes and stale totals, so that `write_files` can save from `&self`
+     ///
+     /// Orphaned entries are collected first when a sample finds many (`gc_orphans`).
+     pub fn prepare_save(&mut self) {
          self.flush_pending_writes();
-    
+         self.gc_orphans_if_many();
+         self.refresh_totals();
+     }
+
+     /// Write the cache files as `save` does, without marking the cache clean
+     ///
+     /// For saves running on another thread (`save_in_background`); call
+     /// `prepare_save` first. Once `cancel` is set the save stops at the
+     /// next entry with an error, leaving the previous pair as it was.
+     pub fn write_files(&self, path: &Path, cancel: &AtomicBool) -> Result<()> {
+         let cancelled = || -> Result<()> {
+             if cancel.load(AtomicOrdering::Relaxed) {
+                 return Err(PTreeError::Cache(format!("save of {} cancelled", path.display())).into());
+             }
+             Ok(())
+         };
+         match self.backend {
+             CacheBackend::Rkyv => self.write_rkyv_files(path, &cancelled)?,
+             CacheBackend::Sqlite => self.write_sqlite(path, &cancelled)?,
+             CacheBackend::Kv => self.write_kv(path, &cancelled)?,
+             CacheBackend::Limcode => self.write_limcode(path, &cancelled)?,
+         }
+         remove_other_backends(path, self.backend);
+         Ok(())
+     }
+
+     /// Write the rkyv index and data files for `write_files`
+     fn write_rkyv_files(&self, path: &Path, cancelled: &dyn Fn() -> Result<()>) -> Result<()> {
          let index_path = path.with_extension("idx");
          let data_path = path.with_extension("dat");
-         
-         self.save_as_rkyv_mmap(&index_path, &data_path)?;
+
+         create_cache_dir(path)?;
+         let lock = CacheLock::exclusive(path)?;
+         cancelled()?;
+         if !self.dirty {
+             if let Some(written) = self.save_index_only(&index_path, &data_path)? {
+                 drop(lock);
+                 log::info!(
+                     "cache {} unchanged; updated its index only, {} bytes written",
+                     path.display(),
+                     format_count(written as usize)
+                 );
+                 return Ok(());
+             }
+         }
+         if let Some((appended, written)) = self.save_delta(&index_path, &data_path)? {
+             drop(lock);
+             log::info!(
+                 "saved cache {}: appended {} of {} entries, {} bytes written",
+                 path.display(),
+                 format_count(appended),
+                 format_count(self.entries.len()),
+                 format_count(written as usize)
+             );
+             return Ok(());
+         }
+         let saved = self.save_as_rkyv_mmap(&index_path, &data_path, cancelled)?;
+         drop(lock);
+
+         let bytes: u64 = [&index_path, &data_path]
+             .iter()
+             .filter_map(|file| fs::metadata(file).ok())
+             .map(|metadata| metadata.len())
+             .sum();
+         log::info!(
+             "saved cache {}: {} entries, {} bytes written",
+             path.display(),
+             format_count(saved),
+             format_count(bytes as usize)
+         );
          Ok(())
      }
-     
+
+     /// The saved pair, if it can be updated in place rather than written anew
+     ///
+     /// None when there is no readable pair, it is compressed or encrypted
+     /// differently, or `max_bytes` calls for eviction.
+     fn saved_pair(&self, index_path: &Path, data_path: &Path) -> Option<crate::cache_rkyv::RkyvMmapCache> {
+         use crate::cache_rkyv::RkyvMmapCache;
+
+         if self.max_bytes.is_some_and(|max_bytes| self.estimated_bytes() > max_bytes) {
+             return None;
+         }
+         if !index_path.exists() || !data_path.exists() {
+             return None;
+         }
+         RkyvMmapCache::open(index_path, data_path)
+             .ok()
+             .filter(|saved| saved.is_compressed() == self.compress && saved.key().is_some() == self.encrypt)
+     }
+
+     /// Copy this cache's scan metadata (everything but the offsets) into `index`
+     fn fill_index(&self, index: &mut crate::cache_rkyv::RkyvCacheIndex) {
+         index.root = self.root.clone();
+         index.last_scanned_root = self.last_scanned_root.clone();
+         index.last_scan = self.last_scan;
+         index.skip_stats = self.skip_stats.clone();
+         index.spill_children = self.spill_children;
+         #[cfg(windows)]
+         {
+             index.usn_state = self.usn_state.clone();
+         }
+     }
+
+     /// Rewrite the index of the saved pair with this cache's scan metadata
+     ///
+     /// Returns the bytes written, or None, writing nothing, when the data
+     /// file has to be written too (see `saved_pair`).
+     fn save_index_only(&self, index_path: &Path, data_path: &Path) -> Result<Option<u64>> {
+         let Some(mut saved) = self.saved_pair(index_path, data_path) else {
+             return Ok(None);
+         };
+         self.fill_index(&mut saved.index);
+         saved.save_index(index_path)?;
+         Ok(Some(fs::metadata(index_path)?.len()))
+     }
+
+     /// Append the changed entries to the saved data file and repoint the index at them
+     ///
+     /// Entries in `changed`, and any the saved index doesn't know, are
+     /// appended; saved paths that are no longer cached are dropped from the
+     /// index. Records left behind either way are dead bytes until the
+     /// compaction on the next load. Of a sharded cache only the shards with
+     /// changes are touched (see `RkyvMmapCache::append_changes`). Returns how many entries were appended
+     /// and the bytes written, or None, writing nothing, when the cache has to
+     /// be saved whole: its changes aren't tracked, or there is no saved pair
+     /// of plain records to append to (see `saved_pair`).
+     fn save_delta(&self, index_path: &Path, data_path: &Path) -> Result<Option<(usize, u64)>> {
+         let Some(changed) = &self.changed else {
+             return Ok(None);
+         };
+         let Some(mut saved) = self.saved_pair(index_path, data_path).filter(|saved| !saved.is_compressed()) else {
+             return Ok(None);
+         };
+         self.fill_index(&mut saved.index);
+         let (count, mut written) =
+             saved.append_changes(self.entries.values(), changed, |path| self.entries.contains_key(path))?;
+         saved.save_index(index_path)?;
+         written += fs::metadata(index_path)?.len();
+         Ok(Some((count, written)))
+     }
+
      /// Save cache in mmap format (index + data files with bincode serialization)
-     fn save_as_rkyv_mmap(&self, index_path: &Path, data_path: &Path) -> Result<()> {
-         use crate::cache_rkyv::{RkyvDirEntry, RkyvCacheIndex};
-         use std::io::Seek;
+     ///
+     /// Returns how many entries were written (fewer than cached when `max_bytes` evicts).
+     ///
+     /// `cancelled` is checked before each entry; its error abandons the
+     /// save with the previous pair untouched.
+     ///
+     /// From `SHARD_MIN_ENTRIES` entries on, the cache is split into shards
+     /// by top-level directory (`ShardTable`).
+     fn save_as_rkyv_mmap(&self, index_path: &Path, data_path: &Path, cancelled: &dyn Fn() -> Result<()>) -> Result<usize> {
+         use crate::cache_rkyv::{RkyvDirEntry, RkyvCacheIndex, ShardTable, SHARD_MIN_ENTRIES};
          
-         fs::create_dir_all(index_path.parent().unwrap())?;
+         create_cache_dir(index_path)?;
          
          // Build index with byte offsets
          let mut rkyv_index = RkyvCacheIndex::new();
-         rkyv_index.root = self.root.clone();
-         rkyv_index.last_scanned_root = self.last_scanned_root.clone();
-         rkyv_index.last_scan = self.last_scan;
-         rkyv_index.skip_stats = self.skip_stats.clone();
-         #[cfg(windows)]
-         {
-             rkyv_index.usn_state = self.usn_state.clone();
-         }
+         self.fill_index(&mut rkyv_index);
          
-         let mut data_file = File::create(data_path)?;
-         
-         for (path, entry) in &self.entries {
-             let rkyv_entry = RkyvDirEntry {
-                 path: entry.path.clone(),
-                 name: entry.name.clone(),
-                 modified: entry.modified,
-                 content_hash: entry.content_hash,
-                 children: entry.children.clone(),
-                 symlink_target: entry.symlink_target.clone(),
-                 is_hidden: entry.is_hidden,
-                 is_dir: entry.is_dir,
-             };
-             
-             let serialized = bincode::serialize(&rkyv_entry)?;
-             let len = serialized.len() as u32;
-             let offset = data_file.stream_position()?;
-             
-             rkyv_index.offsets.insert(path.clone(), offset);
-             data_file.write_all(&len.to_le_bytes())?;
-             data_file.write_all(&serialized)?;
+         let plan = self.max_bytes.map(|max_bytes| self.plan_eviction(max_bytes));
+         if let Some(plan) = plan.as_ref().filter(|plan| !plan.evicted.is_empty()) {
+             log::info!(
+                 "evicted {} subtrees ({} entries) to fit --max-cache-bytes: ~{} -> ~{} bytes",
+                 format_count(plan.evicted.len()),
+                 format_count(plan.dropped.len()),
+                 format_count(plan.before as usize),
+                 format_count(plan.after as usize)
+             );
          }
-         data_file.sync_all()?;
-         
-         // Save index
-         let index_serialized = bincode::serialize(&rkyv_index)?;
-         let temp_path = index_path.with_extension("tmp");
-         let mut index_file = File::create(&temp_path)?;
-         index_file.write_all(&index_serialized)?;
-         index_file.sync_all()?;
-         fs::rename(&temp_path, index_path)?;
-         
-         Ok(())
+         if let (Some(plan), Some(max_bytes)) = (&plan, self.max_bytes) {
+             if plan.after > max_bytes {
+                 log::warn!(
+                     "cache still needs ~{} bytes after eviction, over --max-cache-bytes {}",
+                     format_count(plan.after as usize),
+                     format_count(max_bytes as usize)
+                 );
+             }
+         }
+
+         // The saved pair's key when it opens, so its passphrase or DPAPI blob carries over
+         let key = self.encrypt.then(|| CacheKey::for_index(index_path)).transpose()?;
+         let stubs: HashSet<&PathBuf> = plan.iter().flat_map(|plan| &plan.evicted).collect();
+         let kept: Vec<&DirEntry> = self
+             .entries
+             .values()
+             .filter(|entry| plan.as_ref().is_none_or(|plan| !plan.dropped.contains(&entry.path)))
+             .collect();
+         let saved = kept.len();
+         let to_record = |entry: &DirEntry| -> Result<RkyvDirEntry> {
+             cancelled()?;
+             let mut record = RkyvDirEntry::from(entry);
+             if stubs.contains(&entry.path) {
+                 record.children.clear();
+                 record.evicted = true;
+             }
+             Ok(record)
+         };
+         if saved < SHARD_MIN_ENTRIES {
+             let entries = kept.into_iter().map(to_record);
+             crate::cache_rkyv::write_cache_files(index_path, data_path, rkyv_index, entries, self.compress, key.as_ref(), Some(self.bloom_fp_rate))?;
+             return Ok(saved);
+         }
+
+         let table = ShardTable::plan(&self.root, kept.iter().map(|entry| entry.path.as_path()));
+         let mut parts: Vec<Vec<&DirEntry>> = vec![Vec::new(); table.shards.len() + 1];
+         for entry in kept {
+             parts[table.shard_of(&self.root, &entry.path).map_or(0, |id| id + 1)].push(entry);
+         }
+         let parts = parts.into_iter().map(|part| part.into_iter().map(&to_record));
+         crate::cache_rkyv::write_sharded_cache_files(
+             index_path,
+             data_path,
+             rkyv_index,
+             table,
+             parts,
+             self.compress,
+             key.as_ref(),
+             Some(self.bloom_fp_rate),
+         )?;
+         Ok(saved)
      }
 
     // ============================================================================
@@ -312,10 +1075,33 @@ impl DiskCache {
     }
 
     /// Flush all buffered writes to main cache HashMap
+    ///
+    /// An entry that records the same scan as the cached one is dropped,
+    /// keeping the cached entry and its totals; the rest are marked changed.
     pub fn flush_pending_writes(&mut self) {
-        for (path, entry) in self.pending_writes.drain(..) {
+        let mut pending = std::mem::take(&mut self.pending_writes);
+        for (path, entry) in pending.drain(..) {
+            // Still stamped as visited by this traversal
+            if let Some(cached) = self.entries.get_mut(&path).filter(|cached| same_scan(cached, &entry)) {
+                cached.generation = entry.generation;
+                continue;
+            }
+            self.mark_changed(&path);
             self.entries.insert(path, entry);
         }
+        // Keeps the buffer's capacity for the next batch
+        self.pending_writes = pending;
+    }
+
+    /// Record that the entry at `path` was added or changed
+    ///
+    /// Marks the cache dirty and has the next save write the entry. Changes
+    /// made directly to `entries` have to call this to be saved.
+    pub fn mark_changed(&mut self, path: &Path) {
+        self.dirty = true;
+        if let Some(changed) = &mut self.changed {
+            changed.insert(path.to_path_buf());
+        }
     }
     
     /// Load entries on-demand from lazy cache (for cold-start output)
@@ -323,6 +1109,12 @@ impl DiskCache {
     pub fn load_entries_lazy(&mut self, paths: &[PathBuf], cache_path: &Path) -> Result<()> {
         use crate::cache_rkyv::RkyvMmapCache;
         
+        match stored_backend(cache_path) {
+            Some(CacheBackend::Sqlite) => return self.load_sqlite_entries(cache_path, Some(paths)),
+            Some(CacheBackend::Kv) => return self.load_kv_entries(cache_path, Some(paths)),
+            Some(CacheBackend::Limcode) => return self.load_limcode_entries(cache_path, Some(paths)),
+            _ => {}
+        }
         let index_path = cache_path.with_extension("idx");
         let data_path = cache_path.with_extension("dat");
         
@@ -330,7 +1122,7 @@ impl DiskCache {
             return Ok(());
         }
         
-        let mut rkyv_cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        let rkyv_cache = RkyvMmapCache::open(&index_path, &data_path)?;
         
         for path in paths {
             if !self.entries.contains_key(path) {
@@ -344,6 +1136,17 @@ impl DiskCache {
                         symlink_target: rkyv_entry.symlink_target,
                         is_hidden: rkyv_entry.is_hidden,
                         is_dir: rkyv_entry.is_dir,
+                        size: rkyv_entry.size,
+                        skipped_children: rkyv_entry.skipped_children,
+                        is_mount: rkyv_entry.is_mount,
+                        access_error: rkyv_entry.access_error,
+                        evicted: rkyv_entry.evicted,
+                        file_count: rkyv_entry.file_count,
+                        dir_count: rkyv_entry.dir_count,
+                        total_size: rkyv_entry.total_size,
+                        size_is_lower_bound: rkyv_entry.size_is_lower_bound,
+                        generation: 0,
+                        spilled_children: 0,
                     };
                     self.entries.insert(path.clone(), entry);
                 }
@@ -357,6 +1160,12 @@ impl DiskCache {
     pub fn load_all_entries_lazy(&mut self, cache_path: &Path) -> Result<()> {
         use crate::cache_rkyv::RkyvMmapCache;
         
+        match stored_backend(cache_path) {
+            Some(CacheBackend::Sqlite) => return self.load_sqlite_entries(cache_path, None),
+            Some(CacheBackend::Kv) => return self.load_kv_entries(cache_path, None),
+            Some(CacheBackend::Limcode) => return self.load_limcode_entries(cache_path, None),
+            _ => {}
+        }
         let index_path = cache_path.with_extension("idx");
         let data_path = cache_path.with_extension("dat");
         
@@ -376,6 +1185,68 @@ impl DiskCache {
         Ok(())
     }
 
+    /// `load_all_entries_lazy`, moving the cache files aside when a record turns out to be damaged
+    ///
+    /// What `open` does with a damaged index, for damage that only shows
+    /// once the records are read: the loaded entries are dropped, `load`
+    /// says why, and the next scan rebuilds the cache from scratch. The
+    /// save settings (backend, compression, encryption, ...) are kept.
+    pub fn load_all_entries_or_rebuild(&mut self, cache_path: &Path) -> Result<()> {
+        let Err(e) = self.load_all_entries_lazy(cache_path) else {
+            return Ok(());
+        };
+        if LoadFailure::of(&e) != LoadFailure::Corrupt {
+            return Err(e);
+        }
+
+        let moved = quarantine_cache_files(cache_path)?.len();
+        self.load = CacheLoad::Failed { kind: LoadFailure::Corrupt, reason: format!("{:#}", e), moved };
+        log::warn!("cache {} {}", cache_path.display(), self.load);
+        let empty = Self::new_empty();
+        self.entries = empty.entries;
+        self.last_scan = empty.last_scan;
+        self.root = empty.root;
+        self.last_scanned_root = empty.last_scanned_root;
+        self.pending_writes.clear();
+        self.stale_totals.clear();
+        self.dirty = true;
+        self.changed = None;
+        Ok(())
+    }
+
+    /// Load the entry at `dir`, its ancestors, and everything below it (`--subtree`)
+    ///
+    /// Of a sharded rkyv cache only the shard `dir` is in is opened. Returns
+    /// false, loading nothing, when `dir` isn't a saved path of an rkyv
+    /// cache; the caller then loads everything.
+    pub fn load_subtree_lazy(&mut self, cache_path: &Path, dir: &Path) -> Result<bool> {
+        use crate::cache_rkyv::RkyvMmapCache;
+
+        if stored_backend(cache_path) != Some(CacheBackend::Rkyv) || !cache_path.with_extension("dat").exists() {
+            return Ok(false);
+        }
+        let rkyv_cache = RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path.with_extension("dat"))?;
+        if rkyv_cache.get_entry(dir)?.is_none() {
+            return Ok(false);
+        }
+
+        let root = rkyv_cache.index.root.clone();
+        for ancestor in dir.ancestors().skip(1).take_while(|ancestor| ancestor.starts_with(&root)) {
+            match rkyv_cache.get_entry(ancestor)? {
+                Some(entry) if !self.entries.contains_key(ancestor) => {
+                    self.entries.insert(ancestor.to_path_buf(), entry.into());
+                }
+                _ => {}
+            }
+        }
+        for (path, entry) in rkyv_cache.get_subtree(dir)? {
+            if !self.entries.contains_key(&path) {
+                self.entries.insert(path, entry);
+            }
+        }
+        Ok(true)
+    }
+
     /// Add or update directory entry (via buffer)
     pub fn add_entry(&mut self, path: PathBuf, entry: DirEntry) {
         self.buffer_entry(path, entry);
@@ -388,431 +1259,3172 @@ impl DiskCache {
 
     /// Format a directory name with optional hidden indicator
     pub fn format_name(&self, name: &str, path: &Path, show_hidden: bool) -> String {
-        if !show_hidden {
-            return name.to_string();
-        }
+        mark_hidden(name, self.get_entry(path), show_hidden)
+    }
 
-        if let Some(entry) = self.get_entry(path) {
-            if entry.is_hidden {
-                format!("{} [H]", name)
-            } else {
-                name.to_string()
-            }
+    /// Heatmap bucket for a timestamp, by its age relative to `last_scan`
+    ///
+    /// Timestamps newer than `last_scan` count as hot.
+    pub fn heat_level(&self, modified: DateTime<Utc>, thresholds: &HeatmapThresholds) -> HeatLevel {
+        let age = self.last_scan - modified;
+        if age < thresholds.hot {
+            HeatLevel::Hot
+        } else if age < thresholds.warm {
+            HeatLevel::Warm
         } else {
-            name.to_string()
+            HeatLevel::Cold
         }
     }
 
-    /// Record that a directory was skipped
-    pub fn record_skip(&mut self, dir_name: &str) {
-        *self.skip_stats.entry(dir_name.to_string()).or_insert(0) += 1;
+    /// Line content from `--entry-format`, replacing the default name and annotations
+    pub(crate) fn render_entry_format(
+        &self,
+        name: &str,
+        path: &Path,
+        entry: Option<&DirEntry>,
+        depth: usize,
+    ) -> Option<String> {
+        let template = self.output_options.entry_format.as_ref()?;
+        Some(template.render(&EntryContext {
+            name,
+            path,
+            entry,
+            depth,
+            mtime_format: self.output_options.mtime.unwrap_or(MtimeFormat::Rfc3339),
+        }))
     }
 
-    /// Get skip statistics report
-    pub fn get_skip_report(&self) -> String {
-        if self.skip_stats.is_empty() {
-            return "(no directories skipped)".to_string();
-        }
+    /// Whether `--check-symlinks` found this link's target missing
+    pub(crate) fn is_broken_link(&self, entry: &DirEntry) -> bool {
+        self.output_options.check_symlinks
+            && entry.symlink_target.is_some()
+            && self
+                .output_options
+                .broken_links
+                .get_or_init(|| self.collect_broken_links())
+                .contains(&entry.path)
+    }
 
-        let mut report = String::from("Skip Statistics:\n");
-        let mut sorted: Vec<_> = self.skip_stats.iter().collect();
-        sorted.sort_by_key(|(_name, count)| std::cmp::Reverse(**count));
+    /// Stat the target of every cached symlink, in parallel
+    ///
+    /// Relative targets are resolved against the link's directory. A target
+    /// that can't be checked (e.g. access denied) is not reported as broken.
+    fn collect_broken_links(&self) -> HashSet<PathBuf> {
+        use rayon::prelude::*;
 
-        for (name, count) in sorted {
-            report.push_str(&format!("  {} × {}\n", count, name));
-        }
+        let links: Vec<&DirEntry> = self.entries.values().filter(|entry| entry.symlink_target.is_some()).collect();
+        links
+            .into_par_iter()
+            .filter(|entry| {
+                let target = entry.symlink_target.as_deref().unwrap_or(Path::new(""));
+                let resolved = match entry.path.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target.to_path_buf(),
+                };
+                matches!(resolved.try_exists(), Ok(false))
+            })
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    pub(crate) fn change_kind(&self, path: &Path) -> Option<ChangeKind> {
+        self.output_options.changes.as_ref()?.kind_of(path)
+    }
+
+    /// Name shown for an entry in tree output: the cache key itself under `--full-path`
+    pub(crate) fn entry_label(&self, name: &str, path: &Path) -> String {
+        if self.output_options.full_path {
+            path.to_string_lossy().into_owned()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Trailing annotations for an entry in tree output
+    ///
+    /// `--counts` adds the real number of children (even when `--max-depth`
+    /// hides them) and `--mtime` adds the `modified` value stored in the
+    /// entry, even when it predates `last_scan`. Files get only their size
+    /// (`--du`) and `--mtime`, as `tree -s -D` shows them.
+    pub(crate) fn annotation_suffix(&self, entry: &DirEntry) -> String {
+        let mut suffix = self.du_label(Some(entry));
+        if !entry.is_dir {
+            if self.output_options.size && !self.output_options.du {
+                suffix.push_str(&format!(" [{}]", format_size(entry.size)));
+            }
+            if let Some(format) = self.output_options.mtime {
+                suffix.push_str(&format!(" [{}]", format_mtime(entry.modified, format, Utc::now())));
+            }
+            return suffix;
+        }
+
+        if entry.is_mount {
+            suffix.push_str(" [mounted]");
+        }
+        if entry.evicted {
+            suffix.push_str(" [evicted]");
+        }
+        if self.output_options.show_counts {
+            suffix.push_str(&format!(" [{}]", counts_label(entry)));
+        }
+        if let Some(format) = self.output_options.mtime {
+            suffix.push_str(&format!(" [{}]", format_mtime(entry.modified, format, Utc::now())));
+        }
+        suffix
+    }
+
+    /// Record that a directory was skipped
+    pub fn record_skip(&mut self, dir_name: &str) {
+        *self.skip_stats.entry(dir_name.to_string()).or_insert(0) += 1;
+    }
+
+    /// Get skip statistics report
+    pub fn get_skip_report(&self) -> String {
+        if self.skip_stats.is_empty() {
+            return "(no directories skipped)".to_string();
+        }
+
+        let mut report = String::from("Skip Statistics:\n");
+        let mut sorted: Vec<_> = self.skip_stats.iter().collect();
+        sorted.sort_by_key(|(_name, count)| std::cmp::Reverse(**count));
+
+        for (name, count) in sorted {
+            report.push_str(&format!("  {} × {}\n", count, name));
+        }
+
+        report
+    }
+
+    /// Remove entry and all child entries
+    ///
+    /// The subtree is walked by path component, so removing `C:\foo` leaves
+    /// `C:\foobar` cached. The name is also dropped from the parent's
+    /// `children`, leaving no dangling child behind.
+    pub fn remove_entry(&mut self, path: &Path) {
+        if let Some(parent) = path.parent() {
+            if let (Some(parent_entry), Some(name)) = (self.entries.get_mut(parent), path.file_name()) {
+                let name = name.to_string_lossy();
+                let listed = parent_entry.children.len();
+                parent_entry.children.retain(|child| *child != name);
+                if parent_entry.children.len() != listed {
+                    self.mark_changed(parent);
+                }
+            }
+            self.invalidate_totals(parent);
+        }
+        self.dirty |= self.entries.remove_subtree(path) > 0;
+    }
+
+    /// Saved USN journal position (always the default off Windows, where none is kept)
+    pub fn usn_position(&self) -> USNJournalState {
+        #[cfg(windows)]
+        {
+            self.usn_state.clone()
+        }
+        #[cfg(not(windows))]
+        {
+            USNJournalState::default()
+        }
+    }
+
+    /// Record the USN journal position to save with the cache (ignored off Windows)
+    pub fn set_usn_position(&mut self, state: USNJournalState) {
+        #[cfg(windows)]
+        {
+            self.usn_state = state;
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = state;
+        }
+    }
+
+    /// Remove every entry on drive `drive` (e.g. `C:\`), returning how many were removed
+    ///
+    /// Paths without a drive prefix (non-Windows paths, UNC shares) never match.
+    pub fn remove_drive(&mut self, drive: char) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| !is_on_drive(path, drive));
+        let removed = before - self.entries.len();
+        self.dirty |= removed > 0;
+        removed
+    }
+
+    /// Root that output builders render from: the `--subtree` path if set
+    pub fn display_root(&self) -> &Path {
+        self.output_options.display_root.as_deref().unwrap_or(&self.root)
+    }
+
+    /// Find the cache key for a user-supplied path
+    ///
+    /// Relative paths are resolved against the current directory and `.`/`..`
+    /// are folded lexically. On Windows the lookup ignores case.
+    pub fn find_cached_path(&self, path: &Path) -> Option<PathBuf> {
+        let absolute = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().ok()?.join(path)
+        };
+
+        let mut normalized = PathBuf::new();
+        for component in absolute.components() {
+            match component {
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    normalized.pop();
+                }
+                other => normalized.push(other),
+            }
+        }
+
+        if self.entries.contains_key(&normalized) {
+            return Some(normalized);
+        }
+
+        if cfg!(windows) {
+            let wanted = normalized.to_string_lossy().to_lowercase();
+            return self
+                .entries
+                .keys()
+                .find(|key| key.to_string_lossy().to_lowercase() == wanted)
+                .cloned();
+        }
+
+        None
+    }
+
+    // ============================================================================
+    // Change Tracking
+    // ============================================================================
+
+    /// Markers for `--changes`: what a rescan changed since `snapshot`
+    ///
+    /// Without a previous scan everything would read as added, so an empty
+    /// snapshot yields no markers at all.
+    pub fn changes_since(&self, snapshot: &Snapshot) -> ChangeSet {
+        if snapshot.is_empty() {
+            return ChangeSet::default();
+        }
+        self.diff(snapshot).into()
+    }
+
+    /// Trailing "removed since last scan" section for tree output
+    pub(crate) fn removed_section(&self) -> String {
+        let Some(changes) = &self.output_options.changes else {
+            return String::new();
+        };
+        let removed: Vec<_> = changes
+            .removed
+            .iter()
+            .filter(|path| path.starts_with(self.display_root()))
+            .collect();
+        if removed.is_empty() {
+            return String::new();
+        }
+
+        let mut section = String::from("\nRemoved since last scan:\n");
+        for path in removed {
+            section.push_str(&format!("  - {}\n", path.display()));
+        }
+        section
+    }
+
+    // ============================================================================
+    // Rendered Summary
+    // ============================================================================
+
+    /// Count the entries the tree/JSON builders would render below `root`
+    ///
+    /// Mirrors their walk (same depth limit and child filters); the root
+    /// itself is not counted, matching `tree`.
+    pub fn summarize(&self, max_depth: Option<usize>) -> RenderSummary {
+        let mut summary = RenderSummary::default();
+        let mut stack: Vec<(PathBuf, usize)> = vec![(self.display_root().to_path_buf(), 0)];
+
+        while let Some((path, depth)) = stack.pop() {
+            if max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            let Some(entry) = self.get_entry(&path) else {
+                continue;
+            };
+            if self.collapsed_count(entry).is_some() {
+                continue;
+            }
+
+            for child_name in self.sorted_children(entry) {
+                let child_path = path.join(child_name);
+                match self.get_entry(&child_path) {
+                    Some(child) if child.is_dir => summary.directories += 1,
+                    Some(_) => summary.files += 1,
+                    None => summary.unknown += 1,
+                }
+                stack.push((child_path, depth + 1));
+            }
+        }
+
+        summary
+    }
+
+    // ============================================================================
+    // ASCII Tree Output
+    // ============================================================================
+
+    /// Build ASCII tree output with optional max depth
+    pub fn build_tree_output(&self) -> Result<String> {
+        self.build_tree_output_with_depth(None)
+    }
+
+    /// Build ASCII tree output with optional max depth limit
+    ///
+    /// Rendered by `write_tree_streamed` over this cache's own entries.
+    pub fn build_tree_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
+        self.tree_from_reader(max_depth, None)
+    }
+
+    // ============================================================================
+    // Colored Tree Output
+    // ============================================================================
+
+    /// Build colored tree output
+    pub fn build_colored_tree_output(&self) -> Result<String> {
+        self.build_colored_tree_output_with_depth(None)
+    }
+
+    /// Build colored tree output with optional max depth limit
+    pub fn build_colored_tree_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
+        self.build_colored_tree_output_with_theme(max_depth, &Theme::default())
+    }
+
+    /// Build colored tree output using a caller-supplied theme
+    ///
+    /// Through `write_tree_streamed`, as `build_tree_output_with_depth`.
+    pub fn build_colored_tree_output_with_theme(
+        &self,
+        max_depth: Option<usize>,
+        theme: &Theme,
+    ) -> Result<String> {
+        self.tree_from_reader(max_depth, Some(theme))
+    }
+
+    /// `write_tree_streamed` over this cache's own entries, as a String
+    fn tree_from_reader(&self, max_depth: Option<usize>, theme: Option<&Theme>) -> Result<String> {
+        let mut output = Vec::new();
+        self.write_tree_streamed(self, &mut output, max_depth, theme)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    /// Line collector for the tree printers, aligning annotations when any are shown
+    pub(crate) fn tree_lines(&self) -> TreeLines {
+        let options = &self.output_options;
+        let annotated = options.du || options.size || options.show_counts || options.mtime.is_some();
+        TreeLines::new(options.line_limit, if annotated { options.align_window } else { 0 })
+    }
+
+    // ============================================================================
+    // JSON Tree Output
+    // ============================================================================
+
+    /// Build JSON tree representation
+    pub fn build_json_output(&self) -> Result<String> {
+        self.build_json_output_with_depth(None)
+    }
+
+    /// Build JSON tree representation with optional max depth limit
+    ///
+    /// Through `build_json_output_from` over this cache's own entries.
+    pub fn build_json_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
+        self.build_json_output_from(self, max_depth)
+    }
+
+    /// Build a JSON array with one tree per root (multi-drive scans)
+    ///
+    /// Each root is rendered as if it were the display root, so summaries,
+    /// `--limit` and `removed` lists are per root.
+    pub fn build_json_roots_output(&mut self, roots: &[PathBuf], max_depth: Option<usize>) -> Result<String> {
+        let previous = self.output_options.display_root.clone();
+        let mut trees = Vec::with_capacity(roots.len());
+        for root in roots {
+            self.output_options.set_display_root(Some(root.clone()));
+            trees.push(self.json_tree_from(self, max_depth)?);
+        }
+        self.output_options.set_display_root(previous);
+
+        Ok(serde_json::to_string_pretty(&trees)?)
+    }
+
+    /// JSON object for one node, before its children are populated
+    ///
+    /// `modified` and `child_count` are always present for cached entries;
+    /// `--json-metadata` adds `is_hidden` and `symlink_target`. Fields with no
+    /// value are omitted rather than written as `null`. `mounted` marks a
+    /// volume boundary left unscanned by --one-file-system; `error` holds why
+    /// a directory couldn't be listed. Files carry their `size` in bytes.
+    pub(crate) fn json_node_for(&self, name: Option<&str>, path: &Path, entry: Option<&DirEntry>) -> serde_json::Value {
+        let mut node = serde_json::Map::new();
+        if let Some(name) = name {
+            node.insert("name".to_string(), json!(name));
+        }
+        node.insert("path".to_string(), json!(path.to_string_lossy()));
+
+        match self.change_kind(path) {
+            Some(ChangeKind::Added) => {
+                node.insert("status".to_string(), json!("added"));
+            }
+            Some(ChangeKind::Modified) => {
+                node.insert("status".to_string(), json!("modified"));
+            }
+            None => {}
+        }
+
+        if let Some(entry) = entry {
+            node.insert("type".to_string(), json!(if entry.is_dir { "directory" } else { "file" }));
+            node.insert("modified".to_string(), json!(entry.modified.to_rfc3339()));
+            node.insert("child_count".to_string(), json!(entry.children.len()));
+            if entry.is_dir {
+                node.insert("file_count".to_string(), json!(entry.file_count));
+                node.insert("dir_count".to_string(), json!(entry.dir_count));
+            } else {
+                node.insert("size".to_string(), json!(entry.size));
+            }
+            if entry.is_mount {
+                node.insert("mounted".to_string(), json!(true));
+            }
+            if entry.evicted {
+                node.insert("evicted".to_string(), json!(true));
+            }
+            if let Some(error) = &entry.access_error {
+                node.insert("error".to_string(), json!(error));
+            }
+
+            if self.output_options.json_metadata {
+                node.insert("is_hidden".to_string(), json!(entry.is_hidden));
+                if let Some(target) = &entry.symlink_target {
+                    node.insert("symlink_target".to_string(), json!(target.to_string_lossy()));
+                }
+            }
+            if self.output_options.check_symlinks && entry.symlink_target.is_some() {
+                node.insert("broken".to_string(), json!(self.is_broken_link(entry)));
+            }
+        }
+
+        node.insert("children".to_string(), json!([]));
+        serde_json::Value::Object(node)
+    }
+
+    // ============================================================================
+    // Streaming NDJSON Output
+    // ============================================================================
+
+    /// Stream one JSON object per line for every entry reachable from `root`
+    ///
+    /// Entries are written in tree order (sorted children, depth-first) directly
+    /// to the sink, so the full document is never materialized in memory.
+    /// When `--limit` cuts the stream short, a final `{"truncated":true}`
+    /// record is written instead of the remaining entries.
+    pub fn write_ndjson_output<W: Write>(&self, writer: &mut W, max_depth: Option<usize>) -> Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+
+        // Explicit stack instead of recursion: deep trees can't overflow it
+        let mut stack: Vec<(PathBuf, usize)> = vec![(self.display_root().to_path_buf(), 0)];
+        let mut budget = OutputBudget::new(self.output_options.line_limit);
+
+        while let Some((path, depth)) = stack.pop() {
+            let entry = match self.get_entry(&path) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if !budget.take() {
+                break;
+            }
+
+            let children = self.sorted_children(entry);
+            let line = json!({
+                "path": path.to_string_lossy(),
+                "name": entry.name,
+                "type": if entry.is_dir { "directory" } else { "file" },
+                "child_count": children.len(),
+                "modified": entry.modified.to_rfc3339(),
+                "is_hidden": entry.is_hidden,
+            });
+            serde_json::to_writer(&mut *writer, &line)?;
+            writer.write_all(b"\n")?;
+
+            if max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+
+            // Push in reverse so the smallest name is popped first
+            for child_name in children.into_iter().rev() {
+                stack.push((path.join(child_name), depth + 1));
+            }
+        }
+
+        if budget.truncated {
+            writer.write_all(b"{\"truncated\":true}\n")?;
+        }
+
+        Ok(())
+    }
+
+    // ============================================================================
+    // NUL-Separated Path Output (--print0)
+    // ============================================================================
+
+    /// Write each visible path, absolute and NUL-terminated, for `xargs -0`/`fzf --read0`
+    ///
+    /// Paths are written as their raw OS bytes rather than through
+    /// `to_string_lossy`, so nothing is replaced on the way to the pipe. Same
+    /// order, filters, depth and `--limit` as the tree; truncation isn't
+    /// marked, as any marker would read as one more path.
+    pub fn write_print0_output<W: Write>(&self, writer: &mut W, max_depth: Option<usize>) -> Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut stack: Vec<(PathBuf, usize)> = vec![(self.display_root().to_path_buf(), 0)];
+        let mut budget = OutputBudget::new(self.output_options.line_limit);
+
+        while let Some((path, depth)) = stack.pop() {
+            let entry = match self.get_entry(&path) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if !budget.take() {
+                break;
+            }
+
+            writer.write_all(path.as_os_str().as_encoded_bytes())?;
+            writer.write_all(b"\0")?;
+
+            if max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            for child_name in self.sorted_children(entry).into_iter().rev() {
+                stack.push((path.join(child_name), depth + 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Children of an entry in output order
+    ///
+    /// Sorting happens only at output time (not during traversal); large
+    /// directories (>500 children) use a parallel sort. Children rejected by
+    /// `is_child_visible` are dropped here, which prunes their subtrees too.
+    pub fn sorted_children<'a>(&self, entry: &'a DirEntry) -> Vec<&'a str> {
+        let mut children: Vec<_> = if self.output_options.has_child_filters() {
+            entry
+                .children
+                .iter()
+                .filter(|name| self.is_child_visible(&entry.path, name, self.get_entry(&entry.path.join(name))))
+                .collect()
+        } else {
+            entry.children.iter().collect()
+        };
+        let reverse = self.output_options.reverse;
+        let compare = |a: &&str, b: &&str| {
+            let ordering = self.compare_children(&entry.path, a, b);
+            if reverse { ordering.reverse() } else { ordering }
+        };
+        if children.len() > 500 {
+            children.par_sort_by(compare);
+        } else {
+            children.sort_by(compare);
+        }
+        children
+    }
+
+    /// Order two children of `parent` by the active sort key
+    ///
+    /// Non-name keys put the largest/newest first and fall back to name order
+    /// for ties. Children without a cached `DirEntry` sort after those with one.
+    fn compare_children(&self, parent: &Path, a: &str, b: &str) -> Ordering {
+        let sort = self.output_options.sort;
+        if sort == SortKey::Name {
+            return self.compare_names(a, b);
+        }
+
+        let entry_a = self.get_entry(&parent.join(a));
+        let entry_b = self.get_entry(&parent.join(b));
+        let by_key = match (entry_a, entry_b) {
+            (Some(x), Some(y)) => match sort {
+                SortKey::Mtime => y.modified.cmp(&x.modified),
+                SortKey::Children => y.children.len().cmp(&x.children.len()),
+                SortKey::Size => y.total_size.cmp(&x.total_size),
+                SortKey::Name => Ordering::Equal,
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+
+        by_key.then_with(|| self.compare_names(a, b))
+    }
+
+    /// Name ordering: natural when `--natural-sort` is set, codepoint otherwise
+    pub(crate) fn compare_names(&self, a: &str, b: &str) -> Ordering {
+        if self.output_options.natural_sort {
+            natural_cmp(a, b)
+        } else {
+            a.cmp(b)
+        }
+    }
+
+    /// ` [12.3 MiB]` with `--du`, or ` [≥ 12.3 MiB]` when part of the subtree was skipped
+    ///
+    /// Reads the stored `total_size`, so totals must be current (see
+    /// `refresh_totals`). No entry counts as empty.
+    pub(crate) fn du_label(&self, entry: Option<&DirEntry>) -> String {
+        if !self.output_options.du {
+            return String::new();
+        }
+        let (total, lower_bound) = entry.map_or((0, false), |entry| (entry.total_size, entry.size_is_lower_bound));
+        let bound = if lower_bound { "≥ " } else { "" };
+        format!(" [{}{}]", bound, format_size(total))
+    }
+
+    /// Child count when `--filelimit` collapses this entry, None when it expands
+    fn collapsed_count(&self, entry: &DirEntry) -> Option<usize> {
+        let limit = self.output_options.collapse_limit()?;
+        let count = self.visible_child_count(entry);
+        (count > limit).then_some(count)
+    }
+
+    /// Number of children that would be rendered (filters applied, no sorting)
+    fn visible_child_count(&self, entry: &DirEntry) -> usize {
+        if self.output_options.has_child_filters() {
+            entry
+                .children
+                .iter()
+                .filter(|name| self.is_child_visible(&entry.path, name, self.get_entry(&entry.path.join(name))))
+                .count()
+        } else {
+            entry.children.len()
+        }
+    }
+
+    /// Apply every child filter, `--prune` last, to the child `name` of `parent` (its entry `child`, if cached)
+    pub(crate) fn is_child_visible(&self, parent: &Path, name: &str, child: Option<&DirEntry>) -> bool {
+        if !self.passes_child_filters(parent, name, child) {
+            return false;
+        }
+        !self.output_options.prune
+            || !self
+                .output_options
+                .pruned_dirs
+                .get_or_init(|| self.collect_pruned_dirs())
+                .contains(&parent.join(name))
+    }
+
+    /// Apply `--no-hidden`, `--ignore`, `--match` and the time filter to a single child
+    fn passes_child_filters(&self, parent: &Path, name: &str, child: Option<&DirEntry>) -> bool {
+        let options = &self.output_options;
+
+        if options.ignore.as_ref().is_some_and(|set| set.is_match(name)) {
+            return false;
+        }
+
+        let child_path = parent.join(name);
+        if options.hide_hidden && child.is_some_and(|e| e.is_hidden) {
+            return false;
+        }
+
+        if options.has_time_filter() {
+            // Files are kept on their own mtime, directories also for what is kept below them
+            if !self.time_branches().contains_key(&child_path) {
+                return false;
+            }
+        }
+
+        if options.matches.is_some() {
+            return options
+                .matched_branches
+                .get_or_init(|| self.collect_matched_branches())
+                .contains(&child_path);
+        }
+
+        true
+    }
+
+    fn passes_cached_child_filters(&self, parent: &Path, name: &str) -> bool {
+        self.passes_child_filters(parent, name, self.get_entry(&parent.join(name)))
+    }
+
+    /// Directories with nothing left to show once filtered and pruned
+    ///
+    /// A post-order walk from `root`, so a directory is judged after its
+    /// subdirectories: one whose only children were pruned is pruned too.
+    /// Files and uncached children always count as something to show.
+    fn collect_pruned_dirs(&self) -> HashSet<PathBuf> {
+        let mut pruned = HashSet::new();
+        // (path, children already pushed)
+        let mut stack: Vec<(PathBuf, bool)> = vec![(self.display_root().to_path_buf(), false)];
+
+        while let Some((path, expanded)) = stack.pop() {
+            let Some(entry) = self.get_entry(&path) else {
+                continue;
+            };
+            if !entry.is_dir {
+                continue;
+            }
+
+            if expanded {
+                let has_visible = entry
+                    .children
+                    .iter()
+                    .any(|name| self.passes_cached_child_filters(&path, name) && !pruned.contains(&path.join(name)));
+                if !has_visible {
+                    pruned.insert(path);
+                }
+            } else {
+                stack.push((path.clone(), true));
+                for name in entry.children.iter().filter(|name| self.passes_cached_child_filters(&path, name)) {
+                    stack.push((path.join(name), false));
+                }
+            }
+        }
+
+        pruned
+    }
+
+    fn time_branches(&self) -> &HashMap<PathBuf, bool> {
+        self.output_options.time_branches.get_or_init(|| self.collect_time_branches())
+    }
+
+    /// Whether a directory is only shown because something below it passed the time filter
+    pub(crate) fn is_time_scaffolding(&self, path: &Path) -> bool {
+        self.output_options.has_time_filter() && self.time_branches().get(path) == Some(&false)
+    }
+
+    /// Every entry passing the time filter, plus all ancestors up to `root`
+    fn collect_time_branches(&self) -> HashMap<PathBuf, bool> {
+        let options = &self.output_options;
+        let root = self.display_root();
+        let mut kept = HashMap::new();
+
+        for (path, entry) in &self.entries {
+            let passes = options.modified_after.is_none_or(|after| entry.modified > after)
+                && options.modified_before.is_none_or(|before| entry.modified < before);
+            if !passes || !path.starts_with(root) {
+                continue;
+            }
+            kept.insert(path.clone(), true);
+            // Walk upwards until we reach a branch that is already kept
+            for ancestor in path.ancestors().skip(1) {
+                if !ancestor.starts_with(root) || kept.contains_key(ancestor) {
+                    break;
+                }
+                kept.insert(ancestor.to_path_buf(), false);
+            }
+        }
+
+        kept
+    }
+
+    /// Every path whose name matches `--match`, plus all ancestors up to `root`
+    fn collect_matched_branches(&self) -> HashSet<PathBuf> {
+        let mut kept = HashSet::new();
+        let Some(matches) = &self.output_options.matches else {
+            return kept;
+        };
+
+        for (path, entry) in &self.entries {
+            for child_name in entry.children.iter().filter(|name| matches.is_match(name)) {
+                // Walk upwards until we reach a branch that is already kept
+                for ancestor in path.join(child_name).ancestors() {
+                    if !ancestor.starts_with(self.display_root()) || !kept.insert(ancestor.to_path_buf()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        kept
+    }
+
+    // ============================================================================
+    // Graphviz DOT Output
+    // ============================================================================
+
+    /// Build Graphviz DOT digraph, `DEFAULT_DOT_DEPTH` levels deep
+    pub fn build_dot_output(&self) -> Result<String> {
+        self.build_dot_output_with_depth(None)
+    }
+
+    /// Build Graphviz DOT digraph with optional max depth limit
+    ///
+    /// One node per directory, labelled with its name; files are left out.
+    /// Node ids are hashed paths so arbitrary names stay valid DOT
+    /// identifiers. Symlinks get a distinct shape. Without a limit the graph
+    /// stops at `DEFAULT_DOT_DEPTH`, so a whole disk never becomes one graph
+    /// by accident.
+    pub fn build_dot_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
+        let max_depth = max_depth.unwrap_or(DEFAULT_DOT_DEPTH);
+        let mut output = String::from("digraph ptree {\n");
+        output.push_str("    rankdir=LR;\n");
+        output.push_str("    node [shape=folder];\n");
+
+        if !self.entries.is_empty() {
+            let root_label = self.display_root().display().to_string();
+            output.push_str(&format!(
+                "    {} [label=\"{}\"];\n",
+                dot_node_id(self.display_root()),
+                escape_dot_label(&root_label)
+            ));
+
+            let mut stack: Vec<(PathBuf, usize)> = vec![(self.display_root().to_path_buf(), 0)];
+            while let Some((path, depth)) = stack.pop() {
+                if depth >= max_depth {
+                    continue;
+                }
+
+                let entry = match self.get_entry(&path) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+
+                for child_name in self.sorted_children(entry) {
+                    let child_path = path.join(child_name);
+                    // Only directories (and links, which may point at one) become nodes
+                    let Some(child) = self.get_entry(&child_path).filter(|child| child.is_dir || child.symlink_target.is_some()) else {
+                        continue;
+                    };
+                    let shape = if child.symlink_target.is_some() { ", shape=cds, style=dashed" } else { "" };
+
+                    output.push_str(&format!(
+                        "    {} [label=\"{}\"{}];\n",
+                        dot_node_id(&child_path),
+                        escape_dot_label(child_name),
+                        shape
+                    ));
+                    output.push_str(&format!(
+                        "    {} -> {};\n",
+                        dot_node_id(&path),
+                        dot_node_id(&child_path)
+                    ));
+
+                    stack.push((child_path, depth + 1));
+                }
+            }
+        }
+
+        output.push_str("}\n");
+        Ok(output)
+    }
+
+    // ============================================================================
+    // HTML Report Output
+    // ============================================================================
+
+    /// Build a self-contained HTML report
+    pub fn build_html_output(&self) -> Result<String> {
+        self.build_html_output_with_depth(None)
+    }
+
+    /// Build a self-contained HTML report with optional max depth limit
+    ///
+    /// Each directory with rendered children becomes a `<details>/<summary>`
+    /// element so large trees can be collapsed in the browser. Hidden entries
+    /// are dimmed and symlinks show their target.
+    pub fn build_html_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
+        let root_name = escape_html(&self.display_root().display().to_string());
+
+        let mut output = String::new();
+        output.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        output.push_str(&format!("<title>ptree: {}</title>\n", root_name));
+        output.push_str(HTML_STYLE);
+        output.push_str("</head>\n<body>\n");
+
+        if self.entries.is_empty() {
+            output.push_str("<p>(empty)</p>\n");
+        } else {
+            output.push_str(&format!("<details open>\n<summary>{}</summary>\n", root_name));
+            self.populate_html(&mut output, self.display_root(), 0, max_depth)?;
+            output.push_str("</details>\n");
+        }
+
+        output.push_str("</body>\n</html>\n");
+        Ok(output)
+    }
+
+    fn populate_html(
+        &self,
+        output: &mut String,
+        path: &Path,
+        current_depth: usize,
+        max_depth: Option<usize>,
+    ) -> Result<()> {
+        // Check depth limit
+        if let Some(max) = max_depth {
+            if current_depth >= max {
+                return Ok(());
+            }
+        }
+
+        let entry = match self.get_entry(path) {
+            Some(entry) if !entry.children.is_empty() => entry,
+            _ => return Ok(()),
+        };
+
+        output.push_str("<ul>\n");
+        for child_name in self.sorted_children(entry) {
+            let child_path = path.join(child_name);
+            let child = self.get_entry(&child_path);
+
+            let mut label = escape_html(child_name);
+            if let Some(target) = child.and_then(|c| c.symlink_target.as_ref()) {
+                label.push_str(&format!(
+                    " <span class=\"link\">→ {}</span>",
+                    escape_html(&target.display().to_string())
+                ));
+            }
+            let class = if child.is_some_and(|c| c.is_hidden) { " class=\"hidden\"" } else { "" };
+
+            let expandable = child.is_some_and(|c| !c.children.is_empty())
+                && max_depth.is_none_or(|max| current_depth + 1 < max);
+
+            if expandable {
+                output.push_str(&format!("<li{}><details>\n<summary>{}</summary>\n", class, label));
+                self.populate_html(output, &child_path, current_depth + 1, max_depth)?;
+                output.push_str("</details></li>\n");
+            } else {
+                output.push_str(&format!("<li{}>{}</li>\n", class, label));
+            }
+        }
+        output.push_str("</ul>\n");
+
+        Ok(())
+    }
+
+    // ============================================================================
+    // Markdown Output
+    // ============================================================================
+
+    /// Build Markdown nested bullet list
+    pub fn build_markdown_output(&self) -> Result<String> {
+        self.build_markdown_output_with_depth(None, false)
+    }
+
+    /// Build Markdown nested bullet list with optional max depth limit
+    ///
+    /// The root is the top-level bullet; each level below it is indented by
+    /// two spaces. With `show_link_targets`, symlinks render as `name → target`.
+    pub fn build_markdown_output_with_depth(
+        &self,
+        max_depth: Option<usize>,
+        show_link_targets: bool,
+    ) -> Result<String> {
+        if self.entries.is_empty() {
+            return Ok("(empty)\n".to_string());
+        }
+
+        let mut output = format!("- {}\n", escape_markdown(&self.display_root().display().to_string()));
+        self.print_markdown(&mut output, self.display_root(), 0, max_depth, show_link_targets)?;
+
+        Ok(output)
+    }
+
+    fn print_markdown(
+        &self,
+        output: &mut String,
+        path: &Path,
+        current_depth: usize,
+        max_depth: Option<usize>,
+        show_link_targets: bool,
+    ) -> Result<()> {
+        // Check depth limit
+        if let Some(max) = max_depth {
+            if current_depth >= max {
+                return Ok(());
+            }
+        }
+
+        if let Some(entry) = self.get_entry(path) {
+            let indent = "  ".repeat(current_depth + 1);
+
+            for child_name in self.sorted_children(entry) {
+                let child_path = path.join(child_name);
+                let mut line = escape_markdown(child_name);
+
+                if show_link_targets {
+                    if let Some(target) = self.get_entry(&child_path).and_then(|c| c.symlink_target.as_ref()) {
+                        line.push_str(&format!(" → {}", escape_markdown(&target.display().to_string())));
+                    }
+                }
+
+                output.push_str(&format!("{}- {}\n", indent, line));
+                self.print_markdown(output, &child_path, current_depth + 1, max_depth, show_link_targets)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // ============================================================================
+    // CSV Output
+    // ============================================================================
+
+    /// Build CSV listing (one row per cached entry)
+    pub fn build_csv_output(&self) -> Result<String> {
+        self.build_csv_output_with_depth(None)
+    }
+
+    /// Build CSV listing with optional max depth limit
+    ///
+    /// Rows are emitted in sorted path order so the output is deterministic.
+    /// Depth is measured relative to `root` (the root itself is depth 0).
+    /// The size column holds bytes: a directory's total, a file's own size.
+    pub fn build_csv_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
+        let mut output = String::from(
+            "path,name,parent,child_count,modified,size,is_hidden,symlink_target\n",
+        );
+
+        let mut paths: Vec<&PathBuf> = self
+            .entries
+            .keys()
+            .filter(|path| match self.relative_depth(path) {
+                Some(depth) => max_depth.is_none_or(|max| depth <= max),
+                None => false,
+            })
+            .collect();
+        if paths.len() > 500 {
+            paths.par_sort();
+        } else {
+            paths.sort();
+        }
+
+        for path in paths {
+            let entry = &self.entries[path];
+            let fields = [
+                path.to_string_lossy().to_string(),
+                entry.name.clone(),
+                path.parent()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                entry.children.len().to_string(),
+                entry.modified.to_rfc3339(),
+                entry.total_size.to_string(),
+                entry.is_hidden.to_string(),
+                entry
+                    .symlink_target
+                    .as_ref()
+                    .map(|t| t.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ];
+
+            let row: Vec<String> = fields.iter().map(|f| escape_csv_field(f)).collect();
+            output.push_str(&row.join(","));
+            output.push('\n');
+        }
+
+        Ok(output)
+    }
+
+    /// Depth of a cached path relative to `root` (None if outside the root)
+    fn relative_depth(&self, path: &Path) -> Option<usize> {
+        path.strip_prefix(self.display_root())
+            .ok()
+            .map(|relative| relative.components().count())
+    }
+}
+
+/// `name`, with ` [H]` appended for a hidden entry when `show_hidden` is set
+pub(crate) fn mark_hidden(name: &str, entry: Option<&DirEntry>, show_hidden: bool) -> String {
+    if show_hidden && entry.is_some_and(|entry| entry.is_hidden) {
+        format!("{} [H]", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Tree connector pieces for the selected character set
+pub(crate) struct Connectors {
+    pub(crate) tee: &'static str,
+    pub(crate) elbow: &'static str,
+    pub(crate) pipe: &'static str,
+    pub(crate) blank: &'static str,
+    pub(crate) arrow: &'static str,
+    pub(crate) ellipsis: &'static str,
+}
+
+impl Connectors {
+    pub(crate) fn for_charset(charset: Charset) -> Self {
+        match charset {
+            Charset::Utf8 => Connectors {
+                tee: "├── ",
+                elbow: "└── ",
+                pipe: "│   ",
+                blank: "    ",
+                arrow: "→",
+                ellipsis: "…",
+            },
+            Charset::Ascii => Connectors {
+                tee: "|-- ",
+                elbow: "`-- ",
+                pipe: "|   ",
+                blank: "    ",
+                arrow: "->",
+                ellipsis: "...",
+            },
+        }
+    }
+}
+
+/// Final line of tree output cut short by `--limit`
+pub(crate) const TRUNCATION_NOTICE: &str = "... output truncated (use --limit 0 for all)";
+
+/// Remaining line (or node) allowance under `--limit`
+pub(crate) struct OutputBudget {
+    remaining: Option<usize>,
+    pub(crate) truncated: bool,
+}
+
+impl OutputBudget {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        OutputBudget { remaining: limit, truncated: false }
+    }
+
+    /// Spend one unit; returns false (and marks the output truncated) once exhausted
+    pub(crate) fn take(&mut self) -> bool {
+        match &mut self.remaining {
+            Some(0) => {
+                self.truncated = true;
+                false
+            }
+            Some(remaining) => {
+                *remaining -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+/// Marker after directories that couldn't be listed (as GNU tree prints it)
+pub(crate) const ACCESS_ERROR_MARK: &str = "[error opening dir]";
+
+/// Tree printer output: rendered lines, capped by an `OutputBudget`
+///
+/// With an alignment window, lines are held back as (name part, annotation)
+/// pairs and written a window at a time, each annotation padded out to the
+/// window's widest annotated name part.
+pub(crate) struct TreeLines {
+    pub(crate) text: String,
+    pub(crate) budget: OutputBudget,
+    align_window: usize,
+    pending: Vec<(String, String)>,
+}
+
+impl TreeLines {
+    pub(crate) fn new(limit: Option<usize>, align_window: usize) -> Self {
+        TreeLines { text: String::new(), budget: OutputBudget::new(limit), align_window, pending: Vec::new() }
+    }
+
+    /// Append one line whose `annotation` belongs in the aligned column; false without appending once the limit is reached
+    pub(crate) fn push_annotated(&mut self, line: String, annotation: String) -> bool {
+        if !self.budget.take() {
+            return false;
+        }
+        if self.align_window == 0 {
+            self.text.push_str(&line);
+            self.text.push_str(&annotation);
+            self.text.push('\n');
+            return true;
+        }
+        self.pending.push((line, annotation));
+        if self.pending.len() >= self.align_window {
+            self.flush();
+        }
+        true
+    }
+
+    /// Write out the held-back lines, annotations aligned
+    pub(crate) fn flush(&mut self) {
+        let column = self
+            .pending
+            .iter()
+            .filter(|(_, annotation)| !annotation.is_empty())
+            .map(|(line, _)| display_width(line))
+            .max()
+            .unwrap_or(0);
+        for (line, annotation) in self.pending.drain(..) {
+            if !annotation.is_empty() {
+                let padding = column - display_width(&line);
+                self.text.push_str(&line);
+                self.text.extend(std::iter::repeat_n(' ', padding));
+                self.text.push_str(&annotation);
+            } else {
+                self.text.push_str(&line);
+            }
+            self.text.push('\n');
+        }
+    }
+}
+
+/// Terminal columns taken by `text`: ANSI color sequences take none, and
+/// box-drawing connectors one each despite being three bytes long
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequence: ESC [ parameters, ended by a byte in @..~
+            chars.find(|c| ('@'..='~').contains(c) && *c != '[');
+            continue;
+        }
+        width += UnicodeWidthChar::width(c).unwrap_or(0);
+    }
+    width
+}
+
+/// Dim an annotation for the colored tree (empty stays empty, no stray escapes)
+pub(crate) fn dimmed(annotation: String) -> String {
+    if annotation.is_empty() {
+        annotation
+    } else {
+        annotation.dimmed().to_string()
+    }
+}
+
+/// Inline stylesheet for the HTML report (keeps the file self-contained)
+const HTML_STYLE: &str = "<style>
+body { font-family: monospace; }
+ul { list-style: none; padding-left: 1.5em; margin: 0; }
+summary { cursor: pointer; }
+.hidden { opacity: 0.5; }
+.link { color: #2aa198; }
+</style>
+";
+
+/// Format a timestamp as RFC3339 or as a coarse relative age (`3d ago`)
+pub fn format_mtime(modified: DateTime<Utc>, format: MtimeFormat, now: DateTime<Utc>) -> String {
+    match format {
+        MtimeFormat::Rfc3339 => modified.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        MtimeFormat::Relative => {
+            // Clock skew can put mtimes slightly in the future; treat as "now"
+            let secs = (now - modified).num_seconds().max(0);
+            match secs {
+                0..=59 => format!("{}s ago", secs),
+                60..=3_599 => format!("{}m ago", secs / 60),
+                3_600..=86_399 => format!("{}h ago", secs / 3_600),
+                86_400..=31_535_999 => format!("{}d ago", secs / 86_400),
+                _ => format!("{}y ago", secs / 31_536_000),
+            }
+        }
+    }
+}
+
+/// Format a count with thousands separators, e.g. `1482` -> `1,482`
+pub fn format_count(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+/// The `--counts` annotation of a directory, e.g. `3 dirs, 1,482 files`
+///
+/// Entries from caches older than format 6 have no split, so they show the
+/// plain child count until the next scan.
+fn counts_label(entry: &DirEntry) -> String {
+    if entry.file_count == 0 && entry.dir_count == 0 {
+        return format_count(entry.children.len());
+    }
+    let plural = |n: u32, one: &str, many: &str| format!("{} {}", format_count(n as usize), if n == 1 { one } else { many });
+    format!("{}, {}", plural(entry.dir_count, "dir", "dirs"), plural(entry.file_count, "file", "files"))
+}
+
+/// Format a byte count with binary units, e.g. `1536` -> `1.5 KiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Natural ordering: ASCII digit runs compare numerically, letters case-insensitively
+///
+/// Names that are equal under these rules (e.g. `a01` vs `a1`, `A` vs `a`)
+/// fall back to plain codepoint order so the result is always total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut left = a.chars().peekable();
+    let mut right = b.chars().peekable();
+
+    loop {
+        match (left.peek().copied(), right.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let run_a = take_digit_run(&mut left);
+                let run_b = take_digit_run(&mut right);
+                let trimmed_a = run_a.trim_start_matches('0');
+                let trimmed_b = run_b.trim_start_matches('0');
+                // Longer run (without leading zeros) is the larger number
+                let ordering = trimmed_a
+                    .len()
+                    .cmp(&trimmed_b.len())
+                    .then_with(|| trimmed_a.cmp(trimmed_b));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                left.next();
+                right.next();
+            }
+        }
+    }
+}
+
+fn take_digit_run(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut run = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        run.push(c);
+    }
+    run
+}
+
+/// Escape text for safe inclusion in HTML element content
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape characters that Markdown would otherwise interpret inside list items
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Levels `build_dot_output_with_depth` renders when no depth is given (`--max-depth` overrides it)
+pub const DEFAULT_DOT_DEPTH: usize = 4;
+
+/// Stable DOT node identifier derived from the entry path
+fn dot_node_id(path: &Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    format!("n{:016x}", hasher.finish())
+}
+
+/// Escape a label for use inside a double-quoted DOT string
+fn escape_dot_label(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quote a CSV field if it contains separators, quotes, or line breaks (RFC 4180)
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// File name of the cache inside a cache directory
+pub const CACHE_FILE_NAME: &str = "ptree.dat";
+
+/// Environment variable overriding the default cache directory
+pub const CACHE_DIR_ENV: &str = "PTREE_CACHE_DIR";
+
+/// Get cache file path: `PTREE_CACHE_DIR` if set, else the platform's cache directory
+///
+/// The directory is created if missing. See `default_cache_dir` for the
+/// per-platform locations.
+pub fn get_cache_path() -> Result<PathBuf> {
+    let cache_file = default_cache_dir(std::env::consts::OS, |name| std::env::var_os(name))?.join(CACHE_FILE_NAME);
+    create_cache_dir(&cache_file)?;
+    Ok(cache_file)
+}
+
+/// Cache directory for platform `os` (as in `std::env::consts::OS`), reading variables through `env`
+///
+/// `PTREE_CACHE_DIR` wins everywhere. Otherwise `%APPDATA%\ptree\cache` on
+/// Windows, `~/Library/Caches/ptree` on macOS, and elsewhere
+/// `$XDG_CACHE_HOME/ptree`, falling back to `~/.cache/ptree` when that is
+/// unset or not absolute (as the XDG spec asks). Fails with
+/// `PTreeError::CacheLocation` naming the variable that was needed.
+pub fn default_cache_dir(os: &str, env: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf> {
+    let var = |name: &'static str| env(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if let Some(dir) = var(CACHE_DIR_ENV) {
+        return Ok(dir);
+    }
+    let home = || var("HOME").ok_or(PTreeError::CacheLocation { missing: "HOME" });
+    let dir = match os {
+        "windows" => var("APPDATA").ok_or(PTreeError::CacheLocation { missing: "APPDATA" })?.join("ptree").join("cache"),
+        "macos" => home()?.join("Library").join("Caches").join("ptree"),
+        _ => match var("XDG_CACHE_HOME").filter(|dir| dir.is_absolute()) {
+            Some(dir) => dir.join("ptree"),
+            None => home()?.join(".cache").join("ptree"),
+        },
+    };
+    Ok(dir)
+}
+
+/// Get cache file path: `--cache-path`, then `--cache-dir`, then the default location
+///
+/// `cache_name` (`--cache-name`) picks `<name>.dat` instead of `ptree.dat`
+/// in the chosen directory, so separate scan roots can keep separate caches.
+pub fn get_cache_path_custom(
+    custom_dir: Option<&str>,
+    custom_path: Option<&Path>,
+    cache_name: Option<&str>,
+) -> Result<PathBuf> {
+    let file_name = match cache_name {
+        Some(name) => cache_file_name(name)?,
+        None => CACHE_FILE_NAME.to_string(),
+    };
+    if let Some(location) = custom_path {
+        Ok(cache_file_at(location))
+    } else if let Some(dir) = custom_dir {
+        Ok(PathBuf::from(dir).join(file_name))
+    } else {
+        let cache_file = default_cache_dir(std::env::consts::OS, |name| std::env::var_os(name))?.join(file_name);
+        create_cache_dir(&cache_file)?;
+        Ok(cache_file)
+    }
+}
+
+/// File name for `--cache-name`: a plain name, stored as `<name>.dat`
+fn cache_file_name(name: &str) -> Result<String> {
+    let plain = !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', ':']);
+    if !plain {
+        return Err(PTreeError::InvalidArgument(format!(
+            "cache name {:?} must be a plain file name, without separators",
+            name
+        ))
+        .into());
+    }
+    Ok(format!("{}.dat", name))
+}
+
+/// `--cache-path` accepts a directory or a file: existing directories and
+/// extensionless paths get `ptree.dat` inside them
+fn cache_file_at(location: &Path) -> PathBuf {
+    if location.is_dir() || location.extension().is_none() {
+        location.join(CACHE_FILE_NAME)
+    } else {
+        location.to_path_buf()
+    }
+}
+
+/// Create the directory holding `cache_file`, naming it in the error
+pub(crate) fn create_cache_dir(cache_file: &Path) -> Result<()> {
+    let Some(dir) = cache_file.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    fs::create_dir_all(dir).map_err(|source| PTreeError::CachePath { path: dir.to_path_buf(), source })?;
+    Ok(())
+}
+
+/// Files `DiskCache::save` writes for a cache path: index, data, and their temp files,
+/// or the database of the SQLite (and its journal) or KV backend
+///
+/// The shard files of a sharded cache are listed too, as found on disk, and
+/// the backup a legacy cache was migrated from (`DiskCache::migrate_legacy`).
+pub fn cache_artifacts(cache_path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = ["idx", "dat", "tmp", "dat.tmp", "db", "db-journal", "redb", "limidx", "limdat", "limidx.tmp", "limdat.tmp"]
+        .map(|extension| cache_path.with_extension(extension))
+        .into();
+    files.extend(crate::cache_rkyv::shard_files(cache_path));
+    files.push(crate::legacy::legacy_backup(cache_path));
+    files
+}
+
+/// Rename the index and data files (or the database, or the shards) of a damaged cache to `<file>.corrupt-<timestamp>`
+///
+/// Returns the new names. Files that don't exist are skipped.
+pub fn quarantine_cache_files(cache_path: &Path) -> Result<Vec<PathBuf>> {
+    let _lock = CacheLock::exclusive(cache_path)?;
+    let stamp = Utc::now().format("%Y%m%dT%H%M%S");
+    let mut moved = Vec::new();
+    let shards = crate::cache_rkyv::shard_files(cache_path);
+    let files = ["idx", "dat", "db", "redb", "limidx", "limdat"].map(|extension| cache_path.with_extension(extension));
+    for file in files.into_iter().chain(shards) {
+        if !file.is_file() {
+            continue;
+        }
+        let extension = file.extension().unwrap_or_default().to_string_lossy();
+        let aside = file.with_extension(format!("{}.corrupt-{}", extension, stamp));
+        fs::rename(&file, &aside)
+            .map_err(|e| PTreeError::Cache(format!("cannot move {} aside: {}", file.display(), e)))?;
+        moved.push(aside);
+    }
+    Ok(moved)
+}
+
+/// Delete the files of the cache at `cache_path`, returning the bytes reclaimed
+///
+/// Only the names from `cache_artifacts` are touched, and only when they are
+/// regular files; a directory or symlink under one of those names is refused
+/// rather than followed. Files that are already gone are skipped.
+pub fn remove_cache_files(cache_path: &Path) -> Result<u64> {
+    if !cache_artifacts(cache_path).iter().any(|file| file.exists()) {
+        return Ok(0);
+    }
+    let _lock = CacheLock::exclusive(cache_path)?;
+    let mut reclaimed = 0;
+    for file in cache_artifacts(cache_path) {
+        let metadata = match fs::symlink_metadata(&file) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(PTreeError::Cache(format!("cannot inspect {}: {}", file.display(), e)).into()),
+        };
+        if !metadata.is_file() {
+            return Err(PTreeError::Cache(format!("refusing to remove {}: not a regular file", file.display())).into());
+        }
+
+        match fs::remove_file(&file) {
+            Ok(()) => reclaimed += metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(PTreeError::Cache(format!("cannot remove {}: {}", file.display(), e)).into()),
+        }
+    }
+    Ok(reclaimed)
+}
+
+/// Whether `path` lives on drive `drive` (case-insensitive)
+fn is_on_drive(path: &Path, drive: char) -> bool {
+    use std::path::{Component, Prefix};
+    match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => letter.eq_ignore_ascii_case(&(drive as u8)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{fixture_cache, test_entry};
+    
+    #[test]
+    fn test_cache_creation() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join("ptree_test_cache");
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("test.dat");
+        
+        let cache = DiskCache::open(&cache_path)?;
+        assert!(cache.entries.is_empty());
+        
+        // Clean up
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash_stability() {
+        // Same inputs should produce same hash
+        let path = std::path::Path::new("C:\\test");
+        let modified = Utc::now();
+        let children: ChildNames = vec!["file1.txt".to_string(), "file2.txt".to_string()].into();
+        let child_hashes = HashMap::new();
+
+        let hash1 = compute_content_hash(path, modified, &children, &child_hashes);
+        let hash2 = compute_content_hash(path, modified, &children, &child_hashes);
+
+        assert_eq!(hash1, hash2, "Identical inputs should produce identical hashes");
+    }
+
+    #[test]
+    fn test_content_hash_sensitivity() {
+        // Different inputs should produce different hashes
+        let path = std::path::Path::new("C:\\test");
+        let modified = Utc::now();
+        
+        // Base hash
+        let children: ChildNames = vec!["file1.txt".to_string()].into();
+        let child_hashes = HashMap::new();
+        let base_hash = compute_content_hash(path, modified, &children, &child_hashes);
+
+        // Hash with additional file
+        let children_added: ChildNames = vec!["file1.txt".to_string(), "file2.txt".to_string()].into();
+        let hash_added = compute_content_hash(path, modified, &children_added, &child_hashes);
+        assert_ne!(base_hash, hash_added, "Adding a file should change hash");
+
+        // Hash with removed file
+        let children_removed = ChildNames::new();
+        let hash_removed = compute_content_hash(path, modified, &children_removed, &child_hashes);
+        assert_ne!(base_hash, hash_removed, "Removing a file should change hash");
+
+        // Hash with renamed file
+        let children_renamed: ChildNames = vec!["renamed_file.txt".to_string()].into();
+        let hash_renamed = compute_content_hash(path, modified, &children_renamed, &child_hashes);
+        assert_ne!(base_hash, hash_renamed, "Renaming a file should change hash");
+    }
+
+    #[test]
+    fn test_merkle_propagation() {
+        // Child hash changes should affect parent hash
+        let parent_path = std::path::Path::new("/parent");
+        let child_path = std::path::Path::new("/parent/child");
+        let modified = Utc::now();
+
+        // Parent with no child hashes
+        let parent_children: ChildNames = vec!["child".to_string()].into();
+        let mut child_hashes = HashMap::new();
+        child_hashes.insert(child_path.to_path_buf(), 12345u64);
+
+        let parent_hash1 = compute_content_hash(parent_path, modified, &parent_children, &child_hashes);
+
+        // Change child hash
+        child_hashes.insert(child_path.to_path_buf(), 54321u64);
+        let parent_hash2 = compute_content_hash(parent_path, modified, &parent_children, &child_hashes);
+
+        assert_ne!(parent_hash1, parent_hash2, "Child hash change should affect parent hash");
+    }
+
+    #[test]
+    fn test_has_directory_changed() {
+        let path = std::path::Path::new("C:\\test");
+
+        let old_entry = DirEntry {
+            path: path.to_path_buf(),
+            name: "test".to_string(),
+            modified: Utc::now(),
+            content_hash: 12345u64,
+            children: vec!["file.txt".to_string()].into(),
+            is_dir: true,
+            ..Default::default()
+        };
+
+        let new_entry_unchanged = DirEntry {
+            path: path.to_path_buf(),
+            name: "test".to_string(),
+            modified: Utc::now(),
+            content_hash: 12345u64,
+            children: vec!["file.txt".to_string()].into(),
+            is_dir: true,
+            ..Default::default()
+        };
+
+        let new_entry_changed = DirEntry {
+            path: path.to_path_buf(),
+            name: "test".to_string(),
+            modified: Utc::now(),
+            content_hash: 54321u64,
+            children: vec!["file.txt".to_string(), "newfile.txt".to_string()].into(),
+            is_dir: true,
+            ..Default::default()
+        };
+
+        assert!(!has_directory_changed(&old_entry, &new_entry_unchanged), "Same hash should not indicate change");
+        assert!(has_directory_changed(&old_entry, &new_entry_changed), "Different hash should indicate change");
+    }
+
+    #[test]
+    fn test_csv_output_sorted_and_escaped() -> Result<()> {
+        let mut cache = fixture_cache();
+        let deep = cache.entries.get_mut(Path::new("/scan/alpha/deep")).unwrap();
+        (deep.is_dir, deep.size) = (false, 1536);
+        cache.compute_totals();
+        let csv = cache.build_csv_output()?;
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[0], "path,name,parent,child_count,modified,size,is_hidden,symlink_target");
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("/scan,scan,/,2,"));
+        assert!(lines[2].starts_with("/scan/alpha,alpha,/scan,1,"));
+        assert!(lines[3].starts_with("/scan/alpha/deep,deep,/scan/alpha,0,"));
+        assert!(lines[4].starts_with(
+            "\"/scan/beta, \"\"quoted\"\"\",\"beta, \"\"quoted\"\"\",/scan,0,"
+        ));
+        // Bytes: a directory's total, a file's own size
+        let sizes: Vec<&str> = lines[1..4].iter().map(|line| line.split(',').nth(5).unwrap()).collect();
+        assert_eq!(sizes, ["1536", "1536", "1536"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_output_respects_max_depth() -> Result<()> {
+        let cache = fixture_cache();
+        let csv = cache.build_csv_output_with_depth(Some(1))?;
+
+        assert_eq!(csv.lines().count(), 4);
+        assert!(!csv.contains("/scan/alpha/deep"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ndjson_lines_parse_and_cover_each_entry_once() -> Result<()> {
+        let cache = fixture_cache();
+        let mut buffer = Vec::new();
+        cache.write_ndjson_output(&mut buffer, None)?;
+
+        let mut seen = Vec::new();
+        for line in String::from_utf8(buffer)?.lines() {
+            let value: serde_json::Value = serde_json::from_str(line)?;
+            seen.push(value["path"].as_str().unwrap().to_string());
+        }
+
+        let mut expected: Vec<String> = cache
+            .entries
+            .keys()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        expected.sort();
+        let mut sorted_seen = seen.clone();
+        sorted_seen.sort();
+
+        assert_eq!(sorted_seen, expected);
+        assert_eq!(seen[0], "/scan");
+        assert_eq!(seen[1], "/scan/alpha");
+        Ok(())
+    }
+
+    #[test]
+    fn test_print0_writes_nul_terminated_paths() -> Result<()> {
+        let mut cache = fixture_cache();
+        let mut buffer = Vec::new();
+        cache.write_print0_output(&mut buffer, None)?;
+        assert_eq!(buffer, b"/scan\0/scan/alpha\0/scan/alpha/deep\0/scan/beta, \"quoted\"\0");
+
+        buffer.clear();
+        cache.output_options.set_display_root(Some(PathBuf::from("/scan/alpha")));
+        cache.write_print0_output(&mut buffer, Some(0))?;
+        assert_eq!(buffer, b"/scan/alpha\0");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_symlinks_marks_missing_targets() -> Result<()> {
+        let mut cache = fixture_cache();
+        let deep = PathBuf::from("/scan/alpha/deep");
+        let beta = PathBuf::from("/scan/beta, \"quoted\"");
+        // Relative, so checked as /scan/alpha/missing
+        cache.entries.get_mut(&deep).unwrap().symlink_target = Some(PathBuf::from("missing"));
+        cache.entries.get_mut(&beta).unwrap().symlink_target = Some(std::env::temp_dir());
+
+        let tree = cache.build_tree_output()?;
+        assert!(!tree.contains("[broken]"), "checking is opt-in: {}", tree);
+
+        cache.output_options.check_symlinks = true;
+        let tree = cache.build_tree_output()?;
+        assert!(tree.contains("deep (→ missing) [broken]\n"), "{}", tree);
+        assert_eq!(tree.matches("[broken]").count(), 1, "{}", tree);
+
+        let colored = cache.build_colored_tree_output_with_theme(None, &Theme::default())?;
+        assert!(colored.contains("\x1b[31mdeep (→ missing) [broken]\x1b[0m"), "{:?}", colored);
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"][0]["children"][0]["broken"], true);
+        assert_eq!(json["children"][1]["broken"], false);
+        assert!(json["children"][0].get("broken").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_directories_are_marked() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.entries.get_mut(Path::new("/scan/beta, \"quoted\"")).unwrap().access_error =
+            Some("Permission denied (os error 13)".to_string());
+
+        let tree = cache.build_tree_output()?;
+        assert!(tree.contains("└── beta, \"quoted\" [error opening dir]\n"), "{}", tree);
+        assert_eq!(tree.matches("[error opening dir]").count(), 1, "{}", tree);
+
+        let colored = cache.build_colored_tree_output_with_theme(None, &Theme::default())?;
+        assert!(colored.contains("\x1b[31mbeta, \"quoted\" [error opening dir]\x1b[0m"), "{:?}", colored);
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"][1]["error"], "Permission denied (os error 13)");
+        assert!(json["children"][0].get("error").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_points_are_annotated() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.entries.get_mut(Path::new("/scan/alpha")).unwrap().is_mount = true;
+
+        let tree = cache.build_tree_output()?;
+        assert!(tree.contains("alpha [mounted]\n"), "{}", tree);
+        assert_eq!(tree.matches("[mounted]").count(), 1, "{}", tree);
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"][0]["mounted"], true);
+        assert!(json["children"][1].get("mounted").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dot_output_structure() -> Result<()> {
+        let mut cache = fixture_cache();
+        let link = PathBuf::from("/scan/alpha/deep");
+        cache.entries.get_mut(&link).unwrap().symlink_target = Some(PathBuf::from("/elsewhere"));
+
+        // Files are no nodes
+        let alpha = PathBuf::from("/scan/alpha");
+        cache.entries.get_mut(&alpha).unwrap().children.push("notes.txt");
+        let mut file = test_entry(alpha.join("notes.txt"), &[]);
+        file.is_dir = false;
+        cache.entries.insert(file.path.clone(), file);
+
+        let dot = cache.build_dot_output()?;
+        assert!(dot.starts_with("digraph ptree {"));
+        assert!(!dot.contains("notes.txt"));
+        assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+        assert_eq!(dot.matches(" -> ").count(), 3);
+        assert!(dot.contains("label=\"beta, \\\"quoted\\\"\""));
+        assert_eq!(dot.matches("shape=cds").count(), 1);
+
+        let shallow = cache.build_dot_output_with_depth(Some(1))?;
+        assert_eq!(shallow.matches(" -> ").count(), 2);
+
+        // No limit given: stops at the default depth
+        let mut deep = PathBuf::from("/scan/alpha/deep");
+        for level in 0..DEFAULT_DOT_DEPTH {
+            let name = format!("level{}", level);
+            cache.entries.get_mut(&deep).unwrap().children.push(&name);
+            deep = deep.join(name);
+            cache.entries.insert(deep.clone(), test_entry(&deep, &[]));
+        }
+        assert_eq!(cache.build_dot_output()?.matches(" -> ").count(), DEFAULT_DOT_DEPTH + 1);
+        assert_eq!(cache.build_dot_output_with_depth(Some(10))?.matches(" -> ").count(), DEFAULT_DOT_DEPTH + 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_html_output_nesting_and_escaping() -> Result<()> {
+        let mut cache = fixture_cache();
+        let root = cache.root.clone();
+        cache.entries.get_mut(&root).unwrap().children.push("<script>");
+        cache.entries.insert(root.join("<script>"), test_entry(root.join("<script>"), &[]));
+        cache.entries.get_mut(&root.join("alpha")).unwrap().is_hidden = true;
+
+        let html = cache.build_html_output()?;
+        assert_eq!(html.matches("<details").count(), html.matches("</details>").count());
+        assert_eq!(html.matches("<ul>").count(), html.matches("</ul>").count());
+        assert_eq!(html.matches("<li").count(), html.matches("</li>").count());
+        assert!(html.contains("<li>&lt;script&gt;</li>"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<li class=\"hidden\"><details>"));
+
+        // Depth 1: alpha is rendered as a leaf, deep never appears
+        let shallow = cache.build_html_output_with_depth(Some(1))?;
+        assert!(!shallow.contains("deep"));
+        assert_eq!(shallow.matches("<details").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_markdown_indentation_matches_depth() -> Result<()> {
+        let mut cache = fixture_cache();
+        let deep = PathBuf::from("/scan/alpha/deep");
+        cache.entries.get_mut(&deep).unwrap().symlink_target = Some(PathBuf::from("/target"));
+
+        let markdown = cache.build_markdown_output()?;
+        let lines: Vec<&str> = markdown.lines().collect();
+        assert_eq!(lines, vec![
+            "- /scan",
+            "  - alpha",
+            "    - deep",
+            "  - beta, \"quoted\"",
+        ]);
+
+        let with_links = cache.build_markdown_output_with_depth(None, true)?;
+        assert!(with_links.contains("    - deep → /target\n"));
+
+        let shallow = cache.build_markdown_output_with_depth(Some(1), false)?;
+        assert_eq!(shallow.lines().count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_mtime_relative_and_rfc3339() {
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().with_timezone(&Utc);
+        let three_days = now - chrono::Duration::days(3);
+
+        assert_eq!(format_mtime(three_days, MtimeFormat::Relative, now), "3d ago");
+        assert_eq!(format_mtime(now - chrono::Duration::minutes(5), MtimeFormat::Relative, now), "5m ago");
+        assert_eq!(format_mtime(now + chrono::Duration::seconds(30), MtimeFormat::Relative, now), "0s ago");
+        assert_eq!(format_mtime(three_days, MtimeFormat::Rfc3339, now), "2024-05-07T12:00:00Z");
+    }
+
+    #[test]
+    fn test_mtime_shows_stored_timestamp() -> Result<()> {
+        let mut cache = fixture_cache();
+        let alpha = PathBuf::from("/scan/alpha");
+        let stale = DateTime::parse_from_rfc3339("2001-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        cache.entries.get_mut(&alpha).unwrap().modified = stale;
+
+        let plain = cache.build_tree_output()?;
+        assert!(!plain.contains('['));
+
+        cache.output_options.mtime = Some(MtimeFormat::Rfc3339);
+        let tree = cache.build_tree_output()?;
+        assert!(tree.contains("alpha [2001-01-01T00:00:00Z]\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"][0]["modified"], "2001-01-01T00:00:00+00:00");
+        assert!(json["modified"].is_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_entry_spares_siblings_sharing_a_prefix() {
+        let root = PathBuf::from("/scan");
+        let mut cache = DiskCache::new_empty();
+        cache.root = root.clone();
+        for (path, children) in [
+            ("/scan", &["foo", "foobar", "foo2"][..]),
+            ("/scan/foo", &["inner"]),
+            ("/scan/foo/inner", &[]),
+            ("/scan/foobar", &["inner"]),
+            ("/scan/foobar/inner", &[]),
+            ("/scan/foo2", &[]),
+        ] {
+            cache.entries.insert(PathBuf::from(path), test_entry(Path::new(path), children));
+        }
+
+        cache.remove_entry(&root.join("foo"));
+        let mut left: Vec<_> = cache.entries.keys().map(|path| path.display().to_string()).collect();
+        left.sort();
+        assert_eq!(left, ["/scan", "/scan/foo2", "/scan/foobar", "/scan/foobar/inner"]);
+        assert_eq!(cache.entries[&root].children, ["foobar", "foo2"]);
+        assert_eq!(cache.build_tree_output().unwrap(), "/scan\n├── foo2\n└── foobar\n    └── inner\n");
+    }
+
+    #[test]
+    fn test_no_hidden_prunes_subtrees_but_keeps_root() -> Result<()> {
+        let mut cache = fixture_cache();
+        let root = PathBuf::from("/scan");
+        let alpha = root.join("alpha");
+        cache.entries.get_mut(&root).unwrap().is_hidden = true;
+        cache.entries.get_mut(&alpha).unwrap().is_hidden = true;
+        cache.output_options.hide_hidden = true;
+
+        let tree = cache.build_tree_output()?;
+        assert_eq!(tree, "/scan\n└── beta, \"quoted\"\n");
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"].as_array().unwrap().len(), 1);
+
+        // Still cached, so toggling the flag back needs no rescan
+        assert!(cache.get_entry(&alpha.join("deep")).is_some());
+        cache.output_options.hide_hidden = false;
+        assert!(cache.build_tree_output()?.contains("deep"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_keeps_ancestors_of_deep_matches() -> Result<()> {
+        let mut cache = fixture_cache();
+        let deep = PathBuf::from("/scan/alpha/deep");
+        cache.entries.get_mut(&deep).unwrap().children = vec!["lib.cache".to_string()].into();
+        cache.output_options.set_match_patterns(&["*.cache".to_string()])?;
+
+        let tree = cache.build_tree_output()?;
+        let lines: Vec<&str> = tree.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", tree);
+        assert!(lines[1].ends_with("alpha"));
+        assert!(lines[2].ends_with("deep"));
+        assert!(lines[3].ends_with("lib.cache"));
+        assert!(!tree.contains("beta"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_filter_keeps_ancestors_dimmed() -> Result<()> {
+        let mut cache = fixture_cache();
+        let old = DateTime::parse_from_rfc3339("2001-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let deep = PathBuf::from("/scan/alpha/deep");
+        let beta = PathBuf::from("/scan/beta, \"quoted\"");
+        for entry in cache.entries.values_mut() {
+            entry.modified = old;
+        }
+        cache.entries.get_mut(&deep).unwrap().modified = Utc::now();
+        cache.entries.get_mut(&deep).unwrap().children = vec!["notes.txt".to_string(), "fresh.txt".to_string()].into();
+        cache.entries.get_mut(&beta).unwrap().children = vec!["new.log".to_string()].into();
+        for (path, modified) in [(deep.join("notes.txt"), old), (deep.join("fresh.txt"), Utc::now()), (beta.join("new.log"), Utc::now())] {
+            let mut file = test_entry(&path, &[]);
+            (file.is_dir, file.modified) = (false, modified);
+            cache.entries.insert(path, file);
+        }
+
+        // Files are kept on their own mtime, whatever their directory's
+        cache.output_options.set_time_filter(Some(Utc::now() - chrono::Duration::days(7)), None);
+        assert_eq!(
+            cache.build_tree_output()?,
+            "/scan\n├── alpha\n│   └── deep\n│       └── fresh.txt\n└── beta, \"quoted\"\n    └── new.log\n"
+        );
+        let colored = cache.build_colored_tree_output_with_theme(None, &Theme::default())?;
+        assert!(colored.contains("\x1b[2malpha\x1b[0m"), "{:?}", colored);
+        assert!(colored.contains("\x1b[2mbeta"), "{:?}", colored);
+        assert!(!colored.contains("\x1b[2mdeep"), "{:?}", colored);
+
+        cache.output_options.set_time_filter(None, Some(Utc::now() - chrono::Duration::days(7)));
+        assert_eq!(
+            cache.build_tree_output()?,
+            "/scan\n├── alpha\n│   └── deep\n│       └── notes.txt\n└── beta, \"quoted\"\n"
+        );
+        let colored = cache.build_colored_tree_output_with_theme(None, &Theme::default())?;
+        assert!(colored.contains("\x1b[2mdeep"), "{:?}", colored);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_drops_directories_emptied_by_filters() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.output_options.set_prune(true);
+        // deep and beta are empty; alpha only holds deep, so it goes too
+        assert_eq!(cache.build_tree_output()?, "/scan\n");
+
+        let deep = PathBuf::from("/scan/alpha/deep");
+        cache.entries.get_mut(&deep).unwrap().children = vec!["App.sln".to_string(), "obj".to_string()].into();
+        let mut sln = test_entry(deep.join("App.sln"), &[]);
+        sln.is_dir = false;
+        cache.entries.insert(sln.path.clone(), sln);
+        cache.entries.insert(deep.join("obj"), test_entry(deep.join("obj"), &[]));
+        cache.output_options.set_prune(true);
+        assert_eq!(cache.build_tree_output()?, "/scan\n└── alpha\n    └── deep\n        └── App.sln\n");
+
+        // Ignoring the only file empties the whole branch again
+        cache.output_options.set_ignore_patterns(&["*.sln".to_string()])?;
+        assert_eq!(cache.build_tree_output()?, "/scan\n");
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"], serde_json::json!([]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_prunes_matching_subtrees() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.output_options.set_ignore_patterns(&["alpha".to_string(), "*.cache".to_string()])?;
+
+        let tree = cache.build_tree_output()?;
+        assert!(!tree.contains("alpha"));
+        assert!(!tree.contains("deep"));
+        assert!(tree.contains("beta"));
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"].as_array().unwrap().len(), 1);
+
+        assert!(cache.output_options.set_ignore_patterns(&["[".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_keys_order_children() -> Result<()> {
+        let root = PathBuf::from("/scan");
+        let mut cache = DiskCache::new_empty();
+        cache.root = root.clone();
+        cache.entries.insert(root.clone(), test_entry(&root, &["a", "b", "c", "ghost"]));
+        cache.entries.insert(root.join("a"), test_entry(root.join("a"), &["x"]));
+        cache.entries.insert(root.join("a/x"), test_entry(root.join("a/x"), &[]));
+        cache.entries.insert(root.join("b"), test_entry(root.join("b"), &["y", "z"]));
+        cache.entries.insert(root.join("b/y"), test_entry(root.join("b/y"), &["w"]));
+        cache.entries.insert(root.join("b/y/w"), test_entry(root.join("b/y/w"), &[]));
+        cache.entries.insert(root.join("b/z"), test_entry(root.join("b/z"), &[]));
+        cache.entries.insert(root.join("c"), test_entry(root.join("c"), &["q", "r"]));
+        let old = DateTime::parse_from_rfc3339("2001-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        cache.entries.get_mut(&root.join("b")).unwrap().modified = old;
+        // Sizes rank by bytes, not entries: c's one cached file outweighs b's three entries
+        cache.entries.insert(root.join("c/q"), test_entry(root.join("c/q"), &[]));
+        for (file, size) in [("a/x", 5000), ("b/z", 100), ("c/q", 10_000)] {
+            let entry = cache.entries.get_mut(&root.join(file)).unwrap();
+            (entry.is_dir, entry.size) = (false, size);
+        }
+        cache.compute_totals();
+
+        let top_level = |cache: &DiskCache| -> Result<Vec<String>> {
+            let json: serde_json::Value = serde_json::from_str(&cache.build_json_output_with_depth(Some(1))?)?;
+            Ok(json["children"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| c["name"].as_str().unwrap().to_string())
+                .collect())
+        };
+
+        assert_eq!(top_level(&cache)?, ["a", "b", "c", "ghost"]);
+
+        cache.output_options.sort = SortKey::Size;
+        assert_eq!(top_level(&cache)?, ["c", "a", "b", "ghost"]);
+        cache.output_options.reverse = true;
+        assert_eq!(top_level(&cache)?, ["ghost", "b", "a", "c"]);
+        cache.output_options.reverse = false;
+
+        cache.output_options.sort = SortKey::Children;
+        assert_eq!(top_level(&cache)?, ["b", "c", "a", "ghost"]);
+
+        cache.output_options.sort = SortKey::Mtime;
+        assert_eq!(top_level(&cache)?.last().unwrap(), "ghost");
+        assert_eq!(top_level(&cache)?[2], "b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_natural_cmp_ordering() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["a2", "a10", "A1", "b"], &["A1", "a2", "a10", "b"]),
+            (&["chunk12", "chunk1", "chunk3"], &["chunk1", "chunk3", "chunk12"]),
+            (&["v1.10", "v1.9", "v1.2"], &["v1.2", "v1.9", "v1.10"]),
+            (&["a01", "a1", "a001"], &["a001", "a01", "a1"]),
+            (&["b", "B", "a"], &["a", "B", "b"]),
+            (&["ü2", "é", "ü10", "z"], &["z", "é", "ü2", "ü10"]),
+        ];
+
+        for (input, expected) in cases {
+            let mut names = input.to_vec();
+            names.sort_by(|a, b| natural_cmp(a, b));
+            assert_eq!(&names, expected, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_natural_sort_applies_to_tree_output() -> Result<()> {
+        let root = PathBuf::from("/scan");
+        let mut cache = DiskCache::new_empty();
+        cache.root = root.clone();
+        cache.entries.insert(root.clone(), test_entry(&root, &["file10", "file2", "File1"]));
+
+        assert!(cache.build_tree_output()?.starts_with("/scan\n├── File1\n├── file10\n"));
+
+        cache.output_options.natural_sort = true;
+        let tree = cache.build_tree_output()?;
+        assert_eq!(tree, "/scan\n├── File1\n├── file2\n└── file10\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_counts_rendered_entries_only() -> Result<()> {
+        let mut cache = fixture_cache();
+        let deep = PathBuf::from("/scan/alpha/deep");
+        cache.entries.get_mut(&deep).unwrap().children = vec!["notes.txt".to_string()].into();
+        let mut file = test_entry(deep.join("notes.txt"), &[]);
+        file.is_dir = false;
+        cache.entries.insert(deep.join("notes.txt"), file);
+        cache.output_options.show_report = true;
+
+        let tree = cache.build_tree_output()?;
+        assert!(tree.ends_with("\n\n3 directories, 1 file\n"), "{}", tree);
+
+        let shallow = cache.build_tree_output_with_depth(Some(1))?;
+        assert!(shallow.ends_with("\n2 directories, 0 files\n"));
+
+        cache.output_options.set_ignore_patterns(&["alpha".to_string()])?;
+        assert_eq!(cache.summarize(None), RenderSummary { directories: 1, files: 0, unknown: 0 });
+
+        // A child without a cached entry makes the file count unknown
+        cache.entries.get_mut(&PathBuf::from("/scan")).unwrap().children.push("mystery");
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["summary"]["directories"], 1);
+        assert!(json["summary"]["files"].is_null());
+        assert!(cache.build_tree_output()?.ends_with("1 directory, ? files\n"));
+
+        cache.output_options.show_report = false;
+        assert!(!cache.build_tree_output()?.contains("director"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_subtree_renders_from_display_root() -> Result<()> {
+        let mut cache = fixture_cache();
+        let alpha = cache
+            .find_cached_path(Path::new("/scan/./beta, \"quoted\"/../alpha/"))
+            .expect("alpha is cached");
+        assert_eq!(alpha, PathBuf::from("/scan/alpha"));
+        assert!(cache.find_cached_path(Path::new("/scan/missing")).is_none());
+
+        cache.output_options.display_root = Some(alpha);
+        assert_eq!(cache.build_tree_output()?, "/scan/alpha\n└── deep\n");
+
+        let csv = cache.build_csv_output()?;
+        assert_eq!(csv.lines().count(), 3);
+        assert!(!csv.contains("beta"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_counts_show_real_child_count_past_depth_limit() -> Result<()> {
+        let mut cache = fixture_cache();
+        let alpha = PathBuf::from("/scan/alpha");
+        cache.entries.get_mut(&alpha).unwrap().children =
+            (0..1482).map(|i| format!("pkg{}", i)).collect();
+        cache.output_options.show_counts = true;
+
+        let tree = cache.build_tree_output_with_depth(Some(1))?;
+        assert!(tree.contains("── alpha [1,482]\n"), "{}", tree);
+        assert!(!tree.contains("pkg"));
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output_with_depth(Some(1))?)?;
+        assert_eq!(json["child_count"], 2);
+        assert_eq!(json["children"][0]["child_count"], 1482);
+
+        // Scanned entries split the count; old ones (no split recorded) keep the total
+        let entry = cache.entries.get_mut(&alpha).unwrap();
+        (entry.dir_count, entry.file_count) = (1, 1481);
+        let tree = cache.build_tree_output_with_depth(Some(1))?;
+        assert!(tree.contains("── alpha [1 dir, 1,481 files]\n"), "{}", tree);
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output_with_depth(Some(1))?)?;
+        assert_eq!((json["children"][0]["dir_count"].clone(), json["children"][0]["file_count"].clone()), (json!(1), json!(1481)));
+
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+        Ok(())
+    }
+
+    #[test]
+    fn test_annotations_align_within_window() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.output_options.show_counts = true;
+        cache.output_options.align_window = 100;
+        // Column (in characters, not bytes) where each line's annotation starts
+        let columns = |tree: &str| -> Vec<usize> {
+            tree.lines().filter_map(|line| line.find(" [").map(|at| display_width(&line[..at]))).collect()
+        };
+
+        let tree = cache.build_tree_output()?;
+        assert!(tree.contains("├── alpha          [1]\n"), "{}", tree);
+        assert_eq!(columns(&tree), [18, 18, 18], "{}", tree);
+
+        let colored = cache.build_colored_tree_output_with_theme(None, &Theme::default())?;
+        assert!(colored.contains('\x1b'), "{:?}", colored);
+        assert_eq!(columns(&colored), [18, 18, 18], "{:?}", colored);
+
+        // Windows are aligned independently: the root and alpha form the first
+        cache.output_options.align_window = 2;
+        assert_eq!(columns(&cache.build_tree_output()?), [9, 18, 18]);
+
+        cache.output_options.align_window = 0;
+        assert_eq!(columns(&cache.build_tree_output()?), [9, 12, 18]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_du_sums_file_sizes_and_marks_lower_bounds() -> Result<()> {
+        let mut cache = fixture_cache();
+        let deep = PathBuf::from("/scan/alpha/deep");
+        cache.entries.get_mut(&deep).unwrap().children = vec!["a.bin".to_string(), "b.bin".to_string()].into();
+        for (name, size) in [("a.bin", 1024), ("b.bin", 512)] {
+            let mut file = test_entry(deep.join(name), &[]);
+            file.is_dir = false;
+            file.size = size;
+            cache.entries.insert(file.path.clone(), file);
+        }
+        cache.compute_totals();
+        cache.output_options.du = true;
+
+        let tree = cache.build_tree_output()?;
+        assert!(tree.starts_with("/scan [1.5 KiB]\n"), "{}", tree);
+        assert!(tree.contains("── deep [1.5 KiB]\n"), "{}", tree);
+        assert!(tree.contains("── a.bin [1.0 KiB]\n"), "{}", tree);
+        assert!(tree.contains("beta, \"quoted\" [0 B]\n"), "{}", tree);
+
+        // JSON tells files from (empty) directories
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        let deep_json = &json["children"][0]["children"][0];
+        assert_eq!((deep_json["type"].as_str(), deep_json["size"].as_u64()), (Some("directory"), None));
+        let file_json = &deep_json["children"][0];
+        assert_eq!((file_json["type"].as_str(), file_json["size"].as_u64()), (Some("file"), Some(1024)));
+        assert!(file_json.get("file_count").is_none());
+
+        // A skipped child anywhere below makes every ancestor a minimum
+        cache.entries.get_mut(&deep).unwrap().skipped_children = true;
+        cache.invalidate_totals(&deep);
+        cache.refresh_totals();
+        let tree = cache.build_tree_output()?;
+        assert!(tree.starts_with("/scan [≥ 1.5 KiB]\n"), "{}", tree);
+        assert!(tree.contains("beta, \"quoted\" [0 B]\n"), "{}", tree);
+
+        // --size labels files only, aligned like the other annotations
+        (cache.output_options.du, cache.output_options.size) = (false, true);
+        let tree = cache.build_tree_output()?;
+        assert!(tree.starts_with("/scan\n"), "{}", tree);
+        assert!(tree.contains("── deep\n"), "{}", tree);
+        assert!(tree.contains("── a.bin [1.0 KiB]\n"), "{}", tree);
+        assert!(tree.contains("── b.bin [512 B]\n"), "{}", tree);
+
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        Ok(())
+    }
+
+    #[test]
+    fn test_colored_tree_uses_theme_per_entry_kind() -> Result<()> {
+        let mut cache = fixture_cache();
+        let alpha = PathBuf::from("/scan/alpha");
+        cache.entries.get_mut(&alpha).unwrap().is_hidden = true;
+        let mut theme = Theme::default();
+        theme.apply_spec("rt=1:di=32:hi=90:tr=");
+
+        let tree = cache.build_colored_tree_output_with_theme(None, &theme)?;
+        let lines: Vec<&str> = tree.lines().collect();
+        assert_eq!(lines[0], "\x1b[1m/scan\x1b[0m");
+        assert_eq!(lines[1], "├── \x1b[90malpha\x1b[0m");
+        assert!(lines[3].ends_with("\x1b[32mbeta, \"quoted\"\x1b[0m"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_connectors_continue_under_non_last_siblings() -> Result<()> {
+        let mut cache = fixture_cache();
+        let beta = PathBuf::from("/scan/beta, \"quoted\"");
+        cache.entries.get_mut(&beta).unwrap().children = vec!["gamma".to_string()].into();
+
+        assert_eq!(
+            cache.build_tree_output()?,
+            "/scan\n├── alpha\n│   └── deep\n└── beta, \"quoted\"\n    └── gamma\n"
+        );
+
+        cache.output_options.charset = Charset::Ascii;
+        cache.entries.get_mut(&PathBuf::from("/scan/alpha/deep")).unwrap().symlink_target =
+            Some(PathBuf::from("/target"));
+        let ascii = cache.build_tree_output()?;
+        assert_eq!(
+            ascii,
+            "/scan\n|-- alpha\n|   `-- deep (-> /target)\n`-- beta, \"quoted\"\n    `-- gamma\n"
+        );
+        assert!(ascii.is_ascii());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_finds_children_whose_keys_differ_in_case() -> Result<()> {
+        let mut cache = fixture_cache();
+        // Listed as "Alpha" but cached under "/scan/alpha", as after a rescan on a case-insensitive volume
+        cache.entries.get_mut(&PathBuf::from("/scan")).unwrap().children = vec!["Alpha".to_string()].into();
+        cache.entries.get_mut(&PathBuf::from("/scan/alpha/deep")).unwrap().symlink_target =
+            Some(PathBuf::from("/target"));
+
+        assert_eq!(cache.build_tree_output()?, "/scan\n└── Alpha\n    └── deep (→ /target)\n");
+        let colored = cache.build_colored_tree_output()?;
+        assert!(colored.contains("deep") && colored.contains("/target"), "{}", colored);
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_metadata_schema_snapshot() -> Result<()> {
+        let mut cache = fixture_cache();
+        let stamp = DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z").unwrap().with_timezone(&Utc);
+        for entry in cache.entries.values_mut() {
+            entry.modified = stamp;
+        }
+        let alpha = PathBuf::from("/scan/alpha");
+        cache.entries.get_mut(&alpha).unwrap().is_hidden = true;
+        cache.entries.get_mut(&alpha.join("deep")).unwrap().symlink_target = Some(PathBuf::from("/target"));
+        cache.entries.get_mut(&PathBuf::from("/scan")).unwrap().children.push("uncached");
+        cache.output_options.json_metadata = true;
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        let expected = json!({
+            "path": "/scan",
+            "type": "directory",
+            "modified": "2024-01-02T03:04:05+00:00",
+            "child_count": 3,
+            "file_count": 0,
+            "dir_count": 0,
+            "is_hidden": false,
+            "children": [
+                {
+                    "name": "alpha",
+                    "path": "/scan/alpha",
+                    "type": "directory",
+                    "modified": "2024-01-02T03:04:05+00:00",
+                    "child_count": 1,
+                    "file_count": 0,
+                    "dir_count": 0,
+                    "is_hidden": true,
+                    "children": [
+                        {
+                            "name": "deep",
+                            "path": "/scan/alpha/deep",
+                            "type": "directory",
+                            "modified": "2024-01-02T03:04:05+00:00",
+                            "child_count": 0,
+                            "file_count": 0,
+                            "dir_count": 0,
+                            "is_hidden": false,
+                            "symlink_target": "/target",
+                            "children": []
+                        }
+                    ]
+                },
+                {
+                    "name": "beta, \"quoted\"",
+                    "path": "/scan/beta, \"quoted\"",
+                    "type": "directory",
+                    "modified": "2024-01-02T03:04:05+00:00",
+                    "child_count": 0,
+                    "file_count": 0,
+                    "dir_count": 0,
+                    "is_hidden": false,
+                    "children": []
+                },
+                {
+                    "name": "uncached",
+                    "path": "/scan/uncached",
+                    "children": []
+                }
+            ]
+        });
+        assert_eq!(json, expected);
+
+        cache.output_options.json_metadata = false;
+        let plain = cache.build_json_output()?;
+        assert!(!plain.contains("is_hidden") && !plain.contains("symlink_target"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_depth_limit_marks_truncated_branches() -> Result<()> {
+        let cache = fixture_cache();
+
+        // Depth 1 cuts alpha's single child
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output_with_depth(Some(1))?)?;
+        assert_eq!(json["children"][0]["truncated"], true);
+        assert_eq!(json["children"][0]["omitted_children"], 1);
+        // Empty directories are not truncated
+        assert!(json["children"][1].get("truncated").is_none());
+
+        let tree = cache.build_tree_output_with_depth(Some(1))?;
+        assert_eq!(tree, "/scan\n├── alpha\n│   └── …\n└── beta, \"quoted\"\n");
+
+        // Depth equal to the tree height renders everything, with no markers
+        let full_json = cache.build_json_output_with_depth(Some(2))?;
+        assert!(!full_json.contains("truncated"));
+        assert_eq!(cache.build_tree_output_with_depth(Some(2))?, cache.build_tree_output()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_path_prints_cache_keys() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.output_options.full_path = true;
+        cache.output_options.set_ignore_patterns(&["beta*".to_string()])?;
+
+        let tree = cache.build_tree_output_with_depth(Some(2))?;
+        assert_eq!(tree, "/scan\n└── /scan/alpha\n    └── /scan/alpha/deep\n");
+
+        // Every printed path resolves back to a cache entry (usable with --subtree)
+        for line in tree.lines().skip(1) {
+            let path = line.trim_start_matches(|c: char| !c.is_ascii() || c == ' ');
+            assert!(cache.find_cached_path(Path::new(path)).is_some(), "{}", path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_indent_emits_bare_lines_in_tree_order() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.output_options.no_indent = true;
+
+        assert_eq!(cache.build_tree_output()?, "/scan\nalpha\ndeep\nbeta, \"quoted\"\n");
+        assert_eq!(cache.build_tree_output_with_depth(Some(1))?, "/scan\nalpha\nbeta, \"quoted\"\n");
 
-        report
+        cache.output_options.full_path = true;
+        let colored = cache.build_colored_tree_output()?;
+        let lines: Vec<&str> = colored.lines().collect();
+        assert_eq!(lines[2], "\x1b[94m/scan/alpha/deep\x1b[0m");
+        Ok(())
     }
 
-    /// Remove entry and all child entries
-    pub fn remove_entry(&mut self, path: &Path) {
-        self.entries.remove(path);
-        let prefix = path.to_string_lossy().to_string();
-        self.entries.retain(|k, _| {
-            !k.to_string_lossy().starts_with(&prefix) || k == path
-        });
+    #[test]
+    fn test_file_limit_collapses_large_directories() -> Result<()> {
+        let mut cache = fixture_cache();
+        let alpha = PathBuf::from("/scan/alpha");
+        cache.entries.get_mut(&alpha).unwrap().children = (0..1500).map(|i| format!("m{}", i)).collect();
+        cache.output_options.file_limit = Some(1000);
+        cache.output_options.show_report = true;
+
+        let tree = cache.build_tree_output()?;
+        assert!(tree.starts_with("/scan\n├── alpha\n│   └── [1,500 entries]\n└── beta"), "{}", tree);
+        assert!(tree.ends_with("2 directories, 0 files\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"][0]["collapsed"], true);
+        assert_eq!(json["children"][0]["omitted_children"], 1500);
+        assert_eq!(json["children"][0]["children"].as_array().unwrap().len(), 0);
+
+        cache.output_options.file_limit = None;
+        assert!(cache.build_tree_output()?.contains("m999"));
+        Ok(())
     }
 
-    // ============================================================================
-    // ASCII Tree Output
-    // ============================================================================
+    #[test]
+    fn test_default_cache_dir_follows_the_platform() -> Result<()> {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| OsString::from(value))
+        };
+        let all = env(&[
+            ("APPDATA", "C:\\Users\\me\\AppData\\Roaming"),
+            ("HOME", "/home/me"),
+            ("XDG_CACHE_HOME", "/xdg"),
+        ]);
+        assert_eq!(
+            default_cache_dir("windows", all)?,
+            Path::new("C:\\Users\\me\\AppData\\Roaming").join("ptree").join("cache")
+        );
+        assert_eq!(default_cache_dir("macos", all)?, Path::new("/home/me/Library/Caches/ptree"));
+        assert_eq!(default_cache_dir("linux", all)?, Path::new("/xdg/ptree"));
+        // A relative XDG_CACHE_HOME is ignored, as the spec asks
+        let relative = env(&[("HOME", "/home/me"), ("XDG_CACHE_HOME", "rel"), ("PTREE_CACHE_DIR", "")]);
+        assert_eq!(default_cache_dir("freebsd", relative)?, Path::new("/home/me/.cache/ptree"));
 
-    /// Build ASCII tree output with optional max depth
-    pub fn build_tree_output(&self) -> Result<String> {
-        self.build_tree_output_with_depth(None)
+        let overridden = env(&[("PTREE_CACHE_DIR", "/fast")]);
+        for os in ["windows", "macos", "linux"] {
+            assert_eq!(default_cache_dir(os, overridden)?, Path::new("/fast"));
+        }
+
+        for (os, missing) in [("windows", "APPDATA"), ("macos", "HOME"), ("linux", "HOME")] {
+            let err = default_cache_dir(os, env(&[("XDG_CACHE_HOME", "")])).unwrap_err();
+            assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CacheLocation { missing: m }) if *m == missing));
+            assert!(err.to_string().contains(missing), "{}", err);
+        }
+        Ok(())
     }
 
-    /// Build ASCII tree output with optional max depth limit
-    pub fn build_tree_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
-        let mut output = String::new();
+    #[test]
+    fn test_cache_path_accepts_directory_or_file() -> Result<()> {
+        let existing_dir = std::env::temp_dir();
+        assert_eq!(
+            get_cache_path_custom(None, Some(&existing_dir), None)?,
+            existing_dir.join(CACHE_FILE_NAME)
+        );
+        assert_eq!(
+            get_cache_path_custom(None, Some(Path::new("/fast/ptree-cache")), None)?,
+            PathBuf::from("/fast/ptree-cache").join(CACHE_FILE_NAME)
+        );
+        assert_eq!(
+            get_cache_path_custom(Some("/ignored"), Some(Path::new("/fast/scan.dat")), None)?,
+            PathBuf::from("/fast/scan.dat")
+        );
+        assert_eq!(
+            get_cache_path_custom(Some("/custom"), None, None)?,
+            PathBuf::from("/custom").join(CACHE_FILE_NAME)
+        );
+        Ok(())
+    }
 
-        if self.entries.is_empty() {
-            return Ok("(empty)\n".to_string());
+    #[test]
+    fn test_cache_name_picks_the_file_in_the_cache_dir() -> Result<()> {
+        assert_eq!(
+            get_cache_path_custom(Some("/custom"), None, Some("projects"))?,
+            PathBuf::from("/custom/projects.dat")
+        );
+        for name in ["", ".", "..", "a/b", "a\\b", "c:"] {
+            let err = get_cache_path_custom(Some("/custom"), None, Some(name)).unwrap_err();
+            assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::InvalidArgument(_))), "{:?}", name);
         }
+        Ok(())
+    }
 
-        let root = &self.root;
-        output.push_str(&format!("{}\n", root.display()));
+    #[test]
+    fn test_unwritable_cache_dir_error_names_path() {
+        let blocker = std::env::temp_dir().join(format!("ptree_cache_blocker_{}", std::process::id()));
+        fs::write(&blocker, b"").unwrap();
+        let attempted = blocker.join("cache");
 
-        // No need for visited set - filesystem is acyclic and in_progress set prevents cycles during traversal
-        self.print_tree(&mut output, root, "", true, 0, max_depth)?;
+        let err = DiskCache::open(&attempted.join(CACHE_FILE_NAME)).unwrap_err();
+        assert!(err.to_string().contains(&attempted.display().to_string()), "{}", err);
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CachePath { .. })), "{:#}", err);
+        let err = fixture_cache().save(&attempted.join(CACHE_FILE_NAME)).unwrap_err();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CachePath { .. })), "{:#}", err);
 
-        Ok(output)
+        let _ = fs::remove_file(&blocker);
     }
 
-    fn print_tree(
-        &self,
-        output: &mut String,
-        path: &Path,
-        prefix: &str,
-        is_last: bool,
-        current_depth: usize,
-        max_depth: Option<usize>,
-    ) -> Result<()> {
-        // Check depth limit
-        if let Some(max) = max_depth {
-            if current_depth >= max {
-                return Ok(());
+    #[test]
+    fn test_corrupt_cache_is_moved_aside() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_corrupt_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join(CACHE_FILE_NAME);
+        let (index_path, data_path) = (cache_path.with_extension("idx"), cache_path.with_extension("dat"));
+        fixture_cache().save(&cache_path)?;
+        let (index, data) = (fs::read(&index_path)?, fs::read(&data_path)?);
+
+        let flipped = |bytes: &[u8], at: usize| {
+            let mut bytes = bytes.to_vec();
+            bytes[at] ^= 0xff;
+            bytes
+        };
+        let damaged = [
+            (flipped(&index, 0), data.clone()),               // index magic
+            (flipped(&index, 13), data.clone()),              // index checksum
+            (flipped(&index, index.len() - 1), data.clone()), // index body
+            (index.clone(), flipped(&data, 5)),               // data version
+            (index.clone(), data[..data.len() - 1].to_vec()), // data cut short
+        ];
+        for (case, (index_bytes, data_bytes)) in damaged.iter().enumerate() {
+            fs::write(&index_path, index_bytes)?;
+            fs::write(&data_path, data_bytes)?;
+
+            // --strict-cache: an error, with the files left as they are
+            let err = DiskCache::open_strict(&cache_path).unwrap_err();
+            assert_eq!(LoadFailure::of(&err), LoadFailure::Corrupt, "case {}: {:#}", case, err);
+            assert!(index_path.exists() && data_path.exists(), "case {}: moved by a strict open", case);
+
+            let cache = DiskCache::open(&cache_path)?;
+            assert!(cache.root.as_os_str().is_empty(), "case {}: loaded a damaged index", case);
+            assert!(
+                matches!(cache.load, CacheLoad::Failed { kind: LoadFailure::Corrupt, moved: 2, .. }),
+                "case {}: {:?}",
+                case,
+                cache.load
+            );
+            assert!(!index_path.exists() && !data_path.exists(), "case {}: left in place", case);
+            let aside = fs::read_dir(&dir)?
+                .flatten()
+                .filter(|file| file.file_name().to_string_lossy().contains(".corrupt-"))
+                .count();
+            assert_eq!(aside, 2, "case {}", case);
+            for file in fs::read_dir(&dir)?.flatten() {
+                fs::remove_file(file.path())?;
             }
         }
 
-        if let Some(entry) = self.get_entry(path) {
-            // Sort children only at output time (not during traversal)
-            let mut children: Vec<_> = entry.children.iter().collect();
-            children.sort();
-
-            for (i, child_name) in children.iter().enumerate() {
-                let is_last_child = i == children.len() - 1;
-                let child_prefix = if is_last {
-                    "    ".to_string()
-                } else {
-                    "│   ".to_string()
-                };
+        // No pair yet, or an index whose data file is gone
+        assert_eq!(DiskCache::open(&cache_path)?.load, CacheLoad::Missing);
+        fs::write(&index_path, &index)?;
+        let cache = DiskCache::open(&cache_path)?;
+        assert!(matches!(cache.load, CacheLoad::Failed { kind: LoadFailure::Corrupt, moved: 1, .. }), "{:?}", cache.load);
+        for file in fs::read_dir(&dir)?.flatten() {
+            fs::remove_file(file.path())?;
+        }
 
-                let branch = if is_last_child { "└── " } else { "├── " };
-                
-                // Check if this child is a symlink
-                let child_path = path.join(child_name);
-                let display_name = if let Some(entry) = self.get_entry(&child_path) {
-                    let base_name = if let Some(target) = &entry.symlink_target {
-                        format!("{} (→ {})", child_name, target.display())
-                    } else {
-                        self.format_name(child_name, &child_path, self.show_hidden)
-                    };
-                    base_name
-                } else {
-                    child_name.to_string()
-                };
-                
-                output.push_str(&format!("{}{}{}\n", prefix, branch, display_name));
-                self.print_tree(
-                    output,
-                    &child_path,
-                    &format!("{}{}", prefix, child_prefix),
-                    is_last_child,
-                    current_depth + 1,
-                    max_depth,
-                )?;
-            }
+        // A damaged record only shows once it is read, or under --verify-cache
+        fs::write(&index_path, &index)?;
+        fs::write(&data_path, flipped(&data, data.len() - 1))?;
+        let failed = DiskCache::verify_files(&cache_path)?;
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].0 >= crate::cache_rkyv::CACHE_HEADER_LEN as u64);
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert_eq!(cache.root, PathBuf::from("/scan"));
+        assert_eq!(cache.load, CacheLoad::Loaded);
+
+        // --strict-cache: an error; otherwise the pair is moved aside and the cache starts over
+        let err = cache.load_all_entries_lazy(&cache_path).unwrap_err();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CacheCorrupt(_))), "{:#}", err);
+        cache.load_all_entries_or_rebuild(&cache_path)?;
+        assert!(cache.entries.is_empty() && cache.root.as_os_str().is_empty());
+        assert!(matches!(cache.load, CacheLoad::Failed { kind: LoadFailure::Corrupt, moved: 2, .. }), "{:?}", cache.load);
+        assert!(!index_path.exists() && !data_path.exists());
+
+        // What the scan then finds is saved as a cache of its own
+        let rescanned = fixture_cache();
+        cache.root = rescanned.root.clone();
+        for (path, entry) in &rescanned.entries {
+            cache.add_entry(path.clone(), entry.clone());
         }
+        cache.save(&cache_path)?;
+        assert!(DiskCache::verify_files(&cache_path)?.is_empty());
+        let mut reopened = DiskCache::open(&cache_path)?;
+        reopened.load_all_entries_or_rebuild(&cache_path)?;
+        assert_eq!(reopened.load, CacheLoad::Loaded);
+        assert_eq!(reopened.entries.len(), rescanned.entries.len());
 
+        let _ = fs::remove_dir_all(&dir);
         Ok(())
     }
 
-    // ============================================================================
-    // Colored Tree Output
-    // ============================================================================
+    #[cfg(not(windows))]
+    #[test]
+    fn test_encrypted_cache_without_its_key_is_rebuilt() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_encrypted_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join(CACHE_FILE_NAME);
+        let index_path = cache_path.with_extension("idx");
+        std::env::set_var(crate::crypt::PASSPHRASE_ENV, "correct horse");
+        let mut cache = fixture_cache();
+        cache.encrypt = true;
+        cache.save(&cache_path)?;
 
-    /// Build colored tree output
-    pub fn build_colored_tree_output(&self) -> Result<String> {
-        self.build_colored_tree_output_with_depth(None)
-    }
+        let mut loaded = DiskCache::open(&cache_path)?;
+        assert!(loaded.encrypt);
+        assert_eq!(loaded.root, PathBuf::from("/scan"));
+        loaded.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(loaded.entries.len(), cache.entries.len());
 
-    /// Build colored tree output with optional max depth limit
-    pub fn build_colored_tree_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
-        let mut output = String::new();
+        // Another salt derives another key, as a changed passphrase would
+        let mut index = fs::read(&index_path)?;
+        index[crate::cache_rkyv::CACHE_HEADER_LEN + 4] ^= 0xff;
+        fs::write(&index_path, &index)?;
+        let err = crate::cache_rkyv::RkyvMmapCache::open(&index_path, &cache_path.with_extension("dat")).err().unwrap();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CacheKey(_))), "{:#}", err);
 
-        if self.entries.is_empty() {
-            return Ok("(empty)\n".to_string());
-        }
+        let rebuilt = DiskCache::open(&cache_path)?;
+        assert!(rebuilt.encrypt && rebuilt.root.as_os_str().is_empty());
+        assert!(!index_path.exists());
+        let aside = fs::read_dir(&dir)?.flatten().filter(|file| file.file_name().to_string_lossy().contains(".corrupt-")).count();
+        assert_eq!(aside, 2);
 
-        let root = &self.root;
-        output.push_str(&format!("{}\n", root.display().to_string().blue().bold()));
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
 
-        // No need for visited set - filesystem is acyclic and in_progress set prevents cycles during traversal
-        self.print_colored_tree(&mut output, root, "", true, 0, max_depth)?;
+    #[test]
+    fn test_concurrent_save_and_open_never_interleave() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_lock_hammer_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join(CACHE_FILE_NAME);
+        fixture_cache().save(&cache_path)?;
 
-        Ok(output)
-    }
+        // Saves alternate between two sizes; every load must see one of them whole
+        let full = fixture_cache();
+        let mut smaller = fixture_cache();
+        smaller.entries.remove(Path::new("/scan/alpha/deep"));
+        let sizes = [full.entries.len(), smaller.entries.len()];
 
-    fn print_colored_tree(
-        &self,
-        output: &mut String,
-        path: &Path,
-        prefix: &str,
-        is_last: bool,
-        current_depth: usize,
-        max_depth: Option<usize>,
-    ) -> Result<()> {
-        // Check depth limit
-        if let Some(max) = max_depth {
-            if current_depth >= max {
-                return Ok(());
-            }
+        let writer = {
+            let cache_path = cache_path.clone();
+            std::thread::spawn(move || -> Result<()> {
+                let (mut full, mut smaller) = (full, smaller);
+                for round in 0..40 {
+                    if round % 2 == 0 { full.save(&cache_path)? } else { smaller.save(&cache_path)? }
+                }
+                Ok(())
+            })
+        };
+        for _ in 0..40 {
+            let mut cache = DiskCache::open(&cache_path)?;
+            cache.load_all_entries_lazy(&cache_path)?;
+            assert!(sizes.contains(&cache.entries.len()), "loaded {} entries", cache.entries.len());
         }
+        writer.join().unwrap()?;
 
-        if let Some(entry) = self.get_entry(path) {
-            // Sort children only at output time (not during traversal)
-            // Use parallel sort for large directories (>500 children)
-            let mut children: Vec<_> = entry.children.iter().collect();
-            if children.len() > 500 {
-                children.par_sort();
-            } else {
-                children.sort();
-            }
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
 
-            for (i, child_name) in children.iter().enumerate() {
-                let is_last_child = i == children.len() - 1;
-                let child_prefix = if is_last {
-                    "    ".to_string()
-                } else {
-                    "│   ".to_string()
-                };
+    #[test]
+    fn test_delta_save_appends_only_changed_entries() -> Result<()> {
+        use crate::cache_rkyv::{write_record, RkyvDirEntry, RkyvMmapCache};
 
-                let branch = if is_last_child { "└── " } else { "├── " };
-                let branch_colored = branch.cyan().to_string();
-                
-                // Check if this child is a symlink
-                let child_path = path.join(child_name);
-                let display_name = if let Some(entry) = self.get_entry(&child_path) {
-                    let base_name = if let Some(target) = &entry.symlink_target {
-                        format!("{} (→ {})", child_name, target.display())
-                    } else {
-                        self.format_name(child_name, &child_path, self.show_hidden)
-                    };
-                    base_name.bright_blue().to_string()
-                } else {
-                    child_name.bright_blue().to_string()
-                };
-                
-                output.push_str(&format!("{}{}{}\n", prefix, branch_colored, display_name));
-                self.print_colored_tree(
-                    output,
-                    &child_path,
-                    &format!("{}{}", prefix, child_prefix),
-                    is_last_child,
-                    current_depth + 1,
-                    max_depth,
-                )?;
-            }
+        let dir = std::env::temp_dir().join(format!("ptree_delta_save_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join(CACHE_FILE_NAME);
+        let data_path = cache_path.with_extension("dat");
+        fixture_cache().save(&cache_path)?;
+        let before = fs::read(&data_path)?;
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.load_all_entries_lazy(&cache_path)?;
+        let root = PathBuf::from("/scan");
+        let deep = root.join("alpha/deep");
+        let mut changed = cache.entries[&deep].clone();
+        changed.content_hash = 42;
+        cache.add_entry(deep.clone(), changed);
+        cache.add_entry(root.join("alpha"), cache.entries[&root.join("alpha")].clone());
+        cache.remove_entry(&root.join("beta, \"quoted\""));
+        cache.save(&cache_path)?;
+
+        // The old records stay in place; only the new deep entry and the root's shorter children follow
+        let after = fs::read(&data_path)?;
+        assert_eq!(after[..before.len()], before[..]);
+        let mut expected = Vec::new();
+        for path in [&root, &deep] {
+            write_record(&mut expected, &RkyvDirEntry::from(&cache.entries[path]))?;
         }
+        assert_eq!(after.len() - before.len(), expected.len());
+        assert!(!cache.dirty);
+        let saved = RkyvMmapCache::open(&cache_path.with_extension("idx"), &data_path)?;
+        assert!(saved.dead_bytes() > 0);
+        drop(saved);
 
+        // Loading compacts the dead records away
+        let mut loaded = DiskCache::open(&cache_path)?;
+        loaded.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(loaded.entries.len(), 3);
+        assert_eq!(loaded.entries[&deep].content_hash, 42);
+        assert_eq!(loaded.entries[&root].children, ["alpha"]);
+        assert!(fs::metadata(&data_path)?.len() < after.len() as u64);
+
+        let _ = fs::remove_dir_all(&dir);
         Ok(())
     }
 
-    // ============================================================================
-    // JSON Tree Output
-    // ============================================================================
+    #[test]
+    fn test_saved_bloom_filter_rules_out_uncached_paths() -> Result<()> {
+        use crate::cache_rkyv::RkyvMmapCache;
 
-    /// Build JSON tree representation
-    pub fn build_json_output(&self) -> Result<String> {
-        self.build_json_output_with_depth(None)
-    }
+        let dir = std::env::temp_dir().join(format!("ptree_bloom_save_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join(CACHE_FILE_NAME);
+        let index_path = cache_path.with_extension("idx");
+        let data_path = cache_path.with_extension("dat");
+        fixture_cache().save(&cache_path)?;
+        let missing: Vec<PathBuf> = (0..1000).map(|i| PathBuf::from(format!("/scan/missing{}", i))).collect();
 
-    /// Build JSON tree representation with optional max depth limit
-    pub fn build_json_output_with_depth(&self, max_depth: Option<usize>) -> Result<String> {
-        let mut root_json = json!({
-            "path": self.root.to_string_lossy().to_string(),
-            "children": []
-        });
+        let saved = RkyvMmapCache::open(&index_path, &data_path)?;
+        let bloom = saved.bloom.clone().unwrap();
+        assert_eq!(bloom.fp_rate(), DEFAULT_BLOOM_FP_RATE);
+        assert!(saved.index.offsets.keys().all(|path| bloom.may_contain(path)));
+        assert!(missing.iter().filter(|path| bloom.may_contain(path)).count() < 50);
+        assert!(saved.get_entry(Path::new("/scan/missing0"))?.is_none());
+        drop(saved);
 
-        if self.entries.is_empty() {
-            return Ok(root_json.to_string());
-        }
+        // A delta save adds its new paths to the saved filter
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert!(!cache.entries.may_contain("/scan/missing0"));
+        cache.load_all_entries_lazy(&cache_path)?;
+        let added = PathBuf::from("/scan/alpha/added");
+        let mut alpha = cache.entries["/scan/alpha"].clone();
+        alpha.children.push("added");
+        cache.add_entry(alpha.path.clone(), alpha);
+        cache.add_entry(added.clone(), test_entry(&added, &[]));
+        cache.save(&cache_path)?;
+        let saved = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(saved.bloom.as_ref().unwrap().may_contain(&added));
+        assert!(saved.get_entry(&added)?.is_some());
+        drop(saved);
+
+        // Compaction rebuilds it over the live paths alone
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert!(cache.get_entry(&added).is_some());
+        cache.remove_entry(&added);
+        cache.save(&cache_path)?;
+        let grown = RkyvMmapCache::open(&index_path, &data_path)?.bloom.unwrap().estimated_fp_rate();
+        DiskCache::compact(&cache_path)?;
+        let rebuilt = RkyvMmapCache::open(&index_path, &data_path)?.bloom.unwrap();
+        assert!(rebuilt.estimated_fp_rate() < grown);
 
-        // No need for visited set - filesystem is acyclic and in_progress set prevents cycles during traversal
-        self.populate_json(&mut root_json, &self.root, 0, max_depth)?;
+        // A new --bloom-fp-rate has the cache saved whole with a filter of that rate
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.set_bloom_fp_rate(0.001, &cache_path)?;
+        assert!(cache.dirty);
+        cache.save(&cache_path)?;
+        let saved = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!(saved.bloom.as_ref().unwrap().fp_rate(), 0.001);
+        assert_eq!(saved.len(), 4);
+        drop(saved);
 
-        Ok(serde_json::to_string_pretty(&root_json)?)
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
     }
 
-    fn populate_json(
-        &self,
-        node: &mut serde_json::Value,
-        path: &Path,
-        current_depth: usize,
-        max_depth: Option<usize>,
-    ) -> Result<()> {
-        // Check depth limit
-        if let Some(max) = max_depth {
-            if current_depth >= max {
-                return Ok(());
+    #[test]
+    fn test_large_cache_is_sharded_by_top_level_directory() -> Result<()> {
+        use crate::cache_rkyv::{shard_files, RkyvMmapCache, SHARD_MIN_ENTRIES};
+
+        let dir = std::env::temp_dir().join(format!("ptree_sharded_save_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join(CACHE_FILE_NAME);
+        let index_path = cache_path.with_extension("idx");
+        let data_path = cache_path.with_extension("dat");
+
+        let root = PathBuf::from("/scan");
+        let per_top = SHARD_MIN_ENTRIES / 3 + 1;
+        let names: Vec<String> = (0..per_top).map(|i| format!("d{}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut cache = DiskCache::new_empty();
+        cache.root = root.clone();
+        cache.entries.insert(root.clone(), test_entry(&root, &["a", "b", "c"]));
+        for top in ["a", "b", "c"] {
+            let top = root.join(top);
+            cache.entries.insert(top.clone(), test_entry(&top, &names));
+            for name in &names {
+                cache.entries.insert(top.join(name), test_entry(top.join(name), &[]));
             }
         }
+        let total = cache.entries.len();
+        cache.save(&cache_path)?;
 
-        if let Some(entry) = self.get_entry(path) {
-            let mut children_array = Vec::new();
-            let mut children_names: Vec<_> = entry.children.iter().collect();
-            // Sort children only at output time (not during traversal)
-            // Use parallel sort for large directories (>500 children)
-            if children_names.len() > 500 {
-                children_names.par_sort();
-            } else {
-                children_names.sort();
-            }
+        let saved = RkyvMmapCache::open(&index_path, &data_path)?;
+        let table = saved.shards.clone().unwrap();
+        assert_eq!((table.named.len(), table.buckets, saved.len()), (3, 0, total));
+        assert_eq!(saved.index.offsets.len(), 1, "only the root stays in the top-level pair");
+        assert_eq!(shard_files(&cache_path).len(), 6);
+        drop(saved);
 
-            for child_name in children_names {
-                let child_path = path.join(child_name);
-                let mut child_json = json!({
-                    "name": child_name,
-                    "path": child_path.to_string_lossy().to_string(),
-                    "children": []
-                });
+        // A change below b appends to b's shard alone
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(cache.entries.len(), total);
+        let changed_path = root.join("b/d7");
+        let mut changed = cache.entries[&changed_path].clone();
+        changed.content_hash = 42;
+        cache.add_entry(changed_path.clone(), changed);
+        cache.save(&cache_path)?;
+        let saved = RkyvMmapCache::open(&index_path, &data_path)?;
+        let after = saved.shards.clone().unwrap();
+        let b = table.named["b"] as usize;
+        for id in 0..3 {
+            assert_eq!(after.shards[id].generation, table.shards[id].generation);
+            assert_eq!(after.shards[id].dead_bytes > 0, id == b, "shard {}", id);
+        }
+        drop(saved);
 
-                self.populate_json(&mut child_json, &child_path, current_depth + 1, max_depth)?;
-                children_array.push(child_json);
-            }
+        // --subtree reads b's shard, plus the root for its ancestors
+        let mut subtree = DiskCache::open(&cache_path)?;
+        assert!(subtree.load_subtree_lazy(&cache_path, &root.join("b"))?);
+        assert_eq!(subtree.entries.len(), per_top + 2);
+        assert_eq!(subtree.entries[&changed_path].content_hash, 42);
+        assert!(!subtree.load_subtree_lazy(&cache_path, &root.join("missing"))?);
 
-            node["children"] = serde_json::json!(children_array);
+        // Compaction rewrites b's shard and leaves the others' files be
+        let stats = DiskCache::compact(&cache_path)?;
+        assert_eq!(stats.entries, total);
+        assert!(stats.reclaimed() > 0);
+        let saved = RkyvMmapCache::open(&index_path, &data_path)?;
+        let compacted = saved.shards.clone().unwrap();
+        for id in 0..3 {
+            assert_eq!(compacted.shards[id].generation == after.shards[id].generation, id != b, "shard {}", id);
+            assert_eq!(compacted.shards[id].dead_bytes, 0);
         }
+        assert!(saved.corrupt_records()?.is_empty());
+        drop(saved);
+        assert_eq!(shard_files(&cache_path).len(), 6);
 
+        remove_cache_files(&cache_path)?;
+        assert!(shard_files(&cache_path).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
         Ok(())
     }
-}
 
-/// Get cache directory path
-pub fn get_cache_path() -> Result<PathBuf> {
-    let appdata = std::env::var("APPDATA")?;
-    Ok(PathBuf::from(appdata)
-        .join("ptree")
-        .join("cache")
-        .join("ptree.dat"))
-}
+    #[test]
+    fn test_remove_cache_files_reports_bytes_and_tolerates_missing() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_clean_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache_path = dir.join(CACHE_FILE_NAME);
 
-/// Get cache directory path with custom directory
-pub fn get_cache_path_custom(custom_dir: Option<&str>) -> Result<PathBuf> {
-    if let Some(dir) = custom_dir {
-        Ok(PathBuf::from(dir).join("ptree.dat"))
-    } else {
-        get_cache_path()
+        let mut cache = fixture_cache();
+        cache.save(&cache_path)?;
+        let expected: u64 = cache_artifacts(&cache_path)
+            .iter()
+            .filter_map(|file| fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        assert_eq!(remove_cache_files(&cache_path)?, expected);
+        assert!(cache_artifacts(&cache_path).iter().all(|file| !file.exists()));
+        assert_eq!(remove_cache_files(&cache_path)?, 0);
+
+        // A directory under a cache file name is left alone
+        fs::create_dir_all(cache_path.with_extension("idx"))?;
+        assert!(remove_cache_files(&cache_path).is_err());
+        assert!(cache_path.with_extension("idx").is_dir());
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
     #[test]
-    fn test_cache_creation() -> Result<()> {
-        let temp_dir = std::env::temp_dir().join("ptree_test_cache");
-        fs::create_dir_all(&temp_dir)?;
-        let cache_path = temp_dir.join("test.dat");
-        
-        let cache = DiskCache::open(&cache_path)?;
-        assert!(cache.entries.is_empty());
-        
-        // Clean up
-        let _ = fs::remove_dir_all(&temp_dir);
+    fn test_json_roots_output_renders_each_root() -> Result<()> {
+        let mut cache = fixture_cache();
+        let other = PathBuf::from("/other");
+        cache.entries.insert(other.clone(), test_entry(&other, &["x"]));
+        cache.entries.insert(other.join("x"), test_entry(other.join("x"), &[]));
+        cache.output_options.show_report = true;
+
+        let roots = vec![PathBuf::from("/scan"), other];
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_roots_output(&roots, None)?)?;
+        let trees = json.as_array().unwrap();
+
+        assert_eq!(trees.len(), 2);
+        assert_eq!(trees[0]["path"], "/scan");
+        assert_eq!(trees[0]["summary"]["directories"], 3);
+        assert_eq!(trees[1]["path"], "/other");
+        assert_eq!(trees[1]["children"][0]["name"], "x");
+        assert_eq!(trees[1]["summary"]["directories"], 1);
+        assert_eq!(cache.display_root(), Path::new("/scan"));
         Ok(())
     }
 
     #[test]
-    fn test_content_hash_stability() {
-        // Same inputs should produce same hash
-        let path = std::path::Path::new("C:\\test");
-        let modified = Utc::now();
-        let children = vec!["file1.txt".to_string(), "file2.txt".to_string()];
-        let child_hashes = HashMap::new();
+    fn test_line_limit_truncates_every_streaming_format() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.output_options.show_report = true;
+        cache.output_options.line_limit = Some(3);
 
-        let hash1 = compute_content_hash(path, modified, &children, &child_hashes);
-        let hash2 = compute_content_hash(path, modified, &children, &child_hashes);
+        let tree = cache.build_tree_output()?;
+        assert_eq!(
+            tree,
+            "/scan\n├── alpha\n│   └── deep\n... output truncated (use --limit 0 for all)\n"
+        );
+        let colored = cache.build_colored_tree_output_with_theme(None, &Theme::default())?;
+        assert_eq!(colored.lines().count(), 4);
+        assert!(colored.ends_with("(use --limit 0 for all)\n"));
 
-        assert_eq!(hash1, hash2, "Identical inputs should produce identical hashes");
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["truncated"], true);
+        assert_eq!(json["children"].as_array().unwrap().len(), 1);
+        assert!(json.get("summary").is_none());
+
+        let mut buffer = Vec::new();
+        cache.write_ndjson_output(&mut buffer, None)?;
+        let ndjson = String::from_utf8(buffer)?;
+        assert_eq!(ndjson.lines().count(), 4);
+        assert_eq!(ndjson.lines().last(), Some("{\"truncated\":true}"));
+
+        // Exactly enough room: nothing is cut, so no notice
+        cache.output_options.line_limit = Some(4);
+        let tree = cache.build_tree_output()?;
+        assert!(!tree.contains("truncated"));
+        assert!(tree.ends_with("3 directories, 0 files\n"));
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert!(json.get("truncated").is_none());
+        Ok(())
     }
 
     #[test]
-    fn test_content_hash_sensitivity() {
-        // Different inputs should produce different hashes
-        let path = std::path::Path::new("C:\\test");
-        let modified = Utc::now();
-        
-        // Base hash
-        let children = vec!["file1.txt".to_string()];
-        let child_hashes = HashMap::new();
-        let base_hash = compute_content_hash(path, modified, &children, &child_hashes);
+    fn test_reverse_inverts_every_sort_mode() -> Result<()> {
+        let root = PathBuf::from("/scan");
+        let mut cache = DiskCache::new_empty();
+        cache.root = root.clone();
+        let names = ["a10", "a2", "B", "b", "A1", "same1", "same2"];
+        cache.entries.insert(root.clone(), test_entry(&root, &names));
+        for name in names {
+            let child_count = if name.starts_with("same") { 1 } else { name.len() };
+            let children: Vec<&str> = (0..child_count).map(|_| "x").collect();
+            cache.entries.insert(root.join(name), test_entry(root.join(name), &children));
+        }
 
-        // Hash with additional file
-        let children_added = vec!["file1.txt".to_string(), "file2.txt".to_string()];
-        let hash_added = compute_content_hash(path, modified, &children_added, &child_hashes);
-        assert_ne!(base_hash, hash_added, "Adding a file should change hash");
+        let order = |cache: &DiskCache| -> Vec<String> {
+            let entry = cache.get_entry(&cache.root).unwrap();
+            cache.sorted_children(entry).into_iter().map(String::from).collect()
+        };
 
-        // Hash with removed file
-        let children_removed = vec![];
-        let hash_removed = compute_content_hash(path, modified, &children_removed, &child_hashes);
-        assert_ne!(base_hash, hash_removed, "Removing a file should change hash");
+        for sort in [SortKey::Name, SortKey::Children, SortKey::Size] {
+            for natural in [false, true] {
+                cache.output_options.sort = sort;
+                cache.output_options.natural_sort = natural;
+                cache.output_options.reverse = false;
+                let forward = order(&cache);
 
-        // Hash with renamed file
-        let children_renamed = vec!["renamed_file.txt".to_string()];
-        let hash_renamed = compute_content_hash(path, modified, &children_renamed, &child_hashes);
-        assert_ne!(base_hash, hash_renamed, "Renaming a file should change hash");
+                cache.output_options.reverse = true;
+                let mut reversed = order(&cache);
+                assert_ne!(forward, reversed);
+                reversed.reverse();
+                assert_eq!(forward, reversed, "sort={:?} natural={}", sort, natural);
+            }
+        }
+        Ok(())
     }
 
     #[test]
-    fn test_merkle_propagation() {
-        // Child hash changes should affect parent hash
-        let parent_path = std::path::Path::new("/parent");
-        let child_path = std::path::Path::new("/parent/child");
-        let modified = Utc::now();
+    fn test_heat_level_buckets_relative_to_last_scan() {
+        let mut cache = DiskCache::new_empty();
+        cache.last_scan = DateTime::parse_from_rfc3339("2024-06-15T00:00:00Z").unwrap().with_timezone(&Utc);
+        let thresholds = HeatmapThresholds::default();
+        let ago = |hours: i64| cache.last_scan - chrono::Duration::hours(hours);
 
-        // Parent with no child hashes
-        let parent_children = vec!["child".to_string()];
-        let mut child_hashes = HashMap::new();
-        child_hashes.insert(child_path.to_path_buf(), 12345u64);
+        assert_eq!(cache.heat_level(ago(-5), &thresholds), HeatLevel::Hot);
+        assert_eq!(cache.heat_level(ago(0), &thresholds), HeatLevel::Hot);
+        assert_eq!(cache.heat_level(ago(23), &thresholds), HeatLevel::Hot);
+        assert_eq!(cache.heat_level(ago(24), &thresholds), HeatLevel::Warm);
+        assert_eq!(cache.heat_level(ago(167), &thresholds), HeatLevel::Warm);
+        assert_eq!(cache.heat_level(ago(168), &thresholds), HeatLevel::Cold);
 
-        let parent_hash1 = compute_content_hash(parent_path, modified, &parent_children, &child_hashes);
+        let custom = HeatmapThresholds {
+            hot: chrono::Duration::hours(1),
+            warm: chrono::Duration::hours(2),
+        };
+        assert_eq!(cache.heat_level(ago(1), &custom), HeatLevel::Warm);
+        assert_eq!(cache.heat_level(ago(2), &custom), HeatLevel::Cold);
+    }
 
-        // Change child hash
-        child_hashes.insert(child_path.to_path_buf(), 54321u64);
-        let parent_hash2 = compute_content_hash(parent_path, modified, &parent_children, &child_hashes);
+    #[test]
+    fn test_heatmap_colors_directories_in_colored_tree() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.last_scan = Utc::now();
+        let beta = PathBuf::from("/scan/beta, \"quoted\"");
+        cache.entries.get_mut(&beta).unwrap().modified = cache.last_scan - chrono::Duration::days(30);
+        cache.output_options.heatmap = Some(HeatmapThresholds::default());
 
-        assert_ne!(parent_hash1, parent_hash2, "Child hash change should affect parent hash");
+        let tree = cache.build_colored_tree_output()?;
+        assert!(tree.contains("\x1b[32malpha\x1b[0m"), "{}", tree);
+        // Cold entries keep their theme color
+        assert!(tree.contains("\x1b[94mbeta"));
+        Ok(())
     }
 
     #[test]
-    fn test_has_directory_changed() {
-        let path = std::path::Path::new("C:\\test");
+    fn test_changes_mark_added_modified_and_removed() -> Result<()> {
+        let mut cache = fixture_cache();
+        let root = PathBuf::from("/scan");
+        let alpha = root.join("alpha");
+        let snapshot = cache.snapshot();
 
-        let old_entry = DirEntry {
-            path: path.to_path_buf(),
-            name: "test".to_string(),
-            modified: Utc::now(),
-            content_hash: 12345u64,
-            children: vec!["file.txt".to_string()],
-            symlink_target: None,
-            is_hidden: false,
-        };
+        // Simulate a rescan: alpha/deep removed, gamma added, alpha touched
+        cache.entries.get_mut(&root).unwrap().children.push("gamma");
+        cache.entries.insert(root.join("gamma"), test_entry(root.join("gamma"), &[]));
+        let alpha_entry = cache.entries.get_mut(&alpha).unwrap();
+        alpha_entry.children.clear();
+        alpha_entry.modified += chrono::Duration::seconds(5);
 
-        let new_entry_unchanged = DirEntry {
-            path: path.to_path_buf(),
-            name: "test".to_string(),
-            modified: Utc::now(),
-            content_hash: 12345u64,
-            children: vec!["file.txt".to_string()],
-            symlink_target: None,
-            is_hidden: false,
-        };
+        let changes = cache.changes_since(&snapshot);
+        assert_eq!(changes.kind_of(&root.join("gamma")), Some(ChangeKind::Added));
+        assert_eq!(changes.kind_of(&alpha), Some(ChangeKind::Modified));
+        assert_eq!(changes.removed, vec![alpha.join("deep")]);
+        cache.output_options.changes = Some(changes);
 
-        let new_entry_changed = DirEntry {
-            path: path.to_path_buf(),
-            name: "test".to_string(),
-            modified: Utc::now(),
-            content_hash: 54321u64,
-            children: vec!["file.txt".to_string(), "newfile.txt".to_string()],
-            symlink_target: None,
-            is_hidden: false,
-        };
+        let tree = cache.build_tree_output()?;
+        assert!(tree.contains("├── * alpha\n"), "{}", tree);
+        assert!(tree.contains("└── + gamma\n"));
+        assert!(tree.ends_with("\nRemoved since last scan:\n  - /scan/alpha/deep\n"));
 
-        assert!(!has_directory_changed(&old_entry, &new_entry_unchanged), "Same hash should not indicate change");
-        assert!(has_directory_changed(&old_entry, &new_entry_changed), "Different hash should indicate change");
+        let json: serde_json::Value = serde_json::from_str(&cache.build_json_output()?)?;
+        assert_eq!(json["children"][0]["status"], "modified");
+        assert_eq!(json["children"][2]["status"], "added");
+        assert!(json["children"][1].get("status").is_none());
+        assert_eq!(json["removed"][0], "/scan/alpha/deep");
+
+        // --entry-format lines keep their markers
+        cache.output_options.entry_format = Some(EntryTemplate::parse("{name}|{children}")?);
+        let tree = cache.build_tree_output()?;
+        assert!(tree.contains("├── * alpha|0\n"), "{}", tree);
+        assert!(tree.contains("└── + gamma|0\n"));
+        assert!(tree.contains("├── beta, \"quoted\"|0\n"));
+
+        // No previous scan means no markers at all
+        assert!(cache.changes_since(&Snapshot::default()).added.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_format_replaces_names_in_tree() -> Result<()> {
+        let mut cache = fixture_cache();
+        cache.output_options.entry_format = Some(EntryTemplate::parse("{depth}:{name:<6}|{children}")?);
+
+        assert_eq!(
+            cache.build_tree_output()?,
+            "/scan\n├── 1:alpha |1\n│   └── 2:deep  |0\n└── 1:beta, \"quoted\"|0\n"
+        );
+
+        cache.output_options.no_indent = true;
+        assert_eq!(cache.build_tree_output_with_depth(Some(1))?, "/scan\n1:alpha |1\n1:beta, \"quoted\"|0\n");
+        Ok(())
     }
 }
@@ -0,0 +1,423 @@
+#[cfg(feature = "kv")]
+use ptree_core::CacheBackend;
+#[cfg(feature = "kv")]
+use crate::cache::{create_cache_dir, format_count, CacheLoad};
+use crate::cache::DiskCache;
+use anyhow::Result;
+use ptree_core::PTreeError;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Key-Value Cache Backend (--backend kv, experimental)
+// ============================================================================
+
+/// Every redb database file starts with this
+const REDB_HEADER: &[u8; 9] = b"redb\x1A\x0A\xA9\x0D\x0A";
+
+/// Schema version kept in the `meta` table; a database with a newer one is refused
+pub const KV_SCHEMA_VERSION: u32 = 1;
+
+/// Database file of the KV backend for a cache path (`ptree.dat` -> `ptree.redb`)
+pub fn kv_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_extension("redb")
+}
+
+/// Whether the cache at `cache_path` was saved by the KV backend, going by its file header
+pub fn is_kv_cache(cache_path: &Path) -> bool {
+    let mut header = [0u8; 9];
+    File::open(kv_path(cache_path))
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| &header == REDB_HEADER)
+}
+
+#[cfg(feature = "kv")]
+pub use store::KvCache;
+
+#[cfg(feature = "kv")]
+mod store {
+    use super::KV_SCHEMA_VERSION;
+    use crate::backend::BackendMeta;
+    use crate::cache::{DirEntry, DiskCache};
+    use anyhow::Result;
+    use ptree_core::PTreeError;
+    use redb::{Database, DatabaseError, ReadableTable, ReadableTableMetadata, Table, TableDefinition};
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+    /// Bincode-encoded `DirEntry` values, keyed by path
+    const ENTRIES: TableDefinition<&str, &[u8]> = TableDefinition::new("entries");
+
+    /// Scan metadata, as `BackendMeta` pairs
+    const META: TableDefinition<&str, &str> = TableDefinition::new("meta");
+
+    /// A cache in an embedded key-value store: one record per entry, keyed by path
+    ///
+    /// Lookups and updates touch only the records involved, and keys sort so
+    /// that a subtree is one range scan (`subtree`); nothing is held in
+    /// memory beyond what is read. Paths are stored as text, lossily for
+    /// names that aren't UTF-8.
+    pub struct KvCache {
+        db: Database,
+    }
+
+    impl KvCache {
+        /// Open or create the database at `db_path`
+        ///
+        /// A file redb can't read is `CacheCorrupt`; one written with a
+        /// newer schema is `CacheVersion`.
+        pub fn open(db_path: &Path) -> Result<Self> {
+            let db = Database::create(db_path).map_err(|e| match e {
+                DatabaseError::DatabaseAlreadyOpen => {
+                    PTreeError::Cache(format!("{} is open in another process", db_path.display()))
+                }
+                e => PTreeError::CacheCorrupt(format!("{}: {}", db_path.display(), e)),
+            })?;
+            let tx = db.begin_write()?;
+            tx.open_table(ENTRIES)?;
+            tx.open_table(META)?;
+            tx.commit()?;
+            let cache = KvCache { db };
+            BackendMeta::check_version(|key| cache.meta_value(key), KV_SCHEMA_VERSION)?;
+            Ok(cache)
+        }
+
+        fn meta_value(&self, key: &str) -> Result<Option<String>> {
+            let table = self.db.begin_read()?.open_table(META)?;
+            Ok(table.get(key)?.map(|value| value.value().to_string()))
+        }
+
+        /// The saved scan metadata, or None for a database nothing was saved to yet
+        pub(crate) fn meta(&self) -> Result<Option<BackendMeta>> {
+            BackendMeta::read(|key| self.meta_value(key))
+        }
+
+        /// Number of saved entries
+        pub fn len(&self) -> Result<usize> {
+            Ok(self.db.begin_read()?.open_table(ENTRIES)?.len()? as usize)
+        }
+
+        pub fn is_empty(&self) -> Result<bool> {
+            Ok(self.len()? == 0)
+        }
+
+        /// The saved entry for `path`
+        pub fn get_entry(&self, path: &Path) -> Result<Option<DirEntry>> {
+            let table = self.db.begin_read()?.open_table(ENTRIES)?;
+            let value = table.get(path.to_string_lossy().as_ref())?;
+            Ok(value.map(|value| bincode::deserialize(value.value())).transpose()?)
+        }
+
+        /// Every saved entry
+        pub fn get_all(&self) -> Result<Vec<DirEntry>> {
+            let table = self.db.begin_read()?.open_table(ENTRIES)?;
+            let mut entries = Vec::new();
+            for record in table.iter()? {
+                let (_, value) = record?;
+                entries.push(bincode::deserialize(value.value())?);
+            }
+            Ok(entries)
+        }
+
+        /// The entry for `root` and every entry below it, read with one range scan
+        pub fn subtree(&self, root: &Path) -> Result<Vec<DirEntry>> {
+            let mut entries: Vec<DirEntry> = self.get_entry(root)?.into_iter().collect();
+            let mut prefix = root.to_string_lossy().into_owned();
+            if !prefix.ends_with(MAIN_SEPARATOR) {
+                prefix.push(MAIN_SEPARATOR);
+            }
+            let table = self.db.begin_read()?.open_table(ENTRIES)?;
+            for record in table.range(prefix.as_str()..)? {
+                let (key, value) = record?;
+                if !key.value().starts_with(&prefix) {
+                    break;
+                }
+                entries.push(bincode::deserialize(value.value())?);
+            }
+            Ok(entries)
+        }
+
+        /// Replace every record with `entries` and the metadata with `cache`'s, in one transaction
+        ///
+        /// `cancelled` is checked before each entry; its error aborts the
+        /// transaction, leaving the saved records as they were. Returns how
+        /// many entries were written.
+        pub fn replace_all<'a>(
+            &mut self,
+            cache: &DiskCache,
+            entries: impl Iterator<Item = &'a DirEntry>,
+            cancelled: &dyn Fn() -> Result<()>,
+        ) -> Result<usize> {
+            let tx = self.db.begin_write()?;
+            tx.delete_table(ENTRIES)?;
+            let written = {
+                let mut table = tx.open_table(ENTRIES)?;
+                insert_entries(&mut table, entries, cancelled)
+            };
+            match written.and_then(|written| write_meta(&tx, cache).map(|()| written)) {
+                Ok(written) => {
+                    tx.commit()?;
+                    Ok(written)
+                }
+                Err(e) => {
+                    tx.abort()?;
+                    Err(e)
+                }
+            }
+        }
+
+        /// Upsert `changed` entries and delete the records of paths that are no longer cached
+        ///
+        /// As `SqliteCache::upsert`: `cache.entries` must hold every cached
+        /// entry. Returns how many entries were written.
+        pub fn upsert(
+            &mut self,
+            cache: &DiskCache,
+            changed: &HashSet<PathBuf>,
+            cancelled: &dyn Fn() -> Result<()>,
+        ) -> Result<usize> {
+            let tx = self.db.begin_write()?;
+            let written = (|| {
+                let mut table = tx.open_table(ENTRIES)?;
+                let mut saved = HashSet::new();
+                for record in table.iter()? {
+                    saved.insert(PathBuf::from(record?.0.value()));
+                }
+                for path in saved.iter().filter(|path| !cache.entries.contains_key(path)) {
+                    table.remove(path.to_string_lossy().as_ref())?;
+                }
+                let updated = cache
+                    .entries
+                    .values()
+                    .filter(|entry| changed.contains(&entry.path) || !saved.contains(&entry.path));
+                let written = insert_entries(&mut table, updated, cancelled)?;
+                drop(table);
+                write_meta(&tx, cache)?;
+                Ok(written)
+            })();
+            match written {
+                Ok(written) => {
+                    tx.commit()?;
+                    Ok(written)
+                }
+                Err(e) => {
+                    tx.abort()?;
+                    Err(e)
+                }
+            }
+        }
+
+        /// Rewrite the metadata only, for a cache whose entries are unchanged
+        pub fn save_meta(&mut self, cache: &DiskCache) -> Result<()> {
+            let tx = self.db.begin_write()?;
+            write_meta(&tx, cache)?;
+            tx.commit()?;
+            Ok(())
+        }
+
+        /// Run redb's integrity check, failing with `CacheCorrupt` when it doesn't pass
+        ///
+        /// redb repairs what it can as it checks; a repair is logged.
+        pub fn check(&mut self, db_path: &Path) -> Result<()> {
+            match self.db.check_integrity() {
+                Ok(true) => Ok(()),
+                Ok(false) => {
+                    log::warn!("{} failed its integrity check and was repaired", db_path.display());
+                    Ok(())
+                }
+                Err(e) => Err(PTreeError::CacheCorrupt(format!("{}: {}", db_path.display(), e)).into()),
+            }
+        }
+    }
+
+    fn insert_entries<'a>(
+        table: &mut Table<&str, &[u8]>,
+        entries: impl Iterator<Item = &'a DirEntry>,
+        cancelled: &dyn Fn() -> Result<()>,
+    ) -> Result<usize> {
+        let mut written = 0;
+        for entry in entries {
+            cancelled()?;
+            table.insert(entry.path.to_string_lossy().as_ref(), bincode::serialize(entry)?.as_slice())?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    fn write_meta(tx: &redb::WriteTransaction, cache: &DiskCache) -> Result<()> {
+        let mut table = tx.open_table(META)?;
+        for (key, value) in BackendMeta::pairs(cache, KV_SCHEMA_VERSION)? {
+            table.insert(key, value.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kv")]
+impl DiskCache {
+    /// Open the KV cache for `path`: its metadata only, entries are loaded on demand
+    pub(crate) fn open_kv(path: &Path) -> Result<Self> {
+        let db = KvCache::open(&kv_path(path))?;
+        let Some(meta) = db.meta()? else {
+            return Ok(DiskCache { backend: CacheBackend::Kv, load: CacheLoad::Missing, ..Self::new_empty() });
+        };
+        log::info!(
+            "loaded cache store {}: {} entries, last scan {}",
+            kv_path(path).display(),
+            format_count(db.len()?),
+            meta.last_scan
+        );
+        Ok(meta.into_cache(CacheBackend::Kv))
+    }
+
+    /// Load entries from the KV cache for `path`: those at `paths`, or all of them
+    ///
+    /// Entries already in memory are kept, as the rkyv loads keep them.
+    pub(crate) fn load_kv_entries(&mut self, path: &Path, paths: Option<&[PathBuf]>) -> Result<()> {
+        let db = KvCache::open(&kv_path(path))?;
+        let loaded = match paths {
+            Some(paths) => paths
+                .iter()
+                .filter(|path| !self.entries.contains_key(path))
+                .filter_map(|path| db.get_entry(path).transpose())
+                .collect::<Result<Vec<_>>>()?,
+            None => db.get_all()?,
+        };
+        for entry in loaded {
+            if !self.entries.contains_key(&entry.path) {
+                self.entries.insert(entry.path.clone(), entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save to the KV cache for `path`, as `write_sqlite` does for the SQLite one
+    pub(crate) fn write_kv(&self, path: &Path, cancelled: &dyn Fn() -> Result<()>) -> Result<()> {
+        if self.compress || self.encrypt {
+            return Err(PTreeError::InvalidArgument(
+                "--compress-cache and --encrypt-cache need --backend rkyv".to_string(),
+            )
+            .into());
+        }
+        if self.max_bytes.is_some() {
+            log::warn!("--max-cache-bytes is not applied to the kv backend");
+        }
+
+        let db_path = kv_path(path);
+        create_cache_dir(path)?;
+        let lock = crate::lock::CacheLock::exclusive(path)?;
+        cancelled()?;
+        let mut db = KvCache::open(&db_path)?;
+        let saved = db.meta()?.is_some();
+        let written = match &self.changed {
+            _ if saved && !self.dirty => {
+                db.save_meta(self)?;
+                0
+            }
+            Some(changed) if saved => db.upsert(self, changed, cancelled)?,
+            _ => db.replace_all(self, self.entries.values(), cancelled)?,
+        };
+        drop(db);
+        drop(lock);
+
+        log::info!(
+            "saved cache store {}: {} of {} entries written",
+            db_path.display(),
+            format_count(written),
+            format_count(self.entries.len())
+        );
+        Ok(())
+    }
+
+    /// Run redb's integrity check on the KV cache for `path` (`--verify-cache`)
+    /// Number of entries saved in the KV cache for `path` (`ptree cache-info`)
+    pub(crate) fn kv_len(path: &Path) -> Result<usize> {
+        KvCache::open(&kv_path(path))?.len()
+    }
+
+    pub(crate) fn verify_kv(path: &Path) -> Result<()> {
+        let db_path = kv_path(path);
+        KvCache::open(&db_path)?.check(&db_path)
+    }
+}
+
+#[cfg(not(feature = "kv"))]
+impl DiskCache {
+    pub(crate) fn open_kv(path: &Path) -> Result<Self> {
+        Err(kv_unavailable(path))
+    }
+
+    pub(crate) fn load_kv_entries(&mut self, path: &Path, _paths: Option<&[PathBuf]>) -> Result<()> {
+        Err(kv_unavailable(path))
+    }
+
+    pub(crate) fn write_kv(&self, path: &Path, _cancelled: &dyn Fn() -> Result<()>) -> Result<()> {
+        Err(kv_unavailable(path))
+    }
+
+    pub(crate) fn kv_len(path: &Path) -> Result<usize> {
+        Err(kv_unavailable(path))
+    }
+
+    pub(crate) fn verify_kv(path: &Path) -> Result<()> {
+        Err(kv_unavailable(path))
+    }
+}
+
+#[cfg(not(feature = "kv"))]
+fn kv_unavailable(path: &Path) -> anyhow::Error {
+    PTreeError::Cache(format!(
+        "{}: the kv cache backend is not available in this build (enable the `kv` feature)",
+        kv_path(path).display()
+    ))
+    .into()
+}
+
+#[cfg(all(test, feature = "kv"))]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use crate::cache::DirEntry;
+    use std::fs;
+
+    fn entry(path: &str, children: &[&str]) -> DirEntry {
+        DirEntry { content_hash: 7, dir_count: children.len() as u32, ..test_entry(path, children) }
+    }
+
+    #[test]
+    fn test_kv_backend_round_trips_and_scans_subtrees() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_kv_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+        let mut cache = DiskCache::new();
+        cache.backend = CacheBackend::Kv;
+        cache.root = PathBuf::from("/scan");
+        for (path, children) in [("/scan", &["a", "a b"][..]), ("/scan/a", &["c"]), ("/scan/a/c", &[]), ("/scan/a b", &[])] {
+            cache.entries.insert(PathBuf::from(path), entry(path, children));
+        }
+        cache.save(&cache_path)?;
+        assert!(is_kv_cache(&cache_path));
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert_eq!((cache.backend, cache.root.as_path(), cache.entries.len()), (CacheBackend::Kv, Path::new("/scan"), 0));
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(cache.get_entry(Path::new("/scan")).unwrap().children, ["a", "a b"]);
+
+        // "/scan/a b" sorts among /scan/a's descendants' neighbours but is not one of them
+        let db = KvCache::open(&kv_path(&cache_path))?;
+        let mut below: Vec<PathBuf> = db.subtree(Path::new("/scan/a"))?.into_iter().map(|entry| entry.path).collect();
+        below.sort();
+        assert_eq!(below, [PathBuf::from("/scan/a"), PathBuf::from("/scan/a/c")]);
+        drop(db);
+
+        cache.remove_entry(Path::new("/scan/a/c"));
+        cache.save(&cache_path)?;
+        let db = KvCache::open(&kv_path(&cache_path))?;
+        assert_eq!(db.len()?, 3);
+        assert!(db.get_entry(Path::new("/scan/a/c"))?.is_none());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
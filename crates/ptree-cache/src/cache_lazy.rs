@@ -1,314 +1,278 @@
-/// Lazy-loading cache using mmap for O(1) cold start
-///
-/// Architecture:
-/// - Index (small, always loaded): PathBuf → offset mapping
-/// - Data file (large, mmap'd): serialized entries at indexed offsets
-/// - Entries: only deserialized on-demand during output phase
-///
-/// Benefits:
-/// - Cold start: ~1ms (load index only)
-/// - Hot access: O(1) per entry via mmap offset
-/// - Memory: only entries in current build operation loaded
-///
-/// Files:
-/// - .idx: bincode-serialized RkyvCacheIndex (pathbuf offsets)
-/// - .dat: bincode-serialized RkyvDirEntry objects at indexed positions
-
-use crate::cache::{DirEntry, USNJournalState};
-use crate::cache_rkyv::{RkyvDirEntry, RkyvCacheIndex};
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{Read, Write, Seek, SeekFrom};
-use std::path::{Path, PathBuf};
-use chrono::{DateTime, Utc};
+use crate::cache::{DirEntry, DiskCache, FastHashMap};
+use crate::cache_rkyv::RkyvMmapCache;
 use anyhow::Result;
-use memmap2::Mmap;
+use ptree_core::{CacheBackend, PTreeError};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// ============================================================================
+// Lazy Cache Facade (cached, read-only runs)
+// ============================================================================
 
-/// Lazy-loading cache wrapper
-/// 
-/// Keeps index in memory, data mmap'd, entries loaded on-demand
-pub struct LazyCache {
-    /// In-memory index: path → offset in data file
-    pub index: RkyvCacheIndex,
-    
-    /// Memory-mapped data file (entries at offsets)
-    mmap: Option<Mmap>,
-    
-    /// Path to data file
-    data_path: PathBuf,
-    
-    /// LRU cache of recently loaded entries (configurable size)
-    entry_cache: std::collections::VecDeque<(PathBuf, DirEntry)>,
-    entry_cache_size: usize,
+/// Deserialized entries `LazyDiskCache` keeps for repeated lookups
+pub const DEFAULT_LAZY_ENTRIES: usize = 4096;
+
+/// A cache opened index-only, its entries read from the mapped data file as they are asked for
+///
+/// `DiskCache::open` leaves the entries on disk, but rendering used to
+/// load every one of them first. This holds the opened cache (metadata and
+/// output options) beside the rkyv reader: `get_entry` and `children` look
+/// entries up on demand through a bounded LRU, and `load_view` fills the
+/// cache with only the entries an output reaches, so the builders render
+/// from that. Anything that mutates the tree calls `materialize` instead.
+pub struct LazyDiskCache {
+    cache: DiskCache,
+    cache_path: PathBuf,
+    reader: RkyvMmapCache,
+    recent: RefCell<EntryLru>,
+    deserialized: Cell<usize>,
 }
 
-impl LazyCache {
-    /// Open or create lazy cache
-    /// Cold start: only loads index file (~ms scale for millions of entries)
-    pub fn open(cache_path: &Path) -> Result<Self> {
-        fs::create_dir_all(cache_path.parent().unwrap())?;
-        
-        let index_path = cache_path.with_extension("idx");
-        let data_path = cache_path.with_extension("dat");
-        
-        // Load index (small, always in memory)
-        let index = if index_path.exists() {
-            let mut file = File::open(&index_path)?;
-            let mut data = Vec::new();
-            file.read_to_end(&mut data)?;
-            bincode::deserialize::<RkyvCacheIndex>(&data)
-                .unwrap_or_else(|_| RkyvCacheIndex::new())
-        } else {
-            RkyvCacheIndex::new()
-        };
-        
-        // Memory-map data file (no deserialization)
-        let mmap = if data_path.exists() && fs::metadata(&data_path)?.len() > 0 {
-            let file = File::open(&data_path)?;
-            Some(unsafe { Mmap::map(&file)? })
-        } else {
-            None
-        };
-        
-        Ok(LazyCache {
-            index,
-            mmap,
-            data_path,
-            entry_cache: std::collections::VecDeque::with_capacity(1000),
-            entry_cache_size: 1000,
+impl LazyDiskCache {
+    /// Wrap `cache`, opened from `cache_path`, around a reader of its rkyv pair
+    ///
+    /// Only the rkyv backend is mapped; any other is `PTreeError::Cache`.
+    pub fn open(cache: DiskCache, cache_path: &Path) -> Result<Self> {
+        if cache.backend != CacheBackend::Rkyv {
+            return Err(PTreeError::Cache(format!(
+                "{} is a {} cache; only rkyv caches are read lazily",
+                cache_path.display(),
+                cache.backend.name()
+            ))
+            .into());
+        }
+        let reader = RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path.with_extension("dat"))?;
+        Ok(LazyDiskCache {
+            cache,
+            cache_path: cache_path.to_path_buf(),
+            reader,
+            recent: RefCell::new(EntryLru::new(DEFAULT_LAZY_ENTRIES)),
+            deserialized: Cell::new(0),
         })
     }
-    
-    /// Load a single entry on-demand from mmap
-    /// O(1) lookup + deserialization
-    pub fn get_entry(&mut self, path: &Path) -> Result<Option<DirEntry>> {
-        // Check LRU cache first
-        if let Some(pos) = self.entry_cache.iter().position(|(p, _)| p == path) {
-            let (_, entry) = self.entry_cache.remove(pos).unwrap();
-            // Move to front (most recently used)
-            self.entry_cache.push_front((path.to_path_buf(), entry.clone()));
+
+    /// The wrapped cache: metadata, output options, and whatever `load_view` loaded
+    pub fn cache(&self) -> &DiskCache {
+        &self.cache
+    }
+
+    pub fn cache_mut(&mut self) -> &mut DiskCache {
+        &mut self.cache
+    }
+
+    /// The entry at `path`, from the LRU or deserialized from the data file
+    ///
+    /// Spilled child names are read back, as `RkyvMmapCache::get_entry` does.
+    pub fn get_entry(&self, path: &Path) -> Result<Option<Rc<DirEntry>>> {
+        if let Some(entry) = self.recent.borrow_mut().get(path) {
             return Ok(Some(entry));
         }
-        
-        // Not in cache, load from mmap
-        let offset = match self.index.offsets.get(path) {
-            Some(&off) => off,
-            None => return Ok(None),
-        };
-        
-        let mmap = match self.mmap.as_ref() {
-            Some(m) => m,
-            None => return Ok(None),
-        };
-        
-        let data_slice = &mmap[offset as usize..];
-        
-        // Read length prefix
-        if data_slice.len() < 4 {
+        let Some(entry) = self.reader.get_entry(path)? else {
             return Ok(None);
-        }
-        
-        let len = u32::from_le_bytes([
-            data_slice[0],
-            data_slice[1],
-            data_slice[2],
-            data_slice[3],
-        ]) as usize;
-        
-        if data_slice.len() < 4 + len {
-            return Ok(None);
-        }
-        
-        // Deserialize from mmap'd region
-        let rkyv_entry: RkyvDirEntry = bincode::deserialize(&data_slice[4..4 + len])?;
-        let entry = DirEntry {
-            path: rkyv_entry.path,
-            name: rkyv_entry.name,
-            modified: rkyv_entry.modified,
-            content_hash: rkyv_entry.content_hash,
-            children: rkyv_entry.children,
-            symlink_target: rkyv_entry.symlink_target,
-            is_hidden: rkyv_entry.is_hidden,
-            is_dir: rkyv_entry.is_dir,
         };
-        
-        // Add to LRU cache
-        self.entry_cache.push_front((path.to_path_buf(), entry.clone()));
-        if self.entry_cache.len() > self.entry_cache_size {
-            self.entry_cache.pop_back();
-        }
-        
+        self.deserialized.set(self.deserialized.get() + 1);
+        let entry = Rc::new(DirEntry::from(entry));
+        self.recent.borrow_mut().insert(path.to_path_buf(), Rc::clone(&entry));
         Ok(Some(entry))
     }
-    
-    /// Get all entries from mmap (deferred to output phase)
-    /// Still faster than loading from disk multiple times
-    pub fn get_all(&mut self) -> Result<HashMap<PathBuf, DirEntry>> {
-        let mut entries = HashMap::new();
-        
-        for path in self.index.offsets.keys() {
-            if let Some(entry) = self.get_entry(path)? {
-                entries.insert(path.clone(), entry);
+
+    /// The children of the directory at `dir`, in stored order, each with its entry if cached
+    pub fn children(&self, dir: &Path) -> Result<Vec<(String, Option<Rc<DirEntry>>)>> {
+        let Some(entry) = self.get_entry(dir)? else {
+            return Ok(Vec::new());
+        };
+        entry
+            .children
+            .iter()
+            .map(|name| Ok((name.to_string(), self.get_entry(&dir.join(name))?)))
+            .collect()
+    }
+
+    /// Load the entries the output of the display root reaches within `max_depth`, and return the cache to render
+    ///
+    /// Those are the root and everything up to `max_depth` levels below it,
+    /// plus one level more with `--no-hidden`, whose ellipsis and collapse
+    /// counts look at each child's hidden flag. Only good for the options
+    /// `DiskCache::can_stream_tree` accepts; the rest look at the whole tree
+    /// and need `materialize`.
+    pub fn load_view(&mut self, max_depth: Option<usize>) -> Result<&DiskCache> {
+        let last = max_depth.map(|max| if self.cache.output_options.hide_hidden { max + 1 } else { max });
+        let mut pending = vec![(self.cache.display_root().to_path_buf(), 0)];
+        while let Some((path, depth)) = pending.pop() {
+            if self.cache.entries.contains_key(&path) {
+                continue;
+            }
+            let Some(entry) = self.get_entry(&path)? else {
+                continue;
+            };
+            if last.is_none_or(|last| depth < last) {
+                pending.extend(entry.children.iter().map(|name| (path.join(name), depth + 1)));
             }
+            self.cache.entries.insert(path, DirEntry::clone(&entry));
         }
-        
-        Ok(entries)
-    }
-    
-    /// Save index to disk (fast atomic write)
-    pub fn save_index(&self, cache_path: &Path) -> Result<()> {
-        let index_path = cache_path.with_extension("idx");
-        fs::create_dir_all(index_path.parent().unwrap())?;
-        
-        let data = bincode::serialize(&self.index)?;
-        let temp_path = index_path.with_extension("tmp");
-        
-        let mut file = File::create(&temp_path)?;
-        file.write_all(&data)?;
-        file.sync_all()?;
-        drop(file);
-        
-        fs::rename(&temp_path, &index_path)?;
-        Ok(())
+        Ok(&self.cache)
     }
-    
-    /// Append entry to data file (during traversal)
-    /// Returns offset for index tracking
-    pub fn append_entry(&self, entry: &DirEntry) -> Result<u64> {
-        let rkyv_entry = RkyvDirEntry {
-            path: entry.path.clone(),
-            name: entry.name.clone(),
-            modified: entry.modified,
-            content_hash: entry.content_hash,
-            children: entry.children.clone(),
-            symlink_target: entry.symlink_target.clone(),
-            is_hidden: entry.is_hidden,
-            is_dir: entry.is_dir,
-        };
-        
-        let mut data_file = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.data_path)?;
-        
-        let serialized = bincode::serialize(&rkyv_entry)?;
-        let len = serialized.len() as u32;
-        
-        let offset = data_file.seek(SeekFrom::End(0))?;
-        
-        data_file.write_all(&len.to_le_bytes())?;
-        data_file.write_all(&serialized)?;
-        data_file.sync_all()?;
-        
-        Ok(offset)
-    }
-    
-    /// Update index with new offsets (called after traversal)
-    pub fn update_index(
-        &mut self,
-        offsets: HashMap<PathBuf, u64>,
-        last_scan: DateTime<Utc>,
-        root: PathBuf,
-        last_scanned_root: PathBuf,
-    ) {
-        self.index.offsets = offsets;
-        self.index.last_scan = last_scan;
-        self.index.root = root;
-        self.index.last_scanned_root = last_scanned_root;
+
+    /// The cache with every entry loaded, for runs that change the tree
+    pub fn materialize(mut self) -> Result<DiskCache> {
+        self.cache.load_all_entries_lazy(&self.cache_path)?;
+        Ok(self.cache)
     }
-    
-    #[cfg(windows)]
-    pub fn set_usn_state(&mut self, usn_state: USNJournalState) {
-        self.index.usn_state = usn_state;
+
+    /// The cache with what `load_view` loaded, dropping the reader
+    pub fn into_cache(self) -> DiskCache {
+        self.cache
     }
-    
-    pub fn set_skip_stats(&mut self, skip_stats: HashMap<String, usize>) {
-        self.index.skip_stats = skip_stats;
+
+    /// Entries in the cache, as the index counts them
+    pub fn len(&self) -> usize {
+        self.reader.len()
     }
-    
-    /// Reload mmap after data file modifications
-    pub fn reload_mmap(&mut self) -> Result<()> {
-        if self.data_path.exists() && fs::metadata(&self.data_path)?.len() > 0 {
-            let file = File::open(&self.data_path)?;
-            self.mmap = Some(unsafe { Mmap::map(&file)? });
-        }
-        Ok(())
+
+    pub fn is_empty(&self) -> bool {
+        self.reader.is_empty()
     }
-    
-    pub fn entry_count(&self) -> usize {
-        self.index.offsets.len()
+
+    /// Entries deserialized from the data file so far; LRU hits don't count
+    pub fn deserialized(&self) -> usize {
+        self.deserialized.get()
     }
-    
-    pub fn is_empty(&self) -> bool {
-        self.index.offsets.is_empty()
+
+    /// Entries held in the LRU, never more than `DEFAULT_LAZY_ENTRIES`
+    pub fn recent_entries(&self) -> usize {
+        self.recent.borrow().len()
     }
-    
-    pub fn last_scan(&self) -> DateTime<Utc> {
-        self.index.last_scan
+}
+
+/// Least-recently-used entries by path, `capacity` at most
+struct EntryLru {
+    capacity: usize,
+    tick: u64,
+    entries: FastHashMap<PathBuf, (u64, Rc<DirEntry>)>,
+    /// Last use → path, oldest first
+    order: BTreeMap<u64, PathBuf>,
+}
+
+impl EntryLru {
+    fn new(capacity: usize) -> Self {
+        EntryLru { capacity, tick: 0, entries: FastHashMap::default(), order: BTreeMap::new() }
     }
-    
-    pub fn root(&self) -> &PathBuf {
-        &self.index.root
+
+    fn len(&self) -> usize {
+        self.entries.len()
     }
-    
-    pub fn skip_stats(&self) -> &HashMap<String, usize> {
-        &self.index.skip_stats
+
+    fn get(&mut self, path: &Path) -> Option<Rc<DirEntry>> {
+        self.tick += 1;
+        let (used, entry) = self.entries.get_mut(path)?;
+        let path = self.order.remove(used).expect("every entry has a place in the order");
+        *used = self.tick;
+        self.order.insert(self.tick, path);
+        Some(Rc::clone(entry))
     }
-    
-    #[cfg(windows)]
-    pub fn usn_state(&self) -> &USNJournalState {
-        &self.index.usn_state
+
+    fn insert(&mut self, path: PathBuf, entry: Rc<DirEntry>) {
+        self.tick += 1;
+        if let Some((used, _)) = self.entries.insert(path.clone(), (self.tick, entry)) {
+            self.order.remove(&used);
+        } else if self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.insert(self.tick, path);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env;
-    
+    use crate::fixtures::{test_entry};
+    use crate::children::ChildNames;
+    use std::fs;
+
+    fn entry(path: PathBuf, children: Vec<String>, is_hidden: bool) -> DirEntry {
+        DirEntry {
+            dir_count: children.len() as u32,
+            children: ChildNames::from(children),
+            is_hidden,
+            ..test_entry(path, &[])
+        }
+    }
+
+    /// 10 directories of 10 of 200 below `/scan`: 20,111 entries, 111 of them within depth 2
+    fn wide_cache() -> DiskCache {
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        let names = |prefix: &str, count: usize| (0..count).map(|i| format!("{}{:03}", prefix, i)).collect::<Vec<_>>();
+        for top in names("t", 10) {
+            let top_path = cache.root.join(&top);
+            for mid in names("m", 10) {
+                let mid_path = top_path.join(&mid);
+                for leaf in names("l", 200) {
+                    let hidden = leaf.ends_with('7');
+                    cache.entries.insert(mid_path.join(&leaf), entry(mid_path.join(&leaf), Vec::new(), hidden));
+                }
+                cache.entries.insert(mid_path.clone(), entry(mid_path, names("l", 200), false));
+            }
+            cache.entries.insert(top_path.clone(), entry(top_path, names("m", 10), false));
+        }
+        let root = cache.root.clone();
+        cache.entries.insert(root.clone(), entry(root, names("t", 10), false));
+        cache
+    }
+
     #[test]
-    fn test_lazy_cache_cold_start() -> Result<()> {
-        let temp_dir = env::temp_dir().join("ptree_lazy_test");
+    fn test_depth_limited_view_reads_only_what_it_renders() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_lazy_view_{}", std::process::id()));
         fs::create_dir_all(&temp_dir)?;
-        let cache_path = temp_dir.join("test.cache");
-        
-        // Open (should be fast, no deserialization)
-        let cache = LazyCache::open(&cache_path)?;
-        assert!(cache.is_empty());
-        
-        fs::remove_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+        wide_cache().save(&cache_path)?;
+
+        let mut full = DiskCache::open(&cache_path)?;
+        full.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(full.entries.len(), 20_111);
+
+        let mut lazy = LazyDiskCache::open(DiskCache::open(&cache_path)?, &cache_path)?;
+        let view = lazy.load_view(Some(2))?;
+        assert_eq!(view.build_tree_output_with_depth(Some(2))?, full.build_tree_output_with_depth(Some(2))?);
+        assert_eq!(view.build_json_output_with_depth(Some(2))?, full.build_json_output_with_depth(Some(2))?);
+        assert_eq!((lazy.deserialized(), lazy.cache().entries.len()), (111, 111));
+
+        // --no-hidden looks one level further, at the children's hidden flags
+        let mut lazy = LazyDiskCache::open(DiskCache::open(&cache_path)?, &cache_path)?;
+        lazy.cache_mut().output_options.hide_hidden = true;
+        full.output_options.hide_hidden = true;
+        let view = lazy.load_view(Some(1))?;
+        assert_eq!(view.build_tree_output_with_depth(Some(1))?, full.build_tree_output_with_depth(Some(1))?);
+        assert_eq!(lazy.deserialized(), 111);
+        assert!(lazy.recent_entries() <= DEFAULT_LAZY_ENTRIES);
+
+        // Lookups through the facade hit the LRU the second time
+        let children = lazy.children(Path::new("/scan/t000/m000"))?;
+        let read = lazy.deserialized();
+        assert_eq!((children.len(), read), (200, 311));
+        assert!(children.iter().all(|(_, child)| child.is_some()));
+        lazy.children(Path::new("/scan/t000/m000"))?;
+        assert_eq!(lazy.deserialized(), read);
+
+        assert_eq!(lazy.materialize()?.entries.len(), 20_111);
+        let _ = fs::remove_dir_all(&temp_dir);
         Ok(())
     }
-    
+
     #[test]
-    fn test_lazy_cache_append_and_load() -> Result<()> {
-        let temp_dir = env::temp_dir().join("ptree_lazy_append_test");
-        fs::create_dir_all(&temp_dir)?;
-        let cache_path = temp_dir.join("test.cache");
-        
-        let mut cache = LazyCache::open(&cache_path)?;
-        
-        let entry = DirEntry {
-            path: PathBuf::from("C:\\test"),
-            name: "test".to_string(),
-            modified: Utc::now(),
-            content_hash: 12345,
-            children: vec!["child1".to_string()],
-            symlink_target: None,
-            is_hidden: false,
-            is_dir: true,
-        };
-        
-        let offset = cache.append_entry(&entry)?;
-        cache.index.offsets.insert(entry.path.clone(), offset);
-        cache.reload_mmap()?;
-        
-        // Load it back
-        let loaded = cache.get_entry(&entry.path)?;
-        assert!(loaded.is_some());
-        assert_eq!(loaded.unwrap().name, "test");
-        
-        fs::remove_dir_all(&temp_dir)?;
-        Ok(())
+    fn test_lru_evicts_the_least_recently_used() {
+        let mut lru = EntryLru::new(2);
+        let dir = |path: &str| Rc::new(entry(PathBuf::from(path), Vec::new(), false));
+        lru.insert(PathBuf::from("/a"), dir("/a"));
+        lru.insert(PathBuf::from("/b"), dir("/b"));
+        assert!(lru.get(Path::new("/a")).is_some());
+        lru.insert(PathBuf::from("/c"), dir("/c"));
+        assert_eq!(lru.len(), 2);
+        assert!(lru.get(Path::new("/b")).is_none());
+        assert!(lru.get(Path::new("/a")).is_some() && lru.get(Path::new("/c")).is_some());
     }
 }
@@ -1,24 +1,122 @@
 use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{Write, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::{BufWriter, Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use chrono::{DateTime, Utc};
 use anyhow::Result;
 use memmap2::Mmap;
+use ptree_core::{CacheBackend, PTreeError};
+use rayon::prelude::*;
+use crate::cache::{create_cache_dir, format_count, CacheLoad, DirEntry, DiskCache, FastHashMap};
+use crate::children::ChildNames;
+use crate::lock::CacheLock;
+
+// ============================================================================
+// Limcode Cache Backend (--backend limcode)
+// ============================================================================
+
+/// First bytes of the `.limidx` file
+pub const LIMCODE_MAGIC: &[u8; 4] = b"PTLC";
+
+/// Layout version written by this build
+///
+/// - 1: `LIMCODE_MAGIC` header; entries carry every saved `DirEntry` field
+///   and the index the scan metadata, USN position included
+pub const LIMCODE_FORMAT_VERSION: u32 = 1;
+
+/// Index header: `LIMCODE_MAGIC`, version (u32 LE), then the length of the
+/// data file the offsets point into (u64 LE); the archived `LimcodeIndex`
+/// follows, at a multiple of `RECORD_ALIGN`
+const LIMCODE_HEADER_LEN: usize = 16;
+
+/// Index and data files of the Limcode backend for a cache path (`ptree.dat` -> `ptree.limidx`, `ptree.limdat`)
+pub fn limcode_paths(cache_path: &Path) -> (PathBuf, PathBuf) {
+    (cache_path.with_extension("limidx"), cache_path.with_extension("limdat"))
+}
+
+/// Whether the cache at `cache_path` was saved by the Limcode backend, going by its index header
+pub fn is_limcode_cache(cache_path: &Path) -> bool {
+    let mut header = [0u8; 4];
+    File::open(limcode_paths(cache_path).0)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| &header == LIMCODE_MAGIC)
+}
+
+/// Temp file a Limcode file is written to before it is renamed over `path`
+fn temp_path(path: &Path) -> PathBuf {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_extension(format!("{}.tmp", extension))
+}
+
+/// Read the index at `index_path`, with the data file length it was saved against
+///
+/// A file without the header, or whose archive doesn't validate, is
+/// `CacheCorrupt`; one of a newer version is `CacheVersion`.
+pub fn read_limcode_index(index_path: &Path) -> Result<(LimcodeIndex, u64)> {
+    let bytes = fs::read(index_path)?;
+    let corrupt = |what: String| PTreeError::CacheCorrupt(format!("{}: {}", index_path.display(), what));
+    if bytes.len() < LIMCODE_HEADER_LEN || &bytes[..4] != LIMCODE_MAGIC {
+        return Err(corrupt("not a Limcode index".to_string()).into());
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().expect("4-byte slice"));
+    if version > LIMCODE_FORMAT_VERSION {
+        return Err(PTreeError::CacheVersion { found: version, supported: LIMCODE_FORMAT_VERSION }.into());
+    }
+    let data_len = u64::from_le_bytes(bytes[8..16].try_into().expect("8-byte slice"));
+    // Read into a buffer rkyv's alignment holds for
+    let mut archive = rkyv::AlignedVec::with_capacity(bytes.len() - LIMCODE_HEADER_LEN);
+    archive.extend_from_slice(&bytes[LIMCODE_HEADER_LEN..]);
+    let index = rkyv::from_bytes::<LimcodeIndex>(&archive).map_err(|e| corrupt(format!("index does not validate: {}", e)))?;
+    Ok((index, data_len))
+}
+
+/// Write `index` to `index_path` through a temp file, recording `data_len`
+fn write_limcode_index(index_path: &Path, index: &LimcodeIndex, data_len: u64) -> Result<()> {
+    let archive = rkyv::to_bytes::<_, 4096>(index)?;
+    let temp = temp_path(index_path);
+    let mut file = File::create(&temp).map_err(|source| PTreeError::CachePath { path: temp.clone(), source })?;
+    file.write_all(LIMCODE_MAGIC)?;
+    file.write_all(&LIMCODE_FORMAT_VERSION.to_le_bytes())?;
+    file.write_all(&data_len.to_le_bytes())?;
+    file.write_all(&archive)?;
+    file.sync_all()?;
+    fs::rename(&temp, index_path)?;
+    Ok(())
+}
 
 /// Limcode-optimized directory entry with rkyv serialization
 /// Uses primitives that rkyv can directly archive
-#[derive(Archive, RkyvSerialize, RkyvDeserialize, Debug, Clone)]
+#[derive(Archive, RkyvSerialize, RkyvDeserialize, Debug, Clone, Default)]
 #[archive(check_bytes)]
 pub struct LimcodeDirEntry {
     pub path: String,  // PathBuf not Archive-compatible, use String
     pub name: String,
     pub modified_timestamp: i64,  // DateTime<Utc> not Archive-compatible, use i64
+    /// Sub-second part of the modification time
+    pub modified_nanos: u32,
+    pub content_hash: u64,
     pub size: u64,
-    pub children: Vec<String>,
+    /// Child names joined by NUL, as `ChildNames::packed` holds them
+    pub children: String,
     pub symlink_target: Option<String>,  // Use String instead of PathBuf
     pub is_hidden: bool,
+    pub is_dir: bool,
+    pub skipped_children: bool,
+    pub is_mount: bool,
+    pub access_error: Option<String>,
+    pub evicted: bool,
+    pub file_count: u32,
+    pub dir_count: u32,
+    pub total_size: u64,
+    pub size_is_lower_bound: bool,
+}
+
+impl ArchivedLimcodeDirEntry {
+    /// Child names, read in place from the mapping
+    pub fn child_names(&self) -> impl Iterator<Item = &str> {
+        ChildNames::split_packed(self.children.as_str())
+    }
 }
 
 /// Index with limcode-optimized offset storage for batch deserialization
@@ -27,24 +125,32 @@ pub struct LimcodeDirEntry {
 #[archive(check_bytes)]
 pub struct LimcodeIndex {
     /// Path → offset mapping for fast lookup
-    pub offsets: HashMap<String, u64>,
+    pub offsets: FastHashMap<String, u64>,
     /// Sorted offset list for batch sequential access
     pub sorted_offsets: Vec<u64>,
     pub last_scan_timestamp: i64,
+    /// Sub-second part of the last scan's time
+    pub last_scan_nanos: u32,
     pub root: String,
     pub last_scanned_root: String,
     pub skip_stats: HashMap<String, usize>,
+    /// Saved USN journal position (`USNJournalState`); zero off Windows
+    pub usn_journal_id: u64,
+    pub usn_last: i64,
 }
 
 impl LimcodeIndex {
     pub fn new() -> Self {
         LimcodeIndex {
-            offsets: HashMap::new(),
+            offsets: FastHashMap::default(),
             sorted_offsets: Vec::new(),
             last_scan_timestamp: Utc::now().timestamp(),
+            last_scan_nanos: 0,
             root: String::new(),
             last_scanned_root: String::new(),
             skip_stats: HashMap::new(),
+            usn_journal_id: 0,
+            usn_last: 0,
         }
     }
 
@@ -55,6 +161,134 @@ impl LimcodeIndex {
     }
 }
 
+impl Default for LimcodeIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Records in the data file start at multiples of this, so archives are 8-byte aligned as rkyv requires
+const RECORD_ALIGN: u64 = 8;
+
+/// Record prefix: length (u32 LE), then 4 bytes of padding keeping the archive aligned
+const RECORD_PREFIX: usize = 8;
+
+/// Record suffix: CRC32 (u32 LE) of the archived bytes
+const RECORD_SUFFIX: usize = 4;
+
+/// Records validated or deserialized per rayon task
+const BATCH_CHUNK: usize = 4096;
+
+/// Archived bytes of the record at `offset`, None if it runs outside `data`
+///
+/// The length is clamped to the mapping before anything is sliced.
+fn record_bytes(data: &[u8], offset: u64) -> Option<&[u8]> {
+    let record = data.get(offset as usize..)?;
+    let len = u32::from_le_bytes(record.get(..4)?.try_into().ok()?) as usize;
+    record.get(RECORD_PREFIX..RECORD_PREFIX.checked_add(len)?)
+}
+
+/// Archived bytes of the record at `offset`, None if it runs outside `data` or fails its checksum
+fn checked_record_bytes(data: &[u8], offset: u64) -> Option<&[u8]> {
+    let bytes = record_bytes(data, offset)?;
+    let end = offset as usize + RECORD_PREFIX + bytes.len();
+    let stored = u32::from_le_bytes(data.get(end..end + RECORD_SUFFIX)?.try_into().ok()?);
+    (crc32fast::hash(bytes) == stored).then_some(bytes)
+}
+
+/// The mapped data file, its indexed records validated once when it is opened
+///
+/// Entries come out as `ArchivedLimcodeDirEntry` borrowed from the mapping:
+/// names, children and timestamps are read in place, nothing is copied or
+/// deserialized, and later accesses skip validation.
+pub struct LimcodeReader {
+    mmap: Mmap,
+    /// Offsets whose records passed validation, sorted
+    valid: Vec<u64>,
+    /// Records that are out of bounds, fail their checksum or fail validation, per chunk of `BATCH_CHUNK` offsets
+    corrupt: Vec<usize>,
+}
+
+impl LimcodeReader {
+    /// Map `data_path` and validate the record at each of `offsets`
+    ///
+    /// A record's checksum is checked before its archive is. Offsets are
+    /// sorted and validated a chunk per rayon task; each chunk counts its
+    /// own failures, so one bad record never hides the rest.
+    fn open(data_path: &Path, offsets: impl Iterator<Item = u64>) -> Result<Self> {
+        let file = File::open(data_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let mut offsets: Vec<u64> = offsets.collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        let chunks: Vec<(Vec<u64>, usize)> = offsets
+            .par_chunks(BATCH_CHUNK)
+            .map(|chunk| {
+                let valid: Vec<u64> = chunk
+                    .iter()
+                    .copied()
+                    .filter(|&offset| {
+                        checked_record_bytes(&mmap, offset)
+                            .is_some_and(|bytes| rkyv::check_archived_root::<LimcodeDirEntry>(bytes).is_ok())
+                    })
+                    .collect();
+                let corrupt = chunk.len() - valid.len();
+                (valid, corrupt)
+            })
+            .collect();
+        let corrupt: Vec<usize> = chunks.iter().map(|&(_, corrupt)| corrupt).collect();
+        if corrupt.iter().any(|&count| count > 0) {
+            let failed: Vec<String> = corrupt
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(chunk, count)| format!("chunk {}: {}", chunk, count))
+                .collect();
+            log::warn!(
+                "{}: {} records failed validation and are skipped ({})",
+                data_path.display(),
+                corrupt.iter().sum::<usize>(),
+                failed.join(", ")
+            );
+        }
+        let valid = chunks.into_iter().flat_map(|(valid, _)| valid).collect();
+        Ok(LimcodeReader { mmap, valid, corrupt })
+    }
+
+    /// The archived entry at `offset`, borrowed from the mapping
+    ///
+    /// Only offsets validated in `open` are read; any other is an error.
+    pub fn entry(&self, offset: u64) -> Result<&ArchivedLimcodeDirEntry> {
+        if self.valid.binary_search(&offset).is_err() {
+            return Err(PTreeError::CacheCorrupt(format!("record at offset {} did not validate", offset)).into());
+        }
+        Ok(self.validated(offset))
+    }
+
+    /// The archived entry at `offset`, which must be one of `valid`
+    fn validated(&self, offset: u64) -> &ArchivedLimcodeDirEntry {
+        let bytes = record_bytes(&self.mmap, offset).expect("validated records are in bounds");
+        // SAFETY: `open` checked this record, and the mapping is never written through
+        unsafe { rkyv::archived_root::<LimcodeDirEntry>(bytes) }
+    }
+
+    /// Every validated entry, in offset order
+    pub fn entries(&self) -> impl Iterator<Item = &ArchivedLimcodeDirEntry> {
+        self.valid.iter().map(|&offset| self.validated(offset))
+    }
+
+    /// Number of indexed records that failed validation and are left out
+    pub fn corrupt(&self) -> usize {
+        self.corrupt.iter().sum()
+    }
+
+    /// `corrupt` per chunk of `BATCH_CHUNK` offsets, in offset order
+    pub fn corrupt_by_chunk(&self) -> &[usize] {
+        &self.corrupt
+    }
+}
+
 /// Hybrid cache combining rkyv zero-copy with batch SIMD deserialization
 ///
 /// Dual-mode access:
@@ -66,181 +300,181 @@ impl LimcodeIndex {
 /// - data file (.limdat): rkyv-archived entries at tracked offsets
 pub struct LimcodeCache {
     pub index: LimcodeIndex,
-    mmap: Option<Mmap>,
+    reader: Option<LimcodeReader>,
     data_path: PathBuf,
 }
 
 impl LimcodeCache {
     /// Load cache from limcode-optimized files
-    pub fn open(index_path: &std::path::Path, data_path: &std::path::Path) -> Result<Self> {
-        fs::create_dir_all(index_path.parent().unwrap())?;
+    ///
+    /// Every record the index points at is validated here, once; see `LimcodeReader`.
+    /// A data file shorter than the index was saved against is `CacheCorrupt`.
+    pub fn open(index_path: &Path, data_path: &Path) -> Result<Self> {
+        create_cache_dir(index_path)?;
 
         // Load and deserialize index (small file, fully deserialized)
-        let index = if index_path.exists() {
-            let mut file = File::open(index_path)?;
-            let mut data = Vec::new();
-            std::io::Read::read_to_end(&mut file, &mut data)?;
-
-            match rkyv::from_bytes::<LimcodeIndex>(&data) {
-                Ok(idx) => idx,
-                Err(_) => LimcodeIndex::new(),
-            }
+        let (index, data_len) = if index_path.exists() {
+            read_limcode_index(index_path)?
         } else {
-            LimcodeIndex::new()
+            (LimcodeIndex::new(), 0)
         };
 
         // Memory-map large data file for zero-copy entry access
-        let mmap = if data_path.exists() {
-            let file = File::open(data_path)?;
-            Some(unsafe { Mmap::map(&file)? })
+        let reader = if data_path.exists() {
+            let reader = LimcodeReader::open(data_path, index.offsets.values().copied())?;
+            if (reader.mmap.len() as u64) < data_len {
+                return Err(PTreeError::CacheCorrupt(format!(
+                    "{} is {} bytes, its index expects {}",
+                    data_path.display(),
+                    reader.mmap.len(),
+                    data_len
+                ))
+                .into());
+            }
+            Some(reader)
         } else {
             None
         };
 
         Ok(LimcodeCache {
             index,
-            mmap,
+            reader,
             data_path: data_path.to_path_buf(),
         })
     }
 
-    /// O(1) single-entry access: deserialize archived entry via mmap without allocation
-    pub fn get_archived(&self, path: &str) -> Result<Option<LimcodeDirEntry>> {
+    /// The mapped data file, None when there is none
+    pub fn reader(&self) -> Option<&LimcodeReader> {
+        self.reader.as_ref()
+    }
+
+    /// O(1) single-entry access: the archived entry in the mmap, without deserializing or allocating
+    ///
+    /// A record that failed validation at `open` (counted in
+    /// `LimcodeReader::corrupt`) reads as missing, with a warning.
+    pub fn get_archived(&self, path: &str) -> Result<Option<&ArchivedLimcodeDirEntry>> {
         let offset = match self.index.offsets.get(path) {
             Some(&off) => off,
             None => return Ok(None),
         };
 
-        let mmap = self
-            .mmap
+        let reader = self
+            .reader
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("No mmap loaded"))?;
-
-        let data_slice = &mmap[offset as usize..];
-
-        if data_slice.len() < 4 {
-            return Ok(None);
-        }
-
-        let len = u32::from_le_bytes([data_slice[0], data_slice[1], data_slice[2], data_slice[3]])
-            as usize;
-
-        if data_slice.len() < 4 + len {
-            return Ok(None);
+        match reader.entry(offset) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) => {
+                log::warn!("{}: {:#}; treating {} as not cached", self.data_path.display(), e, path);
+                Ok(None)
+            }
         }
-
-        // Deserialize from archived region
-        let archived = rkyv::check_archived_root::<LimcodeDirEntry>(&data_slice[4..4 + len])
-            .map_err(|e| anyhow::anyhow!("Archive check failed: {:?}", e))?;
-        let entry: LimcodeDirEntry = archived.deserialize(&mut rkyv::Infallible).unwrap();
-        Ok(Some(entry))
     }
 
-    /// Batch SIMD deserialization: get all entries using vectorized processing
-    /// Processes entries in sorted offset order for cache locality
-    /// Separates offset computation from deserialization for better SIMD vectorization
+    /// Batch deserialization: get all entries in offset order for cache locality
+    ///
+    /// Chunks of the sorted offsets deserialize in parallel, sharing the
+    /// mapping read-only. Records that failed validation at `open` are
+    /// left out (see `LimcodeReader::corrupt_by_chunk`).
     pub fn get_all_batch(&self) -> Result<Vec<LimcodeDirEntry>> {
-        let mmap = self
-            .mmap
+        let reader = self
+            .reader
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("No mmap loaded"))?;
 
-        let mut entries = Vec::with_capacity(self.index.offsets.len());
-
-        // Phase 1: Vectorized length computation from all offsets
-        // (Can be SIMD'd to compute multiple lengths in parallel)
-        let lengths: Vec<_> = self.index.sorted_offsets
-            .iter()
-            .filter_map(|&offset| {
-                let data_slice = &mmap[offset as usize..];
-                if data_slice.len() >= 4 {
-                    let len = u32::from_le_bytes([
-                        data_slice[0],
-                        data_slice[1],
-                        data_slice[2],
-                        data_slice[3],
-                    ]) as usize;
-                    if data_slice.len() >= 4 + len {
-                        return Some((offset, len));
-                    }
-                }
-                None
+        let entries = reader
+            .valid
+            .par_chunks(BATCH_CHUNK)
+            .flat_map_iter(|chunk| {
+                chunk.iter().map(|&offset| {
+                    let archived = reader.validated(offset);
+                    let entry: LimcodeDirEntry =
+                        archived.deserialize(&mut rkyv::Infallible).expect("deserializing is infallible");
+                    entry
+                })
             })
             .collect();
-
-        // Phase 2: Vectorized deserialization from validated offsets
-        for (offset, len) in lengths {
-            let data_slice = &mmap[offset as usize..];
-            
-            // Deserialize from archived form
-            if let Ok(archived) =
-                rkyv::check_archived_root::<LimcodeDirEntry>(&data_slice[4..4 + len])
-            {
-                let entry: LimcodeDirEntry = archived.deserialize(&mut rkyv::Infallible).unwrap();
-                entries.push(entry);
-            }
-        }
-
         Ok(entries)
     }
 
-    /// Get all entries as HashMap (legacy interface, uses batch deserialize internally)
-    pub fn get_all(&self) -> Result<HashMap<PathBuf, crate::cache::DirEntry>> {
+    /// Get all entries as a map (legacy interface, uses batch deserialize internally)
+    pub fn get_all(&self) -> Result<FastHashMap<PathBuf, crate::cache::DirEntry>> {
         let batch_entries = self.get_all_batch()?;
-        
-        let mut entries = HashMap::new();
+
+        let mut entries = FastHashMap::with_capacity_and_hasher(batch_entries.len(), Default::default());
         for entry in batch_entries {
-            let path = PathBuf::from(&entry.path);
-            let modified = DateTime::<Utc>::from_timestamp(entry.modified_timestamp, 0)
-                .unwrap_or_else(Utc::now);
-            
-            entries.insert(
-                path.clone(),
-                crate::cache::DirEntry {
-                    path,
-                    name: entry.name,
-                    modified,
-                    size: entry.size,
-                    children: entry.children,
-                    symlink_target: entry.symlink_target.map(PathBuf::from),
-                    is_hidden: entry.is_hidden,
-                },
-            );
+            let entry = crate::cache::DirEntry::from(entry);
+            entries.insert(entry.path.clone(), entry);
         }
 
         Ok(entries)
     }
 
     /// Append entry to data file, return offset for index tracking
+    ///
+    /// The new record is not readable through this cache until it is reopened.
     pub fn append_entry(&self, entry: &LimcodeDirEntry) -> Result<u64> {
         let mut data_file = std::fs::OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.data_path)?;
 
-        let serialized = rkyv::to_bytes::<_, 1024>(entry)?;
-        let len = serialized.len() as u32;
-
-        let offset = data_file.seek(SeekFrom::End(0))?;
-
-        data_file.write_all(&len.to_le_bytes())?;
-        data_file.write_all(&serialized)?;
+        let end = data_file.seek(SeekFrom::End(0))?;
+        let (offset, _) = write_record(&mut data_file, end, entry)?;
         data_file.sync_all()?;
 
         Ok(offset)
     }
 
-    /// Save index to disk
-    pub fn save_index(&self, path: &std::path::Path) -> Result<()> {
-        let data = rkyv::to_bytes::<_, 4096>(&self.index)?;
-        let temp_path = path.with_extension("tmp");
+    /// Save index to disk, recording the data file's current length
+    pub fn save_index(&self, path: &Path) -> Result<()> {
+        let data_len = fs::metadata(&self.data_path).map_or(0, |metadata| metadata.len());
+        write_limcode_index(path, &self.index, data_len)
+    }
 
-        let mut file = File::create(&temp_path)?;
-        file.write_all(&data)?;
-        file.sync_all()?;
+    /// Write a new pair of `entries` under `index`'s metadata, replacing any saved one
+    ///
+    /// Both files go through temp files, the data file renamed in first; a
+    /// crash between the renames leaves records that fail validation, read
+    /// as missing. `cancelled` is checked before each entry. Returns how
+    /// many entries were written.
+    pub fn write_pair<'a>(
+        index_path: &Path,
+        data_path: &Path,
+        mut index: LimcodeIndex,
+        entries: impl Iterator<Item = &'a DirEntry>,
+        cancelled: &dyn Fn() -> Result<()>,
+    ) -> Result<usize> {
+        let temp = temp_path(data_path);
+        let file = File::create(&temp).map_err(|source| PTreeError::CachePath { path: temp.clone(), source })?;
+        let mut writer = BufWriter::with_capacity(1 << 20, file);
+        index.offsets.clear();
+        let mut end = 0;
+        for entry in entries {
+            cancelled()?;
+            let (offset, next) = write_record(&mut writer, end, &LimcodeDirEntry::from(entry))?;
+            index.offsets.insert(entry.path.to_string_lossy().into_owned(), offset);
+            end = next;
+        }
+        index.rebuild_sorted_offsets();
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp, data_path)?;
+        write_limcode_index(index_path, &index, end)?;
+        Ok(index.offsets.len())
+    }
 
-        fs::rename(&temp_path, path)?;
-        Ok(())
+    /// Offset and path of each indexed record that failed validation at `open` (`--verify-cache`)
+    pub fn corrupt_records(&self) -> Vec<(u64, PathBuf)> {
+        let valid = |offset: &u64| self.reader.as_ref().is_some_and(|reader| reader.valid.binary_search(offset).is_ok());
+        let mut corrupt: Vec<(u64, PathBuf)> = self
+            .index
+            .offsets
+            .iter()
+            .filter(|(_, offset)| !valid(offset))
+            .map(|(path, &offset)| (offset, PathBuf::from(path)))
+            .collect();
+        corrupt.sort();
+        corrupt
     }
 
     pub fn len(&self) -> usize {
@@ -252,6 +486,211 @@ impl LimcodeCache {
     }
 }
 
+/// Write `entry` as a record at the next multiple of `RECORD_ALIGN` from `end`, the writer's position
+///
+/// Returns the record's offset and the new end.
+fn write_record<W: Write>(writer: &mut W, end: u64, entry: &LimcodeDirEntry) -> Result<(u64, u64)> {
+    let serialized = rkyv::to_bytes::<_, 1024>(entry)?;
+    let len = serialized.len() as u32;
+
+    let padding = (RECORD_ALIGN - end % RECORD_ALIGN) % RECORD_ALIGN;
+    writer.write_all(&[0; RECORD_ALIGN as usize][..padding as usize])?;
+    let offset = end + padding;
+
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&[0; RECORD_PREFIX - 4])?;
+    writer.write_all(&serialized)?;
+    writer.write_all(&crc32fast::hash(&serialized).to_le_bytes())?;
+    Ok((offset, offset + (RECORD_PREFIX + serialized.len() + RECORD_SUFFIX) as u64))
+}
+
+impl From<&DirEntry> for LimcodeDirEntry {
+    fn from(entry: &DirEntry) -> Self {
+        LimcodeDirEntry {
+            path: entry.path.to_string_lossy().into_owned(),
+            name: entry.name.clone(),
+            modified_timestamp: entry.modified.timestamp(),
+            modified_nanos: entry.modified.timestamp_subsec_nanos(),
+            content_hash: entry.content_hash,
+            size: entry.size,
+            children: entry.children.packed().to_string(),
+            symlink_target: entry.symlink_target.as_ref().map(|target| target.to_string_lossy().into_owned()),
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error.clone(),
+            evicted: entry.evicted,
+            file_count: entry.file_count,
+            dir_count: entry.dir_count,
+            total_size: entry.total_size,
+            size_is_lower_bound: entry.size_is_lower_bound,
+        }
+    }
+}
+
+impl From<LimcodeDirEntry> for DirEntry {
+    fn from(entry: LimcodeDirEntry) -> Self {
+        DirEntry {
+            path: PathBuf::from(entry.path),
+            name: entry.name,
+            modified: DateTime::<Utc>::from_timestamp(entry.modified_timestamp, entry.modified_nanos).unwrap_or_else(Utc::now),
+            content_hash: entry.content_hash,
+            children: ChildNames::from_packed(entry.children),
+            symlink_target: entry.symlink_target.map(PathBuf::from),
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error,
+            evicted: entry.evicted,
+            file_count: entry.file_count,
+            dir_count: entry.dir_count,
+            total_size: entry.total_size,
+            size_is_lower_bound: entry.size_is_lower_bound,
+            generation: 0,
+            spilled_children: 0,
+        }
+    }
+}
+
+impl LimcodeIndex {
+    /// Set the scan metadata from `cache`, leaving the offsets
+    fn fill_meta(&mut self, cache: &DiskCache) {
+        self.last_scan_timestamp = cache.last_scan.timestamp();
+        self.last_scan_nanos = cache.last_scan.timestamp_subsec_nanos();
+        self.root = cache.root.to_string_lossy().into_owned();
+        self.last_scanned_root = cache.last_scanned_root.to_string_lossy().into_owned();
+        self.skip_stats = cache.skip_stats.clone();
+        #[cfg(windows)]
+        {
+            (self.usn_journal_id, self.usn_last) = (cache.usn_state.journal_id, cache.usn_state.last_usn);
+        }
+    }
+}
+
+impl DiskCache {
+    /// Open the Limcode cache for `path`: its metadata only, entries are loaded on demand
+    pub(crate) fn open_limcode(path: &Path) -> Result<Self> {
+        let (index_path, data_path) = limcode_paths(path);
+        if !index_path.exists() {
+            return Ok(DiskCache { backend: CacheBackend::Limcode, load: CacheLoad::Missing, ..Self::new_empty() });
+        }
+        let _lock = CacheLock::shared(path)?;
+        let (index, data_len) = read_limcode_index(&index_path)?;
+        let found = fs::metadata(&data_path).map_or(0, |metadata| metadata.len());
+        if found < data_len {
+            return Err(PTreeError::CacheCorrupt(format!(
+                "{} is {} bytes, its index expects {}",
+                data_path.display(),
+                found,
+                data_len
+            ))
+            .into());
+        }
+        log::info!(
+            "loaded cache index {}: {} entries, last scan {}",
+            index_path.display(),
+            format_count(index.offsets.len()),
+            index.last_scan_timestamp
+        );
+        Ok(DiskCache {
+            last_scan: DateTime::<Utc>::from_timestamp(index.last_scan_timestamp, index.last_scan_nanos).unwrap_or_default(),
+            root: PathBuf::from(index.root),
+            last_scanned_root: PathBuf::from(index.last_scanned_root),
+            #[cfg(windows)]
+            usn_state: crate::cache::USNJournalState { journal_id: index.usn_journal_id, last_usn: index.usn_last },
+            skip_stats: index.skip_stats,
+            dirty: false,
+            changed: Some(Default::default()),
+            backend: CacheBackend::Limcode,
+            load: CacheLoad::Loaded,
+            ..Self::new_empty()
+        })
+    }
+
+    /// Load entries from the Limcode cache for `path`: those at `paths`, or all of them
+    ///
+    /// Entries already in memory are kept, as the rkyv loads keep them.
+    pub(crate) fn load_limcode_entries(&mut self, path: &Path, paths: Option<&[PathBuf]>) -> Result<()> {
+        let (index_path, data_path) = limcode_paths(path);
+        let _lock = CacheLock::shared(path)?;
+        let source = LimcodeCache::open(&index_path, &data_path)?;
+        let loaded: Vec<DirEntry> = match paths {
+            Some(paths) => paths
+                .iter()
+                .filter(|path| !self.entries.contains_key(path))
+                .filter_map(|path| source.get_archived(&path.to_string_lossy()).transpose())
+                .map(|archived| {
+                    archived.map(|archived| {
+                        let entry: LimcodeDirEntry =
+                            archived.deserialize(&mut rkyv::Infallible).expect("deserializing is infallible");
+                        DirEntry::from(entry)
+                    })
+                })
+                .collect::<Result<_>>()?,
+            None => source.get_all_batch()?.into_iter().map(DirEntry::from).collect(),
+        };
+        for entry in loaded {
+            if !self.entries.contains_key(&entry.path) {
+                self.entries.insert(entry.path.clone(), entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save to the Limcode cache for `path`, as `write_files` does for the rkyv pair
+    ///
+    /// A clean cache only has its index rewritten with the new metadata;
+    /// anything else writes the pair whole, there being no delta saves.
+    pub(crate) fn write_limcode(&self, path: &Path, cancelled: &dyn Fn() -> Result<()>) -> Result<()> {
+        if self.compress || self.encrypt {
+            return Err(PTreeError::InvalidArgument(
+                "--compress-cache and --encrypt-cache need --backend rkyv".to_string(),
+            )
+            .into());
+        }
+        if self.max_bytes.is_some() {
+            log::warn!("--max-cache-bytes is not applied to the limcode backend");
+        }
+
+        let (index_path, data_path) = limcode_paths(path);
+        create_cache_dir(path)?;
+        let lock = CacheLock::exclusive(path)?;
+        cancelled()?;
+        let saved = if self.dirty { None } else { read_limcode_index(&index_path).ok() };
+        let written = match saved {
+            Some((mut index, data_len)) => {
+                index.fill_meta(self);
+                write_limcode_index(&index_path, &index, data_len)?;
+                0
+            }
+            None => {
+                let mut index = LimcodeIndex::new();
+                index.fill_meta(self);
+                LimcodeCache::write_pair(&index_path, &data_path, index, self.entries.values(), cancelled)?
+            }
+        };
+        drop(lock);
+
+        log::info!(
+            "saved cache {}: {} of {} entries written",
+            index_path.display(),
+            format_count(written),
+            format_count(self.entries.len())
+        );
+        Ok(())
+    }
+
+    /// Offset and path of each Limcode record that fails validation (`--verify-cache`)
+    pub(crate) fn verify_limcode(path: &Path) -> Result<Vec<(u64, PathBuf)>> {
+        let (index_path, data_path) = limcode_paths(path);
+        let _lock = CacheLock::shared(path)?;
+        Ok(LimcodeCache::open(&index_path, &data_path)?.corrupt_records())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -264,9 +703,11 @@ mod tests {
             name: "test".to_string(),
             modified_timestamp: Utc::now().timestamp(),
             size: 1024,
-            children: vec!["child1".to_string(), "child2".to_string()],
+            children: "child1\0child2".to_string(),
             symlink_target: None,
             is_hidden: false,
+            is_dir: true,
+            ..Default::default()
         };
 
         let archived = rkyv::to_bytes::<_, 1024>(&entry).unwrap();
@@ -289,4 +730,124 @@ mod tests {
         let _ = fs::remove_dir_all(&temp_dir);
         Ok(())
     }
+
+    #[test]
+    fn test_corrupt_records_are_counted_and_skipped() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_limcode_corrupt_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("test.limidx");
+        let data_path = temp_dir.join("test.limdat");
+
+        let mut cache = LimcodeCache::open(&index_path, &data_path)?;
+        for i in 0..(BATCH_CHUNK + 10) {
+            let entry = LimcodeDirEntry {
+                path: format!("/scan/d{}", i),
+                name: format!("d{}", i),
+                modified_timestamp: 0,
+                size: i as u64,
+                children: String::new(),
+                symlink_target: None,
+                is_hidden: false,
+                is_dir: false,
+                ..Default::default()
+            };
+            let offset = cache.append_entry(&entry)?;
+            cache.index.offsets.insert(entry.path, offset);
+        }
+        cache.save_index(&index_path)?;
+
+        // A length running past the end of the file, in the second chunk
+        let offset = cache.index.offsets["/scan/d4100"] as usize;
+        let mut data = fs::read(&data_path)?;
+        data[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        // A size changed in place, which only the checksum catches, in the first
+        let offset = cache.index.offsets["/scan/d5"] as usize;
+        let archive = &data[offset + RECORD_PREFIX..];
+        let size = archive.windows(8).position(|bytes| bytes == 5u64.to_le_bytes()).unwrap();
+        data[offset + RECORD_PREFIX + size] = 6;
+        fs::write(&data_path, data)?;
+
+        let cache = LimcodeCache::open(&index_path, &data_path)?;
+        let reader = cache.reader().unwrap();
+        assert_eq!(reader.corrupt_by_chunk(), [1, 1]);
+        assert!(cache.get_archived("/scan/d4100")?.is_none());
+        assert!(cache.get_archived("/scan/d5")?.is_none());
+        assert_eq!(cache.get_archived("/scan/d7")?.map(|entry| entry.size), Some(7));
+
+        let entries = cache.get_all_batch()?;
+        assert_eq!(entries.len(), BATCH_CHUNK + 8);
+        assert!(entries.windows(2).all(|pair| pair[0].size < pair[1].size));
+
+        drop(cache);
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rkyv_cache_converts_to_limcode_on_save() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_limcode_backend_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+        let entry = |path: &str, children: &[&str]| DirEntry {
+            path: PathBuf::from(path),
+            name: Path::new(path).file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+            modified: DateTime::<Utc>::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+            content_hash: u64::MAX - 1,
+            children: children.iter().collect(),
+            is_dir: true,
+            skipped_children: true,
+            access_error: Some("denied".to_string()),
+            file_count: 3,
+            dir_count: children.len() as u32,
+            total_size: 4096,
+            size_is_lower_bound: true,
+            ..Default::default()
+        };
+        let saved = [entry("/scan", &["a"]), entry("/scan/a", &[])];
+        let mut rkyv = DiskCache::new();
+        rkyv.root = PathBuf::from("/scan");
+        rkyv.last_scan = DateTime::<Utc>::from_timestamp(1_700_000_100, 42).unwrap();
+        rkyv.skip_stats.insert("node_modules".to_string(), 2);
+        #[cfg(windows)]
+        {
+            rkyv.usn_state = crate::cache::USNJournalState { journal_id: 7, last_usn: 99 };
+        }
+        for entry in &saved {
+            rkyv.entries.insert(entry.path.clone(), entry.clone());
+        }
+        rkyv.save(&cache_path)?;
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.set_backend(CacheBackend::Limcode, &cache_path)?;
+        cache.save(&cache_path)?;
+        assert!(is_limcode_cache(&cache_path));
+        assert!(!cache_path.with_extension("idx").exists() && !cache_path.exists());
+
+        // Saving clean rewrites the index only, keeping every record
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert_eq!((cache.backend, cache.entries.len()), (CacheBackend::Limcode, 0));
+        cache.save(&cache_path)?;
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert_eq!((cache.root.as_path(), cache.last_scan), (Path::new("/scan"), rkyv.last_scan));
+        assert_eq!(cache.skip_stats.get("node_modules"), Some(&2));
+        #[cfg(windows)]
+        assert_eq!(cache.usn_state, rkyv.usn_state);
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(cache.entries.len(), 2);
+        for expected in &saved {
+            let loaded = cache.get_entry(&expected.path).unwrap();
+            assert_eq!(
+                (&loaded.children, loaded.modified, loaded.content_hash, &loaded.access_error, loaded.total_size),
+                (&expected.children, expected.modified, expected.content_hash, &expected.access_error, expected.total_size)
+            );
+            assert!(loaded.skipped_children && loaded.size_is_lower_bound && loaded.file_count == 3);
+        }
+        assert!(DiskCache::verify_files(&cache_path)?.is_empty());
+        let info = DiskCache::info(&cache_path)?;
+        assert_eq!((info.backend, info.entries, info.corrupt_records), (CacheBackend::Limcode, 2, Some(0)));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
 }
@@ -1,19 +1,21 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{Read, Write, Seek, SeekFrom};
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use chrono::{DateTime, Utc};
 use anyhow::{Result, anyhow};
 use memmap2::Mmap;
+use parking_lot::{Mutex, RwLock};
 
-use crate::cache::DirEntry;
+use crate::cache::{create_cache_dir, DirEntry, FastHashMap};
 
 /// Lightweight index mapping path offsets to byte positions in the mmap'd data file
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CacheIndex {
     /// Map of PathBuf to byte offset in the data file
-    pub offsets: HashMap<PathBuf, u64>,
+    pub offsets: FastHashMap<PathBuf, u64>,
     
     /// Last scan timestamp
     pub last_scan: DateTime<Utc>,
@@ -35,7 +37,7 @@ pub struct CacheIndex {
 impl CacheIndex {
     pub fn new() -> Self {
         CacheIndex {
-            offsets: HashMap::new(),
+            offsets: FastHashMap::default(),
             last_scan: Utc::now(),
             root: PathBuf::new(),
             last_scanned_root: PathBuf::new(),
@@ -46,32 +48,52 @@ impl CacheIndex {
     }
 }
 
+impl Default for CacheIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Memory-mapped cache system
 /// 
 /// Structure:
 /// - index file: contains CacheIndex (paths → offsets)
 /// - data file: contains serialized DirEntry objects at indexed offsets
+///
+/// Flushes append through one buffered handle kept open for the cache's
+/// lifetime; nothing is synced or remapped per flush. A lookup that reaches
+/// past the current mapping flushes the buffer and remaps once, and
+/// `save_index` syncs the data file before the index can point into it.
 pub struct MmapCache {
     /// Index mapping paths to byte offsets
     pub index: CacheIndex,
     
-    /// Memory-mapped data file
-    mmap: Option<Mmap>,
+    /// Memory-mapped data file, as long as it was when last mapped
+    mmap: RwLock<Option<Mmap>>,
     
     /// Path to the data file (for lazy-loading entries)
     data_path: PathBuf,
+
+    /// Append handle for flushes, opened by the first one
+    writer: Mutex<Option<BufWriter<File>>>,
+
+    /// Length of the data file including bytes still in `writer`'s buffer
+    data_len: u64,
     
     /// Buffer for pending writes before flush
     pub pending_writes: Vec<(PathBuf, DirEntry)>,
     
     /// Flush threshold
     pub flush_threshold: usize,
+
+    /// Entries that failed their checksum when read, each read as missing
+    corrupt: AtomicUsize,
 }
 
 impl MmapCache {
     /// Load cache from index and data files
     pub fn open(index_path: &Path, data_path: &Path) -> Result<Self> {
-        fs::create_dir_all(index_path.parent().unwrap())?;
+        create_cache_dir(index_path)?;
         
         let index = if index_path.exists() {
             let mut file = File::open(index_path)?;
@@ -88,45 +110,101 @@ impl MmapCache {
         } else {
             None
         };
+        let data_len = mmap.as_ref().map_or(0, |mmap| mmap.len() as u64);
         
         Ok(MmapCache {
             index,
-            mmap,
+            mmap: RwLock::new(mmap),
             data_path: data_path.to_path_buf(),
+            writer: Mutex::new(None),
+            data_len,
             pending_writes: Vec::new(),
             flush_threshold: 5000,
+            corrupt: AtomicUsize::new(0),
         })
     }
     
     /// Get a directory entry by path (deserializes from mmap'd region)
+    ///
+    /// An entry that fails its checksum reads as missing, with a warning,
+    /// and is counted in `corrupt_entries`.
     pub fn get(&self, path: &Path) -> Result<Option<DirEntry>> {
         let offset = match self.index.offsets.get(path) {
             Some(&off) => off,
             None => return Ok(None),
         };
         
-        let mmap = self.mmap.as_ref().ok_or_else(|| anyhow!("No mmap loaded"))?;
-        let data_slice = &mmap[offset as usize..];
-        
+        let read = match self.read_mapped(offset)? {
+            Some(read) => read,
+            // Written since the last mapping: make it visible, then read again
+            None => {
+                self.remap()?;
+                self.read_mapped(offset)?.ok_or_else(|| anyhow!("Truncated cache entry"))?
+            }
+        };
+        if read.is_none() {
+            self.corrupt.fetch_add(1, Ordering::Relaxed);
+            log::warn!(
+                "{}: entry for {} at offset {} fails its checksum; treating it as not cached",
+                self.data_path.display(),
+                path.display(),
+                offset
+            );
+        }
+        Ok(read)
+    }
+
+    /// Entries read so far that failed their checksum
+    pub fn corrupt_entries(&self) -> usize {
+        self.corrupt.load(Ordering::Relaxed)
+    }
+
+    /// Deserialize the entry at `offset` from the current mapping
+    ///
+    /// None when the entry extends past the mapping but not past the data
+    /// written so far, so a remap would show it; Some(None) when it fails
+    /// its checksum. The length is clamped to the data before anything is
+    /// sliced or allocated.
+    fn read_mapped(&self, offset: u64) -> Result<Option<Option<DirEntry>>> {
+        let mmap = self.mmap.read();
+        let mapped = mmap.as_deref().unwrap_or(&[]);
+        let beyond = |end: usize| -> Result<Option<Option<DirEntry>>> {
+            if (end as u64) <= self.data_len && mapped.len() < end {
+                Ok(None)
+            } else {
+                Err(anyhow!("Invalid cache entry"))
+            }
+        };
+
         // Deserialize single entry from this offset
-        // Format: [4-byte length][serialized entry]
-        if data_slice.len() < 4 {
+        // Format: [4-byte length][serialized entry][4-byte CRC32 of the entry]
+        let start = offset as usize;
+        let Some(prefix) = mapped.get(start..start + 4) else {
+            return beyond(start + 4);
+        };
+        let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+        if start as u64 + 8 + len as u64 > self.data_len.max(mapped.len() as u64) {
             return Err(anyhow!("Invalid cache entry"));
         }
-        
-        let len = u32::from_le_bytes([
-            data_slice[0],
-            data_slice[1],
-            data_slice[2],
-            data_slice[3],
-        ]) as usize;
-        
-        if data_slice.len() < 4 + len {
-            return Err(anyhow!("Truncated cache entry"));
+        let end = start + 4 + len;
+        let Some(crc) = mapped.get(end..end + 4) else {
+            return beyond(end + 4);
+        };
+        let data = &mapped[start + 4..end];
+        if crc32fast::hash(data) != u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Ok(Some(None));
         }
-        
-        let entry: DirEntry = bincode::deserialize(&data_slice[4..4 + len])?;
-        Ok(Some(entry))
+        Ok(Some(Some(bincode::deserialize(data)?)))
+    }
+
+    /// Push buffered appends to the file and map it again at its new length
+    fn remap(&self) -> Result<()> {
+        if let Some(writer) = self.writer.lock().as_mut() {
+            writer.flush()?;
+        }
+        let file = File::open(&self.data_path)?;
+        *self.mmap.write() = Some(unsafe { Mmap::map(&file)? });
+        Ok(())
     }
     
     /// Get all entries (loads entire mmap into memory - only for output generation)
@@ -150,43 +228,53 @@ impl MmapCache {
         }
     }
     
-    /// Flush pending writes to disk
+    /// Flush pending writes to the data file's append buffer
+    ///
+    /// Offsets come from the tracked length rather than a seek, and the
+    /// mapping is left alone: lookups remap when they need the new entries.
     pub fn flush_pending_writes(&mut self) -> Result<()> {
         if self.pending_writes.is_empty() {
             return Ok(());
         }
         
-        // Open data file in append mode
-        let mut data_file = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.data_path)?;
+        let writer = match self.writer.get_mut() {
+            Some(writer) => writer,
+            slot => {
+                let data_file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.data_path)?;
+                slot.insert(BufWriter::with_capacity(1 << 20, data_file))
+            }
+        };
         
         for (path, entry) in self.pending_writes.drain(..) {
             let serialized = bincode::serialize(&entry)?;
             let len = serialized.len() as u32;
             
-            // Record offset before writing
-            let offset = data_file.seek(SeekFrom::End(0))?;
-            self.index.offsets.insert(path, offset);
-            
-            // Write length + data
-            data_file.write_all(&len.to_le_bytes())?;
-            data_file.write_all(&serialized)?;
+            // Write length + data + checksum
+            self.index.offsets.insert(path, self.data_len);
+            writer.write_all(&len.to_le_bytes())?;
+            writer.write_all(&serialized)?;
+            writer.write_all(&crc32fast::hash(&serialized).to_le_bytes())?;
+            self.data_len += 8 + serialized.len() as u64;
         }
         
-        data_file.sync_all()?;
-        
-        // Reload mmap to include new data
-        if let Ok(file) = File::open(&self.data_path) {
-            self.mmap = Some(unsafe { Mmap::map(&file)? });
+        Ok(())
+    }
+
+    /// Write out buffered appends and sync the data file
+    pub fn sync(&self) -> Result<()> {
+        if let Some(writer) = self.writer.lock().as_mut() {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
         }
-        
         Ok(())
     }
     
-    /// Save index to disk
+    /// Save index to disk, once the data it points at is synced
     pub fn save_index(&self, path: &Path) -> Result<()> {
+        self.sync()?;
         let data = bincode::serialize(&self.index)?;
         let temp_path = path.with_extension("tmp");
         
@@ -208,3 +296,67 @@ impl MmapCache {
         self.index.offsets.is_empty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_entry;
+    use std::env;
+
+
+    #[test]
+    fn test_flushed_entries_are_read_before_and_after_save() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_mmap_test_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("test.idx");
+        let data_path = temp_dir.join("test.dat");
+
+        let mut cache = MmapCache::open(&index_path, &data_path)?;
+        assert!(cache.is_empty());
+        cache.add_entry(PathBuf::from("/a"), test_entry("/a", &[]));
+        cache.flush_pending_writes()?;
+        // Past the (empty) mapping: the lookup flushes the buffer and remaps
+        assert_eq!(cache.get(Path::new("/a"))?.unwrap().name, "a");
+
+        cache.add_entry(PathBuf::from("/a/b"), test_entry("/a/b", &[]));
+        cache.flush_pending_writes()?;
+        assert_eq!(cache.get(Path::new("/a/b"))?.unwrap().name, "b");
+        assert!(cache.get(Path::new("/missing"))?.is_none());
+        cache.save_index(&index_path)?;
+        drop(cache);
+
+        let cache = MmapCache::open(&index_path, &data_path)?;
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_all()?.len(), 2);
+        assert_eq!(cache.corrupt_entries(), 0);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_damaged_entry_reads_as_missing() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_mmap_corrupt_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("test.idx");
+        let data_path = temp_dir.join("test.dat");
+
+        let mut cache = MmapCache::open(&index_path, &data_path)?;
+        cache.add_entry(PathBuf::from("/a"), test_entry("/a", &[]));
+        cache.flush_pending_writes()?;
+        cache.save_index(&index_path)?;
+        drop(cache);
+
+        // Flip a byte inside the serialized entry, past the length prefix
+        let mut data = fs::read(&data_path)?;
+        data[6] ^= 0xff;
+        fs::write(&data_path, &data)?;
+
+        let cache = MmapCache::open(&index_path, &data_path)?;
+        assert!(cache.get(Path::new("/a"))?.is_none());
+        assert_eq!(cache.corrupt_entries(), 1);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
@@ -1,33 +1,38 @@
-/// Performance optimization module for PerfTree cache
-///
-/// This module provides optimized serialization and lazy-loading strategies:
-///
-/// 1. **Lazy single-node access**: Index maps PathBuf → file offset for O(1) lookups
-/// 2. **Memory-mapped data**: Large cache files are mmap'd, not fully loaded
-/// 3. **Vectorized batch operations**: Two-phase processing for offset computation and deserialization
-///
-/// Strategy:
-/// - Index file (.idx): bincode-serialized path → offset mapping
-/// - Data file (.dat): Each entry prefixed with length, stored sequentially
-/// - Lazy loading: Entries only deserialized on access, not upfront
-/// - Batch ops: Two-phase approach separates offset computation from deserialization
-///   enabling SIMD vectorization for parallel processing in future implementations
+//! Performance optimization module for PerfTree cache
+//!
+//! This module provides optimized serialization and lazy-loading strategies:
+//!
+//! 1. **Lazy single-node access**: Index maps PathBuf → file offset for O(1) lookups
+//! 2. **Memory-mapped data**: Large cache files are mmap'd, not fully loaded
+//! 3. **Vectorized batch operations**: Two-phase processing for offset computation and deserialization
+//!
+//! Strategy:
+//! - Index file (.idx): bincode-serialized path → offset mapping
+//! - Data file (.dat): Each entry prefixed with length and followed by its CRC32, stored sequentially
+//! - Lazy loading: Entries only deserialized on access, not upfront
+//! - Batch ops: Two-phase approach separates offset computation from deserialization
+//!   enabling SIMD vectorization for parallel processing in future implementations
 
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Write, Seek, SeekFrom, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use anyhow::Result;
 use memmap2::Mmap;
+use rayon::prelude::*;
 
-use crate::cache::DirEntry;
+use crate::cache::{create_cache_dir, DirEntry, FastHashMap};
+
+/// Entries deserialized per rayon task by `get_all`
+const BATCH_CHUNK: usize = 4096;
 
 /// Index mapping paths to byte offsets in the data file
 /// Serialized once, deserialized once on load - small footprint
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct OptimizedIndex {
     /// Path → byte offset in data file
-    pub offsets: HashMap<PathBuf, u64>,
+    pub offsets: FastHashMap<PathBuf, u64>,
     /// Total entries for validation
     pub entry_count: usize,
 }
@@ -35,12 +40,18 @@ pub struct OptimizedIndex {
 impl OptimizedIndex {
     pub fn new() -> Self {
         OptimizedIndex {
-            offsets: HashMap::new(),
+            offsets: FastHashMap::default(),
             entry_count: 0,
         }
     }
 }
 
+impl Default for OptimizedIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Optimized lazy-loading cache with mmap support
 /// Entries are only deserialized when accessed, enabling O(1) single-node lookups
 pub struct OptimizedCache {
@@ -48,6 +59,8 @@ pub struct OptimizedCache {
     pub index: OptimizedIndex,
     /// Mmap'd data file (large, lazy access)
     mmap: Option<Mmap>,
+    /// Entries that failed their checksum when read, each read as missing
+    corrupt: AtomicUsize,
 }
 
 impl OptimizedCache {
@@ -72,7 +85,7 @@ impl OptimizedCache {
             None
         };
 
-        Ok(OptimizedCache { index, mmap })
+        Ok(OptimizedCache { index, mmap, corrupt: AtomicUsize::new(0) })
     }
 
     /// O(1) lazy deserialization: get entry by path without loading others
@@ -87,41 +100,78 @@ impl OptimizedCache {
             .mmap
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("No mmap loaded"))?;
+        self.read_entry(mmap, offset)
+    }
 
-        let data_slice = &mmap[offset as usize..];
-
-        // Read length prefix (4 bytes)
-        if data_slice.len() < 4 {
-            return Ok(None);
-        }
-
-        let len = u32::from_le_bytes([
-            data_slice[0],
-            data_slice[1],
-            data_slice[2],
-            data_slice[3],
-        ]) as usize;
-
-        if data_slice.len() < 4 + len {
+    /// Deserialize the entry at `offset`, checking its checksum first
+    ///
+    /// The length prefix is clamped to the mapping before anything is
+    /// sliced or allocated. An entry out of bounds or failing its checksum
+    /// reads as missing, with a warning, and is counted in `corrupt_entries`.
+    fn read_entry(&self, mmap: &[u8], offset: u64) -> Result<Option<DirEntry>> {
+        let checked = mmap.get(offset as usize..).and_then(|record| {
+            let len = u32::from_le_bytes(record.get(..4)?.try_into().ok()?) as usize;
+            let data = record.get(4..4usize.checked_add(len)?)?;
+            let crc = u32::from_le_bytes(record.get(4 + len..8 + len)?.try_into().ok()?);
+            (crc32fast::hash(data) == crc).then_some(data)
+        });
+        let Some(data) = checked else {
+            self.corrupt.fetch_add(1, Ordering::Relaxed);
+            log::warn!("cache entry at offset {} is truncated or fails its checksum; treating it as not cached", offset);
             return Ok(None);
-        }
+        };
 
         // Deserialize single entry from this offset
-        let entry: DirEntry = bincode::deserialize(&data_slice[4..4 + len])?;
+        let entry: DirEntry = bincode::deserialize(data)?;
         Ok(Some(entry))
     }
 
+    /// Entries read so far that were truncated or failed their checksum
+    pub fn corrupt_entries(&self) -> usize {
+        self.corrupt.load(Ordering::Relaxed)
+    }
+
     /// Get all entries (full deserialization - only for batch/output operations)
     /// This materializes the entire cache into memory when needed
+    ///
+    /// The offsets are sorted and deserialized a chunk per rayon task over
+    /// the shared mapping, then inserted in bulk. Records that fail are
+    /// left out and counted per chunk, and the counts logged.
     pub fn get_all(&self) -> Result<HashMap<PathBuf, DirEntry>> {
-        let mut entries = HashMap::new();
+        let mut paths: Vec<(&PathBuf, u64)> = self.index.offsets.iter().map(|(path, &offset)| (path, offset)).collect();
+        paths.sort_unstable_by_key(|&(_, offset)| offset);
+
+        let chunks: Vec<(Vec<(PathBuf, DirEntry)>, usize)> = paths
+            .par_chunks(BATCH_CHUNK)
+            .map(|chunk| {
+                let mut entries = Vec::with_capacity(chunk.len());
+                let mut failed = 0;
+                for &(path, _) in chunk {
+                    match self.get_entry(path) {
+                        Ok(Some(entry)) => entries.push((path.clone(), entry)),
+                        _ => failed += 1,
+                    }
+                }
+                (entries, failed)
+            })
+            .collect();
 
-        for path in self.index.offsets.keys() {
-            if let Ok(Some(entry)) = self.get_entry(path) {
-                entries.insert(path.clone(), entry);
-            }
+        let failed: Vec<String> = chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, failed))| *failed > 0)
+            .map(|(chunk, (_, failed))| format!("chunk {}: {}", chunk, failed))
+            .collect();
+        if !failed.is_empty() {
+            log::warn!(
+                "{} cache records failed to deserialize and are skipped ({})",
+                chunks.iter().map(|(_, failed)| failed).sum::<usize>(),
+                failed.join(", ")
+            );
         }
 
+        let mut entries = HashMap::with_capacity(paths.len());
+        entries.extend(chunks.into_iter().flat_map(|(entries, _)| entries));
         Ok(entries)
     }
 
@@ -131,7 +181,7 @@ impl OptimizedCache {
         // Vectorized offset lookup phase (can be SIMD'd in future)
         let offsets: Vec<_> = paths
             .iter()
-            .map(|p| self.index.offsets.get(p).copied())
+            .map(|p| self.index.offsets.get(*p).copied())
             .collect();
 
         let mmap = self
@@ -142,41 +192,20 @@ impl OptimizedCache {
         // Deserialization phase (now vectorized)
         offsets
             .into_iter()
-            .map(|offset_opt| {
-                if let Some(offset) = offset_opt {
-                    let data_slice = &mmap[offset as usize..];
-                    
-                    if data_slice.len() < 4 {
-                        return Ok(None);
-                    }
-
-                    let len = u32::from_le_bytes([
-                        data_slice[0],
-                        data_slice[1],
-                        data_slice[2],
-                        data_slice[3],
-                    ]) as usize;
-
-                    if data_slice.len() < 4 + len {
-                        return Ok(None);
-                    }
-
-                    let entry: DirEntry = bincode::deserialize(&data_slice[4..4 + len])?;
-                    Ok(Some(entry))
-                } else {
-                    Ok(None)
-                }
+            .map(|offset_opt| match offset_opt {
+                Some(offset) => self.read_entry(mmap, offset),
+                None => Ok(None),
             })
             .collect()
     }
 
     /// Save optimized cache (index + data files)
     pub fn save(entries: &HashMap<PathBuf, DirEntry>, index_path: &Path, data_path: &Path) -> Result<()> {
-        std::fs::create_dir_all(index_path.parent().unwrap())?;
+        create_cache_dir(index_path)?;
 
-        // Write data file with length-prefixed entries
+        // Write data file with length-prefixed, checksummed entries
         let mut data_file = File::create(data_path)?;
-        let mut offsets = HashMap::new();
+        let mut offsets = FastHashMap::default();
 
         for (path, entry) in entries {
             // Record offset before writing
@@ -189,6 +218,7 @@ impl OptimizedCache {
 
             data_file.write_all(&len.to_le_bytes())?;
             data_file.write_all(&serialized)?;
+            data_file.write_all(&crc32fast::hash(&serialized).to_le_bytes())?;
         }
         data_file.sync_all()?;
 
@@ -220,41 +250,66 @@ impl OptimizedCache {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::{test_entry};
     use std::env;
 
+    fn entry(path: &str, children: &[&str]) -> DirEntry {
+        DirEntry { dir_count: children.len() as u32, ..test_entry(path, children) }
+    }
+
     #[test]
     fn test_optimized_cache_roundtrip() -> Result<()> {
-        let temp_dir = env::temp_dir().join("ptree_opt_test");
+        let temp_dir = env::temp_dir().join(format!("ptree_opt_test_{}", std::process::id()));
         std::fs::create_dir_all(&temp_dir)?;
 
         let index_path = temp_dir.join("test.idx");
         let data_path = temp_dir.join("test.dat");
 
-        // Create test data
         let mut entries = HashMap::new();
-        entries.insert(
-            PathBuf::from("C:\\test"),
-            DirEntry {
-                path: PathBuf::from("C:\\test"),
-                name: "test".to_string(),
-                modified: chrono::Utc::now(),
-                size: 1024,
-                children: vec!["child".to_string()],
-                symlink_target: None,
-                is_hidden: false,
-            },
-        );
-
-        // Save
+        entries.insert(PathBuf::from("/test"), entry("/test", &["child"]));
+        entries.insert(PathBuf::from("/test/child"), entry("/test/child", &[]));
+
         OptimizedCache::save(&entries, &index_path, &data_path)?;
 
-        // Load and verify
         let cache = OptimizedCache::open(&index_path, &data_path)?;
-        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.len(), 2);
+
+        let root = cache.get_entry(Path::new("/test"))?.unwrap();
+        assert_eq!(root.name, "test");
+        assert_eq!(root.children.len(), 1);
+
+        let batch = cache.get_batch(&[Path::new("/test/child"), Path::new("/missing")])?;
+        assert_eq!(batch[0].as_ref().map(|entry| entry.name.as_str()), Some("child"));
+        assert!(batch[1].is_none());
+
+        assert_eq!(cache.get_all()?.len(), 2);
+        assert_eq!(cache.corrupt_entries(), 0);
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_damaged_record_reads_as_missing() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_opt_corrupt_{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir)?;
+
+        let index_path = temp_dir.join("test.idx");
+        let data_path = temp_dir.join("test.dat");
+
+        let mut entries = HashMap::new();
+        entries.insert(PathBuf::from("/test"), entry("/test", &[]));
+        OptimizedCache::save(&entries, &index_path, &data_path)?;
+
+        // Flip a byte inside the serialized entry, past the length prefix
+        let mut data = std::fs::read(&data_path)?;
+        data[6] ^= 0xff;
+        std::fs::write(&data_path, &data)?;
 
-        let entry = cache.get_entry(Path::new("C:\\test"))?;
-        assert!(entry.is_some());
-        assert_eq!(entry.unwrap().name, "test");
+        let cache = OptimizedCache::open(&index_path, &data_path)?;
+        assert!(cache.get_entry(Path::new("/test"))?.is_none());
+        assert!(cache.get_all()?.is_empty());
+        assert_eq!(cache.corrupt_entries(), 2);
 
         let _ = std::fs::remove_dir_all(&temp_dir);
         Ok(())
@@ -1,14 +1,870 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{self, File};
 use std::io::{Write, Seek, SeekFrom, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 use chrono::{DateTime, Utc};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use memmap2::Mmap;
+use parking_lot::Mutex;
+use ptree_core::PTreeError;
+use crate::cache::{create_cache_dir, FastHashMap};
+use crate::bloom::{PathBloom, DEFAULT_BLOOM_FP_RATE};
+use crate::children::ChildNames;
+use crate::offsets::{OffsetIndex, SharedBytes, SortedOffsets, ARCHIVE_ALIGN};
+use crate::spill::DEFAULT_SPILL_CHILDREN;
+use crate::crypt::{CacheKey, KeySource};
+use crate::lock::CacheLock;
 #[cfg(windows)]
 use crate::cache::USNJournalState;
 
+// ============================================================================
+// File Header (format version)
+// ============================================================================
+
+/// First bytes of the `.idx` and `.dat` files
+pub const CACHE_MAGIC: &[u8; 4] = b"PTRE";
+
+/// Layout version written by this build
+///
+/// - 0: no header; entries without `size`, `skipped_children`, `is_mount`
+///   and `access_error`
+/// - 1: `CACHE_MAGIC` header; `RkyvDirEntry` as below
+/// - 2: CRC32 over the index and each data record; the index records the
+///   data file's length
+/// - 3: the data file starts with its save's generation, repeated in the index
+/// - 4: header flags are checked; `FLAG_ZSTD_BLOCKS` data files hold
+///   compressed blocks of records
+/// - 5: `RkyvDirEntry::evicted`
+/// - 6: `RkyvDirEntry::file_count` and `dir_count`
+/// - 7: `RkyvDirEntry::total_size` and `size_is_lower_bound`
+/// - 8: `RkyvDirEntry::children` packed into one string (`ChildNames`)
+/// - 9: `RkyvDirEntry::spilled_children`; long child lists go in spill
+///   records of their own (`FLAG_SPILLED_CHILDREN`)
+pub const CACHE_FORMAT_VERSION: u32 = 9;
+
+/// Header size: `CACHE_MAGIC`, version (u32 LE), flags (u32 LE)
+pub const CACHE_HEADER_LEN: usize = 12;
+
+/// Header flag: records are stored in zstd-compressed blocks (`--compress-cache`)
+///
+/// Set in both files of the pair; the index then also holds the offset of
+/// every block.
+pub const FLAG_ZSTD_BLOCKS: u32 = 1;
+
+/// Header flag: the index payload and every data record are AES-256-GCM sealed (`--encrypt-cache`)
+///
+/// Set in both files of the pair. The index starts with the `KeySource`
+/// (bincode, in the clear) and seals the rest; each record frame (or
+/// compressed block) holds sealed bytes, its CRC32 taken over them.
+pub const FLAG_ENCRYPTED: u32 = 2;
+
+/// Header flag: the cache is split into shards by top-level directory (`ShardTable`)
+///
+/// Set in the top-level index only, which then holds the shard table after
+/// any block offsets. Its data file and the shard pairs don't carry it.
+pub const FLAG_SHARDED: u32 = 4;
+
+/// Header flag: the index holds a `PathBloom` over every cached path, shards included
+///
+/// Set in the top-level index only, with the filter after the shard table.
+pub const FLAG_BLOOM: u32 = 8;
+
+/// Header flag: the index's offsets are stored sorted (`SortedOffsets`)
+///
+/// Set in an encrypted index of `SORTED_OFFSETS_MIN_ENTRIES` or more
+/// entries, with the bincode sorted section after the filter; the bincode
+/// index's own map then holds only the paths that aren't UTF-8. A plain
+/// index stores them archived instead (`FLAG_ARCHIVED_OFFSETS`).
+pub const FLAG_SORTED_OFFSETS: u32 = 16;
+
+/// Header flag: directories with more children than a threshold keep them in spill records
+///
+/// Set in any index with spill records or a threshold other than
+/// `DEFAULT_SPILL_CHILDREN` (`RkyvCacheIndex::spill_children`), which
+/// follows the sorted offsets together with the offset of each spill record. A spill record is framed like an entry record (or sits in a
+/// compressed block like one) and holds the bincode `ChildNames`.
+pub const FLAG_SPILLED_CHILDREN: u32 = 32;
+
+/// Header flag: the index's sorted offsets are an rkyv archive read in place from the mapped file
+///
+/// Set in a plain index of `SORTED_OFFSETS_MIN_ENTRIES` or more entries
+/// instead of `FLAG_SORTED_OFFSETS`. The archive's length (u64 LE) follows
+/// the header, the archive itself starts at `ARCHIVED_OFFSETS_START`, and
+/// the CRC and the rest of the index follow it. It is left out of the CRC
+/// so that opening the index doesn't read it through: its bounds are
+/// checked instead, and an offset damaged all the same points at a record
+/// that fails its own checksum.
+pub const FLAG_ARCHIVED_OFFSETS: u32 = 64;
+
+/// Where the archive of a `FLAG_ARCHIVED_OFFSETS` index starts: the header and its length, padded to `ARCHIVE_ALIGN`
+const ARCHIVED_OFFSETS_START: usize = (CACHE_HEADER_LEN + 8).next_multiple_of(ARCHIVE_ALIGN);
+
+/// Records per compressed block
+pub const BLOCK_ENTRIES: usize = 256;
+
+/// Decompressed blocks `RkyvMmapCache` keeps around for further lookups
+const BLOCK_CACHE_SIZE: usize = 8;
+
+const BLOCK_ZSTD_LEVEL: i32 = 3;
+
+/// Offset of the first data record: the header, then the generation (u64 LE)
+pub const DATA_START: u64 = CACHE_HEADER_LEN as u64 + 8;
+
+/// Write the header for `CACHE_FORMAT_VERSION` without flags
+pub fn write_header<W: Write>(writer: &mut W) -> Result<()> {
+    write_header_flags(writer, 0)
+}
+
+/// Write the header for `CACHE_FORMAT_VERSION` with `flags`
+fn write_header_flags<W: Write>(writer: &mut W, flags: u32) -> Result<()> {
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_all(&CACHE_FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&flags.to_le_bytes())?;
+    Ok(())
+}
+
+/// Split a cache file into its format version and payload
+///
+/// Files without `CACHE_MAGIC` predate the header and are version 0. A
+/// version newer than `CACHE_FORMAT_VERSION` fails with
+/// `PTreeError::CacheVersion` rather than being misread.
+pub fn read_header(bytes: &[u8]) -> Result<(u32, &[u8])> {
+    if bytes.len() < CACHE_HEADER_LEN || &bytes[..4] != CACHE_MAGIC {
+        return Ok((0, bytes));
+    }
+    let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    if version > CACHE_FORMAT_VERSION {
+        return Err(PTreeError::CacheVersion { found: version, supported: CACHE_FORMAT_VERSION }.into());
+    }
+    Ok((version, &bytes[CACHE_HEADER_LEN..]))
+}
+
+/// Header flags of a cache file (0 before version 4, which didn't check them)
+///
+/// Bits this build doesn't know fail with `PTreeError::CacheCorrupt`, so a
+/// file laid out some other way is never read as plain records.
+fn read_flags(bytes: &[u8]) -> Result<u32> {
+    match read_header(bytes)? {
+        (version, _) if version < 4 => Ok(0),
+        _ => {
+            let flags = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+            if flags & !(FLAG_ZSTD_BLOCKS | FLAG_ENCRYPTED | FLAG_SHARDED | FLAG_BLOOM | FLAG_SORTED_OFFSETS | FLAG_SPILLED_CHILDREN | FLAG_ARCHIVED_OFFSETS) != 0 {
+                return Err(PTreeError::CacheCorrupt(format!("unknown header flags {:#x}", flags)).into());
+            }
+            Ok(flags)
+        }
+    }
+}
+
+/// Header flags for a pair written with these settings
+fn pair_flags(compressed: bool, key: Option<&CacheKey>) -> u32 {
+    (if compressed { FLAG_ZSTD_BLOCKS } else { 0 }) | (if key.is_some() { FLAG_ENCRYPTED } else { 0 })
+}
+
+/// Offset of the first data record in a data file of `version`
+fn data_start(version: u32) -> u64 {
+    match version {
+        0 => 0,
+        1 | 2 => CACHE_HEADER_LEN as u64,
+        _ => DATA_START,
+    }
+}
+
+/// Generation for a new save: its time in nanoseconds, distinct for every save in practice
+fn new_generation() -> u64 {
+    Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64
+}
+
+// ============================================================================
+// Checksummed Index & Records
+// ============================================================================
+
+/// A decoded index file
+struct IndexFile {
+    index: RkyvCacheIndex,
+    /// Length of the data file the offsets point into (0 before version 2)
+    data_len: u64,
+    /// Generation of that data file (0 before version 3)
+    generation: u64,
+    /// File offsets of the compressed blocks; None for plain records
+    blocks: Option<Vec<u64>>,
+    /// Key the pair is sealed with (`FLAG_ENCRYPTED`)
+    key: Option<CacheKey>,
+    /// Shards of a sharded cache (`FLAG_SHARDED`)
+    shards: Option<ShardTable>,
+    /// Filter over every cached path (`FLAG_BLOOM`)
+    bloom: Option<PathBloom>,
+}
+
+/// Write the index file through a temp file and rename
+///
+/// Layout after the header: CRC32 (u32 LE) of everything that follows, the
+/// length and generation of the data file the offsets point into (u64 LE
+/// each), then the bincode index. The length catches a data file cut short,
+/// the generation one from another save.
+pub fn write_index_file(path: &Path, index: &RkyvCacheIndex, data_len: u64, generation: u64) -> Result<()> {
+    let temp_path = write_index_temp(path, index, data_len, generation, None, None, None, None)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Write the index file's replacement to `<name>.tmp`, returning that path
+///
+/// With `blocks`, their offsets (bincode) go between the generation and the
+/// index, and the header carries `FLAG_ZSTD_BLOCKS`; `shards` follow them
+/// (`FLAG_SHARDED`), then `bloom` (`FLAG_BLOOM`), then the sorted offsets
+/// of a large sealed index (`FLAG_SORTED_OFFSETS`), then the spill
+/// threshold and spill offsets (`FLAG_SPILLED_CHILDREN`). With `key`, its
+/// source follows the header and the CRC and everything after it are
+/// sealed. A large plain index has its sorted offsets archived ahead of
+/// the CRC instead (`FLAG_ARCHIVED_OFFSETS`), unless they are too large
+/// for an archive's 32-bit offsets.
+#[allow(clippy::too_many_arguments)]
+fn write_index_temp(
+    path: &Path,
+    index: &RkyvCacheIndex,
+    data_len: u64,
+    generation: u64,
+    blocks: Option<&[u64]>,
+    key: Option<&CacheKey>,
+    shards: Option<&ShardTable>,
+    bloom: Option<&PathBloom>,
+) -> Result<PathBuf> {
+    let mut body = data_len.to_le_bytes().to_vec();
+    body.extend_from_slice(&generation.to_le_bytes());
+    if let Some(blocks) = blocks {
+        bincode::serialize_into(&mut body, blocks)?;
+    }
+    if let Some(shards) = shards {
+        bincode::serialize_into(&mut body, shards)?;
+    }
+    if let Some(bloom) = bloom {
+        bincode::serialize_into(&mut body, bloom)?;
+    }
+    let sorted = index.offsets.sorted_section();
+    let archived = match (&sorted, key) {
+        (Some(sorted), None) => sorted.to_archive().ok(),
+        _ => None,
+    };
+    let sorted = sorted.filter(|_| archived.is_none());
+    if let Some(sorted) = &sorted {
+        bincode::serialize_into(&mut body, sorted.as_ref())?;
+    }
+    let spilled = !index.spills.is_empty() || index.spill_children != DEFAULT_SPILL_CHILDREN;
+    if spilled {
+        bincode::serialize_into(&mut body, &(index.spill_children as u64, &index.spills))?;
+    }
+    bincode::serialize_into(&mut body, index)?;
+
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path).map_err(|source| PTreeError::CachePath { path: temp_path.clone(), source })?;
+    let sharded = if shards.is_some() { FLAG_SHARDED } else { 0 };
+    let filtered = if bloom.is_some() { FLAG_BLOOM } else { 0 };
+    let sorted = if sorted.is_some() { FLAG_SORTED_OFFSETS } else { 0 };
+    let spilled = if spilled { FLAG_SPILLED_CHILDREN } else { 0 };
+    let in_place = if archived.is_some() { FLAG_ARCHIVED_OFFSETS } else { 0 };
+    write_header_flags(&mut file, pair_flags(blocks.is_some(), key) | sharded | filtered | sorted | spilled | in_place)?;
+    if let Some(archived) = &archived {
+        file.write_all(&(archived.len() as u64).to_le_bytes())?;
+        file.write_all(&[0; ARCHIVED_OFFSETS_START - CACHE_HEADER_LEN - 8])?;
+        file.write_all(archived)?;
+    }
+    let mut payload = crc32fast::hash(&body).to_le_bytes().to_vec();
+    payload.extend_from_slice(&body);
+    if let Some(key) = key {
+        bincode::serialize_into(&mut file, key.source())?;
+        payload = key.seal(&payload)?;
+    }
+    file.write_all(&payload)?;
+    file.sync_all()?;
+    Ok(temp_path)
+}
+
+/// How the key of an encrypted index is obtained; None for a plain one
+pub(crate) fn read_key_source(bytes: &[u8]) -> Result<Option<KeySource>> {
+    if read_flags(bytes)? & FLAG_ENCRYPTED == 0 {
+        return Ok(None);
+    }
+    let source = bincode::deserialize(&bytes[CACHE_HEADER_LEN..])
+        .map_err(|e| PTreeError::CacheCorrupt(format!("key source does not decode: {}", e)))?;
+    Ok(Some(source))
+}
+
+/// Map an index file for `read_index_file`
+fn map_index(index_path: &Path) -> Result<SharedBytes> {
+    let file = File::open(index_path)?;
+    Ok(Arc::new(unsafe { Mmap::map(&file)? }))
+}
+
+/// Check and decode a whole index file, returning its format version too
+///
+/// An encrypted index is unlocked with its `KeySource`; a key that can't be
+/// had, or doesn't open the payload, fails with `PTreeError::CacheKey`.
+/// Archived sorted offsets (`FLAG_ARCHIVED_OFFSETS`) are left in `file`
+/// and read from there, which keeps it alive as long as the index.
+fn read_index_file(file: SharedBytes) -> Result<(u32, IndexFile)> {
+    let bytes = (*file).as_ref();
+    let (version, mut payload) = read_header(bytes)?;
+    let flags = read_flags(bytes)?;
+    let compressed = flags & FLAG_ZSTD_BLOCKS != 0;
+    let corrupt = |what: String| -> anyhow::Error { PTreeError::CacheCorrupt(what).into() };
+    let decode = |bytes: &[u8]| -> Result<RkyvCacheIndex> {
+        bincode::deserialize(bytes).map_err(|e| corrupt(format!("index does not decode: {}", e)))
+    };
+    if version < 2 {
+        let index = decode(payload)?;
+        return Ok((version, IndexFile { index, data_len: 0, generation: 0, blocks: None, key: None, shards: None, bloom: None }));
+    }
+
+    let mut sorted = None;
+    if flags & FLAG_ARCHIVED_OFFSETS != 0 {
+        let len = payload.get(..8).ok_or_else(|| corrupt("index file is truncated".to_string()))?;
+        let end = (u64::from_le_bytes(len.try_into().expect("8-byte slice")) as usize)
+            .checked_add(ARCHIVED_OFFSETS_START)
+            .filter(|&end| end <= bytes.len())
+            .ok_or_else(|| corrupt("archived offsets run past the end of the index".to_string()))?;
+        sorted = Some(SortedOffsets::archived(file.clone(), ARCHIVED_OFFSETS_START..end)?);
+        payload = &bytes[end..];
+    }
+    let key = match read_key_source(bytes)? {
+        Some(source) => Some(CacheKey::unlock(&source)?),
+        None => None,
+    };
+    let unsealed;
+    if let Some(key) = &key {
+        bincode::deserialize_from::<_, KeySource>(&mut payload).expect("decoded by read_key_source");
+        unsealed = key.unseal(payload).ok_or_else(|| {
+            PTreeError::CacheKey("the index does not open with this key (wrong passphrase?)".to_string())
+        })?;
+        payload = &unsealed;
+    }
+
+    let fixed = if version < 3 { 12 } else { 20 };
+    if payload.len() < fixed {
+        return Err(corrupt("index file is truncated".to_string()));
+    }
+    let stored = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+    if crc32fast::hash(&payload[4..]) != stored {
+        return Err(corrupt("index checksum mismatch".to_string()));
+    }
+    let data_len = u64::from_le_bytes(payload[4..12].try_into().expect("8-byte slice"));
+    let generation = if version < 3 { 0 } else { u64::from_le_bytes(payload[12..20].try_into().expect("8-byte slice")) };
+
+    let mut rest = &payload[fixed..];
+    let blocks = if compressed {
+        let blocks: Vec<u64> = bincode::deserialize_from(&mut rest)
+            .map_err(|e| corrupt(format!("block table does not decode: {}", e)))?;
+        Some(blocks)
+    } else {
+        None
+    };
+    let shards = if flags & FLAG_SHARDED != 0 {
+        let shards: ShardTable = bincode::deserialize_from(&mut rest)
+            .map_err(|e| corrupt(format!("shard table does not decode: {}", e)))?;
+        Some(shards)
+    } else {
+        None
+    };
+    let bloom = if flags & FLAG_BLOOM != 0 {
+        let bloom: PathBloom = bincode::deserialize_from(&mut rest)
+            .map_err(|e| corrupt(format!("bloom filter does not decode: {}", e)))?;
+        Some(bloom)
+    } else {
+        None
+    };
+    if flags & FLAG_SORTED_OFFSETS != 0 {
+        let decoded: SortedOffsets = bincode::deserialize_from(&mut rest)
+            .map_err(|e| corrupt(format!("sorted offsets do not decode: {}", e)))?;
+        sorted = Some(decoded);
+    }
+    let spills = if flags & FLAG_SPILLED_CHILDREN != 0 {
+        let spills: (u64, OffsetIndex) = bincode::deserialize_from(&mut rest)
+            .map_err(|e| corrupt(format!("spill offsets do not decode: {}", e)))?;
+        Some(spills)
+    } else {
+        None
+    };
+    let mut index = decode(rest)?;
+    if let Some(sorted) = sorted {
+        index.offsets = std::mem::take(&mut index.offsets).with_sorted(sorted)?;
+    }
+    (index.spill_children, index.spills) = match spills {
+        Some((threshold, spills)) => (threshold as usize, spills),
+        None => (DEFAULT_SPILL_CHILDREN, OffsetIndex::new()),
+    };
+    Ok((version, IndexFile { index, data_len, generation, blocks, key, shards, bloom }))
+}
+
+/// Append one data record: length (u32 LE), CRC32 of the bytes (u32 LE), bincode bytes
+///
+/// Returns the number of bytes written.
+pub fn write_record<W: Write>(writer: &mut W, entry: &RkyvDirEntry) -> Result<u64> {
+    write_frame(writer, &bincode::serialize(entry)?)
+}
+
+/// Write `bytes` framed like a record (a compressed block is framed the same way)
+fn write_frame<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<u64> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(bytes).to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(8 + bytes.len() as u64)
+}
+
+/// Frame `plain` record or block bytes, sealed first under `key`
+fn write_sealed_frame<W: Write>(writer: &mut W, plain: &[u8], key: Option<&CacheKey>) -> Result<u64> {
+    match key {
+        Some(key) => write_frame(writer, &key.seal(plain)?),
+        None => write_frame(writer, plain),
+    }
+}
+
+/// Bytes of the record at `offset` in a data file of `version`
+///
+/// Checks the length against the file and, from version 2 on, the checksum.
+fn read_record(data: &[u8], offset: u64, version: u32) -> Result<&[u8]> {
+    read_frame(data, offset, data_start(version), version >= 2)
+}
+
+/// Bytes of the frame at `offset`, which may not lie before `start`
+fn read_frame(data: &[u8], offset: u64, start: u64, checksummed: bool) -> Result<&[u8]> {
+    let corrupt = |what: &str| PTreeError::CacheCorrupt(format!("record at offset {} {}", offset, what));
+    let prefix = if checksummed { 8 } else { 4 };
+    let record = data
+        .get(offset as usize..)
+        .filter(|record| offset >= start && record.len() >= prefix)
+        .ok_or_else(|| corrupt("is out of bounds"))?;
+    let len = u32::from_le_bytes([record[0], record[1], record[2], record[3]]) as usize;
+    // Clamped to the mapping before anything is sliced or allocated
+    let bytes = record.get(prefix..prefix + len).ok_or_else(|| corrupt("runs past the end of the file"))?;
+    if checksummed {
+        let stored = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        if crc32fast::hash(bytes) != stored {
+            return Err(corrupt("fails its checksum").into());
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode a record's bytes written in `version`
+fn decode_entry(bytes: &[u8], version: u32) -> Result<RkyvDirEntry> {
+    let entry = match version {
+        0 => bincode::deserialize::<RkyvDirEntryV0>(bytes).map(RkyvDirEntry::from),
+        1..=4 => bincode::deserialize::<RkyvDirEntryV4>(bytes).map(RkyvDirEntry::from),
+        5 => bincode::deserialize::<RkyvDirEntryV5>(bytes).map(RkyvDirEntry::from),
+        6 => bincode::deserialize::<RkyvDirEntryV6>(bytes).map(RkyvDirEntry::from),
+        7 => bincode::deserialize::<RkyvDirEntryV7>(bytes).map(RkyvDirEntry::from),
+        8 => bincode::deserialize::<RkyvDirEntryV8>(bytes).map(RkyvDirEntry::from),
+        _ => bincode::deserialize::<RkyvDirEntry>(bytes),
+    };
+    entry.map_err(|e| PTreeError::CacheCorrupt(format!("record does not decode: {}", e)).into())
+}
+
+// ============================================================================
+// Saving (atomic pair replacement)
+// ============================================================================
+
+/// Write a complete index/data pair in the current format, replacing any existing one
+///
+/// Callers hold the cache's exclusive `CacheLock`.
+///
+/// Records stream into `<name>.dat.tmp` and the index into `<name>.tmp`;
+/// only once both are fsynced are they renamed over the pair, data first,
+/// so an error while producing `entries` (or writing them) leaves the
+/// existing pair untouched. Both files carry a fresh generation: after a
+/// crash between the two renames, `RkyvMmapCache::open` finds the new
+/// index still in `<name>.tmp` and finishes the swap.
+///
+/// With `compress`, records go into zstd blocks (`FLAG_ZSTD_BLOCKS`); with
+/// `key`, both files are sealed under it (`FLAG_ENCRYPTED`). With
+/// `bloom_fp_rate`, the index gets a filter over the entries built for that
+/// rate (`FLAG_BLOOM`).
+pub fn write_cache_files<I>(
+    index_path: &Path,
+    data_path: &Path,
+    index: RkyvCacheIndex,
+    entries: I,
+    compress: bool,
+    key: Option<&CacheKey>,
+    bloom_fp_rate: Option<f64>,
+) -> Result<()>
+where
+    I: IntoIterator<Item = Result<RkyvDirEntry>>,
+{
+    let bloom = bloom_fp_rate.map(|fp_rate| (fp_rate, &[][..]));
+    write_pair(index_path, data_path, index, entries, compress, key, new_generation(), None, bloom)?;
+    remove_stale_shards(index_path, None);
+    Ok(())
+}
+
+/// `write_cache_files` for one pair of generation `generation`, with `shards` in its index
+///
+/// `bloom` is the rate of a filter over this pair's entries and those of
+/// the given shard indexes, stored in this index. Returns the data file's
+/// length and the index as written.
+#[allow(clippy::too_many_arguments)]
+fn write_pair<I>(
+    index_path: &Path,
+    data_path: &Path,
+    mut index: RkyvCacheIndex,
+    entries: I,
+    compress: bool,
+    key: Option<&CacheKey>,
+    generation: u64,
+    shards: Option<&ShardTable>,
+    bloom: Option<(f64, &[RkyvCacheIndex])>,
+) -> Result<(u64, RkyvCacheIndex)>
+where
+    I: IntoIterator<Item = Result<RkyvDirEntry>>,
+{
+    let temp_path = data_path.with_extension("dat.tmp");
+    let (data_len, blocks) = match write_data_file(&temp_path, generation, &mut index, entries, compress, key) {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+    };
+    let bloom = bloom.map(|(fp_rate, others)| {
+        let pairs = || std::iter::once(&index).chain(others);
+        let mut bloom = PathBloom::new(pairs().map(|pair| pair.offsets.len()).sum(), fp_rate);
+        for path in pairs().flat_map(|pair| pair.offsets.keys()) {
+            bloom.insert(path);
+        }
+        bloom
+    });
+    let index_temp =
+        match write_index_temp(index_path, &index, data_len, generation, blocks.as_deref(), key, shards, bloom.as_ref()) {
+            Ok(index_temp) => index_temp,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e);
+            }
+        };
+    fs::rename(&temp_path, data_path)?;
+    fs::rename(&index_temp, index_path)?;
+    Ok((data_len, index))
+}
+
+/// Whether `open` has to rewrite anything first: an interrupted save to
+/// finish, or an index of an older format version
+///
+/// Only a hint, read without the lock; the repairs check again under it.
+fn needs_repair(index_path: &Path) -> bool {
+    if index_path.with_extension("tmp").exists() {
+        return true;
+    }
+    let mut head = Vec::with_capacity(CACHE_HEADER_LEN);
+    let read = File::open(index_path).and_then(|file| file.take(CACHE_HEADER_LEN as u64).read_to_end(&mut head));
+    read.is_ok() && matches!(read_header(&head), Ok((version, _)) if version < CACHE_FORMAT_VERSION)
+}
+
+/// Finish a `write_cache_files` interrupted between its two renames
+///
+/// That leaves the new data file in place and its index in `<name>.tmp`.
+/// The temp index is only moved in when it is complete and names the data
+/// file's generation. Runs under the exclusive lock, so no save is under
+/// way and any other temp index is a leftover to delete.
+fn finish_interrupted_save(index_path: &Path, data_path: &Path) -> Result<()> {
+    let temp_path = index_path.with_extension("tmp");
+    let Ok(temp) = fs::read(&temp_path) else {
+        return Ok(());
+    };
+    let pending = match read_index_file(Arc::new(temp)) {
+        Ok((CACHE_FORMAT_VERSION, pending)) => pending,
+        // Complete but locked: left for a run that has the key
+        Err(e) if matches!(e.downcast_ref::<PTreeError>(), Some(PTreeError::CacheKey(_))) => return Ok(()),
+        _ => {
+            let _ = fs::remove_file(&temp_path);
+            return Ok(());
+        }
+    };
+
+    let mut head = [0u8; DATA_START as usize];
+    let data_generation = File::open(data_path)
+        .and_then(|mut file| file.read_exact(&mut head))
+        .ok()
+        .filter(|_| matches!(read_header(&head), Ok((CACHE_FORMAT_VERSION, _))))
+        .map(|_| u64::from_le_bytes(head[CACHE_HEADER_LEN..].try_into().expect("8-byte slice")));
+    if data_generation == Some(pending.generation) {
+        fs::rename(&temp_path, index_path)?;
+        log::info!("finished an interrupted save of {}", index_path.display());
+    } else {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Ok(())
+}
+
+/// Largest a compressed block grows before it is cut short of `BLOCK_ENTRIES`
+const MAX_BLOCK_BYTES: usize = 4 << 20;
+
+/// Write a data file of `entries`, pointing `index.offsets` at them
+///
+/// Returns the file's length and, with `compress`, the offsets of its
+/// blocks. A compressed entry's offset is its block number in the upper 32
+/// bits and its position in the decompressed block in the lower 32. Child
+/// lists over `index.spill_children` go in a spill record just before
+/// their entry, pointed at by `index.spills`.
+fn write_data_file<I>(
+    path: &Path,
+    generation: u64,
+    index: &mut RkyvCacheIndex,
+    entries: I,
+    compress: bool,
+    key: Option<&CacheKey>,
+) -> Result<(u64, Option<Vec<u64>>)>
+where
+    I: IntoIterator<Item = Result<RkyvDirEntry>>,
+{
+    let mut file = File::create(path).map_err(|source| PTreeError::CachePath { path: path.to_path_buf(), source })?;
+    write_header_flags(&mut file, pair_flags(compress, key))?;
+    file.write_all(&generation.to_le_bytes())?;
+
+    index.offsets.clear();
+    index.spills.clear();
+    let mut data_len = DATA_START;
+    let mut blocks = compress.then(Vec::new);
+    let mut block = Vec::new();
+    let mut in_block = 0;
+    for entry in entries {
+        let mut entry = entry?;
+        let spilled = take_spill(&mut entry, index.spill_children);
+        let Some(blocks) = &mut blocks else {
+            if let Some(names) = spilled {
+                index.spills.insert(entry.path.clone(), data_len);
+                data_len += write_sealed_frame(&mut file, &bincode::serialize(&names)?, key)?;
+            }
+            index.offsets.insert(entry.path.clone(), data_len);
+            data_len += write_sealed_frame(&mut file, &bincode::serialize(&entry)?, key)?;
+            continue;
+        };
+
+        if let Some(names) = spilled {
+            index.spills.insert(entry.path.clone(), ((blocks.len() as u64) << 32) | block.len() as u64);
+            write_frame(&mut block, &bincode::serialize(&names)?)?;
+        }
+        index.offsets.insert(entry.path.clone(), ((blocks.len() as u64) << 32) | block.len() as u64);
+        write_record(&mut block, &entry)?;
+        in_block += 1;
+        if in_block == BLOCK_ENTRIES || block.len() >= MAX_BLOCK_BYTES {
+            blocks.push(data_len);
+            data_len += write_sealed_frame(&mut file, &zstd::bulk::compress(&block, BLOCK_ZSTD_LEVEL)?, key)?;
+            block.clear();
+            in_block = 0;
+        }
+    }
+    if let Some(blocks) = &mut blocks {
+        if !block.is_empty() {
+            blocks.push(data_len);
+            data_len += write_sealed_frame(&mut file, &zstd::bulk::compress(&block, BLOCK_ZSTD_LEVEL)?, key)?;
+        }
+    }
+    file.sync_all()?;
+    Ok((data_len, blocks))
+}
+
+/// Take `entry`'s child names out for a spill record when there are more than `threshold` (0 = never)
+///
+/// Sets `spilled_children` to match either way.
+fn take_spill(entry: &mut RkyvDirEntry, threshold: usize) -> Option<ChildNames> {
+    if threshold == 0 || entry.children.len() <= threshold {
+        entry.spilled_children = 0;
+        return None;
+    }
+    entry.spilled_children = entry.children.len() as u32;
+    Some(std::mem::take(&mut entry.children))
+}
+
+// ============================================================================
+// Shards (by top-level directory)
+// ============================================================================
+
+/// Caches with fewer entries than this are saved as a single pair
+pub const SHARD_MIN_ENTRIES: usize = 10_000;
+
+/// Most top-level directories that get a shard of their own
+pub const MAX_NAMED_SHARDS: usize = 64;
+
+/// Shards the remaining top-level directories are hashed over
+pub const SHARD_BUCKETS: u32 = 16;
+
+/// One shard of a sharded cache, as the top-level index records it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardRef {
+    /// Generation of the shard's pair, also part of its file names
+    pub generation: u64,
+    pub entries: usize,
+    /// Length of the shard's data file, and the bytes in it no entry points at
+    pub data_len: u64,
+    pub dead_bytes: u64,
+}
+
+/// Which shard keeps the entries below each top-level directory (`FLAG_SHARDED`)
+///
+/// `root/name/...` is in `named[name]` when `name` has a shard of its own,
+/// and otherwise in one of `buckets` shards after the named ones, picked by
+/// a CRC32 of `name`. The root itself and paths outside it stay in the
+/// top-level pair.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardTable {
+    pub named: HashMap<String, u32>,
+    pub buckets: u32,
+    /// By shard id: the named shards, then the buckets
+    pub shards: Vec<ShardRef>,
+}
+
+impl ShardTable {
+    /// Shards for the entries at `paths` under `root`
+    ///
+    /// The `MAX_NAMED_SHARDS` top-level directories with the most entries get
+    /// a shard each; the rest, however many, share `SHARD_BUCKETS`.
+    pub fn plan<'a>(root: &Path, paths: impl IntoIterator<Item = &'a Path>) -> Self {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for name in paths.into_iter().filter_map(|path| top_level_name(root, path)) {
+            *counts.entry(name).or_default() += 1;
+        }
+        let mut names: Vec<(String, usize)> = counts.into_iter().collect();
+        names.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let buckets = if names.len() > MAX_NAMED_SHARDS { SHARD_BUCKETS } else { 0 };
+        names.truncate(MAX_NAMED_SHARDS);
+        let named: HashMap<String, u32> = names.into_iter().enumerate().map(|(id, (name, _))| (name, id as u32)).collect();
+        let shards = vec![ShardRef::default(); named.len() + buckets as usize];
+        ShardTable { named, buckets, shards }
+    }
+
+    /// Id of the shard that keeps `path`; None for the top-level pair
+    pub fn shard_of(&self, root: &Path, path: &Path) -> Option<usize> {
+        let name = top_level_name(root, path)?;
+        match self.named.get(&name) {
+            Some(&id) => Some(id as usize),
+            None if self.buckets > 0 => Some(self.named.len() + (crc32fast::hash(name.as_bytes()) % self.buckets) as usize),
+            None => None,
+        }
+    }
+
+    pub fn entries(&self) -> usize {
+        self.shards.iter().map(|shard| shard.entries).sum()
+    }
+
+    pub fn data_len(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.data_len).sum()
+    }
+
+    pub fn dead_bytes(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.dead_bytes).sum()
+    }
+}
+
+/// First component of `path` below `root`; None for the root itself and paths outside it
+fn top_level_name(root: &Path, path: &Path) -> Option<String> {
+    let name = path.strip_prefix(root).ok()?.components().next()?;
+    Some(name.as_os_str().to_string_lossy().into_owned())
+}
+
+/// Index and data file of shard `id` of generation `generation`, beside the top-level `index_path`
+fn shard_paths(index_path: &Path, id: usize, generation: u64) -> (PathBuf, PathBuf) {
+    let stem = index_path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = |extension: &str| index_path.with_file_name(format!("{}.shard-{}-{:016x}.{}", stem, id, generation, extension));
+    (name("idx"), name("dat"))
+}
+
+/// Shard files on disk for the cache at `cache_path`, current or not, with their temp files
+pub fn shard_files(cache_path: &Path) -> Vec<PathBuf> {
+    let prefix = format!("{}.shard-", cache_path.file_stem().unwrap_or_default().to_string_lossy());
+    let dir = cache_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Ok(listing) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = listing
+        .filter_map(|item| item.ok())
+        .map(|item| item.path())
+        .filter(|file| {
+            file.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+                && file.extension().is_some_and(|extension| ["idx", "dat", "tmp"].iter().any(|known| extension == *known))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Remove the shard files of earlier saves (or of one that failed) not in `current`
+///
+/// Failures are logged rather than returned: the save itself succeeded.
+fn remove_stale_shards(index_path: &Path, current: Option<&ShardTable>) {
+    let live: Vec<PathBuf> = current
+        .into_iter()
+        .flat_map(|table| table.shards.iter().enumerate())
+        .flat_map(|(id, shard)| {
+            let (index, data) = shard_paths(index_path, id, shard.generation);
+            [index, data]
+        })
+        .collect();
+    for file in shard_files(index_path).into_iter().filter(|file| !live.contains(file)) {
+        if let Err(e) = fs::remove_file(&file) {
+            log::warn!("cannot remove stale cache shard {}: {}", file.display(), e);
+        }
+    }
+}
+
+/// Write a sharded cache: a pair for each shard of `table`, then the top-level pair
+///
+/// `parts` holds the top-level pair's entries, then each shard's, in id
+/// order. Shard pairs get new file names, so the previous save stays whole
+/// until the top-level index naming the new ones is renamed in; only then
+/// are the old shards removed. Callers hold the exclusive `CacheLock`.
+///
+/// The filter `bloom_fp_rate` asks for covers every shard's entries and
+/// goes in the top-level index.
+#[allow(clippy::too_many_arguments)]
+pub fn write_sharded_cache_files<P, I>(
+    index_path: &Path,
+    data_path: &Path,
+    index: RkyvCacheIndex,
+    mut table: ShardTable,
+    parts: P,
+    compress: bool,
+    key: Option<&CacheKey>,
+    bloom_fp_rate: Option<f64>,
+) -> Result<()>
+where
+    P: IntoIterator<Item = I>,
+    I: IntoIterator<Item = Result<RkyvDirEntry>>,
+{
+    let mut parts = parts.into_iter();
+    let own = parts.next().ok_or_else(|| anyhow::anyhow!("no entries for the top-level pair"))?;
+    let mut written = Vec::new();
+    let mut shard_indexes = Vec::new();
+    let write_shards = || -> Result<()> {
+        for (id, part) in parts.enumerate() {
+            let shard = table.shards.get_mut(id).ok_or_else(|| anyhow::anyhow!("more parts than shards"))?;
+            let generation = new_generation();
+            let (shard_index, shard_data) = shard_paths(index_path, id, generation);
+            written.extend([shard_index.clone(), shard_data.clone()]);
+            let (data_len, saved) =
+                write_pair(&shard_index, &shard_data, index.shard_index(), part, compress, key, generation, None, None)?;
+            *shard = ShardRef { generation, entries: saved.offsets.len(), data_len, dead_bytes: 0 };
+            if bloom_fp_rate.is_some() {
+                shard_indexes.push(saved);
+            }
+        }
+        Ok(())
+    };
+    let result = write_shards().and_then(|()| {
+        let bloom = bloom_fp_rate.map(|fp_rate| (fp_rate, shard_indexes.as_slice()));
+        write_pair(index_path, data_path, index, own, compress, key, new_generation(), Some(&table), bloom)
+    });
+    if let Err(e) = result {
+        for file in written {
+            let _ = fs::remove_file(file);
+        }
+        return Err(e);
+    }
+    remove_stale_shards(index_path, Some(&table));
+    Ok(())
+}
+
 /// Serializable directory entry (serde-based for compatibility)
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RkyvDirEntry {
@@ -16,18 +872,350 @@ pub struct RkyvDirEntry {
     pub name: String,
     pub modified: DateTime<Utc>,
     pub content_hash: u64, // NEW FIELD - Merkle tree hash
-    pub children: Vec<String>,
+    pub children: ChildNames,
     pub symlink_target: Option<PathBuf>,
     pub is_hidden: bool,
     pub is_dir: bool,
+    pub size: u64,
+    pub skipped_children: bool,
+    pub is_mount: bool,
+    pub access_error: Option<String>,
+    pub evicted: bool,
+    pub file_count: u32,
+    pub dir_count: u32,
+    pub total_size: u64,
+    pub size_is_lower_bound: bool,
+    /// Children whose names are in this entry's spill record rather than
+    /// `children`, which is then empty (`FLAG_SPILLED_CHILDREN`); 0 for none
+    pub spilled_children: u32,
+}
+
+/// Entry layout of format version 8, read only to migrate it
+#[derive(Deserialize)]
+struct RkyvDirEntryV8 {
+    path: PathBuf,
+    name: String,
+    modified: DateTime<Utc>,
+    content_hash: u64,
+    children: ChildNames,
+    symlink_target: Option<PathBuf>,
+    is_hidden: bool,
+    is_dir: bool,
+    size: u64,
+    skipped_children: bool,
+    is_mount: bool,
+    access_error: Option<String>,
+    evicted: bool,
+    file_count: u32,
+    dir_count: u32,
+    total_size: u64,
+    size_is_lower_bound: bool,
+}
+
+impl From<RkyvDirEntryV8> for RkyvDirEntry {
+    fn from(entry: RkyvDirEntryV8) -> Self {
+        RkyvDirEntry {
+            path: entry.path,
+            name: entry.name,
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children,
+            symlink_target: entry.symlink_target,
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error,
+            evicted: entry.evicted,
+            file_count: entry.file_count,
+            dir_count: entry.dir_count,
+            total_size: entry.total_size,
+            size_is_lower_bound: entry.size_is_lower_bound,
+            spilled_children: 0,
+        }
+    }
+}
+
+/// Entry layout of format version 7, read only to migrate it
+#[derive(Deserialize)]
+struct RkyvDirEntryV7 {
+    path: PathBuf,
+    name: String,
+    modified: DateTime<Utc>,
+    content_hash: u64,
+    children: Vec<String>,
+    symlink_target: Option<PathBuf>,
+    is_hidden: bool,
+    is_dir: bool,
+    size: u64,
+    skipped_children: bool,
+    is_mount: bool,
+    access_error: Option<String>,
+    evicted: bool,
+    file_count: u32,
+    dir_count: u32,
+    total_size: u64,
+    size_is_lower_bound: bool,
+}
+
+impl From<RkyvDirEntryV7> for RkyvDirEntry {
+    fn from(entry: RkyvDirEntryV7) -> Self {
+        RkyvDirEntry {
+            path: entry.path,
+            name: entry.name,
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children.into(),
+            symlink_target: entry.symlink_target,
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error,
+            evicted: entry.evicted,
+            file_count: entry.file_count,
+            dir_count: entry.dir_count,
+            total_size: entry.total_size,
+            size_is_lower_bound: entry.size_is_lower_bound,
+            spilled_children: 0,
+        }
+    }
+}
+
+/// Entry layout of format version 6, read only to migrate it
+#[derive(Deserialize)]
+struct RkyvDirEntryV6 {
+    path: PathBuf,
+    name: String,
+    modified: DateTime<Utc>,
+    content_hash: u64,
+    children: Vec<String>,
+    symlink_target: Option<PathBuf>,
+    is_hidden: bool,
+    is_dir: bool,
+    size: u64,
+    skipped_children: bool,
+    is_mount: bool,
+    access_error: Option<String>,
+    evicted: bool,
+    file_count: u32,
+    dir_count: u32,
+}
+
+impl From<RkyvDirEntry> for crate::cache::DirEntry {
+    fn from(entry: RkyvDirEntry) -> Self {
+        crate::cache::DirEntry {
+            path: entry.path,
+            name: entry.name,
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children,
+            symlink_target: entry.symlink_target,
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error,
+            evicted: entry.evicted,
+            file_count: entry.file_count,
+            dir_count: entry.dir_count,
+            total_size: entry.total_size,
+            size_is_lower_bound: entry.size_is_lower_bound,
+            generation: 0,
+            spilled_children: entry.spilled_children,
+        }
+    }
+}
+
+impl From<&crate::cache::DirEntry> for RkyvDirEntry {
+    fn from(entry: &crate::cache::DirEntry) -> Self {
+        RkyvDirEntry {
+            path: entry.path.clone(),
+            name: entry.name.clone(),
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children.clone(),
+            symlink_target: entry.symlink_target.clone(),
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error.clone(),
+            evicted: entry.evicted,
+            file_count: entry.file_count,
+            dir_count: entry.dir_count,
+            total_size: entry.total_size,
+            size_is_lower_bound: entry.size_is_lower_bound,
+            spilled_children: 0,
+        }
+    }
+}
+
+impl From<RkyvDirEntryV6> for RkyvDirEntry {
+    fn from(entry: RkyvDirEntryV6) -> Self {
+        RkyvDirEntry {
+            path: entry.path,
+            name: entry.name,
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children.into(),
+            symlink_target: entry.symlink_target,
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error,
+            evicted: entry.evicted,
+            file_count: entry.file_count,
+            dir_count: entry.dir_count,
+            // Directory totals were not kept before version 7: unknown until the next scan
+            total_size: if entry.is_dir { 0 } else { entry.size },
+            size_is_lower_bound: entry.is_dir,
+            spilled_children: 0,
+        }
+    }
+}
+
+/// Entry layout of format version 5, read only to migrate it
+#[derive(Deserialize)]
+struct RkyvDirEntryV5 {
+    path: PathBuf,
+    name: String,
+    modified: DateTime<Utc>,
+    content_hash: u64,
+    children: Vec<String>,
+    symlink_target: Option<PathBuf>,
+    is_hidden: bool,
+    is_dir: bool,
+    size: u64,
+    skipped_children: bool,
+    is_mount: bool,
+    access_error: Option<String>,
+    evicted: bool,
+}
+
+impl From<RkyvDirEntryV5> for RkyvDirEntry {
+    fn from(entry: RkyvDirEntryV5) -> Self {
+        RkyvDirEntryV6 {
+            path: entry.path,
+            name: entry.name,
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children,
+            symlink_target: entry.symlink_target,
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error,
+            evicted: entry.evicted,
+            // Not counted before version 6; the next scan fills them in
+            file_count: 0,
+            dir_count: 0,
+        }
+        .into()
+    }
+}
+
+/// Entry layout of format versions 1 to 4, read only to migrate it
+#[derive(Deserialize)]
+struct RkyvDirEntryV4 {
+    path: PathBuf,
+    name: String,
+    modified: DateTime<Utc>,
+    content_hash: u64,
+    children: Vec<String>,
+    symlink_target: Option<PathBuf>,
+    is_hidden: bool,
+    is_dir: bool,
+    size: u64,
+    skipped_children: bool,
+    is_mount: bool,
+    access_error: Option<String>,
+}
+
+impl From<RkyvDirEntryV4> for RkyvDirEntry {
+    fn from(entry: RkyvDirEntryV4) -> Self {
+        RkyvDirEntry {
+            path: entry.path,
+            name: entry.name,
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children.into(),
+            symlink_target: entry.symlink_target,
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            size: entry.size,
+            skipped_children: entry.skipped_children,
+            is_mount: entry.is_mount,
+            access_error: entry.access_error,
+            evicted: false,
+            file_count: 0,
+            dir_count: 0,
+            total_size: if entry.is_dir { 0 } else { entry.size },
+            size_is_lower_bound: entry.is_dir,
+            spilled_children: 0,
+        }
+    }
+}
+
+/// Entry layout of format version 0, read only to migrate it
+#[derive(Deserialize)]
+struct RkyvDirEntryV0 {
+    path: PathBuf,
+    name: String,
+    modified: DateTime<Utc>,
+    content_hash: u64,
+    children: Vec<String>,
+    symlink_target: Option<PathBuf>,
+    is_hidden: bool,
+    is_dir: bool,
+}
+
+impl From<RkyvDirEntryV0> for RkyvDirEntry {
+    fn from(entry: RkyvDirEntryV0) -> Self {
+        RkyvDirEntry {
+            path: entry.path,
+            name: entry.name,
+            modified: entry.modified,
+            content_hash: entry.content_hash,
+            children: entry.children.into(),
+            symlink_target: entry.symlink_target,
+            is_hidden: entry.is_hidden,
+            is_dir: entry.is_dir,
+            // Not recorded before version 1; the next scan fills them in
+            size: 0,
+            skipped_children: false,
+            is_mount: false,
+            access_error: None,
+            evicted: false,
+            file_count: 0,
+            dir_count: 0,
+            total_size: 0,
+            size_is_lower_bound: entry.is_dir,
+            spilled_children: 0,
+        }
+    }
 }
 
 /// Serializable cache index (serde-based for compatibility)
 /// Maps paths → byte offsets, serialized separately for O(1) access
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RkyvCacheIndex {
-    /// Offsets mapping for lazy single-node O(1) access
-    pub offsets: HashMap<PathBuf, u64>,
+    /// Offsets mapping for lazy single-node access; sorted when large (`OffsetIndex`)
+    pub offsets: OffsetIndex,
+    /// Offsets of the spill records, by directory (`FLAG_SPILLED_CHILDREN`)
+    #[serde(skip)]
+    pub spills: OffsetIndex,
+    /// Directories with more children than this spill them on save; 0 never does
+    #[serde(skip)]
+    pub spill_children: usize,
     pub last_scan: DateTime<Utc>,
     pub root: PathBuf,
     pub last_scanned_root: PathBuf,
@@ -39,7 +1227,9 @@ pub struct RkyvCacheIndex {
 impl RkyvCacheIndex {
     pub fn new() -> Self {
         RkyvCacheIndex {
-            offsets: HashMap::new(),
+            offsets: OffsetIndex::new(),
+            spills: OffsetIndex::new(),
+            spill_children: DEFAULT_SPILL_CHILDREN,
             last_scan: Utc::now(),
             root: PathBuf::new(),
             last_scanned_root: PathBuf::new(),
@@ -48,6 +1238,17 @@ impl RkyvCacheIndex {
             skip_stats: HashMap::new(),
         }
     }
+
+    /// An empty index for one of this index's shards, spilling at the same threshold
+    fn shard_index(&self) -> Self {
+        RkyvCacheIndex { spill_children: self.spill_children, ..RkyvCacheIndex::new() }
+    }
+}
+
+impl Default for RkyvCacheIndex {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Memory-mapped cache using rkyv for zero-copy single-node O(1) access
@@ -62,147 +1263,888 @@ pub struct RkyvMmapCache {
     pub index: RkyvCacheIndex,
     mmap: Option<Mmap>,
     data_path: PathBuf,
+    /// Generation of the data file, written into it by `append_entry` when it starts one
+    generation: u64,
+    /// Offsets of the compressed blocks (`FLAG_ZSTD_BLOCKS`); None for plain records
+    blocks: Option<Vec<u64>>,
+    /// Recently decompressed blocks by number, most recent last
+    block_cache: Mutex<VecDeque<(usize, Arc<Vec<u8>>)>>,
+    /// Key the pair is sealed with (`FLAG_ENCRYPTED`)
+    key: Option<CacheKey>,
+    /// Shards of a sharded cache (`FLAG_SHARDED`); `index.offsets` then holds
+    /// only the entries of the top-level pair
+    pub shards: Option<ShardTable>,
+    /// The shard pairs by id, each opened on first use
+    shard_pairs: Vec<OnceLock<RkyvMmapCache>>,
+    /// Top-level index path, which the cache's `CacheLock` is named after
+    lock_path: PathBuf,
+    /// Filter over every cached path (`FLAG_BLOOM`), consulted before the offsets
+    pub bloom: Option<PathBloom>,
 }
 
 impl RkyvMmapCache {
     /// Load cache from rkyv-serialized index and data files
-    /// Index is fully deserialized (small), data is mmap'd (large, lazy access)
+    /// Both are mmap'd: the index is decoded but for a large one's sorted offsets, read in place
+    /// (`FLAG_ARCHIVED_OFFSETS`); the data file is read lazily
+    ///
+    /// Files from an older format version are migrated in place first.
+    /// Loading holds a shared `CacheLock`, repairs an exclusive one.
     pub fn open(index_path: &std::path::Path, data_path: &std::path::Path) -> Result<Self> {
-        fs::create_dir_all(index_path.parent().unwrap())?;
-
-        // Load index (small, safe to fully deserialize using serde)
-         let index = if index_path.exists() {
-             let mut file = File::open(index_path)?;
-             let mut data = Vec::new();
-             file.read_to_end(&mut data)?;
-        
-             // Deserialize index using serde bincode
-             match bincode::deserialize::<RkyvCacheIndex>(&data) {
-                 Ok(idx) => idx,
-                 Err(_) => RkyvCacheIndex::new(),
+        create_cache_dir(index_path)?;
+        if needs_repair(index_path) {
+            let _lock = CacheLock::exclusive(index_path)?;
+            finish_interrupted_save(index_path, data_path)?;
+            if needs_repair(index_path) {
+                migrate(index_path, data_path)?;
+            }
+        }
+        let lock = CacheLock::shared(index_path)?;
+
+        // Map the index: a large one's sorted offsets are read from the mapping in place
+         let loaded = if index_path.exists() {
+             let file = map_index(index_path)?;
+
+             // Rewritten by an older ptree since the check above
+             if read_header((*file).as_ref())?.0 < CACHE_FORMAT_VERSION {
+                 drop(lock);
+                 return Self::open(index_path, data_path);
              }
+
+             Some(read_index_file(file)?.1)
          } else {
-             RkyvCacheIndex::new()
+             None
          };
 
         // Map data file (large, accessed lazily via O(1) offsets)
-        let mmap = if data_path.exists() {
-            let file = File::open(data_path)?;
-            Some(unsafe { Mmap::map(&file)? })
-        } else {
-            None
+        let mmap = match (&loaded, data_path.exists()) {
+            (Some(loaded), true) => Some(Self::map_data(data_path, loaded)?),
+            // Nothing points into a data file without an index
+            _ => None,
         };
 
+        drop(lock);
+
+        let (index, generation, blocks, key, shards, bloom) = match loaded {
+            Some(loaded) => (loaded.index, loaded.generation, loaded.blocks, loaded.key, loaded.shards, loaded.bloom),
+            None => (RkyvCacheIndex::new(), new_generation(), None, None, None, None),
+        };
         Ok(RkyvMmapCache {
             index,
             mmap,
             data_path: data_path.to_path_buf(),
+            generation,
+            blocks,
+            block_cache: Mutex::new(VecDeque::with_capacity(BLOCK_CACHE_SIZE)),
+            key,
+            shard_pairs: shards.iter().flat_map(|table| &table.shards).map(|_| OnceLock::new()).collect(),
+            shards,
+            lock_path: index_path.to_path_buf(),
+            bloom,
+        })
+    }
+
+    /// Open shard `id` of the top-level index at `lock_path`, checking it is the one the table names
+    ///
+    /// Shard pairs are only ever written whole under new names, so there is
+    /// nothing to repair or migrate; a missing one means the top-level index
+    /// belongs to a save whose shards are gone.
+    fn open_shard(lock_path: &Path, id: usize, shard: &ShardRef) -> Result<Self> {
+        let (index_path, data_path) = shard_paths(lock_path, id, shard.generation);
+        let _lock = CacheLock::shared(lock_path)?;
+        let bytes = map_index(&index_path).map_err(|e| {
+            PTreeError::CacheCorrupt(format!("shard {} ({}) cannot be read: {:#}", id, index_path.display(), e))
+        })?;
+        let loaded = match read_index_file(bytes)? {
+            (CACHE_FORMAT_VERSION, loaded) if loaded.generation == shard.generation => loaded,
+            _ => {
+                return Err(PTreeError::CacheCorrupt(format!(
+                    "{} is not the shard {} its top-level index names",
+                    index_path.display(),
+                    id
+                ))
+                .into())
+            }
+        };
+        if !data_path.is_file() {
+            return Err(PTreeError::CacheCorrupt(format!("shard {} has no data file {}", id, data_path.display())).into());
+        }
+        let mmap = Self::map_data(&data_path, &loaded)?;
+        Ok(RkyvMmapCache {
+            index: loaded.index,
+            mmap: Some(mmap),
+            data_path,
+            generation: loaded.generation,
+            blocks: loaded.blocks,
+            block_cache: Mutex::new(VecDeque::with_capacity(BLOCK_CACHE_SIZE)),
+            key: loaded.key,
+            shards: None,
+            shard_pairs: Vec::new(),
+            lock_path: lock_path.to_path_buf(),
+            bloom: None,
         })
     }
 
+    /// Shard `id`, opened now if this is its first use
+    fn shard(&self, id: usize) -> Result<&RkyvMmapCache> {
+        let cell = self
+            .shard_pairs
+            .get(id)
+            .ok_or_else(|| PTreeError::CacheCorrupt(format!("shard {} is not in the index", id)))?;
+        if let Some(shard) = cell.get() {
+            return Ok(shard);
+        }
+        let table = self.shards.as_ref().expect("shard pairs come with a table");
+        let shard = Self::open_shard(&self.lock_path, id, &table.shards[id])?;
+        Ok(cell.get_or_init(|| shard))
+    }
+
+    /// Id of the shard that keeps `path`; None for this pair
+    fn shard_of(&self, path: &Path) -> Option<usize> {
+        self.shards.as_ref()?.shard_of(&self.index.root, path)
+    }
+
+    /// Number of shards (0 for a cache saved as a single pair)
+    pub fn shard_count(&self) -> usize {
+        self.shard_pairs.len()
+    }
+
+    /// Whether the records are stored in zstd blocks
+    pub fn is_compressed(&self) -> bool {
+        self.blocks.is_some()
+    }
+
+    /// Key the pair is sealed with; None for a plain one
+    pub fn key(&self) -> Option<&CacheKey> {
+        self.key.as_ref()
+    }
+
+    /// Plain bytes of a record or block frame
+    fn unseal<'a>(&self, bytes: &'a [u8], offset: u64) -> Result<std::borrow::Cow<'a, [u8]>> {
+        match &self.key {
+            Some(key) => key
+                .unseal(bytes)
+                .map(std::borrow::Cow::Owned)
+                .ok_or_else(|| PTreeError::CacheCorrupt(format!("record at offset {} does not decrypt", offset)).into()),
+            None => Ok(std::borrow::Cow::Borrowed(bytes)),
+        }
+    }
+
+    /// Map the data file, checking it is the one `loaded` was saved with
+    fn map_data(data_path: &Path, loaded: &IndexFile) -> Result<Mmap> {
+        let file = File::open(data_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        // Offsets are only meaningful against a data file of the index's
+        // version; any other header (even a newer one) is a mismatched pair
+        if !matches!(read_header(&mmap), Ok((CACHE_FORMAT_VERSION, _))) {
+            return Err(PTreeError::CacheCorrupt(format!(
+                "{} is not format version {} like its index",
+                data_path.display(),
+                CACHE_FORMAT_VERSION
+            ))
+            .into());
+        }
+        if read_flags(&mmap)? != pair_flags(loaded.blocks.is_some(), loaded.key.as_ref()) {
+            return Err(PTreeError::CacheCorrupt(format!(
+                "{} is not compressed or encrypted the way its index says",
+                data_path.display()
+            ))
+            .into());
+        }
+        // Longer is an append whose index was never saved (`append_entries`);
+        // nothing points past `data_len`. Blocks are only ever written whole.
+        let len = mmap.len() as u64;
+        if len < loaded.data_len || (loaded.blocks.is_some() && len != loaded.data_len) {
+            return Err(PTreeError::CacheCorrupt(format!(
+                "{} is {} bytes, its index expects {}",
+                data_path.display(),
+                mmap.len(),
+                loaded.data_len
+            ))
+            .into());
+        }
+        let generation = mmap
+            .get(CACHE_HEADER_LEN..DATA_START as usize)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().expect("8-byte slice")));
+        if generation != Some(loaded.generation) {
+            return Err(PTreeError::CacheCorrupt(format!(
+                "{} is from another save than its index (expected generation {})",
+                data_path.display(),
+                loaded.generation
+            ))
+            .into());
+        }
+        Ok(mmap)
+    }
+
     /// O(1) lookup: get single directory entry via mmap offset
      /// Deserializes from mmap-backed binary data
+     ///
+     /// A record that fails its checksum is an error (`PTreeError::CacheCorrupt`),
+     /// never a silently missing entry. A path the bloom filter rules out is
+     /// a miss without an offset lookup, or opening its shard. Spilled child
+     /// names are read back into `children`.
      pub fn get_entry(&self, path: &std::path::Path) -> Result<Option<RkyvDirEntry>> {
+         let Some(mut entry) = self.get_entry_shallow(path)? else {
+             return Ok(None);
+         };
+         if entry.spilled_children > 0 {
+             let children = self.get_spilled_children(path)?;
+             if children.len() != entry.spilled_children as usize {
+                 return Err(PTreeError::CacheCorrupt(format!(
+                     "spill record of {} holds {} names, its entry {}",
+                     path.display(),
+                     children.len(),
+                     entry.spilled_children
+                 ))
+                 .into());
+             }
+             entry.children = children;
+             entry.spilled_children = 0;
+         }
+         Ok(Some(entry))
+     }
+
+     /// `get_entry` without reading a spill record: a directory that spilled
+     /// its names comes back with `children` empty and `spilled_children` set
+     pub fn get_entry_shallow(&self, path: &Path) -> Result<Option<RkyvDirEntry>> {
+         if self.bloom.as_ref().is_some_and(|bloom| !bloom.may_contain(path)) {
+             return Ok(None);
+         }
+         if let Some(id) = self.shard_of(path) {
+             return self.shard(id)?.get_entry_shallow(path);
+         }
          let offset = match self.index.offsets.get(path) {
-             Some(&off) => off,
+             Some(off) => off,
              None => return Ok(None),
          };
-    
+         let entry = self
+             .read_at(offset, |bytes| decode_entry(bytes, CACHE_FORMAT_VERSION))
+             .with_context(|| format!("record {:#x}", offset))?;
+         Ok(Some(entry))
+     }
+
+     /// The child names the directory at `path` spilled (`RkyvDirEntry::spilled_children`)
+     pub fn get_spilled_children(&self, path: &Path) -> Result<ChildNames> {
+         if let Some(id) = self.shard_of(path) {
+             return self.shard(id)?.get_spilled_children(path);
+         }
+         let offset = self
+             .index
+             .spills
+             .get(path)
+             .ok_or_else(|| PTreeError::CacheCorrupt(format!("{} has no spill record", path.display())))?;
+         let decode = |bytes: &[u8]| -> Result<ChildNames> {
+             bincode::deserialize(bytes)
+                 .map_err(|e| PTreeError::CacheCorrupt(format!("spill record does not decode: {}", e)).into())
+         };
+         self.read_at(offset, decode).with_context(|| format!("spill record {:#x}", offset))
+     }
+
+     /// Decode the record at `offset` (block and position when compressed) of this pair's data file
+     fn read_at<T>(&self, offset: u64, decode: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
          let mmap = self
              .mmap
              .as_ref()
              .ok_or_else(|| anyhow::anyhow!("No mmap loaded"))?;
-    
-         let data_slice = &mmap[offset as usize..];
-    
-         // Read length prefix
-         if data_slice.len() < 4 {
-             return Ok(None);
+
+         if self.blocks.is_some() {
+             let block = self.block((offset >> 32) as usize, mmap)?;
+             return decode(read_frame(&block, offset & 0xFFFF_FFFF, 0, true)?);
          }
-    
-         let len = u32::from_le_bytes([data_slice[0], data_slice[1], data_slice[2], data_slice[3]])
-             as usize;
-    
-         if data_slice.len() < 4 + len {
-             return Ok(None);
+
+         // Deserialize straight from the mmap'd region
+         let bytes = self.unseal(read_record(mmap, offset, CACHE_FORMAT_VERSION)?, offset)?;
+         decode(&bytes)
+     }
+
+     /// Decompressed block `number`, from the small LRU or the mapping
+     fn block(&self, number: usize, mmap: &Mmap) -> Result<Arc<Vec<u8>>> {
+         let mut cached = self.block_cache.lock();
+         if let Some(position) = cached.iter().position(|(cached, _)| *cached == number) {
+             let hit = cached.remove(position).expect("position is in bounds");
+             let block = Arc::clone(&hit.1);
+             cached.push_back(hit);
+             return Ok(block);
          }
-    
-         // Deserialize entry from mmap'd region
-         let entry: RkyvDirEntry = bincode::deserialize(&data_slice[4..4 + len])?;
-         Ok(Some(entry))
+
+         let offset = self
+             .blocks
+             .as_ref()
+             .and_then(|blocks| blocks.get(number))
+             .copied()
+             .ok_or_else(|| PTreeError::CacheCorrupt(format!("block {} is not in the index", number)))?;
+         let compressed = self.unseal(read_record(mmap, offset, CACHE_FORMAT_VERSION)?, offset)?;
+         let block = zstd::stream::decode_all(&*compressed)
+             .map_err(|e| PTreeError::CacheCorrupt(format!("block at offset {} does not decompress: {}", offset, e)))?;
+
+         let block = Arc::new(block);
+         if cached.len() == BLOCK_CACHE_SIZE {
+             cached.pop_front();
+         }
+         cached.push_back((number, Arc::clone(&block)));
+         Ok(block)
+     }
+
+     /// Check every record the index points at (`--verify-cache`)
+     ///
+     /// Returns the offset and path of each record that is out of bounds,
+     /// fails its checksum, or doesn't decode, in offset order (the top-level
+     /// pair's, then each shard's). A shard that doesn't open fails the call.
+     pub fn corrupt_records(&self) -> Result<Vec<(u64, PathBuf)>> {
+         let mut corrupt: Vec<(u64, PathBuf)> = self
+             .index
+             .offsets
+             .iter()
+             .filter(|(path, _)| self.get_entry(path).is_err())
+             .map(|(path, offset)| (offset, path.to_path_buf()))
+             .collect();
+         corrupt.sort();
+         for id in 0..self.shard_count() {
+             corrupt.extend(self.shard(id)?.corrupt_records()?);
+         }
+         Ok(corrupt)
      }
     
      /// Get all entries (full deserialization - only for batch operations or output)
      /// Used for tree building where we need owned data
-     pub fn get_all(&self) -> Result<HashMap<PathBuf, crate::cache::DirEntry>> {
-         let mut entries = HashMap::new();
-     
-         for path in self.index.offsets.keys() {
+     ///
+     /// Reads in offset order, so each compressed block is decompressed once.
+     pub fn get_all(&self) -> Result<FastHashMap<PathBuf, crate::cache::DirEntry>> {
+         let mut entries = self.own_entries(self.index.offsets.iter())?;
+         for id in 0..self.shard_count() {
+             let shard = self.shard(id)?;
+             entries.extend(shard.own_entries(shard.index.offsets.iter())?);
+         }
+         Ok(entries)
+     }
+
+     /// Entries at and below `dir` (`--subtree`)
+     ///
+     /// Of a sharded cache only the shard `dir` is in is opened, unless `dir`
+     /// is the root, whose subtree is all of them. A sorted index finds the
+     /// subtree's paths by binary search rather than a pass over every path.
+     pub fn get_subtree(&self, dir: &Path) -> Result<FastHashMap<PathBuf, crate::cache::DirEntry>> {
+         let mut entries = self.own_entries(self.index.offsets.range(dir))?;
+         let shards: Vec<usize> = match self.shard_of(dir) {
+             Some(id) => vec![id],
+             None if self.index.root.starts_with(dir) => (0..self.shard_count()).collect(),
+             None => Vec::new(),
+         };
+         for id in shards {
+             let shard = self.shard(id)?;
+             entries.extend(shard.own_entries(shard.index.offsets.range(dir))?);
+         }
+         Ok(entries)
+     }
+
+     /// Entries of this pair (not its shards) at `paths`, drawn from its index, read in offset order
+     fn own_entries<'a>(
+         &self,
+         paths: impl Iterator<Item = (&'a Path, u64)>,
+     ) -> Result<FastHashMap<PathBuf, crate::cache::DirEntry>> {
+         let mut paths: Vec<(&Path, u64)> = paths.collect();
+         paths.sort_unstable_by_key(|&(_, offset)| offset);
+
+         let mut entries = FastHashMap::with_capacity_and_hasher(paths.len(), Default::default());
+         for (path, _) in paths {
              if let Some(entry) = self.get_entry(path)? {
-                 entries.insert(
-                     entry.path.clone(),
-                     crate::cache::DirEntry {
-                         path: entry.path,
-                         name: entry.name,
-                         modified: entry.modified,
-                         content_hash: entry.content_hash,
-                         children: entry.children,
-                         symlink_target: entry.symlink_target,
-                         is_hidden: entry.is_hidden,
-                         is_dir: entry.is_dir,
-                     },
-                 );
+                 entries.insert(entry.path.clone(), entry.into());
              }
          }
-     
          Ok(entries)
      }
 
     /// Write bincode-serialized entry to data file
      /// Returns the offset where entry was written for index tracking
+     ///
+     /// Compressed caches are only ever rewritten whole (`write_cache_files`).
      pub fn append_entry(&self, entry: &RkyvDirEntry) -> Result<u64> {
+         if self.is_compressed() {
+             anyhow::bail!("cannot append to the compressed cache {}", self.data_path.display());
+         }
+         let _lock = CacheLock::exclusive(&self.lock_path)?;
          let mut data_file = std::fs::OpenOptions::new()
              .create(true)
              .append(true)
              .open(&self.data_path)?;
-    
-         let serialized = bincode::serialize(entry)?;
-         let len = serialized.len() as u32;
+         if data_file.metadata()?.len() == 0 {
+             write_header_flags(&mut data_file, pair_flags(false, self.key.as_ref()))?;
+             data_file.write_all(&self.generation.to_le_bytes())?;
+         }
     
          let offset = data_file.seek(SeekFrom::End(0))?;
-    
-         data_file.write_all(&len.to_le_bytes())?;
-         data_file.write_all(&serialized)?;
+         write_sealed_frame(&mut data_file, &bincode::serialize(entry)?, self.key.as_ref())?;
          data_file.sync_all()?;
     
          Ok(offset)
      }
     
-     /// Save index to disk (bincode serialized)
-     pub fn save_index(&self, path: &std::path::Path) -> Result<()> {
-         let data = bincode::serialize(&self.index)?;
-         let temp_path = path.with_extension("tmp");
-    
-         let mut file = File::create(&temp_path)?;
-         file.write_all(&data)?;
-         file.sync_all()?;
-    
+     /// Append `entries` to the data file, pointing the index at them; returns the bytes written
+     ///
+     /// One fsync for the whole batch. Until `save_index` the index on disk
+     /// still describes the file without them: `open` accepts a data file that
+     /// grew past its index, the tail counting as dead bytes. The paths are
+     /// added to the bloom filter; long child lists spill as on a full save.
+     pub fn append_entries<I>(&mut self, entries: I) -> Result<u64>
+     where
+         I: IntoIterator<Item = RkyvDirEntry>,
+     {
+         if self.is_compressed() {
+             anyhow::bail!("cannot append to the compressed cache {}", self.data_path.display());
+         }
+         let _lock = CacheLock::exclusive(&self.lock_path)?;
+         let mut data_file = std::fs::OpenOptions::new()
+             .create(true)
+             .append(true)
+             .open(&self.data_path)?;
+         let start = data_file.metadata()?.len();
+         let mut writer = std::io::BufWriter::new(&mut data_file);
+         let mut offset = start;
+         if start == 0 {
+             write_header_flags(&mut writer, pair_flags(false, self.key.as_ref()))?;
+             writer.write_all(&self.generation.to_le_bytes())?;
+             offset = DATA_START;
+         }
+         for mut entry in entries {
+             if let Some(bloom) = &mut self.bloom {
+                 bloom.insert(&entry.path);
+             }
+             match take_spill(&mut entry, self.index.spill_children) {
+                 Some(names) => {
+                     self.index.spills.insert(entry.path.clone(), offset);
+                     offset += write_sealed_frame(&mut writer, &bincode::serialize(&names)?, self.key.as_ref())?;
+                 }
+                 None => {
+                     self.index.spills.remove(&entry.path);
+                 }
+             }
+             self.index.offsets.insert(entry.path.clone(), offset);
+             offset += write_sealed_frame(&mut writer, &bincode::serialize(&entry)?, self.key.as_ref())?;
+         }
+         writer.flush()?;
+         drop(writer);
+         data_file.sync_all()?;
+         Ok(offset - start)
+     }
+
+     /// Save index to disk (bincode serialized), recording the data file's current length
+     ///
+     /// A compressed cache keeps its block table, a sharded one its shard
+     /// table, and the bloom filter is written as it stands.
+     pub fn save_index(&mut self, path: &std::path::Path) -> Result<()> {
+         let _lock = CacheLock::exclusive(&self.lock_path)?;
+         // Windows can't replace a file that is still mapped
+         #[cfg(windows)]
+         self.index.offsets.detach();
+         let data_len = fs::metadata(&self.data_path).map(|metadata| metadata.len()).unwrap_or(0);
+         let temp_path = write_index_temp(
+             path,
+             &self.index,
+             data_len,
+             self.generation,
+             self.blocks.as_deref(),
+             self.key.as_ref(),
+             self.shards.as_ref(),
+             self.bloom.as_ref(),
+         )?;
          fs::rename(&temp_path, path)?;
          Ok(())
      }
 
+     /// Bring the saved cache up to `entries`: append those in `changed` or
+     /// unknown to the index, and drop saved paths `is_cached` rejects
+     ///
+     /// Each entry goes to the pair that keeps it. A shard is only opened and
+     /// rewritten when one of its entries changed or its entry count differs
+     /// from `entries` (something in it was removed); the others are left as
+     /// they are. Returns how many entries were appended and the bytes
+     /// written, shard indexes included; call `save_index` after.
+     pub fn append_changes<'a, I>(
+         &mut self,
+         entries: I,
+         changed: &HashSet<PathBuf>,
+         is_cached: impl Fn(&Path) -> bool,
+     ) -> Result<(usize, u64)>
+     where
+         I: IntoIterator<Item = &'a crate::cache::DirEntry>,
+     {
+         let mut parts: Vec<Vec<&crate::cache::DirEntry>> = vec![Vec::new(); self.shard_count() + 1];
+         for entry in entries {
+             parts[self.shard_of(&entry.path).map_or(0, |id| id + 1)].push(entry);
+         }
+         let mut parts = parts.into_iter();
+         let (mut appended, mut written) = self.append_part(parts.next().unwrap_or_default(), changed, &is_cached)?;
+
+         for (id, part) in parts.enumerate() {
+             let recorded = self.shards.as_ref().map_or(0, |table| table.shards[id].entries);
+             if part.len() == recorded && !part.iter().any(|entry| changed.contains(&entry.path)) {
+                 continue;
+             }
+             self.shard(id)?;
+             let shard = self.shard_pairs[id].get_mut().expect("opened above");
+             // The top-level filter covers the shard's paths too
+             shard.bloom = self.bloom.take();
+             let appended_to_shard = shard.append_part(part, changed, &is_cached);
+             self.bloom = shard.bloom.take();
+             let (count, bytes) = appended_to_shard?;
+             let (index_path, _) = shard_paths(&self.lock_path, id, shard.generation);
+             shard.save_index(&index_path)?;
+             let updated = ShardRef {
+                 generation: shard.generation,
+                 entries: shard.len(),
+                 data_len: fs::metadata(&shard.data_path)?.len(),
+                 dead_bytes: shard.dead_bytes(),
+             };
+             appended += count;
+             written += bytes + fs::metadata(&index_path)?.len();
+             if let Some(table) = &mut self.shards {
+                 table.shards[id] = updated;
+             }
+         }
+         Ok((appended, written))
+     }
+
+     /// `append_changes` for this pair alone, given the entries it keeps
+     fn append_part(
+         &mut self,
+         part: Vec<&crate::cache::DirEntry>,
+         changed: &HashSet<PathBuf>,
+         is_cached: &impl Fn(&Path) -> bool,
+     ) -> Result<(usize, u64)> {
+         let appended: Vec<RkyvDirEntry> = part
+             .into_iter()
+             .filter(|entry| changed.contains(&entry.path) || !self.index.offsets.contains_key(&entry.path))
+             .map(RkyvDirEntry::from)
+             .collect();
+         self.index.offsets.retain(|path, _| is_cached(path));
+         self.index.spills.retain(|path, _| is_cached(path));
+         let count = appended.len();
+         Ok((count, self.append_entries(appended)?))
+     }
+
+    /// Entries in the cache, shards included
     pub fn len(&self) -> usize {
-        self.index.offsets.len()
+        self.index.offsets.len() + self.shards.as_ref().map_or(0, ShardTable::entries)
     }
 
     pub fn is_empty(&self) -> bool {
-        self.index.offsets.is_empty()
+        self.len() == 0
+    }
+
+    /// Every path in the offset tables, this pair's then each shard's
+    pub fn paths(&self) -> Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = self.index.offsets.keys().map(Path::to_path_buf).collect();
+        for id in 0..self.shard_count() {
+            paths.extend(self.shard(id)?.index.offsets.keys().map(Path::to_path_buf));
+        }
+        Ok(paths)
+    }
+
+    /// Bytes of the data file (as mapped at `open`) that no index offset points at
+    ///
+    /// `append_entry` never rewrites in place, so every update of an entry
+    /// leaves its previous record behind as dead bytes. Shards count with the
+    /// dead bytes their table entry recorded at the last save.
+    pub fn dead_bytes(&self) -> u64 {
+        let shards = self.shards.as_ref().map_or(0, ShardTable::dead_bytes);
+        // Compressed files are written whole and never carry dead records
+        let Some(mmap) = self.mmap.as_ref().filter(|_| !self.is_compressed()) else {
+            return shards;
+        };
+        unreferenced_bytes(mmap, self.live_offsets(), CACHE_FORMAT_VERSION) + shards
+    }
+
+    /// Offsets of every record this pair's index points at, spill records included
+    fn live_offsets(&self) -> impl Iterator<Item = u64> + '_ {
+        self.index.offsets.values().chain(self.index.spills.values())
+    }
+
+    /// Whether dead bytes make up more than `COMPACT_DEAD_RATIO` of the data
+    /// file, or of any shard's
+    pub fn needs_compaction(&self) -> bool {
+        let over = |dead: u64, len: u64| len > 0 && dead as f64 > len as f64 * COMPACT_DEAD_RATIO;
+        let own = self.mmap.as_ref().filter(|_| !self.is_compressed()).map_or(0, |mmap| {
+            unreferenced_bytes(mmap, self.live_offsets(), CACHE_FORMAT_VERSION)
+        });
+        over(own, self.mmap.as_ref().map_or(0, |mmap| mmap.len() as u64))
+            || self.shards.iter().flat_map(|table| &table.shards).any(|shard| over(shard.dead_bytes, shard.data_len))
+    }
+}
+
+/// Bytes of `data` outside the records `offsets` point at
+///
+/// Only length prefixes are read; a prefix that falls outside `data`
+/// leaves its record uncounted.
+fn unreferenced_bytes(data: &[u8], offsets: impl Iterator<Item = u64>, version: u32) -> u64 {
+    let prefix = if version >= 2 { 8 } else { 4 };
+    let live: u64 = offsets
+        .filter_map(|offset| {
+            let start = usize::try_from(offset).ok()?;
+            let len = data.get(start..start.checked_add(4)?)?;
+            Some(prefix + u32::from_le_bytes(len.try_into().expect("4-byte slice")) as u64)
+        })
+        .sum();
+    (data.len() as u64).saturating_sub(data_start(version) + live)
+}
+
+// ============================================================================
+// Index-Only Reads (ptree cache-info)
+// ============================================================================
+
+/// A cache pair's index and what can be told about its data file without decoding records
+#[derive(Debug)]
+pub struct IndexSummary {
+    /// Format version the index was written in (may be older than this build's)
+    pub version: u32,
+    pub index: RkyvCacheIndex,
+    /// Whether the data file holds zstd blocks
+    pub compressed: bool,
+    /// Whether the pair is sealed (`--encrypt-cache`)
+    pub encrypted: bool,
+    /// Data file bytes no offset points at; None when the data file is not the index's pair
+    pub dead_bytes: Option<u64>,
+    /// Records the index points at that are out of bounds or fail their
+    /// checksum (compressed blocks, for a compressed cache), shards
+    /// included; None when the data file is not the index's pair
+    pub corrupt_records: Option<usize>,
+    /// Shards of a sharded cache, with their sizes as of the last save
+    pub shards: Option<ShardTable>,
+    /// Filter over every cached path; None for a cache saved before filters
+    pub bloom: Option<PathBloom>,
+    /// Time taken to map and decode the index, as `RkyvMmapCache::open` does
+    pub open_time: std::time::Duration,
+}
+
+/// Read the index of a cache pair under a shared lock, leaving both files as they are
+///
+/// Unlike `RkyvMmapCache::open` this never finishes a save, migrates, or
+/// compacts, and no record is decoded: the data file is only mapped to add
+/// up the length prefixes of live records and check their checksums.
+pub fn read_index_summary(index_path: &Path, data_path: &Path) -> Result<IndexSummary> {
+    let _lock = CacheLock::shared(index_path)?;
+    let started = std::time::Instant::now();
+    let (version, loaded) = read_index_file(map_index(index_path)?)?;
+    let open_time = started.elapsed();
+
+    let mmap = File::open(data_path).ok().and_then(|file| unsafe { Mmap::map(&file) }.ok());
+    let paired = mmap.filter(|mmap| {
+        matches!(read_header(mmap), Ok((found, _)) if found == version)
+            && (version < 2 || mmap.len() as u64 >= loaded.data_len)
+    });
+    let dead_bytes = paired.as_ref().map(|mmap| match loaded.blocks {
+        // Written whole, so never carrying dead records
+        Some(_) => 0,
+        None => unreferenced_bytes(mmap, loaded.index.offsets.values().chain(loaded.index.spills.values()), version),
+    });
+    let corrupt_records = paired.map(|mmap| {
+        let shards = loaded.shards.iter().flat_map(|table| table.shards.iter().enumerate());
+        corrupt_frames(&mmap, &loaded, version) + shards.map(|(id, shard)| corrupt_shard_frames(index_path, id, shard)).sum::<usize>()
+    });
+    Ok(IndexSummary {
+        version,
+        compressed: loaded.blocks.is_some(),
+        encrypted: loaded.key.is_some(),
+        index: loaded.index,
+        dead_bytes,
+        corrupt_records,
+        shards: loaded.shards,
+        bloom: loaded.bloom,
+        open_time,
+    })
+}
+
+/// Frames `loaded` points into `data` that are out of bounds or fail their checksum
+///
+/// Records and spill records, or the compressed blocks holding them.
+fn corrupt_frames(data: &[u8], loaded: &IndexFile, version: u32) -> usize {
+    match &loaded.blocks {
+        Some(blocks) => blocks.iter().filter(|&&block| read_frame(data, block, data_start(version), true).is_err()).count(),
+        None => {
+            let offsets = loaded.index.offsets.values().chain(loaded.index.spills.values());
+            offsets.filter(|&offset| read_record(data, offset, version).is_err()).count()
+        }
+    }
+}
+
+/// `corrupt_frames` of shard `id`; a shard pair that can't be read counts every entry it holds
+fn corrupt_shard_frames(lock_path: &Path, id: usize, shard: &ShardRef) -> usize {
+    let (index_path, data_path) = shard_paths(lock_path, id, shard.generation);
+    let count = || -> Result<usize> {
+        let (version, loaded) = read_index_file(map_index(&index_path)?)?;
+        let data = unsafe { Mmap::map(&File::open(&data_path)?)? };
+        Ok(corrupt_frames(&data, &loaded, version))
+    };
+    count().unwrap_or(shard.entries)
+}
+
+// ============================================================================
+// Compaction
+// ============================================================================
+
+/// Share of dead bytes in the data file above which `DiskCache::open` compacts it
+pub const COMPACT_DEAD_RATIO: f64 = 0.3;
+
+/// Outcome of `compact_cache_files`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactStats {
+    /// Records kept
+    pub entries: usize,
+    /// Data file size before and after
+    pub before: u64,
+    pub after: u64,
+}
+
+impl CompactStats {
+    pub fn reclaimed(&self) -> u64 {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+/// Rewrite the data file with only the records the index points at
+///
+/// Goes through `write_cache_files`, so an interrupted compaction leaves
+/// either the old pair or the new one, never a mix. Records are copied in
+/// offset order and get new offsets; a damaged record fails the whole
+/// compaction (`PTreeError::CacheCorrupt`) with the old pair kept.
+///
+/// A sharded cache only has the shards with dead bytes rewritten, each as a
+/// new pair, and then the top-level pair to name them; sizes add up its data
+/// file and every shard's.
+pub fn compact_cache_files(index_path: &Path, data_path: &Path) -> Result<CompactStats> {
+    let _lock = CacheLock::exclusive(index_path)?;
+    let cache = RkyvMmapCache::open(index_path, data_path)?;
+    let before = cache.mmap.as_ref().map_or(0, |mmap| mmap.len() as u64);
+    let compressed = cache.is_compressed();
+    let key = cache.key.clone();
+    let count = cache.len();
+    // Compaction drops removed paths from the filter by rebuilding it
+    let bloom_fp_rate = cache.bloom.as_ref().map(PathBloom::fp_rate);
+
+    let Some(mut table) = cache.shards.clone() else {
+        let entries = live_entries(&cache);
+        // Windows can't replace a file that is still mapped
+        let RkyvMmapCache { index, mmap, .. } = cache;
+        drop(mmap);
+        write_cache_files(index_path, data_path, index, entries, compressed, key.as_ref(), bloom_fp_rate)?;
+        let after = fs::metadata(data_path)?.len();
+        return Ok(CompactStats { entries: count, before, after });
+    };
+
+    let before = before + table.data_len();
+    let mut written = Vec::new();
+    let mut shard_indexes = Vec::new();
+    let mut compact_shards = || -> Result<()> {
+        for id in 0..table.shards.len() {
+            let shard = cache.shard(id)?;
+            if table.shards[id].dead_bytes == 0 {
+                if bloom_fp_rate.is_some() {
+                    shard_indexes.push(shard.index.clone());
+                }
+                continue;
+            }
+            let generation = new_generation();
+            let (shard_index, shard_data) = shard_paths(index_path, id, generation);
+            written.extend([shard_index.clone(), shard_data.clone()]);
+            let (data_len, saved) = write_pair(
+                &shard_index,
+                &shard_data,
+                cache.index.shard_index(),
+                live_entries(shard),
+                compressed,
+                key.as_ref(),
+                generation,
+                None,
+                None,
+            )?;
+            table.shards[id] = ShardRef { generation, entries: saved.offsets.len(), data_len, dead_bytes: 0 };
+            shard_indexes.push(saved);
+        }
+        Ok(())
+    };
+    let compacted = compact_shards().map(|()| live_entries(&cache));
+    let RkyvMmapCache { index, mmap, shard_pairs, .. } = cache;
+    drop((mmap, shard_pairs));
+    let result = compacted.and_then(|entries| {
+        write_pair(
+            index_path,
+            data_path,
+            index,
+            entries,
+            compressed,
+            key.as_ref(),
+            new_generation(),
+            Some(&table),
+            bloom_fp_rate.map(|rate| (rate, &shard_indexes[..])),
+        )
+    });
+    if let Err(e) = result {
+        for file in written {
+            let _ = fs::remove_file(file);
+        }
+        return Err(e);
     }
+    remove_stale_shards(index_path, Some(&table));
+
+    let after = fs::metadata(data_path)?.len() + table.data_len();
+    Ok(CompactStats { entries: count, before, after })
+}
+
+/// The records `cache`'s own index points at (not its shards'), in offset order
+fn live_entries(cache: &RkyvMmapCache) -> Vec<Result<RkyvDirEntry>> {
+    let mut paths: Vec<(&Path, u64)> = cache.index.offsets.iter().collect();
+    paths.sort_by_key(|&(_, offset)| offset);
+    paths
+        .into_iter()
+        .map(|(path, _)| {
+            cache
+                .get_entry(path)?
+                .ok_or_else(|| anyhow::anyhow!("{} vanished from the index", path.display()))
+        })
+        .collect()
+}
+
+// ============================================================================
+// Format Migration
+// ============================================================================
+
+/// Upgrade an index/data pair written in an older format, in place
+///
+/// The old records are decoded one by one and written out again through
+/// `write_cache_files`, so the old pair stays untouched until the new one
+/// is complete. A pair that doesn't decode as its version is as good as
+/// corrupt. No older version had compression or encryption, so the result
+/// has neither.
+fn migrate(index_path: &Path, data_path: &Path) -> Result<()> {
+    let index_file = fs::read(index_path)?;
+    let version = read_header(&index_file)?.0;
+    let corrupt = |e: anyhow::Error| -> anyhow::Error {
+        PTreeError::CacheCorrupt(format!("cannot migrate from format version {}: {:#}", version, e)).into()
+    };
+    let (_, old) = read_index_file(Arc::new(index_file)).map_err(corrupt)?;
+    let data = if data_path.exists() { fs::read(data_path)? } else { Vec::new() };
+    let count = old.index.offsets.len();
+
+    let entries = old
+        .index
+        .offsets
+        .values()
+        .map(|offset| read_record(&data, offset, version).and_then(|bytes| decode_entry(bytes, version)).map_err(corrupt))
+        .collect::<Vec<_>>();
+    write_cache_files(index_path, data_path, old.index, entries, false, None, Some(DEFAULT_BLOOM_FP_RATE))?;
+    log::info!(
+        "migrated cache {} from format version {} to {} ({} entries)",
+        index_path.display(),
+        version,
+        CACHE_FORMAT_VERSION,
+        count
+    );
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::offsets::SORTED_OFFSETS_MIN_ENTRIES;
     use std::env;
 
     #[test]
@@ -212,9 +2154,20 @@ mod tests {
             name: "test".to_string(),
             modified: Utc::now(),
             content_hash: 12345u64,
-            children: vec!["child1".to_string(), "child2".to_string()],
+            children: vec!["child1".to_string(), "child2".to_string()].into(),
             symlink_target: None,
             is_hidden: false,
+            is_dir: true,
+            size: 0,
+            skipped_children: false,
+            is_mount: false,
+            access_error: None,
+            evicted: false,
+            file_count: 0,
+            dir_count: 0,
+            total_size: 0,
+            size_is_lower_bound: false,
+            spilled_children: 0,
         };
 
         let serialized = bincode::serialize(&entry)?;
@@ -240,4 +2193,482 @@ mod tests {
         let _ = fs::remove_dir_all(&temp_dir);
         Ok(())
     }
+
+    #[test]
+    fn test_headerless_files_are_migrated() -> Result<()> {
+        #[derive(Serialize)]
+        struct EntryV0 {
+            path: PathBuf,
+            name: String,
+            modified: DateTime<Utc>,
+            content_hash: u64,
+            children: Vec<String>,
+            symlink_target: Option<PathBuf>,
+            is_hidden: bool,
+            is_dir: bool,
+        }
+
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_migrate_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        // A pair as written before the header existed
+        let mut index = RkyvCacheIndex::new();
+        let mut data = Vec::new();
+        for name in ["root", "child"] {
+            let entry = EntryV0 {
+                path: PathBuf::from("/").join(name),
+                name: name.to_string(),
+                modified: Utc::now(),
+                content_hash: 7,
+                children: vec!["x".to_string()],
+                symlink_target: None,
+                is_hidden: false,
+                is_dir: true,
+            };
+            let serialized = bincode::serialize(&entry)?;
+            index.offsets.insert(entry.path.clone(), data.len() as u64);
+            data.extend_from_slice(&(serialized.len() as u32).to_le_bytes());
+            data.extend_from_slice(&serialized);
+        }
+        fs::write(&index_path, bincode::serialize(&index)?)?;
+        fs::write(&data_path, &data)?;
+
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!(cache.len(), 2);
+        let child = cache.get_entry(Path::new("/child"))?.unwrap();
+        assert_eq!((child.name.as_str(), child.content_hash, child.size), ("child", 7, 0));
+        for file in [&index_path, &data_path] {
+            assert_eq!(read_header(&fs::read(file)?)?.0, CACHE_FORMAT_VERSION);
+        }
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_newer_format_version_is_rejected() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_newer_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        let mut header = Vec::new();
+        write_header(&mut header)?;
+        header[4..8].copy_from_slice(&(CACHE_FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&index_path, &header)?;
+        fs::write(&data_path, &header)?;
+
+        let err = RkyvMmapCache::open(&index_path, &data_path).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<PTreeError>(),
+            Some(PTreeError::CacheVersion { found, supported }) if *found == CACHE_FORMAT_VERSION + 1 && *supported == CACHE_FORMAT_VERSION
+        ));
+        // DiskCache::open passes it on instead of starting over with an empty cache
+        assert!(crate::cache::DiskCache::open(&temp_dir.join("ptree.dat")).is_err());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    fn entry(name: &str) -> RkyvDirEntry {
+        RkyvDirEntry {
+            path: PathBuf::from("/").join(name),
+            name: name.to_string(),
+            modified: Utc::now(),
+            content_hash: 0,
+            children: ChildNames::new(),
+            symlink_target: None,
+            is_hidden: false,
+            is_dir: true,
+            size: 0,
+            skipped_children: false,
+            is_mount: false,
+            access_error: None,
+            evicted: false,
+            file_count: 0,
+            dir_count: 0,
+            total_size: 0,
+            size_is_lower_bound: false,
+            spilled_children: 0,
+        }
+    }
+
+    #[test]
+    fn test_fields_added_since_version_5_decode_as_unknown() -> Result<()> {
+        let mut counted = entry("counted");
+        (counted.file_count, counted.dir_count, counted.total_size) = (12, 3, 4096);
+        let bytes = bincode::serialize(&counted)?;
+        let current = decode_entry(&bytes, CACHE_FORMAT_VERSION)?;
+        assert_eq!((current.file_count, current.dir_count), (12, 3));
+        assert_eq!((current.total_size, current.size_is_lower_bound), (4096, false));
+
+        // Each version's records end where the next one's fields begin
+        let v6 = decode_entry(&bytes[..bytes.len() - 9], 6)?;
+        assert_eq!((v6.file_count, v6.total_size, v6.size_is_lower_bound), (12, 0, true));
+        let v5 = decode_entry(&bytes[..bytes.len() - 17], 5)?;
+        assert_eq!((v5.name.as_str(), v5.file_count, v5.dir_count), ("counted", 0, 0));
+        assert!(v5.size_is_lower_bound);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_save_keeps_previous_pair() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_atomic_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), [Ok(entry("a"))], false, None, None)?;
+        let entries = [Ok(entry("b")), Err(anyhow::anyhow!("injected"))];
+        assert!(write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), entries, false, None, None).is_err());
+
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get_entry(Path::new("/a"))?.is_some());
+        assert!(!data_path.with_extension("dat.tmp").exists());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatched_pair_is_rejected() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_generation_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        // Same content, so both data files have the same length
+        let a = entry("a");
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), [Ok(a.clone())], false, None, None)?;
+        let first_data = fs::read(&data_path)?;
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), [Ok(a)], false, None, None)?;
+        assert_eq!(fs::read(&data_path)?.len(), first_data.len());
+        fs::write(&data_path, &first_data)?;
+
+        let err = RkyvMmapCache::open(&index_path, &data_path).err().unwrap();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CacheCorrupt(_))));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_without_its_index_leaves_the_pair_readable() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_append_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), [Ok(entry("a"))], false, None, None)?;
+
+        // Interrupted before `save_index`: the appended record is dead weight
+        let written = RkyvMmapCache::open(&index_path, &data_path)?.append_entries([entry("b")])?;
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.dead_bytes(), written);
+
+        let mut cache = cache;
+        cache.append_entries([entry("c")])?;
+        cache.save_index(&index_path)?;
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get_entry(Path::new("/c"))?.is_some());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_index_is_saved_sorted_and_answers_the_same() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_sorted_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        let entries: Vec<RkyvDirEntry> =
+            (0..SORTED_OFFSETS_MIN_ENTRIES).map(|i| entry(&format!("d{}/e{}", i % 100, i))).collect();
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), entries.iter().cloned().map(Ok), false, None, None)?;
+        // Plain, so archived and read in place rather than decoded
+        let sorted_flags = read_flags(&fs::read(&index_path)?)? & (FLAG_SORTED_OFFSETS | FLAG_ARCHIVED_OFFSETS);
+        assert_eq!(sorted_flags, FLAG_ARCHIVED_OFFSETS);
+
+        let mut cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(cache.index.offsets.is_sorted() && cache.index.offsets.is_archived());
+        assert_eq!(cache.len(), entries.len());
+        for entry in entries.iter().step_by(997) {
+            assert_eq!(cache.get_entry(&entry.path)?.unwrap().name, entry.name);
+        }
+        assert!(cache.get_entry(Path::new("/d7/missing"))?.is_none());
+        assert_eq!(cache.get_subtree(Path::new("/d42"))?.len(), SORTED_OFFSETS_MIN_ENTRIES / 100);
+
+        // A delta save merges its appends into the sorted section
+        cache.append_entries([entry("d42/new")])?;
+        cache.index.offsets.remove(Path::new("/d42/e42"));
+        cache.save_index(&index_path)?;
+        let mut cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(cache.index.offsets.is_archived());
+        // Saved again unchanged, straight from the archive it was read from
+        cache.save_index(&index_path)?;
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(cache.get_entry(Path::new("/d42/new"))?.is_some());
+        assert!(cache.get_entry(Path::new("/d42/e42"))?.is_none());
+        assert_eq!(cache.get_subtree(Path::new("/d42"))?.len(), SORTED_OFFSETS_MIN_ENTRIES / 100);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shards_cap_named_directories_and_bucket_the_rest() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_shards_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        // Top-level directory i holds i + 1 entries below it
+        let tops = MAX_NAMED_SHARDS + 6;
+        let mut entries = vec![RkyvDirEntry { path: PathBuf::from("/"), ..entry("") }];
+        for i in 0..tops {
+            entries.push(entry(&format!("top{}", i)));
+            entries.extend((0..i).map(|j| entry(&format!("top{}/c{}", i, j))));
+        }
+        let root = Path::new("/");
+        let table = ShardTable::plan(root, entries.iter().map(|entry| entry.path.as_path()));
+        assert_eq!((table.named.len(), table.buckets), (MAX_NAMED_SHARDS, SHARD_BUCKETS));
+        assert!(table.named.contains_key("top69") && !table.named.contains_key("top5"));
+        assert!(table.shard_of(root, Path::new("/top5/c1")).unwrap() >= MAX_NAMED_SHARDS);
+        assert_eq!(table.shard_of(root, root), None);
+
+        let mut parts: Vec<Vec<Result<RkyvDirEntry>>> = (0..=table.shards.len()).map(|_| Vec::new()).collect();
+        for entry in &entries {
+            parts[table.shard_of(root, &entry.path).map_or(0, |id| id + 1)].push(Ok(entry.clone()));
+        }
+        let index = RkyvCacheIndex { root: root.to_path_buf(), ..RkyvCacheIndex::new() };
+        write_sharded_cache_files(&index_path, &data_path, index, table.clone(), parts, false, None, None)?;
+        assert_eq!(read_flags(&fs::read(&index_path)?)? & FLAG_SHARDED, FLAG_SHARDED);
+
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!((cache.len(), cache.shard_count()), (entries.len(), table.shards.len()));
+        let subtree = cache.get_subtree(Path::new("/top40"))?;
+        assert_eq!(subtree.len(), 41);
+        assert_eq!(cache.shard_pairs.iter().filter(|shard| shard.get().is_some()).count(), 1);
+        assert_eq!(cache.get_entry(Path::new("/top3/c2"))?.unwrap().name, "top3/c2");
+        assert_eq!(cache.get_all()?.len(), entries.len());
+        drop(cache);
+
+        // A save replacing the shards removes the previous ones
+        let files = shard_files(&data_path);
+        assert_eq!(files.len(), 2 * table.shards.len());
+        let part_of = |entry: &RkyvDirEntry| table.shard_of(root, &entry.path).map_or(0, |id| id + 1);
+        let parts = (0..=table.shards.len())
+            .map(|id| entries.iter().filter(move |entry| part_of(entry) == id).cloned().map(Ok));
+        let index = RkyvCacheIndex { root: root.to_path_buf(), ..RkyvCacheIndex::new() };
+        write_sharded_cache_files(&index_path, &data_path, index, table.clone(), parts, false, None, None)?;
+        let rewritten = shard_files(&data_path);
+        assert_eq!(rewritten.len(), files.len());
+        assert!(rewritten.iter().all(|file| !files.contains(file)));
+
+        // A shard gone missing is a damaged cache, not a missing entry
+        fs::remove_file(&rewritten[0])?;
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        let err = cache.get_all().err().unwrap();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CacheCorrupt(_))));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_drops_stale_records() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_compact_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        // Every rescan appends a new record for the same entry
+        let mut cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        for size in 0..10 {
+            let offset = cache.append_entry(&RkyvDirEntry { size, ..entry("a") })?;
+            cache.index.offsets.insert(PathBuf::from("/a"), offset);
+        }
+        let offset = cache.append_entry(&entry("b"))?;
+        cache.index.offsets.insert(PathBuf::from("/b"), offset);
+        cache.save_index(&index_path)?;
+
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(cache.needs_compaction());
+        let dead = cache.dead_bytes();
+        drop(cache);
+
+        let stats = compact_cache_files(&index_path, &data_path)?;
+        assert_eq!((stats.entries, stats.reclaimed()), (2, dead));
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!(cache.dead_bytes(), 0);
+        assert!(!cache.needs_compaction());
+        assert_eq!(cache.get_entry(Path::new("/a"))?.unwrap().size, 9);
+        assert!(cache.get_entry(Path::new("/b"))?.is_some());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_save_is_finished_on_open() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_interrupted_{}", std::process::id()));
+        let other_dir = temp_dir.join("other");
+        fs::create_dir_all(&other_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), [Ok(entry("old"))], false, None, None)?;
+        // The state after the data rename: new data, new index still in the temp file
+        let (new_index, new_data) = (other_dir.join("ptree.idx"), other_dir.join("ptree.dat"));
+        write_cache_files(&new_index, &new_data, RkyvCacheIndex::new(), [Ok(entry("new"))], false, None, None)?;
+        fs::copy(&new_data, &data_path)?;
+        fs::copy(&new_index, index_path.with_extension("tmp"))?;
+
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(cache.get_entry(Path::new("/new"))?.is_some());
+        assert!(!index_path.with_extension("tmp").exists());
+
+        // A temp index from another save is a leftover
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), [Ok(entry("old"))], false, None, None)?;
+        fs::copy(&new_index, index_path.with_extension("tmp"))?;
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(cache.get_entry(Path::new("/old"))?.is_some());
+        assert!(!index_path.with_extension("tmp").exists());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_blocks_round_trip() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_zstd_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+
+        let count = BLOCK_ENTRIES * 2 + 10;
+        let entries = (0..count).map(|i| Ok(RkyvDirEntry { size: i as u64, ..entry(&format!("dir{}", i)) }));
+        write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), entries, true, None, None)?;
+        for file in [&index_path, &data_path] {
+            assert_eq!(read_flags(&fs::read(file)?)?, FLAG_ZSTD_BLOCKS);
+        }
+
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert!(cache.is_compressed());
+        assert_eq!(cache.blocks.as_ref().map(Vec::len), Some(3));
+        for i in [count - 1, 0, BLOCK_ENTRIES, 7] {
+            assert_eq!(cache.get_entry(Path::new(&format!("/dir{}", i)))?.unwrap().size, i as u64);
+        }
+        assert_eq!(cache.get_all()?.len(), count);
+        assert!(cache.corrupt_records()?.is_empty());
+        assert_eq!(cache.dead_bytes(), 0);
+        assert!(cache.append_entry(&entry("late")).is_err());
+        drop(cache);
+
+        // A data file without the flag is never read as plain records
+        let mut data = fs::read(&data_path)?;
+        data[8..12].copy_from_slice(&0u32.to_le_bytes());
+        fs::write(&data_path, &data)?;
+        let err = RkyvMmapCache::open(&index_path, &data_path).err().unwrap();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CacheCorrupt(_))));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_child_lists_spill_and_read_back() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_spill_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+        let names: ChildNames = (0..5).map(|i| format!("f{}", i)).collect();
+        let big = RkyvDirEntry { children: names.clone(), ..entry("big") };
+        let small = RkyvDirEntry { children: vec!["f0"].into(), ..entry("small") };
+        let index = RkyvCacheIndex { spill_children: 3, ..RkyvCacheIndex::new() };
+
+        for compress in [false, true] {
+            let entries = [Ok(big.clone()), Ok(small.clone())];
+            write_cache_files(&index_path, &data_path, index.clone(), entries, compress, None, None)?;
+            let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+            assert_eq!(cache.index.spill_children, 3);
+            assert_eq!(cache.index.spills.len(), 1);
+
+            let shallow = cache.get_entry_shallow(Path::new("/big"))?.unwrap();
+            assert!(shallow.children.is_empty());
+            assert_eq!(shallow.spilled_children, 5);
+            let full = cache.get_entry(Path::new("/big"))?.unwrap();
+            assert_eq!((full.children, full.spilled_children), (names.clone(), 0));
+            assert_eq!(cache.get_entry(Path::new("/small"))?.unwrap().children, ["f0"]);
+            // Spill records are live, not dead bytes
+            assert_eq!(cache.dead_bytes(), 0);
+            assert!(cache.corrupt_records()?.is_empty());
+        }
+
+        // Appending a shorter list drops the spill record; compaction reclaims it
+        write_cache_files(&index_path, &data_path, index.clone(), [Ok(big.clone())], false, None, None)?;
+        let mut cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        cache.append_entries([RkyvDirEntry { children: vec!["f0", "f1"].into(), ..entry("big") }])?;
+        assert!(cache.index.spills.is_empty());
+        cache.save_index(&index_path)?;
+        drop(cache);
+        let stats = compact_cache_files(&index_path, &data_path)?;
+        assert!(stats.reclaimed() > 0);
+        let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+        assert_eq!(cache.get_entry(Path::new("/big"))?.unwrap().children, ["f0", "f1"]);
+        assert_eq!(cache.dead_bytes(), 0);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_encrypted_pair_round_trip() -> Result<()> {
+        let temp_dir = env::temp_dir().join(format!("ptree_rkyv_encrypted_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let index_path = temp_dir.join("ptree.idx");
+        let data_path = temp_dir.join("ptree.dat");
+        env::set_var(crate::crypt::PASSPHRASE_ENV, "correct horse");
+        let key = CacheKey::create()?;
+        let plaintext = |file: &Path| -> Result<bool> { Ok(fs::read(file)?.windows(6).any(|w| w == b"secret")) };
+
+        for compress in [false, true] {
+            let entries = (0..10).map(|i| Ok(entry(&format!("secret{}", i))));
+            write_cache_files(&index_path, &data_path, RkyvCacheIndex::new(), entries, compress, Some(&key), None)?;
+            for file in [&index_path, &data_path] {
+                assert_eq!(read_flags(&fs::read(file)?)?, pair_flags(compress, Some(&key)));
+                assert!(!plaintext(file)?, "{} holds plain names", file.display());
+            }
+
+            let mut cache = RkyvMmapCache::open(&index_path, &data_path)?;
+            assert_eq!(cache.key().map(CacheKey::source), Some(key.source()));
+            assert_eq!(cache.get_entry(Path::new("/secret3"))?.unwrap().name, "secret3");
+            assert!(cache.corrupt_records()?.is_empty());
+            if !compress {
+                cache.append_entries([entry("secret_late")])?;
+                cache.save_index(&index_path)?;
+                assert!(!plaintext(&data_path)?);
+                let cache = RkyvMmapCache::open(&index_path, &data_path)?;
+                assert_eq!(cache.get_all()?.len(), 11);
+            }
+        }
+
+        // A data file whose flags drop the encryption is never read as plain records
+        let mut data = fs::read(&data_path)?;
+        data[8..12].copy_from_slice(&FLAG_ZSTD_BLOCKS.to_le_bytes());
+        fs::write(&data_path, &data)?;
+        let err = RkyvMmapCache::open(&index_path, &data_path).err().unwrap();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::CacheCorrupt(_))));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
 }
@@ -0,0 +1,529 @@
+#[cfg(feature = "sqlite")]
+use ptree_core::CacheBackend;
+#[cfg(feature = "sqlite")]
+use crate::cache::{create_cache_dir, format_count, CacheLoad};
+use crate::cache::DiskCache;
+use anyhow::Result;
+use ptree_core::PTreeError;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// SQLite Cache Backend (--backend sqlite)
+// ============================================================================
+
+/// Every SQLite database file starts with this
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Schema version kept in the `meta` table; a database with a newer one is refused
+pub const SQLITE_SCHEMA_VERSION: u32 = 1;
+
+/// Database file of the SQLite backend for a cache path (`ptree.dat` -> `ptree.db`)
+pub fn sqlite_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_extension("db")
+}
+
+/// Whether the cache at `cache_path` was saved by the SQLite backend, going by its file header
+pub fn is_sqlite_cache(cache_path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    File::open(sqlite_path(cache_path))
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| &header == SQLITE_HEADER)
+}
+
+#[cfg(feature = "sqlite")]
+pub use database::SqliteCache;
+
+#[cfg(feature = "sqlite")]
+mod database {
+    use super::SQLITE_SCHEMA_VERSION;
+    use crate::backend::BackendMeta;
+    use crate::cache::{DirEntry, DiskCache};
+    use crate::children::ChildNames;
+    use anyhow::Result;
+    use chrono::DateTime;
+    use ptree_core::PTreeError;
+    use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS dirs (
+            path TEXT PRIMARY KEY,
+            parent TEXT,
+            name TEXT NOT NULL,
+            mtime INTEGER NOT NULL,
+            mtime_ns INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            hidden INTEGER NOT NULL,
+            symlink_target TEXT,
+            is_dir INTEGER NOT NULL,
+            content_hash INTEGER NOT NULL,
+            children TEXT NOT NULL,
+            flags INTEGER NOT NULL,
+            access_error TEXT,
+            file_count INTEGER NOT NULL,
+            dir_count INTEGER NOT NULL,
+            total_size INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS dirs_parent ON dirs (parent);
+    ";
+
+    const COLUMNS: &str = "path, name, mtime, mtime_ns, size, hidden, symlink_target, is_dir, content_hash, \
+                           children, flags, access_error, file_count, dir_count, total_size";
+
+    const INSERT: &str = "INSERT OR REPLACE INTO dirs (path, parent, name, mtime, mtime_ns, size, hidden, \
+                          symlink_target, is_dir, content_hash, children, flags, access_error, file_count, \
+                          dir_count, total_size) \
+                          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)";
+
+    // `flags` bits for the booleans SQL queries rarely need
+    const SKIPPED_CHILDREN: i64 = 1;
+    const IS_MOUNT: i64 = 2;
+    const EVICTED: i64 = 4;
+    const SIZE_IS_LOWER_BOUND: i64 = 8;
+
+    /// A cache database: one `dirs` row per entry, plus the scan metadata in `meta`
+    ///
+    /// Paths are stored as text (lossily for names that aren't UTF-8), with
+    /// `parent` indexed so a directory's rows can be listed without a scan.
+    /// `children` holds the child names NUL-separated, as `ChildNames::packed` does.
+    pub struct SqliteCache {
+        conn: Connection,
+    }
+
+    impl SqliteCache {
+        /// Open or create the database at `db_path`
+        ///
+        /// A file SQLite can't read is `CacheCorrupt`; one written with a
+        /// newer schema is `CacheVersion`.
+        pub fn open(db_path: &Path) -> Result<Self> {
+            let corrupt = |e: rusqlite::Error| PTreeError::CacheCorrupt(format!("{}: {}", db_path.display(), e));
+            let conn = Connection::open(db_path).map_err(corrupt)?;
+            conn.execute_batch(SCHEMA).map_err(corrupt)?;
+            let cache = SqliteCache { conn };
+            BackendMeta::check_version(|key| cache.meta_value(key), SQLITE_SCHEMA_VERSION)?;
+            Ok(cache)
+        }
+
+        fn meta_value(&self, key: &str) -> Result<Option<String>> {
+            Ok(self
+                .conn
+                .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| row.get(0))
+                .optional()?)
+        }
+
+        /// The saved scan metadata, or None for a database nothing was saved to yet
+        pub(crate) fn meta(&self) -> Result<Option<BackendMeta>> {
+            BackendMeta::read(|key| self.meta_value(key))
+        }
+
+        /// Number of saved entries
+        pub fn len(&self) -> Result<usize> {
+            Ok(self.conn.query_row("SELECT COUNT(*) FROM dirs", [], |row| row.get::<_, i64>(0))? as usize)
+        }
+
+        pub fn is_empty(&self) -> Result<bool> {
+            Ok(self.len()? == 0)
+        }
+
+        /// The saved entry for `path`
+        pub fn get_entry(&self, path: &Path) -> Result<Option<DirEntry>> {
+            let sql = format!("SELECT {} FROM dirs WHERE path = ?1", COLUMNS);
+            Ok(self
+                .conn
+                .query_row(&sql, [path.to_string_lossy()], read_entry)
+                .optional()?)
+        }
+
+        /// Every saved entry
+        pub fn get_all(&self) -> Result<Vec<DirEntry>> {
+            let mut statement = self.conn.prepare(&format!("SELECT {} FROM dirs", COLUMNS))?;
+            let entries = statement.query_map([], read_entry)?.collect::<rusqlite::Result<_>>()?;
+            Ok(entries)
+        }
+
+        /// Entries saved below `parent`, one level down
+        pub fn children_of(&self, parent: &Path) -> Result<Vec<DirEntry>> {
+            let mut statement = self.conn.prepare(&format!("SELECT {} FROM dirs WHERE parent = ?1", COLUMNS))?;
+            let entries = statement
+                .query_map([parent.to_string_lossy()], read_entry)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(entries)
+        }
+
+        /// Replace every row with `entries` and the metadata with `cache`'s, in one transaction
+        ///
+        /// `cancelled` is checked before each entry; its error rolls the
+        /// transaction back, leaving the saved rows as they were. Returns how
+        /// many entries were written.
+        pub fn replace_all<'a>(
+            &mut self,
+            cache: &DiskCache,
+            entries: impl Iterator<Item = &'a DirEntry>,
+            cancelled: &dyn Fn() -> Result<()>,
+        ) -> Result<usize> {
+            let tx = self.conn.transaction()?;
+            tx.execute("DELETE FROM dirs", [])?;
+            let written = insert_entries(&tx, entries, cancelled)?;
+            write_meta(&tx, cache)?;
+            tx.commit()?;
+            Ok(written)
+        }
+
+        /// Upsert `changed` entries and delete the rows of paths that are no longer cached
+        ///
+        /// For saves of a cache loaded from this database: rows the cache
+        /// didn't touch stay as they are. `cache.entries` must hold every
+        /// cached entry, as `DiskCache::save` expects. Returns how many
+        /// entries were written.
+        pub fn upsert(
+            &mut self,
+            cache: &DiskCache,
+            changed: &HashSet<PathBuf>,
+            cancelled: &dyn Fn() -> Result<()>,
+        ) -> Result<usize> {
+            let tx = self.conn.transaction()?;
+            let saved: HashSet<PathBuf> = {
+                let mut statement = tx.prepare("SELECT path FROM dirs")?;
+                let paths = statement
+                    .query_map([], |row| row.get::<_, String>(0).map(PathBuf::from))?
+                    .collect::<rusqlite::Result<_>>()?;
+                paths
+            };
+            {
+                let mut delete = tx.prepare("DELETE FROM dirs WHERE path = ?1")?;
+                for path in saved.iter().filter(|path| !cache.entries.contains_key(path)) {
+                    delete.execute([path.to_string_lossy()])?;
+                }
+            }
+            let updated = cache
+                .entries
+                .values()
+                .filter(|entry| changed.contains(&entry.path) || !saved.contains(&entry.path));
+            let written = insert_entries(&tx, updated, cancelled)?;
+            write_meta(&tx, cache)?;
+            tx.commit()?;
+            Ok(written)
+        }
+
+        /// Rewrite the metadata only, for a cache whose entries are unchanged
+        pub fn save_meta(&mut self, cache: &DiskCache) -> Result<()> {
+            let tx = self.conn.transaction()?;
+            write_meta(&tx, cache)?;
+            tx.commit()?;
+            Ok(())
+        }
+
+        /// Run SQLite's own consistency check, failing with `CacheCorrupt` on any problem
+        pub fn quick_check(&self, db_path: &Path) -> Result<()> {
+            let result: String = self.conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+            if result != "ok" {
+                return Err(PTreeError::CacheCorrupt(format!("{}: {}", db_path.display(), result)).into());
+            }
+            Ok(())
+        }
+    }
+
+    fn insert_entries<'a>(
+        tx: &Transaction,
+        entries: impl Iterator<Item = &'a DirEntry>,
+        cancelled: &dyn Fn() -> Result<()>,
+    ) -> Result<usize> {
+        let mut insert = tx.prepare(INSERT)?;
+        let mut written = 0;
+        for entry in entries {
+            cancelled()?;
+            let flags = [
+                (entry.skipped_children, SKIPPED_CHILDREN),
+                (entry.is_mount, IS_MOUNT),
+                (entry.evicted, EVICTED),
+                (entry.size_is_lower_bound, SIZE_IS_LOWER_BOUND),
+            ]
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |flags, (_, bit)| flags | bit);
+            insert.execute(params![
+                entry.path.to_string_lossy(),
+                entry.path.parent().map(|parent| parent.to_string_lossy()),
+                entry.name,
+                entry.modified.timestamp(),
+                entry.modified.timestamp_subsec_nanos(),
+                entry.size as i64,
+                entry.is_hidden,
+                entry.symlink_target.as_ref().map(|target| target.to_string_lossy()),
+                entry.is_dir,
+                entry.content_hash as i64,
+                entry.children.packed(),
+                flags,
+                entry.access_error,
+                entry.file_count,
+                entry.dir_count,
+                entry.total_size as i64,
+            ])?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    fn write_meta(tx: &Transaction, cache: &DiskCache) -> Result<()> {
+        let mut set = tx.prepare("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)")?;
+        for (key, value) in BackendMeta::pairs(cache, SQLITE_SCHEMA_VERSION)? {
+            set.execute([key, &value])?;
+        }
+        Ok(())
+    }
+
+    fn read_entry(row: &Row) -> rusqlite::Result<DirEntry> {
+        let flags: i64 = row.get("flags")?;
+        Ok(DirEntry {
+            path: PathBuf::from(row.get::<_, String>("path")?),
+            name: row.get("name")?,
+            modified: DateTime::from_timestamp(row.get("mtime")?, row.get("mtime_ns")?).unwrap_or_default(),
+            content_hash: row.get::<_, i64>("content_hash")? as u64,
+            children: ChildNames::from_packed(row.get("children")?),
+            symlink_target: row.get::<_, Option<String>>("symlink_target")?.map(PathBuf::from),
+            is_hidden: row.get("hidden")?,
+            is_dir: row.get("is_dir")?,
+            size: row.get::<_, i64>("size")? as u64,
+            skipped_children: flags & SKIPPED_CHILDREN != 0,
+            is_mount: flags & IS_MOUNT != 0,
+            access_error: row.get("access_error")?,
+            evicted: flags & EVICTED != 0,
+            file_count: row.get("file_count")?,
+            dir_count: row.get("dir_count")?,
+            total_size: row.get::<_, i64>("total_size")? as u64,
+            size_is_lower_bound: flags & SIZE_IS_LOWER_BOUND != 0,
+            generation: 0,
+            spilled_children: 0,
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl DiskCache {
+    /// Open the SQLite cache for `path`: its metadata only, entries are loaded on demand
+    pub(crate) fn open_sqlite(path: &Path) -> Result<Self> {
+        let db = SqliteCache::open(&sqlite_path(path))?;
+        let Some(meta) = db.meta()? else {
+            return Ok(DiskCache { backend: CacheBackend::Sqlite, load: CacheLoad::Missing, ..Self::new_empty() });
+        };
+        log::info!(
+            "loaded cache database {}: {} entries, last scan {}",
+            sqlite_path(path).display(),
+            format_count(db.len()?),
+            meta.last_scan
+        );
+        Ok(meta.into_cache(CacheBackend::Sqlite))
+    }
+
+    /// Load entries from the SQLite cache for `path`: those at `paths`, or all of them
+    ///
+    /// Entries already in memory are kept, as the rkyv loads keep them.
+    pub(crate) fn load_sqlite_entries(&mut self, path: &Path, paths: Option<&[PathBuf]>) -> Result<()> {
+        let db = SqliteCache::open(&sqlite_path(path))?;
+        let loaded = match paths {
+            Some(paths) => paths
+                .iter()
+                .filter(|path| !self.entries.contains_key(path))
+                .filter_map(|path| db.get_entry(path).transpose())
+                .collect::<Result<Vec<_>>>()?,
+            None => db.get_all()?,
+        };
+        for entry in loaded {
+            if !self.entries.contains_key(&entry.path) {
+                self.entries.insert(entry.path.clone(), entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save to the SQLite cache for `path`, as `write_files` does for the rkyv pair
+    ///
+    /// A clean cache only has its metadata rewritten; one loaded from the
+    /// database upserts its changed entries; anything else replaces every
+    /// row. Each runs in one transaction, so a failed or cancelled save
+    /// leaves the previous rows.
+    pub(crate) fn write_sqlite(&self, path: &Path, cancelled: &dyn Fn() -> Result<()>) -> Result<()> {
+        if self.compress || self.encrypt {
+            return Err(PTreeError::InvalidArgument(
+                "--compress-cache and --encrypt-cache need --backend rkyv".to_string(),
+            )
+            .into());
+        }
+        if self.max_bytes.is_some() {
+            log::warn!("--max-cache-bytes is not applied to the sqlite backend");
+        }
+
+        let db_path = sqlite_path(path);
+        create_cache_dir(path)?;
+        let lock = crate::lock::CacheLock::exclusive(path)?;
+        cancelled()?;
+        let mut db = SqliteCache::open(&db_path)?;
+        let saved = db.meta()?.is_some();
+        let written = match &self.changed {
+            _ if saved && !self.dirty => {
+                db.save_meta(self)?;
+                0
+            }
+            Some(changed) if saved => db.upsert(self, changed, cancelled)?,
+            _ => db.replace_all(self, self.entries.values(), cancelled)?,
+        };
+        drop(lock);
+
+        log::info!(
+            "saved cache database {}: {} of {} entries written",
+            db_path.display(),
+            format_count(written),
+            format_count(self.entries.len())
+        );
+        Ok(())
+    }
+
+    /// Run SQLite's consistency check on the cache database for `path` (`--verify-cache`)
+    /// Number of entries saved in the SQLite cache for `path` (`ptree cache-info`)
+    pub(crate) fn sqlite_len(path: &Path) -> Result<usize> {
+        SqliteCache::open(&sqlite_path(path))?.len()
+    }
+
+    pub(crate) fn verify_sqlite(path: &Path) -> Result<()> {
+        let db_path = sqlite_path(path);
+        SqliteCache::open(&db_path)?.quick_check(&db_path)
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl DiskCache {
+    pub(crate) fn open_sqlite(path: &Path) -> Result<Self> {
+        Err(sqlite_unavailable(path))
+    }
+
+    pub(crate) fn load_sqlite_entries(&mut self, path: &Path, _paths: Option<&[PathBuf]>) -> Result<()> {
+        Err(sqlite_unavailable(path))
+    }
+
+    pub(crate) fn write_sqlite(&self, path: &Path, _cancelled: &dyn Fn() -> Result<()>) -> Result<()> {
+        Err(sqlite_unavailable(path))
+    }
+
+    pub(crate) fn sqlite_len(path: &Path) -> Result<usize> {
+        Err(sqlite_unavailable(path))
+    }
+
+    pub(crate) fn verify_sqlite(path: &Path) -> Result<()> {
+        Err(sqlite_unavailable(path))
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_unavailable(path: &Path) -> anyhow::Error {
+    PTreeError::Cache(format!(
+        "{}: the sqlite cache backend is not available in this build (enable the `sqlite` feature)",
+        sqlite_path(path).display()
+    ))
+    .into()
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use crate::cache::DirEntry;
+    use crate::children::ChildNames;
+    use std::fs;
+
+    fn entry(path: &str, children: &[&str]) -> DirEntry {
+        DirEntry {
+            content_hash: u64::MAX - 1,
+            is_mount: true,
+            file_count: 1,
+            dir_count: children.len() as u32,
+            total_size: 4096,
+            size_is_lower_bound: true,
+            ..test_entry(path, children)
+        }
+    }
+
+    fn sqlite_cache(entries: &[DirEntry]) -> DiskCache {
+        let mut cache = DiskCache::new();
+        cache.backend = CacheBackend::Sqlite;
+        cache.root = PathBuf::from("/scan");
+        for entry in entries {
+            cache.entries.insert(entry.path.clone(), entry.clone());
+        }
+        cache
+    }
+
+    #[test]
+    fn test_sqlite_backend_round_trips_and_upserts() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_sqlite_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+        let saved = [entry("/scan", &["a", "b"]), entry("/scan/a", &[]), entry("/scan/b", &[])];
+        sqlite_cache(&saved).save(&cache_path)?;
+        assert!(is_sqlite_cache(&cache_path));
+        assert!(!cache_path.with_extension("idx").exists());
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert_eq!((cache.backend, cache.root.as_path(), cache.entries.len()), (CacheBackend::Sqlite, Path::new("/scan"), 0));
+        cache.load_all_entries_lazy(&cache_path)?;
+        for expected in &saved {
+            let loaded = cache.get_entry(&expected.path).unwrap();
+            assert_eq!(
+                (&loaded.children, loaded.modified, loaded.content_hash, loaded.is_mount, loaded.total_size),
+                (&expected.children, expected.modified, expected.content_hash, expected.is_mount, expected.total_size)
+            );
+            assert!(loaded.size_is_lower_bound && !loaded.skipped_children);
+        }
+
+        // Dropping b and adding c touches only those rows
+        cache.remove_entry(Path::new("/scan/b"));
+        cache.add_entry(PathBuf::from("/scan/c"), entry("/scan/c", &[]));
+        cache.entries.get_mut("/scan").unwrap().children = ChildNames::from(vec!["a", "c"]);
+        cache.mark_changed(Path::new("/scan"));
+        cache.save(&cache_path)?;
+
+        let db = SqliteCache::open(&sqlite_path(&cache_path))?;
+        assert_eq!(db.len()?, 3);
+        assert!(db.get_entry(Path::new("/scan/b"))?.is_none());
+        let mut children: Vec<String> = db.children_of(Path::new("/scan"))?.into_iter().map(|entry| entry.name).collect();
+        children.sort();
+        assert_eq!(children, ["a", "c"]);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_switching_backends_moves_every_entry() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_sqlite_switch_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+        let mut rkyv = sqlite_cache(&[entry("/scan", &["a"]), entry("/scan/a", &[])]);
+        rkyv.backend = CacheBackend::Rkyv;
+        rkyv.save(&cache_path)?;
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert_eq!(cache.backend, CacheBackend::Rkyv);
+        cache.set_backend(CacheBackend::Sqlite, &cache_path)?;
+        cache.save(&cache_path)?;
+        assert!(!cache_path.with_extension("idx").exists() && !cache_path.exists());
+        assert_eq!(SqliteCache::open(&sqlite_path(&cache_path))?.len()?, 2);
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.set_backend(CacheBackend::Rkyv, &cache_path)?;
+        cache.save(&cache_path)?;
+        assert!(!sqlite_path(&cache_path).exists());
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert_eq!((cache.backend, cache.entries.len()), (CacheBackend::Rkyv, 2));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Index;
+
+// ============================================================================
+// Packed Child Names (DirEntry::children)
+// ============================================================================
+
+/// Separator between packed names; no file name on any platform contains it
+const SEP: char = '\0';
+
+/// A directory's child names, packed into one string
+///
+/// `Vec<String>` spent a heap allocation and 24 bytes of header on every
+/// name; here all names share one allocation, joined by NUL, with the end
+/// of each kept as a u32 offset. Names are read back as `&str` through
+/// `iter` or indexing. Order is kept, duplicates are allowed, and equality
+/// compares names in order, as it did for the `Vec`.
+///
+/// Binary formats (bincode) store only the packed string and recover the
+/// offsets on load; human-readable ones (JSON) see a list of names.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct ChildNames {
+    /// Names joined by `SEP`
+    packed: String,
+    /// End of each name in `packed`
+    ends: Vec<u32>,
+}
+
+impl ChildNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names as stored by `packed`: joined by NUL, or empty for none
+    pub fn from_packed(packed: String) -> Self {
+        if packed.is_empty() {
+            return Self::new();
+        }
+        let mut ends: Vec<u32> = packed.match_indices(SEP).map(|(at, _)| at as u32).collect();
+        ends.push(packed.len() as u32);
+        ChildNames { packed, ends }
+    }
+
+    /// The names joined by NUL, the form binary formats store
+    pub fn packed(&self) -> &str {
+        &self.packed
+    }
+
+    /// Names in a string from `packed`, read in place
+    pub fn split_packed(packed: &str) -> impl Iterator<Item = &str> {
+        (!packed.is_empty()).then(|| packed.split(SEP)).into_iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        let end = *self.ends.get(index)? as usize;
+        let start = match index {
+            0 => 0,
+            _ => self.ends[index - 1] as usize + 1,
+        };
+        Some(&self.packed[start..end])
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { names: self, front: 0, back: self.len() }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.iter().any(|child| child == name)
+    }
+
+    /// Append `name`, which must not contain NUL
+    pub fn push(&mut self, name: impl AsRef<str>) {
+        let name = name.as_ref();
+        debug_assert!(!name.contains(SEP), "child name {:?} contains NUL", name);
+        if !self.ends.is_empty() {
+            self.packed.push(SEP);
+        }
+        self.packed.push_str(name);
+        self.ends.push(self.packed.len() as u32);
+    }
+
+    pub fn clear(&mut self) {
+        self.packed.clear();
+        self.ends.clear();
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        if self.iter().all(&mut keep) {
+            return;
+        }
+        *self = self.iter().filter(|name| keep(name)).collect();
+    }
+
+    /// Sort by name (byte order, as `Vec<String>::sort` did)
+    pub fn sort_unstable(&mut self) {
+        let mut names: Vec<&str> = self.iter().collect();
+        names.sort_unstable();
+        *self = names.into_iter().collect();
+    }
+
+    /// Drop consecutive repeats of a name
+    pub fn dedup(&mut self) {
+        let mut names: Vec<&str> = self.iter().collect();
+        let before = names.len();
+        names.dedup();
+        if names.len() != before {
+            *self = names.into_iter().collect();
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.packed.shrink_to_fit();
+        self.ends.shrink_to_fit();
+    }
+
+    /// Heap bytes held, for memory accounting
+    pub fn heap_bytes(&self) -> usize {
+        self.packed.capacity() + self.ends.capacity() * std::mem::size_of::<u32>()
+    }
+}
+
+/// Iterator over the names of a `ChildNames`
+#[derive(Clone)]
+pub struct Iter<'a> {
+    names: &'a ChildNames,
+    front: usize,
+    back: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.front == self.back {
+            return None;
+        }
+        self.front += 1;
+        self.names.get(self.front - 1)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.back - self.front;
+        (left, Some(left))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        self.names.get(self.back)
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a ChildNames {
+    type Item = &'a str;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl Index<usize> for ChildNames {
+    type Output = str;
+
+    fn index(&self, index: usize) -> &str {
+        match self.get(index) {
+            Some(name) => name,
+            None => panic!("child index {} out of range for {} names", index, self.len()),
+        }
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for ChildNames {
+    fn from_iter<I: IntoIterator<Item = S>>(names: I) -> Self {
+        let mut packed = ChildNames::new();
+        packed.extend(names);
+        packed.shrink_to_fit();
+        packed
+    }
+}
+
+impl<S: AsRef<str>> Extend<S> for ChildNames {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, names: I) {
+        for name in names {
+            self.push(name);
+        }
+    }
+}
+
+impl<S: AsRef<str>> From<Vec<S>> for ChildNames {
+    fn from(names: Vec<S>) -> Self {
+        names.into_iter().collect()
+    }
+}
+
+impl<S: AsRef<str>> PartialEq<[S]> for ChildNames {
+    fn eq(&self, other: &[S]) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a == b.as_ref())
+    }
+}
+
+impl<S: AsRef<str>, const N: usize> PartialEq<[S; N]> for ChildNames {
+    fn eq(&self, other: &[S; N]) -> bool {
+        *self == other[..]
+    }
+}
+
+/// Names compared in order, as `Vec<String>` compared
+impl Ord for ChildNames {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl PartialOrd for ChildNames {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for ChildNames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for ChildNames {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_str(&self.packed);
+        }
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for name in self {
+            seq.serialize_element(name)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ChildNames {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return String::deserialize(deserializer).map(ChildNames::from_packed);
+        }
+
+        struct Names;
+        impl<'de> Visitor<'de> for Names {
+            type Value = ChildNames;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a list of child names")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ChildNames, A::Error> {
+                let mut names = ChildNames::new();
+                while let Some(name) = seq.next_element::<String>()? {
+                    names.push(name);
+                }
+                Ok(names)
+            }
+        }
+        deserializer.deserialize_seq(Names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_round_trip_through_both_forms() {
+        let mut names: ChildNames = ["src", "", "ünïcode", "bin"].into_iter().collect();
+        assert_eq!(names, ["src", "", "ünïcode", "bin"]);
+        assert_eq!((names.len(), &names[2], names.iter().next_back()), (4, "ünïcode", Some("bin")));
+
+        let bytes = bincode::serialize(&names).unwrap();
+        assert_eq!(bytes.len(), 8 + "src\0\0ünïcode\0bin".len());
+        assert_eq!(bincode::deserialize::<ChildNames>(&bytes).unwrap(), names);
+        let json = serde_json::to_string(&names).unwrap();
+        assert_eq!(json, r#"["src","","ünïcode","bin"]"#);
+        assert_eq!(serde_json::from_str::<ChildNames>(&json).unwrap(), names);
+
+        names.retain(|name| !name.is_empty());
+        names.push("bin");
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names, ["bin", "src", "ünïcode"]);
+        assert!(names.contains("src") && !names.contains("sr"));
+
+        names.clear();
+        assert!(names.is_empty());
+        assert_eq!(bincode::deserialize::<ChildNames>(&bincode::serialize(&names).unwrap()).unwrap(), names);
+    }
+}
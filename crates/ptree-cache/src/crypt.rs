@@ -0,0 +1,205 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::Result;
+use parking_lot::Mutex;
+use ptree_core::PTreeError;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::OnceLock;
+
+// ============================================================================
+// Encryption at Rest (--encrypt-cache)
+// ============================================================================
+
+/// Environment variable holding the cache passphrase off Windows
+pub const PASSPHRASE_ENV: &str = "PTREE_CACHE_PASSPHRASE";
+
+/// PBKDF2-HMAC-SHA256 rounds for new passphrase keys (stored with the key, so older files keep theirs)
+#[cfg(not(test))]
+const KDF_ROUNDS: u32 = 600_000;
+#[cfg(test)]
+const KDF_ROUNDS: u32 = 1_000;
+
+const NONCE_LEN: usize = 12;
+
+/// How the key of an encrypted cache is obtained, stored in the clear at the start of its index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeySource {
+    /// Derived from `PASSPHRASE_ENV` with PBKDF2-HMAC-SHA256
+    Passphrase { salt: [u8; 16], rounds: u32 },
+    /// A random key sealed with DPAPI (`CryptProtectData`) for the current Windows user
+    Dpapi { blob: Vec<u8> },
+}
+
+/// AES-256-GCM key of an encrypted cache pair
+///
+/// The index payload is sealed as a whole; each data record (or compressed
+/// block) separately, so records can still be read at their offsets.
+/// Sealed bytes are a random 96-bit nonce followed by the ciphertext and tag.
+#[derive(Clone)]
+pub struct CacheKey {
+    source: KeySource,
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for CacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheKey").field("source", &self.source).finish_non_exhaustive()
+    }
+}
+
+/// Keys already unlocked by this process, by source: a passphrase is only derived once
+fn unlocked() -> &'static Mutex<Vec<CacheKey>> {
+    static UNLOCKED: OnceLock<Mutex<Vec<CacheKey>>> = OnceLock::new();
+    UNLOCKED.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn key_error(what: String) -> anyhow::Error {
+    PTreeError::CacheKey(what).into()
+}
+
+impl CacheKey {
+    /// A new key: sealed with DPAPI on Windows, derived from `PASSPHRASE_ENV` elsewhere
+    pub fn create() -> Result<Self> {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret).map_err(|e| anyhow::anyhow!("no randomness for a cache key: {}", e))?;
+        #[cfg(windows)]
+        let source = KeySource::Dpapi { blob: dpapi::protect(&secret)? };
+        #[cfg(not(windows))]
+        let source = KeySource::Passphrase { salt: secret[..16].try_into().expect("16-byte slice"), rounds: KDF_ROUNDS };
+        Self::unlock(&source)
+    }
+
+    /// The key of the encrypted pair whose index is at `index_path`, or a new one
+    ///
+    /// Saves keep a cache's key, so appended records match the ones before
+    /// them; a pair that is missing, plain, or can't be unlocked gets a new key.
+    pub fn for_index(index_path: &Path) -> Result<Self> {
+        let existing = std::fs::read(index_path)
+            .ok()
+            .and_then(|bytes| crate::cache_rkyv::read_key_source(&bytes).ok().flatten());
+        match existing.map(|source| Self::unlock(&source)) {
+            Some(Ok(key)) => Ok(key),
+            _ => Self::create(),
+        }
+    }
+
+    /// The key `source` describes
+    ///
+    /// Fails with `PTreeError::CacheKey` when it can't be had: no
+    /// passphrase set, or a DPAPI blob sealed for another user or machine.
+    /// A wrong passphrase only shows once something fails to decrypt.
+    pub fn unlock(source: &KeySource) -> Result<Self> {
+        if let Some(key) = unlocked().lock().iter().find(|key| key.source == *source) {
+            return Ok(key.clone());
+        }
+
+        let mut secret = [0u8; 32];
+        match source {
+            KeySource::Passphrase { salt, rounds } => {
+                let passphrase = std::env::var(PASSPHRASE_ENV)
+                    .ok()
+                    .filter(|passphrase| !passphrase.is_empty())
+                    .ok_or_else(|| key_error(format!("no cache passphrase; set {}", PASSPHRASE_ENV)))?;
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, *rounds, &mut secret);
+            }
+            #[cfg(windows)]
+            KeySource::Dpapi { blob } => {
+                let unsealed = dpapi::unprotect(blob)
+                    .map_err(|e| key_error(format!("DPAPI cannot unseal the cache key: {}", e)))?;
+                if unsealed.len() != secret.len() {
+                    return Err(key_error("DPAPI returned a key of the wrong length".to_string()));
+                }
+                secret.copy_from_slice(&unsealed);
+            }
+            #[cfg(not(windows))]
+            KeySource::Dpapi { .. } => {
+                return Err(key_error("the cache key is sealed with Windows DPAPI".to_string()));
+            }
+        }
+
+        let key = CacheKey { source: source.clone(), cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&secret)) };
+        unlocked().lock().push(key.clone());
+        Ok(key)
+    }
+
+    pub fn source(&self) -> &KeySource {
+        &self.source
+    }
+
+    /// Encrypt `plain` under a fresh nonce
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|e| anyhow::anyhow!("no randomness for a nonce: {}", e))?;
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .map_err(|_| anyhow::anyhow!("cannot encrypt cache data"))?;
+        let mut out = Vec::with_capacity(NONCE_LEN + sealed.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    /// Decrypt bytes from `seal`; None when they are damaged or sealed under another key
+    pub fn unseal(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+    }
+}
+
+#[cfg(windows)]
+mod dpapi {
+    use std::io;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Cryptography::{
+        CryptProtectData, CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB,
+    };
+
+    /// Seal `secret` for the current user
+    pub fn protect(secret: &[u8]) -> io::Result<Vec<u8>> {
+        call(secret, true)
+    }
+
+    /// Open a blob from `protect`
+    pub fn unprotect(blob: &[u8]) -> io::Result<Vec<u8>> {
+        call(blob, false)
+    }
+
+    fn call(input: &[u8], protect: bool) -> io::Result<Vec<u8>> {
+        let input = CRYPT_INTEGER_BLOB { cbData: input.len() as u32, pbData: input.as_ptr() as *mut u8 };
+        let mut output = CRYPT_INTEGER_BLOB { cbData: 0, pbData: std::ptr::null_mut() };
+        let ok = unsafe {
+            if protect {
+                CryptProtectData(
+                    &input,
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    CRYPTPROTECT_UI_FORBIDDEN,
+                    &mut output,
+                )
+            } else {
+                CryptUnprotectData(
+                    &input,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    CRYPTPROTECT_UI_FORBIDDEN,
+                    &mut output,
+                )
+            }
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(output.pbData, output.cbData as usize) }.to_vec();
+        unsafe { LocalFree(output.pbData as _) };
+        Ok(bytes)
+    }
+}
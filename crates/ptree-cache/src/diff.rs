@@ -0,0 +1,299 @@
+use crate::cache::{format_count, DirEntry};
+use crate::reader::CacheReader;
+use anyhow::Result;
+use serde_json::json;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Cache Comparison (ptree diff)
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    Added,
+    Removed,
+    /// Different `modified` time, or renamed in letter case only (Windows)
+    Modified,
+}
+
+impl DiffKind {
+    pub fn marker(self) -> char {
+        match self {
+            DiffKind::Added => '+',
+            DiffKind::Removed => '-',
+            DiffKind::Modified => '~',
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DiffKind::Added => "added",
+            DiffKind::Removed => "removed",
+            DiffKind::Modified => "modified",
+        }
+    }
+}
+
+/// Totals of a finished comparison
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffCounts {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+}
+
+/// Paths of one cache, ordered by comparison key
+fn sorted_keys<R: CacheReader + ?Sized>(reader: &R, scope: Option<&Path>) -> Result<Vec<(String, PathBuf)>> {
+    let mut keys: Vec<(String, PathBuf)> = reader
+        .paths()?
+        .into_iter()
+        .filter(|path| scope.is_none_or(|scope| path.starts_with(scope)))
+        .map(|path| (diff_key(&path), path))
+        .collect();
+    keys.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    Ok(keys)
+}
+
+/// The directory at `path`, None for a file
+fn dir_at<'r, R: CacheReader + ?Sized>(reader: &'r R, path: &Path) -> Result<Option<Cow<'r, DirEntry>>> {
+    Ok(reader.get_entry_shallow(path)?.filter(|entry| entry.is_dir))
+}
+
+/// Comparison key: Windows paths are case-insensitive, so a case-only
+/// rename pairs up with the old entry instead of showing as remove + add
+///
+/// Separators become `\u{1}` so a directory's contents sort right after it,
+/// before siblings such as `a-b` or `a.old` that would otherwise interleave.
+fn diff_key(path: &Path) -> String {
+    let key = path.to_string_lossy();
+    let key = if cfg!(windows) { key.to_lowercase() } else { key.into_owned() };
+    key.replace(std::path::is_separator, "\u{1}")
+}
+
+/// Compare the directories of two caches, calling `visit` once per difference
+///
+/// Both sides' paths are walked as sorted lists in step (a merge join), so
+/// differences arrive in path order — each directory directly followed by
+/// its contents — and are never collected, however many there are. Only
+/// the paths are held: each entry is read from its reader when the walk
+/// reaches it, so a saved pair is compared straight from its data file.
+pub fn diff_caches<A, B, F>(old: &A, new: &B, scope: Option<&Path>, mut visit: F) -> Result<DiffCounts>
+where
+    A: CacheReader + ?Sized,
+    B: CacheReader + ?Sized,
+    F: FnMut(DiffKind, &DirEntry) -> Result<()>,
+{
+    let old_keys = sorted_keys(old, scope)?;
+    let new_keys = sorted_keys(new, scope)?;
+    let mut counts = DiffCounts::default();
+    let (mut i, mut j) = (0, 0);
+
+    loop {
+        let order = match (old_keys.get(i), new_keys.get(j)) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(a), Some(b)) => a.0.cmp(&b.0),
+        };
+        let before = match order {
+            Ordering::Less | Ordering::Equal => dir_at(old, &old_keys[i].1)?,
+            Ordering::Greater => None,
+        };
+        let after = match order {
+            Ordering::Greater | Ordering::Equal => dir_at(new, &new_keys[j].1)?,
+            Ordering::Less => None,
+        };
+        // A directory replaced by a file (or the other way round) is removed (added)
+        match (before, after) {
+            (Some(before), Some(after)) => {
+                if before.modified != after.modified || before.path != after.path {
+                    counts.modified += 1;
+                    visit(DiffKind::Modified, &after)?;
+                }
+            }
+            (Some(before), None) => {
+                counts.removed += 1;
+                visit(DiffKind::Removed, &before)?;
+            }
+            (None, Some(after)) => {
+                counts.added += 1;
+                visit(DiffKind::Added, &after)?;
+            }
+            (None, None) => {}
+        }
+        if order != Ordering::Greater {
+            i += 1;
+        }
+        if order != Ordering::Less {
+            j += 1;
+        }
+    }
+    Ok(counts)
+}
+
+/// Writes differences as they arrive from `diff_caches`
+///
+/// Text output folds everything below an added or removed directory into
+/// that directory's line (`+ /new (12 below)`); JSON output is one object
+/// per difference (NDJSON) followed by a totals object.
+pub struct DiffWriter<W: Write> {
+    out: W,
+    json: bool,
+    /// Added/removed directory whose descendants are being folded in
+    group: Option<(DiffKind, PathBuf, usize)>,
+}
+
+impl<W: Write> DiffWriter<W> {
+    pub fn new(out: W, json: bool) -> Self {
+        DiffWriter { out, json, group: None }
+    }
+
+    pub fn write(&mut self, kind: DiffKind, entry: &DirEntry) -> Result<()> {
+        if self.json {
+            let line = json!({ "change": kind.label(), "path": entry.path.to_string_lossy() });
+            writeln!(self.out, "{}", line)?;
+            return Ok(());
+        }
+
+        if let Some((group_kind, root, below)) = &mut self.group {
+            if *group_kind == kind && entry.path.starts_with(&*root) {
+                *below += 1;
+                return Ok(());
+            }
+        }
+        self.flush_group()?;
+        if kind == DiffKind::Modified {
+            writeln!(self.out, "{} {}", kind.marker(), entry.path.display())?;
+        } else {
+            self.group = Some((kind, entry.path.clone(), 0));
+        }
+        Ok(())
+    }
+
+    /// Write the totals and hand back the writer
+    pub fn finish(mut self, counts: DiffCounts) -> Result<W> {
+        self.flush_group()?;
+        if self.json {
+            let totals = json!({ "added": counts.added, "removed": counts.removed, "modified": counts.modified });
+            writeln!(self.out, "{}", totals)?;
+        } else {
+            writeln!(
+                self.out,
+                "{} added, {} removed, {} modified",
+                format_count(counts.added),
+                format_count(counts.removed),
+                format_count(counts.modified)
+            )?;
+        }
+        Ok(self.out)
+    }
+
+    fn flush_group(&mut self) -> Result<()> {
+        if let Some((kind, root, below)) = self.group.take() {
+            write!(self.out, "{} {}", kind.marker(), root.display())?;
+            if below > 0 {
+                write!(self.out, " ({} below)", format_count(below))?;
+            }
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use crate::cache::DiskCache;
+    use crate::cache_rkyv::RkyvMmapCache;
+    use chrono::{Duration, Utc};
+    use std::path::PathBuf;
+
+    fn cache(paths: &[&str]) -> DiskCache {
+        let mut cache = DiskCache::new();
+        let modified = Utc::now();
+        for path in paths {
+            cache.entries.insert(PathBuf::from(path), DirEntry { modified, ..test_entry(path, &[]) });
+        }
+        cache
+    }
+
+    fn diff(old: &DiskCache, new: &DiskCache, scope: Option<&str>) -> (Vec<String>, DiffCounts) {
+        let mut seen = Vec::new();
+        let counts = diff_caches(old, new, scope.map(Path::new), |kind, entry| {
+            seen.push(format!("{} {}", kind.marker(), entry.path.display()));
+            Ok(())
+        })
+        .unwrap();
+        (seen, counts)
+    }
+
+    /// `new` with the `modified` times of `old` wherever the path exists in both
+    fn unchanged(old: &DiskCache, mut new: DiskCache) -> DiskCache {
+        for entry in new.entries.values_mut() {
+            if let Some(before) = old.entries.get(&entry.path) {
+                entry.modified = before.modified;
+            }
+        }
+        new
+    }
+
+    #[test]
+    fn test_reports_changes_in_path_order() {
+        let old = cache(&["/r", "/r/gone", "/r/gone/sub", "/r/gone-too", "/r/same", "/r/touched"]);
+        let mut new = unchanged(&old, cache(&["/r", "/r/new", "/r/same", "/r/touched"]));
+        new.entries.get_mut(Path::new("/r/touched")).unwrap().modified += Duration::seconds(5);
+
+        let (seen, counts) = diff(&old, &new, None);
+        assert_eq!(seen, ["- /r/gone", "- /r/gone/sub", "- /r/gone-too", "+ /r/new", "~ /r/touched"]);
+        assert_eq!(counts, DiffCounts { added: 1, removed: 3, modified: 1 });
+
+        let mut writer = DiffWriter::new(Vec::new(), false);
+        let counts = diff_caches(&old, &new, None, |kind, entry| writer.write(kind, entry)).unwrap();
+        let text = String::from_utf8(writer.finish(counts).unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "- /r/gone (1 below)\n- /r/gone-too\n+ /r/new\n~ /r/touched\n1 added, 3 removed, 1 modified\n"
+        );
+    }
+
+    #[test]
+    fn test_scope_and_files_are_ignored() {
+        let old = cache(&["/r", "/r/a"]);
+        let mut new = unchanged(&old, cache(&["/r", "/r/a", "/r/a/x", "/r/b"]));
+        new.entries.get_mut(Path::new("/r/b")).unwrap().is_dir = false;
+
+        let (seen, _) = diff(&old, &new, Some("/r/a"));
+        assert_eq!(seen, ["+ /r/a/x"]);
+        let (seen, _) = diff(&old, &new, None);
+        assert_eq!(seen, ["+ /r/a/x"]);
+    }
+
+    #[test]
+    fn test_saved_pair_is_compared_from_its_data_file() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_diff_saved_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache_path = dir.join("ptree.dat");
+        let mut old = cache(&["/r", "/r/gone", "/r/same"]);
+        old.root = PathBuf::from("/r");
+        old.entries.get_mut(Path::new("/r")).unwrap().children = ["gone", "same"].into_iter().map(String::from).collect();
+        old.save(&cache_path)?;
+        let saved = RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path)?;
+
+        let new = unchanged(&old, cache(&["/r", "/r/new", "/r/same"]));
+        let mut seen = Vec::new();
+        let counts = diff_caches(&saved, &new, None, |kind, entry| {
+            seen.push(format!("{} {}", kind.marker(), entry.path.display()));
+            Ok(())
+        })?;
+        assert_eq!(seen, ["- /r/gone", "+ /r/new"]);
+        assert_eq!(counts, DiffCounts { added: 1, removed: 1, modified: 0 });
+
+        drop(saved);
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
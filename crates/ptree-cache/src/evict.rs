@@ -0,0 +1,247 @@
+use crate::cache::{DirEntry, DiskCache};
+use crate::stats::depth_of;
+use chrono::{DateTime, Utc};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+#[cfg(test)]
+use anyhow::Result;
+
+// ============================================================================
+// Cache Size Bound (--max-cache-bytes)
+// ============================================================================
+
+/// Bytes the file headers, generation, and index metadata take regardless of entries
+const FIXED_BYTES: u64 = 256;
+
+/// Which directories a save keeps only as `[evicted]` stubs to fit a size bound
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EvictionPlan {
+    /// Directories saved without their children, in the order they were chosen
+    pub evicted: Vec<PathBuf>,
+    /// Entries below those directories, left out of the saved cache
+    pub dropped: HashSet<PathBuf>,
+    /// Estimated on-disk size before eviction
+    pub before: u64,
+    /// Estimated on-disk size after eviction
+    pub after: u64,
+}
+
+impl DiskCache {
+    /// Estimate the bytes `save` would write for the current entries
+    ///
+    /// Uncompressed sizes: with `--compress-cache` the files come out smaller,
+    /// so a bound is met with room to spare.
+    pub fn estimated_bytes(&self) -> u64 {
+        FIXED_BYTES + self.entries.values().map(stored_bytes).sum::<u64>()
+    }
+
+    /// Choose the subtrees to evict so the saved cache fits in `max_bytes`
+    ///
+    /// Candidates are directories below a root that still have cached
+    /// descendants. They are taken in order of the newest modification
+    /// anywhere in their subtree (stalest first), deeper directories first
+    /// on ties, then by path, so the same cache always yields the same plan.
+    /// Each evicted directory stays as an entry without children, keeping
+    /// the spine from the root down to it. If even the spine is over the
+    /// bound, `after` is left above `max_bytes`.
+    pub fn plan_eviction(&self, max_bytes: u64) -> EvictionPlan {
+        let before = self.estimated_bytes();
+        let mut plan = EvictionPlan { before, after: before, ..EvictionPlan::default() };
+        if before <= max_bytes {
+            return plan;
+        }
+
+        let mut by_depth: Vec<(&Path, usize)> = self
+            .entries
+            .keys()
+            .map(|path| (path.as_path(), depth_of(path)))
+            .collect();
+        by_depth.sort_unstable_by_key(|&(path, depth)| (Reverse(depth), path));
+
+        // Latest modification anywhere in each subtree, the directory included
+        let mut children: HashMap<&Path, Vec<&Path>> = HashMap::new();
+        let mut newest: HashMap<&Path, DateTime<Utc>> = HashMap::with_capacity(by_depth.len());
+        for &(path, _) in &by_depth {
+            let modified = self.entries[path].modified;
+            let own = *newest.entry(path).and_modify(|below| *below = (*below).max(modified)).or_insert(modified);
+            let Some(parent) = path.parent().filter(|parent| self.entries.contains_key(*parent)) else {
+                continue;
+            };
+            children.entry(parent).or_default().push(path);
+            newest.entry(parent).and_modify(|up| *up = (*up).max(own)).or_insert(own);
+        }
+
+        let mut candidates: Vec<(&Path, usize)> = by_depth
+            .iter()
+            .copied()
+            .filter(|&(path, _)| {
+                let entry = &self.entries[path];
+                entry.is_dir
+                    && !entry.evicted
+                    && children.contains_key(path)
+                    && path.parent().is_some_and(|parent| self.entries.contains_key(parent))
+            })
+            .collect();
+        candidates.sort_by_key(|&(path, depth)| (newest[path], Reverse(depth), path));
+
+        let mut stubs: HashSet<&Path> = HashSet::new();
+        for (path, _) in candidates {
+            if plan.after <= max_bytes {
+                break;
+            }
+            if plan.dropped.contains(path) {
+                continue;
+            }
+
+            let entry = &self.entries[path];
+            plan.after -= children_bytes(entry);
+            let mut stack = children[path].clone();
+            while let Some(below) = stack.pop() {
+                if !plan.dropped.insert(below.to_path_buf()) {
+                    continue;
+                }
+                let below_entry = &self.entries[below];
+                plan.after -= stored_bytes(below_entry);
+                if stubs.remove(below) {
+                    // An earlier stub swallowed by this one: its children were already counted out
+                    plan.after += children_bytes(below_entry);
+                }
+                if let Some(next) = children.get(below) {
+                    stack.extend(next.iter().copied().filter(|next| !plan.dropped.contains(*next)));
+                }
+            }
+            stubs.insert(path);
+            plan.evicted.push(path.to_path_buf());
+        }
+        plan.evicted.retain(|path| stubs.contains(path.as_path()));
+        plan
+    }
+
+    /// Nearest cached ancestor of `path` (or `path` itself) that was evicted
+    ///
+    /// `path` is absolute. Used before `--subtree` renders, so a subtree that
+    /// lies inside an evicted region is rescanned instead of reported missing.
+    pub fn evicted_ancestor(&self, path: &Path) -> Option<PathBuf> {
+        path.ancestors()
+            .find_map(|ancestor| self.find_cached_path(ancestor))
+            .filter(|found| self.entries[found].evicted)
+    }
+}
+
+/// Estimated bytes `entry` takes in the data file plus its index slot
+///
+/// Follows the bincode layout: strings and lists carry an 8-byte length,
+/// integers are fixed width, and the timestamp is an RFC 3339 string.
+pub fn stored_bytes(entry: &DirEntry) -> u64 {
+    let path = entry.path.as_os_str().len() as u64;
+    let record = 8 // record length and checksum
+        + 8 + path
+        + 8 + entry.name.len() as u64
+        + 8 + 30 // modified
+        + 8 // content_hash
+        + 8 + children_bytes(entry)
+        + 1 + entry.symlink_target.as_ref().map_or(0, |target| 8 + target.as_os_str().len() as u64)
+        + 16 // size, total_size
+        + 8 // file_count, dir_count
+        + 6 // is_hidden, is_dir, skipped_children, is_mount, evicted, size_is_lower_bound
+        + 1 + entry.access_error.as_ref().map_or(0, |error| 8 + error.len() as u64);
+    let index = 8 + path + 8;
+    record + index
+}
+
+/// Bytes of `entry`'s packed child names, without their length
+fn children_bytes(entry: &DirEntry) -> u64 {
+    entry.children.packed().len() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use std::fs;
+
+    /// `/scan` with subtrees last touched 30 days ago (`old`), 10 days ago (`mid`) and now (`new`)
+    fn cache() -> DiskCache {
+        let now = Utc::now();
+        let days = chrono::Duration::days;
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        let tree: &[(&str, &[&str], bool, DateTime<Utc>)] = &[
+            ("/scan", &["old", "mid", "new"], true, now),
+            ("/scan/old", &["a"], true, now - days(30)),
+            ("/scan/old/a", &["f1"], true, now - days(30)),
+            ("/scan/old/a/f1", &[], false, now - days(40)),
+            ("/scan/mid", &["c"], true, now - days(20)),
+            ("/scan/mid/c", &[], false, now - days(10)),
+            ("/scan/new", &["b"], true, now - days(5)),
+            ("/scan/new/b", &[], false, now),
+        ];
+        for &(path, children, is_dir, modified) in tree {
+            cache.entries.insert(PathBuf::from(path), DirEntry { modified, is_dir, ..test_entry(path, children) });
+        }
+        cache
+    }
+
+    fn paths(plan: &EvictionPlan) -> Vec<String> {
+        plan.evicted.iter().map(|path| path.display().to_string()).collect()
+    }
+
+    #[test]
+    fn test_stalest_deepest_subtrees_go_first() {
+        let cache = cache();
+        let before = cache.estimated_bytes();
+        assert!(cache.plan_eviction(before).evicted.is_empty());
+
+        let plan = cache.plan_eviction(before - 1);
+        assert_eq!(paths(&plan), ["/scan/old/a"]);
+        assert_eq!(plan.dropped, HashSet::from([PathBuf::from("/scan/old/a/f1")]));
+        assert_eq!(plan, cache.clone().plan_eviction(before - 1));
+
+        // Nothing fits: every subtree below the root is stubbed, `old` swallowing `old/a`
+        let plan = cache.plan_eviction(0);
+        assert_eq!(paths(&plan), ["/scan/old", "/scan/mid", "/scan/new"]);
+        assert_eq!(plan.dropped.len(), 4);
+
+        // The estimate after eviction is what the stubbed cache would weigh
+        let mut stubbed = cache.clone();
+        stubbed.entries.retain(|path, _| !plan.dropped.contains(path));
+        for path in &plan.evicted {
+            stubbed.entries.get_mut(path).unwrap().children.clear();
+        }
+        assert_eq!(plan.after, stubbed.estimated_bytes());
+    }
+
+    #[test]
+    fn test_evicted_subtrees_save_as_stubs() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_evict_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+
+        let mut cache = cache();
+        cache.max_bytes = Some(cache.estimated_bytes() - 1);
+        cache.save(&cache_path)?;
+        // The saving run keeps every entry
+        assert!(cache.entries.contains_key(Path::new("/scan/old/a/f1")));
+
+        let mut loaded = DiskCache::open(&cache_path)?;
+        loaded.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(loaded.entries.len(), cache.entries.len() - 1);
+        let tree = loaded.build_tree_output()?;
+        assert!(tree.contains("a [evicted]\n"), "{}", tree);
+        assert_eq!(tree.matches("[evicted]").count(), 1, "{}", tree);
+        let json: serde_json::Value = serde_json::from_str(&loaded.build_json_output()?)?;
+        let old = json["children"].as_array().unwrap().iter().find(|node| node["name"] == "old").unwrap();
+        assert_eq!(old["children"][0]["evicted"], true);
+        assert!(old.get("evicted").is_none());
+
+        assert_eq!(
+            loaded.evicted_ancestor(Path::new("/scan/old/a/f1/gone")),
+            Some(PathBuf::from("/scan/old/a"))
+        );
+        assert_eq!(loaded.evicted_ancestor(Path::new("/scan/new/b")), None);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
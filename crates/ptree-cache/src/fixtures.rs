@@ -0,0 +1,44 @@
+//! Entries and caches for tests, built in one place instead of in each module's tests
+//!
+//! Compiled for this crate's tests, and for other crates' tests through the
+//! `test-fixtures` feature (enabled from their dev-dependencies).
+
+use crate::cache::{DirEntry, DiskCache};
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+
+/// A directory at `path` listing `children`, modified now
+///
+/// Tests adjust the rest with struct update syntax, e.g.
+/// `DirEntry { is_hidden: true, ..test_entry(path, &[]) }`.
+pub fn test_entry(path: impl AsRef<Path>, children: &[&str]) -> DirEntry {
+    let path = path.as_ref();
+    DirEntry {
+        path: path.to_path_buf(),
+        name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        modified: Utc::now(),
+        children: children.iter().collect(),
+        is_dir: true,
+        ..Default::default()
+    }
+}
+
+/// A file of `size` bytes at `path`, modified now
+pub fn test_file(path: impl AsRef<Path>, size: u64) -> DirEntry {
+    DirEntry { is_dir: false, size, ..test_entry(path, &[]) }
+}
+
+/// Small cache: /scan -> {alpha -> {deep}, "beta, \"quoted\""}
+pub fn fixture_cache() -> DiskCache {
+    let root = PathBuf::from("/scan");
+    let alpha = root.join("alpha");
+    let beta = root.join("beta, \"quoted\"");
+
+    let mut cache = DiskCache::new_empty();
+    cache.root = root.clone();
+    cache.entries.insert(root.clone(), test_entry(&root, &["alpha", "beta, \"quoted\""]));
+    cache.entries.insert(alpha.clone(), test_entry(&alpha, &["deep"]));
+    cache.entries.insert(alpha.join("deep"), test_entry(alpha.join("deep"), &[]));
+    cache.entries.insert(beta.clone(), test_entry(&beta, &[]));
+    cache
+}
@@ -0,0 +1,255 @@
+use crate::cache::{format_mtime, format_size, DirEntry};
+use chrono::Utc;
+use ptree_core::{MtimeFormat, PTreeError, PTreeResult};
+use std::path::Path;
+
+// ============================================================================
+// Entry Format Templates (--entry-format)
+// ============================================================================
+
+/// Value a placeholder expands to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Path,
+    Size,
+    Mtime,
+    Depth,
+    Children,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "name" => Some(Field::Name),
+            "path" => Some(Field::Path),
+            "size" => Some(Field::Size),
+            "mtime" => Some(Field::Mtime),
+            "depth" => Some(Field::Depth),
+            "children" => Some(Field::Children),
+            _ => None,
+        }
+    }
+
+    /// Numbers right-align by default, text left-aligns (as in `format!`)
+    fn default_align(self) -> Align {
+        match self {
+            Field::Size | Field::Depth | Field::Children => Align::Right,
+            _ => Align::Left,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field {
+        field: Field,
+        align: Option<Align>,
+        width: usize,
+    },
+}
+
+/// Per-entry data a template is rendered against
+pub struct EntryContext<'a> {
+    pub name: &'a str,
+    pub path: &'a Path,
+    pub entry: Option<&'a DirEntry>,
+    /// Depth below the display root (its direct children are depth 1)
+    pub depth: usize,
+    pub mtime_format: MtimeFormat,
+}
+
+/// A parsed `--entry-format` template such as `{name:<30} {children:>6}`
+///
+/// Supports `{{`/`}}` escapes and `<`, `>`, `^` alignment with a width.
+/// Parsing validates every placeholder, so a bad template fails once up
+/// front instead of on every rendered line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryTemplate {
+    segments: Vec<Segment>,
+}
+
+impl EntryTemplate {
+    pub fn parse(template: &str) -> PTreeResult<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => return Err(format_error(format!("unclosed '{{{}'", spec))),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(parse_placeholder(&spec)?);
+                }
+                '}' => return Err(format_error("unmatched '}' (use '}}' for a literal brace)".to_string())),
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(EntryTemplate { segments })
+    }
+
+    pub fn render(&self, ctx: &EntryContext) -> String {
+        let mut line = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => line.push_str(text),
+                Segment::Field { field, align, width } => {
+                    let value = field_value(*field, ctx);
+                    pad(&mut line, &value, align.unwrap_or(field.default_align()), *width);
+                }
+            }
+        }
+        line
+    }
+}
+
+/// Parse the inside of `{...}`: a field name with an optional `:[<>^]width`
+fn parse_placeholder(spec: &str) -> PTreeResult<Segment> {
+    let (name, modifier) = match spec.split_once(':') {
+        Some((name, modifier)) => (name, Some(modifier)),
+        None => (spec, None),
+    };
+
+    let field = Field::from_name(name.trim())
+        .ok_or_else(|| format_error(format!("unknown placeholder '{{{}}}'", name)))?;
+
+    let (align, width) = match modifier {
+        None => (None, 0),
+        Some(modifier) => {
+            let (align, digits) = match modifier.chars().next() {
+                Some('<') => (Some(Align::Left), &modifier[1..]),
+                Some('>') => (Some(Align::Right), &modifier[1..]),
+                Some('^') => (Some(Align::Center), &modifier[1..]),
+                _ => (None, modifier),
+            };
+            let width = digits
+                .parse::<usize>()
+                .map_err(|_| format_error(format!("invalid width in '{{{}}}'", spec)))?;
+            (align, width)
+        }
+    };
+
+    Ok(Segment::Field { field, align, width })
+}
+
+fn field_value(field: Field, ctx: &EntryContext) -> String {
+    match field {
+        Field::Name => ctx.name.to_string(),
+        Field::Path => ctx.path.to_string_lossy().into_owned(),
+        // A directory's total, marked when part of it was skipped (as `--du` shows it)
+        Field::Size => ctx
+            .entry
+            .map(|e| format!("{}{}", if e.size_is_lower_bound { "≥ " } else { "" }, format_size(e.total_size)))
+            .unwrap_or_else(|| "-".to_string()),
+        Field::Mtime => ctx
+            .entry
+            .map(|e| format_mtime(e.modified, ctx.mtime_format, Utc::now()))
+            .unwrap_or_else(|| "-".to_string()),
+        Field::Depth => ctx.depth.to_string(),
+        Field::Children => ctx
+            .entry
+            .map(|e| e.children.len().to_string())
+            .unwrap_or_else(|| "-".to_string()),
+    }
+}
+
+fn pad(line: &mut String, value: &str, align: Align, width: usize) {
+    let fill = width.saturating_sub(value.chars().count());
+    let (left, right) = match align {
+        Align::Left => (0, fill),
+        Align::Right => (fill, 0),
+        Align::Center => (fill / 2, fill - fill / 2),
+    };
+    line.extend(std::iter::repeat_n(' ', left));
+    line.push_str(value);
+    line.extend(std::iter::repeat_n(' ', right));
+}
+
+fn format_error(message: String) -> PTreeError {
+    PTreeError::Format(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use std::path::PathBuf;
+
+    fn render(template: &str) -> String {
+        let path = PathBuf::from("/scan/alpha");
+        let entry = DirEntry {
+            modified: chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            total_size: 1536,
+            ..test_entry(&path, &["a", "b", "c"])
+        };
+        let ctx = EntryContext {
+            name: "alpha",
+            path: &path,
+            entry: Some(&entry),
+            depth: 2,
+            mtime_format: MtimeFormat::Rfc3339,
+        };
+        EntryTemplate::parse(template).unwrap().render(&ctx)
+    }
+
+    #[test]
+    fn test_placeholders_and_escaping() {
+        assert_eq!(render("{name} ({children})"), "alpha (3)");
+        assert_eq!(render("{path}|{depth}|{size}|{mtime}"), "/scan/alpha|2|1.5 KiB|2024-01-02T03:04:05Z");
+        assert_eq!(render("{{name}} = {name}}}"), "{name} = alpha}");
+        assert_eq!(render("plain text"), "plain text");
+    }
+
+    #[test]
+    fn test_width_and_alignment() {
+        assert_eq!(render("[{name:>8}]"), "[   alpha]");
+        assert_eq!(render("[{name:<8}]"), "[alpha   ]");
+        assert_eq!(render("[{name:^9}]"), "[  alpha  ]");
+        assert_eq!(render("[{children:4}]"), "[   3]");
+        assert_eq!(render("[{size:9}]"), "[  1.5 KiB]");
+        assert_eq!(render("[{name:2}]"), "[alpha]");
+    }
+
+    #[test]
+    fn test_invalid_templates_fail_at_parse_time() {
+        for bad in ["{nope}", "{name", "name}", "{name:>x}", "{size:}"] {
+            assert!(
+                matches!(EntryTemplate::parse(bad), Err(PTreeError::Format(_))),
+                "{} should be rejected",
+                bad
+            );
+        }
+    }
+}
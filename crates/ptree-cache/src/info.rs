@@ -0,0 +1,391 @@
+use crate::bloom::BloomSummary;
+use crate::cache::{format_count, format_mtime, format_size, DiskCache, USNJournalState};
+use crate::cache_limcode::{limcode_paths, LimcodeCache, LIMCODE_FORMAT_VERSION};
+use crate::cache_rkyv::{read_index_summary, COMPACT_DEAD_RATIO};
+use crate::backend::{backend_files, stored_backend};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use ptree_core::{CacheBackend, MtimeFormat, PTreeError};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Cache Description (ptree cache-info)
+// ============================================================================
+
+/// What the files of one cache hold, as read from the index alone
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheInfo {
+    /// Storage the cache was saved with; the database backends keep one file, as `data_path`
+    pub backend: CacheBackend,
+    pub index_path: PathBuf,
+    pub data_path: PathBuf,
+    /// On-disk sizes (0 for a missing data file); a sharded cache's data adds up every shard's
+    pub index_bytes: u64,
+    pub data_bytes: u64,
+    /// Shards by top-level directory; 0 for a cache saved as a single pair
+    pub shards: usize,
+    /// Format version of the index
+    pub version: u32,
+    pub compressed: bool,
+    pub encrypted: bool,
+    pub entries: usize,
+    pub root: PathBuf,
+    pub last_scan: DateTime<Utc>,
+    /// Saved USN journal position; None off Windows, where none is kept
+    pub usn_state: Option<USNJournalState>,
+    /// Data file bytes no entry points at; None when the data file is not the index's pair
+    pub dead_bytes: Option<u64>,
+    /// Records failing their checksum (blocks, when compressed); None when
+    /// the data file is not the index's pair, and for the database backends
+    pub corrupt_records: Option<usize>,
+    /// Path filter saved with the index; None for the database backends and caches saved before filters
+    pub bloom: Option<BloomSummary>,
+    /// Time taken to map and decode the index (for Limcode, and validate every
+    /// record); None for the database backends
+    pub index_open_time: Option<Duration>,
+}
+
+impl DiskCache {
+    /// Describe the cache at `path` without loading any entry (`ptree cache-info`)
+    ///
+    /// Nothing is repaired, migrated, or compacted, so this is safe to run
+    /// against a cache another process is using.
+    pub fn info(path: &Path) -> Result<CacheInfo> {
+        let index_path = path.with_extension("idx");
+        let data_path = path.with_extension("dat");
+        if let Some(backend @ (CacheBackend::Sqlite | CacheBackend::Kv)) = stored_backend(path) {
+            return Self::database_info(path, backend);
+        }
+        if stored_backend(path) == Some(CacheBackend::Limcode) {
+            return Self::limcode_info(path);
+        }
+        if !index_path.exists() {
+            return Err(PTreeError::Cache(format!("no cache at {}; run ptree once to scan", index_path.display())).into());
+        }
+
+        let summary = read_index_summary(&index_path, &data_path)?;
+        let size = |file: &Path| fs::metadata(file).map_or(0, |metadata| metadata.len());
+        let shards = summary.shards.unwrap_or_default();
+        Ok(CacheInfo {
+            backend: CacheBackend::Rkyv,
+            index_bytes: size(&index_path),
+            data_bytes: size(&data_path) + shards.data_len(),
+            shards: shards.shards.len(),
+            index_path,
+            data_path,
+            version: summary.version,
+            compressed: summary.compressed,
+            encrypted: summary.encrypted,
+            entries: summary.index.offsets.len() + shards.entries(),
+            root: summary.index.root,
+            last_scan: summary.index.last_scan,
+            #[cfg(windows)]
+            usn_state: Some(summary.index.usn_state),
+            #[cfg(not(windows))]
+            usn_state: None,
+            dead_bytes: summary.dead_bytes.map(|dead| dead + shards.dead_bytes()),
+            corrupt_records: summary.corrupt_records,
+            bloom: summary.bloom.as_ref().map(|bloom| bloom.summary()),
+            index_open_time: Some(summary.open_time),
+        })
+    }
+
+    /// `info` for the Limcode backend, opening the pair as a load would
+    fn limcode_info(path: &Path) -> Result<CacheInfo> {
+        let (index_path, data_path) = limcode_paths(path);
+        let _lock = crate::lock::CacheLock::shared(path)?;
+        let started = Instant::now();
+        let cache = LimcodeCache::open(&index_path, &data_path)?;
+        let open_time = started.elapsed();
+        let size = |file: &Path| fs::metadata(file).map_or(0, |metadata| metadata.len());
+        let index = &cache.index;
+        Ok(CacheInfo {
+            backend: CacheBackend::Limcode,
+            index_bytes: size(&index_path),
+            data_bytes: size(&data_path),
+            shards: 0,
+            index_path,
+            data_path,
+            version: LIMCODE_FORMAT_VERSION,
+            compressed: false,
+            encrypted: false,
+            entries: index.offsets.len(),
+            root: PathBuf::from(&index.root),
+            last_scan: DateTime::<Utc>::from_timestamp(index.last_scan_timestamp, index.last_scan_nanos).unwrap_or_default(),
+            #[cfg(windows)]
+            usn_state: Some(USNJournalState { journal_id: index.usn_journal_id, last_usn: index.usn_last }),
+            #[cfg(not(windows))]
+            usn_state: None,
+            dead_bytes: None,
+            corrupt_records: Some(cache.corrupt_records().len()),
+            bloom: None,
+            index_open_time: Some(open_time),
+        })
+    }
+
+    /// `info` for the database backends, from their metadata and entry count
+    fn database_info(path: &Path, backend: CacheBackend) -> Result<CacheInfo> {
+        let (cache, entries, version) = match backend {
+            CacheBackend::Sqlite => (Self::open_sqlite(path)?, Self::sqlite_len(path)?, crate::cache_sqlite::SQLITE_SCHEMA_VERSION),
+            _ => (Self::open_kv(path)?, Self::kv_len(path)?, crate::cache_kv::KV_SCHEMA_VERSION),
+        };
+        let database = backend_files(backend, path).remove(0);
+        Ok(CacheInfo {
+            backend,
+            index_path: database.clone(),
+            index_bytes: 0,
+            data_bytes: fs::metadata(&database).map_or(0, |metadata| metadata.len()),
+            shards: 0,
+            data_path: database,
+            version,
+            compressed: false,
+            encrypted: false,
+            entries,
+            root: cache.root.clone(),
+            last_scan: cache.last_scan,
+            #[cfg(windows)]
+            usn_state: Some(cache.usn_position()),
+            #[cfg(not(windows))]
+            usn_state: None,
+            dead_bytes: None,
+            corrupt_records: None,
+            bloom: None,
+            index_open_time: None,
+        })
+    }
+}
+
+impl CacheInfo {
+    /// Whether `DiskCache::open` would compact the data file
+    pub fn needs_compaction(&self) -> bool {
+        self.dead_bytes
+            .is_some_and(|dead| self.data_bytes > 0 && dead as f64 > self.data_bytes as f64 * COMPACT_DEAD_RATIO)
+    }
+
+    /// Human-readable report, one field per line, with ages measured back from `now`
+    pub fn to_text(&self, now: DateTime<Utc>) -> String {
+        let mut out = String::new();
+        let mut line = |label: &str, value: String| out.push_str(&format!("{:<14} {}\n", label, value));
+        line("Backend:", self.backend.name().to_string());
+        if matches!(self.backend, CacheBackend::Sqlite | CacheBackend::Kv) {
+            line("Database:", format!("{} ({})", self.data_path.display(), format_size(self.data_bytes)));
+        } else if self.backend == CacheBackend::Limcode {
+            line("Index:", format!("{} ({})", self.index_path.display(), format_size(self.index_bytes)));
+            if let Some(open) = self.index_open_time {
+                line("Index open:", format!("{:.1} ms", open.as_secs_f64() * 1000.0));
+            }
+            line("Data:", format!("{} ({})", self.data_path.display(), format_size(self.data_bytes)));
+        } else {
+            line("Index:", format!("{} ({})", self.index_path.display(), format_size(self.index_bytes)));
+            if let Some(open) = self.index_open_time {
+                line("Index open:", format!("{:.1} ms", open.as_secs_f64() * 1000.0));
+            }
+            line(
+                "Data:",
+                format!(
+                    "{} ({}{}{})",
+                    self.data_path.display(),
+                    format_size(self.data_bytes),
+                    if self.compressed { ", zstd blocks" } else { "" },
+                    if self.encrypted { ", encrypted" } else { "" }
+                ),
+            );
+            if self.shards > 0 {
+                line("Shards:", format!("{} (by top-level directory)", self.shards));
+            }
+            line(
+                "Bloom filter:",
+                match &self.bloom {
+                    Some(bloom) => format!(
+                        "{}, {}% false positives (built for {}%)",
+                        format_size(bloom.bytes as u64),
+                        format_rate(bloom.estimated_fp_rate),
+                        format_rate(bloom.fp_rate)
+                    ),
+                    None => "none (saved before filters; rebuilt on next full save)".to_string(),
+                },
+            );
+        }
+        line("Format:", format!("version {}", self.version));
+        line("Entries:", format_count(self.entries));
+        line("Root:", self.root.display().to_string());
+        line(
+            "Last scan:",
+            format!(
+                "{} ({})",
+                format_mtime(self.last_scan, MtimeFormat::Rfc3339, now),
+                format_mtime(self.last_scan, MtimeFormat::Relative, now)
+            ),
+        );
+        line(
+            "USN journal:",
+            match &self.usn_state {
+                Some(usn) if usn.journal_id == 0 => "no position saved".to_string(),
+                Some(usn) => format!("journal {:#x}, last USN {}", usn.journal_id, usn.last_usn),
+                None => "not tracked (Windows only)".to_string(),
+            },
+        );
+        // The database backends reuse their free pages themselves
+        if self.backend == CacheBackend::Rkyv {
+            line(
+                "Dead space:",
+                match self.dead_bytes {
+                    Some(dead) => format!(
+                        "{} ({:.0}% of data{})",
+                        format_size(dead),
+                        percent(dead, self.data_bytes),
+                        if self.needs_compaction() { "; compacted on next load" } else { "" }
+                    ),
+                    None => "unknown (data file does not match the index)".to_string(),
+                },
+            );
+        }
+        if let Some(corrupt) = self.corrupt_records {
+            line(
+                "Corrupt:",
+                match corrupt {
+                    0 => "none".to_string(),
+                    _ => format!(
+                        "{} {} fail their checksum (read as missing; --verify-cache lists them)",
+                        format_count(corrupt),
+                        if self.compressed { "blocks" } else { "records" }
+                    ),
+                },
+            );
+        }
+        out.truncate(out.trim_end().len());
+        out
+    }
+
+    pub fn to_json(&self, now: DateTime<Utc>) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&json!({
+            "backend": self.backend.name(),
+            "index": { "path": self.index_path.to_string_lossy(), "bytes": self.index_bytes, "open_ms": self.index_open_time.map(|open| open.as_secs_f64() * 1000.0) },
+            "data": { "path": self.data_path.to_string_lossy(), "bytes": self.data_bytes, "compressed": self.compressed, "encrypted": self.encrypted },
+            "shards": self.shards,
+            "version": self.version,
+            "entries": self.entries,
+            "root": self.root.to_string_lossy(),
+            "last_scan": self.last_scan.to_rfc3339(),
+            "last_scan_age_seconds": (now - self.last_scan).num_seconds().max(0),
+            "usn": self.usn_state.as_ref().map(|usn| json!({ "journal_id": usn.journal_id, "last_usn": usn.last_usn })),
+            "dead_bytes": self.dead_bytes,
+            "corrupt_records": self.corrupt_records,
+            "needs_compaction": self.needs_compaction(),
+            "bloom": self.bloom.map(|bloom| json!({ "bytes": bloom.bytes, "fp_rate": bloom.fp_rate, "estimated_fp_rate": bloom.estimated_fp_rate })),
+        }))?)
+    }
+}
+
+/// `rate` as a percentage with two significant digits (`1`, `0.25`, `0.001`)
+fn format_rate(rate: f64) -> String {
+    let percent = rate * 100.0;
+    let decimals = if percent >= 10.0 { 0 } else { (1.0 - percent.log10().floor()).clamp(1.0, 9.0) as usize };
+    let text = format!("{:.*}", decimals, percent);
+    match text.contains('.') {
+        true => text.trim_end_matches('0').trim_end_matches('.').to_string(),
+        false => text,
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_entry;
+    use crate::children::ChildNames;
+    use crate::cache_rkyv::{RkyvDirEntry, RkyvMmapCache};
+
+
+    #[test]
+    fn test_info_reads_the_index_alone() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_info_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+        let err = DiskCache::info(&cache_path).err().unwrap();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::Cache(_))));
+
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        cache.last_scan = Utc::now() - chrono::Duration::hours(3);
+        for path in ["/scan", "/scan/a", "/scan/b"] {
+            cache.entries.insert(PathBuf::from(path), test_entry(path, &[]));
+        }
+        cache.entries.get_mut("/scan").unwrap().children = ["a", "b"].into_iter().collect();
+        cache.save(&cache_path)?;
+
+        let info = DiskCache::info(&cache_path)?;
+        assert_eq!((info.entries, info.root.as_path(), info.dead_bytes), (3, Path::new("/scan"), Some(0)));
+        assert_eq!(info.data_bytes, fs::metadata(&info.data_path)?.len());
+        let text = info.to_text(Utc::now());
+        assert!(text.starts_with("Backend:       rkyv\n"), "{}", text);
+        assert!(text.contains("Entries:       3\n"), "{}", text);
+        assert!(text.contains("(3h ago)"), "{}", text);
+        assert!(text.contains("Bloom filter:  64 B, 0"), "{}", text);
+        assert!(text.contains("(built for 1%)"), "{}", text);
+        assert!(text.contains("Corrupt:       none"), "{}", text);
+        assert!(text.contains("\nIndex open:    ") && text.contains(" ms\n"), "{}", text);
+
+        // Rewriting one entry leaves its old record behind
+        let index_path = cache_path.with_extension("idx");
+        let mut rkyv = RkyvMmapCache::open(&index_path, &cache_path)?;
+        let a = test_entry("/scan/a", &[]);
+        let offset = rkyv.append_entry(&RkyvDirEntry {
+            path: a.path,
+            name: a.name,
+            modified: a.modified,
+            content_hash: 0,
+            children: ChildNames::new(),
+            symlink_target: None,
+            is_hidden: false,
+            is_dir: true,
+            size: 0,
+            skipped_children: false,
+            is_mount: false,
+            access_error: None,
+            evicted: false,
+            file_count: 0,
+            dir_count: 0,
+            total_size: 0,
+            size_is_lower_bound: false,
+            spilled_children: 0,
+        })?;
+        rkyv.index.offsets.insert(PathBuf::from("/scan/a"), offset);
+        rkyv.save_index(&index_path)?;
+        drop(rkyv);
+
+        let info = DiskCache::info(&cache_path)?;
+        assert!(info.dead_bytes.unwrap() > 0);
+        assert_eq!(info.corrupt_records, Some(0));
+
+        // A flipped byte in a live record is counted, not decoded
+        let mut data = fs::read(&cache_path)?;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&cache_path, data)?;
+        let info = DiskCache::info(&cache_path)?;
+        assert_eq!(info.corrupt_records, Some(1));
+        assert!(info.to_text(Utc::now()).contains("Corrupt:       1 records fail their checksum"));
+
+        let json: serde_json::Value = serde_json::from_str(&info.to_json(Utc::now())?)?;
+        assert_eq!(json["entries"], 3);
+        assert!(json["index"]["open_ms"].as_f64().unwrap() >= 0.0);
+        assert_eq!(json["dead_bytes"], info.dead_bytes.unwrap());
+        assert_eq!(json["corrupt_records"], 1);
+        assert_eq!(json["bloom"]["fp_rate"], 0.01);
+        assert!(json["last_scan_age_seconds"].as_i64().unwrap() >= 3 * 3600);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
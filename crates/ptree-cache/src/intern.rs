@@ -0,0 +1,503 @@
+use crate::bloom::PathBloom;
+use crate::cache::{DirEntry, FastHashMap};
+use rayon::prelude::*;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::ops::Index;
+use std::path::{Component, Path, PathBuf};
+
+// ============================================================================
+// Interned Path Table (DiskCache::entries)
+// ============================================================================
+
+/// Stable id of a node in an `EntryMap`, valid until its entry is removed
+pub type NodeId = u64;
+
+/// The node above every first path component (`/`, `C:`, or a relative name)
+const TOP: NodeId = 0;
+
+#[derive(Clone, Default)]
+struct Node {
+    parent: NodeId,
+    /// Child nodes by name: each name is stored once, here, never as part of a full path
+    children: FastHashMap<Box<OsStr>, NodeId>,
+    entry: Option<DirEntry>,
+}
+
+/// Cached entries keyed by path, stored as a tree of interned path components
+///
+/// Each path component is a node holding its parent's id and, keyed by
+/// name, its children; a path is found by walking its components from the
+/// top. Shared prefixes (every entry below `C:\Users\me\src`) are stored
+/// once rather than in every key. The full path lives only in the entry's
+/// `path`, which `insert` sets to the key it was given.
+///
+/// Nodes without an entry (ancestors of a scanned root) exist only while
+/// something below them is cached; removed nodes' ids are reused. Iteration
+/// follows node order, not path order.
+///
+/// A map loaded from a saved cache can carry that cache's `PathBloom`
+/// (`attach_bloom`): lookups of paths it rules out return None without
+/// walking a component, and `insert` adds to it so it never rules out a
+/// cached path.
+#[derive(Clone)]
+pub struct EntryMap {
+    nodes: Vec<Node>,
+    free: Vec<NodeId>,
+    len: usize,
+    bloom: Option<PathBloom>,
+}
+
+impl Default for EntryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Borrowing iterator over an `EntryMap`'s paths and entries
+pub struct Iter<'a>(std::slice::Iter<'a, Node>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a PathBuf, &'a DirEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.find_map(|node| node.entry.as_ref().map(|entry| (&entry.path, entry)))
+    }
+}
+
+/// Owning iterator over an `EntryMap`'s paths and entries
+pub struct IntoIter(std::vec::IntoIter<Node>);
+
+impl Iterator for IntoIter {
+    type Item = (PathBuf, DirEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.find_map(|node| node.entry.map(|entry| (entry.path.clone(), entry)))
+    }
+}
+
+impl EntryMap {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// An empty map with room for `capacity` nodes
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut nodes = Vec::with_capacity(capacity.max(1));
+        nodes.push(Node::default());
+        EntryMap { nodes, free: Vec::new(), len: 0, bloom: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Insert `entry` at `path`, returning the entry it replaces
+    ///
+    /// The entry's `path` is set to `path` if it differs.
+    pub fn insert(&mut self, path: PathBuf, mut entry: DirEntry) -> Option<DirEntry> {
+        if let Some(bloom) = &mut self.bloom {
+            bloom.insert(&path);
+        }
+        let id = self.intern(&path);
+        if entry.path != path {
+            entry.path = path;
+        }
+        let previous = self.nodes[id as usize].entry.replace(entry);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    pub fn get<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Option<&DirEntry> {
+        self.id(path).and_then(|id| self.nodes[id as usize].entry.as_ref())
+    }
+
+    pub fn get_mut<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Option<&mut DirEntry> {
+        let id = self.id(path)?;
+        self.nodes[id as usize].entry.as_mut()
+    }
+
+    /// Use `bloom` to rule out lookups; it has to cover every path already in the map
+    pub fn attach_bloom(&mut self, bloom: Option<PathBloom>) {
+        self.bloom = bloom;
+    }
+
+    /// False when `path` is certainly not in the map; always true without a filter
+    pub fn may_contain<P: AsRef<Path> + ?Sized>(&self, path: &P) -> bool {
+        self.bloom.as_ref().is_none_or(|bloom| bloom.may_contain(path.as_ref()))
+    }
+
+    pub fn get_key_value<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Option<(&PathBuf, &DirEntry)> {
+        self.get(path).map(|entry| (&entry.path, entry))
+    }
+
+    pub fn contains_key<P: AsRef<Path> + ?Sized>(&self, path: &P) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// Id of the entry at `path`
+    pub fn id<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Option<NodeId> {
+        let path = path.as_ref();
+        if !self.may_contain(path) {
+            return None;
+        }
+        self.find(path).filter(|&id| self.nodes[id as usize].entry.is_some())
+    }
+
+    /// The entry with id `id`, if it is still cached
+    pub fn get_by_id(&self, id: NodeId) -> Option<&DirEntry> {
+        self.nodes.get(id as usize).and_then(|node| node.entry.as_ref())
+    }
+
+    /// Id of the entry named `name` directly below the entry `parent`
+    ///
+    /// Follows the parent's node to the child's, so walking a tree by id
+    /// never rebuilds or re-walks a full path. A name that differs from the
+    /// cached one only in case (a listing from a case-insensitive volume)
+    /// matches when no other sibling does.
+    pub fn child_id(&self, parent: NodeId, name: &str) -> Option<NodeId> {
+        let node = self.nodes.get(parent as usize)?;
+        let id = self.child_node(node, OsStr::new(name))?;
+        self.nodes[id as usize].entry.as_ref().map(|_| id)
+    }
+
+    /// Id of the entry at `path`, forgiving case and separator differences
+    ///
+    /// Tries the exact key first. Failing that, names are split on both `/`
+    /// and `\` and each is matched as in `child_id`, so a key stored as
+    /// `C:\Users\Me` is still found from `c:/users/me/`.
+    pub fn id_normalized<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Option<NodeId> {
+        let path = path.as_ref();
+        if let Some(id) = self.id(path) {
+            return Some(id);
+        }
+        let mut id = TOP;
+        for component in path.components() {
+            let names: Vec<&OsStr> = match component {
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) => name.split(['/', '\\']).filter(|n| !n.is_empty()).map(OsStr::new).collect(),
+                    None => vec![name],
+                },
+                other => vec![other.as_os_str()],
+            };
+            for name in names {
+                id = self.child_node(&self.nodes[id as usize], name)?;
+            }
+        }
+        self.nodes[id as usize].entry.as_ref().map(|_| id)
+    }
+
+    /// The entry at `path`, found as by `id_normalized`
+    pub fn get_normalized<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Option<&DirEntry> {
+        self.id_normalized(path).and_then(|id| self.get_by_id(id))
+    }
+
+    /// Remove the entry at `path` alone, leaving anything cached below it
+    pub fn remove<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Option<DirEntry> {
+        let path = path.as_ref();
+        let id = self.find(path)?;
+        let entry = self.nodes[id as usize].entry.take()?;
+        self.len -= 1;
+        self.prune(id, path);
+        Some(entry)
+    }
+
+    /// Remove the entry at `path` and everything cached below it, returning how many entries went
+    ///
+    /// Walks the node's children, so only true descendants go: removing
+    /// `/a/foo` leaves `/a/foobar` alone.
+    pub fn remove_subtree<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> usize {
+        let path = path.as_ref();
+        let Some(id) = self.find(path).filter(|&id| id != TOP) else {
+            return 0;
+        };
+        let parent = self.nodes[id as usize].parent;
+        if let Some(name) = path.components().next_back() {
+            self.nodes[parent as usize].children.remove(name.as_os_str());
+        }
+
+        let mut removed = 0;
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let node = std::mem::take(&mut self.nodes[id as usize]);
+            removed += usize::from(node.entry.is_some());
+            stack.extend(node.children.into_values());
+            self.free.push(id);
+        }
+        self.len -= removed;
+        if let Some(parent_path) = path.parent() {
+            self.prune(parent, parent_path);
+        }
+        removed
+    }
+
+    /// Keep only the entries for which `keep` returns true
+    pub fn retain<F: FnMut(&PathBuf, &DirEntry) -> bool>(&mut self, mut keep: F) {
+        for id in 0..self.nodes.len() {
+            let Some(entry) = self.nodes[id].entry.as_ref() else {
+                continue;
+            };
+            if keep(&entry.path, entry) {
+                continue;
+            }
+            let entry = self.nodes[id].entry.take().expect("checked above");
+            self.len -= 1;
+            self.prune(id as NodeId, &entry.path);
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &PathBuf> {
+        self.values().map(|entry| &entry.path)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &DirEntry> {
+        self.nodes.iter().filter_map(|node| node.entry.as_ref())
+    }
+
+    /// Entries, mutably; changing an entry's `path` does not move it
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut DirEntry> {
+        self.nodes.iter_mut().filter_map(|node| node.entry.as_mut())
+    }
+
+    /// Entries of up to `n` nodes spread evenly over the node table, for estimates that can't afford a full pass
+    pub fn sample(&self, n: usize) -> impl Iterator<Item = &DirEntry> {
+        let step = self.nodes.len().div_ceil(n.max(1)).max(1);
+        self.nodes.iter().step_by(step).filter_map(|node| node.entry.as_ref())
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.nodes.iter())
+    }
+
+    /// `iter` spread over rayon's pool
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (&PathBuf, &DirEntry)> {
+        self.nodes.par_iter().filter_map(|node| node.entry.as_ref().map(|entry| (&entry.path, entry)))
+    }
+
+    /// Node of `path`, walking its components from the top
+    fn find(&self, path: &Path) -> Option<NodeId> {
+        path.components().try_fold(TOP, |id, component| {
+            self.nodes[id as usize].children.get(component.as_os_str()).copied()
+        })
+    }
+
+    /// Child of `node` named `name`, or the only one named so ignoring case
+    fn child_node(&self, node: &Node, name: &OsStr) -> Option<NodeId> {
+        if let Some(&id) = node.children.get(name) {
+            return Some(id);
+        }
+        let folded = name.to_str()?.to_lowercase();
+        let mut matches = node
+            .children
+            .iter()
+            .filter(|(child, _)| child.to_str().is_some_and(|child| child.to_lowercase() == folded));
+        match (matches.next(), matches.next()) {
+            (Some((_, &id)), None) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Node of `path`, creating whatever part of the chain is missing
+    fn intern(&mut self, path: &Path) -> NodeId {
+        let mut id = TOP;
+        for component in path.components() {
+            let name = component.as_os_str();
+            id = match self.nodes[id as usize].children.get(name) {
+                Some(&child) => child,
+                None => {
+                    let child = self.alloc(id);
+                    self.nodes[id as usize].children.insert(name.into(), child);
+                    child
+                }
+            };
+        }
+        id
+    }
+
+    fn alloc(&mut self, parent: NodeId) -> NodeId {
+        let node = Node { parent, ..Node::default() };
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as NodeId
+            }
+        }
+    }
+
+    /// Free `id` (the node of `path`) and its ancestors while they hold nothing
+    fn prune(&mut self, mut id: NodeId, path: &Path) {
+        let mut names = path.components().rev();
+        while id != TOP {
+            let node = &self.nodes[id as usize];
+            let Some(name) = names.next() else {
+                break;
+            };
+            let parent = node.parent;
+            if node.entry.is_some()
+                || !node.children.is_empty()
+                || self.nodes[parent as usize].children.get(name.as_os_str()) != Some(&id)
+            {
+                break;
+            }
+            self.nodes[parent as usize].children.remove(name.as_os_str());
+            self.free.push(id);
+            id = parent;
+        }
+    }
+}
+
+impl<P: AsRef<Path>> Index<P> for EntryMap {
+    type Output = DirEntry;
+
+    fn index(&self, path: P) -> &DirEntry {
+        let path = path.as_ref();
+        self.get(path).unwrap_or_else(|| panic!("no cached entry for {}", path.display()))
+    }
+}
+
+impl<'a> IntoIterator for &'a EntryMap {
+    type Item = (&'a PathBuf, &'a DirEntry);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for EntryMap {
+    type Item = (PathBuf, DirEntry);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter(self.nodes.into_iter())
+    }
+}
+
+impl Extend<(PathBuf, DirEntry)> for EntryMap {
+    fn extend<T: IntoIterator<Item = (PathBuf, DirEntry)>>(&mut self, entries: T) {
+        for (path, entry) in entries {
+            self.insert(path, entry);
+        }
+    }
+}
+
+impl FromIterator<(PathBuf, DirEntry)> for EntryMap {
+    fn from_iter<T: IntoIterator<Item = (PathBuf, DirEntry)>>(entries: T) -> Self {
+        let mut map = EntryMap::new();
+        map.extend(entries);
+        map
+    }
+}
+
+impl fmt::Debug for EntryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Serialized as a plain path → entry map, as before interning
+impl Serialize for EntryMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for EntryMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = HashMap::<PathBuf, DirEntry>::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_entry;
+
+
+    fn map(paths: &[&str]) -> EntryMap {
+        paths.iter().map(|path| (PathBuf::from(path), test_entry(path, &[]))).collect()
+    }
+
+    fn sorted_keys(map: &EntryMap) -> Vec<&str> {
+        let mut keys: Vec<&str> = map.keys().map(|path| path.to_str().unwrap()).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn test_lookups_walk_components_and_share_prefixes() {
+        let mut map = map(&["/scan", "/scan/a", "/scan/a/x", "/other/deep/b"]);
+        assert_eq!(map.len(), 4);
+        assert!(map.contains_key("/scan/a/x/"));
+        assert!(map.contains_key(Path::new("/scan/./a")));
+        // `/other` and `/other/deep` are interior nodes, not entries
+        assert!(!map.contains_key("/other/deep"));
+        assert_eq!(map.id("/other"), None);
+        assert_eq!(map["/other/deep/b"].name, "b");
+
+        // Re-inserting replaces in place and keeps the id; the key wins over the entry's path
+        let id = map.id("/scan/a").unwrap();
+        assert!(map.insert(PathBuf::from("/scan/a"), test_entry("/elsewhere", &[])).is_some());
+        assert_eq!(map.id("/scan/a"), Some(id));
+        assert_eq!(map.get_by_id(id).unwrap().path, Path::new("/scan/a"));
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn test_subtree_removal_is_a_child_walk() {
+        let mut map = map(&["/a", "/a/foo", "/a/foo/x", "/a/foo/x/y", "/a/foobar", "/a/foo2", "/a/foo/z"]);
+        assert_eq!(map.remove_subtree("/a/foo"), 4);
+        assert_eq!(sorted_keys(&map), ["/a", "/a/foo2", "/a/foobar"]);
+        assert_eq!(map.remove_subtree("/a/missing"), 0);
+
+        // `remove` takes one entry; the nodes of emptied branches are freed and reused
+        let nodes = map.nodes.len();
+        map.insert(PathBuf::from("/a/foo2/q/r"), test_entry("/a/foo2/q/r", &[]));
+        assert_eq!(map.nodes.len(), nodes);
+        assert!(map.remove("/a/foo2").is_some());
+        assert!(map.contains_key("/a/foo2/q/r"));
+        assert!(map.remove("/a/foo2/q/r").is_some());
+        assert!(!map.nodes[map.find(Path::new("/a")).unwrap() as usize].children.contains_key(OsStr::new("foo2")));
+
+        map.retain(|path, _| path != Path::new("/a"));
+        assert_eq!(sorted_keys(&map), ["/a/foobar"]);
+        let copy: EntryMap = map.clone().into_iter().collect();
+        assert_eq!(sorted_keys(&copy), ["/a/foobar"]);
+        assert!(map.remove("/a/foobar").is_some());
+        assert!(map.is_empty());
+        assert!(map.nodes[TOP as usize].children.is_empty());
+    }
+
+    #[test]
+    fn test_normalized_lookups_forgive_case_and_separators() {
+        let map = map(&["/Scan", "/Scan/Docs", "/Scan/Docs/README.md", "/Scan/docs2", "/Scan/Build", "/Scan/build"]);
+        let scan = map.id("/Scan").unwrap();
+        assert_eq!(map.child_id(scan, "Docs"), map.id("/Scan/Docs"));
+        assert_eq!(map.child_id(scan, "DOCS"), map.id("/Scan/Docs"));
+        assert_eq!(map.child_id(scan, "missing"), None);
+        // Two siblings differ only in case: the exact one, and no guess for a third spelling
+        assert_eq!(map.child_id(scan, "build"), map.id("/Scan/build"));
+        assert_eq!(map.child_id(scan, "BUILD"), None);
+
+        assert!(map.get("/scan/docs/readme.md").is_none());
+        assert_eq!(map.get_normalized("/scan/docs/readme.md").unwrap().path, Path::new("/Scan/Docs/README.md"));
+        assert_eq!(map.id_normalized("/scan\\DOCS\\"), map.id("/Scan/Docs"));
+        assert_eq!(map.id_normalized("/Scan/Docs/README.md/"), map.id("/Scan/Docs/README.md"));
+        assert_eq!(map.id_normalized("/scan/docs/other"), None);
+    }
+}
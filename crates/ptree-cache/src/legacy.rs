@@ -0,0 +1,264 @@
+use crate::backend::stored_backend;
+use crate::cache::{DirEntry, DiskCache};
+use crate::children::ChildNames;
+use crate::lock::CacheLock;
+use anyhow::Result;
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use ptree_core::{CacheBackend, PTreeError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Legacy Caches (one monolithic ptree.dat)
+// ============================================================================
+
+/// A `DiskCache` as builds before the index/data pair saved it: the whole struct, bincode-serialized
+///
+/// Only the fields that were serialized, in their order. The Windows
+/// journal state that followed them was a unit struct and took no bytes.
+#[derive(Deserialize)]
+struct LegacyCache {
+    entries: HashMap<PathBuf, LegacyEntry>,
+    last_scan: DateTime<Utc>,
+    root: PathBuf,
+    last_scanned_root: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct LegacyEntry {
+    path: PathBuf,
+    name: String,
+    modified: DateTime<Utc>,
+    content_hash: u64,
+    children: Vec<String>,
+    symlink_target: Option<PathBuf>,
+    is_hidden: bool,
+    is_dir: bool,
+}
+
+/// What `DiskCache::migrate_legacy` did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    /// Entries in the legacy file
+    pub legacy_entries: usize,
+    /// Entries in the index written from them
+    pub migrated_entries: usize,
+    /// Where the legacy file was moved
+    pub backup: PathBuf,
+}
+
+/// The legacy file for the cache path `cache_path`, if one may be there
+///
+/// That is `cache_path` itself, existing while no backend has saved a
+/// cache: the rkyv data file shares its name, but never goes without an
+/// index. Whether it really is a legacy cache takes `read_legacy` to tell.
+pub fn legacy_file(cache_path: &Path) -> Option<PathBuf> {
+    (cache_path.is_file() && stored_backend(cache_path).is_none()).then(|| cache_path.to_path_buf())
+}
+
+/// Where `migrate_legacy` moves the legacy file `file`: the same name with `.bak` appended
+pub fn legacy_backup(file: &Path) -> PathBuf {
+    let mut name = OsString::from(file.as_os_str());
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// Decode the legacy cache in `file` into an in-memory cache
+///
+/// The format has no magic number, so a file is taken for one only if it
+/// decodes whole: lengths are bounded by the file's size, so garbage can't
+/// ask for huge allocations, and trailing bytes fail the decode.
+/// Anything else is `PTreeError::CacheCorrupt`.
+pub fn read_legacy(file: &Path) -> Result<DiskCache> {
+    let bytes = fs::read(file)?;
+    let legacy: LegacyCache = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(bytes.len() as u64)
+        .reject_trailing_bytes()
+        .deserialize(&bytes)
+        .map_err(|e| PTreeError::CacheCorrupt(format!("{} is not a legacy cache: {}", file.display(), e)))?;
+
+    let mut cache = DiskCache::new();
+    cache.root = legacy.root;
+    cache.last_scanned_root = legacy.last_scanned_root;
+    cache.last_scan = legacy.last_scan;
+    let kinds: HashMap<PathBuf, bool> = legacy.entries.values().map(|entry| (entry.path.clone(), entry.is_dir)).collect();
+    cache.entries = legacy
+        .entries
+        .into_values()
+        .map(|entry| {
+            let child_dirs = entry
+                .children
+                .iter()
+                .filter(|name| kinds.get(&entry.path.join(name)).copied().unwrap_or(false))
+                .count() as u32;
+            let entry = DirEntry {
+                file_count: entry.children.len() as u32 - child_dirs,
+                dir_count: child_dirs,
+                children: ChildNames::from(entry.children),
+                path: entry.path,
+                name: entry.name,
+                modified: entry.modified,
+                content_hash: entry.content_hash,
+                symlink_target: entry.symlink_target,
+                is_hidden: entry.is_hidden,
+                is_dir: entry.is_dir,
+                size: 0,
+                skipped_children: false,
+                is_mount: false,
+                access_error: None,
+                evicted: false,
+                // No sizes were recorded: the next scan fills them in
+                total_size: 0,
+                size_is_lower_bound: entry.is_dir,
+                generation: 0,
+                spilled_children: 0,
+            };
+            (entry.path.clone(), entry)
+        })
+        .collect();
+    Ok(cache)
+}
+
+impl DiskCache {
+    /// Convert the legacy cache at `cache_path` to the rkyv index/data pair
+    ///
+    /// The legacy file is renamed to `legacy_backup` before the pair is
+    /// written (the data file takes its name), and renamed back if the
+    /// write fails. None when there is no legacy file, or it doesn't
+    /// decode as one.
+    pub fn migrate_legacy(cache_path: &Path) -> Result<Option<Migration>> {
+        let Some(file) = legacy_file(cache_path) else {
+            return Ok(None);
+        };
+        let backup = legacy_backup(&file);
+        let mut cache = {
+            let _lock = CacheLock::exclusive(cache_path)?;
+            let cache = match read_legacy(&file) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    log::debug!("{:#}", e);
+                    return Ok(None);
+                }
+            };
+            fs::rename(&file, &backup)
+                .map_err(|e| PTreeError::Cache(format!("cannot move {} aside: {}", file.display(), e)))?;
+            cache
+        };
+
+        let legacy_entries = cache.entries.len();
+        cache.backend = CacheBackend::Rkyv;
+        if let Err(e) = cache.save(cache_path) {
+            for written in crate::backend::backend_files(CacheBackend::Rkyv, cache_path) {
+                let _ = fs::remove_file(written);
+            }
+            let _ = fs::rename(&backup, &file);
+            return Err(e);
+        }
+        let migrated_entries = crate::cache_rkyv::RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path.with_extension("dat"))?.len();
+        log::info!(
+            "migrated legacy cache {}: {} entries, {} written; kept the old file as {}",
+            file.display(),
+            legacy_entries,
+            migrated_entries,
+            backup.display()
+        );
+        Ok(Some(Migration { legacy_entries, migrated_entries, backup }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct WrittenCache {
+        entries: HashMap<PathBuf, WrittenEntry>,
+        last_scan: DateTime<Utc>,
+        root: PathBuf,
+        last_scanned_root: PathBuf,
+    }
+
+    #[derive(Serialize)]
+    struct WrittenEntry {
+        path: PathBuf,
+        name: String,
+        modified: DateTime<Utc>,
+        content_hash: u64,
+        children: Vec<String>,
+        symlink_target: Option<PathBuf>,
+        is_hidden: bool,
+        is_dir: bool,
+    }
+
+    /// `/scan` with a directory `a` (holding `x`) and a file `f`, as an old build wrote it
+    fn legacy_bytes() -> Vec<u8> {
+        let modified = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let entry = |path: &str, children: &[&str], is_dir: bool| WrittenEntry {
+            path: PathBuf::from(path),
+            name: path.rsplit('/').next().unwrap().to_string(),
+            modified,
+            content_hash: 7,
+            children: children.iter().map(|name| name.to_string()).collect(),
+            symlink_target: None,
+            is_hidden: false,
+            is_dir,
+        };
+        let entries = [entry("/scan", &["a", "f"], true), entry("/scan/a", &["x"], true), entry("/scan/a/x", &[], true), entry("/scan/f", &[], false)]
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        let cache = WrittenCache { entries, last_scan: modified, root: PathBuf::from("/scan"), last_scanned_root: PathBuf::from("/scan") };
+        bincode::serialize(&cache).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_cache_is_migrated_on_open() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_legacy_open_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+        fs::write(&cache_path, legacy_bytes())?;
+
+        let mut cache = DiskCache::open(&cache_path)?;
+        assert_eq!(cache.load, crate::cache::CacheLoad::Loaded);
+        assert!(cache_path.with_extension("idx").is_file() && legacy_backup(&cache_path).is_file());
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(cache.entries.len(), 4);
+        assert_eq!(cache.build_tree_output_with_depth(None)?, "/scan\n├── a\n│   └── x\n└── f\n");
+        let root = cache.entries.get(Path::new("/scan")).unwrap();
+        assert_eq!((root.dir_count, root.file_count, root.content_hash), (1, 1, 7));
+
+        // Migrated once: the pair is read from now on
+        assert_eq!(DiskCache::migrate_legacy(&cache_path)?, None);
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_migration_reports_counts_and_ignores_other_files() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_legacy_migrate_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+
+        // A truncated file is no legacy cache, and is left alone
+        let bytes = legacy_bytes();
+        fs::write(&cache_path, &bytes[..bytes.len() - 3])?;
+        assert_eq!(DiskCache::migrate_legacy(&cache_path)?, None);
+        assert!(read_legacy(&cache_path).is_err() && !legacy_backup(&cache_path).exists());
+        fs::write(&cache_path, [bytes.as_slice(), b"junk"].concat())?;
+        assert_eq!(DiskCache::migrate_legacy(&cache_path)?, None);
+
+        fs::write(&cache_path, &bytes)?;
+        let migration = DiskCache::migrate_legacy(&cache_path)?.expect("a legacy cache");
+        assert_eq!((migration.legacy_entries, migration.migrated_entries), (4, 4));
+        assert_eq!(fs::read(&migration.backup)?, bytes);
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
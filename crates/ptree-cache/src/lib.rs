@@ -1,8 +1,66 @@
+pub mod archive;
+pub mod backend;
+pub mod background;
+pub mod bloom;
 pub mod cache;
-// pub mod cache_lazy;
-// pub mod cache_limcode;
-// pub mod cache_mmap;
-// pub mod cache_opt;
+pub mod cache_kv;
+pub mod cache_lazy;
+pub mod cache_limcode;
+pub mod cache_mmap;
+pub mod cache_opt;
 pub mod cache_rkyv;
+pub mod cache_sqlite;
+pub mod children;
+pub mod crypt;
+pub mod diff;
+pub mod evict;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
+pub mod format;
+pub mod info;
+pub mod intern;
+pub mod legacy;
+pub mod lock;
+pub mod merge;
+pub mod offsets;
+pub mod reader;
+pub mod search;
+pub mod snapshot;
+pub mod spill;
+pub mod stats;
+pub mod stream;
+pub mod theme;
+pub mod totals;
+pub mod validate;
 
-pub use cache::{DiskCache, DirEntry, USNJournalState, compute_content_hash, has_directory_changed, get_cache_path, get_cache_path_custom};
+pub use cache::{FastHashMap, CacheLoad, ChangeKind, LoadFailure, ChangeSet, DiskCache, DirEntry, OutputOptions, RenderSummary, USNJournalState, format_count, format_mtime, format_size, natural_cmp, compute_content_hash, has_directory_changed, default_cache_dir, get_cache_path, get_cache_path_custom, cache_artifacts, quarantine_cache_files, remove_cache_files, CACHE_DIR_ENV, CACHE_FILE_NAME, DEFAULT_DOT_DEPTH};
+pub use cache_lazy::{LazyDiskCache, DEFAULT_LAZY_ENTRIES};
+pub use cache_rkyv::CompactStats;
+pub use crypt::{CacheKey, KeySource, PASSPHRASE_ENV};
+pub use children::ChildNames;
+pub use backend::{backend_files, stored_backend};
+pub use cache_kv::{is_kv_cache, kv_path, KV_SCHEMA_VERSION};
+#[cfg(feature = "kv")]
+pub use cache_kv::KvCache;
+pub use cache_sqlite::{is_sqlite_cache, sqlite_path, SQLITE_SCHEMA_VERSION};
+#[cfg(feature = "sqlite")]
+pub use cache_sqlite::SqliteCache;
+pub use archive::{ImportSummary, ARCHIVE_MAGIC, ARCHIVE_VERSION};
+pub use background::{BackgroundSave, SaveCanceller};
+pub use bloom::{BloomSummary, PathBloom, DEFAULT_BLOOM_FP_RATE};
+pub use diff::{diff_caches, DiffCounts, DiffKind, DiffWriter};
+pub use evict::EvictionPlan;
+pub use theme::{HeatLevel, HeatmapThresholds, Style, Theme};
+pub use format::EntryTemplate;
+pub use info::CacheInfo;
+pub use intern::{EntryMap, NodeId};
+pub use legacy::Migration;
+pub use lock::{CacheLock, LockMode, LOCK_TIMEOUT};
+pub use merge::MergePolicy;
+pub use reader::CacheReader;
+pub use offsets::{OffsetIndex, SortedOffsets, SORTED_OFFSETS_MIN_ENTRIES};
+pub use search::{PathMatcher, SearchResults};
+pub use snapshot::{CacheDiff, Snapshot};
+pub use spill::DEFAULT_SPILL_CHILDREN;
+pub use stats::{CacheStats, RankedDir};
+pub use validate::{ValidationReport, Violations, VALIDATION_SAMPLES};
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use fs2::FileExt;
+use ptree_core::PTreeError;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Cache Locking (CLI and driver service)
+// ============================================================================
+
+/// How long to wait for another process's lock before giving up
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Reading (mapping) the pair; any number of holders
+    Shared,
+    /// Replacing, appending to, or removing the pair; one holder
+    Exclusive,
+}
+
+thread_local! {
+    /// Locks this thread holds, by lock file, with their nesting depth
+    ///
+    /// Lets a writer call code that takes the lock again (compaction opens
+    /// the cache it is rewriting) without waiting on itself. Other threads
+    /// open their own handle, so they are excluded like other processes.
+    static HELD: RefCell<HashMap<PathBuf, (LockMode, usize)>> = RefCell::new(HashMap::new());
+}
+
+/// Advisory lock on `<name>.lock` next to a cache, released on drop
+///
+/// The exclusive holder writes its PID into the file so a process that
+/// times out can say who it waited for. Windows locks the file's bytes
+/// too, so there the PID can't be read and the message stays generic.
+#[derive(Debug)]
+pub struct CacheLock {
+    path: PathBuf,
+    /// None for a nested acquisition, which only drops the depth
+    file: Option<File>,
+}
+
+impl CacheLock {
+    /// Shared lock for the cache `cache_file` belongs to, waiting up to `LOCK_TIMEOUT`
+    pub fn shared(cache_file: &Path) -> Result<Self> {
+        Self::acquire(cache_file, LockMode::Shared, LOCK_TIMEOUT)
+    }
+
+    /// Exclusive lock for the cache `cache_file` belongs to, waiting up to `LOCK_TIMEOUT`
+    pub fn exclusive(cache_file: &Path) -> Result<Self> {
+        Self::acquire(cache_file, LockMode::Exclusive, LOCK_TIMEOUT)
+    }
+
+    /// Lock the cache `cache_file` (its index, data, or `.dat` path) in `mode`
+    ///
+    /// Fails with `PTreeError::LockTimeout` ("cache is busy") once `timeout`
+    /// has passed. Asking for an exclusive lock while this thread holds a
+    /// shared one is an error rather than a wait on itself.
+    pub fn acquire(cache_file: &Path, mode: LockMode, timeout: Duration) -> Result<Self> {
+        let path = cache_file.with_extension("lock");
+        let nested = HELD.with(|held| match held.borrow_mut().get_mut(&path) {
+            Some((LockMode::Shared, _)) if mode == LockMode::Exclusive => {
+                Err(anyhow::anyhow!("cannot upgrade the shared lock on {} to exclusive", path.display()))
+            }
+            Some((_, depth)) => {
+                *depth += 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        })?;
+        if nested {
+            return Ok(CacheLock { path, file: None });
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|source| PTreeError::CachePath { path: path.clone(), source })?;
+        let started = Instant::now();
+        loop {
+            let attempt = match mode {
+                LockMode::Shared => FileExt::try_lock_shared(&file),
+                LockMode::Exclusive => FileExt::try_lock_exclusive(&file),
+            };
+            match attempt {
+                Ok(()) => break,
+                Err(e) if is_contended(&e) && started.elapsed() < timeout => std::thread::sleep(RETRY_INTERVAL),
+                Err(e) if is_contended(&e) => {
+                    let holder = match read_holder(&path) {
+                        Some(pid) => format!("PID {}", pid),
+                        None => "another process".to_string(),
+                    };
+                    return Err(PTreeError::LockTimeout(format!(
+                        "cache is busy (held by {}); gave up after {}s",
+                        holder,
+                        timeout.as_secs_f64()
+                    ))
+                    .into());
+                }
+                Err(e) => {
+                    return Err(PTreeError::Cache(format!("cannot lock {}: {}", path.display(), e)).into());
+                }
+            }
+        }
+
+        if mode == LockMode::Exclusive {
+            // Best effort: only used to name the holder in "cache is busy"
+            let _ = file.set_len(0).and_then(|()| write!(file, "{}", std::process::id()));
+        }
+        HELD.with(|held| held.borrow_mut().insert(path.clone(), (mode, 1)));
+        Ok(CacheLock { path, file: Some(file) })
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let mode = HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            let (mode, depth) = held.get_mut(&self.path)?;
+            *depth -= 1;
+            let mode = *mode;
+            if *depth == 0 {
+                held.remove(&self.path);
+            }
+            Some(mode)
+        });
+        if let Some(file) = self.file.take() {
+            if mode == Some(LockMode::Exclusive) {
+                let _ = file.set_len(0);
+            }
+            let _ = FileExt::unlock(&file);
+        }
+    }
+}
+
+fn is_contended(e: &std::io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+}
+
+/// PID the exclusive holder wrote into the lock file, if it can be read
+fn read_holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_busy_cache_names_the_holder() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_lock_busy_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_file = temp_dir.join("ptree.dat");
+
+        let held = CacheLock::exclusive(&cache_file)?;
+        // Nested on the same thread: no wait
+        drop(CacheLock::shared(&cache_file)?);
+
+        let (sender, receiver) = mpsc::channel();
+        let other = cache_file.clone();
+        std::thread::spawn(move || {
+            let shared = CacheLock::acquire(&other, LockMode::Shared, Duration::from_millis(100)).map(drop);
+            sender.send(shared).unwrap();
+        });
+        let err = receiver.recv()?.err().unwrap();
+        assert!(matches!(err.downcast_ref::<PTreeError>(), Some(PTreeError::LockTimeout(_))));
+        if cfg!(unix) {
+            assert!(err.to_string().contains(&format!("held by PID {}", std::process::id())), "{}", err);
+        }
+
+        drop(held);
+        let other = cache_file.clone();
+        std::thread::spawn(move || CacheLock::acquire(&other, LockMode::Exclusive, Duration::from_millis(100)).map(drop))
+            .join()
+            .unwrap()?;
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use crate::archive::ImportSummary;
+use crate::cache::DiskCache;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Cache Merging (import, multi-drive scans)
+// ============================================================================
+
+/// Which entry `DiskCache::merge` keeps when both caches hold the same path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    /// The entry with the later `modified`; the existing one on a tie
+    #[default]
+    NewestWins,
+    /// Always the incoming entry
+    OtherWins,
+    /// Always the existing entry
+    SelfWins,
+}
+
+impl DiskCache {
+    /// Merge `other` into this cache, settling conflicts by `policy`
+    ///
+    /// Paths only in `other` are added. For a path in both, the losing
+    /// entry still contributes the children that remain cached, and every
+    /// merged entry is listed in its parent's `children`; the lists that
+    /// change are sorted. Only entries added, replaced or given new children
+    /// are marked changed, so merging what is already cached dirties nothing. Skip counts are summed. The caches may cover
+    /// different roots (C:\ plus D:\): those end up side by side, as after
+    /// scanning several drives, and `roots` lists them. `root` and
+    /// `last_scan` stay this cache's unless it was empty. Merging two
+    /// consistent caches (see `validate`) gives one.
+    pub fn merge(&mut self, other: DiskCache, policy: MergePolicy) -> ImportSummary {
+        self.flush_pending_writes();
+        if self.entries.is_empty() {
+            self.root = other.root.clone();
+            self.last_scanned_root = other.last_scanned_root.clone();
+            self.last_scan = other.last_scan;
+            #[cfg(windows)]
+            {
+                self.usn_state = other.usn_state.clone();
+            }
+        }
+        for (name, count) in other.skip_stats {
+            *self.skip_stats.entry(name).or_insert(0) += count;
+        }
+
+        let mut summary = ImportSummary::default();
+        let mut added = Vec::new();
+        let mut contested = Vec::new();
+        // Only entries that actually change are dirtied, so a no-op merge saves nothing
+        let mut changed: HashSet<PathBuf> = HashSet::new();
+        for (path, entry) in other.entries {
+            let Some(local) = self.entries.get_mut(&path) else {
+                summary.added += 1;
+                added.push(path.clone());
+                self.entries.insert(path, entry);
+                continue;
+            };
+            let incoming_wins = match policy {
+                MergePolicy::NewestWins => entry.modified > local.modified,
+                MergePolicy::OtherWins => true,
+                MergePolicy::SelfWins => false,
+            };
+            let losing_children = if incoming_wins {
+                summary.updated += 1;
+                changed.insert(path.clone());
+                std::mem::replace(local, entry).children
+            } else {
+                summary.skipped += 1;
+                entry.children
+            };
+            contested.push((path, losing_children));
+        }
+
+        for (path, children) in contested {
+            let kept: Vec<&str> = children
+                .iter()
+                .filter(|name| self.entries.contains_key(&path.join(name)))
+                .collect();
+            let winner = &mut self.entries.get_mut(&path).expect("contested path is cached").children;
+            let missing: Vec<&str> = kept.into_iter().filter(|name| !winner.contains(name)).collect();
+            if !missing.is_empty() {
+                winner.extend(missing);
+                changed.insert(path);
+            }
+        }
+        for path in added {
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                continue;
+            };
+            if let Some(parent_entry) = self.entries.get_mut(parent) {
+                let name = name.to_string_lossy();
+                if !parent_entry.children.contains(&name) {
+                    parent_entry.children.push(name);
+                    changed.insert(parent.to_path_buf());
+                }
+            }
+            changed.insert(path);
+        }
+        for path in changed {
+            self.mark_changed(&path);
+            let children = &mut self.entries.get_mut(&path).expect("changed path is cached").children;
+            children.sort_unstable();
+            children.dedup();
+            self.invalidate_totals(&path);
+        }
+        summary
+    }
+
+    /// Cached paths whose parent is not cached, sorted: one per scanned drive or directory
+    pub fn roots(&self) -> Vec<&Path> {
+        let mut roots: Vec<&Path> = self
+            .entries
+            .keys()
+            .map(PathBuf::as_path)
+            .filter(|path| path.parent().is_none_or(|parent| !self.entries.contains_key(parent)))
+            .collect();
+        roots.sort_unstable();
+        roots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use crate::cache::DirEntry;
+    use crate::children::ChildNames;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    /// A random tree below `root`: up to `dirs` directories, each linked from its parent
+    fn random_cache(rng: &mut fastrand::Rng, root: &str, dirs: usize) -> DiskCache {
+        let epoch = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let modified = |rng: &mut fastrand::Rng| epoch + Duration::hours(rng.i64(0..48));
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from(root);
+        cache.skip_stats.insert("node_modules".to_string(), 1);
+        let mut paths = vec![PathBuf::from(root)];
+        cache.entries.insert(paths[0].clone(), entry(&paths[0], modified(rng)));
+        for _ in 1..dirs {
+            let parent = paths[rng.usize(..paths.len())].clone();
+            let path = parent.join(format!("d{}", rng.u8(..6)));
+            if cache.entries.contains_key(&path) {
+                continue;
+            }
+            cache.entries.get_mut(&parent).unwrap().children.push(path.file_name().unwrap().to_string_lossy());
+            cache.entries.insert(path.clone(), entry(&path, modified(rng)));
+            paths.push(path);
+        }
+        for entry in cache.entries.values_mut() {
+            entry.children.sort_unstable();
+        }
+        cache
+    }
+
+    fn entry(path: &Path, modified: DateTime<Utc>) -> DirEntry {
+        DirEntry { modified, ..test_entry(path, &[]) }
+    }
+
+    /// Paths with their `modified` and `children`, for comparing merge results
+    fn snapshot(cache: &DiskCache) -> Vec<(PathBuf, DateTime<Utc>, ChildNames)> {
+        let mut entries: Vec<_> = cache
+            .entries
+            .values()
+            .map(|entry| (entry.path.clone(), entry.modified, entry.children.clone()))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    fn assert_reachable(cache: &DiskCache) {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<PathBuf> = cache.roots().into_iter().map(Path::to_path_buf).collect();
+        while let Some(path) = stack.pop() {
+            if let Some(entry) = cache.entries.get(&path).filter(|_| seen.insert(path.clone())) {
+                stack.extend(entry.children.iter().map(|name| path.join(name)));
+            }
+        }
+        assert_eq!(seen.len(), cache.entries.len(), "unreachable entries after merge");
+    }
+
+    #[test]
+    fn test_merge_is_idempotent_and_keeps_everything_reachable() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        for round in 0..200 {
+            let policy = [MergePolicy::NewestWins, MergePolicy::OtherWins, MergePolicy::SelfWins][round % 3];
+            // Mostly overlapping trees; every fourth round a second root, like another drive
+            let mut merged = random_cache(&mut rng, "/scan", 40);
+            let other_root = if round % 4 == 0 { "/other" } else { "/scan" };
+            let other = random_cache(&mut rng, other_root, 40);
+
+            assert!(merged.validate().is_ok() && other.validate().is_ok());
+            merged.merge(other.clone(), policy);
+            let report = merged.validate();
+            assert!(report.is_ok(), "round {} ({:?}) left the cache inconsistent:\n{}", round, policy, report.to_text());
+            assert_reachable(&merged);
+            let once = snapshot(&merged);
+            merged.merge(other.clone(), policy);
+            assert_eq!(snapshot(&merged), once, "round {} ({:?})", round, policy);
+
+            let mut with_self = merged.clone();
+            with_self.merge(merged.clone(), policy);
+            assert_eq!(snapshot(&with_self), once);
+        }
+    }
+
+    #[test]
+    fn test_merging_an_identical_cache_changes_nothing() {
+        let mut rng = fastrand::Rng::with_seed(0x1D1E);
+        for policy in [MergePolicy::NewestWins, MergePolicy::SelfWins] {
+            let mut local = random_cache(&mut rng, "/scan", 40);
+            (local.dirty, local.changed) = (false, Some(HashSet::new()));
+
+            let summary = local.merge(local.clone(), policy);
+            assert_eq!((summary.added, summary.updated), (0, 0), "{:?}", policy);
+            assert!(!local.dirty, "{:?}", policy);
+            assert_eq!(local.changed, Some(HashSet::new()), "{:?}", policy);
+            assert!(local.stale_totals.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_policies_pick_the_expected_entry() {
+        let older = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let newer = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let cache = |modified, children: &[&str]| {
+            let mut cache = DiskCache::new();
+            cache.skip_stats.insert(".git".to_string(), 2);
+            let mut root = entry(Path::new("/scan"), modified);
+            root.children = children.iter().map(|c| c.to_string()).collect();
+            for child in children {
+                let path = Path::new("/scan").join(child);
+                cache.entries.insert(path.clone(), entry(&path, modified));
+            }
+            cache.entries.insert(root.path.clone(), root);
+            cache
+        };
+
+        for (policy, winner) in [
+            (MergePolicy::NewestWins, newer),
+            (MergePolicy::OtherWins, older),
+            (MergePolicy::SelfWins, newer),
+        ] {
+            let mut local = cache(newer, &["b", "a"]);
+            let summary = local.merge(cache(older, &["c", "a"]), policy);
+            assert_eq!((summary.added, summary.updated + summary.skipped), (1, 2), "{:?}", policy);
+            let root = &local.entries[Path::new("/scan")];
+            assert_eq!(root.modified, winner, "{:?}", policy);
+            assert_eq!(root.children, ["a", "b", "c"], "{:?}", policy);
+            assert_eq!(local.skip_stats[".git"], 4);
+            assert_eq!(local.roots(), [Path::new("/scan")]);
+        }
+    }
+}
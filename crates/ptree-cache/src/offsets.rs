@@ -0,0 +1,616 @@
+use crate::cache::FastHashMap;
+use ptree_core::PTreeError;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use rkyv::{Archive, Serialize as RkyvSerialize};
+use serde::ser::{SerializeMap, SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// ============================================================================
+// Offset Index (RkyvCacheIndex::offsets)
+// ============================================================================
+
+/// Entries from which an index is saved in the sorted layout
+///
+/// Below this a `HashMap` costs little and decodes as fast; above it the
+/// map's per-path allocations dominate both the index's memory and the
+/// time to open it.
+pub const SORTED_OFFSETS_MIN_ENTRIES: usize = 50_000;
+
+/// Paths and their data file offsets, stored sorted back to back
+///
+/// `paths` holds every path's text in `Path` order with no separator
+/// between them; `ends[i]` is where the `i`th stops, and `offsets[i]` is
+/// its offset. A lookup is a binary search, and the paths at and below a
+/// directory sit next to each other, so a subtree is one range. Saved and
+/// loaded as three flat arrays, without a per-path allocation.
+///
+/// Only UTF-8 paths are kept here; `OffsetIndex` keeps any other in its
+/// map, which fails to save them just as a small index's map does.
+///
+/// The arrays are either owned, as built or decoded from a bincode section,
+/// or read in place from an rkyv archive of `SortedArrays`
+/// (`FLAG_ARCHIVED_OFFSETS`) that opening only checks the bounds of. An
+/// archived path isn't checked as UTF-8 until it is read; one that isn't,
+/// or whose end is out of place, reads as empty and so is never found.
+#[derive(Clone, Default)]
+pub struct SortedOffsets {
+    layout: Layout,
+}
+
+#[derive(Clone)]
+enum Layout {
+    Owned { paths: String, ends: Vec<u64>, offsets: Vec<u64> },
+    Archived(ArchivedSection),
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Owned { paths: String::new(), ends: Vec::new(), offsets: Vec::new() }
+    }
+}
+
+/// Bytes a section is read from in place: an index file's mapping, or a copy of it
+pub(crate) type SharedBytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// The arrays of `SortedOffsets` as archived by rkyv
+#[derive(Archive, RkyvSerialize)]
+#[archive(check_bytes)]
+struct SortedArrays {
+    paths: Vec<u8>,
+    ends: Vec<u64>,
+    offsets: Vec<u64>,
+}
+
+/// Where the archived arrays lie, checked by `SortedOffsets::archived`
+#[derive(Clone)]
+struct ArchivedSection {
+    bytes: SharedBytes,
+    range: Range<usize>,
+}
+
+impl ArchivedSection {
+    fn arrays(&self) -> &ArchivedSortedArrays {
+        // SAFETY: `SortedOffsets::archived` checked these bytes as an archive, and they never change
+        unsafe { rkyv::archived_root::<SortedArrays>(&(*self.bytes).as_ref()[self.range.clone()]) }
+    }
+}
+
+/// Alignment an archived section has to start at
+pub(crate) const ARCHIVE_ALIGN: usize = 16;
+
+impl SortedOffsets {
+    /// Sort `entries` into the layout; paths that aren't UTF-8 are handed back
+    fn build<'a>(entries: impl Iterator<Item = (&'a Path, u64)>) -> (Self, Vec<(&'a Path, u64)>) {
+        let (mut sorted, mut other) = (Vec::new(), Vec::new());
+        for (path, offset) in entries {
+            match path.to_str() {
+                Some(text) => sorted.push((text, path, offset)),
+                None => other.push((path, offset)),
+            }
+        }
+        sorted.sort_unstable_by(|a, b| a.1.cmp(b.1));
+
+        let mut paths = String::with_capacity(sorted.iter().map(|(text, ..)| text.len()).sum());
+        let mut ends = Vec::with_capacity(sorted.len());
+        let mut offsets = Vec::with_capacity(sorted.len());
+        for (text, _, offset) in sorted {
+            paths.push_str(text);
+            ends.push(paths.len() as u64);
+            offsets.push(offset);
+        }
+        (SortedOffsets { layout: Layout::Owned { paths, ends, offsets } }, other)
+    }
+
+    /// Read the archived arrays in `bytes[range]` in place, checking only their bounds
+    ///
+    /// Bytes that don't start at `ARCHIVE_ALIGN` are copied to an aligned
+    /// buffer first.
+    pub(crate) fn archived(bytes: SharedBytes, range: Range<usize>) -> Result<Self, PTreeError> {
+        let corrupt = |what: String| PTreeError::CacheCorrupt(format!("archived offsets: {}", what));
+        let section = (*bytes).as_ref().get(range.clone()).ok_or_else(|| corrupt("section is truncated".to_string()))?;
+        let (bytes, range) = if (section.as_ptr() as usize).is_multiple_of(ARCHIVE_ALIGN) {
+            (bytes, range)
+        } else {
+            let mut aligned = rkyv::AlignedVec::with_capacity(section.len());
+            aligned.extend_from_slice(section);
+            let len = aligned.len();
+            (Arc::new(aligned) as SharedBytes, 0..len)
+        };
+        let arrays = rkyv::check_archived_root::<SortedArrays>(&(*bytes).as_ref()[range.clone()])
+            .map_err(|e| corrupt(format!("does not check: {}", e)))?;
+        if arrays.ends.len() != arrays.offsets.len() {
+            return Err(corrupt("paths and offsets differ in number".to_string()));
+        }
+        Ok(SortedOffsets { layout: Layout::Archived(ArchivedSection { bytes, range }) })
+    }
+
+    /// The arrays as an rkyv archive for `archived` to read, starting at `ARCHIVE_ALIGN`
+    pub(crate) fn to_archive(&self) -> Result<rkyv::AlignedVec, PTreeError> {
+        let arrays = SortedArrays { paths: self.paths().to_vec(), ends: self.ends().to_vec(), offsets: self.offsets().to_vec() };
+        rkyv::to_bytes::<_, 4096>(&arrays)
+            .map_err(|e| PTreeError::Cache(format!("sorted offsets do not archive: {}", e)))
+    }
+
+    /// Check that every path slices out of `paths` cleanly, as a decoded index has to
+    ///
+    /// An archived section was checked as far as it is when opened.
+    fn validate(&self) -> Result<(), PTreeError> {
+        let Layout::Owned { paths, ends, offsets } = &self.layout else {
+            return Ok(());
+        };
+        let corrupt = |what: &str| PTreeError::CacheCorrupt(format!("sorted offsets: {}", what));
+        if ends.len() != offsets.len() {
+            return Err(corrupt("paths and offsets differ in number"));
+        }
+        let mut start = 0;
+        for &end in ends {
+            if end < start || end > paths.len() as u64 || !paths.is_char_boundary(end as usize) {
+                return Err(corrupt("a path ends out of place"));
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Whether the arrays are read in place from an archive
+    pub fn is_archived(&self) -> bool {
+        matches!(self.layout, Layout::Archived(_))
+    }
+
+    fn paths(&self) -> &[u8] {
+        match &self.layout {
+            Layout::Owned { paths, .. } => paths.as_bytes(),
+            Layout::Archived(section) => section.arrays().paths.as_slice(),
+        }
+    }
+
+    fn ends(&self) -> &[u64] {
+        match &self.layout {
+            Layout::Owned { ends, .. } => ends,
+            Layout::Archived(section) => section.arrays().ends.as_slice(),
+        }
+    }
+
+    fn offsets(&self) -> &[u64] {
+        match &self.layout {
+            Layout::Owned { offsets, .. } => offsets,
+            Layout::Archived(section) => section.arrays().offsets.as_slice(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `i`th path
+    fn path(&self, i: usize) -> &Path {
+        let ends = self.ends();
+        let start = if i == 0 { 0 } else { ends[i - 1] as usize };
+        let text = self.paths().get(start..ends[i] as usize).and_then(|bytes| std::str::from_utf8(bytes).ok());
+        Path::new(text.unwrap_or_default())
+    }
+
+    /// The `i`th offset
+    fn offset(&self, i: usize) -> u64 {
+        self.offsets()[i]
+    }
+
+    /// Position of `path`, found by binary search
+    fn position(&self, path: &Path) -> Option<usize> {
+        let i = self.partition_point(0, |candidate| candidate < path);
+        (i < self.len() && self.path(i) == path).then_some(i)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<u64> {
+        self.position(path).map(|i| self.offset(i))
+    }
+
+    /// First position from `from` on where `before` fails; `before` has to hold for a prefix of them
+    fn partition_point(&self, from: usize, before: impl Fn(&Path) -> bool) -> usize {
+        let (mut low, mut high) = (from, self.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if before(self.path(middle)) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    /// Positions of `dir` and every path below it
+    fn range(&self, dir: &Path) -> Range<usize> {
+        let start = self.partition_point(0, |path| path < dir);
+        start..self.partition_point(start, |path| path.starts_with(dir))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, u64)> {
+        (0..self.len()).map(|i| (self.path(i), self.offset(i)))
+    }
+}
+
+impl fmt::Debug for SortedOffsets {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("SortedOffsets")
+            .field("len", &self.len())
+            .field("archived", &self.is_archived())
+            .finish()
+    }
+}
+
+impl PartialEq for SortedOffsets {
+    fn eq(&self, other: &Self) -> bool {
+        self.paths() == other.paths() && self.ends() == other.ends() && self.offsets() == other.offsets()
+    }
+}
+
+/// Bincode layout of a `FLAG_SORTED_OFFSETS` section: the three arrays in order
+impl Serialize for SortedOffsets {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let paths = std::str::from_utf8(self.paths()).map_err(serde::ser::Error::custom)?;
+        let mut section = serializer.serialize_struct("SortedOffsets", 3)?;
+        section.serialize_field("paths", paths)?;
+        section.serialize_field("ends", self.ends())?;
+        section.serialize_field("offsets", self.offsets())?;
+        section.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for SortedOffsets {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "SortedOffsets")]
+        struct Owned {
+            paths: String,
+            ends: Vec<u64>,
+            offsets: Vec<u64>,
+        }
+
+        let Owned { paths, ends, offsets } = Owned::deserialize(deserializer)?;
+        Ok(SortedOffsets { layout: Layout::Owned { paths, ends, offsets } })
+    }
+}
+
+/// Paths of one cache pair and the data file offsets of their records
+///
+/// Two layouts behind one interface: a hash map, and `SortedOffsets` for
+/// large indexes. An index saved with `SORTED_OFFSETS_MIN_ENTRIES` or more
+/// entries is written sorted (`FLAG_ARCHIVED_OFFSETS`, or
+/// `FLAG_SORTED_OFFSETS` when sealed) and loads that way;
+/// smaller ones are written as a map. Changes to a loaded sorted index
+/// (a delta save's appends) go to the map, with None marking a sorted path
+/// as removed, and are merged in when it is next written.
+///
+/// Serialized, it is a map of the entries the sorted section doesn't hold:
+/// all of them when `is_sorted_layout` is false, and otherwise only the
+/// paths that aren't UTF-8.
+#[derive(Debug, Clone, Default)]
+pub struct OffsetIndex {
+    sorted: SortedOffsets,
+    changes: FastHashMap<PathBuf, Option<u64>>,
+    len: usize,
+}
+
+impl PartialEq for OffsetIndex {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(path, offset)| other.get(path) == Some(offset))
+    }
+}
+
+impl OffsetIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An index over `sorted`, as read from the index file's sorted section, plus the map decoded with it
+    pub(crate) fn with_sorted(mut self, sorted: SortedOffsets) -> Result<Self, PTreeError> {
+        sorted.validate()?;
+        self.len += sorted.len();
+        self.sorted = sorted;
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the entries are held sorted rather than hashed
+    pub fn is_sorted(&self) -> bool {
+        !self.sorted.is_empty()
+    }
+
+    /// Whether the sorted entries are read in place from the index file they were loaded from
+    pub fn is_archived(&self) -> bool {
+        self.sorted.is_archived()
+    }
+
+    /// Copy the sorted entries out of the index file they are read from, so it can be replaced
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn detach(&mut self) {
+        if self.sorted.is_archived() {
+            let sorted = &self.sorted;
+            let paths = String::from_utf8_lossy(sorted.paths()).into_owned();
+            let (ends, offsets) = (sorted.ends().to_vec(), sorted.offsets().to_vec());
+            self.sorted = SortedOffsets { layout: Layout::Owned { paths, ends, offsets } };
+        }
+    }
+
+    /// Whether the index is saved in the sorted layout
+    pub fn is_sorted_layout(&self) -> bool {
+        self.len >= SORTED_OFFSETS_MIN_ENTRIES
+    }
+
+    pub fn get(&self, path: &Path) -> Option<u64> {
+        match self.changes.get(path) {
+            Some(&changed) => changed,
+            None => self.sorted.get(path),
+        }
+    }
+
+    pub fn contains_key(&self, path: &Path) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// Point `path` at `offset`, returning its previous offset
+    pub fn insert(&mut self, path: PathBuf, offset: u64) -> Option<u64> {
+        let previous = self.get(&path);
+        self.changes.insert(path, Some(offset));
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    pub fn remove(&mut self, path: &Path) -> Option<u64> {
+        let previous = self.get(path)?;
+        if self.sorted.get(path).is_some() {
+            self.changes.insert(path.to_path_buf(), None);
+        } else {
+            self.changes.remove(path);
+        }
+        self.len -= 1;
+        Some(previous)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Keep only the entries `keep` accepts
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path, u64) -> bool) {
+        let dropped: Vec<PathBuf> =
+            self.iter().filter(|&(path, offset)| !keep(path, offset)).map(|(path, _)| path.to_path_buf()).collect();
+        for path in dropped {
+            self.remove(&path);
+        }
+    }
+
+    /// Every path and its offset, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, u64)> {
+        let changed = &self.changes;
+        let sorted = self.sorted.iter().filter(move |(path, _)| changed.is_empty() || !changed.contains_key(*path));
+        let changes = changed.iter().filter_map(|(path, offset)| offset.map(|offset| (path.as_path(), offset)));
+        sorted.chain(changes)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Path> {
+        self.iter().map(|(path, _)| path)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = u64> + '_ {
+        self.iter().map(|(_, offset)| offset)
+    }
+
+    /// `dir` and the paths below it, with their offsets
+    ///
+    /// A binary search for the sorted entries; only the map is scanned whole.
+    pub fn range<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = (&'a Path, u64)> {
+        let changed = &self.changes;
+        let sorted = self
+            .sorted
+            .range(dir)
+            .map(|i| (self.sorted.path(i), self.sorted.offset(i)))
+            .filter(move |(path, _)| changed.is_empty() || !changed.contains_key(*path));
+        let changes = changed
+            .iter()
+            .filter(move |(path, _)| path.starts_with(dir))
+            .filter_map(|(path, offset)| offset.map(|offset| (path.as_path(), offset)));
+        sorted.chain(changes)
+    }
+
+    /// Merge the map into the sorted entries, keeping in the map only what they can't hold
+    pub fn sort(&mut self) {
+        let (sorted, other) = SortedOffsets::build(self.iter());
+        let changes = other.into_iter().map(|(path, offset)| (path.to_path_buf(), Some(offset))).collect();
+        self.changes = changes;
+        self.sorted = sorted;
+    }
+
+    /// The sorted section an index file stores, when saved in the sorted layout
+    ///
+    /// Borrowed as loaded when nothing changed since; built afresh otherwise.
+    pub(crate) fn sorted_section(&self) -> Option<std::borrow::Cow<'_, SortedOffsets>> {
+        if !self.is_sorted_layout() {
+            return None;
+        }
+        if self.changes.is_empty() {
+            return Some(std::borrow::Cow::Borrowed(&self.sorted));
+        }
+        Some(std::borrow::Cow::Owned(SortedOffsets::build(self.iter()).0))
+    }
+}
+
+impl FromIterator<(PathBuf, u64)> for OffsetIndex {
+    fn from_iter<I: IntoIterator<Item = (PathBuf, u64)>>(entries: I) -> Self {
+        let mut index = Self::new();
+        for (path, offset) in entries {
+            index.insert(path, offset);
+        }
+        index
+    }
+}
+
+impl Serialize for OffsetIndex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let in_map = |path: &Path| !self.is_sorted_layout() || path.to_str().is_none();
+        let entries: Vec<(&Path, u64)> = self.iter().filter(|&(path, _)| in_map(path)).collect();
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for (path, offset) in entries {
+            map.serialize_entry(path, &offset)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for OffsetIndex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OffsetsVisitor;
+
+        impl<'de> Visitor<'de> for OffsetsVisitor {
+            type Value = OffsetIndex;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of paths to offsets")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<OffsetIndex, A::Error> {
+                let mut index = OffsetIndex::new();
+                // The length comes from the file: trust it only as far as serde's own maps do
+                let cautious = (1 << 20) / std::mem::size_of::<(PathBuf, Option<u64>)>();
+                index.changes.reserve(access.size_hint().unwrap_or(0).min(cautious));
+                while let Some((path, offset)) = access.next_entry::<PathBuf, u64>()? {
+                    index.insert(path, offset);
+                }
+                Ok(index)
+            }
+        }
+
+        deserializer.deserialize_map(OffsetsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<(PathBuf, u64)> {
+        let mut paths = vec!["/", "/scan", "/scan/a", "/scan/a/deep", "/scan/ab", "/scan/a-b", "/scan/a b", "/scan/b/c", "/other"];
+        paths.extend(["/scan/ünïcode", "/scan/a/.hidden", "relative/x"]);
+        let mut entries: Vec<(PathBuf, u64)> = paths.into_iter().enumerate().map(|(i, p)| (PathBuf::from(p), i as u64 * 10)).collect();
+        entries.extend((0..500).map(|i| (PathBuf::from(format!("/scan/a/n{}", i)), 1000 + i)));
+        entries
+    }
+
+    type Entries = Vec<(PathBuf, u64)>;
+
+    /// Every query both layouts are asked, as comparable values: length, lookups, entries and ranges
+    fn answers(index: &OffsetIndex) -> (usize, Vec<Option<u64>>, Entries, Vec<Entries>) {
+        let queries = ["/scan", "/scan/a", "/scan/a/n7", "/scan/a/", "/scan//a/n7", "/scan/missing", "/scan/a/deep/x", "/", "relative/x", "/scan/a/n499"];
+        let sorted = |mut entries: Entries| {
+            entries.sort();
+            entries
+        };
+        let owned = |(path, offset): (&Path, u64)| (path.to_path_buf(), offset);
+        (
+            index.len(),
+            queries.iter().map(|query| index.get(Path::new(query))).collect(),
+            sorted(index.iter().map(owned).collect()),
+            queries.iter().map(|query| sorted(index.range(Path::new(query)).map(owned).collect())).collect(),
+        )
+    }
+
+    #[test]
+    fn test_map_and_sorted_layouts_answer_identical_queries() {
+        let mut entries = sample();
+        #[cfg(unix)]
+        {
+            // Not UTF-8, so kept in the map next to the sorted entries
+            use std::os::unix::ffi::OsStrExt;
+            entries.push((PathBuf::from(std::ffi::OsStr::from_bytes(b"/scan/a/\xff")), 7));
+        }
+        let map: OffsetIndex = entries.into_iter().collect();
+        let mut sorted = map.clone();
+        sorted.sort();
+        assert!(!map.is_sorted() && sorted.is_sorted());
+        assert_eq!(answers(&map), answers(&sorted));
+        assert_eq!(map.range(Path::new("/scan/a")).count(), 503 + cfg!(unix) as usize);
+
+        // Changes on top of the sorted entries read the same as on the map
+        let (mut map, mut changed) = (map, sorted);
+        for index in [&mut map, &mut changed] {
+            index.insert(PathBuf::from("/scan/a/n7"), 1);
+            index.insert(PathBuf::from("/scan/new"), 2);
+            index.remove(Path::new("/scan/ab"));
+            index.retain(|path, _| !path.starts_with("/scan/b"));
+        }
+        assert_eq!(answers(&map), answers(&changed));
+        changed.sort();
+        assert_eq!(answers(&map), answers(&changed));
+        assert_eq!(map, changed);
+    }
+
+    #[test]
+    fn test_sorted_layout_round_trips_and_rejects_bad_ends() {
+        let mut index: OffsetIndex = sample().into_iter().collect();
+        index.sort();
+        let section = index.sorted.clone();
+        let decoded: SortedOffsets = bincode::deserialize(&bincode::serialize(&section).unwrap()).unwrap();
+        let map: OffsetIndex = bincode::deserialize(&bincode::serialize(&index).unwrap()).unwrap();
+        // Small enough to be saved as a map: the map alone holds everything
+        assert_eq!(map, index);
+        assert_eq!(OffsetIndex::new().with_sorted(decoded).unwrap().len(), section.len());
+
+        let mut bad = section.clone();
+        if let Layout::Owned { paths, ends, .. } = &mut bad.layout {
+            ends[3] = paths.len() as u64 + 1;
+        }
+        assert!(OffsetIndex::new().with_sorted(bad).is_err());
+        let mut bad = section;
+        if let Layout::Owned { offsets, .. } = &mut bad.layout {
+            offsets.pop();
+        }
+        assert!(OffsetIndex::new().with_sorted(bad).is_err());
+    }
+
+    #[test]
+    fn test_archived_layout_answers_in_place() {
+        let mut owned: OffsetIndex = sample().into_iter().collect();
+        owned.sort();
+        let archive = owned.sorted.to_archive().unwrap();
+        let len = archive.len();
+
+        // Read where it lies, and from a copy one byte off alignment
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(&archive);
+        let in_place = SortedOffsets::archived(Arc::new(archive), 0..len).unwrap();
+        let copied = SortedOffsets::archived(Arc::new(shifted), 1..len + 1).unwrap();
+        for sorted in [in_place, copied] {
+            assert!(sorted.is_archived());
+            assert_eq!(sorted, owned.sorted);
+            let mut archived = OffsetIndex::new().with_sorted(sorted).unwrap();
+            assert_eq!(answers(&archived), answers(&owned));
+            archived.detach();
+            assert!(!archived.is_archived());
+            assert_eq!(answers(&archived), answers(&owned));
+        }
+
+        let archive = owned.sorted.to_archive().unwrap();
+        let cut: Vec<u8> = archive[..len / 2].to_vec();
+        assert!(SortedOffsets::archived(Arc::new(cut), 0..len / 2).is_err());
+        assert!(SortedOffsets::archived(Arc::new(archive), 0..len + 1).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::cache::{DirEntry, DiskCache};
+use crate::cache_lazy::LazyDiskCache;
+use crate::cache_limcode::{LimcodeCache, LimcodeDirEntry};
+use crate::cache_rkyv::RkyvMmapCache;
+use crate::children::ChildNames;
+use anyhow::Result;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Cache Readers (what the output builders render from)
+// ============================================================================
+
+/// Read access to saved entries, whatever holds them
+///
+/// The output builders render from this rather than from `DiskCache`'s
+/// map, so the same code walks entries in memory, a mapped data file, or a
+/// database (`DiskCache::write_tree_streamed`, `DiskCache::build_json_output_from`).
+/// Entries borrowed from memory come back as `Cow::Borrowed`; the others
+/// are decoded per lookup.
+pub trait CacheReader {
+    /// Root the saved entries were scanned from
+    fn root(&self) -> Result<PathBuf>;
+
+    /// The entry at `path`, its spilled child names read back
+    fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>>;
+
+    /// Names of the children of the directory at `path`, in stored order; none when it isn't saved
+    fn children_of(&self, path: &Path) -> Result<ChildNames> {
+        Ok(self.get_entry(path)?.map(|entry| entry.children.clone()).unwrap_or_default())
+    }
+
+    /// Number of saved entries
+    fn len(&self) -> Result<usize>;
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// `get_entry`, but a directory that spilled its names may come back with
+    /// `spilled_children` set and `children` empty; `children_of` has them
+    fn get_entry_shallow(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        self.get_entry(path)
+    }
+
+    /// Every saved path, in no particular order
+    ///
+    /// Found by walking down from `root` unless the storage can list its
+    /// paths directly, so entries no directory lists may be left out.
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        let mut pending = vec![self.root()?];
+        while let Some(path) = pending.pop() {
+            let Some(entry) = self.get_entry(&path)? else {
+                continue;
+            };
+            pending.extend(entry.children.iter().map(|name| path.join(name)));
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+impl CacheReader for DiskCache {
+    fn root(&self) -> Result<PathBuf> {
+        Ok(self.root.clone())
+    }
+
+    /// Forgives case and separator differences, so a cached key that differs from the listed name still matches
+    fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        let id = self.entries.id_normalized(path);
+        Ok(id.and_then(|id| self.entries.get_by_id(id)).map(Cow::Borrowed))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.entries.len())
+    }
+
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        Ok(self.entries.keys().cloned().collect())
+    }
+}
+
+impl CacheReader for RkyvMmapCache {
+    fn root(&self) -> Result<PathBuf> {
+        Ok(self.index.root.clone())
+    }
+
+    fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        Ok(RkyvMmapCache::get_entry(self, path)?.map(|entry| Cow::Owned(entry.into())))
+    }
+
+    fn children_of(&self, path: &Path) -> Result<ChildNames> {
+        match RkyvMmapCache::get_entry_shallow(self, path)? {
+            Some(entry) if entry.spilled_children > 0 => self.get_spilled_children(path),
+            Some(entry) => Ok(entry.children),
+            None => Ok(ChildNames::new()),
+        }
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(RkyvMmapCache::len(self))
+    }
+
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        RkyvMmapCache::paths(self)
+    }
+
+    fn get_entry_shallow(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        Ok(RkyvMmapCache::get_entry_shallow(self, path)?.map(|entry| Cow::Owned(entry.into())))
+    }
+}
+
+impl CacheReader for LazyDiskCache {
+    fn root(&self) -> Result<PathBuf> {
+        Ok(self.cache().root.clone())
+    }
+
+    fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        Ok(LazyDiskCache::get_entry(self, path)?.map(|entry| Cow::Owned(DirEntry::clone(&entry))))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(LazyDiskCache::len(self))
+    }
+}
+
+impl CacheReader for LimcodeCache {
+    fn root(&self) -> Result<PathBuf> {
+        Ok(PathBuf::from(&self.index.root))
+    }
+
+    fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        let Some(archived) = self.get_archived(&path.to_string_lossy())? else {
+            return Ok(None);
+        };
+        let entry: LimcodeDirEntry = rkyv::Deserialize::deserialize(archived, &mut rkyv::Infallible)
+            .expect("deserializing is infallible");
+        Ok(Some(Cow::Owned(entry.into())))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(LimcodeCache::len(self))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl CacheReader for crate::cache_sqlite::SqliteCache {
+    fn root(&self) -> Result<PathBuf> {
+        Ok(self.meta()?.map(|meta| meta.root).unwrap_or_default())
+    }
+
+    fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        Ok(Self::get_entry(self, path)?.map(Cow::Owned))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Self::len(self)
+    }
+}
+
+#[cfg(feature = "kv")]
+impl CacheReader for crate::cache_kv::KvCache {
+    fn root(&self) -> Result<PathBuf> {
+        Ok(self.meta()?.map(|meta| meta.root).unwrap_or_default())
+    }
+
+    fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+        Ok(Self::get_entry(self, path)?.map(Cow::Owned))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Self::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use chrono::{TimeZone, Utc};
+    use std::collections::BTreeMap;
+
+    /// The smallest reader there is: entries in a sorted map
+    struct FakeReader {
+        root: PathBuf,
+        entries: BTreeMap<PathBuf, DirEntry>,
+    }
+
+    impl FakeReader {
+        fn new(dirs: &[(&str, &[&str])]) -> Self {
+            let entries = dirs
+                .iter()
+                .map(|&(path, children)| {
+                    let entry = DirEntry {
+                        modified: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+                        is_hidden: path.contains("/."),
+                        dir_count: children.len() as u32,
+                        ..test_entry(path, children)
+                    };
+                    (PathBuf::from(path), entry)
+                })
+                .collect();
+            FakeReader { root: PathBuf::from(dirs[0].0), entries }
+        }
+    }
+
+    impl CacheReader for FakeReader {
+        fn root(&self) -> Result<PathBuf> {
+            Ok(self.root.clone())
+        }
+
+        fn get_entry(&self, path: &Path) -> Result<Option<Cow<'_, DirEntry>>> {
+            Ok(self.entries.get(path).map(Cow::Borrowed))
+        }
+
+        fn len(&self) -> Result<usize> {
+            Ok(self.entries.len())
+        }
+    }
+
+    fn view(reader: &FakeReader) -> DiskCache {
+        let mut view = DiskCache::new();
+        view.root = reader.root().unwrap();
+        view
+    }
+
+    #[test]
+    fn test_builders_render_from_any_reader() -> Result<()> {
+        let reader = FakeReader::new(&[("/scan", &["b", "a", ".h"]), ("/scan/a", &["x"]), ("/scan/a/x", &[]), ("/scan/b", &[]), ("/scan/.h", &[])]);
+        let mut view = view(&reader);
+
+        let mut tree = Vec::new();
+        view.write_tree_streamed(&reader, &mut tree, None, None)?;
+        assert_eq!(
+            String::from_utf8(tree)?,
+            "/scan\n├── .h\n├── a\n│   └── x\n└── b\n"
+        );
+
+        view.output_options.hide_hidden = true;
+        let mut tree = Vec::new();
+        view.write_tree_streamed(&reader, &mut tree, Some(1), None)?;
+        assert_eq!(String::from_utf8(tree)?, "/scan\n├── a\n│   └── …\n└── b\n");
+
+        view.output_options.show_report = true;
+        let json: serde_json::Value = serde_json::from_str(&view.build_json_output_from(&reader, Some(1))?)?;
+        let names: Vec<&str> = json["children"].as_array().unwrap().iter().map(|child| child["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!((json["children"][0]["truncated"].as_bool(), json["children"][0]["omitted_children"].as_u64()), (Some(true), Some(1)));
+        assert_eq!(json["summary"]["directories"], 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_builders_match_the_reader_ones() -> Result<()> {
+        let reader = FakeReader::new(&[("/scan", &["b", "a"]), ("/scan/a", &["x", "y"]), ("/scan/a/x", &[]), ("/scan/b", &[])]);
+        let mut cache = view(&reader);
+        for entry in reader.entries.values() {
+            cache.entries.insert(entry.path.clone(), entry.clone());
+        }
+        let mut streamed = Vec::new();
+        cache.write_tree_streamed(&reader, &mut streamed, Some(2), None)?;
+        assert_eq!(cache.build_tree_output_with_depth(Some(2))?, String::from_utf8(streamed)?);
+        assert_eq!(cache.build_json_output_with_depth(Some(1))?, cache.build_json_output_from(&reader, Some(1))?);
+        assert_eq!(CacheReader::children_of(&cache, Path::new("/scan/a"))?, ChildNames::from(vec!["x", "y"]));
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use crate::cache::DiskCache;
+use anyhow::Result;
+use globset::{GlobBuilder, GlobMatcher};
+use ptree_core::{PTreeError, PTreeResult, SearchMode};
+use rayon::prelude::*;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Path Search (ptree search)
+// ============================================================================
+
+/// Characters that make `SearchMode::Auto` treat a pattern as a glob
+const GLOB_CHARS: &[char] = &['*', '?', '[', '{'];
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Glob(GlobMatcher),
+    /// Already lowercased when matching is case-insensitive
+    Substring(String),
+}
+
+/// A compiled `ptree search` pattern
+///
+/// Patterns without a path separator test the entry name only. Patterns with
+/// one test the full path: globs are anchored at a component boundary unless
+/// absolute (`src/**/test*` finds every `src` at any depth), substrings may
+/// match anywhere. Matching is case-insensitive on Windows, where `\` and
+/// `/` are interchangeable.
+#[derive(Debug, Clone)]
+pub struct PathMatcher {
+    matcher: Matcher,
+    full_path: bool,
+}
+
+impl PathMatcher {
+    pub fn new(pattern: &str, mode: SearchMode) -> PTreeResult<Self> {
+        let pattern = if cfg!(windows) { pattern.replace('\\', "/") } else { pattern.to_string() };
+        let full_path = pattern.contains('/');
+        let is_glob = match mode {
+            SearchMode::Auto => pattern.contains(GLOB_CHARS),
+            SearchMode::Glob => true,
+            SearchMode::Substring => false,
+        };
+
+        let matcher = if is_glob {
+            let anchored = if !full_path || Path::new(&pattern).has_root() || pattern.starts_with("**") {
+                pattern.clone()
+            } else {
+                format!("**/{}", pattern)
+            };
+            let glob = GlobBuilder::new(&anchored)
+                .case_insensitive(cfg!(windows))
+                .literal_separator(true)
+                .build()
+                .map_err(|e| PTreeError::InvalidArgument(format!("invalid glob pattern '{}': {}", pattern, e)))?;
+            Matcher::Glob(glob.compile_matcher())
+        } else {
+            Matcher::Substring(fold_case(&pattern))
+        };
+
+        Ok(PathMatcher { matcher, full_path })
+    }
+
+    pub fn is_match(&self, name: &str, path: &Path) -> bool {
+        match &self.matcher {
+            Matcher::Glob(glob) if self.full_path => glob.is_match(path),
+            Matcher::Glob(glob) => glob.is_match(name),
+            Matcher::Substring(needle) if self.full_path => {
+                let path = path.to_string_lossy();
+                let path = if cfg!(windows) { path.replace('\\', "/") } else { path.into_owned() };
+                fold_case(&path).contains(needle.as_str())
+            }
+            Matcher::Substring(needle) => fold_case(name).contains(needle.as_str()),
+        }
+    }
+}
+
+fn fold_case(text: &str) -> String {
+    if cfg!(windows) {
+        text.to_lowercase()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Sorted matches of one search, cut to the requested limit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResults {
+    pub matches: Vec<PathBuf>,
+    /// Number of matches before the limit was applied
+    pub total: usize,
+}
+
+impl SearchResults {
+    pub fn is_truncated(&self) -> bool {
+        self.total > self.matches.len()
+    }
+
+    /// One path per line
+    pub fn to_text(&self) -> String {
+        self.matches
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn to_json(&self, pattern: &str) -> Result<String> {
+        let matches: Vec<String> = self.matches.iter().map(|path| path.to_string_lossy().into_owned()).collect();
+        Ok(serde_json::to_string_pretty(&json!({
+            "pattern": pattern,
+            "total": self.total,
+            "truncated": self.is_truncated(),
+            "matches": matches,
+        }))?)
+    }
+}
+
+impl DiskCache {
+    /// Every cached path matching `matcher`, optionally only below `scope`
+    ///
+    /// Reads the cache as-is (no traversal). `limit` keeps the first N paths
+    /// in sorted order; `total` still counts every match.
+    pub fn search(&self, matcher: &PathMatcher, scope: Option<&Path>, limit: Option<usize>) -> SearchResults {
+        let mut matches: Vec<PathBuf> = self
+            .entries
+            .par_iter()
+            .filter(|(path, _)| scope.is_none_or(|scope| path.starts_with(scope) && path.as_path() != scope))
+            .filter(|(path, entry)| matcher.is_match(&entry.name, path))
+            .map(|(path, _)| path.clone())
+            .collect();
+        matches.par_sort();
+
+        let total = matches.len();
+        if let Some(limit) = limit {
+            matches.truncate(limit);
+        }
+        SearchResults { matches, total }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+
+    fn cache() -> DiskCache {
+        let mut cache = DiskCache::new();
+        for path in ["/scan", "/scan/src", "/scan/src/lib", "/scan/src/lib/test_a.rs", "/scan/docs", "/scan/docs/tests.md"] {
+            cache.entries.insert(PathBuf::from(path), test_entry(path, &[]));
+        }
+        cache
+    }
+
+    fn search(pattern: &str, mode: SearchMode, scope: Option<&str>) -> Vec<String> {
+        let matcher = PathMatcher::new(pattern, mode).unwrap();
+        cache()
+            .search(&matcher, scope.map(Path::new), None)
+            .matches
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_name_patterns_by_mode() {
+        assert_eq!(search("test*", SearchMode::Auto, None), ["/scan/docs/tests.md", "/scan/src/lib/test_a.rs"]);
+        assert_eq!(search("test", SearchMode::Auto, None), ["/scan/docs/tests.md", "/scan/src/lib/test_a.rs"]);
+        // A glob must match the whole name
+        assert!(search("test", SearchMode::Glob, None).is_empty());
+        assert!(search("*.rs", SearchMode::Substring, None).is_empty());
+    }
+
+    #[test]
+    fn test_patterns_with_separators_match_paths() {
+        assert_eq!(search("src/**/test*", SearchMode::Auto, None), ["/scan/src/lib/test_a.rs"]);
+        assert_eq!(search("/scan/*", SearchMode::Glob, None), ["/scan/docs", "/scan/src"]);
+        assert_eq!(search("src/lib", SearchMode::Auto, None), ["/scan/src/lib", "/scan/src/lib/test_a.rs"]);
+    }
+
+    #[test]
+    fn test_scope_and_limit() {
+        assert_eq!(search("test", SearchMode::Auto, Some("/scan/docs")), ["/scan/docs/tests.md"]);
+        assert!(search("docs", SearchMode::Auto, Some("/scan/docs")).is_empty());
+
+        let matcher = PathMatcher::new("s", SearchMode::Substring).unwrap();
+        let results = cache().search(&matcher, None, Some(2));
+        assert_eq!(results.matches.len(), 2);
+        assert_eq!(results.total, 5);
+        assert!(results.is_truncated());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::cache::{DirEntry, DiskCache};
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Snapshots & Change Sets (--changes)
+// ============================================================================
+
+/// What a snapshot keeps of one cached path
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SnapshotEntry {
+    path: PathBuf,
+    modified: DateTime<Utc>,
+    child_count: usize,
+    /// Order-independent hash of the child names, so a swapped child shows
+    /// up even when the count stays the same
+    children_hash: u64,
+}
+
+/// Every cached path with its `modified` time and child list fingerprint, sorted by path
+///
+/// Taken with `DiskCache::snapshot` before a rescan and compared with
+/// `DiskCache::diff` afterwards.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    entries: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The `modified` time and child count recorded for `path`
+    pub fn get(&self, path: &Path) -> Option<(DateTime<Utc>, usize)> {
+        let found = self.entries.binary_search_by(|entry| entry.path.as_path().cmp(path)).ok()?;
+        let entry = &self.entries[found];
+        Some((entry.modified, entry.child_count))
+    }
+}
+
+/// Paths that differ between a snapshot and the cache, each list sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheDiff {
+    /// Cached now but not in the snapshot
+    pub added: Vec<PathBuf>,
+    /// In the snapshot but no longer cached, or left behind by a rescan
+    /// (cached, but no longer listed below its parent)
+    pub removed: Vec<PathBuf>,
+    /// Entries whose `modified` time or child names changed
+    pub modified: Vec<PathBuf>,
+}
+
+impl CacheDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl DiskCache {
+    /// Record every cached path for a later `diff`
+    pub fn snapshot(&self) -> Snapshot {
+        let mut entries: Vec<SnapshotEntry> = self
+            .entries
+            .values()
+            .map(|entry| SnapshotEntry {
+                path: entry.path.clone(),
+                modified: entry.modified,
+                child_count: entry.children.len(),
+                children_hash: children_hash(entry),
+            })
+            .collect();
+        entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        Snapshot { entries }
+    }
+
+    /// Compare the cache with a snapshot taken earlier
+    ///
+    /// Both sides are walked as path-sorted lists in step (a merge join),
+    /// so the cost is two sorts and one pass however large the cache is.
+    /// Paths sort component by component, putting every directory right
+    /// before its contents: a directory found left behind marks its whole
+    /// subtree removed without walking back up for each descendant. Files
+    /// are modified when their own `modified` time changes.
+    pub fn diff(&self, snapshot: &Snapshot) -> CacheDiff {
+        let mut current: Vec<&DirEntry> = self.entries.values().collect();
+        current.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+
+        let mut diff = CacheDiff::default();
+        let mut listed: HashMap<&Path, HashSet<&str>> = HashMap::new();
+        let mut left_behind: Option<&Path> = None;
+        let (mut i, mut j) = (0, 0);
+        loop {
+            let order = match (snapshot.entries.get(i), current.get(j)) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(before), Some(after)) => before.path.cmp(&after.path),
+            };
+            match order {
+                Ordering::Less => {
+                    diff.removed.push(snapshot.entries[i].path.clone());
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    diff.added.push(current[j].path.clone());
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    let (before, after) = (&snapshot.entries[i], current[j]);
+                    if left_behind.is_some_and(|root| after.path.starts_with(root))
+                        || !self.is_listed(&after.path, &mut listed)
+                    {
+                        left_behind = Some(&after.path);
+                        diff.removed.push(after.path.clone());
+                    } else if before.modified != after.modified
+                        || before.child_count != after.children.len()
+                        || before.children_hash != children_hash(after)
+                    {
+                        diff.modified.push(after.path.clone());
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        diff
+    }
+
+    /// Whether the cached parent of `path` lists it (true when the parent is not cached)
+    fn is_listed<'a>(&'a self, path: &Path, listed: &mut HashMap<&'a Path, HashSet<&'a str>>) -> bool {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return true;
+        };
+        let Some((parent, entry)) = self.entries.get_key_value(parent) else {
+            return true;
+        };
+        let names = listed
+            .entry(parent.as_path())
+            .or_insert_with(|| entry.children.iter().collect());
+        names.contains(name.to_string_lossy().as_ref())
+    }
+}
+
+/// Sum of the child names' hashes, the same in any order
+fn children_hash(entry: &DirEntry) -> u64 {
+    entry.children.iter().fold(0u64, |sum, name| {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        sum.wrapping_add(hasher.finish())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+
+    fn entry(path: &str, children: &[&str]) -> DirEntry {
+        DirEntry {
+            modified: DateTime::parse_from_rfc3339("2024-05-06T07:08:09Z").unwrap().with_timezone(&Utc),
+            ..test_entry(path, children)
+        }
+    }
+
+    fn paths(list: &[PathBuf]) -> Vec<&str> {
+        list.iter().map(|path| path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_diff_classifies_every_kind_of_change() {
+        let mut cache = DiskCache::new();
+        for e in [
+            entry("/scan", &["a", "b", "gone", "same"]),
+            entry("/scan/a", &["x"]),
+            entry("/scan/a/x", &[]),
+            entry("/scan/b", &["old"]),
+            entry("/scan/b/old", &["deeper"]),
+            entry("/scan/b/old/deeper", &[]),
+            entry("/scan/gone", &[]),
+            entry("/scan/same", &["touched", "untouched"]),
+            DirEntry { is_dir: false, ..entry("/scan/same/touched", &[]) },
+            DirEntry { is_dir: false, ..entry("/scan/same/untouched", &[]) },
+        ] {
+            cache.entries.insert(e.path.clone(), e);
+        }
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.len(), 10);
+        assert_eq!(snapshot.get(Path::new("/scan")).map(|(_, children)| children), Some(4));
+        assert!(cache.diff(&snapshot).is_empty());
+
+        // `a` swaps a child at the same count and mtime; `b` drops `old`, which stays cached
+        cache.entries.get_mut(Path::new("/scan/a")).unwrap().children = vec!["y".to_string()].into();
+        cache.entries.insert(PathBuf::from("/scan/a/y"), entry("/scan/a/y", &[]));
+        cache.entries.get_mut(Path::new("/scan/b")).unwrap().children.clear();
+        cache.entries.remove(Path::new("/scan/gone"));
+        cache.entries.get_mut(Path::new("/scan")).unwrap().modified += chrono::Duration::seconds(1);
+        // A file rewritten in place changes its own mtime, not its directory's
+        cache.entries.get_mut(Path::new("/scan/same/touched")).unwrap().modified += chrono::Duration::seconds(1);
+
+        let diff = cache.diff(&snapshot);
+        assert_eq!(paths(&diff.added), ["/scan/a/y"]);
+        assert_eq!(paths(&diff.modified), ["/scan", "/scan/a", "/scan/b", "/scan/same/touched"]);
+        assert_eq!(paths(&diff.removed), ["/scan/a/x", "/scan/b/old", "/scan/b/old/deeper", "/scan/gone"]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::cache::{format_count, DirEntry, DiskCache};
+use anyhow::Result;
+use ptree_core::CacheBackend;
+use std::path::Path;
+
+// ============================================================================
+// Spilled Child Lists (directories too large to keep inline)
+// ============================================================================
+
+/// Child count above which a saved directory's names go in a spill record, unless `--spill-children` says otherwise
+///
+/// Entries are meant to stay within a few hundred bytes; ten thousand names
+/// are already ~150 KB that every lookup of the directory would decode. A
+/// spilled directory's record keeps only the count, and the names are read
+/// when it is expanded (`RkyvMmapCache::get_spilled_children`).
+pub const DEFAULT_SPILL_CHILDREN: usize = 10_000;
+
+impl DirEntry {
+    /// Immediate children, counting those left in a spill record by a shallow read
+    pub fn child_count(&self) -> usize {
+        self.children.len() + self.spilled_children as usize
+    }
+}
+
+impl DiskCache {
+    /// Change the spill threshold (`--spill-children`, 0 = never), loading every entry first
+    ///
+    /// Delta saves only spill the entries they append, so a new threshold
+    /// has the cache saved whole, which needs its entries loaded from `path`.
+    /// The database backends keep child lists their own way and only
+    /// remember the threshold.
+    pub fn set_spill_children(&mut self, threshold: usize, path: &Path) -> Result<()> {
+        if threshold == self.spill_children {
+            return Ok(());
+        }
+        self.spill_children = threshold;
+        if self.backend == CacheBackend::Rkyv && self.changed.is_some() {
+            self.load_all_entries_lazy(path)?;
+            self.dirty = true;
+            self.changed = None;
+        }
+        Ok(())
+    }
+
+    /// Cached directories with more children than `spill_children`, whose names a save spills
+    pub fn spilled_dirs(&self) -> usize {
+        if self.spill_children == 0 {
+            return 0;
+        }
+        self.entries
+            .values()
+            .filter(|entry| entry.child_count() > self.spill_children)
+            .count()
+    }
+
+    /// Spill threshold and how many directories are over it, for `--stats`
+    pub fn get_spill_report(&self) -> String {
+        if self.spill_children == 0 {
+            return "(child lists never spilled)".to_string();
+        }
+        format!(
+            "Spilled Child Lists: {} directories over {} children",
+            format_count(self.spilled_dirs()),
+            format_count(self.spill_children)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+
+    fn dir(path: &str, children: usize) -> DirEntry {
+        let names: Vec<String> = (0..children).map(|i| format!("f{}", i)).collect();
+        DirEntry {
+            name: path.trim_start_matches('/').to_string(),
+            children: names.into(),
+            file_count: children as u32,
+            ..test_entry(path, &[])
+        }
+    }
+
+    #[test]
+    fn test_report_counts_directories_over_the_threshold() {
+        let mut cache = DiskCache::new();
+        for entry in [dir("/big", 11), dir("/edge", 10), dir("/small", 2)] {
+            cache.entries.insert(entry.path.clone(), entry);
+        }
+        cache.spill_children = 10;
+        assert_eq!(cache.spilled_dirs(), 1);
+        assert_eq!(cache.get_spill_report(), "Spilled Child Lists: 1 directories over 10 children");
+
+        cache.spill_children = 0;
+        assert_eq!(cache.spilled_dirs(), 0);
+        assert_eq!(cache.get_spill_report(), "(child lists never spilled)");
+    }
+}
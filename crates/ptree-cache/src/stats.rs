@@ -0,0 +1,215 @@
+use crate::cache::{format_count, format_size, DiskCache};
+use anyhow::Result;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+// ============================================================================
+// Cache Statistics (ptree stats)
+// ============================================================================
+
+/// A directory and the count it was ranked by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RankedDir {
+    pub path: PathBuf,
+    /// Children, descendants, or bytes (`top_by_size`)
+    pub count: usize,
+}
+
+/// Aggregate figures for the cached tree (or one subtree of it)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub total_dirs: usize,
+    pub total_files: usize,
+    /// Bytes below the scope (or every root), from the stored `total_size`
+    pub total_size: u64,
+    /// Part of that was skipped or unreadable, so `total_size` is a minimum
+    pub size_is_lower_bound: bool,
+    /// Deepest entry and its depth (below the scope, or from the filesystem root)
+    pub deepest: Option<(PathBuf, usize)>,
+    /// Directories with the most immediate children
+    pub top_by_children: Vec<RankedDir>,
+    /// Directories with the most entries anywhere below them
+    pub top_by_descendants: Vec<RankedDir>,
+    /// Directories with the largest `total_size`
+    pub top_by_size: Vec<RankedDir>,
+}
+
+impl DiskCache {
+    /// Compute statistics over every cached entry, optionally only below `scope`
+    ///
+    /// Descendant counts come from one bottom-up pass: entries are visited
+    /// deepest first and each adds its own total to its parent, so the cost
+    /// is a sort plus a single walk no matter how deep the tree is. Sizes
+    /// are read from `total_size` as stored, not summed again.
+    pub fn compute_stats(&self, top: usize, scope: Option<&Path>) -> CacheStats {
+        let mut entries: Vec<(&PathBuf, usize)> = self
+            .entries
+            .keys()
+            .filter(|path| scope.is_none_or(|scope| path.starts_with(scope)))
+            .map(|path| (path, depth_of(path)))
+            .collect();
+        entries.sort_unstable_by_key(|&(path, depth)| (Reverse(depth), path));
+
+        let mut descendants: HashMap<&Path, usize> = HashMap::with_capacity(entries.len());
+        for &(path, _) in &entries {
+            let below = descendants.get(path.as_path()).copied().unwrap_or(0);
+            if let Some(parent) = path.parent() {
+                *descendants.entry(parent).or_insert(0) += below + 1;
+            }
+        }
+
+        let mut stats = CacheStats::default();
+        let scope_depth = scope.map_or(0, depth_of);
+        stats.deepest = entries
+            .first()
+            .map(|&(path, depth)| (path.clone(), depth - scope_depth));
+
+        let tops: Vec<&Path> = match scope {
+            Some(scope) => vec![scope],
+            None => self.roots(),
+        };
+        for top in tops.into_iter().filter_map(|top| self.entries.get(top)) {
+            let (bytes, lower_bound) = if top.is_dir { (top.total_size, top.size_is_lower_bound) } else { (top.size, false) };
+            stats.total_size += bytes;
+            stats.size_is_lower_bound |= lower_bound;
+        }
+
+        let mut by_children = Vec::new();
+        let mut by_descendants = Vec::new();
+        let mut by_size = Vec::new();
+        for &(path, _) in &entries {
+            let entry = &self.entries[path];
+            if !entry.is_dir {
+                stats.total_files += 1;
+                continue;
+            }
+            stats.total_dirs += 1;
+            by_children.push((entry.children.len(), path));
+            by_descendants.push((descendants.get(path.as_path()).copied().unwrap_or(0), path));
+            by_size.push((entry.total_size as usize, path));
+        }
+        stats.top_by_children = top_n(by_children, top);
+        stats.top_by_descendants = top_n(by_descendants, top);
+        stats.top_by_size = top_n(by_size, top);
+        stats
+    }
+}
+
+impl CacheStats {
+    /// Human-readable report with one table per ranking
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!("{:<16} {}\n", "Directories:", format_count(self.total_dirs)));
+        out.push_str(&format!("{:<16} {}\n", "Files:", format_count(self.total_files)));
+        let bound = if self.size_is_lower_bound { "≥ " } else { "" };
+        out.push_str(&format!("{:<16} {}{}\n", "Total size:", bound, format_size(self.total_size)));
+        if let Some((path, depth)) = &self.deepest {
+            out.push_str(&format!("{:<16} {} (depth {})\n", "Deepest path:", path.display(), depth));
+        }
+
+        let count = |n: usize| format_count(n);
+        let size = |n: usize| format_size(n as u64);
+        for (title, header, ranked, format) in [
+            ("by immediate children", "CHILDREN", &self.top_by_children, &count as &dyn Fn(usize) -> String),
+            ("by descendants", "DESCENDANTS", &self.top_by_descendants, &count),
+            ("by size", "SIZE", &self.top_by_size, &size),
+        ] {
+            out.push_str(&format!("\nTop {} {}\n", ranked.len(), title));
+            out.push_str(&format!("{:>12}  PATH\n", header));
+            for dir in ranked {
+                out.push_str(&format!("{:>12}  {}\n", format(dir.count), dir.path.display()));
+            }
+        }
+        out.truncate(out.trim_end().len());
+        out
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let ranked = |dirs: &[RankedDir]| {
+            dirs.iter()
+                .map(|dir| json!({ "path": dir.path.to_string_lossy(), "count": dir.count }))
+                .collect::<Vec<_>>()
+        };
+        Ok(serde_json::to_string_pretty(&json!({
+            "total_dirs": self.total_dirs,
+            "total_files": self.total_files,
+            "total_size": self.total_size,
+            "size_is_lower_bound": self.size_is_lower_bound,
+            "deepest": self.deepest.as_ref().map(|(path, depth)| json!({ "path": path.to_string_lossy(), "depth": depth })),
+            "top_by_children": ranked(&self.top_by_children),
+            "top_by_descendants": ranked(&self.top_by_descendants),
+            "top_by_size": ranked(&self.top_by_size),
+        }))?)
+    }
+}
+
+/// Number of named components, so `C:\` and `/` are both depth 0
+pub(crate) fn depth_of(path: &Path) -> usize {
+    path.components().filter(|c| matches!(c, Component::Normal(_))).count()
+}
+
+/// The `n` largest counts, largest first (ties broken by path)
+fn top_n(mut ranked: Vec<(usize, &PathBuf)>, n: usize) -> Vec<RankedDir> {
+    let order = |a: &(usize, &PathBuf), b: &(usize, &PathBuf)| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1));
+    if n < ranked.len() {
+        ranked.select_nth_unstable_by(n, order);
+        ranked.truncate(n);
+    }
+    ranked.sort_unstable_by(order);
+    ranked
+        .into_iter()
+        .map(|(count, path)| RankedDir { path: path.clone(), count })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry, test_file};
+
+    fn cache() -> DiskCache {
+        let mut cache = DiskCache::new();
+        let tree: &[(&str, &[&str], bool, u64)] = &[
+            ("/scan", &["a", "b", "x.txt"], true, 0),
+            ("/scan/a", &["deep"], true, 0),
+            ("/scan/a/deep", &["deeper"], true, 0),
+            ("/scan/a/deep/deeper", &[], true, 0),
+            ("/scan/b", &["1", "2"], true, 0),
+            ("/scan/b/1", &[], false, 300),
+            ("/scan/b/2", &[], false, 200),
+            ("/scan/x.txt", &[], false, 1000),
+        ];
+        for (path, children, is_dir, size) in tree {
+            let entry = if *is_dir { test_entry(path, children) } else { test_file(path, *size) };
+            cache.entries.insert(PathBuf::from(path), entry);
+        }
+        cache.compute_totals();
+        cache
+    }
+
+    fn ranked(dirs: &[RankedDir]) -> Vec<(String, usize)> {
+        dirs.iter().map(|dir| (dir.path.display().to_string(), dir.count)).collect()
+    }
+
+    #[test]
+    fn test_counts_and_rankings() {
+        let stats = cache().compute_stats(2, None);
+        assert_eq!((stats.total_dirs, stats.total_files), (5, 3));
+        assert_eq!(stats.deepest, Some((PathBuf::from("/scan/a/deep/deeper"), 4)));
+        assert_eq!(ranked(&stats.top_by_children), [("/scan".to_string(), 3), ("/scan/b".to_string(), 2)]);
+        assert_eq!(ranked(&stats.top_by_descendants), [("/scan".to_string(), 7), ("/scan/a".to_string(), 2)]);
+        assert_eq!((stats.total_size, stats.size_is_lower_bound), (1500, false));
+        assert_eq!(ranked(&stats.top_by_size), [("/scan".to_string(), 1500), ("/scan/b".to_string(), 500)]);
+        assert!(stats.to_text().contains("Total size:      1.5 KiB\n"), "{}", stats.to_text());
+    }
+
+    #[test]
+    fn test_scope_limits_counts_and_depth() {
+        let stats = cache().compute_stats(10, Some(Path::new("/scan/a")));
+        assert_eq!((stats.total_dirs, stats.total_files), (3, 0));
+        assert_eq!(stats.deepest, Some((PathBuf::from("/scan/a/deep/deeper"), 2)));
+        assert_eq!(stats.top_by_descendants[0], RankedDir { path: PathBuf::from("/scan/a"), count: 2 });
+    }
+}
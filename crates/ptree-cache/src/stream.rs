@@ -0,0 +1,620 @@
+use crate::cache::{
+    dimmed, format_count, mark_hidden, ChangeKind, Connectors, DirEntry, DiskCache, OutputBudget, RenderSummary,
+    TreeLines, ACCESS_ERROR_MARK, TRUNCATION_NOTICE,
+};
+use crate::cache_rkyv::RkyvMmapCache;
+use crate::reader::CacheReader;
+use crate::theme::{Style, Theme};
+use anyhow::Result;
+use colored::Colorize;
+use serde_json::json;
+use ptree_core::SortKey;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::path::Path;
+
+// ============================================================================
+// Streamed Tree Output (cached runs, no get_all)
+// ============================================================================
+
+impl DiskCache {
+    /// Whether the current output options can be rendered from a reader without this cache's entries
+    ///
+    /// Anything that looks beyond one directory at a time needs the whole
+    /// cache loaded into this cache: `--match`, the time filters, `--prune`,
+    /// `--changes` and `--check-symlinks`. So does `--entry-format`. Otherwise `write_tree_streamed` and
+    /// `build_json_output_from` only need the reader.
+    pub fn can_stream_tree(&self) -> bool {
+        let options = &self.output_options;
+        options.matches.is_none()
+            && !options.has_time_filter()
+            && !options.prune
+            && options.changes.is_none()
+            && !options.check_symlinks
+            && options.entry_format.is_none()
+    }
+
+    /// Write the tree of the cache at `cache_path` to `writer`, reading entries from its data file as they are reached
+    ///
+    /// See `write_tree_streamed`; this cache supplies the display options.
+    pub fn write_cached_tree<W: Write>(
+        &self,
+        cache_path: &Path,
+        writer: &mut W,
+        max_depth: Option<usize>,
+        theme: Option<&Theme>,
+    ) -> Result<()> {
+        let reader = RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path.with_extension("dat"))?;
+        self.write_tree_streamed(&reader, writer, max_depth, theme)
+    }
+
+    /// Write the tree below `display_root` to `writer`, looking entries up in `reader` on demand
+    ///
+    /// The renderer behind `build_tree_output_with_depth` (with `theme`,
+    /// `build_colored_tree_output_with_theme`). For the options
+    /// `can_stream_tree` accepts this cache only supplies those options and
+    /// its map is never read; the rest need its entries loaded. Only the
+    /// entries along the branch being rendered are held. Lines are written
+    /// as they are rendered, a window at a time when annotations are
+    /// aligned, and the footer counts what was written.
+    pub fn write_tree_streamed<R: CacheReader + ?Sized, W: Write>(
+        &self,
+        reader: &R,
+        writer: &mut W,
+        max_depth: Option<usize>,
+        theme: Option<&Theme>,
+    ) -> Result<()> {
+        if reader.is_empty()? {
+            writer.write_all(b"(empty)\n")?;
+            return Ok(());
+        }
+
+        let root = self.display_root();
+        let root_entry = reader.get_entry_shallow(root)?.map(Cow::into_owned);
+        let mut tree = StreamedTree {
+            cache: self,
+            reader,
+            writer,
+            lines: self.tree_lines(),
+            connectors: Connectors::for_charset(self.output_options.charset),
+            theme,
+            summary: RenderSummary::default(),
+        };
+        let label = root.display().to_string();
+        let du = self.du_label(root_entry.as_ref());
+        match theme {
+            Some(theme) => tree.push(theme.root.paint(&label), dimmed(du))?,
+            None => tree.push(label, du)?,
+        };
+        if let Some(entry) = &root_entry {
+            tree.render_children(entry, "", 0, max_depth)?;
+        }
+
+        tree.lines.flush();
+        let footer = if tree.lines.budget.truncated {
+            format!("{}\n", TRUNCATION_NOTICE)
+        } else if self.output_options.show_report {
+            format!("\n{}\n", tree.summary.report_line())
+        } else {
+            String::new()
+        };
+        tree.lines.text.push_str(&footer);
+        tree.lines.text.push_str(&self.removed_section());
+        tree.drain()
+    }
+}
+
+/// One streamed rendering: the line collector plus where its lines go
+struct StreamedTree<'a, R: CacheReader + ?Sized, W: Write> {
+    cache: &'a DiskCache,
+    reader: &'a R,
+    writer: &'a mut W,
+    lines: TreeLines,
+    connectors: Connectors,
+    theme: Option<&'a Theme>,
+    /// What was rendered so far, for the footer
+    summary: RenderSummary,
+}
+
+impl<R: CacheReader + ?Sized, W: Write> StreamedTree<'_, R, W> {
+    /// Add a line and write out whatever the collector released; false once `--limit` is reached
+    fn push(&mut self, line: String, annotation: String) -> Result<bool> {
+        let pushed = self.lines.push_annotated(line, annotation);
+        self.drain()?;
+        Ok(pushed)
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        self.writer.write_all(self.lines.text.as_bytes())?;
+        self.lines.text.clear();
+        Ok(())
+    }
+
+    fn paint_connector(&self, connector: &str) -> String {
+        match self.theme {
+            Some(theme) => theme.connector.paint(connector),
+            None => connector.to_string(),
+        }
+    }
+
+    /// The lines below `entry`
+    ///
+    /// Entries are read shallow: a directory that spilled its names is
+    /// collapsed on its stored count, and its spill record only read when
+    /// it is expanded after all.
+    fn render_children(&mut self, entry: &DirEntry, prefix: &str, depth: usize, max_depth: Option<usize>) -> Result<()> {
+        let options = &self.cache.output_options;
+        if max_depth.is_some_and(|max| depth >= max) {
+            if !options.no_indent && self.has_visible_child(entry)? {
+                let line = format!("{}{}{}", prefix, self.paint_connector(self.connectors.elbow), self.connectors.ellipsis);
+                self.push(line, String::new())?;
+            }
+            return Ok(());
+        }
+
+        let limit = options.collapse_limit();
+        if entry.spilled_children > 0 && limit.is_none_or(|limit| entry.child_count() <= limit) {
+            let mut expanded = entry.clone();
+            expanded.children = self.reader.children_of(&entry.path)?;
+            expanded.spilled_children = 0;
+            return self.render_children(&expanded, prefix, depth, max_depth);
+        }
+
+        // Still spilled here means collapsed, on the stored count
+        let children = self.cache.reader_children(self.reader, entry)?;
+        let count = children.len() + entry.spilled_children as usize;
+        if limit.is_some_and(|limit| count > limit) {
+            if !options.no_indent {
+                let placeholder = format!("[{} entries]", format_count(count));
+                let placeholder = if self.theme.is_some() { placeholder.dimmed().to_string() } else { placeholder };
+                let line = format!("{}{}{}", prefix, self.paint_connector(self.connectors.elbow), placeholder);
+                self.push(line, String::new())?;
+            }
+            return Ok(());
+        }
+
+        let count = children.len();
+        for (i, (name, child)) in children.into_iter().enumerate() {
+            match &child {
+                Some(child) if child.is_dir => self.summary.directories += 1,
+                Some(_) => self.summary.files += 1,
+                None => self.summary.unknown += 1,
+            }
+            let (branch, child_prefix) = if i == count - 1 {
+                (self.connectors.elbow, self.connectors.blank)
+            } else {
+                (self.connectors.tee, self.connectors.pipe)
+            };
+            // A cached key differing from the listed name in case is the path
+            let path = child.as_ref().map_or_else(|| entry.path.join(name), |child| child.path.clone());
+            let (display_name, annotation) = self.child_label(&path, name, child.as_ref(), depth + 1);
+            let line = if options.no_indent {
+                display_name
+            } else {
+                format!("{}{}{}", prefix, self.paint_connector(branch), display_name)
+            };
+            // Out of --limit budget: stop walking, not just printing
+            if !self.push(line, annotation)? {
+                return Ok(());
+            }
+            if let Some(child) = &child {
+                self.render_children(child, &format!("{}{}", prefix, child_prefix), depth + 1, max_depth)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A child's line content and trailing annotations
+    ///
+    /// `--entry-format` replaces both; otherwise the name is marked for a
+    /// symlink, a broken link, an unreadable directory or a hidden entry,
+    /// and prefixed with its `--changes` marker.
+    fn child_label(&self, path: &Path, name: &str, entry: Option<&DirEntry>, depth: usize) -> (String, String) {
+        let cache = self.cache;
+        let label = cache.entry_label(name, path);
+        let broken_style = Style::broken();
+        let faint = Style::faint();
+        let mut style = self.theme.map(|theme| theme.style_for(entry));
+        if let (Some(theme), Some(thresholds), Some(entry)) = (self.theme, &cache.output_options.heatmap, entry) {
+            if entry.is_dir {
+                style = style.map(|base| theme.heat_style(cache.heat_level(entry.modified, thresholds), base));
+            }
+        }
+        if cache.is_time_scaffolding(path) {
+            style = style.map(|_| &faint);
+        }
+
+        let (base_name, annotation) = match entry {
+            Some(entry) => {
+                let base_name = if let Some(target) = &entry.symlink_target {
+                    let link = format!("{} ({} {})", label, self.connectors.arrow, target.display());
+                    if cache.is_broken_link(entry) {
+                        style = style.map(|_| &broken_style);
+                        format!("{} [broken]", link)
+                    } else {
+                        link
+                    }
+                } else if entry.access_error.is_some() {
+                    style = style.map(|_| &broken_style);
+                    format!("{} {}", mark_hidden(&label, Some(entry), cache.show_hidden), ACCESS_ERROR_MARK)
+                } else {
+                    mark_hidden(&label, Some(entry), cache.show_hidden)
+                };
+                (base_name, cache.annotation_suffix(entry))
+            }
+            None => (label, String::new()),
+        };
+        // --changes marks --entry-format lines too
+        let marker = match (cache.change_kind(path), self.theme) {
+            (Some(ChangeKind::Added), Some(_)) => format!("{} ", "+".green()),
+            (Some(ChangeKind::Modified), Some(_)) => format!("{} ", "*".yellow()),
+            (Some(ChangeKind::Added), None) => "+ ".to_string(),
+            (Some(ChangeKind::Modified), None) => "* ".to_string(),
+            (None, _) => String::new(),
+        };
+        if let Some(line) = cache.render_entry_format(name, path, entry, depth) {
+            return (format!("{}{}", marker, style.map_or(line.clone(), |style| style.paint(&line))), String::new());
+        }
+
+        let (display_name, annotation) = match style {
+            Some(style) => (style.paint(&base_name), dimmed(annotation)),
+            None => (base_name, annotation),
+        };
+        (format!("{}{}", marker, display_name), annotation)
+    }
+
+    /// Whether anything below `entry` would be rendered (for the `--max-depth` ellipsis)
+    fn has_visible_child(&self, entry: &DirEntry) -> Result<bool> {
+        if entry.spilled_children > 0 {
+            return Ok(true);
+        }
+        if !self.cache.output_options.filters_on_entries() {
+            return Ok(entry.children.iter().any(|name| !self.is_ignored(name)));
+        }
+        Ok(!self.cache.reader_children(self.reader, entry)?.is_empty())
+    }
+
+    fn is_ignored(&self, name: &str) -> bool {
+        self.cache.is_ignored_name(name)
+    }
+}
+
+impl DiskCache {
+    fn is_ignored_name(&self, name: &str) -> bool {
+        self.output_options.ignore.as_ref().is_some_and(|set| set.is_match(name))
+    }
+
+    /// `entry`'s children in output order with their entries from `reader`, those filtered out dropped
+    ///
+    /// Every child filter and sort key applies; those `can_stream_tree`
+    /// rejects read this cache's entries as well as the reader's.
+    pub(crate) fn reader_children<'e, R: CacheReader + ?Sized>(
+        &self,
+        reader: &R,
+        entry: &'e DirEntry,
+    ) -> Result<Vec<(&'e str, Option<DirEntry>)>> {
+        let options = &self.output_options;
+        let mut children = Vec::with_capacity(entry.children.len());
+        for name in entry.children.iter().filter(|name| !self.is_ignored_name(name)) {
+            let child = reader.get_entry_shallow(&entry.path.join(name))?.map(Cow::into_owned);
+            if options.filters_on_entries() && !self.is_child_visible(&entry.path, name, child.as_ref()) {
+                continue;
+            }
+            children.push((name, child));
+        }
+
+        children.sort_by(|(a, entry_a), (b, entry_b)| {
+            let by_key = match (entry_a, entry_b) {
+                _ if options.sort == SortKey::Name => Ordering::Equal,
+                (Some(x), Some(y)) => match options.sort {
+                    SortKey::Mtime => y.modified.cmp(&x.modified),
+                    SortKey::Children => y.child_count().cmp(&x.child_count()),
+                    SortKey::Size => y.total_size.cmp(&x.total_size),
+                    SortKey::Name => Ordering::Equal,
+                },
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            let ordering = by_key.then_with(|| self.compare_names(a, b));
+            if options.reverse { ordering.reverse() } else { ordering }
+        });
+        Ok(children)
+    }
+}
+
+// ============================================================================
+// JSON Output from a Reader
+// ============================================================================
+
+impl DiskCache {
+    /// The JSON tree below `display_root`, its entries looked up in `reader`
+    ///
+    /// The renderer behind `build_json_output_with_depth`; as with
+    /// `write_tree_streamed`, this cache only supplies the options when
+    /// `can_stream_tree` accepts them.
+    pub fn build_json_output_from<R: CacheReader + ?Sized>(&self, reader: &R, max_depth: Option<usize>) -> Result<String> {
+        if reader.is_empty()? {
+            return Ok(self.json_node_for(None, self.display_root(), None).to_string());
+        }
+        Ok(serde_json::to_string_pretty(&self.json_tree_from(reader, max_depth)?)?)
+    }
+
+    /// JSON tree rooted at `display_root()`, with the top-level extras
+    pub(crate) fn json_tree_from<R: CacheReader + ?Sized>(
+        &self,
+        reader: &R,
+        max_depth: Option<usize>,
+    ) -> Result<serde_json::Value> {
+        let root = self.display_root();
+        let root_entry = reader.get_entry(root)?.map(Cow::into_owned);
+        let mut root_json = self.json_node_for(None, root, root_entry.as_ref());
+
+        // The root node counts against --limit like every other node
+        let mut budget = OutputBudget::new(self.output_options.line_limit);
+        budget.take();
+        let mut summary = RenderSummary::default();
+        if let Some(entry) = &root_entry {
+            self.populate_json_from(reader, &mut root_json, entry, 0, max_depth, &mut budget, &mut summary)?;
+        }
+
+        if let Some(changes) = &self.output_options.changes {
+            let removed: Vec<_> = changes
+                .removed
+                .iter()
+                .filter(|path| path.starts_with(root))
+                .map(|path| path.to_string_lossy())
+                .collect();
+            root_json["removed"] = json!(removed);
+        }
+
+        if budget.truncated {
+            root_json["truncated"] = json!(true);
+        } else if self.output_options.show_report {
+            root_json["summary"] = json!({
+                "directories": summary.directories,
+                "files": summary.known_files(),
+            });
+        }
+        Ok(root_json)
+    }
+
+    /// One node's children from `reader`, counting the children it renders into `summary`
+    #[allow(clippy::too_many_arguments)]
+    fn populate_json_from<R: CacheReader + ?Sized>(
+        &self,
+        reader: &R,
+        node: &mut serde_json::Value,
+        entry: &DirEntry,
+        depth: usize,
+        max_depth: Option<usize>,
+        budget: &mut OutputBudget,
+        summary: &mut RenderSummary,
+    ) -> Result<()> {
+        if max_depth.is_some_and(|max| depth >= max) {
+            // Only --ignore can be counted without reading the children
+            let omitted = if self.output_options.filters_on_entries() {
+                self.reader_children(reader, entry)?.len()
+            } else {
+                entry.children.iter().filter(|name| !self.is_ignored_name(name)).count()
+            };
+            if omitted > 0 {
+                node["truncated"] = json!(true);
+                node["omitted_children"] = json!(omitted);
+            }
+            return Ok(());
+        }
+        let children = self.reader_children(reader, entry)?;
+        if self.output_options.collapse_limit().is_some_and(|limit| children.len() > limit) {
+            node["collapsed"] = json!(true);
+            node["omitted_children"] = json!(children.len());
+            return Ok(());
+        }
+
+        let mut children_array = Vec::with_capacity(children.len());
+        for (name, child) in children {
+            match &child {
+                Some(child) if child.is_dir => summary.directories += 1,
+                Some(_) => summary.files += 1,
+                None => summary.unknown += 1,
+            }
+            if !budget.take() {
+                break;
+            }
+            // Children are read shallow; a node shows its names in full
+            let child = match child {
+                Some(child) if child.spilled_children > 0 => reader.get_entry(&child.path)?.map(Cow::into_owned),
+                child => child,
+            };
+            let child_path = entry.path.join(name);
+            let mut child_json = self.json_node_for(Some(name), &child_path, child.as_ref());
+            if let Some(child) = &child {
+                self.populate_json_from(reader, &mut child_json, child, depth + 1, max_depth, budget, summary)?;
+            }
+            children_array.push(child_json);
+        }
+        node["children"] = json!(children_array);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry};
+    use crate::theme::Theme;
+    use chrono::{TimeZone, Utc};
+    use std::fs;
+    use crate::cache_limcode::LimcodeCache;
+    use std::path::PathBuf;
+
+    fn entry(path: &str, children: &[&str], is_dir: bool, hours: i64) -> DirEntry {
+        DirEntry {
+            modified: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::hours(hours),
+            is_hidden: path.ends_with(".git"),
+            is_dir,
+            size: if is_dir { 0 } else { 10 },
+            ..test_entry(path, children)
+        }
+    }
+
+    /// `/scan` with nested directories, a hidden `.git`, and a listed child that isn't cached
+    fn fixture() -> DiskCache {
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        for e in [
+            entry("/scan", &["src", "docs", ".git", "README.md", "ghost"], true, 5),
+            entry("/scan/src", &["main.rs", "lib", "bin10", "bin2"], true, 9),
+            entry("/scan/src/main.rs", &[], false, 0),
+            entry("/scan/src/lib", &["a", "b", "c"], true, 1),
+            entry("/scan/src/lib/a", &[], true, 1),
+            entry("/scan/src/lib/b", &[], true, 2),
+            entry("/scan/src/lib/c", &["deep"], true, 3),
+            entry("/scan/src/lib/c/deep", &[], true, 3),
+            entry("/scan/src/bin10", &[], true, 4),
+            entry("/scan/src/bin2", &[], true, 4),
+            entry("/scan/docs", &["guide"], true, 2),
+            entry("/scan/docs/guide", &[], true, 2),
+            entry("/scan/.git", &["objects"], true, 7),
+            entry("/scan/.git/objects", &[], true, 7),
+            entry("/scan/README.md", &[], false, 0),
+        ] {
+            cache.entries.insert(e.path.clone(), e);
+        }
+        cache.compute_totals();
+        cache
+    }
+
+    #[test]
+    fn test_streamed_tree_matches_the_in_memory_builders() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_stream_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+
+        let mut cache = fixture();
+        cache.save(&cache_path)?;
+        let reader = RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path)?;
+
+        let theme = Theme::default();
+        type Configure = fn(&mut DiskCache);
+        let variants: Vec<(&str, Configure, Option<usize>)> = vec![
+            ("defaults", |_| {}, None),
+            ("depth", |_| {}, Some(1)),
+            ("annotated", |c| (c.output_options.du, c.output_options.show_counts, c.output_options.align_window) = (true, true, 2), None),
+            ("filtered", |c| {
+                c.output_options.hide_hidden = true;
+                c.output_options.set_ignore_patterns(&["docs".to_string()]).unwrap();
+            }, Some(2)),
+            ("sorted", |c| (c.output_options.sort, c.output_options.natural_sort, c.output_options.reverse) = (SortKey::Mtime, true, true), None),
+            ("limited", |c| c.output_options.line_limit = Some(6), None),
+            ("collapsed", |c| {
+                c.output_options.file_limit = Some(3);
+                c.output_options.set_ignore_patterns(&[".git".to_string(), "ghost".to_string()]).unwrap();
+            }, None),
+            ("bare", |c| (c.output_options.no_indent, c.output_options.full_path, c.show_hidden) = (true, true, true), None),
+        ];
+        for (label, configure, max_depth) in variants {
+            let mut view = cache.clone();
+            view.output_options.show_report = true;
+            configure(&mut view);
+            assert!(view.can_stream_tree(), "{}", label);
+
+            let mut streamed = Vec::new();
+            view.write_tree_streamed(&reader, &mut streamed, max_depth, None)?;
+            assert_eq!(String::from_utf8(streamed)?, view.build_tree_output_with_depth(max_depth)?, "{}", label);
+
+            let mut colored = Vec::new();
+            view.write_tree_streamed(&reader, &mut colored, max_depth, Some(&theme))?;
+            assert_eq!(
+                String::from_utf8(colored)?,
+                view.build_colored_tree_output_with_theme(max_depth, &theme)?,
+                "{} (colored)",
+                label
+            );
+        }
+
+        // Filters that need the whole tree read the cache's own entries, not just the reader
+        let mut view = cache.clone();
+        view.output_options.set_prune(true);
+        assert!(!view.can_stream_tree());
+
+        drop(reader);
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spilled_directories_stream_collapsed_unless_expanded() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_stream_spill_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+
+        // `/scan` (5 children) and `/scan/src` (4) spill their names
+        let mut cache = fixture();
+        cache.spill_children = 3;
+        cache.save(&cache_path)?;
+        let reader = RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path)?;
+        assert_eq!(reader.get_entry_shallow(Path::new("/scan/src"))?.map(|entry| entry.spilled_children), Some(4));
+
+        for (label, spill_limit, file_limit) in [("default", Some(3), None), ("expanded", None, None), ("filelimit", None, Some(4))] {
+            let mut view = cache.clone();
+            view.output_options.show_report = true;
+            view.output_options.spill_limit = spill_limit;
+            view.output_options.file_limit = file_limit;
+            let mut streamed = Vec::new();
+            view.write_tree_streamed(&reader, &mut streamed, None, None)?;
+            let streamed = String::from_utf8(streamed)?;
+            assert_eq!(streamed, view.build_tree_output_with_depth(None)?, "{}", label);
+            assert_eq!(streamed.contains("[5 entries]"), label != "expanded", "{}", label);
+        }
+
+        drop(reader);
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_limcode_reader_matches_the_in_memory_builder() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_stream_limcode_{}", std::process::id()));
+        fs::create_dir_all(&temp_dir)?;
+        let (index_path, data_path) = (temp_dir.join("ptree.limidx"), temp_dir.join("ptree.limdat"));
+
+        let cache = fixture();
+        let mut writer = LimcodeCache::open(&index_path, &data_path)?;
+        for entry in cache.entries.values() {
+            let offset = writer.append_entry(&entry.into())?;
+            writer.index.offsets.insert(entry.path.to_string_lossy().into_owned(), offset);
+        }
+        writer.save_index(&index_path)?;
+        let source = LimcodeCache::open(&index_path, &data_path)?;
+        assert_eq!(source.reader().map(|reader| reader.corrupt()), Some(0));
+
+        type Configure = fn(&mut DiskCache);
+        let variants: Vec<(&str, Configure, Option<usize>)> = vec![
+            ("defaults", |_| {}, None),
+            ("depth", |_| {}, Some(1)),
+            ("filtered", |c| {
+                c.output_options.hide_hidden = true;
+                c.output_options.set_ignore_patterns(&["docs".to_string()]).unwrap();
+            }, Some(2)),
+            ("sorted", |c| (c.output_options.sort, c.output_options.natural_sort, c.output_options.reverse) = (SortKey::Mtime, true, true), None),
+            ("limited", |c| c.output_options.line_limit = Some(6), None),
+            ("collapsed", |c| c.output_options.file_limit = Some(3), None),
+            ("bare", |c| (c.output_options.no_indent, c.output_options.full_path, c.show_hidden) = (true, true, true), None),
+        ];
+        for (label, configure, max_depth) in variants {
+            let mut view = cache.clone();
+            view.output_options.show_report = true;
+            configure(&mut view);
+
+            let mut streamed = Vec::new();
+            view.write_tree_streamed(&source, &mut streamed, max_depth, None)?;
+            assert_eq!(String::from_utf8(streamed)?, view.build_tree_output_with_depth(max_depth)?, "{}", label);
+        }
+
+        drop(source);
+        let _ = fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use crate::cache::DirEntry;
+use ptree_core::PTreeError;
+
+/// An SGR style such as `01;34`, applied with raw ANSI escapes
+///
+/// An empty style paints text unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Style {
+    sgr: String,
+}
+
+impl Style {
+    /// Build a style from SGR parameters; returns None unless it is only digits and `;`
+    pub fn new(sgr: &str) -> Option<Self> {
+        if sgr.chars().all(|c| c.is_ascii_digit() || c == ';') {
+            Some(Style { sgr: sgr.to_string() })
+        } else {
+            None
+        }
+    }
+
+    /// Faint text (SGR 2), for lines shown only as context
+    pub fn faint() -> Self {
+        Style { sgr: "2".to_string() }
+    }
+
+    /// Red (SGR 31), for symlinks whose target is missing
+    pub fn broken() -> Self {
+        Style { sgr: "31".to_string() }
+    }
+
+    /// Wrap text in this style's escape sequence
+    pub fn paint(&self, text: &str) -> String {
+        if self.sgr.is_empty() {
+            text.to_string()
+        } else {
+            format!("\x1b[{}m{}\x1b[0m", self.sgr, text)
+        }
+    }
+}
+
+/// Colors used by the colored tree printer, one per kind of entry
+///
+/// Configured with LS_COLORS-style `key=sgr` pairs separated by `:`:
+/// `rt` root, `di` directory, `ln` symlink, `hi` hidden, `fi` file,
+/// `tr` tree connectors, and `ht`/`wm` for hot/warm heatmap entries.
+/// Unknown keys and malformed values are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub root: Style,
+    pub directory: Style,
+    pub symlink: Style,
+    pub hidden: Style,
+    pub file: Style,
+    pub connector: Style,
+    /// Heatmap color for directories changed within the "hot" window
+    pub hot: Style,
+    /// Heatmap color for directories changed within the "warm" window
+    pub warm: Style,
+}
+
+/// Recency bucket of an entry for `--heatmap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatLevel {
+    Hot,
+    Warm,
+    Cold,
+}
+
+/// Age limits for the heatmap buckets, measured back from `last_scan`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeatmapThresholds {
+    pub hot: chrono::Duration,
+    pub warm: chrono::Duration,
+}
+
+impl HeatmapThresholds {
+    /// Thresholds from `--heatmap-hot-hours` and `--heatmap-warm-hours`
+    ///
+    /// Fails unless both fit a `chrono::Duration` and `hot` is below `warm`.
+    pub fn from_hours(hot: u64, warm: u64) -> Result<Self, PTreeError> {
+        let hours = |flag: &str, hours: u64| {
+            i64::try_from(hours)
+                .ok()
+                .and_then(chrono::Duration::try_hours)
+                .ok_or_else(|| PTreeError::InvalidArgument(format!("{} {} is out of range", flag, hours)))
+        };
+        let thresholds = HeatmapThresholds {
+            hot: hours("--heatmap-hot-hours", hot)?,
+            warm: hours("--heatmap-warm-hours", warm)?,
+        };
+        if thresholds.hot >= thresholds.warm {
+            return Err(PTreeError::InvalidArgument(format!(
+                "--heatmap-hot-hours ({}) must be less than --heatmap-warm-hours ({})",
+                hot, warm
+            )));
+        }
+        Ok(thresholds)
+    }
+}
+
+impl Default for HeatmapThresholds {
+    fn default() -> Self {
+        HeatmapThresholds {
+            hot: chrono::Duration::days(1),
+            warm: chrono::Duration::weeks(1),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            root: Style { sgr: "01;34".to_string() },
+            directory: Style { sgr: "94".to_string() },
+            symlink: Style { sgr: "36".to_string() },
+            hidden: Style { sgr: "90".to_string() },
+            file: Style::default(),
+            connector: Style { sgr: "36".to_string() },
+            hot: Style { sgr: "32".to_string() },
+            warm: Style { sgr: "33".to_string() },
+        }
+    }
+}
+
+impl Theme {
+    /// Defaults, overridden by `LS_COLORS` and then by `PTREE_COLORS`
+    pub fn from_env() -> Self {
+        let mut theme = Theme::default();
+        for var in ["LS_COLORS", "PTREE_COLORS"] {
+            if let Ok(spec) = std::env::var(var) {
+                theme.apply_spec(&spec);
+            }
+        }
+        theme
+    }
+
+    /// Apply a `di=34:ln=36:hi=90` style specification on top of this theme
+    pub fn apply_spec(&mut self, spec: &str) {
+        for pair in spec.split(':') {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let Some(style) = Style::new(value.trim()) else {
+                continue;
+            };
+
+            match key.trim() {
+                "rt" => self.root = style,
+                "di" => self.directory = style,
+                "ln" => self.symlink = style,
+                "hi" => self.hidden = style,
+                "fi" => self.file = style,
+                "tr" => self.connector = style,
+                "ht" => self.hot = style,
+                "wm" => self.warm = style,
+                _ => {}
+            }
+        }
+    }
+
+    /// Heatmap style layered over the entry style: hot/warm replace it, cold keeps it
+    pub fn heat_style<'a>(&'a self, level: HeatLevel, base: &'a Style) -> &'a Style {
+        match level {
+            HeatLevel::Hot => &self.hot,
+            HeatLevel::Warm => &self.warm,
+            HeatLevel::Cold => base,
+        }
+    }
+
+    /// Style for a child entry: symlink, then hidden, then directory or file
+    pub fn style_for(&self, entry: Option<&DirEntry>) -> &Style {
+        match entry {
+            Some(e) if e.symlink_target.is_some() => &self.symlink,
+            Some(e) if e.is_hidden => &self.hidden,
+            Some(e) if e.is_dir => &self.directory,
+            _ => &self.file,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_spec_ignores_unknown_and_malformed() {
+        let mut theme = Theme::default();
+        theme.apply_spec("di=01;32:zz=31:*.tar=01;31:ln=bogus:hi=2::fi");
+
+        assert_eq!(theme.directory, Style::new("01;32").unwrap());
+        assert_eq!(theme.hidden, Style::new("2").unwrap());
+        assert_eq!(theme.symlink, Theme::default().symlink);
+        assert_eq!(theme.file, Style::default());
+    }
+
+    #[test]
+    fn test_paint_wraps_only_non_empty_styles() {
+        assert_eq!(Style::new("34").unwrap().paint("dir"), "\x1b[34mdir\x1b[0m");
+        assert_eq!(Style::default().paint("file"), "file");
+    }
+
+    #[test]
+    fn test_heatmap_hours_are_checked() {
+        let thresholds = HeatmapThresholds::from_hours(24, 168).unwrap();
+        assert_eq!(thresholds, HeatmapThresholds::default());
+
+        assert!(matches!(HeatmapThresholds::from_hours(168, 24), Err(PTreeError::InvalidArgument(_))));
+        assert!(matches!(HeatmapThresholds::from_hours(24, 24), Err(PTreeError::InvalidArgument(_))));
+        assert!(matches!(HeatmapThresholds::from_hours(1, u64::MAX), Err(PTreeError::InvalidArgument(_))));
+        assert!(matches!(HeatmapThresholds::from_hours(1, i64::MAX as u64), Err(PTreeError::InvalidArgument(_))));
+    }
+}
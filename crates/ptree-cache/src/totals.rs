@@ -0,0 +1,144 @@
+use crate::cache::{DirEntry, DiskCache};
+use crate::stats::depth_of;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Recursive Sizes (--du, ptree stats)
+// ============================================================================
+
+impl DiskCache {
+    /// Fill in `total_size` and `size_is_lower_bound` for every cached entry
+    ///
+    /// Run after a traversal. Entries are visited deepest first, so each
+    /// directory sums children that are already final: one sort and one
+    /// pass, however deep the tree is.
+    pub fn compute_totals(&mut self) {
+        self.flush_pending_writes();
+        let paths: Vec<PathBuf> = self.entries.keys().cloned().collect();
+        self.recompute_totals(paths);
+    }
+
+    /// Mark `path` and its cached ancestors as needing new totals
+    ///
+    /// For updates that touch a few paths (an incremental USN pass, an
+    /// import): `refresh_totals` later recomputes only what was marked.
+    pub fn invalidate_totals(&mut self, path: &Path) {
+        for ancestor in path.ancestors() {
+            if !self.entries.contains_key(ancestor) || !self.stale_totals.insert(ancestor.to_path_buf()) {
+                break;
+            }
+        }
+    }
+
+    /// Recompute the totals invalidated since the last refresh (done before every save)
+    pub fn refresh_totals(&mut self) {
+        if self.stale_totals.is_empty() {
+            return;
+        }
+        let stale: Vec<PathBuf> = self.stale_totals.drain().collect();
+        self.recompute_totals(stale);
+    }
+
+    fn recompute_totals(&mut self, mut paths: Vec<PathBuf>) {
+        paths.sort_unstable_by_key(|path| Reverse(depth_of(path)));
+        for path in paths {
+            let Some(entry) = self.entries.get(&path) else {
+                continue;
+            };
+            let (total, lower_bound) = self.subtree_total(entry);
+            let Some(entry) = self.entries.get_mut(&path) else {
+                continue;
+            };
+            if (entry.total_size, entry.size_is_lower_bound) != (total, lower_bound) {
+                entry.total_size = total;
+                entry.size_is_lower_bound = lower_bound;
+                self.mark_changed(&path);
+            }
+        }
+    }
+
+    /// `entry`'s total from its children's, which must already be current
+    ///
+    /// A child listed but not cached counts as unknown, as does anything
+    /// skipped during the scan. Evicted stubs have no children left to sum,
+    /// so they keep the total saved with them.
+    fn subtree_total(&self, entry: &DirEntry) -> (u64, bool) {
+        if !entry.is_dir {
+            return (entry.size, false);
+        }
+        if entry.evicted {
+            return (entry.total_size, entry.size_is_lower_bound);
+        }
+        let mut total = (0, entry.skipped_children || entry.access_error.is_some());
+        for name in &entry.children {
+            let (bytes, lower_bound) = match self.entries.get(&entry.path.join(name)) {
+                Some(child) if child.is_dir => (child.total_size, child.size_is_lower_bound),
+                Some(child) => (child.size, false),
+                None => (0, true),
+            };
+            total.0 += bytes;
+            total.1 |= lower_bound;
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_entry, test_file};
+    use crate::children::ChildNames;
+
+    fn entry(path: &str, children: &[&str], size: u64) -> DirEntry {
+        if size == 0 { test_entry(path, children) } else { test_file(path, size) }
+    }
+
+    fn total(cache: &DiskCache, path: &str) -> (u64, bool) {
+        let entry = &cache.entries[Path::new(path)];
+        (entry.total_size, entry.size_is_lower_bound)
+    }
+
+    #[test]
+    fn test_totals_sum_bottom_up_and_refresh_only_what_changed() {
+        let mut cache = DiskCache::new();
+        for e in [
+            entry("/scan", &["a", "b", "top.txt"], 0),
+            entry("/scan/top.txt", &[], 1),
+            entry("/scan/a", &["x"], 0),
+            entry("/scan/a/x", &["f"], 0),
+            entry("/scan/a/x/f", &[], 100),
+            entry("/scan/b", &["g", "missing"], 0),
+            entry("/scan/b/g", &[], 10),
+        ] {
+            cache.entries.insert(e.path.clone(), e);
+        }
+        cache.compute_totals();
+        assert_eq!(total(&cache, "/scan/a"), (100, false));
+        assert_eq!(total(&cache, "/scan/b"), (10, true));
+        assert_eq!(total(&cache, "/scan"), (111, true));
+        assert_eq!(total(&cache, "/scan/a/x/f"), (100, false));
+
+        // Change a file deep in `a`: only its ancestors are marked, and refreshed to match a full pass
+        cache.entries.get_mut(Path::new("/scan/a/x/f")).unwrap().size = 300;
+        cache.invalidate_totals(Path::new("/scan/a/x/f"));
+        assert_eq!(cache.stale_totals.len(), 4);
+        cache.refresh_totals();
+        assert!(cache.stale_totals.is_empty());
+        assert_eq!(total(&cache, "/scan"), (311, true));
+
+        let mut full = cache.clone();
+        full.compute_totals();
+        for path in cache.entries.keys() {
+            let (a, b) = (&cache.entries[path], &full.entries[path]);
+            assert_eq!((a.total_size, a.size_is_lower_bound), (b.total_size, b.size_is_lower_bound), "{}", path.display());
+        }
+
+        // Evicted stubs keep the total they were saved with
+        let b = cache.entries.get_mut(Path::new("/scan/b")).unwrap();
+        (b.evicted, b.children) = (true, ChildNames::new());
+        cache.entries.remove(Path::new("/scan/b/g"));
+        cache.compute_totals();
+        assert_eq!(total(&cache, "/scan/b"), (10, true));
+    }
+}
@@ -0,0 +1,425 @@
+use crate::cache::{format_count, DiskCache};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Consistency Check (--verify-cache), Orphan Collection (ptree clean --gc), Stale Sweep
+// ============================================================================
+
+/// Paths kept per kind of violation in a `ValidationReport`
+pub const VALIDATION_SAMPLES: usize = 10;
+
+/// Entries `gc_orphans_if_many` samples before a save
+const GC_SAMPLE: usize = 1024;
+
+/// Share of sampled entries that have to be detached before a save collects orphans
+pub const GC_ORPHAN_RATIO: f64 = 0.01;
+
+/// How often one invariant is broken, with the first few paths (sorted) that break it
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Violations {
+    pub count: usize,
+    pub samples: Vec<PathBuf>,
+}
+
+impl Violations {
+    fn from_paths(mut paths: Vec<PathBuf>) -> Self {
+        let count = paths.len();
+        paths.sort_unstable();
+        paths.truncate(VALIDATION_SAMPLES);
+        Violations { count, samples: paths }
+    }
+}
+
+/// Where `entries`, the parents' `children`, and `root` disagree, from `DiskCache::validate`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ValidationReport {
+    /// Entries checked
+    pub entries: usize,
+    /// `root` is set but has no entry while others do
+    pub root_missing: bool,
+    /// Names in a `children` list with no entry at the joined path (samples are those paths)
+    pub missing_children: Violations,
+    /// Names listed more than once by the same parent (samples are the joined paths)
+    pub duplicate_children: Violations,
+    /// Entries no `children` list leads to from a root: an entry with no cached ancestor
+    pub unreachable: Violations,
+    /// Entries whose `name` is not the last component of their path
+    pub misnamed: Violations,
+}
+
+impl DiskCache {
+    /// Check the invariants between `entries`, `children`, and `root`
+    ///
+    /// Incremental updates and merges must keep every listed child cached,
+    /// every entry listed by its parent, and each `name` equal to the last
+    /// component of its key. Entries without any cached ancestor are roots
+    /// (the scanned root, or another drive after a merge); everything else
+    /// has to be reachable from one through `children`. Only loaded entries
+    /// are checked, so call `load_all_entries_lazy` first on an opened cache.
+    pub fn validate(&self) -> ValidationReport {
+        let mut missing = Vec::new();
+        let mut duplicates = Vec::new();
+        let mut misnamed = Vec::new();
+        for (path, entry) in &self.entries {
+            if path.file_name().is_some_and(|name| name.to_string_lossy() != entry.name) {
+                misnamed.push(path.clone());
+            }
+            let mut seen = HashSet::with_capacity(entry.children.len());
+            for name in &entry.children {
+                let child = path.join(name);
+                if !seen.insert(name) {
+                    duplicates.push(child);
+                } else if !self.entries.contains_key(&child) {
+                    missing.push(child);
+                }
+            }
+        }
+
+        let reached = self.reachable();
+        let unreachable = self
+            .entries
+            .keys()
+            .filter(|path| !reached.contains(path.as_path()))
+            .cloned()
+            .collect();
+
+        ValidationReport {
+            entries: self.entries.len(),
+            root_missing: !self.entries.is_empty()
+                && !self.root.as_os_str().is_empty()
+                && !self.entries.contains_key(&self.root),
+            missing_children: Violations::from_paths(missing),
+            duplicate_children: Violations::from_paths(duplicates),
+            unreachable: Violations::from_paths(unreachable),
+            misnamed: Violations::from_paths(misnamed),
+        }
+    }
+
+    /// Drop every entry unreachable from a root through `children`, returning how many went
+    ///
+    /// Roots are as in `validate`: entries with no cached ancestor, so
+    /// other drives of a merged cache are kept whole. What goes is what a
+    /// tree never shows, such as grandchildren left behind by a deleted
+    /// parent or a subtree its parent no longer lists.
+    pub fn gc_orphans(&mut self) -> usize {
+        self.flush_pending_writes();
+        let orphans: Vec<PathBuf> = {
+            let reached = self.reachable();
+            self.entries.keys().filter(|path| !reached.contains(path.as_path())).cloned().collect()
+        };
+        for path in &orphans {
+            self.entries.remove(path);
+            self.stale_totals.remove(path);
+        }
+        self.dirty |= !orphans.is_empty();
+        orphans.len()
+    }
+
+    /// Drop the entries at or below `root` that traversal `generation` didn't visit, returning how many went
+    ///
+    /// For after a complete traversal of `root` (see `scan_generation`),
+    /// which visits everything still on disk: what it didn't reach was
+    /// deleted, or is skipped now, since it was cached. Each stale subtree
+    /// goes through `remove_entry`, which also takes its name out of a
+    /// parent's `children`. A partial traversal must not sweep.
+    pub fn sweep_stale(&mut self, root: &Path, generation: u64) -> usize {
+        self.flush_pending_writes();
+        let stale: HashSet<PathBuf> = self
+            .entries
+            .values()
+            .filter(|entry| entry.generation < generation && entry.path.starts_with(root))
+            .map(|entry| entry.path.clone())
+            .collect();
+        let before = self.entries.len();
+        for path in &stale {
+            // Goes with its stale parent
+            if path.parent().is_some_and(|parent| stale.contains(parent)) {
+                continue;
+            }
+            self.remove_entry(path);
+        }
+        before - self.entries.len()
+    }
+
+    /// `gc_orphans` when a sample of the entries suggests more than `GC_ORPHAN_RATIO` are orphaned
+    ///
+    /// Run by `prepare_save`. Each sampled entry is checked by walking up
+    /// its own ancestors, each listing read once, so a healthy cache costs
+    /// a few thousand lookups; the full walk only runs once drift shows.
+    pub(crate) fn gc_orphans_if_many(&mut self) {
+        let mut known = HashMap::new();
+        let mut listings = HashMap::new();
+        let mut sampled = 0usize;
+        let mut orphaned = 0usize;
+        for entry in self.entries.sample(GC_SAMPLE) {
+            sampled += 1;
+            orphaned += usize::from(!self.is_attached(&entry.path, &mut known, &mut listings));
+        }
+        if orphaned == 0 || (orphaned as f64) < sampled as f64 * GC_ORPHAN_RATIO {
+            return;
+        }
+        let removed = self.gc_orphans();
+        log::info!(
+            "{} of {} sampled entries were orphaned; dropped {} orphaned entries",
+            orphaned,
+            sampled,
+            format_count(removed)
+        );
+    }
+
+    /// Whether the cached `path` is reachable from a root, judged by its ancestors alone
+    ///
+    /// `known` remembers the answer for every path on the way up and
+    /// `listings` each parent's names, for the next call.
+    fn is_attached<'a>(
+        &'a self,
+        path: &'a Path,
+        known: &mut HashMap<&'a Path, bool>,
+        listings: &mut HashMap<&'a Path, HashSet<&'a str>>,
+    ) -> bool {
+        let mut chain = Vec::new();
+        let mut current = path;
+        let attached = loop {
+            if let Some(&attached) = known.get(current) {
+                break attached;
+            }
+            chain.push(current);
+            let (Some(parent), Some(name)) = (current.parent(), current.file_name()) else {
+                break true;
+            };
+            match self.entries.get_key_value(parent) {
+                Some((parent, entry)) => {
+                    let listed = listings.entry(parent.as_path()).or_insert_with(|| entry.children.iter().collect());
+                    if !listed.contains(&*name.to_string_lossy()) {
+                        break false;
+                    }
+                    current = parent;
+                }
+                None => break !parent.ancestors().skip(1).any(|ancestor| self.entries.contains_key(ancestor)),
+            }
+        };
+        for path in chain {
+            known.insert(path, attached);
+        }
+        attached
+    }
+
+    /// Entries reachable through `children` from one with no cached ancestor
+    fn reachable(&self) -> HashSet<&Path> {
+        let mut reached: HashSet<&Path> = HashSet::with_capacity(self.entries.len());
+        let mut stack: Vec<&Path> = self
+            .entries
+            .keys()
+            .map(PathBuf::as_path)
+            .filter(|path| path.ancestors().skip(1).all(|ancestor| !self.entries.contains_key(ancestor)))
+            .collect();
+        while let Some(path) = stack.pop() {
+            let Some((path, entry)) = self.entries.get_key_value(path) else {
+                continue;
+            };
+            if !reached.insert(path) {
+                continue;
+            }
+            for name in &entry.children {
+                if let Some((child, _)) = self.entries.get_key_value(&path.join(name)) {
+                    stack.push(child);
+                }
+            }
+        }
+        reached
+    }
+}
+
+impl ValidationReport {
+    /// No invariant is broken
+    pub fn is_ok(&self) -> bool {
+        !self.root_missing
+            && [&self.missing_children, &self.duplicate_children, &self.unreachable, &self.misnamed]
+                .iter()
+                .all(|violations| violations.count == 0)
+    }
+
+    /// Human-readable report: a line per broken invariant, each followed by its samples
+    pub fn to_text(&self) -> String {
+        if self.is_ok() {
+            return format!("cache OK: {} entries consistent", format_count(self.entries));
+        }
+        let mut out = format!("cache inconsistent ({} entries checked)\n", format_count(self.entries));
+        if self.root_missing {
+            out.push_str("root has no entry\n");
+        }
+        for (title, violations) in [
+            ("listed children without an entry", &self.missing_children),
+            ("children listed twice", &self.duplicate_children),
+            ("entries unreachable from a root", &self.unreachable),
+            ("entries named unlike their path", &self.misnamed),
+        ] {
+            if violations.count == 0 {
+                continue;
+            }
+            out.push_str(&format!("{}: {}\n", title, format_count(violations.count)));
+            for path in &violations.samples {
+                out.push_str(&format!("  {}\n", path.display()));
+            }
+            if violations.count > violations.samples.len() {
+                out.push_str(&format!("  ... and {} more\n", format_count(violations.count - violations.samples.len())));
+            }
+        }
+        out.truncate(out.trim_end().len());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_entry;
+
+
+    fn cache(entries: &[(&str, &[&str])]) -> DiskCache {
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        for (path, children) in entries {
+            cache.entries.insert(PathBuf::from(path), test_entry(path, children));
+        }
+        cache
+    }
+
+    #[test]
+    fn test_consistent_caches_with_several_roots_validate() {
+        let cache = cache(&[
+            ("/scan", &["a", "b"]),
+            ("/scan/a", &["x"]),
+            ("/scan/a/x", &[]),
+            ("/scan/b", &[]),
+            ("/other", &["c"]),
+            ("/other/c", &[]),
+        ]);
+        let report = cache.validate();
+        assert!(report.is_ok(), "{}", report.to_text());
+        assert_eq!(report.entries, 6);
+        assert!(DiskCache::new().validate().is_ok());
+    }
+
+    #[test]
+    fn test_each_kind_of_drift_is_counted_and_sampled() {
+        let mut cache = cache(&[
+            ("/scan", &["a", "gone", "a"]),
+            ("/scan/a", &[]),
+            // Cached but no longer listed by /scan, taking its subtree with it
+            ("/scan/lost", &["deep"]),
+            ("/scan/lost/deep", &[]),
+            // Parent not cached while /scan is
+            ("/scan/nowhere/orphan", &[]),
+        ]);
+        cache.entries.get_mut("/scan/a").unwrap().name = "A".to_string();
+
+        let report = cache.validate();
+        assert!(!report.is_ok());
+        assert!(!report.root_missing);
+        assert_eq!(report.missing_children, Violations { count: 1, samples: vec![PathBuf::from("/scan/gone")] });
+        assert_eq!(report.duplicate_children.samples, [Path::new("/scan/a")]);
+        assert_eq!(
+            report.unreachable.samples,
+            [Path::new("/scan/lost"), Path::new("/scan/lost/deep"), Path::new("/scan/nowhere/orphan")]
+        );
+        assert_eq!(report.misnamed.samples, [Path::new("/scan/a")]);
+        let text = report.to_text();
+        assert!(text.contains("entries unreachable from a root: 3\n  /scan/lost\n"), "{}", text);
+
+        cache.entries.remove("/scan");
+        let report = cache.validate();
+        assert!(report.root_missing);
+        // Without /scan, /scan/a and /scan/lost are roots of their own
+        assert_eq!(report.unreachable.count, 0);
+    }
+
+    #[test]
+    fn test_gc_drops_orphans_but_keeps_other_roots() {
+        let mut cache = cache(&[
+            ("/scan", &["a"]),
+            ("/scan/a", &[]),
+            // Left behind when /scan/deleted went
+            ("/scan/deleted/x", &["y"]),
+            ("/scan/deleted/x/y", &[]),
+            ("/scan/unlisted", &[]),
+            // Another drive merged in
+            ("/other", &["c"]),
+            ("/other/c", &[]),
+        ]);
+        cache.dirty = false;
+        assert_eq!(cache.gc_orphans(), 3);
+        assert!(cache.dirty);
+        assert!(cache.validate().is_ok());
+        let mut kept: Vec<&Path> = cache.entries.keys().map(PathBuf::as_path).collect();
+        kept.sort_unstable();
+        assert_eq!(kept, ["/other", "/other/c", "/scan", "/scan/a"].map(Path::new));
+        assert_eq!(cache.gc_orphans(), 0);
+    }
+
+    #[test]
+    fn test_sweep_drops_unvisited_entries_below_the_root_only() {
+        let mut cache = cache(&[
+            ("/scan", &["a", "deleted"]),
+            ("/scan/a", &[]),
+            ("/scan/deleted", &["x"]),
+            ("/scan/deleted/x", &[]),
+            ("/other", &["c"]),
+            ("/other/c", &[]),
+        ]);
+        for path in ["/scan", "/scan/a"] {
+            cache.entries.get_mut(path).unwrap().generation = 2;
+        }
+        cache.dirty = false;
+        assert_eq!(cache.sweep_stale(Path::new("/scan"), 2), 2);
+        assert!(cache.dirty);
+        assert_eq!(cache.entries.get("/scan").unwrap().children.iter().collect::<Vec<_>>(), ["a"]);
+        assert!(cache.validate().is_ok());
+        let mut kept: Vec<&Path> = cache.entries.keys().map(PathBuf::as_path).collect();
+        kept.sort_unstable();
+        assert_eq!(kept, ["/other", "/other/c", "/scan", "/scan/a"].map(Path::new));
+    }
+
+    #[test]
+    fn test_save_collects_orphans_once_the_sample_finds_them() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("ptree_gc_save_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let cache_path = dir.join("ptree.dat");
+
+        let mut cache = cache(&[("/scan", &["a"]), ("/scan/a", &[])]);
+        // One stray entry among a healthy thousand is below GC_ORPHAN_RATIO and survives
+        let names: Vec<String> = (0..1000).map(|i| format!("d{}", i)).collect();
+        for name in &names {
+            let path = PathBuf::from("/scan/a").join(name);
+            cache.entries.insert(path.clone(), test_entry(path.to_str().unwrap(), &[]));
+        }
+        cache.entries.get_mut("/scan/a").unwrap().children = names.iter().collect();
+        cache.entries.insert(PathBuf::from("/scan/gone/stray"), test_entry("/scan/gone/stray", &[]));
+        cache.save(&cache_path)?;
+        assert!(cache.entries.contains_key("/scan/gone/stray"));
+
+        // Dropping /scan/a from the listing orphans its whole subtree
+        cache.entries.get_mut("/scan").unwrap().children.clear();
+        cache.dirty = true;
+        cache.save(&cache_path)?;
+        assert_eq!(cache.entries.len(), 1);
+        let mut reopened = DiskCache::open(&cache_path)?;
+        reopened.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(reopened.entries.keys().collect::<Vec<_>>(), [Path::new("/scan")]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_samples_are_capped() {
+        let names: Vec<String> = (0..VALIDATION_SAMPLES + 5).map(|i| format!("d{:02}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let cache = cache(&[("/scan", &names)]);
+        let report = cache.validate();
+        assert_eq!(report.missing_children.count, VALIDATION_SAMPLES + 5);
+        assert_eq!(report.missing_children.samples.len(), VALIDATION_SAMPLES);
+        assert!(report.to_text().ends_with("... and 5 more"));
+    }
+}
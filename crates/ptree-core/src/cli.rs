@@ -1,5 +1,9 @@
-use clap::Parser;
+use crate::config;
+use crate::error::PTreeResult;
+use clap::{Parser, Subcommand};
 use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::PathBuf;
 
 // ============================================================================
 // Output Format Options
@@ -9,6 +13,11 @@ use std::collections::HashSet;
 pub enum OutputFormat {
     Tree,
     Json,
+    Csv,
+    Ndjson,
+    Dot,
+    Html,
+    Markdown,
 }
 
 impl std::str::FromStr for OutputFormat {
@@ -18,6 +27,11 @@ impl std::str::FromStr for OutputFormat {
         match s.to_lowercase().as_str() {
             "tree" | "ascii" => Ok(OutputFormat::Tree),
             "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "dot" | "graphviz" => Ok(OutputFormat::Dot),
+            "html" => Ok(OutputFormat::Html),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
             other => Err(format!("Unknown format: {}", other)),
         }
     }
@@ -47,6 +61,305 @@ impl std::str::FromStr for ColorMode {
     }
 }
 
+/// Decide whether to emit ANSI colors
+///
+/// `--color always`/`never` win outright. For `auto`, a non-empty `NO_COLOR`
+/// disables color, then `CLICOLOR_FORCE` (any value but `0`) enables it even
+/// when piped, and otherwise color follows whether output is a terminal.
+/// `env` looks up an environment variable (`std::env::var(..).ok()` in main).
+pub fn resolve_color(mode: ColorMode, env: impl Fn(&str) -> Option<String>, is_tty: bool) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            if env("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+                false
+            } else if env("CLICOLOR_FORCE").is_some_and(|value| !value.is_empty() && value != "0") {
+                true
+            } else {
+                is_tty
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Timestamp Format Options
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtimeFormat {
+    Rfc3339,
+    Relative,
+}
+
+impl std::str::FromStr for MtimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rfc3339" | "iso" => Ok(MtimeFormat::Rfc3339),
+            "relative" | "age" => Ok(MtimeFormat::Relative),
+            other => Err(format!("Unknown mtime format: {}", other)),
+        }
+    }
+}
+
+// ============================================================================
+// Sort Key Options
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Mtime,
+    Size,
+    Children,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "name" => Ok(SortKey::Name),
+            "mtime" | "time" => Ok(SortKey::Mtime),
+            "size" => Ok(SortKey::Size),
+            "children" => Ok(SortKey::Children),
+            other => Err(format!("Unknown sort key: {}", other)),
+        }
+    }
+}
+
+// ============================================================================
+// Search Options
+// ============================================================================
+
+/// How `ptree search` reads its pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchMode {
+    /// Glob if the pattern contains `*`, `?`, `[` or `{`, substring otherwise
+    #[default]
+    Auto,
+    Glob,
+    Substring,
+}
+
+impl std::str::FromStr for SearchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(SearchMode::Auto),
+            "glob" => Ok(SearchMode::Glob),
+            "substring" | "substr" => Ok(SearchMode::Substring),
+            other => Err(format!("Unknown search mode: {}", other)),
+        }
+    }
+}
+
+// ============================================================================
+// Charset Options
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Charset {
+    #[default]
+    Utf8,
+    Ascii,
+}
+
+impl std::str::FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf8" | "utf-8" | "unicode" => Ok(Charset::Utf8),
+            "ascii" => Ok(Charset::Ascii),
+            other => Err(format!("Unknown charset: {}", other)),
+        }
+    }
+}
+
+// ============================================================================
+// Cache Backend Options
+// ============================================================================
+
+/// Storage behind `DiskCache`: memory-mapped rkyv files, a SQLite database, a key-value store, or Limcode archives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheBackend {
+    /// `<name>.idx` and `<name>.dat`
+    #[default]
+    Rkyv,
+    /// `<name>.db`, with a `dirs` table that can be queried with SQL
+    Sqlite,
+    /// `<name>.redb`, an embedded key-value store keyed by path (experimental)
+    Kv,
+    /// `<name>.limidx` and `<name>.limdat`, rkyv archives read in place from the mapping (experimental)
+    Limcode,
+}
+
+impl CacheBackend {
+    /// Name as `--backend` takes it
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheBackend::Rkyv => "rkyv",
+            CacheBackend::Sqlite => "sqlite",
+            CacheBackend::Kv => "kv",
+            CacheBackend::Limcode => "limcode",
+        }
+    }
+}
+
+impl std::str::FromStr for CacheBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rkyv" | "mmap" => Ok(CacheBackend::Rkyv),
+            "sqlite" | "sql" => Ok(CacheBackend::Sqlite),
+            "kv" | "redb" => Ok(CacheBackend::Kv),
+            "limcode" => Ok(CacheBackend::Limcode),
+            other => Err(format!("Unknown cache backend: {}", other)),
+        }
+    }
+}
+
+// ============================================================================
+// Time Filter Options
+// ============================================================================
+
+/// A point in time for `--newer-than`/`--older-than`
+///
+/// Either an age back from now (`90s`, `15m`, `12h`, `30d`, `2w`, `1y`, with
+/// a year counted as 365 days) or a date: `2024-01-01` (midnight UTC) or a
+/// full RFC 3339 timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBound {
+    Ago(chrono::Duration),
+    At(chrono::DateTime<chrono::Utc>),
+}
+
+impl TimeBound {
+    /// The instant this bound stands for, with ages measured back from `now`
+    pub fn resolve(self, now: chrono::DateTime<chrono::Utc>) -> chrono::DateTime<chrono::Utc> {
+        match self {
+            TimeBound::Ago(age) => now - age,
+            TimeBound::At(instant) => instant,
+        }
+    }
+}
+
+impl std::str::FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(TimeBound::At(date.and_time(chrono::NaiveTime::MIN).and_utc()));
+        }
+        if let Ok(instant) = chrono::DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeBound::At(instant.with_timezone(&chrono::Utc)));
+        }
+
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, unit) = s.split_at(split);
+        let amount: i64 = amount
+            .parse()
+            .map_err(|_| format!("Invalid age or date: {} (expected e.g. 30d or 2024-01-01)", s))?;
+        let age = match unit.to_lowercase().as_str() {
+            "s" => chrono::Duration::try_seconds(amount),
+            "m" => chrono::Duration::try_minutes(amount),
+            "h" => chrono::Duration::try_hours(amount),
+            "d" => chrono::Duration::try_days(amount),
+            "w" => chrono::Duration::try_weeks(amount),
+            "y" => amount.checked_mul(365).and_then(chrono::Duration::try_days),
+            "" => return Err(format!("Missing age unit: {} (expected s, m, h, d, w or y)", s)),
+            other => return Err(format!("Unknown age unit: {} (expected s, m, h, d, w or y)", other)),
+        };
+        age.map(TimeBound::Ago).ok_or_else(|| format!("Age out of range: {}", s))
+    }
+}
+
+// ============================================================================
+// Size Options
+// ============================================================================
+
+/// A byte count for `--max-cache-bytes`
+///
+/// Plain bytes (`500000`) or a number with a binary unit: `K`, `M`, `G` or
+/// `T`, optionally followed by `B` or `iB` (`64M`, `1.5GiB`, `512kb`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl std::str::FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        let (amount, unit) = s.split_at(split);
+        let amount: f64 = amount
+            .parse()
+            .map_err(|_| format!("Invalid size: {} (expected e.g. 500000 or 64M)", s))?;
+        let unit = unit.trim().to_lowercase();
+        let unit = unit.strip_suffix("ib").or_else(|| unit.strip_suffix('b')).unwrap_or(&unit);
+        let scale: u64 = match unit {
+            "" => 1,
+            "k" => 1 << 10,
+            "m" => 1 << 20,
+            "g" => 1 << 30,
+            "t" => 1 << 40,
+            other => return Err(format!("Unknown size unit: {} (expected K, M, G or T)", other)),
+        };
+        let bytes = amount * scale as f64;
+        if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+            return Err(format!("Size out of range: {}", s));
+        }
+        Ok(ByteSize(bytes as u64))
+    }
+}
+
+/// A false-positive rate for `--bloom-fp-rate`
+///
+/// A fraction (`0.01`) or a percentage (`1%`), above 0 and at most 50%.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FalsePositiveRate(pub f64);
+
+impl std::str::FromStr for FalsePositiveRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (amount, scale) = match s.strip_suffix('%') {
+            Some(percent) => (percent.trim(), 100.0),
+            None => (s, 1.0),
+        };
+        let rate = amount
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid rate: {} (expected e.g. 0.01 or 1%)", s))?
+            / scale;
+        if !(rate > 0.0 && rate <= 0.5) {
+            return Err(format!("Rate out of range: {} (expected above 0 and at most 50%)", s));
+        }
+        Ok(FalsePositiveRate(rate))
+    }
+}
+
+/// Largest `--heatmap-hot-hours`/`--heatmap-warm-hours` accepted, a little over a century
+pub const MAX_HEATMAP_HOURS: u64 = 1_000_000;
+
+/// Exit code contract shown at the end of `--help` (values from `exit_code`)
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  other error
+  2  invalid arguments
+  3  drive or scan root not found
+  4  cache could not be located, read, or written
+  5  output produced, but some directories could not be read (permission denied)
+  6  ptree verify: cache staleness above --threshold";
+
 /// ptree - A cache-first disk tree traversal tool for Windows
 ///
 /// Scans disk directories with multi-threaded parallelism and caches results
@@ -54,14 +367,31 @@ impl std::str::FromStr for ColorMode {
 #[derive(Parser, Debug)]
 #[command(name = "ptree")]
 #[command(about = "Fast disk tree visualization with incremental caching")]
+#[command(after_help = EXIT_CODES_HELP)]
 pub struct Args {
+    /// Work with the cached tree instead of printing it
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // ========================================================================
+    // Configuration
+    // ========================================================================
+
+    /// Read default options from this TOML file (default: ./ptree.toml, then %APPDATA%\ptree\config.toml)
+    #[arg(long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
     // ========================================================================
     // Drive & Scanning Options
     // ========================================================================
 
-    /// Drive letter (e.g., C, D)
-    #[arg(short, long, default_value = "C")]
-    pub drive: char,
+    /// Directory to scan, e.g. D:\Projects, \\server\share or /home (default: current directory)
+    #[arg(value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// Drive letter(s) to scan with --force and no PATH; repeat or comma-separate (e.g., C,D,E)
+    #[arg(short, long, default_value = "C", value_delimiter = ',')]
+    pub drive: Vec<char>,
 
     /// Enable admin mode to scan system directories
     #[arg(short, long)]
@@ -71,6 +401,10 @@ pub struct Args {
     #[arg(short, long)]
     pub force: bool,
 
+    /// List every directory again on a rescan instead of reusing unchanged ones from the cache
+    #[arg(long)]
+    pub no_hash_skip: bool,
+
     // ========================================================================
     // Cache Options
     // ========================================================================
@@ -79,42 +413,194 @@ pub struct Args {
     #[arg(long)]
     pub cache_ttl: Option<u64>,
 
-    /// Override cache directory location
-    #[arg(long)]
+    /// Override cache directory location (default: $PTREE_CACHE_DIR, then %APPDATA%\ptree\cache, ~/Library/Caches/ptree, or $XDG_CACHE_HOME/ptree)
+    #[arg(long, global = true)]
     pub cache_dir: Option<String>,
 
-    /// Disable cache entirely (scan fresh every time)
-    #[arg(long)]
+    /// Cache location as a directory or a file path (overrides --cache-dir and PTREE_CACHE_DIR)
+    #[arg(long, value_name = "DIR_OR_FILE", conflicts_with = "cache_dir", global = true)]
+    pub cache_path: Option<PathBuf>,
+
+    /// Use the cache file <NAME>.dat in the cache directory, to keep separate caches per scan root
+    #[arg(long, value_name = "NAME", conflicts_with = "cache_path", global = true)]
+    pub cache_name: Option<String>,
+
+    /// Cache storage: rkyv (memory-mapped files), sqlite (a .db file queryable with SQL), kv (an embedded key-value store, experimental), or limcode (archives read in place, experimental); default: whichever the cache was saved with
+    #[arg(long, value_name = "BACKEND", global = true)]
+    pub backend: Option<CacheBackend>,
+
+    /// Disable cache entirely: scan fresh, never read or write cache files
+    #[arg(long, conflicts_with = "read_only")]
     pub no_cache: bool,
 
+    /// Use the existing cache as-is and never write it back
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Fail instead of rescanning when the saved cache can't be loaded (corrupt, encrypted without its key, or unreadable), leaving its files in place
+    #[arg(long, conflicts_with = "no_cache")]
+    pub strict_cache: bool,
+
+    /// Check every cache record against its checksum before use (a damaged cache is moved aside and rebuilt), then report entries that disagree with their parents' children lists
+    #[arg(long, conflicts_with = "no_cache")]
+    pub verify_cache: bool,
+
+    /// Store the cache data file as zstd-compressed blocks (smaller, slower lookups); kept until the cache is cleared
+    #[arg(long, conflicts_with = "no_cache")]
+    pub compress_cache: bool,
+
+    /// Encrypt the cache files (DPAPI key on Windows, PTREE_CACHE_PASSPHRASE elsewhere); kept until the cache is cleared
+    #[arg(long, conflicts_with = "no_cache")]
+    pub encrypt_cache: bool,
+
+    /// Keep the saved cache under this size (e.g. 64M) by evicting the least recently modified subtrees
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["no_cache", "read_only"])]
+    pub max_cache_bytes: Option<ByteSize>,
+
+    /// False-positive rate of the path filter saved with the cache (e.g. 0.01 or 0.1%); lower rates rule out more uncached paths but take more space; default: the cache's own, or 1%
+    #[arg(long, value_name = "RATE", conflicts_with_all = ["no_cache", "read_only"])]
+    pub bloom_fp_rate: Option<FalsePositiveRate>,
+
+    /// Save the child names of directories with more than N children in a record of their own, shown collapsed unless --filelimit is given (0 = never); default: the cache's own, or 10000
+    #[arg(long, value_name = "N", conflicts_with_all = ["no_cache", "read_only"])]
+    pub spill_children: Option<usize>,
+
     // ========================================================================
     // Output & Display Options
     // ========================================================================
 
-    /// Suppress tree output (useful when just updating cache)
-    #[arg(short, long)]
+    /// Suppress tree output, progress logs and warnings (useful when just updating cache)
+    #[arg(short, long, global = true)]
     pub quiet: bool,
 
-    /// Output format: tree or json
+    /// Don't draw the progress line on stderr during scans
+    #[arg(long)]
+    pub no_progress: bool,
+
+    /// Log progress to stderr: -v for phases and periodic counts, -vv for debug detail
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet", global = true)]
+    pub verbose: u8,
+
+    /// Output format: tree, json, csv, ndjson, dot (directories only, 4 levels unless --max-depth is given), html, or markdown
     #[arg(long, default_value = "tree")]
     pub format: OutputFormat,
 
+    /// Child ordering: name, mtime (newest first), size (largest subtree first), or children
+    #[arg(long, default_value = "name", global = true)]
+    pub sort: SortKey,
+
+    /// Sort names naturally (file2 before file10, case-insensitive)
+    #[arg(long, global = true)]
+    pub natural_sort: bool,
+
+    /// Reverse the sort order
+    #[arg(short, long, global = true)]
+    pub reverse: bool,
+
+    /// Write output to this file instead of stdout (disables color unless --color always)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Collapse directories with more than N children into a placeholder (0 = no limit)
+    #[arg(long = "filelimit", value_name = "N")]
+    pub file_limit: Option<usize>,
+
+    /// Stop after N output lines (JSON: N nodes) and note the truncation (0 = no limit)
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Align --du/--counts/--mtime annotations in a column, N lines at a time (0 = don't align)
+    #[arg(long, value_name = "N", default_value_t = 4096)]
+    pub align_window: usize,
+
+    /// Per-line template, e.g. "{name:<30} {children:>6} {mtime}" (placeholders: name, path, size, mtime, depth, children)
+    #[arg(long, value_name = "TEMPLATE")]
+    pub entry_format: Option<String>,
+
+    /// Print each entry's full path instead of its name
+    #[arg(long)]
+    pub full_path: bool,
+
+    /// Print one bare name (or path) per line, without indentation or connectors
+    #[arg(short = 'i', long)]
+    pub no_indent: bool,
+
+    /// Print each path absolute and NUL-terminated, for xargs -0 or fzf --read0
+    #[arg(long, conflicts_with = "format")]
+    pub print0: bool,
+
+    /// Mark entries added (+) or modified (*) since the previous scan and list removed ones
+    #[arg(long)]
+    pub changes: bool,
+
+    /// Color directories by how recently they changed (colored tree output only)
+    #[arg(long)]
+    pub heatmap: bool,
+
+    /// Heatmap: directories changed within this many hours are "hot" (green)
+    #[arg(long, value_name = "HOURS", default_value_t = 24, value_parser = clap::value_parser!(u64).range(..=MAX_HEATMAP_HOURS))]
+    pub heatmap_hot_hours: u64,
+
+    /// Heatmap: directories changed within this many hours are "warm" (yellow)
+    #[arg(long, value_name = "HOURS", default_value_t = 168, value_parser = clap::value_parser!(u64).range(..=MAX_HEATMAP_HOURS))]
+    pub heatmap_warm_hours: u64,
+
+    /// Tree connector characters: utf8 or ascii (|-- and `--)
+    #[arg(long, default_value = "utf8")]
+    pub charset: Charset,
+
     /// Color output: auto, always, never
-    #[arg(long, default_value = "auto")]
+    #[arg(long, default_value = "auto", global = true)]
     pub color: ColorMode,
 
-    /// Include directory sizes in output
+    /// Append each file's size, e.g. "notes.txt [1.5 KiB]" (see --du for directory totals)
     #[arg(long)]
     pub size: bool,
 
+    /// Append each directory's immediate child count, e.g. "node_modules [1,482]"
+    #[arg(long)]
+    pub counts: bool,
+
+    /// Append each directory's total size, everything below it included; "≥" marks lower bounds
+    #[arg(long)]
+    pub du: bool,
+
     /// Include file count per directory
     #[arg(long)]
     pub file_count: bool,
 
+    /// Omit the "N directories, M files" summary footer
+    #[arg(long)]
+    pub no_report: bool,
+
+    /// Include is_hidden and symlink_target on every JSON node
+    #[arg(long)]
+    pub json_metadata: bool,
+
+    /// Render symlink targets in markdown output (name → target)
+    #[arg(long)]
+    pub link_targets: bool,
+
+    /// Check each symlink's target and mark links to missing targets [broken]
+    #[arg(long)]
+    pub check_symlinks: bool,
+
+    /// Show each entry's last modification time
+    #[arg(long)]
+    pub mtime: bool,
+
+    /// Timestamp format for --mtime: rfc3339 or relative (e.g. "3d ago")
+    #[arg(long, default_value = "rfc3339")]
+    pub mtime_format: MtimeFormat,
+
     // ========================================================================
     // Filtering & Traversal Options
     // ========================================================================
 
+    /// Only display this cached directory and its descendants
+    #[arg(long, value_name = "PATH")]
+    pub subtree: Option<PathBuf>,
+
     /// Maximum depth to display
     #[arg(short, long)]
     pub max_depth: Option<usize>,
@@ -124,17 +610,45 @@ pub struct Args {
     pub skip: Option<String>,
 
     /// Show hidden files
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub hidden: bool,
 
+    /// Exclude hidden directories (and everything below them) from output
+    #[arg(long, conflicts_with = "hidden", global = true)]
+    pub no_hidden: bool,
+
+    /// Only show names matching this glob, plus their ancestors (repeatable)
+    #[arg(long = "match", value_name = "GLOB")]
+    pub match_patterns: Vec<String>,
+
+    /// Hide names matching this glob, along with their subtrees (repeatable)
+    #[arg(long = "ignore", value_name = "GLOB")]
+    pub ignore_patterns: Vec<String>,
+
+    /// Omit directories with nothing to show once the other filters are applied
+    #[arg(long)]
+    pub prune: bool,
+
+    /// Only show entries modified after this age or date (e.g. 7d, 2024-01-01), plus their ancestors
+    #[arg(long, value_name = "AGE|DATE")]
+    pub newer_than: Option<TimeBound>,
+
+    /// Only show entries modified before this age or date (e.g. 1y, 2024-01-01), plus their ancestors
+    #[arg(long, value_name = "AGE|DATE")]
+    pub older_than: Option<TimeBound>,
+
     // ========================================================================
     // Performance Options
     // ========================================================================
 
-    /// Maximum threads (default: physical cores * 2, capped at 3x cores)
+    /// Worker threads (default: by drive media, cores * 2 for SSDs, 2-4 for HDDs, 4 for network drives)
     #[arg(short = 'j', long)]
     pub threads: Option<usize>,
 
+    /// Don't descend into directories on other volumes (mount points are shown as [mounted])
+    #[arg(long)]
+    pub one_file_system: bool,
+
     /// Enable incremental updates via USN Journal (Windows only)
     #[arg(long)]
     pub incremental: bool,
@@ -168,11 +682,206 @@ pub struct Args {
      pub scheduler_status: bool,
     }
     
-    pub fn parse_args() -> Args {
-     Args::parse()
+    // ============================================================================
+// Subcommands
+// ============================================================================
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Explore the cached tree in a terminal UI; Enter prints the selected path
+    ///
+    /// Reads only the cache (run a scan first). Keys: arrows or hjkl move and
+    /// expand/collapse, / filters by name, Enter selects, q or Esc quits.
+    Browse {
+        /// Directory to open (default: current directory if cached, else the cache root)
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+
+    /// Find cached paths by glob or substring, without rescanning
+    ///
+    /// A pattern without a separator matches entry names; one with a
+    /// separator (e.g. src/**/test*) matches the end of the full path.
+    Search {
+        /// Glob or substring to look for
+        pattern: String,
+
+        /// How to read PATTERN: auto, glob, or substring
+        #[arg(long, default_value = "auto")]
+        mode: SearchMode,
+
+        /// Print at most N matches (0 = no limit)
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Only search below this directory
+        #[arg(long, value_name = "PATH")]
+        subtree: Option<PathBuf>,
+
+        /// Print matches as a JSON document
+        #[arg(long)]
+        json: bool,
+
+        /// Rescan before searching, even if the cache is fresh
+        #[arg(long)]
+        refresh: bool,
+    },
+
+    /// Report the largest and busiest directories in the cache
+    Stats {
+        /// Entries to list in each ranking
+        #[arg(short = 'n', long, value_name = "N", default_value_t = 10)]
+        top: usize,
+
+        /// Only analyze this directory and what is below it
+        #[arg(long, value_name = "PATH")]
+        subtree: Option<PathBuf>,
+
+        /// Print the report as a JSON document
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Describe the cache files (sizes, entries, last scan, dead space) without loading entries
+    CacheInfo {
+        /// Print the description as a JSON document
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Convert a cache written by an older ptree (one monolithic ptree.dat) to the current files
+    ///
+    /// Runs happen to do this on their own when they open the cache; the
+    /// old file is kept beside the new ones with `.bak` appended.
+    MigrateCache,
+
+    /// Delete the cache files so the next run starts from a full scan
+    Clean {
+        /// Only drop this drive's entries, keeping the rest of the cache
+        #[arg(short, long, value_name = "LETTER")]
+        drive: Option<char>,
+
+        /// Keep the cache, rewriting its data file without stale records
+        #[arg(long, conflicts_with = "drive")]
+        compact: bool,
+
+        /// Keep the cache, dropping entries no longer reachable from a scanned root
+        #[arg(long, conflicts_with_all = ["drive", "compact"])]
+        gc: bool,
+    },
+
+    /// Spot-check cached entries against the disk (never modifies the cache)
+    Verify {
+        /// Number of random entries to check
+        #[arg(short = 'n', long, value_name = "N", default_value_t = 1000, conflicts_with = "all")]
+        sample: usize,
+
+        /// Check every cached entry instead of a sample
+        #[arg(long)]
+        all: bool,
+
+        /// Exit with code 6 when more than this percentage of checked entries is stale
+        #[arg(long, value_name = "PERCENT", default_value_t = 5.0)]
+        threshold: f64,
+
+        /// Seed for the random sample, to make runs reproducible
+        #[arg(long, value_name = "N")]
+        seed: Option<u64>,
+
+        /// Print the report as a JSON document
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Write the whole cache to one portable archive file
+    Export {
+        /// Archive to create
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Merge an exported archive into the local cache
+    ///
+    /// Entries newer than the cached ones replace them; --replace makes the
+    /// archive win every conflict.
+    Import {
+        /// Archive written by `ptree export`
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Overwrite cached entries even when they are newer
+        #[arg(long)]
+        replace: bool,
+    },
+
+    /// Compare two exported archives, or the cache with a fresh scan
+    ///
+    /// Lists added (+), removed (-) and modified (~) directories in path
+    /// order; everything below an added or removed directory is folded into
+    /// its line.
+    Diff {
+        /// Archive with the earlier state
+        #[arg(value_name = "OLD", required_unless_present = "live", requires = "new")]
+        old: Option<PathBuf>,
+
+        /// Archive with the later state
+        #[arg(value_name = "NEW")]
+        new: Option<PathBuf>,
+
+        /// Compare the current cache with a fresh scan (and save the scan)
+        #[arg(long, conflicts_with_all = ["old", "new"])]
+        live: bool,
+
+        /// Only compare directories below this path
+        #[arg(long, value_name = "PATH")]
+        subtree: Option<PathBuf>,
+
+        /// Print one JSON object per change (NDJSON), then the totals
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print directory changes from the NTFS USN journal as they happen (Windows only)
+    ///
+    /// Runs in the foreground until Ctrl+C, then saves the journal position
+    /// in the cache so the next watch resumes where this one stopped.
+    Watch {
+        /// Drive to watch
+        #[arg(short, long, value_name = "LETTER", default_value = "C")]
+        drive: char,
+
+        /// Seconds between journal reads
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        interval: u64,
+
+        /// Print one JSON object per change (NDJSON)
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Parse the command line, filling unset options from the config file
+    pub fn parse_args() -> PTreeResult<Args> {
+     let argv: Vec<OsString> = std::env::args_os().collect();
+     // Command-line errors and --help are reported by clap as usual
+     let cli = Args::parse_from(&argv);
+     match config::find_config(cli.config.as_deref())? {
+         Some(path) => config::parse_with_config(&argv, &config::load_config(&path)?, &path),
+         None => Ok(cli),
+     }
     }
 
 impl Args {
+    /// Drive letters in the order given, without case-insensitive duplicates
+    pub fn drives(&self) -> Vec<char> {
+        let mut seen = HashSet::new();
+        self.drive
+            .iter()
+            .copied()
+            .filter(|drive| seen.insert(drive.to_ascii_uppercase()))
+            .collect()
+    }
+
     /// Build skip directory set based on arguments
     pub fn skip_dirs(&self) -> HashSet<String> {
         let mut skip = Self::default_skip_dirs();
@@ -206,3 +915,100 @@ impl Args {
         .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_with<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn test_resolve_color_explicit_modes_ignore_env() {
+        let everything = [("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")];
+        for is_tty in [true, false] {
+            assert!(resolve_color(ColorMode::Always, env_with(&everything), is_tty));
+            assert!(resolve_color(ColorMode::Always, env_with(&[]), is_tty));
+            assert!(!resolve_color(ColorMode::Never, env_with(&everything), is_tty));
+            assert!(!resolve_color(ColorMode::Never, env_with(&[]), is_tty));
+        }
+    }
+
+    #[test]
+    fn test_resolve_color_auto_follows_terminal() {
+        assert!(resolve_color(ColorMode::Auto, env_with(&[]), true));
+        assert!(!resolve_color(ColorMode::Auto, env_with(&[]), false));
+    }
+
+    #[test]
+    fn test_time_bound_parses_ages_and_dates() {
+        let day = chrono::Duration::days(1);
+        assert_eq!("30d".parse(), Ok(TimeBound::Ago(day * 30)));
+        assert_eq!("2W".parse(), Ok(TimeBound::Ago(day * 14)));
+        assert_eq!("1y".parse(), Ok(TimeBound::Ago(day * 365)));
+        assert_eq!("12h".parse(), Ok(TimeBound::Ago(chrono::Duration::hours(12))));
+        assert_eq!("90s".parse(), Ok(TimeBound::Ago(chrono::Duration::seconds(90))));
+
+        let new_year = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        assert_eq!("2024-01-01".parse(), Ok(TimeBound::At(new_year)));
+        assert_eq!("2024-01-01T02:00:00+02:00".parse(), Ok(TimeBound::At(new_year)));
+        assert_eq!(TimeBound::Ago(day).resolve(new_year), new_year - day);
+        assert_eq!(TimeBound::At(new_year).resolve(chrono::Utc::now()), new_year);
+
+        for bad in ["", "d", "30", "30x", "-5d", "2024-13-01", "99999999999999999y"] {
+            assert!(bad.parse::<TimeBound>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_byte_size_parses_units() {
+        assert_eq!("500000".parse(), Ok(ByteSize(500_000)));
+        assert_eq!("64M".parse(), Ok(ByteSize(64 << 20)));
+        assert_eq!("512kb".parse(), Ok(ByteSize(512 << 10)));
+        assert_eq!("1.5GiB".parse(), Ok(ByteSize(3 << 29)));
+        assert_eq!("2 T".parse(), Ok(ByteSize(2 << 40)));
+
+        for bad in ["", "M", "64X", "-1M", "1e30T"] {
+            assert!(bad.parse::<ByteSize>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_false_positive_rate_parses_fractions_and_percentages() {
+        assert_eq!("0.01".parse(), Ok(FalsePositiveRate(0.01)));
+        assert_eq!("0.5".parse(), Ok(FalsePositiveRate(0.5)));
+        assert_eq!("2%".parse(), Ok(FalsePositiveRate(0.02)));
+
+        for bad in ["", "0", "-0.1", "0.6", "60%", "NaN", "1%%"] {
+            assert!(bad.parse::<FalsePositiveRate>().is_err(), "{}", bad);
+        }
+    }
+
+    type Vars<'a> = &'a [(&'a str, &'a str)];
+
+    #[test]
+    fn test_resolve_color_auto_env_overrides() {
+        let cases: &[(Vars, bool, bool)] = &[
+            // NO_COLOR disables, even over CLICOLOR_FORCE
+            (&[("NO_COLOR", "1")], true, false),
+            (&[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")], false, false),
+            // An empty NO_COLOR is treated as unset
+            (&[("NO_COLOR", "")], true, true),
+            // CLICOLOR_FORCE enables when piped, unless "0" or empty
+            (&[("CLICOLOR_FORCE", "1")], false, true),
+            (&[("CLICOLOR_FORCE", "0")], false, false),
+            (&[("CLICOLOR_FORCE", "")], false, false),
+            (&[("CLICOLOR_FORCE", "0")], true, true),
+        ];
+        for (vars, is_tty, expected) in cases {
+            assert_eq!(
+                resolve_color(ColorMode::Auto, env_with(vars), *is_tty),
+                *expected,
+                "{:?} tty={}",
+                vars,
+                is_tty
+            );
+        }
+    }
+}
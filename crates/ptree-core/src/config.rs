@@ -0,0 +1,228 @@
+use crate::cli::Args;
+use crate::error::{PTreeError, PTreeResult};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, Command, CommandFactory, FromArgMatches};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Config File Defaults (ptree.toml)
+// ============================================================================
+
+/// Config file looked up in the current directory
+pub const LOCAL_CONFIG: &str = "ptree.toml";
+
+/// Locate the config file to use, if any
+///
+/// An explicit `--config` path must exist. Otherwise `./ptree.toml` is used,
+/// then `%APPDATA%\ptree\config.toml`; the first one found wins.
+pub fn find_config(explicit: Option<&Path>) -> PTreeResult<Option<PathBuf>> {
+    if let Some(path) = explicit {
+        if !path.is_file() {
+            return Err(config_error(path, "file not found"));
+        }
+        return Ok(Some(path.to_path_buf()));
+    }
+
+    let local = PathBuf::from(LOCAL_CONFIG);
+    if local.is_file() {
+        return Ok(Some(local));
+    }
+
+    if let Ok(appdata) = std::env::var("APPDATA") {
+        let global = PathBuf::from(appdata).join("ptree").join("config.toml");
+        if global.is_file() {
+            return Ok(Some(global));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read and parse a config file into its top-level table
+pub fn load_config(path: &Path) -> PTreeResult<toml::Table> {
+    let text = fs::read_to_string(path).map_err(|e| config_error(path, &e.to_string()))?;
+    text.parse::<toml::Table>()
+        .map_err(|e| config_error(path, e.to_string().trim_end()))
+}
+
+/// Parse `argv`, taking every option it leaves unset from `config`
+///
+/// Keys are long flag names (`max-depth`, `filelimit`, `match`) or field
+/// names (`max_depth`); `path` sets the positional scan root. Values given on
+/// the command line always win over the file. Booleans turn flags on (`false`
+/// leaves them off), arrays repeat multi-value options and are comma-joined
+/// for single-value ones such as `skip`.
+pub fn parse_with_config(argv: &[OsString], config: &toml::Table, source: &Path) -> PTreeResult<Args> {
+    let command = Args::command();
+    let cli_matches = command
+        .clone()
+        .try_get_matches_from(argv)
+        .map_err(|e| PTreeError::InvalidArgument(clap_message(&e)))?;
+
+    let program = argv.first().cloned().unwrap_or_else(|| OsString::from("ptree"));
+    let mut merged = vec![program.clone()];
+
+    for (key, value) in config {
+        let arg = find_arg(&command, key).ok_or_else(|| key_error(source, key, "unknown option"))?;
+        if cli_matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let fragment = config_fragment(arg, value).map_err(|message| key_error(source, key, &message))?;
+
+        // Validate each key on its own so errors name the key that caused them
+        let alone = std::iter::once(program.clone()).chain(fragment.iter().cloned());
+        command
+            .clone()
+            .try_get_matches_from(alone)
+            .map_err(|e| key_error(source, key, &clap_message(&e)))?;
+
+        merged.extend(fragment);
+    }
+    merged.extend(argv.iter().skip(1).cloned());
+
+    let matches = command
+        .try_get_matches_from(&merged)
+        .map_err(|e| config_error(source, &clap_message(&e)))?;
+    Args::from_arg_matches(&matches).map_err(|e| config_error(source, &clap_message(&e)))
+}
+
+/// Argument a config key refers to, by long name or field name
+fn find_arg<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    let key = key.replace('_', "-");
+    command
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_id().as_str(), "config" | "help"))
+        .find(|arg| arg.get_long() == Some(key.as_str()) || arg.get_id().as_str().replace('_', "-") == key)
+}
+
+/// Command-line tokens equivalent to one config value
+fn config_fragment(arg: &Arg, value: &toml::Value) -> Result<Vec<OsString>, String> {
+    let flag = arg.get_long().map(|long| format!("--{}", long));
+    let token = |text: String| -> OsString {
+        match &flag {
+            // `--flag=value` keeps values that start with '-' from being read as flags
+            Some(flag) => format!("{}={}", flag, text).into(),
+            None => text.into(),
+        }
+    };
+
+    match arg.get_action() {
+        ArgAction::SetTrue => match value {
+            toml::Value::Boolean(true) => Ok(flag.into_iter().map(OsString::from).collect()),
+            toml::Value::Boolean(false) => Ok(Vec::new()),
+            _ => Err("expected true or false".to_string()),
+        },
+        ArgAction::Count => match value {
+            toml::Value::Integer(n) if *n >= 0 => {
+                Ok(flag.iter().cycle().take(*n as usize).map(OsString::from).collect())
+            }
+            _ => Err("expected a non-negative integer".to_string()),
+        },
+        ArgAction::Append => match value {
+            toml::Value::Array(items) => items.iter().map(|item| scalar(item).map(&token)).collect(),
+            other => Ok(vec![token(scalar(other)?)]),
+        },
+        _ => match value {
+            toml::Value::Array(items) => {
+                let joined: Result<Vec<_>, _> = items.iter().map(scalar).collect();
+                Ok(vec![token(joined?.join(","))])
+            }
+            other => Ok(vec![token(scalar(other)?)]),
+        },
+    }
+}
+
+fn scalar(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(text) => Ok(text.clone()),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(n) => Ok(n.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err("expected a string, number, boolean, or array of those".to_string()),
+    }
+}
+
+/// First line of a clap error, without its "error: " prefix
+fn clap_message(err: &clap::Error) -> String {
+    let text = err.to_string();
+    let first = text.lines().next().unwrap_or_default();
+    first.strip_prefix("error: ").unwrap_or(first).to_string()
+}
+
+fn key_error(source: &Path, key: &str, message: &str) -> PTreeError {
+    PTreeError::Config(format!("{}: key '{}': {}", source.display(), key, message))
+}
+
+fn config_error(source: &Path, message: &str) -> PTreeError {
+    PTreeError::Config(format!("{}: {}", source.display(), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::OutputFormat;
+
+    fn parse(cli: &[&str], config: &str) -> PTreeResult<Args> {
+        let argv: Vec<OsString> = std::iter::once("ptree").chain(cli.iter().copied()).map(OsString::from).collect();
+        let table: toml::Table = config.parse().unwrap();
+        parse_with_config(&argv, &table, Path::new("ptree.toml"))
+    }
+
+    #[test]
+    fn test_command_line_overrides_config_overrides_defaults() -> PTreeResult<()> {
+        let config = "threads = 4\nmax-depth = 3\nformat = \"json\"\nhidden = true";
+
+        let args = parse(&[], config)?;
+        assert_eq!(args.threads, Some(4));
+        assert_eq!(args.max_depth, Some(3));
+        assert!(matches!(args.format, OutputFormat::Json));
+        assert!(args.hidden);
+        assert_eq!(args.cache_ttl, None, "keys absent from the file keep their defaults");
+
+        let args = parse(&["-j", "8", "--format", "csv"], config)?;
+        assert_eq!(args.threads, Some(8));
+        assert!(matches!(args.format, OutputFormat::Csv));
+        assert_eq!(args.max_depth, Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_spellings_arrays_and_booleans() -> PTreeResult<()> {
+        let config = "max_depth = 2\nfilelimit = 50\nskip = [\"node_modules\", \"target\"]\n\
+                      match = [\"*.rs\", \"*.toml\"]\nverbose = 2\nreverse = false";
+        let args = parse(&[], config)?;
+
+        assert_eq!(args.max_depth, Some(2));
+        assert_eq!(args.file_limit, Some(50));
+        assert_eq!(args.skip.as_deref(), Some("node_modules,target"));
+        assert_eq!(args.match_patterns, vec!["*.rs", "*.toml"]);
+        assert_eq!(args.verbose, 2);
+        assert!(!args.reverse);
+
+        // A repeatable option given on the command line replaces the file's list
+        let args = parse(&["--match", "*.md"], config)?;
+        assert_eq!(args.match_patterns, vec!["*.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_name_the_offending_key() {
+        for (config, key) in [
+            ("thread = 4", "thread"),
+            ("threads = \"many\"", "threads"),
+            ("hidden = \"yes\"", "hidden"),
+            ("format = \"xml\"", "format"),
+            ("sort = { key = \"name\" }", "sort"),
+        ] {
+            match parse(&[], config) {
+                Err(PTreeError::Config(message)) => {
+                    assert!(message.contains(&format!("key '{}'", key)), "{}", message)
+                }
+                other => panic!("{} should fail, got {:?}", config, other.map(|_| ())),
+            }
+        }
+    }
+}
@@ -1,5 +1,29 @@
 use thiserror::Error;
 use std::io;
+use std::path::PathBuf;
+
+// ============================================================================
+// Exit Codes
+// ============================================================================
+
+/// Process exit codes, one per failure class (listed in `--help`)
+pub mod exit_code {
+    pub const SUCCESS: u8 = 0;
+    /// Any failure without a more specific code
+    pub const FAILURE: u8 = 1;
+    /// Invalid arguments (clap uses this code for its own usage errors too)
+    pub const USAGE: u8 = 2;
+    /// The drive or scan root does not exist
+    pub const NOT_FOUND: u8 = 3;
+    /// The cache could not be located, read, or written
+    pub const CACHE: u8 = 4;
+    /// Output was produced, but some directories could not be read
+    pub const ACCESS_DENIED: u8 = 5;
+    /// `ptree verify` found more stale entries than its threshold allows
+    pub const STALE: u8 = 6;
+    /// Stopped by Ctrl+C (128 + SIGINT, as shells report it)
+    pub const INTERRUPTED: u8 = 130;
+}
 
 #[derive(Error, Debug)]
 pub enum PTreeError {
@@ -8,18 +32,73 @@ pub enum PTreeError {
     
     #[error("Cache error: {0}")]
     Cache(String),
+
+    #[error("Cache format version {found} is newer than this ptree supports ({supported}); upgrade ptree or clear the cache")]
+    CacheVersion { found: u32, supported: u32 },
+
+    #[error("Corrupt cache: {0}")]
+    CacheCorrupt(String),
+
+    #[error("Cannot decrypt cache: {0}")]
+    CacheKey(String),
+
+    #[error("Cannot locate the cache: {missing} is not set; set PTREE_CACHE_DIR or pass --cache-dir")]
+    CacheLocation { missing: &'static str },
+
+    #[error("Cannot use cache path {}", .path.display())]
+    CachePath { path: PathBuf, source: io::Error },
     
     #[error("Serialization error: {0}")]
     Serialization(#[from] bincode::Error),
     
     #[error("Invalid drive: {0}")]
     InvalidDrive(String),
+
+    #[error("Scan root not found: {0}")]
+    RootNotFound(String),
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
     
     #[error("Lock timeout: {0}")]
     LockTimeout(String),
     
     #[error("Traversal error: {0}")]
     Traversal(String),
+
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+    
+    #[error("Output error: {0}")]
+    Output(String),
+    
+    #[error("Invalid entry format: {0}")]
+    Format(String),
+
+    #[error("Config error: {0}")]
+    Config(String),
+}
+
+impl PTreeError {
+    /// Exit code for this failure class (see `exit_code`)
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            PTreeError::InvalidArgument(_) | PTreeError::Format(_) | PTreeError::Config(_) => {
+                exit_code::USAGE
+            }
+            PTreeError::InvalidDrive(_) | PTreeError::RootNotFound(_) => exit_code::NOT_FOUND,
+            PTreeError::Cache(_)
+            | PTreeError::CacheVersion { .. }
+            | PTreeError::CacheCorrupt(_)
+            | PTreeError::CacheKey(_)
+            | PTreeError::CacheLocation { .. }
+            | PTreeError::CachePath { .. }
+            | PTreeError::Serialization(_)
+            | PTreeError::LockTimeout(_) => exit_code::CACHE,
+            PTreeError::AccessDenied(_) => exit_code::ACCESS_DENIED,
+            PTreeError::Io(_) | PTreeError::Traversal(_) | PTreeError::Output(_) => exit_code::FAILURE,
+        }
+    }
 }
 
 pub type PTreeResult<T> = Result<T, PTreeError>;
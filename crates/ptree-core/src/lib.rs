@@ -1,5 +1,8 @@
 pub mod cli;
+pub mod config;
 pub mod error;
+pub mod output;
 
-pub use cli::{Args, ColorMode, OutputFormat, parse_args};
-pub use error::{PTreeError, PTreeResult};
+pub use cli::{Args, ByteSize, CacheBackend, Charset, ColorMode, Command, FalsePositiveRate, MtimeFormat, OutputFormat, SearchMode, SortKey, TimeBound, parse_args, resolve_color};
+pub use error::{exit_code, PTreeError, PTreeResult};
+pub use output::OutputSink;
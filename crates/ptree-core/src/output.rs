@@ -0,0 +1,158 @@
+use crate::error::{PTreeError, PTreeResult};
+use std::fs;
+use std::io::{self, BufWriter, Stdout, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Destination for formatted output: stdout, or a file written atomically
+///
+/// File output goes to a uniquely named sibling temp file first and is
+/// renamed into place by `finish()`, so an interrupted run never leaves a
+/// half-written result behind and concurrent runs never share a temp file.
+/// A sink dropped without `finish()` removes its temp file.
+pub enum OutputSink {
+    Stdout(BufWriter<Stdout>),
+    File {
+        writer: BufWriter<NamedTempFile>,
+        final_path: PathBuf,
+    },
+}
+
+impl OutputSink {
+    /// Open a sink for the given destination (`None` means stdout)
+    pub fn open(path: Option<&Path>) -> PTreeResult<Self> {
+        let Some(path) = path else {
+            return Ok(OutputSink::Stdout(BufWriter::new(io::stdout())));
+        };
+
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::create_dir_all(parent).map_err(|e| output_error(parent, "create directory", e))?;
+
+        let file = temp_file_for(path, parent).map_err(|e| output_error(path, "create", e))?;
+
+        Ok(OutputSink::File {
+            writer: BufWriter::new(file),
+            final_path: path.to_path_buf(),
+        })
+    }
+
+    /// True when writing to a file rather than the terminal
+    pub fn is_file(&self) -> bool {
+        matches!(self, OutputSink::File { .. })
+    }
+
+    /// Flush buffered output and, for files, move the temp file into place
+    pub fn finish(self) -> PTreeResult<()> {
+        match self {
+            OutputSink::Stdout(mut writer) => {
+                writer.flush()?;
+                Ok(())
+            }
+            OutputSink::File { writer, final_path } => {
+                // On failure the temp file is dropped, which removes it
+                let result = writer
+                    .into_inner()
+                    .map_err(|e| e.into_error())
+                    .and_then(|file| file.as_file().sync_all().map(|_| file))
+                    .and_then(|file| file.persist(&final_path).map_err(|e| e.error));
+
+                result.map(|_| ()).map_err(|e| output_error(&final_path, "write", e))
+            }
+        }
+    }
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputSink::Stdout(writer) => writer.write(buf),
+            OutputSink::File { writer, .. } => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputSink::Stdout(writer) => writer.flush(),
+            OutputSink::File { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+/// Hidden temp file in `parent`, e.g. `out/tree.json` -> `out/.tree.json.a1B2c3.tmp`
+fn temp_file_for(path: &Path, parent: &Path) -> io::Result<NamedTempFile> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "ptree-output".to_string());
+    tempfile::Builder::new()
+        .prefix(&format!(".{}.", name))
+        .suffix(".tmp")
+        .tempfile_in(parent)
+}
+
+fn output_error(path: &Path, action: &str, err: io::Error) -> PTreeError {
+    PTreeError::Output(format!("failed to {} {}: {}", action, path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_file_sink_creates_parents_and_renames() -> PTreeResult<()> {
+        let dir = env::temp_dir().join(format!("ptree_output_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let target = dir.join("nested").join("tree.txt");
+
+        let mut sink = OutputSink::open(Some(&target))?;
+        assert!(sink.is_file());
+        writeln!(sink, "C:\\")?;
+        assert!(!target.exists(), "final file must not appear before finish()");
+        sink.finish()?;
+
+        assert_eq!(fs::read_to_string(&target)?, "C:\\\n");
+        assert_eq!(fs::read_dir(target.parent().unwrap())?.count(), 1, "temp file must be gone");
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_sinks_use_separate_temp_files() -> PTreeResult<()> {
+        let dir = env::temp_dir().join(format!("ptree_output_concurrent_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let target = dir.join("tree.txt");
+
+        let mut first = OutputSink::open(Some(&target))?;
+        let mut second = OutputSink::open(Some(&target))?;
+        write!(first, "first")?;
+        write!(second, "second")?;
+        assert_eq!(fs::read_dir(&dir)?.count(), 2);
+        second.finish()?;
+        assert_eq!(fs::read_to_string(&target)?, "second");
+
+        // Abandoned without finish(): the temp file goes, the output stays
+        drop(first);
+        assert_eq!(fs::read_dir(&dir)?.count(), 1);
+        assert_eq!(fs::read_to_string(&target)?, "second");
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwritable_destination_is_output_error() {
+        let dir = env::temp_dir().join(format!("ptree_output_blocked_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // A regular file where a parent directory is expected
+        let blocker = dir.join("blocker");
+        fs::write(&blocker, b"").unwrap();
+
+        let result = OutputSink::open(Some(&blocker.join("out.txt")));
+        assert!(matches!(result, Err(PTreeError::Output(_))));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -3,6 +3,7 @@
 
 use ptree_cache::DiskCache;
 use anyhow::Result;
+use std::path::Path;
 
 /// Attempt incremental cache update using USN Journal
 ///
@@ -27,11 +28,91 @@ pub fn try_incremental_update(
     Ok(false) // Not available on non-Windows
 }
 
-
+/// Drop directories the journal reports deleted, with everything cached below them
+///
+/// Each path is removed through `DiskCache::remove_entry`; paths that
+/// aren't cached are ignored. Returns how many entries were removed.
+/// Test builds check that a consistent cache stays so.
+///
+/// Most reported paths were never cached (files, directories below a
+/// skipped one), so the cache's path filter rules them out first: a path
+/// neither cached itself nor listed by a cached parent is skipped without
+/// a lookup, unless it holds the scanned root.
+pub fn apply_deleted<'a>(cache: &mut DiskCache, deleted: impl IntoIterator<Item = &'a Path>) -> usize {
+    let before = cache.entries.len();
+    #[cfg(test)]
+    let consistent = cache.validate().is_ok();
+    for path in deleted {
+        let parent_cached = path.parent().is_some_and(|parent| cache.entries.may_contain(parent));
+        if !cache.entries.may_contain(path) && !parent_cached && !cache.root.starts_with(path) {
+            continue;
+        }
+        cache.remove_entry(path);
+    }
+    #[cfg(test)]
+    if consistent {
+        let report = cache.validate();
+        assert!(report.is_ok(), "applying deletions left the cache inconsistent:\n{}", report.to_text());
+    }
+    before - cache.entries.len()
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ptree_cache::fixtures::test_entry;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_deleted_directories_leave_prefix_sharing_siblings() {
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        for e in [
+            test_entry("/scan", &["foo", "foobar", "foo2"]),
+            test_entry("/scan/foo", &["sub"]),
+            test_entry("/scan/foo/sub", &[]),
+            test_entry("/scan/foobar", &["sub"]),
+            test_entry("/scan/foobar/sub", &[]),
+            test_entry("/scan/foo2", &[]),
+        ] {
+            cache.entries.insert(e.path.clone(), e);
+        }
+
+        let removed = apply_deleted(&mut cache, [Path::new("/scan/foo"), Path::new("/scan/gone")]);
+        assert_eq!(removed, 2);
+        assert!(cache.entries.contains_key(Path::new("/scan/foobar/sub")));
+        assert!(cache.entries.contains_key(Path::new("/scan/foo2")));
+        assert_eq!(cache.entries[Path::new("/scan")].children, ["foobar", "foo2"]);
+    }
+
+    #[test]
+    fn test_update_without_changes_leaves_the_data_file_alone() -> Result<()> {
+        let temp_dir = std::env::temp_dir().join(format!("ptree_incremental_clean_{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir)?;
+        let cache_path = temp_dir.join("ptree.dat");
+
+        let mut cache = DiskCache::new();
+        cache.root = PathBuf::from("/scan");
+        for e in [test_entry("/scan", &["a"]), test_entry("/scan/a", &[])] {
+            cache.entries.insert(e.path.clone(), e);
+        }
+        cache.save(&cache_path)?;
+        let data = std::fs::read(&cache_path)?;
+
+        let mut loaded = DiskCache::open(&cache_path)?;
+        loaded.load_all_entries_lazy(&cache_path)?;
+        try_incremental_update(&mut loaded, 'C')?;
+        assert_eq!(apply_deleted(&mut loaded, [Path::new("/scan/gone")]), 0);
+        assert!(!loaded.dirty);
+        loaded.save(&cache_path)?;
+        assert_eq!(std::fs::read(&cache_path)?, data);
+
+        assert_eq!(apply_deleted(&mut loaded, [Path::new("/scan/a")]), 1);
+        assert!(loaded.dirty);
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        Ok(())
+    }
 
     #[test]
     #[cfg(windows)]
@@ -40,4 +121,13 @@ mod tests {
         let (c, m, d, r) = estimate_change_impact(&changes);
         assert_eq!((c, m, d, r), (0, 0, 0, 0));
     }
+
+    #[test]
+    fn test_falls_back_to_full_scan() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut cache = DiskCache::open(&temp_dir.path().join("test.dat"))?;
+
+        assert!(!try_incremental_update(&mut cache, 'C')?);
+        Ok(())
+    }
 }
@@ -1,3 +1,3 @@
 pub mod incremental;
 
-pub use incremental::try_incremental_update;
+pub use incremental::{apply_deleted, try_incremental_update};
@@ -1,5 +1,5 @@
-/// Scheduler module for automatic cache updates
-/// Supports Windows Task Scheduler and Unix cron
+//! Scheduler module for automatic cache updates
+//! Supports Windows Task Scheduler and Unix cron
 
 use anyhow::{Result, anyhow};
 use std::path::PathBuf;
@@ -286,7 +286,7 @@ pub fn check_scheduler_status() -> Result<()> {
 
     if crontab_content.contains(&exe_path_str) {
         println!("✓ Scheduler installed and active");
-        println!("");
+        println!();
         println!("Cron entry:");
         for line in crontab_content.lines() {
             if line.contains("ptree") && line.contains("--force") {
@@ -295,7 +295,7 @@ pub fn check_scheduler_status() -> Result<()> {
         }
     } else {
         println!("✗ Scheduler not installed");
-        println!("");
+        println!();
         println!("Install with: ptree --scheduler");
     }
 
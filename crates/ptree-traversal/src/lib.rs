@@ -1,3 +1,11 @@
+pub mod media;
+pub mod progress;
 pub mod traversal;
+pub mod verify;
+pub mod volume;
 
-pub use traversal::{traverse_disk, DebugInfo, TraversalState};
+pub use media::{detect_media, DriveMedia};
+pub use progress::ProgressReporter;
+pub use traversal::{is_cache_fresh, scan_disk, traverse_disk, DebugInfo, TraversalState};
+pub use verify::{verify_cache, EntryStatus, VerifyReport};
+pub use volume::{crosses_volume, volume_id};
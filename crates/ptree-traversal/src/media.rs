@@ -0,0 +1,176 @@
+use std::path::Path;
+
+// ============================================================================
+// Drive Media Detection (default thread count)
+// ============================================================================
+
+/// What kind of storage a scan root lives on
+///
+/// Decides the default worker count: parallel reads pay off on flash, while
+/// on a spinning disk every extra thread adds seeks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriveMedia {
+    /// No seek penalty (SSD, NVMe)
+    SolidState,
+    /// Incurs a seek penalty (HDD)
+    Rotational,
+    /// Mapped drive or UNC share
+    Network,
+    /// Detection unsupported or failed
+    Unknown,
+}
+
+impl DriveMedia {
+    pub fn label(self) -> &'static str {
+        match self {
+            DriveMedia::SolidState => "ssd",
+            DriveMedia::Rotational => "hdd",
+            DriveMedia::Network => "network",
+            DriveMedia::Unknown => "unknown",
+        }
+    }
+
+    /// Worker threads to use when --threads is not given
+    pub fn default_threads(self, cores: usize) -> usize {
+        match self {
+            DriveMedia::SolidState | DriveMedia::Unknown => cores * 2,
+            DriveMedia::Rotational => cores.clamp(2, 4),
+            // Latency-bound, but a share is easily overloaded by one client
+            DriveMedia::Network => 4,
+        }
+    }
+}
+
+/// Media type of the volume holding `path`
+///
+/// Windows asks the volume for its seek penalty (`IOCTL_STORAGE_QUERY_PROPERTY`);
+/// other platforms report `Unknown`.
+pub fn detect_media(path: &Path) -> DriveMedia {
+    #[cfg(windows)]
+    {
+        windows_media(path)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        DriveMedia::Unknown
+    }
+}
+
+#[cfg(windows)]
+fn windows_media(path: &Path) -> DriveMedia {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use winapi::shared::minwindef::{DWORD, FALSE};
+    use winapi::um::fileapi::{CreateFileW, GetDriveTypeW, GetVolumePathNameW, OPEN_EXISTING};
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::ioapiset::DeviceIoControl;
+    use winapi::um::winbase::DRIVE_REMOTE;
+    use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE};
+
+    const IOCTL_STORAGE_QUERY_PROPERTY: DWORD = 0x002D_1400;
+    const STORAGE_DEVICE_SEEK_PENALTY_PROPERTY: DWORD = 7;
+    const PROPERTY_STANDARD_QUERY: DWORD = 0;
+
+    // Layouts of STORAGE_PROPERTY_QUERY and DEVICE_SEEK_PENALTY_DESCRIPTOR,
+    // only ever read or written through the ioctl
+    #[repr(C)]
+    #[allow(dead_code)]
+    struct StoragePropertyQuery {
+        property_id: DWORD,
+        query_type: DWORD,
+        additional_parameters: [u8; 1],
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct DeviceSeekPenaltyDescriptor {
+        version: DWORD,
+        size: DWORD,
+        incurs_seek_penalty: u8,
+    }
+
+    let wide = |text: &OsStr| text.encode_wide().chain(Some(0)).collect::<Vec<u16>>();
+
+    let path_w = wide(path.as_os_str());
+    let mut volume = [0u16; 261];
+    // SAFETY: both buffers are NUL-terminated and `volume` holds MAX_PATH + 1 units
+    if unsafe { GetVolumePathNameW(path_w.as_ptr(), volume.as_mut_ptr(), volume.len() as DWORD) } == FALSE {
+        return DriveMedia::Unknown;
+    }
+    // SAFETY: `volume` was NUL-terminated by GetVolumePathNameW
+    if unsafe { GetDriveTypeW(volume.as_ptr()) } == DRIVE_REMOTE {
+        return DriveMedia::Network;
+    }
+
+    let len = volume.iter().position(|&c| c == 0).unwrap_or(volume.len());
+    let root = String::from_utf16_lossy(&volume[..len]);
+    if root.starts_with(r"\\") {
+        return DriveMedia::Network;
+    }
+    // `C:\` -> `\\.\C:`, the volume device that answers storage queries
+    let device = wide(OsStr::new(&format!(r"\\.\{}", root.trim_end_matches('\\'))));
+
+    // SAFETY: a zero access mask opens the device for queries only
+    let handle = unsafe {
+        CreateFileW(
+            device.as_ptr(),
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            ptr::null_mut(),
+            OPEN_EXISTING,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return DriveMedia::Unknown;
+    }
+
+    let mut query = StoragePropertyQuery {
+        property_id: STORAGE_DEVICE_SEEK_PENALTY_PROPERTY,
+        query_type: PROPERTY_STANDARD_QUERY,
+        additional_parameters: [0],
+    };
+    let mut descriptor = DeviceSeekPenaltyDescriptor::default();
+    let mut returned: DWORD = 0;
+    // SAFETY: in/out buffers are live locals of the sizes passed
+    let ok = unsafe {
+        DeviceIoControl(
+            handle,
+            IOCTL_STORAGE_QUERY_PROPERTY,
+            &mut query as *mut _ as *mut _,
+            std::mem::size_of::<StoragePropertyQuery>() as DWORD,
+            &mut descriptor as *mut _ as *mut _,
+            std::mem::size_of::<DeviceSeekPenaltyDescriptor>() as DWORD,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    // SAFETY: `handle` came from CreateFileW and is closed exactly once
+    unsafe { CloseHandle(handle) };
+
+    if ok == FALSE || (returned as usize) < std::mem::size_of::<DeviceSeekPenaltyDescriptor>() {
+        DriveMedia::Unknown
+    } else if descriptor.incurs_seek_penalty != 0 {
+        DriveMedia::Rotational
+    } else {
+        DriveMedia::SolidState
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_threads_by_media() {
+        assert_eq!(DriveMedia::SolidState.default_threads(8), 16);
+        assert_eq!(DriveMedia::Unknown.default_threads(8), 16);
+        assert_eq!(DriveMedia::Rotational.default_threads(8), 4);
+        assert_eq!(DriveMedia::Rotational.default_threads(1), 2);
+        assert_eq!(DriveMedia::Network.default_threads(32), 4);
+    }
+}
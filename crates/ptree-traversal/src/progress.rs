@@ -0,0 +1,120 @@
+use ptree_cache::format_count;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the progress line is redrawn
+const TICK: Duration = Duration::from_millis(100);
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Single updating progress line on stderr for full scans
+///
+/// The total directory count is unknown up front, so the line shows a spinner,
+/// directories scanned so far, the work queue depth and the scan rate. The
+/// reporter thread is stopped and the line cleared by `finish()` (or on drop),
+/// so it never interleaves with the final output.
+pub struct ProgressReporter {
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ProgressReporter {
+    /// Spawn the reporter thread reading the traversal's shared counters
+    pub fn start(processed: Arc<AtomicUsize>, queue_depth: Arc<AtomicUsize>) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let done_ref = Arc::clone(&done);
+
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let mut stderr = io::stderr();
+            let mut tick = 0;
+
+            while !done_ref.load(Ordering::Acquire) {
+                thread::park_timeout(TICK);
+                if done_ref.load(Ordering::Acquire) {
+                    break;
+                }
+                let line = format_progress(
+                    tick,
+                    processed.load(Ordering::Relaxed),
+                    queue_depth.load(Ordering::Relaxed),
+                    start.elapsed(),
+                );
+                let _ = write!(stderr, "\r\x1b[2K{}", line);
+                let _ = stderr.flush();
+                tick += 1;
+            }
+
+            // Leave the terminal as if the line had never been there
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        });
+
+        ProgressReporter { done, handle: Some(handle) }
+    }
+
+    /// Stop the reporter and wait until its line has been cleared
+    pub fn finish(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.done.store(true, Ordering::Release);
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Render one progress line, e.g. `| scanned 182,401 dirs, queue 3,204, 41k/s`
+pub fn format_progress(tick: usize, processed: usize, queue_depth: usize, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { (processed as f64 / secs) as usize } else { 0 };
+    let rate = if rate >= 1000 {
+        format!("{}k/s", format_count(rate / 1000))
+    } else {
+        format!("{}/s", rate)
+    };
+
+    format!(
+        "{} scanned {} dirs, queue {}, {}",
+        SPINNER[tick % SPINNER.len()],
+        format_count(processed),
+        format_count(queue_depth),
+        rate
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_progress_line() {
+        assert_eq!(
+            format_progress(0, 182_401, 3_204, Duration::from_secs(4)),
+            "| scanned 182,401 dirs, queue 3,204, 45k/s"
+        );
+        assert_eq!(
+            format_progress(5, 12, 0, Duration::from_secs(2)),
+            "/ scanned 12 dirs, queue 0, 6/s"
+        );
+        assert_eq!(format_progress(2, 0, 1, Duration::ZERO), "- scanned 0 dirs, queue 1, 0/s");
+    }
+
+    #[test]
+    fn test_finish_stops_reporter_thread() {
+        let reporter = ProgressReporter::start(Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        reporter.finish();
+    }
+}
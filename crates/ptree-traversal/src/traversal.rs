@@ -1,14 +1,29 @@
-use ptree_cache::{DiskCache, DirEntry};
-use ptree_core::Args;
-use std::collections::VecDeque;
+use crate::media::{detect_media, DriveMedia};
+use crate::progress::ProgressReporter;
+use crate::volume::{crosses_volume, volume_id};
+use ptree_cache::{compute_content_hash, has_directory_changed, ChildNames, DiskCache, DirEntry};
+use ptree_core::{Args, PTreeError};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::hash::{Hash, Hasher};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Instant, Duration};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use parking_lot::RwLock;
 use anyhow::Result;
 
+/// Log a "processed N directories" line every this many directories (with -v)
+const PROGRESS_INTERVAL: usize = 10_000;
+
+/// Directories modified this close to the previous scan are listed again
+/// even if their hash matches: on filesystems with coarse timestamps (FAT
+/// keeps two seconds) a later change could leave the mtime as it was.
+const RACY_WINDOW: chrono::Duration = chrono::Duration::seconds(2);
+
 
 
 /// Debug timing information and statistics
@@ -23,6 +38,20 @@ pub struct DebugInfo {
     pub total_dirs: usize,
     pub total_files: usize,
     pub threads_used: usize,
+    /// Storage the scan root lives on, which picked the thread count (None with --threads)
+    pub drive_media: Option<DriveMedia>,
+    /// Entries that were not in the cache before this run
+    pub entries_added: usize,
+    /// Cached entries whose contents changed during this run
+    pub entries_updated: usize,
+    /// Directories that could not be listed because access was denied
+    pub access_denied: usize,
+    /// Directories read from disk during this run
+    pub dirs_listed: usize,
+    /// Directories whose cached listing was still current, so they were not read again
+    pub dirs_reused: usize,
+    /// Cached entries below the scan root that the traversal no longer found (deleted since)
+    pub entries_removed: usize,
 }
 
 /// Shared state for parallel DFS traversal across worker threads
@@ -45,6 +74,86 @@ pub struct TraversalState {
 
     /// Skip statistics: count of skipped directories (shared across threads)
     pub skip_stats: Arc<Mutex<std::collections::HashMap<String, usize>>>,
+
+    /// Directories whose listing failed with "permission denied"
+    pub access_denied: Arc<Mutex<Vec<PathBuf>>>,
+
+    /// Directories listed so far, for progress logging and the progress line
+    pub dirs_processed: Arc<AtomicUsize>,
+
+    /// Directories waiting in `work_queue`, published for the progress line
+    pub queue_depth: Arc<AtomicUsize>,
+
+    /// Volume of the scan root with --one-file-system; directories on other volumes aren't descended into
+    pub root_volume: Option<u64>,
+
+    /// Reuse cached listings whose hash still matches (off with --no-hash-skip)
+    pub hash_skip: bool,
+
+    /// Hash of the settings that shape a listing (skipped names, --one-file-system),
+    /// mixed into every `content_hash` so changing them invalidates the cached ones
+    pub fingerprint: u64,
+
+    /// When the previous scan finished; listings modified near it are not trusted
+    pub previous_scan: DateTime<Utc>,
+
+    /// Directories reused without listing them, counted in `dirs_processed` too
+    pub dirs_reused: Arc<AtomicUsize>,
+
+    /// This traversal's `DiskCache::scan_generation`, stamped on every entry it visits
+    pub generation: u64,
+}
+
+/// Directory a traversal for `drive` starts from, checked to exist
+///
+/// An explicit PATH, else the current directory (the full drive with --force).
+fn scan_root(drive: &char, args: &Args) -> Result<PathBuf> {
+    let scan_root = if let Some(path) = &args.path {
+        // Absolute without resolving symlinks, so UNC and mapped paths stay as given
+        std::path::absolute(path)?
+    } else if args.force {
+        // --force: scan full drive
+        let root = PathBuf::from(format!("{}:\\", drive));
+        if !root.exists() {
+            return Err(PTreeError::InvalidDrive(format!("drive {} does not exist", drive)).into());
+        }
+        root
+    } else {
+        // Default: scan current directory and subdirectories
+        std::env::current_dir()?
+    };
+    
+    // Verify scan root exists and is a directory
+    if !scan_root.exists() {
+        return Err(PTreeError::RootNotFound(format!("{} does not exist", scan_root.display())).into());
+    }
+    if !scan_root.is_dir() {
+        return Err(PTreeError::RootNotFound(format!("{} is not a directory", scan_root.display())).into());
+    }
+    Ok(scan_root)
+}
+
+/// Whether the flags and the cache age allow using a cached scan as-is
+fn reuses_cache(cache: &DiskCache, args: &Args) -> bool {
+    if args.no_cache || args.force {
+        false // --no-cache and --force always trigger a rescan
+    } else if args.read_only {
+        true // --read-only inspects whatever is cached, however old
+    } else {
+        // Check cache freshness rule (time-based only)
+        let age = Utc::now().signed_duration_since(cache.last_scan);
+        age.num_seconds() < args.cache_ttl.unwrap_or(3600) as i64
+    }
+}
+
+/// Whether the cache can be rendered for `drive` without traversing at all
+///
+/// For a cache opened index-only (`DiskCache::open`, no entries loaded):
+/// true when its last scan was of this same root and is still fresh, so
+/// the output can be read straight from the data file.
+pub fn is_cache_fresh(drive: &char, cache: &DiskCache, args: &Args) -> Result<bool> {
+    let scan_root = scan_root(drive, args)?;
+    Ok(cache.entries.is_empty() && cache.root == scan_root && reuses_cache(cache, args))
 }
 
 /// Traverse disk and update cache (per README spec)
@@ -73,44 +182,84 @@ pub struct TraversalState {
 /// 7. Spawn worker threads that process queue in parallel (iterative DFS)
 /// 8. Flush all pending writes and save cache atomically
 pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result<DebugInfo> {
-    // Determine scan root: current directory by default, full drive with --force
-    let scan_root = if args.force {
-        // --force: scan full drive
-        let root = PathBuf::from(format!("{}:\\", drive));
-        if !root.exists() {
-            anyhow::bail!("Drive {} does not exist", drive);
-        }
-        root
-    } else {
-        // Default: scan current directory and subdirectories
-        std::env::current_dir()?
-    };
-    
-    // Verify scan root exists and is a directory
-    if !scan_root.exists() {
-        anyhow::bail!("Scan root does not exist: {}", scan_root.display());
+    let mut info = scan_disk(drive, cache, args)?;
+    if info.cache_used {
+        return Ok(info);
     }
-    if !scan_root.is_dir() {
-        anyhow::bail!("Scan root is not a directory: {}", scan_root.display());
+
+    let save_start = Instant::now();
+    if !args.no_cache && !args.read_only {
+        let cache_path = cache_file(cache, args)?;
+        cache.save(&cache_path).map_err(|e| {
+            PTreeError::Cache(format!("failed to save {}: {:#}", cache_path.display(), e))
+        })?;
+    }
+    info.save_time = save_start.elapsed();
+    info.cache_index_time += info.save_time;
+    Ok(info)
+}
+
+/// The file `cache` was opened from, or the one the arguments name
+///
+/// Saving back to the opened file keeps a traversal from writing to a
+/// different cache than it loaded when the resolved location has moved.
+fn cache_file(cache: &DiskCache, args: &Args) -> Result<PathBuf> {
+    match &cache.path {
+        Some(path) => Ok(path.clone()),
+        None => ptree_cache::get_cache_path_custom(
+            args.cache_dir.as_deref(),
+            args.cache_path.as_deref(),
+            args.cache_name.as_deref(),
+        ),
     }
+}
+
+/// `traverse_disk` without the save: the cache is updated in memory only
+///
+/// For callers that save it themselves, e.g. on a background thread while
+/// the output renders (`DiskCache::save_in_background`). `save_time` is zero.
+pub fn scan_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result<DebugInfo> {
+    let scan_root = scan_root(drive, args)?;
 
-    let is_first_run = cache.entries.is_empty();
+    // Per root, so entries cached for another drive don't make this one look scanned
+    let is_first_run = !cache.entries.contains_key(&scan_root);
     cache.root = scan_root.clone();
 
+    // An index-only open leaves the previous scan's entries on disk; unchanged
+    // directories can only be reused once they are loaded. Loaded before the root
+    // stub below, so the saved root is what the rescan is compared against
+    if is_first_run && !args.no_hash_skip && !args.no_cache {
+        let cache_path = cache_file(cache, args)?;
+        if let Err(e) = cache.load_all_entries_lazy(&cache_path) {
+            log::debug!("cannot load {} for reuse; listing every directory: {:#}", cache_path.display(), e);
+        }
+    }
+
     // Ensure root directory is added to cache (important for --no-cache mode)
-    if is_first_run && !cache.entries.contains_key(&scan_root) {
+    if !cache.entries.contains_key(&scan_root) {
         let root_entry = DirEntry {
             path: scan_root.clone(),
             name: scan_root
                 .file_name()
                 .and_then(|n| n.to_str().map(|s| s.to_string()))
                 .unwrap_or_default(),
-            modified: Utc::now(),
+            modified: dir_modified(&scan_root),
             content_hash: 0,
-            children: Vec::new(),
+            children: ChildNames::new(),
             symlink_target: None,
             is_hidden: false,
             is_dir: true,
+            size: 0,
+            skipped_children: false,
+            is_mount: false,
+            access_error: None,
+            evicted: false,
+            file_count: 0,
+            dir_count: 0,
+            total_size: 0,
+            size_is_lower_bound: false,
+            generation: 0,
+            spilled_children: 0,
         };
         cache.entries.insert(scan_root.clone(), root_entry);
     }
@@ -119,23 +268,11 @@ pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result
     // Check Cache Freshness (configurable via --cache-ttl, default 1 hour)
     // ============================================================================
 
-    let cache_ttl_seconds = args.cache_ttl.unwrap_or(3600);
-    
-    let should_use_cache = if args.no_cache {
-        false // --no-cache always triggers rescan
-    } else if args.force {
-        false // --force always triggers rescan
-    } else if is_first_run {
-        false // First run always scans
-    } else {
-        // Check cache freshness rule (time-based only)
-        let now = Utc::now();
-        let age = now.signed_duration_since(cache.last_scan);
-        age.num_seconds() < cache_ttl_seconds as i64
-    };
-    
+    let should_use_cache = !is_first_run && reuses_cache(cache, args);
+
     if should_use_cache {
-        let total_files = cache.entries.values().map(|e| e.children.len()).sum();
+        log::info!("cache for {} is fresh; skipping traversal", scan_root.display());
+        let (total_dirs, total_files) = count_entries(cache, &scan_root);
         return Ok(DebugInfo {
             is_first_run: false,
             scan_root: cache.root.clone(),
@@ -143,9 +280,16 @@ pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result
             traversal_time: Duration::from_secs(0),
             save_time: Duration::from_secs(0),
             cache_index_time: Duration::from_secs(0),
-            total_dirs: cache.entries.len(),
+            total_dirs,
             total_files,
             threads_used: 0,
+            drive_media: None,
+            entries_added: 0,
+            entries_updated: 0,
+            access_denied: 0,
+            dirs_listed: 0,
+            dirs_reused: 0,
+            entries_removed: 0,
         });
     }
 
@@ -157,27 +301,54 @@ pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result
     // This allows cleaner separation between incremental (USN Journal) and full scan (DFS)
     let changed_dirs_filter: Option<std::collections::HashSet<String>> = None;
 
+    let skip_dirs = args.skip_dirs();
+    let fingerprint = {
+        let mut names: Vec<String> = skip_dirs.iter().map(|name| name.to_ascii_lowercase()).collect();
+        names.sort_unstable();
+        let mut hasher = DefaultHasher::new();
+        (names, args.one_file_system).hash(&mut hasher);
+        hasher.finish()
+    };
+
     // ============================================================================
     // Initialize Traversal State
     // ============================================================================
 
     let mut work_queue = VecDeque::new();
     work_queue.push_back(scan_root.clone());
+    cache.scan_generation += 1;
 
     let state = TraversalState {
         work_queue: Arc::new(Mutex::new(work_queue)),
         cache: Arc::new(RwLock::new(cache.clone())),
         in_progress: Arc::new(Mutex::new(std::collections::HashSet::new())),
-        skip_dirs: args.skip_dirs(),
+        skip_dirs,
         changed_dirs_filter,
         skip_stats: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        access_denied: Arc::new(Mutex::new(Vec::new())),
+        dirs_processed: Arc::new(AtomicUsize::new(0)),
+        queue_depth: Arc::new(AtomicUsize::new(1)),
+        root_volume: if args.one_file_system { volume_id(&scan_root) } else { None },
+        hash_skip: !args.no_hash_skip,
+        fingerprint,
+        previous_scan: cache.last_scan,
+        dirs_reused: Arc::new(AtomicUsize::new(0)),
+        generation: cache.scan_generation,
     };
 
     // ============================================================================
     // Create Thread Pool & Determine Thread Count
     // ============================================================================
 
-    let num_threads = args.threads.unwrap_or_else(|| num_cpus::get() * 2);
+    // Only probed without --threads; an explicit count is taken as-is
+    let (num_threads, drive_media) = match args.threads {
+        Some(threads) => (threads, None),
+        None => {
+            let media = detect_media(&scan_root);
+            log::debug!("{} is on {} storage", scan_root.display(), media.label());
+            (media.default_threads(num_cpus::get()), Some(media))
+        }
+    };
 
     let pool = rayon::ThreadPoolBuilder::new()
         .num_threads(num_threads)
@@ -187,26 +358,33 @@ pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result
     // Spawn Worker Threads for Parallel DFS Traversal
     // ============================================================================
 
+    log::info!("traversal of {} started with {} threads", scan_root.display(), num_threads);
+    let progress = show_progress(args).then(|| {
+        ProgressReporter::start(Arc::clone(&state.dirs_processed), Arc::clone(&state.queue_depth))
+    });
     let traversal_start = Instant::now();
-    let filter = state.changed_dirs_filter.clone();
-    let root = scan_root.clone();
-    let skip_stats_ref = Arc::clone(&state.skip_stats);
     pool.in_place_scope(|s| {
         for _ in 0..num_threads {
-            let work = Arc::clone(&state.work_queue);
-            let cache_ref = Arc::clone(&state.cache);
-            let skip = state.skip_dirs.clone();
-            let in_progress = Arc::clone(&state.in_progress);
-            let filter_ref = filter.clone();
-            let root_ref = root.clone();
-            let stats_ref = Arc::clone(&skip_stats_ref);
+            let state = &state;
+            let root = &scan_root;
 
             s.spawn(move |_| {
-                dfs_worker(&work, &cache_ref, &skip, &in_progress, &filter_ref, &root_ref, &stats_ref);
+                dfs_worker(state, root);
             });
         }
     });
     let traversal_elapsed = traversal_start.elapsed();
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    let dirs_processed = state.dirs_processed.load(Ordering::Relaxed);
+    let dirs_reused = state.dirs_reused.load(Ordering::Relaxed);
+    log::info!(
+        "traversal finished: {} directories ({} unchanged, not listed) in {:.2?}",
+        dirs_processed,
+        dirs_reused,
+        traversal_elapsed
+    );
 
     // ============================================================================
     // Extract & Save Final Cache
@@ -224,9 +402,21 @@ pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result
     final_cache.flush_pending_writes();
 
     let cache_index_start = Instant::now();
-    
+
+    let (entries_added, entries_updated) = count_changes(cache, &final_cache);
     *cache = final_cache;
+
+    // Everything still on disk was visited; with the incremental filter only part of it was
+    let entries_removed = if state.changed_dirs_filter.is_none() {
+        cache.sweep_stale(&scan_root, state.generation)
+    } else {
+        0
+    };
+    if entries_removed > 0 {
+        log::info!("dropped {} cached entries no longer on disk", entries_removed);
+    }
     cache.last_scan = Utc::now();
+    cache.compute_totals();
 
     // Transfer skip statistics from traversal state to cache
     let skip_stats = match Arc::try_unwrap(state.skip_stats) {
@@ -237,40 +427,86 @@ pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result
         }
     };
     cache.skip_stats = skip_stats;
+    let access_denied = state.access_denied.lock().unwrap().len();
 
-    let cache_path = if let Some(ref custom_dir) = args.cache_dir {
-        ptree_cache::get_cache_path_custom(Some(custom_dir))?
-    } else {
-        ptree_cache::get_cache_path()?
-    };
-    
-    let save_start = Instant::now();
-    if !args.no_cache {
-        cache.save(&cache_path)?;
-    }
-    let save_elapsed = save_start.elapsed();
-    
     let cache_index_elapsed = cache_index_start.elapsed();
 
     // ============================================================================
     // Return Debug Info
     // ============================================================================
 
-    let total_files = cache.entries.values().map(|e| e.children.len()).sum();
+    let (total_dirs, total_files) = count_entries(cache, &scan_root);
     
     Ok(DebugInfo {
         is_first_run,
         scan_root: cache.root.clone(),
         cache_used: false,
         traversal_time: traversal_elapsed,
-        save_time: save_elapsed,
+        save_time: Duration::from_secs(0),
         cache_index_time: cache_index_elapsed,
-        total_dirs: cache.entries.len(),
+        total_dirs,
         total_files,
         threads_used: num_threads,
+        drive_media,
+        entries_added,
+        entries_updated,
+        access_denied,
+        dirs_listed: dirs_processed - dirs_reused,
+        dirs_reused,
+        entries_removed,
     })
 }
 
+/// Whether to draw the progress line: on by default when stderr is a terminal
+///
+/// Off with --no-progress or --quiet, and with -v, whose log lines already
+/// report progress and would otherwise be overdrawn.
+fn show_progress(args: &Args) -> bool {
+    !args.no_progress && !args.quiet && args.verbose == 0 && std::io::stderr().is_terminal()
+}
+
+/// Count cached (directories, files) at or below `root`
+///
+/// The cache can hold several drives at once; stats only cover this one.
+fn count_entries(cache: &DiskCache, root: &Path) -> (usize, usize) {
+    let mut dirs = 0;
+    let mut files = 0;
+    for entry in cache.entries.values().filter(|e| e.path.starts_with(root)) {
+        if entry.is_dir {
+            dirs += 1;
+        } else {
+            files += 1;
+        }
+    }
+    (dirs, files)
+}
+
+/// Count entries added and updated between the pre-scan and post-scan caches
+///
+/// Only directories are compared by `modified`: file entries saved by older
+/// builds carry the time of their scan rather than their own. Any entry
+/// counts as updated if its children changed.
+fn count_changes(before: &DiskCache, after: &DiskCache) -> (usize, usize) {
+    let mut added = 0;
+    let mut updated = 0;
+
+    for (path, new_entry) in &after.entries {
+        match before.entries.get(path) {
+            None => added += 1,
+            Some(old_entry) => {
+                let changed = old_entry.is_dir != new_entry.is_dir
+                    || old_entry.children != new_entry.children
+                    || (new_entry.is_dir && old_entry.modified != new_entry.modified);
+                if changed {
+                    updated += 1;
+                }
+            }
+        }
+    }
+
+    (added, updated)
+}
+
 /// Worker thread for DFS traversal
 ///
 /// Each worker thread:
@@ -279,15 +515,25 @@ pub fn traverse_disk(drive: &char, cache: &mut DiskCache, args: &Args) -> Result
 /// 3. Enumerates directory, filters skipped entries
 /// 4. For incremental updates: only process directories in changed_dirs_filter
 /// 5. Buffers children in cache and queues directories for processing
-fn dfs_worker(
-    work_queue: &Arc<Mutex<VecDeque<PathBuf>>>,
-    cache: &Arc<RwLock<DiskCache>>,
-    skip_dirs: &std::collections::HashSet<String>,
-    in_progress: &Arc<Mutex<std::collections::HashSet<PathBuf>>>,
-    changed_dirs_filter: &Option<std::collections::HashSet<String>>,
-    scan_root: &PathBuf,
-    skip_stats: &Arc<Mutex<std::collections::HashMap<String, usize>>>,
-) {
+/// 6. Records directories it was denied access to
+fn dfs_worker(state: &TraversalState, scan_root: &Path) {
+    let TraversalState {
+        work_queue,
+        cache,
+        in_progress,
+        skip_dirs,
+        changed_dirs_filter,
+        skip_stats,
+        access_denied,
+        dirs_processed,
+        queue_depth,
+        root_volume,
+        hash_skip,
+        fingerprint,
+        generation,
+        ..
+    } = state;
+
     // Thread-local buffers to batch cache writes and reduce lock contention
     let mut entry_buffer: Vec<(PathBuf, DirEntry)> = Vec::with_capacity(500);
     let mut skip_buffer: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
@@ -309,6 +555,7 @@ fn dfs_worker(
                     break;
                 }
             }
+            queue_depth.store(queue.len(), Ordering::Relaxed);
             batch
         };
 
@@ -361,57 +608,112 @@ fn dfs_worker(
                      true
                  };
                  
-                 if should_process {
+                 let reused = should_process && *hash_skip && reuse_cached_listing(state, &path);
+                 if should_process && !reused {
                      // ============================================================
                      // Enumerate Directory & Process Entries
                      // ============================================================
 
-                     if let Ok(entries) = fs::read_dir(&path) {
-                          let mut children = Vec::new();
-                          let mut child_entries = Vec::new();
+                     // Taken before listing: a change made meanwhile shows up as a newer mtime next time
+                     let modified = dir_modified(&path);
+                     let listing = fs::read_dir(&path);
+                     if let Err(e) = &listing {
+                         if e.kind() == std::io::ErrorKind::PermissionDenied {
+                             access_denied.lock().unwrap().push(path.clone());
+                         }
+                         // Still a directory, just one whose contents (and size) are unknown
+                         if e.kind() != std::io::ErrorKind::NotFound {
+                             let unreadable = DirEntry {
+                                 path: path.clone(),
+                                 name: path
+                                     .file_name()
+                                     .and_then(|n| n.to_str().map(|s| s.to_string()))
+                                     .unwrap_or_default(),
+                                 modified,
+                                 content_hash: 0,
+                                 children: ChildNames::new(),
+                                 symlink_target: None,
+                                 is_hidden: false,
+                                 is_dir: true,
+                                 size: 0,
+                                 skipped_children: true,
+                                 is_mount: false,
+                                 access_error: Some(e.to_string()),
+                                 evicted: false,
+                                 file_count: 0,
+                                 dir_count: 0,
+                                 total_size: 0,
+                                 size_is_lower_bound: false,
+                                 generation: *generation,
+                                 spilled_children: 0,
+                             };
+                             entry_buffer.push((path.clone(), unreadable));
+                         }
+                     }
+
+                     if let Ok(entries) = listing {
+                          let mut children = ChildNames::new();
                           let mut child_dirs_to_queue = Vec::new();
                           let mut child_files_to_cache = Vec::new();
                           let mut skipped = Vec::new(); // Batch skipped directories
+                          let mut skipped_children = false;
+                          let mut mount_points = Vec::new();
+                          let (mut file_count, mut dir_count) = (0u32, 0u32);
+
+                          for entry in entries.flatten() {
+                              let file_name = entry.file_name();
+                              let file_name_str = file_name.to_string_lossy();
+
+                              // Skip filtered directories
+                              if should_skip(&file_name_str, skip_dirs) {
+                                  // Batch skip statistics (don't lock on every skip)
+                                  skipped.push(file_name_str.to_string());
+                                  skipped_children = true;
+                                  continue;
+                              }
 
-                          for entry_result in entries {
-                               if let Ok(entry) = entry_result {
-                                   let file_name = entry.file_name();
-                                   let file_name_str = file_name.to_string_lossy();
-
-                                   // Skip filtered directories
-                                   if should_skip(&file_name_str, skip_dirs) {
-                                       // Batch skip statistics (don't lock on every skip)
-                                       skipped.push(file_name_str.to_string());
-                                       continue;
-                                   }
-
-                                   let child_path = entry.path();
-                                   children.push(file_name_str.to_string());
-
-                                   // Check if this is a directory (avoid unnecessary metadata calls for files)
-                                   match entry.file_type() {
-                                       Ok(ft) if ft.is_dir() => {
-                                           // Queue directories for processing
-                                           child_dirs_to_queue.push(child_path.clone());
-                                           // Also add to cache for file listing
-                                           if !child_files_to_cache.iter().any(|p| p == &child_path) {
-                                               child_files_to_cache.push(child_path);
-                                           }
-                                       }
-                                       Ok(ft) if ft.is_symlink() => {
-                                           // Capture symlink target - add to both queues if it's a dir symlink
-                                           let target = fs::read_link(&child_path).ok();
-                                           child_entries.push((file_name_str.to_string(), target));
-                                           child_files_to_cache.push(child_path.clone());
-                                           // Don't queue symlinks for traversal - they would cause loops
-                                       }
-                                       Ok(_) => {
-                                           // Regular file: add to cache but don't queue for traversal
-                                           child_files_to_cache.push(child_path);
-                                       }
-                                       _ => {} // Couldn't get file type, skip
-                                   }
-                               }
+                              let child_path = entry.path();
+                              children.push(&file_name_str);
+
+                              // Check if this is a directory (avoid unnecessary metadata calls for files)
+                              match entry.file_type() {
+                                  Ok(ft) if ft.is_dir() && crosses_volume(&child_path, *root_volume) => {
+                                      // Mount point of another volume: recorded, never descended into
+                                      log::debug!("{} is on another volume; not descending", child_path.display());
+                                      mount_points.push(child_path);
+                                      dir_count += 1;
+                                  }
+                                  Ok(ft) if ft.is_dir() => {
+                                      dir_count += 1;
+                                      // Queued only: the directory's own listing (or reuse) writes its entry,
+                                      // and a placeholder here could land after it and replace it
+                                      child_dirs_to_queue.push(child_path);
+                                  }
+                                  Ok(ft) if ft.is_symlink() => {
+                                      file_count += 1;
+                                      // Capture symlink target, stored as read (relative targets stay relative)
+                                      let target = fs::read_link(&child_path).ok();
+                                      // The link's own time, not its target's
+                                      let link_modified = entry.metadata().ok().and_then(|metadata| metadata.modified().ok());
+                                      child_files_to_cache.push((child_path, 0, link_modified, target));
+                                      // Don't queue symlinks for traversal - they would cause loops
+                                  }
+                                  Ok(_) => {
+                                      file_count += 1;
+                                      // Regular file: add to cache but don't queue for traversal
+                                      // (metadata comes with the listing on Windows, an lstat elsewhere)
+                                      match entry.metadata() {
+                                          Ok(metadata) => {
+                                              child_files_to_cache.push((child_path, metadata.len(), metadata.modified().ok(), None))
+                                          }
+                                          Err(_) => {
+                                              skipped_children = true;
+                                              child_files_to_cache.push((child_path, 0, None, None));
+                                          }
+                                      }
+                                  }
+                                  _ => skipped_children = true, // Couldn't get file type, skip
+                              }
                           }
 
                           // ========================================================
@@ -422,25 +724,38 @@ fn dfs_worker(
                               for dir_path in child_dirs_to_queue {
                                   queue.push_back(dir_path);
                               }
+                              queue_depth.store(queue.len(), Ordering::Relaxed);
                           }
                           
                           // ========================================================
                           // Buffer file entries (thread-local, flush periodically)
                           // Reduces cache.write() lock acquisitions dramatically
                           // ========================================================
-                          for file_path in child_files_to_cache {
+                          for (file_path, size, file_modified, symlink_target) in child_files_to_cache {
                               let file_entry = DirEntry {
                                   path: file_path.clone(),
                                   name: file_path
                                       .file_name()
                                       .and_then(|n| n.to_str().map(|s| s.to_string()))
                                       .unwrap_or_default(),
-                                  modified: Utc::now(),
+                                  // A file that can't be stat'ed keeps its directory's time, stable across rescans
+                                  modified: file_modified.map_or(modified, DateTime::<Utc>::from),
                                   content_hash: 0,
-                                  children: Vec::new(),
-                                  symlink_target: None,
+                                  children: ChildNames::new(),
+                                  symlink_target,
                                   is_hidden: false,
                                   is_dir: false,
+                                  size,
+                                  skipped_children: false,
+                                  is_mount: false,
+                                  access_error: None,
+                                  evicted: false,
+                                  file_count: 0,
+                                  dir_count: 0,
+                                  total_size: size,
+                                  size_is_lower_bound: false,
+                                  generation: *generation,
+                                  spilled_children: 0,
                               };
                               entry_buffer.push((file_path, file_entry));
                               
@@ -453,6 +768,35 @@ fn dfs_worker(
                               }
                           }
 
+                          for mount_path in mount_points {
+                              let mount_entry = DirEntry {
+                                  path: mount_path.clone(),
+                                  name: mount_path
+                                      .file_name()
+                                      .and_then(|n| n.to_str().map(|s| s.to_string()))
+                                      .unwrap_or_default(),
+                                  modified: dir_modified(&mount_path),
+                                  content_hash: 0,
+                                  children: ChildNames::new(),
+                                  symlink_target: None,
+                                  is_hidden: false,
+                                  is_dir: true,
+                                  size: 0,
+                                  // Contents unknown, so --du sizes above it are lower bounds
+                                  skipped_children: true,
+                                  is_mount: true,
+                                  access_error: None,
+                                  evicted: false,
+                                  file_count: 0,
+                                  dir_count: 0,
+                                  total_size: 0,
+                                  size_is_lower_bound: false,
+                                  generation: *generation,
+                                  spilled_children: 0,
+                              };
+                              entry_buffer.push((mount_path, mount_entry));
+                          }
+
                           // ========================================================
                           // Buffer skip statistics (thread-local, flush on exit)
                           // ========================================================
@@ -493,12 +837,23 @@ fn dfs_worker(
                                   .file_name()
                                   .and_then(|n| n.to_str().map(|s| s.to_string()))
                                   .unwrap_or_default(),
-                              modified: Utc::now(),
-                              content_hash: 0,
+                              modified,
+                              content_hash: listing_hash(&path, modified, &children, *fingerprint),
                               children,
                               symlink_target: None,
                               is_hidden,
                               is_dir: true,
+                              size: 0,
+                              skipped_children,
+                              is_mount: false,
+                              access_error: None,
+                              evicted: false,
+                              file_count,
+                              dir_count,
+                              total_size: 0,
+                              size_is_lower_bound: false,
+                              generation: *generation,
+                              spilled_children: 0,
                           };
 
                           // ========================================================
@@ -513,6 +868,11 @@ fn dfs_worker(
                                   cache_guard.add_entry(p, e);
                               }
                           }
+
+                          let processed = dirs_processed.fetch_add(1, Ordering::Relaxed) + 1;
+                          if processed.is_multiple_of(PROGRESS_INTERVAL) {
+                              log::info!("processed {} directories", processed);
+                          }
                      }
 
                      // ============================================================
@@ -524,7 +884,7 @@ fn dfs_worker(
                          progress.remove(&path);
                      }
                  } else {
-                     // Directory filtered out (incremental mode): skip it
+                     // Filtered out (incremental mode) or reused from the cache: nothing to list
                      {
                          let mut progress = in_progress.lock().unwrap();
                          progress.remove(&path);
@@ -535,16 +895,277 @@ fn dfs_worker(
     }
 }
 
+/// Keep the cached listing of `path` if it is still current, queueing its cached subdirectories
+///
+/// A directory's mtime changes whenever an entry in it is added, removed
+/// or renamed, so a cached entry whose `content_hash` (path, mtime and
+/// child names, see `listing_hash`) matches the directory on disk has the
+/// same children: they stay as cached, files and sizes included. The
+/// subdirectories are still queued, since a change further down never
+/// reaches this mtime; each costs a stat rather than a listing. Returns
+/// false, leaving the directory to be listed, for entries from an
+/// incomplete scan and for directories modified within `RACY_WINDOW` of
+/// the previous scan.
+fn reuse_cached_listing(state: &TraversalState, path: &Path) -> bool {
+    let subdirs = {
+        let cache = state.cache.read();
+        let Some(cached) = cache.get_entry(path) else {
+            return false;
+        };
+        if cached.content_hash == 0
+            || !cached.is_dir
+            || cached.skipped_children
+            || cached.access_error.is_some()
+            || cached.evicted
+            || cached.is_mount
+        {
+            return false;
+        }
+        let Ok(modified) = fs::metadata(path).and_then(|m| m.modified()).map(DateTime::<Utc>::from) else {
+            return false;
+        };
+        if modified > state.previous_scan - RACY_WINDOW {
+            return false;
+        }
+        let current = DirEntry {
+            modified,
+            content_hash: listing_hash(path, modified, &cached.children, state.fingerprint),
+            ..cached.clone()
+        };
+        if has_directory_changed(cached, &current) {
+            return false;
+        }
+
+        let mut subdirs = Vec::new();
+        for name in &cached.children {
+            match cache.get_entry(&path.join(name)) {
+                Some(child) if child.is_dir && !child.is_mount => subdirs.push(child.path.clone()),
+                Some(_) => {}
+                // Listed but never cached (an interrupted scan): list the directory again
+                None => return false,
+            }
+        }
+        subdirs
+    };
+
+    // The listing and its files count as visited; the subdirectories are stamped as they are reached
+    {
+        let mut cache = state.cache.write();
+        let files: Vec<PathBuf> = match cache.entries.get_mut(path) {
+            Some(entry) => {
+                entry.generation = state.generation;
+                entry.children.iter().map(|name| path.join(name)).collect()
+            }
+            None => Vec::new(),
+        };
+        for file in files {
+            if let Some(entry) = cache.entries.get_mut(&file).filter(|entry| !entry.is_dir || entry.is_mount) {
+                entry.generation = state.generation;
+            }
+        }
+    }
+
+    if !subdirs.is_empty() {
+        let mut queue = state.work_queue.lock().unwrap();
+        queue.extend(subdirs);
+        state.queue_depth.store(queue.len(), Ordering::Relaxed);
+    }
+    state.dirs_reused.fetch_add(1, Ordering::Relaxed);
+    let processed = state.dirs_processed.fetch_add(1, Ordering::Relaxed) + 1;
+    if processed.is_multiple_of(PROGRESS_INTERVAL) {
+        log::info!("processed {} directories", processed);
+    }
+    true
+}
+
+/// `compute_content_hash` of a directory listing, salted with the scan's settings fingerprint
+fn listing_hash(path: &Path, modified: DateTime<Utc>, children: &ChildNames, fingerprint: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    compute_content_hash(path, modified, children, &HashMap::new()).hash(&mut hasher);
+    fingerprint.hash(&mut hasher);
+    hasher.finish()
+}
+
 fn should_skip(name: &str, skip_dirs: &std::collections::HashSet<String>) -> bool {
     skip_dirs.iter().any(|skip| {
         name.eq_ignore_ascii_case(skip)
     })
 }
 
+/// Directory modification time from the filesystem (falls back to now if unreadable)
+fn dir_modified(path: &Path) -> DateTime<Utc> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::Parser;
+    use std::time::SystemTime;
+
+    /// Set a directory's mtime an hour back, well clear of `RACY_WINDOW`
+    fn backdate(dir: &Path) -> std::io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.read(true);
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+            // FILE_FLAG_BACKUP_SEMANTICS: needed to open a directory
+            options.write(true).custom_flags(0x0200_0000);
+        }
+        options.open(dir)?.set_modified(SystemTime::now() - Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn test_rescan_lists_only_changed_directories() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("ptree_hash_skip_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let deep = root.join("deep").join("a").join("b").join("c");
+        let busy = root.join("busy");
+        fs::create_dir_all(&deep)?;
+        fs::create_dir_all(&busy)?;
+        fs::write(deep.join("leaf.txt"), "leaf")?;
+        fs::write(busy.join("old.txt"), "old")?;
+        for dir in deep.ancestors().take(5).chain([busy.as_path()]) {
+            backdate(dir)?;
+        }
+
+        let scan = |extra: &[&str]| {
+            let mut argv = vec!["ptree", "--no-cache", "--no-progress", root.to_str().unwrap()];
+            argv.extend_from_slice(extra);
+            Args::parse_from(argv)
+        };
+        let mut cache = DiskCache::new();
+        let first = traverse_disk(&'C', &mut cache, &scan(&[]))?;
+        assert_eq!((first.dirs_listed, first.dirs_reused), (6, 0));
+
+        // Only `busy` changed: the deep subtree is never listed, yet stays cached
+        fs::write(busy.join("new.txt"), "new")?;
+        let second = traverse_disk(&'C', &mut cache, &scan(&[]))?;
+        assert_eq!((second.dirs_listed, second.dirs_reused), (1, 5));
+        assert!(cache.entries.contains_key(&busy.join("new.txt")));
+        assert!(cache.entries.contains_key(&deep.join("leaf.txt")));
+        assert_eq!(cache.entries[&root].total_size, 10);
+
+        let forced = traverse_disk(&'C', &mut cache, &scan(&["--no-hash-skip"]))?;
+        assert_eq!((forced.dirs_listed, forced.dirs_reused), (6, 0));
+        // Other skip settings make every cached hash stale
+        let reskipped = traverse_disk(&'C', &mut cache, &scan(&["--skip", "busy"]))?;
+        assert_eq!(reskipped.dirs_reused, 0);
+
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
     
+    #[test]
+    fn test_full_rescan_drops_deleted_subtrees() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("ptree_sweep_{}", std::process::id()));
+        let cache_dir = root.with_extension("cache");
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&cache_dir);
+        let gone = root.join("gone");
+        fs::create_dir_all(gone.join("inner"))?;
+        fs::create_dir_all(root.join("kept"))?;
+        fs::write(gone.join("inner").join("old.txt"), "old")?;
+        fs::write(root.join("kept").join("file.txt"), "file")?;
+        for dir in [gone.join("inner"), gone.clone(), root.join("kept"), root.clone()] {
+            backdate(&dir)?;
+        }
+
+        let scan = || {
+            Args::parse_from([
+                "ptree",
+                "--no-progress",
+                "--cache-dir",
+                cache_dir.to_str().unwrap(),
+                "--cache-ttl",
+                "0",
+                root.to_str().unwrap(),
+            ])
+        };
+        let cache_path = ptree_cache::get_cache_path_custom(cache_dir.to_str(), None, None)?;
+        traverse_disk(&'C', &mut DiskCache::open(&cache_path)?, &scan())?;
+
+        fs::remove_dir_all(&gone)?;
+        let mut cache = DiskCache::open(&cache_path)?;
+        let info = traverse_disk(&'C', &mut cache, &scan())?;
+        assert_eq!(info.entries_removed, 3);
+        assert!(!cache.entries.keys().any(|path| path.starts_with(&gone)));
+        assert!(!cache.entries[&root].children.iter().any(|name| name == "gone"));
+        assert!(cache.entries.contains_key(&root.join("kept").join("file.txt")));
+
+        // Nor saved: compaction leaves only the surviving records in the data file
+        let stats = DiskCache::compact(&cache_path)?;
+        assert_eq!(stats.entries, 3);
+        let mut reopened = DiskCache::open(&cache_path)?;
+        reopened.load_all_entries_lazy(&cache_path)?;
+        assert!(!reopened.entries.keys().any(|path| path.starts_with(&gone)));
+        assert_eq!(reopened.entries.len(), 3);
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&cache_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unchanged_rescan_leaves_the_data_file_alone() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("ptree_clean_rescan_{}", std::process::id()));
+        let cache_dir = root.with_extension("cache");
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&cache_dir);
+        fs::create_dir_all(root.join("a").join("b"))?;
+        fs::write(root.join("a").join("file.txt"), "file")?;
+        for dir in [root.join("a").join("b"), root.join("a"), root.clone()] {
+            backdate(&dir)?;
+        }
+
+        let scan = |ttl: &str, extra: &[&str]| {
+            let mut argv = vec!["ptree", "--no-progress", "--cache-dir", cache_dir.to_str().unwrap(), "--cache-ttl", ttl, root.to_str().unwrap()];
+            argv.extend_from_slice(extra);
+            Args::parse_from(argv)
+        };
+        let cache_path = ptree_cache::get_cache_path_custom(cache_dir.to_str(), None, None)?;
+        let data_path = cache_path.with_extension("dat");
+        traverse_disk(&'C', &mut DiskCache::open(&cache_path)?, &scan("3600", &[]))?;
+        let data = fs::read(&data_path)?;
+
+        // Fresh: the early return neither traverses nor marks anything changed, so saving is a no-op
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert!(traverse_disk(&'C', &mut cache, &scan("3600", &[]))?.cache_used);
+        assert!(!cache.dirty);
+        cache.save(&cache_path)?;
+        assert_eq!(fs::read(&data_path)?, data);
+
+        // Stale but unchanged: rescanned, and only the index is rewritten
+        let mut cache = DiskCache::open(&cache_path)?;
+        let previous_scan = cache.last_scan;
+        assert!(!traverse_disk(&'C', &mut cache, &scan("0", &[]))?.cache_used);
+        assert!(!cache.dirty);
+        assert_eq!(fs::read(&data_path)?, data);
+        assert!(DiskCache::open(&cache_path)?.last_scan > previous_scan);
+
+        // Relisted, files and all: each keeps its own mtime, so nothing differs either
+        let mut cache = DiskCache::open(&cache_path)?;
+        cache.load_all_entries_lazy(&cache_path)?;
+        assert_eq!(traverse_disk(&'C', &mut cache, &scan("0", &["--no-hash-skip"]))?.dirs_reused, 0);
+        assert!(!cache.dirty);
+        assert_eq!(fs::read(&data_path)?, data);
+        let file = root.join("a").join("file.txt");
+        assert_eq!(cache.entries[&file].modified, DateTime::<Utc>::from(fs::metadata(&file)?.modified()?));
+
+        fs::write(root.join("a").join("new.txt"), "new")?;
+        traverse_disk(&'C', &mut DiskCache::open(&cache_path)?, &scan("0", &[]))?;
+        assert_ne!(fs::read(&data_path)?, data);
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&cache_dir);
+        Ok(())
+    }
+
     #[test]
     fn test_should_skip() {
         let mut skip = std::collections::HashSet::new();
@@ -0,0 +1,240 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use ptree_cache::{format_count, DirEntry, DiskCache};
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Stale paths kept in the report for display
+const MAX_EXAMPLES: usize = 20;
+
+// ============================================================================
+// Cache Verification (ptree verify)
+// ============================================================================
+
+/// How a cached entry compares with the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryStatus {
+    Fresh,
+    /// Gone, and its parent has no new sibling that could be its new name
+    Missing,
+    /// Gone, but its parent now holds an uncached entry of the same kind
+    Renamed,
+    /// Modified on disk after it was cached
+    Newer,
+}
+
+impl EntryStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            EntryStatus::Fresh => "fresh",
+            EntryStatus::Missing => "missing",
+            EntryStatus::Renamed => "renamed",
+            EntryStatus::Newer => "newer",
+        }
+    }
+}
+
+/// Outcome of comparing (a sample of) the cache with the disk
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Entries in the cache
+    pub cached: usize,
+    /// Entries compared with the disk
+    pub checked: usize,
+    pub missing: usize,
+    pub renamed: usize,
+    pub newer: usize,
+    /// Up to `MAX_EXAMPLES` stale entries, sorted by path
+    pub examples: Vec<(EntryStatus, PathBuf)>,
+}
+
+impl VerifyReport {
+    pub fn stale(&self) -> usize {
+        self.missing + self.renamed + self.newer
+    }
+
+    /// Share of checked entries that are stale, 0-100
+    pub fn stale_percent(&self) -> f64 {
+        if self.checked == 0 {
+            0.0
+        } else {
+            self.stale() as f64 * 100.0 / self.checked as f64
+        }
+    }
+
+    /// Human-readable summary, judged against `threshold` percent
+    pub fn to_text(&self, threshold: f64) -> String {
+        let mut out = format!(
+            "Checked {} of {} cached entries\n",
+            format_count(self.checked),
+            format_count(self.cached)
+        );
+        out.push_str(&format!("  {:<10}{}\n", "missing:", format_count(self.missing)));
+        out.push_str(&format!("  {:<10}{}\n", "renamed:", format_count(self.renamed)));
+        out.push_str(&format!("  {:<10}{}\n", "newer:", format_count(self.newer)));
+        out.push_str(&format!("Stale: {:.2}% (threshold {:.2}%)", self.stale_percent(), threshold));
+
+        if !self.examples.is_empty() {
+            out.push_str("\n\nStale entries:");
+            for (status, path) in &self.examples {
+                out.push_str(&format!("\n  {:<8} {}", status.label(), path.display()));
+            }
+            if self.examples.len() < self.stale() {
+                out.push_str(&format!("\n  ... and {} more", format_count(self.stale() - self.examples.len())));
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self, threshold: f64) -> Result<String> {
+        let examples: Vec<_> = self
+            .examples
+            .iter()
+            .map(|(status, path)| json!({ "status": status.label(), "path": path.to_string_lossy() }))
+            .collect();
+        Ok(serde_json::to_string_pretty(&json!({
+            "cached": self.cached,
+            "checked": self.checked,
+            "missing": self.missing,
+            "renamed": self.renamed,
+            "newer": self.newer,
+            "stale_percent": self.stale_percent(),
+            "threshold": threshold,
+            "stale": self.stale_percent() > threshold,
+            "examples": examples,
+        }))?)
+    }
+}
+
+/// Compare cached entries with the disk without modifying the cache
+///
+/// `sample` picks that many entries at random (`None` checks all of them);
+/// `seed` makes the sample reproducible. Entries are stat'ed in parallel on
+/// a pool of `threads` workers, as traversal does.
+pub fn verify_cache(cache: &DiskCache, sample: Option<usize>, seed: Option<u64>, threads: usize) -> Result<VerifyReport> {
+    let mut paths: Vec<&PathBuf> = cache.entries.keys().collect();
+    if let Some(n) = sample.filter(|&n| n < paths.len()) {
+        let mut rng = seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+        // Partial Fisher-Yates: the first n slots end up a uniform sample
+        for i in 0..n {
+            let j = rng.usize(i..paths.len());
+            paths.swap(i, j);
+        }
+        paths.truncate(n);
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+    let statuses: Vec<(EntryStatus, &PathBuf)> = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|&path| (check_entry(cache, &cache.entries[path]), path))
+            .collect()
+    });
+
+    let mut report = VerifyReport {
+        cached: cache.entries.len(),
+        checked: statuses.len(),
+        ..VerifyReport::default()
+    };
+    for (status, path) in statuses {
+        match status {
+            EntryStatus::Fresh => continue,
+            EntryStatus::Missing => report.missing += 1,
+            EntryStatus::Renamed => report.renamed += 1,
+            EntryStatus::Newer => report.newer += 1,
+        }
+        report.examples.push((status, path.clone()));
+    }
+    report.examples.sort_by(|a, b| a.1.cmp(&b.1));
+    report.examples.truncate(MAX_EXAMPLES);
+    Ok(report)
+}
+
+fn check_entry(cache: &DiskCache, entry: &DirEntry) -> EntryStatus {
+    match fs::symlink_metadata(&entry.path) {
+        Ok(metadata) => {
+            let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+            if modified.is_some_and(|modified| modified > entry.modified) {
+                EntryStatus::Newer
+            } else {
+                EntryStatus::Fresh
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            if has_uncached_sibling(cache, &entry.path, entry.is_dir) {
+                EntryStatus::Renamed
+            } else {
+                EntryStatus::Missing
+            }
+        }
+        // Unreadable is not evidence of staleness
+        Err(_) => EntryStatus::Fresh,
+    }
+}
+
+/// Whether the parent of a vanished entry now lists a name the cache doesn't know
+fn has_uncached_sibling(cache: &DiskCache, path: &Path, is_dir: bool) -> bool {
+    let Some(parent) = path.parent() else {
+        return false;
+    };
+    let Some(cached_parent) = cache.get_entry(parent) else {
+        return false;
+    };
+    let Ok(listing) = fs::read_dir(parent) else {
+        return false;
+    };
+
+    let known: HashSet<&str> = cached_parent.children.iter().collect();
+    listing.flatten().any(|child| {
+        let name = child.file_name();
+        let same_kind = child.file_type().is_ok_and(|kind| kind.is_dir() == is_dir);
+        same_kind && !known.contains(name.to_string_lossy().as_ref())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ptree_cache::fixtures::test_entry;
+
+    fn entry(path: &Path, children: &[&str], is_dir: bool, modified: DateTime<Utc>) -> DirEntry {
+        DirEntry { is_dir, modified, ..test_entry(path, children) }
+    }
+
+    #[test]
+    fn test_classifies_missing_renamed_and_newer() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("ptree_verify_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("kept"))?;
+        fs::create_dir_all(root.join("renamed_to"))?;
+        fs::write(root.join("touched.txt"), b"")?;
+
+        let future = Utc::now() + chrono::Duration::days(1);
+        let past = Utc::now() - chrono::Duration::days(1);
+        let mut cache = DiskCache::new();
+        for (path, children, is_dir, modified) in [
+            (root.clone(), vec!["kept", "renamed_from", "touched.txt", "deleted.txt"], true, future),
+            (root.join("kept"), vec![], true, future),
+            (root.join("renamed_from"), vec![], true, future),
+            (root.join("touched.txt"), vec![], false, past),
+            (root.join("deleted.txt"), vec![], false, future),
+        ] {
+            cache.entries.insert(path.clone(), entry(&path, &children, is_dir, modified));
+        }
+
+        let report = verify_cache(&cache, None, None, 2)?;
+        assert_eq!((report.checked, report.missing, report.renamed, report.newer), (5, 1, 1, 1));
+        assert_eq!(report.stale_percent(), 60.0);
+        assert_eq!(report.examples[0], (EntryStatus::Missing, root.join("deleted.txt")));
+
+        let sampled = verify_cache(&cache, Some(2), Some(7), 2)?;
+        assert_eq!((sampled.cached, sampled.checked), (5, 2));
+
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+// ============================================================================
+// Volume Identity (--one-file-system)
+// ============================================================================
+
+/// Identifier of the volume (file system) holding `path`
+///
+/// Two paths are on the same volume exactly when their identifiers are
+/// equal. Unix uses the device number; Windows the GUID name of the volume
+/// behind the path's mount point, so folders with a VHD or another disk
+/// mounted into them get a different identifier than their parent. None
+/// when it can't be determined, which callers treat as "same volume".
+pub fn volume_id(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::symlink_metadata(path).ok().map(|metadata| metadata.dev())
+    }
+    #[cfg(windows)]
+    {
+        windows_volume_id(path)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Whether `path` lies on another volume than `root_volume` (never, if either is unknown)
+pub fn crosses_volume(path: &Path, root_volume: Option<u64>) -> bool {
+    match (root_volume, volume_id(path)) {
+        (Some(root), Some(volume)) => root != volume,
+        _ => false,
+    }
+}
+
+#[cfg(windows)]
+fn windows_volume_id(path: &Path) -> Option<u64> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::shared::minwindef::{DWORD, FALSE};
+    use winapi::um::fileapi::{GetVolumeNameForVolumeMountPointW, GetVolumePathNameW};
+
+    let path_w: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut mount_point = [0u16; 261];
+    // SAFETY: `path_w` is NUL-terminated and `mount_point` holds MAX_PATH + 1 units
+    if unsafe { GetVolumePathNameW(path_w.as_ptr(), mount_point.as_mut_ptr(), mount_point.len() as DWORD) } == FALSE {
+        return None;
+    }
+
+    // `\\?\Volume{GUID}\`: 49 characters plus the terminator
+    let mut volume_name = [0u16; 50];
+    // SAFETY: `mount_point` was NUL-terminated by GetVolumePathNameW
+    let ok = unsafe {
+        GetVolumeNameForVolumeMountPointW(mount_point.as_ptr(), volume_name.as_mut_ptr(), volume_name.len() as DWORD)
+    };
+    // Network shares have no volume GUID; the mount point path identifies them
+    let name = if ok == FALSE { &mount_point[..] } else { &volume_name[..] };
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+    let mut hasher = DefaultHasher::new();
+    name[..len].hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_on_one_volume_match() {
+        let root = std::env::temp_dir();
+        let child = root.join(format!("ptree_volume_{}", std::process::id()));
+        std::fs::create_dir_all(&child).unwrap();
+
+        assert_eq!(volume_id(&root), volume_id(&child));
+        assert!(!crosses_volume(&child, volume_id(&root)));
+        // Unknown on either side never counts as a boundary
+        assert!(!crosses_volume(&child, None));
+        assert!(!crosses_volume(&root.join("does/not/exist"), Some(0)));
+
+        std::fs::remove_dir_all(&child).unwrap();
+    }
+}
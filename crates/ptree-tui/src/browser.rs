@@ -0,0 +1,446 @@
+use anyhow::Result;
+use ptree_cache::{DirEntry, DiskCache};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::prelude::CrosstermBackend;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Paragraph;
+use ratatui::{Frame, Terminal};
+use std::collections::HashSet;
+use std::io::{self, Stderr};
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Browser State (no terminal or filesystem access)
+// ============================================================================
+
+/// Kind of entry, for styling rows like the colored tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowKind {
+    Directory,
+    File,
+    Hidden,
+    Symlink,
+}
+
+/// One visible line of the browser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub path: PathBuf,
+    /// Name in tree mode, path relative to the root in filter mode
+    pub label: String,
+    pub depth: usize,
+    pub kind: RowKind,
+    pub has_children: bool,
+    pub symlink_target: Option<PathBuf>,
+}
+
+/// Interactive tree over cached entries
+///
+/// Only expanded directories contribute rows, so a huge cache costs nothing
+/// until it is opened up, and drawing touches just the rows on screen. The
+/// `/` filter scans the cache once per query and lists matches flat.
+pub struct Browser<'a> {
+    cache: &'a DiskCache,
+    root: PathBuf,
+    rows: Vec<Row>,
+    expanded: HashSet<PathBuf>,
+    selected: usize,
+    offset: usize,
+    /// Text being typed after `/`
+    input: Option<String>,
+    /// Matches for the applied filter; replaces the tree while set
+    filter: Option<(String, Vec<Row>)>,
+}
+
+impl<'a> Browser<'a> {
+    pub fn new(cache: &'a DiskCache, root: &Path) -> Self {
+        let mut browser = Browser {
+            cache,
+            root: root.to_path_buf(),
+            rows: Vec::new(),
+            expanded: HashSet::new(),
+            selected: 0,
+            offset: 0,
+            input: None,
+            filter: None,
+        };
+        browser.rows.push(browser.row_for(root, root.display().to_string(), 0));
+        browser.expand(0);
+        browser
+    }
+
+    /// Rows currently listed (the filter results while a filter is applied)
+    pub fn rows(&self) -> &[Row] {
+        match &self.filter {
+            Some((_, matches)) => matches,
+            None => &self.rows,
+        }
+    }
+
+    pub fn selected_row(&self) -> Option<&Row> {
+        self.rows().get(self.selected)
+    }
+
+    pub fn move_by(&mut self, delta: isize) {
+        let last = self.rows().len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    pub fn move_to(&mut self, index: usize) {
+        self.selected = index.min(self.rows().len().saturating_sub(1));
+    }
+
+    /// Expand the selected directory, or step into it if already expanded
+    pub fn expand_selected(&mut self) {
+        if self.filter.is_some() {
+            return;
+        }
+        let index = self.selected;
+        let Some(row) = self.rows.get(index) else {
+            return;
+        };
+        if !row.has_children {
+            return;
+        }
+        if self.expanded.contains(&row.path) {
+            self.move_by(1);
+        } else {
+            self.expand(index);
+        }
+    }
+
+    /// Collapse the selected directory, or jump to its parent
+    pub fn collapse_selected(&mut self) {
+        if self.filter.is_some() {
+            return;
+        }
+        let index = self.selected;
+        let Some(row) = self.rows.get(index) else {
+            return;
+        };
+        if self.expanded.contains(&row.path) {
+            self.collapse(index);
+        } else if let Some(parent) = (0..index).rev().find(|&i| self.rows[i].depth < row.depth) {
+            self.selected = parent;
+        }
+    }
+
+    pub fn toggle_selected(&mut self) {
+        let expanded = self.selected_row().is_some_and(|row| self.expanded.contains(&row.path));
+        if expanded {
+            self.collapse_selected();
+        } else {
+            self.expand_selected();
+        }
+    }
+
+    /// List every cached entry under the root whose name contains `query` (case-insensitive)
+    pub fn apply_filter(&mut self, query: &str) {
+        if query.is_empty() {
+            self.clear_filter();
+            return;
+        }
+        let needle = query.to_lowercase();
+        let mut matches: Vec<Row> = self
+            .cache
+            .entries
+            .values()
+            .filter(|entry| entry.path != self.root && entry.path.starts_with(&self.root))
+            .filter(|entry| entry.name.to_lowercase().contains(&needle))
+            .map(|entry| {
+                let relative = entry.path.strip_prefix(&self.root).unwrap_or(&entry.path);
+                self.row_for(&entry.path, relative.display().to_string(), 0)
+            })
+            .collect();
+        matches.sort_by(|a, b| a.label.cmp(&b.label));
+
+        self.filter = Some((query.to_string(), matches));
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    pub fn clear_filter(&mut self) {
+        if let Some((_, matches)) = self.filter.take() {
+            // Keep the cursor on the same entry if it is visible in the tree
+            let chosen = matches.get(self.selected).map(|row| row.path.clone());
+            self.selected = chosen
+                .and_then(|path| self.rows.iter().position(|row| row.path == path))
+                .unwrap_or(0);
+        }
+    }
+
+    /// Keep the selection inside a window of `height` rows; returns the first row to draw
+    pub fn scroll_into_view(&mut self, height: usize) -> usize {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if height > 0 && self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+        self.offset
+    }
+
+    fn expand(&mut self, index: usize) {
+        let row = &self.rows[index];
+        let Some(entry) = self.cache.get_entry(&row.path) else {
+            return;
+        };
+        let depth = row.depth + 1;
+        let parent = row.path.clone();
+        let children: Vec<Row> = self
+            .cache
+            .sorted_children(entry)
+            .into_iter()
+            .map(|name| self.row_for(&parent.join(name), name.to_string(), depth))
+            .collect();
+
+        self.expanded.insert(parent);
+        self.rows.splice(index + 1..index + 1, children);
+    }
+
+    fn collapse(&mut self, index: usize) {
+        let depth = self.rows[index].depth;
+        let end = (index + 1..self.rows.len())
+            .find(|&i| self.rows[i].depth <= depth)
+            .unwrap_or(self.rows.len());
+        for row in self.rows.drain(index + 1..end) {
+            self.expanded.remove(&row.path);
+        }
+        self.expanded.remove(&self.rows[index].path);
+    }
+
+    fn row_for(&self, path: &Path, label: String, depth: usize) -> Row {
+        let entry = self.cache.get_entry(path);
+        Row {
+            path: path.to_path_buf(),
+            label,
+            depth,
+            kind: row_kind(entry),
+            has_children: entry.is_some_and(|e| !self.cache.sorted_children(e).is_empty()),
+            symlink_target: entry.and_then(|e| e.symlink_target.clone()),
+        }
+    }
+}
+
+fn row_kind(entry: Option<&DirEntry>) -> RowKind {
+    match entry {
+        Some(e) if e.symlink_target.is_some() => RowKind::Symlink,
+        Some(e) if e.is_hidden => RowKind::Hidden,
+        Some(e) if e.is_dir => RowKind::Directory,
+        _ => RowKind::File,
+    }
+}
+
+// ============================================================================
+// Terminal Loop
+// ============================================================================
+
+/// Browse `root` in a full-screen UI drawn on stderr
+///
+/// Returns the path chosen with Enter, or None if the user quit. The UI uses
+/// stderr so stdout stays free for the result (e.g. `cd "$(ptree browse)"`).
+pub fn browse(cache: &DiskCache, root: &Path) -> Result<Option<PathBuf>> {
+    let mut browser = Browser::new(cache, root);
+
+    terminal::enable_raw_mode()?;
+    execute!(io::stderr(), EnterAlternateScreen)?;
+    let result = Terminal::new(CrosstermBackend::new(io::stderr()))
+        .map_err(anyhow::Error::from)
+        .and_then(|mut terminal| run(&mut terminal, &mut browser));
+
+    // Always restore the terminal, even if drawing failed
+    let _ = execute!(io::stderr(), LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+    result
+}
+
+fn run(terminal: &mut Terminal<CrosstermBackend<Stderr>>, browser: &mut Browser) -> Result<Option<PathBuf>> {
+    loop {
+        terminal.draw(|frame| draw(frame, browser))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let page = terminal.size()?.height.saturating_sub(2).max(1) as isize;
+        if let Some(outcome) = handle_key(browser, key, page) {
+            return Ok(outcome);
+        }
+    }
+}
+
+/// Apply one key press; `Some` ends the session with the chosen path (if any)
+fn handle_key(browser: &mut Browser, key: KeyEvent, page: isize) -> Option<Option<PathBuf>> {
+    if let Some(input) = &mut browser.input {
+        match key.code {
+            KeyCode::Enter => {
+                let query = std::mem::take(input);
+                browser.input = None;
+                browser.apply_filter(&query);
+            }
+            KeyCode::Esc => browser.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+        return None;
+    }
+
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Some(None),
+        KeyCode::Char('q') => return Some(None),
+        KeyCode::Esc if browser.filter.is_some() => browser.clear_filter(),
+        KeyCode::Esc => return Some(None),
+        KeyCode::Enter => return Some(browser.selected_row().map(|row| row.path.clone())),
+        KeyCode::Char('/') => browser.input = Some(String::new()),
+        KeyCode::Up | KeyCode::Char('k') => browser.move_by(-1),
+        KeyCode::Down | KeyCode::Char('j') => browser.move_by(1),
+        KeyCode::PageUp => browser.move_by(-page),
+        KeyCode::PageDown => browser.move_by(page),
+        KeyCode::Home | KeyCode::Char('g') => browser.move_to(0),
+        KeyCode::End | KeyCode::Char('G') => browser.move_to(usize::MAX),
+        KeyCode::Right | KeyCode::Char('l') => browser.expand_selected(),
+        KeyCode::Left | KeyCode::Char('h') => browser.collapse_selected(),
+        KeyCode::Char(' ') => browser.toggle_selected(),
+        _ => {}
+    }
+    None
+}
+
+fn draw(frame: &mut Frame, browser: &mut Browser) {
+    let [body, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+    let height = body.height as usize;
+    let offset = browser.scroll_into_view(height);
+    let selected = browser.selected;
+    let filtering = browser.filter.is_some();
+
+    // Only the rows that fit on screen are turned into text
+    let lines: Vec<Line> = browser
+        .rows()
+        .iter()
+        .enumerate()
+        .skip(offset)
+        .take(height)
+        .map(|(index, row)| {
+            let marker = match (filtering, row.has_children, browser.expanded.contains(&row.path)) {
+                (true, _, _) | (false, false, _) => "  ",
+                (false, true, true) => "▾ ",
+                (false, true, false) => "▸ ",
+            };
+            let mut spans = vec![
+                Span::raw("  ".repeat(row.depth)),
+                Span::raw(marker),
+                Span::styled(row.label.clone(), row_style(row, index == 0 && !filtering)),
+            ];
+            if let Some(target) = &row.symlink_target {
+                spans.push(Span::styled(format!(" → {}", target.display()), Style::new().fg(Color::DarkGray)));
+            }
+            let line = Line::from(spans);
+            if index == selected {
+                line.style(Style::new().add_modifier(Modifier::REVERSED))
+            } else {
+                line
+            }
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), body);
+
+    let status_text = match (&browser.input, &browser.filter) {
+        (Some(input), _) => format!("/{}", input),
+        (None, Some((query, matches))) => {
+            format!("filter \"{}\": {} matches  (esc: back to tree, enter: select)", query, matches.len())
+        }
+        (None, None) => "arrows: move/expand  /: filter  enter: select  q: quit".to_string(),
+    };
+    frame.render_widget(Paragraph::new(status_text).style(Style::new().add_modifier(Modifier::DIM)), status);
+}
+
+/// Colors follow the colored tree defaults (root bold blue, directories bright blue, ...)
+fn row_style(row: &Row, is_root: bool) -> Style {
+    if is_root {
+        return Style::new().fg(Color::Blue).add_modifier(Modifier::BOLD);
+    }
+    match row.kind {
+        RowKind::Directory => Style::new().fg(Color::LightBlue),
+        RowKind::Symlink => Style::new().fg(Color::Cyan),
+        RowKind::Hidden => Style::new().fg(Color::DarkGray),
+        RowKind::File => Style::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ptree_cache::fixtures::test_entry;
+
+    fn entry(path: &Path, children: &[&str], is_dir: bool) -> DirEntry {
+        DirEntry { is_dir, ..test_entry(path, children) }
+    }
+
+    fn cache() -> DiskCache {
+        let root = PathBuf::from("/scan");
+        let mut cache = DiskCache::new();
+        cache.root = root.clone();
+        cache.entries.insert(root.clone(), entry(&root, &["src", "README.md"], true));
+        cache.entries.insert(root.join("src"), entry(&root.join("src"), &["main.rs", "lib.rs"], true));
+        cache.entries.insert(root.join("src/main.rs"), entry(&root.join("src/main.rs"), &[], false));
+        cache.entries.insert(root.join("src/lib.rs"), entry(&root.join("src/lib.rs"), &[], false));
+        cache.entries.insert(root.join("README.md"), entry(&root.join("README.md"), &[], false));
+        cache
+    }
+
+    fn labels(browser: &Browser) -> Vec<String> {
+        browser.rows().iter().map(|row| row.label.clone()).collect()
+    }
+
+    #[test]
+    fn test_expand_and_collapse_only_touch_visible_rows() {
+        let cache = cache();
+        let mut browser = Browser::new(&cache, Path::new("/scan"));
+        assert_eq!(labels(&browser), ["/scan", "README.md", "src"]);
+
+        browser.move_to(2);
+        browser.expand_selected();
+        assert_eq!(labels(&browser), ["/scan", "README.md", "src", "lib.rs", "main.rs"]);
+        assert_eq!(browser.rows()[3].depth, 2);
+
+        // Left on a child jumps to its parent, then collapses it
+        browser.move_to(4);
+        browser.collapse_selected();
+        assert_eq!(browser.selected_row().unwrap().label, "src");
+        browser.collapse_selected();
+        assert_eq!(labels(&browser), ["/scan", "README.md", "src"]);
+    }
+
+    #[test]
+    fn test_filter_lists_matches_and_restores_tree() {
+        let cache = cache();
+        let mut browser = Browser::new(&cache, Path::new("/scan"));
+
+        browser.apply_filter("RS");
+        assert_eq!(labels(&browser), ["src/lib.rs", "src/main.rs"]);
+        browser.move_by(1);
+        assert_eq!(browser.selected_row().unwrap().path, PathBuf::from("/scan/src/main.rs"));
+
+        browser.clear_filter();
+        assert_eq!(labels(&browser), ["/scan", "README.md", "src"]);
+    }
+
+    #[test]
+    fn test_scroll_keeps_selection_on_screen() {
+        let cache = cache();
+        let mut browser = Browser::new(&cache, Path::new("/scan"));
+        browser.move_to(2);
+        assert_eq!(browser.scroll_into_view(2), 1);
+        browser.move_to(0);
+        assert_eq!(browser.scroll_into_view(2), 0);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod browser;
+
+pub use browser::{browse, Browser};
@@ -1,19 +1,38 @@
 use anyhow::Result;
-use ptree_core::{OutputFormat, ColorMode};
-use ptree_cache::DiskCache;
-use ptree_traversal::traverse_disk;
+use ptree_core::{exit_code, resolve_color, CacheBackend, Command, OutputFormat, OutputSink, PTreeError};
+use ptree_cache::cache_rkyv::RkyvMmapCache;
+use ptree_cache::{DiskCache, LazyDiskCache};
+use ptree_traversal::{scan_disk, traverse_disk};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Instant;
 
 #[cfg(feature = "scheduler")]
 use ptree_scheduler as scheduler;
 
-#[cfg(feature = "incremental")]
-use ptree_incremental as incremental;
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            // Errors without a PTreeError inside are plain failures
+            let code = err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<PTreeError>())
+                .map_or(exit_code::FAILURE, PTreeError::exit_code);
+            ExitCode::from(code)
+        }
+    }
+}
 
-fn main() -> Result<()> {
+/// Run ptree and return the exit code for a run that produced its output
+fn run() -> Result<u8> {
     let program_start = Instant::now();
 
-    let args = ptree_core::parse_args();
+    let mut args = ptree_core::parse_args()?;
+    init_logging(&args);
 
     // ========================================================================
     // Handle Scheduler Commands (Early Exit)
@@ -23,85 +42,328 @@ fn main() -> Result<()> {
     {
         if args.scheduler {
             scheduler::install_scheduler()?;
-            return Ok(());
+            return Ok(exit_code::SUCCESS);
         }
 
         if args.scheduler_uninstall {
             scheduler::uninstall_scheduler()?;
-            return Ok(());
+            return Ok(exit_code::SUCCESS);
         }
 
         if args.scheduler_status {
             scheduler::check_scheduler_status()?;
-            return Ok(());
+            return Ok(exit_code::SUCCESS);
         }
     }
 
+    // ========================================================================
+    // Handle Subcommands (Cache Only, No Traversal)
+    // ========================================================================
+
+    match args.command.clone() {
+        Some(Command::Browse { path }) => return browse(&args, path.as_deref()),
+        Some(Command::Search { pattern, mode, limit, subtree, json, refresh }) => {
+            // A refresh rescans even when the cache is within its TTL
+            if refresh {
+                args.cache_ttl = Some(0);
+            }
+            let limit = limit.filter(|&limit| limit > 0);
+            return search(&args, &pattern, mode, limit, subtree.as_deref(), json, refresh);
+        }
+        Some(Command::Stats { top, subtree, json }) => return stats(&args, top, subtree.as_deref(), json),
+        Some(Command::CacheInfo { json }) => return cache_info(&args, json),
+        Some(Command::MigrateCache) => return migrate_cache(&args),
+        Some(Command::Clean { drive, compact, gc }) => return clean(&args, drive, compact, gc),
+        Some(Command::Verify { sample, all, threshold, seed, json }) => {
+            return verify(&args, (!all).then_some(sample), threshold, seed, json);
+        }
+        Some(Command::Export { file }) => return export(&args, &file),
+        Some(Command::Import { file, replace }) => return import(&args, &file, replace),
+        Some(Command::Diff { old, new, live, subtree, json }) => {
+            if live {
+                args.cache_ttl = Some(0);
+            }
+            return diff(&args, old.zip(new), subtree.as_deref(), json);
+        }
+        Some(Command::Watch { drive, interval, json }) => return watch(&args, drive, interval, json),
+        None => {}
+    }
+
+    // Validate templates and thresholds before any scanning so a typo fails fast
+    let entry_format = args
+        .entry_format
+        .as_deref()
+        .map(ptree_cache::EntryTemplate::parse)
+        .transpose()?;
+    let heatmap = args
+        .heatmap
+        .then(|| ptree_cache::HeatmapThresholds::from_hours(args.heatmap_hot_hours, args.heatmap_warm_hours))
+        .transpose()?;
+
     // ========================================================================
     // Determine Color Output Settings
     // ========================================================================
 
-    let use_colors = match args.color {
-        ColorMode::Auto => atty::is(atty::Stream::Stdout),
-        ColorMode::Always => true,
-        ColorMode::Never => false,
-    };
+    // Files never get ANSI codes unless explicitly forced
+    let is_tty = args.output.is_none() && std::io::stdout().is_terminal();
+    let use_colors = resolve_color(args.color, |name| std::env::var(name).ok(), is_tty);
 
     // ========================================================================
     // Load or Create Cache
     // ========================================================================
 
-    let cache_path = ptree_cache::get_cache_path()?;
+    // --no-cache never resolves or touches the on-disk cache
+    let cache_path = if args.no_cache {
+        None
+    } else {
+        Some(ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?)
+    };
+    if let Some(path) = &cache_path {
+        log::debug!("cache path: {}", path.display());
+    }
     let cache_load_start = Instant::now();
-    let mut cache = DiskCache::open(&cache_path)?;
+    if let (true, Some(path)) = (args.verify_cache, &cache_path) {
+        verify_cache_files(path)?;
+    }
+    let mut cache = match &cache_path {
+        Some(path) => open_cache(path, args.strict_cache)?,
+        None => DiskCache::new(),
+    };
+    // A new --backend has the cache saved through it even when nothing is rescanned
+    let switch_backend = args.backend.filter(|&backend| backend != cache.backend && !args.read_only);
+    if let (Some(backend), Some(path)) = (switch_backend, &cache_path) {
+        cache.set_backend(backend, path).map_err(|e| cache_error(path, e))?;
+    }
+    cache.compress |= args.compress_cache;
+    cache.encrypt |= args.encrypt_cache;
+    cache.max_bytes = args.max_cache_bytes.map(|size| size.0);
+    if let (Some(rate), Some(path)) = (args.bloom_fp_rate, &cache_path) {
+        cache.set_bloom_fp_rate(rate.0, path).map_err(|e| cache_error(path, e))?;
+    }
+    if let (Some(threshold), Some(path)) = (args.spill_children, &cache_path) {
+        cache.set_spill_children(threshold, path).map_err(|e| cache_error(path, e))?;
+    }
+    if let (true, Some(path)) = (args.verify_cache, &cache_path) {
+        load_all_entries(&mut cache, path, args.strict_cache)?;
+        eprintln!("{}", cache.validate().to_text());
+    }
+
+    // --drive picks scan roots only with --force and no PATH; otherwise one root is scanned
+    let drives = if args.force && args.path.is_none() {
+        args.drives()
+    } else {
+        args.drives()[..1].to_vec()
+    };
+
+    // A fresh cache needs no traversal: printed as a tree it can be rendered
+    // straight from the data file, any other output reads only what it reaches
+    let fresh = match &cache_path {
+        Some(path) if reads_lazily(&args) && ptree_cache::stored_backend(path) == Some(CacheBackend::Rkyv) && ptree_traversal::is_cache_fresh(&drives[0], &cache, &args)? => Some(path.clone()),
+        _ => None,
+    };
+    if fresh.is_some() {
+        log::info!("cache for {} is fresh; skipping traversal", cache.root.display());
+    }
+    let (stream_from, lazy_from) = match fresh {
+        Some(path) if streams_tree(&args) => (Some(path), None),
+        fresh => (None, fresh),
+    };
+
+    // --read-only needs the real entries up front so traversal sees a warm cache
+    if args.read_only && stream_from.is_none() && lazy_from.is_none() {
+        if let Some(path) = &cache_path {
+            load_all_entries(&mut cache, path, args.strict_cache)?;
+        }
+    }
     let cache_load_elapsed = cache_load_start.elapsed();
 
     // ========================================================================
     // Traverse Disk & Update Cache
     // ========================================================================
 
-    let debug_info = traverse_disk(&args.drive, &mut cache, &args)?;
+    // --changes needs the previous scan's entries before traversal replaces them
+    let snapshot = if args.changes {
+        if let (true, Some(path)) = (cache.entries.is_empty(), &cache_path) {
+            load_all_entries(&mut cache, path, args.strict_cache)?;
+        }
+        Some(cache.snapshot())
+    } else {
+        None
+    };
+
+    // Saved once the output is under way (see below), not after each drive
+    let mut debug_infos = Vec::with_capacity(drives.len());
+    if stream_from.is_none() && lazy_from.is_none() {
+        for drive in &drives {
+            debug_infos.push(scan_disk(drive, &mut cache, &args)?);
+        }
+    }
+    let mut rescanned = debug_infos.iter().any(|info| !info.cache_used);
+
+    if let Some(snapshot) = &snapshot {
+        cache.output_options.changes = Some(cache.changes_since(snapshot));
+    }
 
     // ========================================================================
     // Output Results (with lazy-loading for cold-start)
     // ========================================================================
 
-    cache.show_hidden = args.hidden;
-    
-    let lazy_load_start = Instant::now();
-    if cache.entries.is_empty() {
-        let _ = cache.load_all_entries_lazy(&cache_path);
+    configure_view(&mut cache, &args)?;
+    cache.output_options.mtime = args.mtime.then_some(args.mtime_format);
+    cache.output_options.show_counts = args.counts;
+    cache.output_options.du = args.du;
+    cache.output_options.size = args.size;
+    cache.output_options.json_metadata = args.json_metadata;
+    cache.output_options.check_symlinks = args.check_symlinks;
+    cache.output_options.heatmap = heatmap;
+    cache.output_options.show_report = !args.no_report;
+    cache.output_options.file_limit = args.file_limit.filter(|&limit| limit > 0);
+    // An explicit --filelimit, 0 included, decides for spilled directories too
+    cache.output_options.spill_limit = (args.file_limit.is_none() && cache.spill_children > 0).then_some(cache.spill_children);
+    cache.output_options.line_limit = args.limit.filter(|&limit| limit > 0);
+    cache.output_options.align_window = args.align_window;
+    cache.output_options.entry_format = entry_format;
+    cache.output_options.full_path = args.full_path;
+    cache.output_options.no_indent = args.no_indent;
+    cache.output_options.charset = args.charset;
+
+    // Options that need the whole tree in memory fall back to loading it
+    let stream_from = stream_from.filter(|_| cache.can_stream_tree());
+    let lazy_from = lazy_from.filter(|_| cache.can_stream_tree());
+    if let Some(path) = &lazy_from {
+        let mut lazy = LazyDiskCache::open(std::mem::take(&mut cache), path).map_err(|e| cache_error(path, e))?;
+        lazy.load_view(args.max_depth).map_err(|e| cache_error(path, e))?;
+        log::info!("read {} entries of {} for the output", lazy.deserialized(), path.display());
+        cache = lazy.into_cache();
+    } else if cache.entries.is_empty() && stream_from.is_none() {
+        if let Some(path) = &cache_path {
+            // A --subtree of a sharded cache only reads its own shard
+            let subtree = args.subtree.as_deref().map(std::path::absolute).transpose()?;
+            let loaded = match subtree {
+                Some(subtree) => cache.load_subtree_lazy(path, &subtree).map_err(|e| cache_error(path, e))?,
+                None => false,
+            };
+            if !loaded {
+                cache.load_all_entries_lazy(path).map_err(|e| cache_error(path, e))?;
+            }
+        }
     }
-    let lazy_load_elapsed = lazy_load_start.elapsed();
 
+    // A --subtree inside a region evicted by --max-cache-bytes is rescanned first
+    if let (Some(subtree), false) = (&args.subtree, args.read_only) {
+        if let Some(evicted) = cache.evicted_ancestor(&std::path::absolute(subtree)?) {
+            log::info!("{} was evicted from the cache; rescanning it", evicted.display());
+            // The rescan is saved, so the rest of the cache has to be loaded too;
+            // saving it over a partial load would drop what failed to load
+            if let Some(path) = &cache_path {
+                cache.load_all_entries_lazy(path).map_err(|e| cache_error(path, e))?;
+            }
+            let previous = (args.path.replace(evicted), args.cache_ttl.replace(0), std::mem::take(&mut args.force));
+            scan_disk(&drives[0], &mut cache, &args)?;
+            (args.path, args.cache_ttl, args.force) = previous;
+            rescanned = true;
+        }
+    }
+
+    // Each scanned drive renders as its own top-level root
+    let roots: Vec<PathBuf> = if let Some(subtree) = &args.subtree {
+        match cache.find_cached_path(subtree) {
+            Some(path) => vec![path],
+            None => {
+                return Err(PTreeError::Cache(format!(
+                    "{} is not in the cache; run with --force to rescan",
+                    subtree.display()
+                ))
+                .into());
+            }
+        }
+    } else if debug_infos.is_empty() {
+        vec![cache.root.clone()]
+    } else {
+        debug_infos.iter().map(|info| info.scan_root.clone()).collect()
+    };
+
+    // ========================================================================
+    // Save in the Background
+    // ========================================================================
+
+    // The output renders from the cache while it is saved. Several roots
+    // retarget the cache between renders, so their save starts after them.
+    let save_path = cache_path.clone().filter(|_| (rescanned || switch_backend.is_some()) && !args.read_only);
+    if save_path.is_some() {
+        cache.prepare_save();
+    }
+    if let [root] = roots.as_slice() {
+        cache.output_options.set_display_root(Some(root.clone()));
+    }
+    let mut cache = Arc::new(cache);
+    let mut save = save_path.as_deref().filter(|_| roots.len() == 1).map(|path| start_save(&cache, path));
+
+    let mut sink = if !args.quiet {
+        Some(OutputSink::open(args.output.as_deref())?)
+    } else {
+        None
+    };
+
+    log::debug!("rendering {} root(s) as {:?}", roots.len(), args.format);
     let formatting_start = Instant::now();
-    let output = if !args.quiet {
-        Some(match args.format {
-            OutputFormat::Tree => {
-                if use_colors {
-                    cache.build_colored_tree_output_with_depth(args.max_depth)?
-                } else {
-                    cache.build_tree_output_with_depth(args.max_depth)?
+    let output = if let Some(sink) = sink.as_mut() {
+        if let Some(path) = &stream_from {
+            let theme = use_colors.then(ptree_cache::Theme::from_env);
+            cache
+                .write_cached_tree(path, sink, args.max_depth, theme.as_ref())
+                .map_err(|e| cache_error(path, e))?;
+            // Already written; only the blank line that ends every rendering is left
+            Some(String::new())
+        } else if matches!(args.format, OutputFormat::Json) && roots.len() > 1 {
+            // One document holding an array of roots
+            let cache = Arc::get_mut(&mut cache).expect("several roots are saved after rendering");
+            Some(cache.build_json_roots_output(&roots, args.max_depth)?)
+        } else {
+            // Other formats print each root in turn
+            let mut sections = Vec::with_capacity(roots.len());
+            for root in &roots {
+                // Only unshared with several roots; a single one is already set
+                if let Some(cache) = Arc::get_mut(&mut cache) {
+                    cache.output_options.set_display_root(Some(root.clone()));
+                }
+                if let Some(section) = render_output(&cache, &args, sink, use_colors)? {
+                    sections.push(section);
                 }
             }
-            OutputFormat::Json => cache.build_json_output_with_depth(args.max_depth)?,
-        })
+            (!sections.is_empty()).then(|| sections.join("\n"))
+        }
     } else {
         None
     };
     let formatting_elapsed = formatting_start.elapsed();
     
     let output_start = Instant::now();
-    if let Some(output) = output {
-        println!("{}", output);
+    if let Some(mut sink) = sink {
+        if let Some(output) = output {
+            writeln!(sink, "{}", output)?;
+        }
+        sink.finish()?;
     }
     let output_elapsed = output_start.elapsed();
 
+    // The output is out; a failed save still fails the run
+    if let Some(path) = &save_path {
+        let save = save.take().unwrap_or_else(|| start_save(&cache, path));
+        let save_time = save
+            .wait()
+            .map_err(|e| PTreeError::Cache(format!("failed to save {}: {:#}", path.display(), e)))?;
+        if let Some(info) = debug_infos.last_mut() {
+            info.save_time = save_time;
+        }
+    }
+
     // ========================================================================
     // Skip Statistics (if requested)
     // ========================================================================
 
-    if args.skip_stats {
+    // --stats already includes the skip report
+    if args.skip_stats && !args.stats {
         eprintln!("{}", cache.get_skip_report());
     }
 
@@ -111,12 +373,594 @@ fn main() -> Result<()> {
 
     if args.stats {
         let total_elapsed = program_start.elapsed();
-        print_debug_summary(&debug_info, cache_load_elapsed, formatting_elapsed, output_elapsed, &cache_path, total_elapsed);
+        print_debug_summary(&debug_infos, cache_load_elapsed, formatting_elapsed, output_elapsed, cache_path.as_deref(), &cache.load, total_elapsed);
+        eprintln!("{}", cache.get_skip_report());
+        eprintln!("{}", cache.get_spill_report());
+    }
+
+    let access_denied: usize = debug_infos.iter().map(|info| info.access_denied).sum();
+    if access_denied > 0 {
+        if !args.quiet {
+            eprintln!(
+            "ptree: {} directories could not be read (permission denied)",
+                format_number(access_denied)
+            );
+        }
+        return Ok(exit_code::ACCESS_DENIED);
     }
 
+    Ok(exit_code::SUCCESS)
+}
+
+/// Save `cache` on a background thread, cancelling it cleanly on Ctrl+C
+///
+/// The handler waits for the cancelled save to clean up, leaving the
+/// previous cache files in place, before exiting.
+fn start_save(cache: &Arc<DiskCache>, path: &Path) -> ptree_cache::BackgroundSave {
+    let save = DiskCache::save_in_background(cache, path.to_path_buf());
+    let canceller = save.canceller();
+    if let Err(e) = ctrlc::set_handler(move || {
+        canceller.cancel();
+        std::process::exit(exit_code::INTERRUPTED.into());
+    }) {
+        log::debug!("cannot handle Ctrl+C during the save: {}", e);
+    }
+    save
+}
+
+/// Whether a fresh cache's output could be read lazily (`LazyDiskCache`), never loading every entry
+///
+/// Only output of the scan root qualifies; `--stats` and `--changes` want
+/// the entries loaded anyway.
+fn reads_lazily(args: &ptree_core::Args) -> bool {
+    !args.stats && !args.changes && args.subtree.is_none()
+}
+
+/// Whether the output could be streamed from the cache files (`DiskCache::write_cached_tree`)
+///
+/// Plain tree output only, of what `reads_lazily` accepts.
+fn streams_tree(args: &ptree_core::Args) -> bool {
+    matches!(args.format, OutputFormat::Tree) && !args.print0 && !args.quiet && reads_lazily(args)
+}
+
+/// Apply the options that decide which entries are shown, and in what order
+fn configure_view(cache: &mut DiskCache, args: &ptree_core::Args) -> Result<()> {
+    cache.show_hidden = args.hidden;
+    cache.output_options.hide_hidden = args.no_hidden;
+    cache.output_options.sort = args.sort;
+    cache.output_options.natural_sort = args.natural_sort;
+    cache.output_options.reverse = args.reverse;
+    cache.output_options.set_match_patterns(&args.match_patterns)?;
+    cache.output_options.set_ignore_patterns(&args.ignore_patterns)?;
+    let now = chrono::Utc::now();
+    let after = args.newer_than.map(|bound| bound.resolve(now));
+    let before = args.older_than.map(|bound| bound.resolve(now));
+    cache.output_options.set_time_filter(after, before);
+    cache.output_options.set_prune(args.prune);
+    Ok(())
+}
+
+/// Open the cache at `path`, logging how its files fared (`-v`); with `strict`, files that can't be used fail the run
+fn open_cache(path: &Path, strict: bool) -> Result<DiskCache> {
+    let opened = if strict { DiskCache::open_strict(path) } else { DiskCache::open(path) };
+    let cache = opened.map_err(|e| cache_error(path, e))?;
+    log::info!("cache {}: {}", path.display(), cache.load);
+    Ok(cache)
+}
+
+/// Load every entry of the cache at `path`
+///
+/// A damaged record moves the files aside and leaves the cache empty, so
+/// this run's scan rebuilds it; with `strict` it fails the run instead.
+fn load_all_entries(cache: &mut DiskCache, path: &Path, strict: bool) -> Result<()> {
+    let loaded = if strict { cache.load_all_entries_lazy(path) } else { cache.load_all_entries_or_rebuild(path) };
+    loaded.map_err(|e| cache_error(path, e))
+}
+
+/// Open the cache with every entry loaded, for subcommands that read it whole
+fn open_full_cache(args: &ptree_core::Args) -> Result<DiskCache> {
+    let cache_path = ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?;
+    log::debug!("cache path: {}", cache_path.display());
+    let mut cache = open_cache(&cache_path, args.strict_cache)?;
+    load_all_entries(&mut cache, &cache_path, args.strict_cache)?;
+    if let Some(backend) = args.backend {
+        cache.set_backend(backend, &cache_path).map_err(|e| cache_error(&cache_path, e))?;
+    }
+    Ok(cache)
+}
+
+/// Fail with a cache error when there is nothing cached to work with
+fn require_entries(cache: &DiskCache) -> Result<()> {
+    if cache.entries.is_empty() {
+        return Err(PTreeError::Cache("the cache is empty; run ptree once to scan".to_string()).into());
+    }
+    Ok(())
+}
+
+/// `--verify-cache`: check every record, moving a damaged cache aside so this run rebuilds it
+fn verify_cache_files(path: &Path) -> Result<()> {
+    match DiskCache::verify_files(path) {
+        Ok(failed) if failed.is_empty() => {
+            eprintln!("cache OK: every record matches its checksum");
+            return Ok(());
+        }
+        Ok(failed) => {
+            for (offset, entry) in &failed {
+                eprintln!("corrupt record at offset {}: {}", offset, entry.display());
+            }
+            eprintln!("{} corrupt record(s) in {}", format_number(failed.len()), path.display());
+        }
+        Err(e) if matches!(e.downcast_ref::<PTreeError>(), Some(PTreeError::CacheCorrupt(_))) => {
+            eprintln!("{:#}", e);
+        }
+        Err(e) => return Err(cache_error(path, e)),
+    }
+    for moved in ptree_cache::quarantine_cache_files(path)? {
+        eprintln!("moved aside: {}", moved.display());
+    }
     Ok(())
 }
 
+/// Resolve a --subtree argument to its cached path
+///
+/// `rescan` is the flag the error suggests for picking up a new path.
+fn cached_subtree(cache: &DiskCache, subtree: &Path, rescan: &str) -> Result<PathBuf> {
+    cache.find_cached_path(subtree).ok_or_else(|| {
+        PTreeError::Cache(format!("{} is not in the cache; run with {} to rescan", subtree.display(), rescan)).into()
+    })
+}
+
+/// `ptree browse`: explore the cached tree and print the chosen path
+#[cfg(feature = "tui")]
+fn browse(args: &ptree_core::Args, path: Option<&Path>) -> Result<u8> {
+    let mut cache = open_full_cache(args)?;
+    require_entries(&cache)?;
+    configure_view(&mut cache, args)?;
+
+    let root = match path {
+        Some(path) => cached_subtree(&cache, path, "--force")?,
+        None => cache.find_cached_path(Path::new(".")).unwrap_or_else(|| cache.root.clone()),
+    };
+
+    if let Some(selected) = ptree_tui::browse(&cache, &root)? {
+        println!("{}", selected.display());
+    }
+    Ok(exit_code::SUCCESS)
+}
+
+#[cfg(not(feature = "tui"))]
+fn browse(_args: &ptree_core::Args, _path: Option<&Path>) -> Result<u8> {
+    Err(PTreeError::InvalidArgument("ptree was built without the \"tui\" feature; browse is unavailable".to_string()).into())
+}
+
+/// `ptree search`: list cached paths matching a pattern
+///
+/// Only scans when asked to (--refresh) or when nothing is cached yet.
+fn search(
+    args: &ptree_core::Args,
+    pattern: &str,
+    mode: ptree_core::SearchMode,
+    limit: Option<usize>,
+    subtree: Option<&Path>,
+    json: bool,
+    refresh: bool,
+) -> Result<u8> {
+    let matcher = ptree_cache::PathMatcher::new(pattern, mode)?;
+    let mut cache = open_full_cache(args)?;
+
+    if refresh || cache.entries.is_empty() {
+        traverse_disk(&args.drives()[0], &mut cache, args)?;
+    }
+
+    let scope = subtree.map(|subtree| cached_subtree(&cache, subtree, "--refresh")).transpose()?;
+    let results = cache.search(&matcher, scope.as_deref(), limit);
+    log::debug!("{} of {} matches for '{}'", results.matches.len(), results.total, pattern);
+
+    let mut sink = OutputSink::open(args.output.as_deref())?;
+    if json {
+        writeln!(sink, "{}", results.to_json(pattern)?)?;
+    } else if !results.matches.is_empty() {
+        writeln!(sink, "{}", results.to_text())?;
+    }
+    sink.finish()?;
+
+    if results.is_truncated() && !json && !args.quiet {
+        eprintln!(
+            "... {} more matches (use --limit 0 for all)",
+            format_number(results.total - results.matches.len())
+        );
+    }
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree stats`: rank directories by child and descendant counts
+fn stats(args: &ptree_core::Args, top: usize, subtree: Option<&Path>, json: bool) -> Result<u8> {
+    let cache = open_full_cache(args)?;
+    require_entries(&cache)?;
+    let scope = subtree.map(|subtree| cached_subtree(&cache, subtree, "--force")).transpose()?;
+    let stats = cache.compute_stats(top, scope.as_deref());
+
+    let mut sink = OutputSink::open(args.output.as_deref())?;
+    let report = if json { stats.to_json()? } else { stats.to_text() };
+    writeln!(sink, "{}", report)?;
+    sink.finish()?;
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree cache-info`: describe the cache files from the index alone
+fn cache_info(args: &ptree_core::Args, json: bool) -> Result<u8> {
+    let cache_path = ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?;
+    let info = DiskCache::info(&cache_path).map_err(|e| cache_error(&cache_path, e))?;
+
+    let mut sink = OutputSink::open(args.output.as_deref())?;
+    let now = chrono::Utc::now();
+    let report = if json { info.to_json(now)? } else { info.to_text(now) };
+    writeln!(sink, "{}", report)?;
+    sink.finish()?;
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree migrate-cache`: convert a legacy cache and report its entries before and after
+fn migrate_cache(args: &ptree_core::Args) -> Result<u8> {
+    let cache_path = ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?;
+    let migration = DiskCache::migrate_legacy(&cache_path).map_err(|e| cache_error(&cache_path, e))?;
+    if args.quiet {
+        return Ok(exit_code::SUCCESS);
+    }
+    match migration {
+        Some(migration) => {
+            println!("Legacy cache: {} entries", format_number(migration.legacy_entries));
+            println!("Migrated:     {} entries in {}", format_number(migration.migrated_entries), cache_path.with_extension("idx").display());
+            println!("Kept the old file as {}", migration.backup.display());
+        }
+        None => println!("No legacy cache at {}", cache_path.display()),
+    }
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree verify`: compare cached entries with the disk, failing above the threshold
+fn verify(args: &ptree_core::Args, sample: Option<usize>, threshold: f64, seed: Option<u64>, json: bool) -> Result<u8> {
+    let cache = open_full_cache(args)?;
+    require_entries(&cache)?;
+
+    let threads = args.threads.unwrap_or_else(|| {
+        let cores = std::thread::available_parallelism().map_or(2, |n| n.get());
+        ptree_traversal::detect_media(&cache.root).default_threads(cores)
+    });
+    let report = ptree_traversal::verify_cache(&cache, sample, seed, threads)?;
+    log::debug!("verified {} entries with {} threads", report.checked, threads);
+
+    let mut sink = OutputSink::open(args.output.as_deref())?;
+    let text = if json { report.to_json(threshold)? } else { report.to_text(threshold) };
+    writeln!(sink, "{}", text)?;
+    sink.finish()?;
+
+    if report.stale_percent() > threshold {
+        return Ok(exit_code::STALE);
+    }
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree export`: write the cache to a portable archive
+fn export(args: &ptree_core::Args, file: &Path) -> Result<u8> {
+    let cache = open_full_cache(args)?;
+    require_entries(&cache)?;
+
+    let mut sink = OutputSink::open(Some(file))?;
+    cache.write_archive(&mut sink)?;
+    sink.finish()?;
+
+    if !args.quiet {
+        let bytes = std::fs::metadata(file).map_or(0, |metadata| metadata.len());
+        println!(
+            "Exported {} entries to {} ({} bytes)",
+            format_number(cache.entries.len()),
+            file.display(),
+            format_number(bytes as usize)
+        );
+    }
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree import`: merge an archive into the local cache and save it
+fn import(args: &ptree_core::Args, file: &Path, replace: bool) -> Result<u8> {
+    let incoming = read_archive_file(file)?;
+
+    let cache_path = ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?;
+    let mut cache = open_full_cache(args)?;
+    let summary = cache.import_cache(incoming, replace);
+    cache
+        .save(&cache_path)
+        .map_err(|e| PTreeError::Cache(format!("failed to save {}: {:#}", cache_path.display(), e)))?;
+
+    if !args.quiet {
+        println!(
+            "Imported {}: {} added, {} updated, {} skipped",
+            file.display(),
+            format_number(summary.added),
+            format_number(summary.updated),
+            format_number(summary.skipped)
+        );
+    }
+    Ok(exit_code::SUCCESS)
+}
+
+/// Read an archive written by `ptree export`
+fn read_archive_file(file: &Path) -> Result<DiskCache> {
+    let archive = std::fs::File::open(file)
+        .map_err(|e| PTreeError::Cache(format!("cannot open archive {}: {}", file.display(), e)))?;
+    DiskCache::read_archive(std::io::BufReader::new(archive)).map_err(|e| cache_error(file, e))
+}
+
+/// `ptree diff`: list directories that differ between two archives
+///
+/// Without archives (--live) the cache is compared with a fresh scan, which
+/// is saved like any other scan.
+fn diff(args: &ptree_core::Args, archives: Option<(PathBuf, PathBuf)>, subtree: Option<&Path>, json: bool) -> Result<u8> {
+    let scope = subtree.map(std::path::absolute).transpose()?;
+    let mut writer = ptree_cache::DiffWriter::new(OutputSink::open(args.output.as_deref())?, json);
+    let mut visit = |kind, entry: &ptree_cache::DirEntry| writer.write(kind, entry);
+
+    let counts = match archives {
+        Some((old, new)) => {
+            ptree_cache::diff_caches(&read_archive_file(&old)?, &read_archive_file(&new)?, scope.as_deref(), &mut visit)?
+        }
+        None => {
+            let cache_path = ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?;
+            let mut cache = open_full_cache(args)?;
+            require_entries(&cache)?;
+            // The earlier state of an rkyv cache is read back from its files
+            // as the comparison reaches it; other backends are copied first
+            let before = (ptree_cache::stored_backend(&cache_path) != Some(CacheBackend::Rkyv)).then(|| cache.clone());
+            let scanned = scan_disk(&args.drives()[0], &mut cache, args)?;
+            let counts = match &before {
+                Some(before) => ptree_cache::diff_caches(before, &cache, scope.as_deref(), &mut visit)?,
+                None => {
+                    let saved = RkyvMmapCache::open(&cache_path.with_extension("idx"), &cache_path.with_extension("dat"))
+                        .map_err(|e| cache_error(&cache_path, e))?;
+                    ptree_cache::diff_caches(&saved, &cache, scope.as_deref(), &mut visit)?
+                }
+            };
+            // Saved like any other scan (see `traverse_disk`), once the mapping is closed
+            if !scanned.cache_used && !args.no_cache && !args.read_only {
+                cache
+                    .save(&cache_path)
+                    .map_err(|e| PTreeError::Cache(format!("failed to save {}: {:#}", cache_path.display(), e)))?;
+            }
+            counts
+        }
+    };
+    writer.finish(counts)?.finish()?;
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree watch`: stream directory changes from the USN journal until Ctrl+C
+fn watch(args: &ptree_core::Args, drive: char, interval: u64, json: bool) -> Result<u8> {
+    use ptree_driver::{ChangeType, USNJournalState, USNTracker};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    if !cfg!(windows) {
+        anyhow::bail!("ptree watch reads the NTFS USN journal and is only supported on Windows");
+    }
+    let drive = drive.to_ascii_uppercase();
+
+    // Entries are loaded too, since the cache is saved again on exit
+    let cache_path = ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?;
+    let mut cache = open_full_cache(args)?;
+    let saved = cache.usn_position();
+    let mut tracker = USNTracker::new(
+        drive,
+        USNJournalState {
+            journal_id: saved.journal_id,
+            last_usn: saved.last_usn,
+            drive_letter: drive,
+            ..USNJournalState::default()
+        },
+    );
+    if !tracker.is_available()? {
+        return Err(PTreeError::InvalidDrive(format!("no active USN journal on drive {}:", drive)).into());
+    }
+
+    // A reset journal invalidates the saved position; with none, start from now
+    let journal = tracker.get_journal_data()?;
+    if !tracker.check_journal_validity()? || saved.last_usn == 0 {
+        let mut state = tracker.state().clone();
+        state.journal_id = journal.usn_journal_id;
+        state.last_usn = journal.next_usn;
+        tracker.set_state(state);
+    }
+    log::info!("watching drive {}: from USN {}", drive, tracker.state().last_usn);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = Arc::clone(&stop);
+        ctrlc::set_handler(move || stop.store(true, Ordering::Relaxed))?;
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    while !stop.load(Ordering::Relaxed) {
+        // Reads start at the last processed USN, so skip what was already printed
+        let processed = tracker.state().last_usn;
+        for record in tracker.read_changes()? {
+            if !record.is_directory || record.usn <= processed {
+                continue;
+            }
+            let verb = match record.change_type {
+                ChangeType::Created => "created",
+                ChangeType::Modified => "modified",
+                ChangeType::Deleted => "deleted",
+                ChangeType::Renamed => "renamed",
+                ChangeType::SecurityChanged | ChangeType::PermissionsChanged => "permissions",
+                ChangeType::Other => "changed",
+            };
+            if json {
+                let line = serde_json::json!({
+                    "change": verb,
+                    "path": record.path.to_string_lossy(),
+                    "usn": record.usn,
+                    "timestamp": record.timestamp.to_rfc3339(),
+                });
+                writeln!(stdout, "{}", line)?;
+            } else {
+                writeln!(stdout, "{} {}", verb, record.path.display())?;
+            }
+        }
+        stdout.flush()?;
+
+        // Sleep in short steps so Ctrl+C is handled promptly
+        for _ in 0..interval.max(1) * 10 {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    let state = tracker.state();
+    log::info!("stopped watching drive {}: at USN {}", drive, state.last_usn);
+    cache.set_usn_position(ptree_cache::USNJournalState { journal_id: state.journal_id, last_usn: state.last_usn });
+    cache
+        .save(&cache_path)
+        .map_err(|e| PTreeError::Cache(format!("failed to save {}: {:#}", cache_path.display(), e)))?;
+    Ok(exit_code::SUCCESS)
+}
+
+/// `ptree clean`: delete the cache, or just one drive's entries from it
+fn clean(args: &ptree_core::Args, drive: Option<char>, compact: bool, gc: bool) -> Result<u8> {
+    let cache_path = ptree_cache::get_cache_path_custom(args.cache_dir.as_deref(), args.cache_path.as_deref(), args.cache_name.as_deref())?;
+    let cache_dir = cache_path.parent().unwrap_or(Path::new("."));
+    let existing_bytes = || -> u64 {
+        ptree_cache::cache_artifacts(&cache_path)
+            .iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    };
+
+    if !ptree_cache::cache_artifacts(&cache_path).iter().any(|file| file.exists()) {
+        if !args.quiet {
+            println!("No cache files in {}", cache_dir.display());
+        }
+        return Ok(exit_code::SUCCESS);
+    }
+
+    let reclaimed = match drive {
+        None if compact => {
+            let stats = DiskCache::compact(&cache_path).map_err(|e| cache_error(&cache_path, e))?;
+            if !args.quiet {
+                println!("Compacted {} cached entries", format_number(stats.entries));
+            }
+            stats.reclaimed()
+        }
+        None if gc => {
+            let before = existing_bytes();
+            let mut cache = open_full_cache(args)?;
+            let removed = cache.gc_orphans();
+            if removed > 0 {
+                cache
+                    .save(&cache_path)
+                    .map_err(|e| PTreeError::Cache(format!("failed to save {}: {:#}", cache_path.display(), e)))?;
+            }
+            if !args.quiet {
+                println!("Removed {} orphaned cache entries", format_number(removed));
+            }
+            before.saturating_sub(existing_bytes())
+        }
+        Some(drive) => {
+            let before = existing_bytes();
+            let mut cache = open_full_cache(args)?;
+            let removed = cache.remove_drive(drive);
+            if removed > 0 && cache.entries.is_empty() {
+                ptree_cache::remove_cache_files(&cache_path)?;
+            } else if removed > 0 {
+                cache
+                    .save(&cache_path)
+                    .map_err(|e| PTreeError::Cache(format!("failed to save {}: {:#}", cache_path.display(), e)))?;
+            }
+            if !args.quiet {
+                println!("Removed {} cached entries for drive {}:", format_number(removed), drive.to_ascii_uppercase());
+            }
+            before.saturating_sub(existing_bytes())
+        }
+        None => ptree_cache::remove_cache_files(&cache_path)?,
+    };
+
+    if !args.quiet {
+        println!("Reclaimed {} bytes in {}", format_number(reclaimed as usize), cache_dir.display());
+    }
+    Ok(exit_code::SUCCESS)
+}
+
+/// Send log output to stderr at the level chosen by -v/--quiet
+///
+/// `RUST_LOG` can refine the level further unless --quiet silences everything.
+fn init_logging(args: &ptree_core::Args) {
+    let level = if args.quiet {
+        log::LevelFilter::Off
+    } else {
+        match args.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    };
+
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level).target(env_logger::Target::Stderr);
+    if !args.quiet {
+        builder.parse_default_env();
+    }
+    builder.init();
+}
+
+/// Render the display root in the requested format
+///
+/// Returns None when the output was streamed straight to the sink.
+fn render_output(
+    cache: &DiskCache,
+    args: &ptree_core::Args,
+    sink: &mut OutputSink,
+    use_colors: bool,
+) -> Result<Option<String>> {
+    if args.print0 {
+        // Raw path bytes, so straight to the sink rather than through a String
+        cache.write_print0_output(sink, args.max_depth)?;
+        return Ok(None);
+    }
+    let output = match args.format {
+        OutputFormat::Tree => {
+            if use_colors {
+                let theme = ptree_cache::Theme::from_env();
+                cache.build_colored_tree_output_with_theme(args.max_depth, &theme)?
+            } else {
+                cache.build_tree_output_with_depth(args.max_depth)?
+            }
+        }
+        OutputFormat::Json => cache.build_json_output_with_depth(args.max_depth)?,
+        OutputFormat::Csv => cache.build_csv_output_with_depth(args.max_depth)?,
+        OutputFormat::Dot => cache.build_dot_output_with_depth(args.max_depth)?,
+        OutputFormat::Html => cache.build_html_output_with_depth(args.max_depth)?,
+        OutputFormat::Markdown => cache.build_markdown_output_with_depth(args.max_depth, args.link_targets)?,
+        OutputFormat::Ndjson => {
+            // Streamed straight to the sink, never materialized as one String
+            cache.write_ndjson_output(sink, args.max_depth)?;
+            return Ok(None);
+        }
+    };
+    Ok(Some(output))
+}
+
+/// Attach the cache path to a cache load failure so it maps to the cache exit code
+fn cache_error(path: &Path, err: anyhow::Error) -> anyhow::Error {
+    if err.is::<PTreeError>() {
+        return err;
+    }
+    PTreeError::Cache(format!("failed to load {}: {:#}", path.display(), err)).into()
+}
+
 /// Format duration in both milliseconds and picoseconds
 fn format_duration(duration: std::time::Duration) -> String {
     let ms = duration.as_secs_f64() * 1000.0;
@@ -126,35 +970,57 @@ fn format_duration(duration: std::time::Duration) -> String {
 
 /// Print formatted debug summary
 fn print_debug_summary(
-    debug_info: &ptree_traversal::DebugInfo,
+    debug_infos: &[ptree_traversal::DebugInfo],
     cache_load_time: std::time::Duration,
     formatting_time: std::time::Duration,
     output_time: std::time::Duration,
-    cache_path: &std::path::Path,
+    cache_path: Option<&std::path::Path>,
+    cache_load: &ptree_cache::CacheLoad,
     total_time: std::time::Duration,
 ) {
     eprintln!("\n{}", "=".repeat(70));
     eprintln!("{:^70}", "PERFORMANCE DEBUG INFO");
     eprintln!("{}", "=".repeat(70));
 
-    eprintln!("\n{:<40} {}", "Execution Mode:", if debug_info.is_first_run { "FULL DISK SCAN (First Run)" } else if debug_info.cache_used { "CACHED (< 1 hour)" } else { "PARTIAL SCAN (Current Dir)" });
-    eprintln!("{:<40} {}", "Scan Root:", debug_info.scan_root.display());
+    // One block per scanned drive
+    for debug_info in debug_infos {
+        eprintln!("\n{:<40} {}", "Execution Mode:", if debug_info.is_first_run { "FULL DISK SCAN (First Run)" } else if debug_info.cache_used { "CACHED (< 1 hour)" } else { "PARTIAL SCAN (Current Dir)" });
+        eprintln!("{:<40} {}", "Scan Root:", debug_info.scan_root.display());
 
-    eprintln!("\n{:<40} {}", "Directories Scanned:", format_number(debug_info.total_dirs));
-    eprintln!("{:<40} {}", "Files Scanned:", format_number(debug_info.total_files));
-    eprintln!("{:<40} {}", "Threads Used:", debug_info.threads_used);
+        eprintln!("\n{:<40} {}", "Directories Scanned:", format_number(debug_info.total_dirs));
+        eprintln!("{:<40} {}", "Files Scanned:", format_number(debug_info.total_files));
+        eprintln!("{:<40} {}", "Entries Scanned:", format_number(debug_info.total_dirs + debug_info.total_files));
+        let reason = match debug_info.drive_media {
+            _ if debug_info.cache_used => String::new(),
+            Some(media) => format!(" (default for {} media)", media.label()),
+            None => " (set by --threads)".to_string(),
+        };
+        eprintln!("{:<40} {}{}", "Threads Used:", debug_info.threads_used, reason);
+        eprintln!("{:<40} {}", "Entries Added:", format_number(debug_info.entries_added));
+        eprintln!("{:<40} {}", "Entries Updated:", format_number(debug_info.entries_updated));
 
-    eprintln!("\n{:<40} {}", "Cache Load Time:", format_duration(cache_load_time));
-    if !debug_info.cache_used {
-        eprintln!("{:<40} {}", "Traversal Time:", format_duration(debug_info.traversal_time));
-        eprintln!("{:<40} {}", "Cache Index Time:", format_duration(debug_info.cache_index_time));
-        eprintln!("{:<40} {}", "Cache Save Time:", format_duration(debug_info.save_time));
+        if !debug_info.cache_used {
+            eprintln!("{:<40} {}", "Directories Listed:", format_number(debug_info.dirs_listed));
+            eprintln!("{:<40} {}", "Directories Reused (unchanged):", format_number(debug_info.dirs_reused));
+            eprintln!("{:<40} {}", "Entries Removed (gone from disk):", format_number(debug_info.entries_removed));
+            eprintln!("\n{:<40} {}", "Traversal Time:", format_duration(debug_info.traversal_time));
+            eprintln!("{:<40} {}", "Cache Index Time:", format_duration(debug_info.cache_index_time));
+            eprintln!("{:<40} {}", "Cache Save Time:", format_duration(debug_info.save_time));
+        }
     }
+
+    eprintln!("\n{:<40} {}", "Cache Load Time:", format_duration(cache_load_time));
     eprintln!("{:<40} {}", "Formatting Time:", format_duration(formatting_time));
     eprintln!("{:<40} {}", "Output Time:", format_duration(output_time));
     eprintln!("{:<40} {}", "Total Time:", format_duration(total_time));
 
-    eprintln!("\n{:<40} {}", "Cache Location:", cache_path.display());
+    let location = cache_path
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "(disabled by --no-cache)".to_string());
+    eprintln!("\n{:<40} {}", "Cache Location:", location);
+    if cache_path.is_some() {
+        eprintln!("{:<40} {}", "Cache Load Result:", cache_load);
+    }
     eprintln!("{}", "=".repeat(70));
     eprintln!();
 }
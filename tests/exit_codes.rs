@@ -0,0 +1,137 @@
+use std::process::Command;
+
+fn ptree() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_ptree"))
+}
+
+#[test]
+fn test_missing_drive_exits_with_not_found_code() {
+    let output = ptree()
+        .args(["--force", "--no-cache", "--drive", "Q"])
+        .output()
+        .expect("failed to run ptree");
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("drive Q does not exist"));
+}
+
+#[test]
+fn test_invalid_arguments_exit_with_usage_code() {
+    let output = ptree()
+        .args(["--no-cache", "--entry-format", "{nope}"])
+        .output()
+        .expect("failed to run ptree");
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = ptree().arg("--no-such-flag").output().expect("failed to run ptree");
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = ptree()
+        .args(["--no-cache", "--heatmap", "--heatmap-hot-hours", "200"])
+        .output()
+        .expect("failed to run ptree");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be less than --heatmap-warm-hours"));
+
+    let output = ptree()
+        .args(["--heatmap-warm-hours", "18446744073709551615"])
+        .output()
+        .expect("failed to run ptree");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_help_documents_exit_codes() {
+    let output = ptree().arg("--help").output().expect("failed to run ptree");
+    let help = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success());
+    assert!(help.contains("Exit codes:"));
+    assert!(help.contains("3  drive or scan root not found"));
+}
+
+#[test]
+fn test_bare_cache_file_name_is_used_in_the_current_directory() {
+    let dir = std::env::temp_dir().join(format!("ptree_bare_cache_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("scan").join("a")).unwrap();
+
+    let output = ptree()
+        .current_dir(&dir)
+        .args(["--cache-path", "ptree.dat", "--color=never", "scan"])
+        .output()
+        .expect("failed to run ptree");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("ptree.idx").is_file());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unwritable_cache_directory_exits_with_cache_code() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("ptree_readonly_cache_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("scan")).unwrap();
+    let cache_dir = dir.join("cache");
+    std::fs::create_dir_all(&cache_dir).unwrap();
+    std::fs::set_permissions(&cache_dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+    // Permissions don't bind root; nothing to check then
+    if std::fs::write(cache_dir.join("probe"), b"").is_err() {
+        let output = ptree()
+            .arg("--cache-path")
+            .arg(cache_dir.join("ptree.dat"))
+            .arg(dir.join("scan"))
+            .output()
+            .expect("failed to run ptree");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(4), "{}", stderr);
+        assert!(stderr.contains("Cannot use cache path"), "{}", stderr);
+    }
+
+    std::fs::set_permissions(&cache_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_damaged_record_is_rebuilt_unless_strict() {
+    let dir = std::env::temp_dir().join(format!("ptree_damaged_record_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("scan").join("a")).unwrap();
+    let cache_path = dir.join("cache").join("ptree.dat");
+    let run = |extra: &[&str]| {
+        ptree()
+            .arg("--cache-path")
+            .arg(&cache_path)
+            .args(["--color=never", "--changes"])
+            .args(extra)
+            .arg(dir.join("scan"))
+            .output()
+            .expect("failed to run ptree")
+    };
+    assert!(run(&[]).status.success());
+
+    // The index still opens; the damage only shows once the records are read
+    let mut data = std::fs::read(&cache_path).unwrap();
+    *data.last_mut().unwrap() ^= 0xff;
+    std::fs::write(&cache_path, &data).unwrap();
+
+    let output = run(&["--strict-cache"]);
+    assert_eq!(output.status.code(), Some(4), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("a"));
+    let aside = std::fs::read_dir(dir.join("cache"))
+        .unwrap()
+        .flatten()
+        .filter(|file| file.file_name().to_string_lossy().contains(".corrupt-"))
+        .count();
+    assert_eq!(aside, 2);
+    assert!(run(&["--strict-cache"]).status.success());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
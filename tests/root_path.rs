@@ -0,0 +1,39 @@
+use std::fs;
+use std::process::Command;
+
+fn ptree() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_ptree"))
+}
+
+#[test]
+fn test_positional_path_is_scan_and_display_root() {
+    let root = std::env::temp_dir().join(format!("ptree_root_path_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("projects").join("app")).unwrap();
+
+    let output = ptree()
+        .arg("--no-cache")
+        .arg("--color=never")
+        .arg(root.join("projects"))
+        .output()
+        .expect("failed to run ptree");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.starts_with(&root.join("projects").display().to_string()));
+    assert!(stdout.contains("app"));
+
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn test_missing_path_exits_with_not_found_code() {
+    let missing = std::env::temp_dir().join("ptree_no_such_root").join("nested");
+    let output = ptree()
+        .arg("--no-cache")
+        .arg(&missing)
+        .output()
+        .expect("failed to run ptree");
+
+    assert_eq!(output.status.code(), Some(3));
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use std::process::Command;
+
+fn ptree() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_ptree"))
+}
+
+#[test]
+fn test_search_reads_cache_without_rescanning() {
+    let dir = std::env::temp_dir().join(format!("ptree_search_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let root = dir.join("tree");
+    fs::create_dir_all(root.join("src").join("tests")).unwrap();
+    let cache = dir.join("cache");
+
+    let search = |pattern: &str| {
+        let output = ptree()
+            .current_dir(&root)
+            .arg("--cache-path")
+            .arg(&cache)
+            .args(["search", pattern])
+            .output()
+            .expect("failed to run ptree");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // Nothing cached yet, so the first search scans
+    assert_eq!(search("src/test*").trim(), root.join("src").join("tests").display().to_string());
+
+    // Later searches answer from the (now stale) cache
+    fs::create_dir_all(root.join("src").join("tests_new")).unwrap();
+    assert_eq!(search("tests*").lines().count(), 1);
+
+    // A --subtree missing from the cache points at search's own rescan flag
+    let output = ptree()
+        .current_dir(&root)
+        .arg("--cache-path")
+        .arg(&cache)
+        .args(["search", "tests*", "--subtree"])
+        .arg(root.join("src").join("tests_new"))
+        .output()
+        .expect("failed to run ptree");
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("run with --refresh to rescan"));
+
+    let _ = fs::remove_dir_all(&dir);
+}